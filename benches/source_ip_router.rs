@@ -0,0 +1,50 @@
+use divan::Bencher;
+use quilkin::filters::{source_ip_router, Filter, ReadContext, SourceIpRouter, StaticFilter};
+use rand::{Rng, SeedableRng};
+
+/// Routes each matching a /24 of their own, with sources spread across them.
+#[divan::bench(args = [10, 1_000, 10_000])]
+fn source_ip_router(b: Bencher, routes: usize) {
+    let mut rand = rand::rngs::SmallRng::seed_from_u64(42);
+    let networks: Vec<[u8; 3]> = (0..routes).map(|_| rand.gen()).collect();
+
+    let config = source_ip_router::Config {
+        routes: networks
+            .iter()
+            .map(|[x, y, z]| {
+                serde_json::from_value(serde_json::json!({
+                    "sources": [format!("{x}.{y}.{z}.0/24")],
+                    "endpoint": "127.0.0.1:7001",
+                }))
+                .unwrap()
+            })
+            .collect(),
+        cache: None,
+        dns_ttl_secs: 30,
+        log_sample: 0,
+    };
+    let filter = SourceIpRouter::from_config(Some(config));
+
+    let cm = std::sync::Arc::new(quilkin::net::ClusterMap::default());
+    let pool = std::sync::Arc::new(quilkin::pool::BufferPool::new(1, 64));
+
+    b.with_inputs(|| {
+        let [x, y, z] = networks[rand.gen_range(0..networks.len())];
+        let source: quilkin::net::EndpointAddress =
+            (std::net::Ipv4Addr::new(x, y, z, rand.gen()), 9000).into();
+        (
+            cm.clone(),
+            source,
+            pool.clone().alloc(),
+            Vec::with_capacity(1),
+        )
+    })
+    .bench_local_values(|(cm, source, buffer, mut dest)| {
+        let mut ctx = ReadContext::new(cm, source, buffer, &mut dest);
+        let _ = divan::black_box(filter.read(&mut ctx));
+    })
+}
+
+fn main() {
+    divan::main();
+}
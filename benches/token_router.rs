@@ -52,8 +52,11 @@ fn token_router(b: Bencher, token_kind: &str) {
             endpoints: cm,
             destinations: &mut dest,
             source: quilkin::net::EndpointAddress::LOCALHOST,
+            destination_port: None,
             contents: buffer,
             metadata,
+            reply: None,
+            drop_reason: None,
         };
 
         let _ = divan::black_box(filter.sync_read(&mut rc));
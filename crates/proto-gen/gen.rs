@@ -199,18 +199,28 @@ fn execute(which: &str) {
             &[
                 "relay/v1alpha1/relay",
                 "config/v1alpha1/config",
+                "filters/address_rewrite/v1alpha1/address_rewrite",
                 "filters/capture/v1alpha1/capture",
                 "filters/compress/v1alpha1/compress",
                 "filters/concatenate/v1alpha1/concatenate",
+                "filters/content_router/v1alpha1/content_router",
+                "filters/control/v1alpha1/control",
                 "filters/debug/v1alpha1/debug",
                 "filters/drop/v1alpha1/drop",
+                "filters/dscp/v1alpha1/dscp",
                 "filters/firewall/v1alpha1/firewall",
+                "filters/health_probe/v1alpha1/health_probe",
+                "filters/listeners/v1alpha1/listeners",
                 "filters/load_balancer/v1alpha1/load_balancer",
                 "filters/local_rate_limit/v1alpha1/local_rate_limit",
                 "filters/match/v1alpha1/match",
                 "filters/pass/v1alpha1/pass",
+                "filters/reassembly/v1alpha1/reassembly",
+                "filters/tenants/v1alpha1/tenants",
+                "filters/token_push/v1alpha1/token_push",
                 "filters/token_router/v1alpha1/token_router",
                 "filters/timestamp/v1alpha1/timestamp",
+                "filters/traffic_split/v1alpha1/traffic_split",
                 "filters/source_ip_router/v1alpha1/source_ip_router",
             ],
         ),
@@ -60,6 +60,8 @@ pub struct Endpoint {
     pub metadata: ::core::option::Option<::prost_types::Struct>,
     #[prost(message, optional, tag = "4")]
     pub host2: ::core::option::Option<Host>,
+    #[prost(message, optional, tag = "5")]
+    pub weight: ::core::option::Option<u32>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -1,13 +1,23 @@
+pub mod address_rewrite;
 pub mod capture;
 pub mod compress;
 pub mod concatenate;
+pub mod content_router;
+pub mod control;
 pub mod debug;
 pub mod drop;
+pub mod dscp;
 pub mod firewall;
+pub mod health_probe;
+pub mod listeners;
 pub mod load_balancer;
 pub mod local_rate_limit;
 pub mod matches;
 pub mod pass;
+pub mod reassembly;
 pub mod source_ip_router;
+pub mod tenants;
 pub mod timestamp;
+pub mod token_push;
 pub mod token_router;
+pub mod traffic_split;
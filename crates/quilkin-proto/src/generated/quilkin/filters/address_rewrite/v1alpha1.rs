@@ -0,0 +1,51 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AddressRewrite {
+    #[prost(string, tag = "1")]
+    pub address: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub fields: ::prost::alloc::vec::Vec<address_rewrite::Field>,
+}
+/// Nested message and enum types in `AddressRewrite`.
+pub mod address_rewrite {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Field {
+        #[prost(uint32, tag = "1")]
+        pub offset: u32,
+        #[prost(enumeration = "Format", tag = "2")]
+        pub format: i32,
+    }
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Format {
+        Ipv4 = 0,
+        Ipv6 = 1,
+        TextU8 = 2,
+        TextU16 = 3,
+    }
+    impl Format {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Format::Ipv4 => "Ipv4",
+                Format::Ipv6 => "Ipv6",
+                Format::TextU8 => "TextU8",
+                Format::TextU16 => "TextU16",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "Ipv4" => Some(Self::Ipv4),
+                "Ipv6" => Some(Self::Ipv6),
+                "TextU8" => Some(Self::TextU8),
+                "TextU16" => Some(Self::TextU16),
+                _ => None,
+            }
+        }
+    }
+}
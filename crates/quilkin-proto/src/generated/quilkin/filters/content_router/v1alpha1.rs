@@ -0,0 +1,27 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ContentRouter {
+    #[prost(message, optional, tag = "1")]
+    pub metadata_key: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "2")]
+    pub scan_limit: ::core::option::Option<u32>,
+    #[prost(message, repeated, tag = "3")]
+    pub routes: ::prost::alloc::vec::Vec<content_router::Route>,
+}
+/// Nested message and enum types in `ContentRouter`.
+pub mod content_router {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Route {
+        #[prost(string, tag = "1")]
+        pub name: ::prost::alloc::string::String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub bytes: ::prost::alloc::vec::Vec<u8>,
+        #[prost(uint32, tag = "3")]
+        pub offset: u32,
+        #[prost(message, optional, tag = "4")]
+        pub regex: ::core::option::Option<::prost::alloc::string::String>,
+        #[prost(message, optional, tag = "5")]
+        pub cluster: ::core::option::Option<::prost::alloc::string::String>,
+    }
+}
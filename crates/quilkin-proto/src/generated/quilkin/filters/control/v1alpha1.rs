@@ -0,0 +1,6 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Control {
+    #[prost(message, optional, tag = "1")]
+    pub metadata_key: ::core::option::Option<::prost::alloc::string::String>,
+}
@@ -0,0 +1,8 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Dscp {
+    #[prost(message, optional, tag = "1")]
+    pub upstream: ::core::option::Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    pub downstream: ::core::option::Option<u32>,
+}
@@ -0,0 +1,10 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HealthProbe {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub payloads: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    #[prost(string, repeated, tag = "2")]
+    pub sources: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "3")]
+    pub response: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
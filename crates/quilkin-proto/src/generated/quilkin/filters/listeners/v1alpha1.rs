@@ -0,0 +1,87 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Listeners {
+    #[prost(message, repeated, tag = "1")]
+    pub listeners: ::prost::alloc::vec::Vec<listeners::Listener>,
+    #[prost(message, repeated, tag = "2")]
+    pub fallthrough:
+        ::prost::alloc::vec::Vec<super::super::super::super::envoy::config::listener::v3::Filter>,
+}
+/// Nested message and enum types in `Listeners`.
+pub mod listeners {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Listener {
+        #[prost(uint32, repeated, tag = "1")]
+        pub ports: ::prost::alloc::vec::Vec<u32>,
+        #[prost(message, repeated, tag = "2")]
+        pub filters: ::prost::alloc::vec::Vec<
+            super::super::super::super::super::envoy::config::listener::v3::Filter,
+        >,
+        #[prost(message, optional, tag = "3")]
+        pub session_timeout_secs: ::core::option::Option<u64>,
+        #[prost(message, optional, tag = "4")]
+        pub session_quota: ::core::option::Option<SessionQuota>,
+        #[prost(message, optional, tag = "5")]
+        pub metrics: ::core::option::Option<Metrics>,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Metrics {
+        #[prost(message, optional, tag = "1")]
+        pub namespace: ::core::option::Option<::prost::alloc::string::String>,
+        #[prost(map = "string, string", tag = "2")]
+        pub labels: ::std::collections::HashMap<
+            ::prost::alloc::string::String,
+            ::prost::alloc::string::String,
+        >,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionQuota {
+        #[prost(message, optional, tag = "1")]
+        pub max_bytes: ::core::option::Option<u64>,
+        #[prost(message, optional, tag = "2")]
+        pub max_packets: ::core::option::Option<u64>,
+        #[prost(message, optional, tag = "3")]
+        pub max_duration_secs: ::core::option::Option<u64>,
+        #[prost(enumeration = "session_quota::Action", tag = "4")]
+        pub action: i32,
+        #[prost(message, optional, tag = "5")]
+        pub throttle_packets_per_sec: ::core::option::Option<u32>,
+    }
+    /// Nested message and enum types in `SessionQuota`.
+    pub mod session_quota {
+        #[derive(
+            Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration,
+        )]
+        #[repr(i32)]
+        pub enum Action {
+            Terminate = 0,
+            Throttle = 1,
+            Event = 2,
+        }
+        impl Action {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    Action::Terminate => "Terminate",
+                    Action::Throttle => "Throttle",
+                    Action::Event => "Event",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "Terminate" => Some(Self::Terminate),
+                    "Throttle" => Some(Self::Throttle),
+                    "Event" => Some(Self::Event),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
@@ -3,6 +3,9 @@
 pub struct LoadBalancer {
     #[prost(message, optional, tag = "1")]
     pub policy: ::core::option::Option<load_balancer::PolicyValue>,
+    #[prost(map = "string, string", tag = "2")]
+    pub overrides:
+        ::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
 }
 /// Nested message and enum types in `LoadBalancer`.
 pub mod load_balancer {
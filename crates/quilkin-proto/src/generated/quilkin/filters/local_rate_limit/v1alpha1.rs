@@ -5,4 +5,7 @@ pub struct LocalRateLimit {
     pub max_packets: u64,
     #[prost(message, optional, tag = "2")]
     pub period: ::core::option::Option<u32>,
+    #[prost(map = "string, string", tag = "3")]
+    pub overrides:
+        ::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
 }
@@ -0,0 +1,18 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Reassembly {
+    #[prost(uint32, tag = "1")]
+    pub offset: u32,
+    #[prost(message, optional, tag = "2")]
+    pub id_size: ::core::option::Option<u32>,
+    #[prost(message, optional, tag = "3")]
+    pub field_size: ::core::option::Option<u32>,
+    #[prost(message, optional, tag = "4")]
+    pub max_fragments: ::core::option::Option<u32>,
+    #[prost(message, optional, tag = "5")]
+    pub max_message_size: ::core::option::Option<u32>,
+    #[prost(message, optional, tag = "6")]
+    pub max_pending: ::core::option::Option<u32>,
+    #[prost(message, optional, tag = "7")]
+    pub timeout_ms: ::core::option::Option<u64>,
+}
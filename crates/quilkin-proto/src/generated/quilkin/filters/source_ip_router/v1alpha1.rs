@@ -3,15 +3,113 @@
 pub struct SourceIpRouter {
     #[prost(message, repeated, tag = "1")]
     pub routes: ::prost::alloc::vec::Vec<source_ip_router::Route>,
+    #[prost(message, optional, tag = "2")]
+    pub cache: ::core::option::Option<source_ip_router::Cache>,
+    #[prost(message, optional, tag = "3")]
+    pub dns_ttl_secs: ::core::option::Option<u64>,
+    #[prost(uint32, tag = "4")]
+    pub log_sample: u32,
 }
 /// Nested message and enum types in `SourceIpRouter`.
 pub mod source_ip_router {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PolicyValue {
+        #[prost(enumeration = "Policy", tag = "1")]
+        pub value: i32,
+    }
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Message)]
     pub struct Route {
         #[prost(string, repeated, tag = "1")]
         pub sources: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
-        #[prost(string, tag = "2")]
-        pub endpoint: ::prost::alloc::string::String,
+        /// Formerly a single `endpoint`, which is encoded the same way.
+        #[prost(string, repeated, tag = "2")]
+        pub endpoints: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        #[prost(uint32, tag = "3")]
+        pub priority: u32,
+        #[prost(message, optional, tag = "4")]
+        pub weight: ::core::option::Option<u32>,
+        #[prost(message, optional, tag = "5")]
+        pub policy: ::core::option::Option<PolicyValue>,
+        #[prost(message, repeated, tag = "6")]
+        pub port_ranges: ::prost::alloc::vec::Vec<PortRange>,
+        #[prost(string, repeated, tag = "7")]
+        pub exclude: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        /// The countries the autonomous systems are registered in.
+        #[prost(string, repeated, tag = "8")]
+        pub as_countries: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        #[prost(uint64, repeated, tag = "9")]
+        pub asns: ::prost::alloc::vec::Vec<u64>,
+        #[prost(message, optional, tag = "10")]
+        pub name: ::core::option::Option<::prost::alloc::string::String>,
+        /// Routes matching packets to `endpoints` when unset.
+        #[prost(oneof = "route::Action", tags = "11")]
+        pub action: ::core::option::Option<route::Action>,
+    }
+    /// Nested message and enum types in `Route`.
+    pub mod route {
+        /// Routes matching packets to `endpoints` when unset.
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Action {
+            #[prost(message, tag = "11")]
+            SetMetadata(super::SetMetadata),
+        }
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SetMetadata {
+        #[prost(map = "string, string", tag = "1")]
+        pub values: ::std::collections::HashMap<
+            ::prost::alloc::string::String,
+            ::prost::alloc::string::String,
+        >,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PortRange {
+        #[prost(uint32, tag = "1")]
+        pub start: u32,
+        /// inclusive
+        #[prost(uint32, tag = "2")]
+        pub end: u32,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Cache {
+        #[prost(uint64, tag = "1")]
+        pub capacity: u64,
+        #[prost(uint64, tag = "2")]
+        pub ttl_ms: u64,
+    }
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Policy {
+        RoundRobin = 0,
+        Random = 1,
+        Hash = 2,
+    }
+    impl Policy {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Policy::RoundRobin => "RoundRobin",
+                Policy::Random => "Random",
+                Policy::Hash => "Hash",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "RoundRobin" => Some(Self::RoundRobin),
+                "Random" => Some(Self::Random),
+                "Hash" => Some(Self::Hash),
+                _ => None,
+            }
+        }
     }
 }
@@ -0,0 +1,32 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Tenants {
+    #[prost(message, optional, tag = "1")]
+    pub metadata_key: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "2")]
+    pub tenants: ::prost::alloc::vec::Vec<tenants::Tenant>,
+    #[prost(message, repeated, tag = "3")]
+    pub fallthrough:
+        ::prost::alloc::vec::Vec<super::super::super::super::envoy::config::listener::v3::Filter>,
+}
+/// Nested message and enum types in `Tenants`.
+pub mod tenants {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Tenant {
+        #[prost(string, tag = "1")]
+        pub name: ::prost::alloc::string::String,
+        #[prost(uint32, repeated, tag = "2")]
+        pub ports: ::prost::alloc::vec::Vec<u32>,
+        #[prost(bytes = "vec", repeated, tag = "3")]
+        pub token_prefixes: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+        #[prost(message, repeated, tag = "4")]
+        pub filters: ::prost::alloc::vec::Vec<
+            super::super::super::super::super::envoy::config::listener::v3::Filter,
+        >,
+        #[prost(message, optional, tag = "5")]
+        pub max_packets_per_second: ::core::option::Option<u64>,
+        #[prost(message, optional, tag = "6")]
+        pub max_clients: ::core::option::Option<u64>,
+    }
+}
@@ -0,0 +1,10 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TokenPush {
+    #[prost(message, optional, tag = "1")]
+    pub metadata_key: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub prefix: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    pub timeout_secs: ::core::option::Option<u64>,
+}
@@ -0,0 +1,17 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TrafficSplit {
+    #[prost(message, repeated, tag = "1")]
+    pub splits: ::prost::alloc::vec::Vec<traffic_split::Split>,
+}
+/// Nested message and enum types in `TrafficSplit`.
+pub mod traffic_split {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Split {
+        #[prost(string, tag = "1")]
+        pub cluster: ::prost::alloc::string::String,
+        #[prost(uint32, tag = "2")]
+        pub weight: u32,
+    }
+}
@@ -362,6 +362,7 @@ impl Pail {
                         (std::net::Ipv4Addr::UNSPECIFIED, server.port).into(),
                         quilkin::net::endpoint::Metadata {
                             tokens: tokens.iter().map(|t| Vec::from(*t)).collect(),
+                            ..<_>::default()
                         },
                     ));
                 }
@@ -499,13 +500,38 @@ impl Pail {
                     components::proxy::Proxy {
                         num_workers: NonZeroUsize::new(1).unwrap(),
                         mmdb: None,
+                        mmdb_refresh: None,
                         to: Vec::new(),
                         to_tokens: None,
                         management_servers,
                         socket,
+                        listener_ports: Vec::new(),
                         qcmp,
                         phoenix,
                         notifier: Some(rttx),
+                        drain_timeout: std::time::Duration::ZERO,
+                        drain_notification: None,
+                        hot_restart: None,
+                        dscp: Default::default(),
+                        overload: Default::default(),
+                        transparent: false,
+                        write_errors: Default::default(),
+                        coalesce: Default::default(),
+                        max_sessions: None,
+                        history: Default::default(),
+                        heatmap: Default::default(),
+                        handshake: Default::default(),
+                        duplicate: Default::default(),
+                        fairness: Default::default(),
+                        ejection: Default::default(),
+                        response_timeout: Default::default(),
+                        admission: Default::default(),
+                        drop_log_sample: 0,
+                        shared_sessions: Default::default(),
+                        tombstones: Default::default(),
+                        warm: Default::default(),
+                        affinity: Default::default(),
+                        address_discovery: None,
                     }
                     .run(
                         RunArgs {
@@ -100,7 +100,10 @@ trace_test!(relay_routing, {
             config.clusters.insert_default(
                 [Endpoint::with_metadata(
                     server_addr.into(),
-                    quilkin::net::endpoint::Metadata { tokens },
+                    quilkin::net::endpoint::Metadata {
+                        tokens,
+                        ..<_>::default()
+                    },
                 )]
                 .into(),
             );
@@ -288,6 +291,7 @@ trace_test!(filter_update, {
                     server_addr.into(),
                     quilkin::net::endpoint::Metadata {
                         tokens: Some(token.clone()).into_iter().collect(),
+                        ..<_>::default()
                     },
                 )]
                 .into(),
@@ -173,6 +173,7 @@ trace_test!(
             config,
             socket,
             pending_sends,
+            Vec::new(),
             &sessions,
             BUFFER_POOL.clone(),
         )
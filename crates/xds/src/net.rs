@@ -49,6 +49,13 @@ impl TcpListener {
     }
 }
 
+impl From<std::net::TcpListener> for TcpListener {
+    #[inline]
+    fn from(inner: std::net::TcpListener) -> Self {
+        Self { inner }
+    }
+}
+
 impl From<TcpListener> for std::net::TcpListener {
     #[inline]
     fn from(value: TcpListener) -> Self {
@@ -27,8 +27,8 @@ use crate::{components::admin::Admin, Config};
 use strum_macros::{Display, EnumString};
 
 pub use self::{
-    agent::Agent, generate_config_schema::GenerateConfigSchema, manage::Manage, proxy::Proxy,
-    qcmp::Qcmp, relay::Relay,
+    agent::Agent, debug::Debug, echo::Echo, generate_config_schema::GenerateConfigSchema,
+    manage::Manage, preflight::Preflight, proxy::Proxy, qcmp::Qcmp, relay::Relay,
 };
 
 macro_rules! define_port {
@@ -42,8 +42,11 @@ macro_rules! define_port {
 }
 
 pub mod agent;
+pub mod debug;
+pub mod echo;
 pub mod generate_config_schema;
 pub mod manage;
+pub mod preflight;
 pub mod proxy;
 pub mod qcmp;
 pub mod relay;
@@ -77,6 +80,40 @@ pub struct Cli {
      .map(|s| s.parse::<LogFormats>().unwrap()),
      )]
     pub log_format: LogFormats,
+    /// Shared libraries providing additional filters, loaded at startup.
+    /// Requires the `filter-plugins` feature.
+    #[clap(
+        long = "filter-plugin",
+        env = "QUILKIN_FILTER_PLUGINS",
+        value_delimiter = ','
+    )]
+    pub filter_plugins: Vec<PathBuf>,
+    /// The namespace every metric's name starts with.
+    #[clap(
+        long,
+        env = "QUILKIN_METRICS_NAMESPACE",
+        default_value = crate::metrics::DEFAULT_NAMESPACE
+    )]
+    pub metrics_namespace: String,
+    /// Static labels added to every metric, such as `region=eu-west`, so
+    /// proxies scraped into the same Prometheus can be told apart.
+    #[clap(
+        long = "metrics-label",
+        env = "QUILKIN_METRICS_LABELS",
+        value_delimiter = ',',
+        value_parser = parse_metrics_label
+    )]
+    pub metrics_labels: Vec<(String, String)>,
+}
+
+/// Parses a `name=value` metric label.
+fn parse_metrics_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((name, value)) => Ok((name.into(), value.into())),
+        None => Err(format!(
+            "`{label}` is not a label, such as `region=eu-west`"
+        )),
+    }
 }
 
 /// The various log format options
@@ -97,8 +134,12 @@ pub enum LogFormats {
 #[derive(Clone, Debug, clap::Subcommand)]
 pub enum Commands {
     Agent(Agent),
+    #[clap(subcommand)]
+    Debug(Debug),
+    Echo(Echo),
     GenerateConfigSchema(GenerateConfigSchema),
     Manage(Manage),
+    Preflight(Preflight),
     #[clap(subcommand)]
     Qcmp(Qcmp),
     Proxy(Proxy),
@@ -134,17 +175,43 @@ impl Cli {
             }
         }
 
+        crate::metrics::configure(
+            &self.metrics_namespace,
+            self.metrics_labels.iter().cloned().collect(),
+        )?;
+
         tracing::info!(
             version = crate_version!(),
             commit = crate::net::endpoint::metadata::build::GIT_COMMIT_HASH,
+            features = ?crate::metrics::enabled_features(),
             "Starting Quilkin"
         );
 
+        let started = std::time::Instant::now();
+        #[cfg(feature = "filter-plugins")]
+        crate::filters::plugin::load(&self.filter_plugins)?;
+        #[cfg(not(feature = "filter-plugins"))]
+        if !self.filter_plugins.is_empty() {
+            eyre::bail!(
+                "filter plugins require quilkin to be built with the `filter-plugins` feature"
+            );
+        }
+        if !self.filter_plugins.is_empty() {
+            crate::metrics::startup_phase("plugins", started.elapsed());
+        }
+
         // Non-long running commands (e.g. ones with no administration server)
         // are executed here.
         use crate::components::{self, admin as admin_server};
         let mode = match &self.command {
             Commands::Qcmp(Qcmp::Ping(ping)) => return ping.run().await,
+            Commands::Echo(echo) => return echo.run().await,
+            Commands::Preflight(preflight) => return preflight.run().await,
+            Commands::Debug(Debug::Repl(repl)) => {
+                let config =
+                    Self::read_config(&self.config)?.unwrap_or_else(Config::default_non_agent);
+                return repl.clone().run(Arc::new(config)).await;
+            }
             Commands::GenerateConfigSchema(generator) => {
                 return generator.generate_config_schema();
             }
@@ -180,6 +247,7 @@ impl Cli {
 
         tracing::debug!(cli = ?self, "config parameters");
 
+        let started = std::time::Instant::now();
         let config = Arc::new(match Self::read_config(self.config)? {
             Some(mut config) => {
                 // Workaround deficiency in serde flatten + untagged
@@ -209,9 +277,12 @@ impl Cli {
             None if matches!(self.command, Commands::Agent(..)) => Config::default_agent(),
             None => Config::default_non_agent(),
         });
+        crate::metrics::startup_phase("config", started.elapsed());
 
         if !self.no_admin {
+            let started = std::time::Instant::now();
             mode.server(config.clone(), self.admin_address);
+            crate::metrics::startup_phase("admin", started.elapsed());
         }
 
         let (shutdown_tx, shutdown_rx) = crate::make_shutdown_channel(Default::default());
@@ -0,0 +1,468 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    io::{BufRead, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use crate::{
+    config::Config,
+    filters::{
+        CreateFilterArgs, Filter as _, FilterError, FilterInstance, FilterRegistry, FilterSet,
+    },
+    net::{
+        endpoint::{DynamicMetadata, EndpointAddress},
+        ClusterMap,
+    },
+    pool::{BufferPool, PoolBuffer},
+};
+
+/// Tools for debugging the behaviour of a configuration.
+#[derive(Clone, Debug, clap::Subcommand)]
+pub enum Debug {
+    Repl(Repl),
+}
+
+/// Loads the configuration's filter chain and lets you interactively inject
+/// packets, step through each filter's `read`, inspect the dynamic metadata
+/// and destinations, and tweak filter configuration between steps.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Repl {
+    /// The source address assigned to injected packets.
+    #[clap(short, long, default_value = "127.0.0.1:7000")]
+    pub source: SocketAddr,
+}
+
+const HELP: &str = "\
+commands:
+  packet <hex>            inject a new packet from hex bytes (whitespace is ignored)
+  text <string>           inject a new packet from a UTF-8 string
+  source <ip:port>        change the source address of injected packets
+  step                    run the next filter's `read` on the current packet
+  run                     run all remaining filters on the current packet
+  reset                   restart the current packet from the first filter
+  show                    print the current packet, destinations, and position
+  metadata                print the dynamic metadata of the current packet
+  keys                    list the registered dynamic metadata keys
+  filters                 list the filters in the chain
+  config <index> <json>   replace the configuration of the filter at <index>
+  help                    print this message
+  quit                    exit the REPL";
+
+impl Repl {
+    pub async fn run(self, config: Arc<Config>) -> crate::Result<()> {
+        tokio::task::spawn_blocking(move || {
+            self.run_with(&config, std::io::stdin().lock(), std::io::stdout())
+        })
+        .await?
+    }
+
+    /// Drives the REPL, reading commands from `input` and writing the results
+    /// to `output` until either `quit` is read or `input` is exhausted.
+    pub fn run_with(
+        &self,
+        config: &Config,
+        input: impl BufRead,
+        mut output: impl Write,
+    ) -> crate::Result<()> {
+        let mut stepper = Stepper::new(config, self.source.into());
+
+        writeln!(
+            output,
+            "loaded {} filter(s), type `help` for a list of commands",
+            stepper.filters.len()
+        )?;
+
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            let (command, args) = line
+                .split_once(char::is_whitespace)
+                .map(|(command, args)| (command, args.trim()))
+                .unwrap_or((line, ""));
+
+            match command {
+                "" => continue,
+                "quit" | "exit" => break,
+                "help" => writeln!(output, "{HELP}")?,
+                "packet" => match decode_hex(args) {
+                    Ok(bytes) => stepper.inject(bytes, &mut output)?,
+                    Err(error) => writeln!(output, "error: {error}")?,
+                },
+                "text" => stepper.inject(args.as_bytes().to_vec(), &mut output)?,
+                "source" => match args.parse::<SocketAddr>() {
+                    Ok(source) => {
+                        stepper.source = source.into();
+                        writeln!(output, "source set to {source}")?;
+                    }
+                    Err(error) => writeln!(output, "error: invalid source address: {error}")?,
+                },
+                "step" => stepper.step(&mut output)?,
+                "run" => {
+                    while stepper.can_step() {
+                        stepper.step(&mut output)?;
+                    }
+                }
+                "reset" => stepper.reset(&mut output)?,
+                "show" => stepper.show(&mut output)?,
+                "metadata" => stepper.show_metadata(&mut output)?,
+                "keys" => show_keys(&mut output)?,
+                "filters" => stepper.show_filters(&mut output)?,
+                "config" => {
+                    let (index, json) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                    stepper.reconfigure(index, json.trim(), &mut output)?;
+                }
+                unknown => writeln!(output, "unknown command `{unknown}`, try `help`")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of a single packet as it moves through the filter chain.
+struct PacketState {
+    original: Vec<u8>,
+    contents: PoolBuffer,
+    metadata: DynamicMetadata,
+    destinations: Vec<EndpointAddress>,
+    next: usize,
+    dropped: Option<FilterError>,
+}
+
+struct Stepper {
+    filters: Vec<(String, FilterInstance)>,
+    /// The filters that reconfigured filters are created from.
+    filter_set: Arc<FilterSet>,
+    endpoints: Arc<ClusterMap>,
+    buffer_pool: Arc<BufferPool>,
+    source: EndpointAddress,
+    packet: Option<PacketState>,
+}
+
+impl Stepper {
+    fn new(config: &Config, source: EndpointAddress) -> Self {
+        let chain = config.filters.load();
+
+        Self {
+            filters: (0..chain.len()).map(|i| chain[i].clone()).collect(),
+            filter_set: config.filter_set(),
+            endpoints: config.clusters.clone_value(),
+            buffer_pool: Arc::new(BufferPool::default()),
+            source,
+            packet: None,
+        }
+    }
+
+    fn can_step(&self) -> bool {
+        self.packet
+            .as_ref()
+            .is_some_and(|p| p.dropped.is_none() && p.next < self.filters.len())
+    }
+
+    fn inject(&mut self, bytes: Vec<u8>, output: &mut impl Write) -> std::io::Result<()> {
+        self.packet = Some(PacketState {
+            contents: self.buffer_pool.clone().alloc_slice(&bytes),
+            original: bytes,
+            metadata: <_>::default(),
+            destinations: Vec::new(),
+            next: 0,
+            dropped: None,
+        });
+
+        self.show(output)
+    }
+
+    fn reset(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        match self.packet.take() {
+            Some(packet) => self.inject(packet.original, output),
+            None => writeln!(output, "no packet injected, use `packet` or `text`"),
+        }
+    }
+
+    fn step(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        let Some(packet) = self.packet.as_mut() else {
+            return writeln!(output, "no packet injected, use `packet` or `text`");
+        };
+
+        if let Some(error) = &packet.dropped {
+            return writeln!(output, "packet was dropped: {error}, use `reset` to retry");
+        }
+
+        let Some((name, instance)) = self.filters.get(packet.next) else {
+            return writeln!(output, "all filters have been run, use `reset` to retry");
+        };
+
+        let contents = std::mem::replace(&mut packet.contents, self.buffer_pool.clone().alloc());
+        let mut ctx = crate::filters::ReadContext::new(
+            self.endpoints.clone(),
+            self.source.clone(),
+            contents,
+            &mut packet.destinations,
+        );
+        ctx.metadata = std::mem::take(&mut packet.metadata);
+
+        let result = instance.filter().read(&mut ctx).and_then(|()| {
+            ctx.drop_reason.map_or(Ok(()), |reason| {
+                Err(crate::filters::FilterError::Discarded(reason))
+            })
+        });
+
+        let crate::filters::ReadContext {
+            contents, metadata, ..
+        } = ctx;
+        packet.contents = contents;
+        packet.metadata = metadata;
+
+        writeln!(output, "[{}] {name}", packet.next)?;
+        packet.next += 1;
+
+        match result {
+            Ok(()) => {
+                // Mirror `FilterChain::read`, which forwards to every endpoint
+                // if no filter has selected any destinations.
+                if packet.next == self.filters.len() && packet.destinations.is_empty() {
+                    packet
+                        .destinations
+                        .extend(self.endpoints.endpoints().into_iter().map(|ep| ep.address));
+                }
+            }
+            Err(error) => {
+                writeln!(output, "dropped: {error}")?;
+                packet.dropped = Some(error);
+            }
+        }
+
+        self.show(output)
+    }
+
+    fn show(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let Some(packet) = &self.packet else {
+            return writeln!(output, "no packet injected, use `packet` or `text`");
+        };
+
+        writeln!(output, "  source:       {}", self.source)?;
+        writeln!(output, "  contents:     {}", encode_hex(&packet.contents))?;
+        writeln!(
+            output,
+            "  as text:      {:?}",
+            String::from_utf8_lossy(&packet.contents)
+        )?;
+        writeln!(
+            output,
+            "  destinations: [{}]",
+            packet
+                .destinations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+
+        let state = if let Some(error) = &packet.dropped {
+            format!("dropped ({error})")
+        } else if packet.next >= self.filters.len() {
+            "complete".into()
+        } else {
+            format!("next filter {}/{}", packet.next, self.filters.len())
+        };
+
+        writeln!(output, "  state:        {state}")
+    }
+
+    fn show_metadata(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let Some(packet) = &self.packet else {
+            return writeln!(output, "no packet injected, use `packet` or `text`");
+        };
+
+        if packet.metadata.is_empty() {
+            return writeln!(output, "  (empty)");
+        }
+
+        let mut entries = packet
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        for (key, value) in entries {
+            writeln!(output, "  {key} = {value}")?;
+        }
+
+        Ok(())
+    }
+
+    fn show_filters(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let next = self.packet.as_ref().map(|p| p.next);
+
+        for (index, (name, instance)) in self.filters.iter().enumerate() {
+            let marker = if Some(index) == next { ">" } else { " " };
+            writeln!(output, "{marker} [{index}] {name} {}", instance.config())?;
+        }
+
+        Ok(())
+    }
+
+    fn reconfigure(
+        &mut self,
+        index: &str,
+        json: &str,
+        output: &mut impl Write,
+    ) -> std::io::Result<()> {
+        let Some(index) = index
+            .parse::<usize>()
+            .ok()
+            .filter(|i| *i < self.filters.len())
+        else {
+            return writeln!(output, "error: `{index}` is not a valid filter index");
+        };
+
+        let config = match json {
+            "" | "null" => None,
+            json => match serde_json::from_str(json) {
+                Ok(config) => Some(config),
+                Err(error) => return writeln!(output, "error: invalid JSON: {error}"),
+            },
+        };
+
+        let name = &self.filters[index].0;
+        match FilterRegistry::get_with(&self.filter_set, name, CreateFilterArgs::fixed(config)) {
+            Ok(instance) => {
+                writeln!(output, "[{index}] {name} reconfigured")?;
+                self.filters[index].1 = instance;
+            }
+            Err(error) => writeln!(output, "error: {error}")?,
+        }
+
+        Ok(())
+    }
+}
+
+fn show_keys(output: &mut impl Write) -> std::io::Result<()> {
+    let keys = crate::net::endpoint::metadata::registered_keys();
+    if keys.is_empty() {
+        return writeln!(output, "  (none)");
+    }
+
+    for schema in keys {
+        writeln!(
+            output,
+            "  {} ({:?}): {}",
+            schema.key, schema.kind, schema.description
+        )?;
+    }
+
+    Ok(())
+}
+
+fn decode_hex(input: &str) -> Result<Vec<u8>, String> {
+    let digits = input
+        .split_whitespace()
+        .map(|chunk| chunk.strip_prefix("0x").unwrap_or(chunk))
+        .collect::<String>();
+
+    if digits.is_empty() {
+        return Err("no bytes provided".into());
+    }
+
+    if digits.len() % 2 != 0 {
+        return Err("hex input must contain an even number of digits".into());
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("`{}` is not a valid hex byte", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+
+    bytes.iter().fold(String::new(), |mut s, byte| {
+        let _ = write!(s, "{byte:02x}");
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::{FilterChain, StaticFilter};
+
+    fn run(config: &Config, input: &str) -> String {
+        let mut output = Vec::new();
+        Repl {
+            source: "127.0.0.1:7000".parse().unwrap(),
+        }
+        .run_with(config, input.as_bytes(), &mut output)
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(decode_hex("68 65 6c6c 0x6f").unwrap(), b"hello");
+        assert!(decode_hex("123").is_err());
+        assert!(decode_hex("zz").is_err());
+        assert_eq!(encode_hex(b"hello"), "68656c6c6f");
+    }
+
+    #[test]
+    fn step_through_chain() {
+        crate::test::load_test_filters();
+        let config = Config::default_non_agent();
+        config
+            .clusters
+            .modify(|clusters| clusters.insert_default(["127.0.0.1:8080".parse().unwrap()].into()));
+        config.filters.store(Arc::new(
+            FilterChain::try_create([
+                crate::test::TestFilter::as_filter_config(None).unwrap(),
+                crate::filters::Pass::as_filter_config(None).unwrap(),
+            ])
+            .unwrap(),
+        ));
+
+        let output = run(&config, "packet 68656c6c6f\nstep\nmetadata\nrun\nquit\n");
+
+        assert!(output.contains("loaded 2 filter(s)"));
+        assert!(output.contains(&encode_hex(b"hello:odr:127.0.0.1:7000")));
+        assert!(output.contains("downstream = receive"));
+        assert!(output.contains("destinations: [127.0.0.1:8080]"));
+        assert!(output.contains("state:        complete"));
+    }
+
+    #[test]
+    fn reconfigure_between_steps() {
+        let config = Config::default_non_agent();
+        config.filters.store(Arc::new(
+            FilterChain::try_create([crate::filters::Drop::as_filter_config(None).unwrap()])
+                .unwrap(),
+        ));
+
+        let output = run(&config, "text hi\nstep\nconfig 0 {}\nconfig 9 {}\nreset\n");
+
+        assert!(output.contains("dropped: dropped"));
+        assert!(output.contains("[0] quilkin.filters.drop.v1alpha1.Drop reconfigured"));
+        assert!(output.contains("error: `9` is not a valid filter index"));
+        assert!(output.contains("state:        next filter 0/1"));
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::net::UdpSocket;
+
+/// The largest packet echoed.
+const MAX_PACKET_SIZE: usize = 65535;
+
+/// Runs a UDP server that sends every packet it receives back to its
+/// sender, optionally delayed or dropped, as an upstream for tests, demos and
+/// load tests.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Echo {
+    /// The port to listen on.
+    #[clap(short, long, env = "QUILKIN_ECHO_PORT", default_value_t = 9000)]
+    pub port: u16,
+    /// How long to wait before echoing each packet, such as `5ms`.
+    #[clap(long, env = "QUILKIN_ECHO_LATENCY", value_parser = parse_duration)]
+    pub latency: Option<Duration>,
+    /// The share of packets dropped rather than echoed, such as `1%`.
+    #[clap(long, env = "QUILKIN_ECHO_LOSS", value_parser = parse_loss, default_value = "0%")]
+    pub loss: f64,
+}
+
+impl Echo {
+    pub async fn run(&self) -> crate::Result<()> {
+        let socket = crate::net::raw_socket_with_reuse(self.port)
+            .map(From::from)
+            .and_then(UdpSocket::from_std)?;
+        let socket = Arc::new(socket);
+        tracing::info!(port = self.port, latency = ?self.latency, loss = self.loss, "echoing packets");
+
+        let shutdown = tokio::signal::ctrl_c();
+        tokio::pin!(shutdown);
+
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        loop {
+            let (size, source) = tokio::select! {
+                result = socket.recv_from(&mut buffer) => match result {
+                    Ok(received) => received,
+                    Err(error) => {
+                        tracing::warn!(%error, "failed to receive packet");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => return Ok(()),
+            };
+
+            if self.loss > 0.0 && rand::random::<f64>() < self.loss {
+                tracing::trace!(%source, size, "dropping packet");
+                continue;
+            }
+
+            let packet = buffer[..size].to_vec();
+            match self.latency {
+                Some(latency) => {
+                    let socket = socket.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(latency).await;
+                        send(&socket, &packet, source).await;
+                    });
+                }
+                None => send(&socket, &packet, source).await,
+            }
+        }
+    }
+}
+
+async fn send(socket: &UdpSocket, packet: &[u8], destination: SocketAddr) {
+    if let Err(error) = socket.send_to(packet, destination).await {
+        tracing::warn!(%error, %destination, "failed to echo packet");
+    }
+}
+
+/// Parses a duration with a unit, such as `5ms`, `250us` or `1s`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(end);
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("`{value}` is not a duration, such as `5ms`"))?;
+
+    let seconds = match unit.trim() {
+        "us" | "µs" => amount / 1_000_000.0,
+        "ms" => amount / 1_000.0,
+        "s" => amount,
+        unit => return Err(format!("unknown unit `{unit}`, expected `us`, `ms` or `s`")),
+    };
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses a percentage, such as `1%` or `0.5%`, into a fraction.
+fn parse_loss(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| format!("`{value}` is not a percentage, such as `1%`"))?;
+
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("`{value}` is not between 0% and 100%"));
+    }
+
+    Ok(percent / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("5ms"), Ok(Duration::from_millis(5)));
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
+    #[test]
+    fn losses() {
+        assert_eq!(parse_loss("1%"), Ok(0.01));
+        assert_eq!(parse_loss("50"), Ok(0.5));
+        assert!(parse_loss("101%").is_err());
+        assert!(parse_loss("lots").is_err());
+    }
+}
@@ -0,0 +1,299 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::{Duration, SystemTime};
+
+use tonic::transport::Endpoint;
+
+use crate::{components::proxy::SessionStore, net::maxmind_db};
+
+/// Checks that the proxy's dependencies are usable before it serves traffic,
+/// printing a JSON report of each check and failing if any of them failed.
+///
+/// Accepts the same environment variables as `quilkin proxy`, so it can be
+/// run with a proxy's environment, e.g. as an init container.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Preflight {
+    /// The `quilkin manage` endpoints that must be reachable.
+    #[clap(long, env = "QUILKIN_MANAGEMENT_SERVER")]
+    pub management_server: Vec<Endpoint>,
+    /// The remote URL or local file path of the Maxmind database that must
+    /// be readable.
+    #[clap(long, env)]
+    pub mmdb: Option<maxmind_db::Source>,
+    /// How old, in days, the Maxmind database can be before it's reported
+    /// as stale.
+    #[clap(long, env = "QUILKIN_MMDB_MAX_AGE_DAYS", default_value_t = 30)]
+    pub mmdb_max_age_days: u64,
+    /// The port the proxy listens on, which must be bindable.
+    #[clap(long, env = super::PORT_ENV_VAR, default_value_t = super::proxy::PORT)]
+    pub port: u16,
+    /// The port QCMP listens on, which must be bindable.
+    #[clap(long, env = "QUILKIN_QCMP_PORT", default_value_t = super::proxy::QCMP_PORT)]
+    pub qcmp_port: u16,
+    /// The store of established clients, which must be readable.
+    #[clap(long, env = "QUILKIN_SHARED_SESSIONS")]
+    pub shared_sessions: Option<SessionStore>,
+    /// How long, in seconds, each check has to finish before it fails.
+    #[clap(long, env = "QUILKIN_PREFLIGHT_TIMEOUT_SECS", default_value_t = 5)]
+    pub timeout_secs: u64,
+}
+
+impl Default for Preflight {
+    fn default() -> Self {
+        Self {
+            management_server: <_>::default(),
+            mmdb: None,
+            mmdb_max_age_days: 30,
+            port: super::proxy::PORT,
+            qcmp_port: super::proxy::QCMP_PORT,
+            shared_sessions: None,
+            timeout_secs: 5,
+        }
+    }
+}
+
+impl From<&super::Proxy> for Preflight {
+    fn from(proxy: &super::Proxy) -> Self {
+        Self {
+            management_server: proxy.management_server.clone(),
+            mmdb: proxy.mmdb.clone(),
+            port: proxy.port,
+            qcmp_port: proxy.qcmp_port,
+            shared_sessions: proxy.shared_sessions.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Preflight {
+    /// Runs every check and prints the report to stdout.
+    pub async fn run(&self) -> crate::Result<()> {
+        let report = self.report().await;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        report.ensure_passed()
+    }
+
+    /// Runs every check.
+    pub async fn report(&self) -> Report {
+        let mut checks = vec![self.ports()];
+        checks.extend(self.management_servers().await);
+        if let Some(source) = &self.mmdb {
+            checks.push(self.maxmind_db(source.clone()).await);
+        }
+        if let Some(store) = &self.shared_sessions {
+            checks.push(self.session_store(store.clone()).await);
+        }
+
+        Report {
+            passed: checks.iter().all(|check| check.status != Status::Failed),
+            checks,
+        }
+    }
+
+    fn ports(&self) -> Check {
+        let bound = crate::net::raw_socket_with_reuse(self.port)
+            .map_err(|error| format!("udp port {}: {error}", self.port))
+            .and_then(|_| {
+                crate::net::raw_socket_with_reuse(self.qcmp_port)
+                    .map_err(|error| format!("udp port {}: {error}", self.qcmp_port))
+            })
+            .and_then(|_| {
+                crate::net::TcpListener::bind(Some(self.qcmp_port))
+                    .map_err(|error| format!("tcp port {}: {error}", self.qcmp_port))
+            });
+
+        match bound {
+            Ok(_) => Check::passed("ports"),
+            Err(detail) => Check::failed("ports", detail),
+        }
+    }
+
+    /// Checks each management server can be connected to. Unreachable
+    /// servers only fail the check when none of them are reachable, as the
+    /// proxy fails over between them.
+    async fn management_servers(&self) -> Vec<Check> {
+        let mut checks = Vec::with_capacity(self.management_server.len());
+        for endpoint in &self.management_server {
+            let name = format!("management_server:{}", endpoint.uri());
+            let connected = self.timeout(async {
+                endpoint
+                    .connect()
+                    .await
+                    .map(drop)
+                    .map_err(|error| eyre::eyre!(error))
+            });
+            checks.push(match connected.await {
+                Ok(()) => Check::passed(name),
+                Err(error) => Check::warning(name, error.to_string()),
+            });
+        }
+
+        if checks.iter().all(|check| check.status == Status::Warning) {
+            for check in &mut checks {
+                check.status = Status::Failed;
+            }
+        }
+
+        checks
+    }
+
+    /// Checks the Maxmind database can be read, warning when it was built
+    /// longer ago than the maximum age.
+    async fn maxmind_db(&self, source: maxmind_db::Source) -> Check {
+        let db = async { Ok::<_, eyre::Error>(maxmind_db::MaxmindDb::from_source(source).await?) };
+        let db = match self.timeout(db).await {
+            Ok(db) => db,
+            Err(error) => return Check::failed("mmdb", error.to_string()),
+        };
+
+        let built = SystemTime::UNIX_EPOCH + Duration::from_secs(db.metadata.build_epoch);
+        let age = SystemTime::now().duration_since(built).unwrap_or_default();
+        let max_age = Duration::from_secs(self.mmdb_max_age_days * 24 * 60 * 60);
+        if age > max_age {
+            let days = age.as_secs() / (24 * 60 * 60);
+            Check::warning("mmdb", format!("built {days} days ago"))
+        } else {
+            Check::passed("mmdb")
+        }
+    }
+
+    async fn session_store(&self, store: SessionStore) -> Check {
+        let probe = crate::components::proxy::shared_sessions::probe(store);
+        match self.timeout(probe).await {
+            Ok(()) => Check::passed("shared_sessions"),
+            Err(error) => Check::failed("shared_sessions", error.to_string()),
+        }
+    }
+
+    async fn timeout<T>(
+        &self,
+        future: impl std::future::Future<Output = eyre::Result<T>>,
+    ) -> eyre::Result<T> {
+        tokio::time::timeout(Duration::from_secs(self.timeout_secs), future)
+            .await
+            .map_err(|_| eyre::eyre!("timed out after {}s", self.timeout_secs))?
+    }
+}
+
+/// The result of every preflight check.
+#[derive(Debug, serde::Serialize)]
+pub struct Report {
+    /// Whether none of the checks failed.
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns an error naming every failed check, if any failed.
+    pub fn ensure_passed(&self) -> crate::Result<()> {
+        let failed: Vec<_> = self
+            .checks
+            .iter()
+            .filter(|check| check.status == Status::Failed)
+            .map(|check| check.name.as_str())
+            .collect();
+
+        eyre::ensure!(
+            failed.is_empty(),
+            "preflight checks failed: {}",
+            failed.join(", ")
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    /// Why the check failed or warned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn passed(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Passed,
+            detail: None,
+        }
+    }
+
+    fn warning(name: impl Into<String>, detail: String) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Warning,
+            detail: Some(detail),
+        }
+    }
+
+    fn failed(name: impl Into<String>, detail: String) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Failed,
+            detail: Some(detail),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Passed,
+    /// Something is wrong, but the proxy can still serve traffic.
+    Warning,
+    Failed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fails_on_unbindable_port() {
+        let taken = std::net::UdpSocket::bind((std::net::Ipv6Addr::UNSPECIFIED, 0)).unwrap();
+        let preflight = Preflight {
+            port: taken.local_addr().unwrap().port(),
+            qcmp_port: 0,
+            ..<_>::default()
+        };
+
+        let report = preflight.report().await;
+        assert!(!report.passed);
+        assert_eq!(report.checks[0].status, Status::Failed);
+        assert!(report.ensure_passed().is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_on_missing_mmdb() {
+        let preflight = Preflight {
+            port: 0,
+            qcmp_port: 0,
+            mmdb: Some(maxmind_db::Source::File {
+                path: "/nonexistent/GeoLite2-ASN.mmdb".into(),
+            }),
+            ..<_>::default()
+        };
+
+        let report = preflight.report().await;
+        assert!(!report.passed);
+        assert_eq!(report.checks[0].status, Status::Passed);
+        assert_eq!(report.checks[1].name, "mmdb");
+        assert_eq!(report.checks[1].status, Status::Failed);
+    }
+}
@@ -14,7 +14,6 @@
  * limitations under the License.
  */
 
-use std::net::SocketAddr;
 use tonic::transport::Endpoint;
 
 #[cfg(doc)]
@@ -26,7 +25,7 @@ pub use crate::components::proxy::Ready;
 
 define_port!(7777);
 
-const QCMP_PORT: u16 = 7600;
+pub(crate) const QCMP_PORT: u16 = 7600;
 
 /// Run Quilkin as a UDP reverse proxy.
 #[derive(clap::Args, Clone, Debug)]
@@ -37,15 +36,27 @@ pub struct Proxy {
     /// The remote URL or local file path to retrieve the Maxmind database.
     #[clap(long, env)]
     pub mmdb: Option<crate::net::maxmind_db::Source>,
+    /// How often, in seconds, the Maxmind database is retrieved again, such
+    /// as for routes matching autonomous systems to follow its updates. Only
+    /// retrieved once when unset or zero.
+    #[clap(long, env = "QUILKIN_MMDB_REFRESH_INTERVAL_SECS", requires("mmdb"))]
+    pub mmdb_refresh_interval_secs: Option<u64>,
     /// The port to listen on.
     #[clap(short, long, env = super::PORT_ENV_VAR, default_value_t = PORT)]
     pub port: u16,
     /// The port to listen on.
     #[clap(short, long, env = "QUILKIN_QCMP_PORT", default_value_t = QCMP_PORT)]
     pub qcmp_port: u16,
-    /// One or more socket addresses to forward packets to.
-    #[clap(long, env = "QUILKIN_DEST")]
-    pub to: Vec<SocketAddr>,
+    /// Other ports to receive packets from clients on, such as those of a
+    /// `Listeners` filter's listeners. The ports of the `Listeners` filters
+    /// in the configuration at startup are received on as well.
+    #[clap(long, env = "QUILKIN_LISTENER_PORTS", value_delimiter = ',')]
+    pub listener_port: Vec<u16>,
+    /// One or more addresses to forward packets to. Hostnames are resolved
+    /// to every address they have, and resolved again when their records
+    /// expire. The names of SRV records can leave out the port.
+    #[clap(long, env = "QUILKIN_DEST", value_parser = crate::net::EndpointAddress::parse_with_srv)]
+    pub to: Vec<crate::net::EndpointAddress>,
     /// Assigns dynamic tokens to each address in the `--to` argument
     ///
     /// Format is `<number of unique tokens>:<length of token suffix for each packet>`
@@ -59,6 +70,299 @@ pub struct Proxy {
     /// to number of cpus.
     #[clap(short, long, env = "QUILKIN_WORKERS")]
     pub workers: Option<std::num::NonZeroUsize>,
+    /// How long in seconds to keep forwarding traffic for established sessions
+    /// after receiving a shutdown signal. New sessions are rejected while
+    /// draining. Defaults to `0`, which disables draining.
+    #[clap(long, env = "QUILKIN_DRAIN_TIMEOUT_SECS", default_value_t = 0)]
+    pub drain_timeout_secs: u64,
+    /// A base64 encoded payload sent once to every client with an established
+    /// session when draining begins, e.g. to tell them the server is migrating.
+    #[clap(long, env = "QUILKIN_DRAIN_NOTIFICATION", value_parser = parse_base64)]
+    pub drain_notification: Option<Base64Payload>,
+    /// The path of a Unix domain socket used to hand off the proxy's sockets
+    /// and sessions to a new process during an upgrade. On startup, if another
+    /// proxy is listening on this path, its sockets are taken over and it
+    /// begins draining.
+    #[clap(long, env = "QUILKIN_HOT_RESTART_SOCKET")]
+    pub hot_restart_socket: Option<std::path::PathBuf>,
+    /// The DSCP to mark packets sent to upstream endpoints with, either a
+    /// name such as `EF` or `AF41`, or a number between 0 and 63. Filters can
+    /// override this per packet through the `quilkin.dev/dscp` metadata key.
+    #[clap(long, env = "QUILKIN_UPSTREAM_DSCP")]
+    pub upstream_dscp: Option<crate::net::dscp::Dscp>,
+    /// The DSCP to mark packets sent back to clients with, either a name such
+    /// as `EF` or `AF41`, or a number between 0 and 63. Filters can override
+    /// this per packet through the `quilkin.dev/dscp` metadata key.
+    #[clap(long, env = "QUILKIN_DOWNSTREAM_DSCP")]
+    pub downstream_dscp: Option<crate::net::dscp::Dscp>,
+    /// The most time in microseconds a packet may spend being processed,
+    /// from being received to being queued for sending, before it is shed.
+    /// By default packets are never shed for taking too long.
+    #[clap(long, env = "QUILKIN_PACKET_BUDGET_MICROS")]
+    pub packet_budget_micros: Option<u64>,
+    /// The most packets that may be waiting to be sent on a single socket,
+    /// further packets are shed until the queue has drained. By default
+    /// queues are unbounded.
+    #[clap(long, env = "QUILKIN_MAX_SEND_QUEUE_DEPTH")]
+    pub max_send_queue_depth: Option<usize>,
+    /// Forwards packets to upstreams from each client's own address, rather
+    /// than one of the proxy's, so upstreams see the real address of every
+    /// client. Requires `CAP_NET_ADMIN`, and routing that sends the upstreams'
+    /// replies back through the proxy. Only supported on Linux.
+    #[clap(long, env = "QUILKIN_TRANSPARENT")]
+    pub transparent: bool,
+    /// What to do when a packet from an upstream fails a filter or can't be
+    /// sent to its client, either `log` to log the error, at most once every
+    /// few seconds, or `drop` to only count it in the metrics.
+    #[clap(long, env = "QUILKIN_WRITE_ERROR_POLICY", default_value_t)]
+    pub write_error_policy: crate::components::proxy::WriteErrorPolicy,
+    /// Sends a packet that couldn't be sent to its client once more before
+    /// dropping it.
+    #[clap(long, env = "QUILKIN_RETRY_FAILED_WRITES")]
+    pub retry_failed_writes: bool,
+    /// Merges packets sent back to the same client into datagrams of up to
+    /// this many bytes. Clients must be able to split the merged datagrams
+    /// back into packets. By default packets are never merged.
+    #[clap(long, env = "QUILKIN_COALESCE_MAX_BYTES")]
+    pub coalesce_max_bytes: Option<usize>,
+    /// The time in microseconds to wait for more packets to merge before
+    /// sending packets back to clients, when merging is enabled with
+    /// `--coalesce-max-bytes`.
+    #[clap(long, env = "QUILKIN_COALESCE_MAX_DELAY_MICROS", default_value_t = 0)]
+    pub coalesce_max_delay_micros: u64,
+    /// The number of active sessions beyond which the proxy reports itself as
+    /// not ready, so load balancers and autoscalers stop sending it new
+    /// clients. Sessions beyond the limit are still served.
+    #[clap(long, env = "QUILKIN_MAX_SESSIONS")]
+    pub max_sessions: Option<usize>,
+    /// The number of the most recent packets from clients each worker keeps
+    /// in memory, to be dumped through the admin server's `/history`
+    /// endpoint after an incident. Zero disables the history.
+    #[clap(
+        long,
+        env = "QUILKIN_PACKET_HISTORY",
+        default_value_t = crate::components::proxy::history::DEFAULT_CAPACITY
+    )]
+    pub packet_history: usize,
+    /// Keeps a hash of the first bytes of each packet in the history, rather
+    /// than the bytes themselves.
+    #[clap(long, env = "QUILKIN_PACKET_HISTORY_HASH_CONTENTS")]
+    pub packet_history_hash_contents: bool,
+    /// Tracks the latency of packets for each source `/16` (IPv4) or `/32`
+    /// (IPv6) prefix over this many seconds, to be reported through the
+    /// admin server's `/heatmap` endpoint. Disabled when unset.
+    #[clap(long, env = "QUILKIN_LATENCY_HEATMAP_SECS")]
+    pub latency_heatmap_secs: Option<u64>,
+    /// The most source prefixes tracked by `--latency-heatmap-secs` at once.
+    #[clap(
+        long,
+        env = "QUILKIN_LATENCY_HEATMAP_MAX_PREFIXES",
+        default_value_t = crate::components::proxy::heatmap::DEFAULT_MAX_PREFIXES
+    )]
+    pub latency_heatmap_max_prefixes: usize,
+    /// Holds back packets from each client until a filter has marked it as
+    /// established through the `quilkin.dev/established` metadata key.
+    #[clap(long, env = "QUILKIN_REQUIRE_HANDSHAKE")]
+    pub require_handshake: bool,
+    /// The number of packets each client can send before being established,
+    /// when `--require-handshake` is set. Any more are dropped until the
+    /// client has been idle for a minute.
+    #[clap(
+        long,
+        env = "QUILKIN_HANDSHAKE_PACKET_BUDGET",
+        default_value_t = crate::components::proxy::handshake::DEFAULT_PACKET_BUDGET
+    )]
+    pub handshake_packet_budget: u32,
+    /// The number of packets at the start of each session that are sent to
+    /// the upstream twice, so a lost handshake packet doesn't cost the client
+    /// a retry. Upstreams must ignore duplicate packets.
+    #[clap(long, env = "QUILKIN_DUPLICATE_FIRST_PACKETS", default_value_t = 0)]
+    pub duplicate_first_packets: u32,
+    /// How long after the original, in milliseconds, each duplicate is sent.
+    #[clap(long, env = "QUILKIN_DUPLICATE_DELAY_MS", default_value_t = 10)]
+    pub duplicate_delay_ms: u64,
+    /// Queues packets from each client separately and handles them in turn,
+    /// so a single client sending more than its share can't starve the
+    /// others. Only supported on Linux.
+    #[clap(long, env = "QUILKIN_FAIR_QUEUE")]
+    pub fair_queue: bool,
+    /// The number of bytes from each client handled per round when
+    /// `--fair-queue` is set.
+    #[clap(
+        long,
+        env = "QUILKIN_FAIR_QUEUE_QUANTUM",
+        default_value_t = crate::components::proxy::fair_queue::DEFAULT_QUANTUM
+    )]
+    pub fair_queue_quantum: usize,
+    /// The most packets queued from a single client when `--fair-queue` is
+    /// set, any more from it are dropped.
+    #[clap(
+        long,
+        env = "QUILKIN_FAIR_QUEUE_MAX_PER_SOURCE",
+        default_value_t = crate::components::proxy::fair_queue::DEFAULT_MAX_PER_SOURCE
+    )]
+    pub fair_queue_max_per_source: usize,
+    /// The most packets queued from all clients per worker when
+    /// `--fair-queue` is set, any more are dropped.
+    #[clap(
+        long,
+        env = "QUILKIN_FAIR_QUEUE_CAPACITY",
+        default_value_t = crate::components::proxy::fair_queue::DEFAULT_CAPACITY
+    )]
+    pub fair_queue_capacity: usize,
+    /// Stops sending packets to upstreams reported unreachable by ICMP
+    /// errors, such as a game server that has exited, for a while. Only
+    /// supported on Linux.
+    #[clap(long, env = "QUILKIN_EJECT_UNREACHABLE_UPSTREAMS")]
+    pub eject_unreachable_upstreams: bool,
+    /// The number of ICMP errors within the ejection duration after which an
+    /// upstream is ejected, when `--eject-unreachable-upstreams` is set.
+    #[clap(
+        long,
+        env = "QUILKIN_EJECTION_THRESHOLD",
+        default_value_t = crate::components::proxy::ejection::DEFAULT_THRESHOLD
+    )]
+    pub ejection_threshold: u32,
+    /// How long, in seconds, an unreachable upstream is ejected for.
+    #[clap(
+        long,
+        env = "QUILKIN_EJECTION_DURATION_SECS",
+        default_value_t = crate::components::proxy::ejection::DEFAULT_DURATION.as_secs()
+    )]
+    pub ejection_duration_secs: u64,
+    /// Flags sessions whose upstream hasn't responded within this many
+    /// milliseconds of a packet being sent to it, catching endpoints that
+    /// black hole traffic.
+    #[clap(long, env = "QUILKIN_RESPONSE_TIMEOUT_MS")]
+    pub response_timeout_ms: Option<u64>,
+    /// Sets `quilkin.dev/unresponsive` in the metadata of packets from
+    /// clients with a flagged session, so filters can route them elsewhere.
+    #[clap(long, env = "QUILKIN_RESPONSE_TIMEOUT_METADATA")]
+    pub response_timeout_metadata: bool,
+    /// Stops sending new sessions to endpoints that have as many sessions as
+    /// the `max_sessions` in their metadata, or a `cpu_score` of `100`.
+    #[clap(long, env = "QUILKIN_ADMISSION_CONTROL")]
+    pub admission_control: bool,
+    /// A base64 encoded payload sent back to clients whose new session
+    /// wasn't admitted, e.g. to tell them to try another server.
+    #[clap(
+        long,
+        env = "QUILKIN_ADMISSION_NACK",
+        requires("admission_control"),
+        value_parser = parse_base64
+    )]
+    pub admission_nack: Option<Base64Payload>,
+    /// Logs one in every this many dropped packets of each drop reason, such
+    /// as `denied` or `rate_limited`. Dropped packets are only counted in the
+    /// metrics when zero, the default.
+    #[clap(long, env = "QUILKIN_DROP_LOG_SAMPLE", default_value_t = 0)]
+    pub drop_log_sample: u32,
+    /// Shares which clients have completed their handshake with other
+    /// proxies through a store, either `redis://host:port` or
+    /// `dynamodb://table`, so clients moved between proxies don't have to
+    /// handshake again. Sessions themselves aren't shared. Requires
+    /// `--require-handshake`.
+    #[clap(long, env = "QUILKIN_SHARED_SESSIONS", requires("require_handshake"))]
+    pub shared_sessions: Option<crate::components::proxy::SessionStore>,
+    /// How long, in seconds, a client stays established in the
+    /// `--shared-sessions` store after it was last written.
+    #[clap(
+        long,
+        env = "QUILKIN_SHARED_SESSIONS_TTL_SECS",
+        default_value_t = crate::components::proxy::shared_sessions::DEFAULT_TTL.as_secs()
+    )]
+    pub shared_sessions_ttl_secs: u64,
+    /// Remembers endpoints removed from the config for this many seconds,
+    /// dropping packets from their clients with the `endpoint removed`
+    /// reason rather than as having no endpoints.
+    #[clap(long, env = "QUILKIN_ENDPOINT_TOMBSTONE_SECS")]
+    pub endpoint_tombstone_secs: Option<u64>,
+    /// A base64 encoded payload sent back once to each client of a removed
+    /// endpoint, e.g. to tell it to reconnect.
+    #[clap(
+        long,
+        env = "QUILKIN_ENDPOINT_REMOVED_NOTIFICATION",
+        requires("endpoint_tombstone_secs"),
+        value_parser = parse_base64
+    )]
+    pub endpoint_removed_notification: Option<Base64Payload>,
+    /// Keeps this many upstream sockets that no session is using ready for
+    /// each way upstream sockets are bound, so new sessions don't wait for
+    /// their socket to be created.
+    #[clap(long, env = "QUILKIN_SPARE_UPSTREAM_SOCKETS", default_value_t = 0)]
+    pub spare_upstream_sockets: usize,
+    /// A base64 encoded payload sent from a spare socket to each endpoint
+    /// added to the config, e.g. to open the path to it through firewalls.
+    #[clap(
+        long,
+        env = "QUILKIN_UPSTREAM_WARM_UP_PROBE",
+        requires("spare_upstream_sockets"),
+        value_parser = parse_base64
+    )]
+    pub upstream_warm_up_probe: Option<Base64Payload>,
+    /// How a client's packets are matched to its sessions, either `address`
+    /// to match by its IP address and port, or `ip` to match by its IP
+    /// address only, so a client whose port is changed by a NAT keeps its
+    /// sessions.
+    #[clap(long, env = "QUILKIN_SESSION_AFFINITY", default_value_t)]
+    pub session_affinity: crate::components::proxy::SessionAffinity,
+    /// Checkpoints the state of the filters, such as rate limit buckets, to
+    /// a file or `redis://host:port/key`, and restores it on startup, so
+    /// clients don't start afresh when the proxy restarts.
+    #[clap(long, env = "QUILKIN_FILTER_STATE_CHECKPOINT")]
+    pub filter_state_checkpoint: Option<crate::components::proxy::CheckpointStore>,
+    /// How often, in seconds, the state of the filters is checkpointed.
+    #[clap(
+        long,
+        env = "QUILKIN_FILTER_STATE_CHECKPOINT_SECS",
+        default_value_t = crate::components::proxy::checkpoint::DEFAULT_INTERVAL.as_secs()
+    )]
+    pub filter_state_checkpoint_secs: u64,
+    /// Periodically runs a probe through the filters to this endpoint and
+    /// back, exporting whether it succeeded as a metric.
+    #[clap(
+        long,
+        env = "QUILKIN_SYNTHETIC_PROBE_CANARY",
+        requires("synthetic_probe")
+    )]
+    pub synthetic_probe_canary: Option<std::net::SocketAddr>,
+    /// A base64 encoded payload of each synthetic probe, repeated to send
+    /// several in order, e.g. a handshake followed by data.
+    #[clap(
+        long,
+        env = "QUILKIN_SYNTHETIC_PROBE",
+        requires("synthetic_probe_canary"),
+        value_parser = parse_base64
+    )]
+    pub synthetic_probe: Vec<Base64Payload>,
+    /// How often, in seconds, a synthetic probe is run.
+    #[clap(
+        long,
+        env = "QUILKIN_SYNTHETIC_PROBE_INTERVAL_SECS",
+        default_value_t = crate::components::proxy::synthetic::DEFAULT_INTERVAL.as_secs()
+    )]
+    pub synthetic_probe_interval_secs: u64,
+    /// How long, in milliseconds, the canary has to reply to each payload of
+    /// a synthetic probe.
+    #[clap(
+        long,
+        env = "QUILKIN_SYNTHETIC_PROBE_TIMEOUT_MS",
+        default_value_t = crate::components::proxy::synthetic::DEFAULT_TIMEOUT.as_millis() as u64
+    )]
+    pub synthetic_probe_timeout_ms: u64,
+    /// Runs the checks of `quilkin preflight` against this proxy's
+    /// configuration before starting, and fails to start if any of them
+    /// failed.
+    #[clap(long, env = "QUILKIN_PREFLIGHT")]
+    pub preflight: bool,
+    /// The port to reply on to address discovery requests with the public
+    /// address each client is seen from, disabled when unset.
+    #[clap(long, env = "QUILKIN_ADDRESS_DISCOVERY_PORT")]
+    pub address_discovery_port: Option<u16>,
+    /// The ICAO code of this proxy's region, included in address discovery
+    /// replies.
+    #[clap(long, env = "QUILKIN_ICAO_CODE", requires("address_discovery_port"))]
+    pub icao_code: Option<crate::config::IcaoCode>,
 }
 
 impl Default for Proxy {
@@ -66,12 +370,67 @@ impl Default for Proxy {
         Self {
             management_server: <_>::default(),
             mmdb: <_>::default(),
+            mmdb_refresh_interval_secs: None,
             port: PORT,
             qcmp_port: QCMP_PORT,
+            listener_port: Vec::new(),
             to: <_>::default(),
             to_tokens: None,
             idle_request_interval_secs: None,
             workers: None,
+            drain_timeout_secs: 0,
+            drain_notification: None,
+            hot_restart_socket: None,
+            upstream_dscp: None,
+            downstream_dscp: None,
+            packet_budget_micros: None,
+            max_send_queue_depth: None,
+            transparent: false,
+            write_error_policy: Default::default(),
+            retry_failed_writes: false,
+            coalesce_max_bytes: None,
+            coalesce_max_delay_micros: 0,
+            max_sessions: None,
+            packet_history: crate::components::proxy::history::DEFAULT_CAPACITY,
+            packet_history_hash_contents: false,
+            latency_heatmap_secs: None,
+            latency_heatmap_max_prefixes: crate::components::proxy::heatmap::DEFAULT_MAX_PREFIXES,
+            require_handshake: false,
+            handshake_packet_budget: crate::components::proxy::handshake::DEFAULT_PACKET_BUDGET,
+            duplicate_first_packets: 0,
+            duplicate_delay_ms: 10,
+            fair_queue: false,
+            fair_queue_quantum: crate::components::proxy::fair_queue::DEFAULT_QUANTUM,
+            fair_queue_max_per_source: crate::components::proxy::fair_queue::DEFAULT_MAX_PER_SOURCE,
+            fair_queue_capacity: crate::components::proxy::fair_queue::DEFAULT_CAPACITY,
+            eject_unreachable_upstreams: false,
+            ejection_threshold: crate::components::proxy::ejection::DEFAULT_THRESHOLD,
+            ejection_duration_secs: crate::components::proxy::ejection::DEFAULT_DURATION.as_secs(),
+            response_timeout_ms: None,
+            response_timeout_metadata: false,
+            admission_control: false,
+            admission_nack: None,
+            drop_log_sample: 0,
+            shared_sessions: None,
+            shared_sessions_ttl_secs: crate::components::proxy::shared_sessions::DEFAULT_TTL
+                .as_secs(),
+            endpoint_tombstone_secs: None,
+            endpoint_removed_notification: None,
+            spare_upstream_sockets: 0,
+            upstream_warm_up_probe: None,
+            session_affinity: Default::default(),
+            filter_state_checkpoint: None,
+            filter_state_checkpoint_secs: crate::components::proxy::checkpoint::DEFAULT_INTERVAL
+                .as_secs(),
+            synthetic_probe_canary: None,
+            synthetic_probe: Vec::new(),
+            synthetic_probe_interval_secs: crate::components::proxy::synthetic::DEFAULT_INTERVAL
+                .as_secs(),
+            synthetic_probe_timeout_ms: crate::components::proxy::synthetic::DEFAULT_TIMEOUT
+                .as_millis() as u64,
+            preflight: false,
+            address_discovery_port: None,
+            icao_code: None,
         }
     }
 }
@@ -92,6 +451,19 @@ impl Proxy {
             "Starting proxy"
         );
 
+        if self.preflight {
+            let report = super::Preflight::from(&self).report().await;
+            for check in &report.checks {
+                tracing::info!(
+                    name = %check.name,
+                    status = ?check.status,
+                    detail = ?check.detail,
+                    "preflight check"
+                );
+            }
+            report.ensure_passed()?;
+        }
+
         // The number of worker tasks to spawn. Each task gets a dedicated queue to
         // consume packets off.
         let num_workers = self.workers.unwrap_or_else(|| {
@@ -99,9 +471,37 @@ impl Proxy {
                 .expect("num_cpus returned 0, which should be impossible")
         });
 
-        let socket = crate::net::raw_socket_with_reuse(self.port)?;
-        let qcmp = crate::net::raw_socket_with_reuse(self.qcmp_port)?;
-        let phoenix = crate::net::TcpListener::bind(Some(self.qcmp_port))?;
+        let handoff = self
+            .hot_restart_socket
+            .as_deref()
+            .map(crate::net::hot_restart::request)
+            .transpose()?
+            .flatten();
+
+        let (socket, qcmp, phoenix, state, upstreams) = match handoff {
+            Some(handoff) => (
+                handoff.socket,
+                handoff.qcmp,
+                handoff.phoenix,
+                handoff.state,
+                handoff.upstreams,
+            ),
+            None => (
+                crate::net::raw_socket_with_reuse(self.port)?,
+                crate::net::raw_socket_with_reuse(self.qcmp_port)?,
+                crate::net::TcpListener::bind(Some(self.qcmp_port))?,
+                <_>::default(),
+                Vec::new(),
+            ),
+        };
+
+        let hot_restart = self
+            .hot_restart_socket
+            .map(|path| crate::net::hot_restart::HotRestart {
+                path,
+                state,
+                upstreams,
+            });
 
         let to_tokens = self
             .to_tokens
@@ -117,16 +517,124 @@ impl Proxy {
             })
             .transpose()?;
 
+        let address_discovery = self
+            .address_discovery_port
+            .map(|port| {
+                Ok::<_, eyre::Error>(crate::net::address_discovery::AddressDiscovery {
+                    socket: crate::net::raw_socket_with_reuse(port)?,
+                    region: self.icao_code.unwrap_or_default(),
+                })
+            })
+            .transpose()?;
+
         crate::components::proxy::Proxy {
             management_servers: self.management_server,
             mmdb: self.mmdb,
+            mmdb_refresh: self
+                .mmdb_refresh_interval_secs
+                .filter(|secs| *secs > 0)
+                .map(std::time::Duration::from_secs),
             to: self.to,
             to_tokens,
             num_workers,
             socket,
+            listener_ports: self.listener_port,
             qcmp,
             phoenix,
             notifier: None,
+            drain_timeout: std::time::Duration::from_secs(self.drain_timeout_secs),
+            drain_notification: self.drain_notification.map(|payload| payload.0),
+            hot_restart,
+            dscp: crate::net::dscp::DscpConfig {
+                upstream: self.upstream_dscp,
+                downstream: self.downstream_dscp,
+            },
+            overload: crate::components::proxy::OverloadConfig {
+                packet_budget: self
+                    .packet_budget_micros
+                    .map(std::time::Duration::from_micros),
+                max_queue_depth: self.max_send_queue_depth,
+            },
+            transparent: self.transparent,
+            write_errors: crate::components::proxy::WriteErrorConfig {
+                policy: self.write_error_policy,
+                retry: self.retry_failed_writes,
+            },
+            coalesce: crate::components::proxy::CoalesceConfig {
+                max_size: self.coalesce_max_bytes,
+                max_delay: std::time::Duration::from_micros(self.coalesce_max_delay_micros),
+            },
+            max_sessions: self.max_sessions,
+            history: crate::components::proxy::PacketHistoryConfig {
+                capacity: self.packet_history,
+                hash_contents: self.packet_history_hash_contents,
+            },
+            heatmap: crate::components::proxy::LatencyHeatmapConfig {
+                window: self
+                    .latency_heatmap_secs
+                    .map(std::time::Duration::from_secs),
+                max_prefixes: self.latency_heatmap_max_prefixes,
+            },
+            handshake: crate::components::proxy::HandshakeConfig {
+                required: self.require_handshake,
+                packet_budget: self.handshake_packet_budget,
+            },
+            duplicate: crate::components::proxy::DuplicateConfig {
+                count: self.duplicate_first_packets,
+                delay: std::time::Duration::from_millis(self.duplicate_delay_ms),
+            },
+            fairness: crate::components::proxy::FairnessConfig {
+                enabled: self.fair_queue,
+                quantum: self.fair_queue_quantum,
+                max_per_source: self.fair_queue_max_per_source,
+                capacity: self.fair_queue_capacity,
+            },
+            ejection: crate::components::proxy::EjectionConfig {
+                enabled: self.eject_unreachable_upstreams,
+                threshold: self.ejection_threshold,
+                duration: std::time::Duration::from_secs(self.ejection_duration_secs),
+            },
+            response_timeout: crate::components::proxy::ResponseTimeoutConfig {
+                window: self
+                    .response_timeout_ms
+                    .map(std::time::Duration::from_millis),
+                metadata: self.response_timeout_metadata,
+            },
+            admission: crate::components::proxy::AdmissionConfig {
+                enabled: self.admission_control,
+                nack: self.admission_nack.map(|payload| payload.0),
+            },
+            drop_log_sample: self.drop_log_sample,
+            shared_sessions: crate::components::proxy::SharedSessionsConfig {
+                store: self.shared_sessions,
+                ttl: std::time::Duration::from_secs(self.shared_sessions_ttl_secs),
+            },
+            tombstones: crate::components::proxy::TombstoneConfig {
+                window: self
+                    .endpoint_tombstone_secs
+                    .map(std::time::Duration::from_secs),
+                notification: self.endpoint_removed_notification.map(|payload| payload.0),
+            },
+            warm: crate::components::proxy::WarmSocketsConfig {
+                spare: self.spare_upstream_sockets,
+                probe: self.upstream_warm_up_probe.map(|payload| payload.0),
+            },
+            affinity: self.session_affinity,
+            checkpoint: crate::components::proxy::CheckpointConfig {
+                store: self.filter_state_checkpoint,
+                interval: std::time::Duration::from_secs(self.filter_state_checkpoint_secs.max(1)),
+            },
+            synthetic: crate::components::proxy::SyntheticProbeConfig {
+                payloads: self
+                    .synthetic_probe
+                    .into_iter()
+                    .map(|payload| payload.0)
+                    .collect(),
+                canary: self.synthetic_probe_canary,
+                interval: std::time::Duration::from_secs(self.synthetic_probe_interval_secs.max(1)),
+                timeout: std::time::Duration::from_millis(self.synthetic_probe_timeout_ms),
+            },
+            address_discovery,
         }
         .run(
             crate::components::RunArgs {
@@ -139,3 +647,25 @@ impl Proxy {
         .await
     }
 }
+
+/// A payload given base64 encoded on the command line, such as a
+/// notification sent to clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Base64Payload(pub Vec<u8>);
+
+fn parse_base64(payload: &str) -> Result<Base64Payload, String> {
+    crate::codec::base64::decode(payload)
+        .map(Base64Payload)
+        .map_err(|error| format!("`{payload}` is not valid base64: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_payloads() {
+        assert_eq!(parse_base64("aGk="), Ok(Base64Payload(b"hi".to_vec())));
+        assert!(parse_base64("not base64!").is_err());
+    }
+}
@@ -17,5 +17,6 @@
 //! Implementations and utility methods for various codecs used in Quilkin.
 
 pub mod base64;
+pub mod control;
 pub mod prost;
 pub mod qcmp;
@@ -0,0 +1,296 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The frame format of in-band control messages exchanged between clients
+//! and the [`Control`](crate::filters::control::Control) filter.
+//!
+//! Control frames are sent on the same socket as game traffic, and are told
+//! apart by their magic prefix. Every frame has the following layout, with
+//! integers in network byte order.
+//!
+//! | Bytes | Field    | Description                                        |
+//! |-------|----------|----------------------------------------------------|
+//! | 4     | Magic    | `QCTL`                                             |
+//! | 1     | Version  | `0`                                                |
+//! | 1     | Kind     | The kind of message, see [`Message`]               |
+//! | 2     | Sequence | Chosen by the client, echoed back in the reply     |
+//! | n     | Payload  | Depends on the kind of message                     |
+//!
+//! Any bytes following the payload are ignored, so control frames can carry
+//! the same routing token as game packets.
+
+// Magic number to distinguish control frames from regular traffic.
+pub const MAGIC_NUMBER: &[u8] = b"QCTL";
+pub const VERSION: u8 = 0;
+/// The length of a frame without its payload.
+pub const HEADER_LEN: usize = 4 /* MAGIC_NUMBER */ + 1 /* VERSION */ + 1 /* KIND */ + 2 /* SEQUENCE */;
+
+const REFRESH_TOKEN: u8 = 0x01;
+const REROUTE: u8 = 0x02;
+const STATUS: u8 = 0x03;
+const ACK: u8 = 0x81;
+const STATUS_REPLY: u8 = 0x83;
+
+const FLAG_TOKEN_REFRESHED: u8 = 1 << 0;
+const FLAG_TOKEN_ROUTABLE: u8 = 1 << 1;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A control frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'buf> {
+    /// Chosen by the client to match replies to requests.
+    pub sequence: u16,
+    pub message: Message<'buf>,
+}
+
+/// The messages that can be sent in a control frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message<'buf> {
+    /// Sent by clients to replace the token their packets are routed with.
+    /// The payload is the length of the token as a single byte, followed by
+    /// the token. Replied to with an [`Message::Ack`].
+    RefreshToken(&'buf [u8]),
+    /// Sent by clients to be routed to a different endpoint by filters that
+    /// don't route by token, such as the load balancer. Has no payload, and
+    /// is replied to with an [`Message::Ack`].
+    Reroute,
+    /// Sent by clients to query the state of their session. Has no payload,
+    /// and is replied to with a [`Message::StatusReply`].
+    Status,
+    /// Sent by the proxy in reply to a request. The payload is a single
+    /// [`AckCode`].
+    Ack(AckCode),
+    /// Sent by the proxy in reply to [`Message::Status`]. The payload is a
+    /// byte of flags followed by the number of reroutes as a `u32`.
+    StatusReply {
+        /// Whether the client's packets are routed with a refreshed token.
+        token_refreshed: bool,
+        /// Whether the refreshed token matches any endpoint.
+        token_routable: bool,
+        /// How many times the client has requested to be rerouted.
+        reroutes: u32,
+    },
+}
+
+impl Message<'_> {
+    fn kind(&self) -> u8 {
+        match self {
+            Self::RefreshToken(_) => REFRESH_TOKEN,
+            Self::Reroute => REROUTE,
+            Self::Status => STATUS,
+            Self::Ack(_) => ACK,
+            Self::StatusReply { .. } => STATUS_REPLY,
+        }
+    }
+}
+
+/// The outcome of a request, sent in a [`Message::Ack`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AckCode {
+    Ok = 0,
+    /// The refreshed token doesn't match any endpoint, the client's packets
+    /// are still routed with its previous token.
+    UnknownToken = 1,
+    /// The request was malformed or not understood.
+    Invalid = 2,
+}
+
+impl TryFrom<u8> for AckCode {
+    type Error = Error;
+
+    fn try_from(code: u8) -> Result<Self> {
+        match code {
+            0 => Ok(Self::Ok),
+            1 => Ok(Self::UnknownToken),
+            2 => Ok(Self::Invalid),
+            code => Err(Error::InvalidAckCode(code)),
+        }
+    }
+}
+
+impl<'buf> Frame<'buf> {
+    #[inline]
+    pub fn new(sequence: u16, message: Message<'buf>) -> Self {
+        Self { sequence, message }
+    }
+
+    /// Whether `input` starts with the magic number of control frames.
+    #[inline]
+    pub fn is_control(input: &[u8]) -> bool {
+        input.starts_with(MAGIC_NUMBER)
+    }
+
+    /// Parses a control frame from `input`, returning `None` if it isn't one.
+    pub fn parse(input: &'buf [u8]) -> Result<Option<Self>> {
+        if !Self::is_control(input) {
+            return Ok(None);
+        }
+
+        if input.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+
+        let version = input[4];
+        if version != VERSION {
+            return Err(Error::UnknownVersion(version));
+        }
+
+        let kind = input[5];
+        let sequence = u16::from_be_bytes([input[6], input[7]]);
+        let payload = &input[HEADER_LEN..];
+
+        let message = match kind {
+            REFRESH_TOKEN => {
+                let (&len, rest) = payload.split_first().ok_or(Error::Truncated)?;
+                Message::RefreshToken(rest.get(..len as usize).ok_or(Error::Truncated)?)
+            }
+            REROUTE => Message::Reroute,
+            STATUS => Message::Status,
+            ACK => Message::Ack(AckCode::try_from(
+                *payload.first().ok_or(Error::Truncated)?,
+            )?),
+            STATUS_REPLY => {
+                let payload = payload.get(..5).ok_or(Error::Truncated)?;
+                Message::StatusReply {
+                    token_refreshed: payload[0] & FLAG_TOKEN_REFRESHED != 0,
+                    token_routable: payload[0] & FLAG_TOKEN_ROUTABLE != 0,
+                    reroutes: u32::from_be_bytes([payload[1], payload[2], payload[3], payload[4]]),
+                }
+            }
+            kind => return Err(Error::InvalidKind(kind)),
+        };
+
+        Ok(Some(Self { sequence, message }))
+    }
+
+    /// Appends the encoded frame to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        if let Message::RefreshToken(token) = self.message {
+            if token.len() > u8::MAX as usize {
+                return Err(Error::TokenTooLong(token.len()));
+            }
+        }
+
+        buf.extend_from_slice(MAGIC_NUMBER);
+        buf.push(VERSION);
+        buf.push(self.message.kind());
+        buf.extend_from_slice(&self.sequence.to_be_bytes());
+
+        match self.message {
+            Message::RefreshToken(token) => {
+                buf.push(token.len() as u8);
+                buf.extend_from_slice(token);
+            }
+            Message::Reroute | Message::Status => {}
+            Message::Ack(code) => buf.push(code as u8),
+            Message::StatusReply {
+                token_refreshed,
+                token_routable,
+                reroutes,
+            } => {
+                let mut flags = 0;
+                if token_refreshed {
+                    flags |= FLAG_TOKEN_REFRESHED;
+                }
+                if token_routable {
+                    flags |= FLAG_TOKEN_ROUTABLE;
+                }
+                buf.push(flags);
+                buf.extend_from_slice(&reroutes.to_be_bytes());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("unknown version: {0}")]
+    UnknownVersion(u8),
+    #[error("frame is shorter than its message")]
+    Truncated,
+    #[error("unknown message kind: {0}")]
+    InvalidKind(u8),
+    #[error("unknown ack code: {0}")]
+    InvalidAckCode(u8),
+    #[error("token is {0} bytes, longer than the maximum of 255")]
+    TokenTooLong(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let messages = [
+            Message::RefreshToken(b"abc"),
+            Message::Reroute,
+            Message::Status,
+            Message::Ack(AckCode::UnknownToken),
+            Message::StatusReply {
+                token_refreshed: true,
+                token_routable: false,
+                reroutes: 3,
+            },
+        ];
+
+        for (sequence, message) in messages.into_iter().enumerate() {
+            let frame = Frame::new(sequence as u16, message);
+            let mut buf = Vec::new();
+            frame.encode(&mut buf).unwrap();
+            assert_eq!(Frame::parse(&buf).unwrap(), Some(frame));
+
+            // Trailing bytes, such as a routing token, are ignored.
+            buf.extend_from_slice(b"token");
+            assert_eq!(Frame::parse(&buf).unwrap(), Some(frame));
+        }
+    }
+
+    #[test]
+    fn refresh_token_layout() {
+        let mut buf = Vec::new();
+        Frame::new(0x0102, Message::RefreshToken(b"ab"))
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"QCTL\x00\x01\x01\x02\x02ab");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(Frame::parse(b"hello world").unwrap(), None);
+        assert!(matches!(Frame::parse(b"QCTL\x00"), Err(Error::Truncated)));
+        assert!(matches!(
+            Frame::parse(b"QCTL\x01\x01\x00\x00"),
+            Err(Error::UnknownVersion(1))
+        ));
+        assert!(matches!(
+            Frame::parse(b"QCTL\x00\x7f\x00\x00"),
+            Err(Error::InvalidKind(0x7f))
+        ));
+        assert!(matches!(
+            Frame::parse(b"QCTL\x00\x01\x00\x00\x05ab"),
+            Err(Error::Truncated)
+        ));
+        assert!(Frame::new(0, Message::RefreshToken(&[0; 256]))
+            .encode(&mut Vec::new())
+            .is_err());
+    }
+}
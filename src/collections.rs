@@ -16,4 +16,5 @@
 
 //! Collection types designed for use with Quilkin.
 
+pub mod decision_cache;
 pub mod ttl;
@@ -0,0 +1,333 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A cache of the decisions made for each packet source, such as the route a
+//! filter picked for it, so they aren't worked out again for every packet.
+//!
+//! A [`DecisionCache`] holds at most `capacity` decisions, evicting the least
+//! recently used, and each decision expires after `ttl`. Larger caches are
+//! split into shards by key, each with its own lock and its own share of the
+//! capacity, so workers looking up different sources rarely wait on each
+//! other, and the least recently used decision of a key's shard is evicted.
+//! Lookups and evictions take constant time.
+//!
+//! Whoever owns the cache invalidates it when the decisions would change,
+//! either by clearing it or by replacing it, as the `SourceIpRouter` filter
+//! does along with its routing table whenever its routes are updated.
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::{IntCounter, IntCounterVec};
+
+use crate::metrics::registry;
+
+/// The most shards a cache is split into.
+const MAX_SHARDS: usize = 16;
+/// The least capacity of each shard, so small caches keep a single shard and
+/// always evict their least recently used decision.
+const MIN_SHARD_CAPACITY: usize = 1024;
+
+/// A bounded cache of decisions, see the [module documentation](self).
+pub struct DecisionCache<K, V> {
+    ttl: Duration,
+    shards: Box<[Mutex<Shard<K, V>>]>,
+    hasher: RandomState,
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> DecisionCache<K, V> {
+    /// Creates an empty cache, counting its hits and misses under `name`.
+    pub fn new(name: &str, capacity: usize, ttl: Duration) -> Self {
+        let capacity = capacity.max(1);
+        let shards = (capacity / MIN_SHARD_CAPACITY).clamp(1, MAX_SHARDS);
+        Self {
+            ttl,
+            shards: (0..shards)
+                .map(|_| Mutex::new(Shard::new(capacity.div_ceil(shards))))
+                .collect(),
+            hasher: RandomState::new(),
+            hits: decision_cache_lookups_total(name, "hit"),
+            misses: decision_cache_lookups_total(name, "miss"),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K, V>> {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    /// Returns the decision for `key`, if it's cached and hasn't expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.shard(key).lock().get(key, self.ttl);
+
+        if value.is_some() {
+            self.hits.inc();
+        } else {
+            self.misses.inc();
+        }
+        value
+    }
+
+    /// Caches `value` as the decision for `key`, evicting the least recently
+    /// used decision of its shard if the shard is full.
+    pub fn insert(&self, key: K, value: V) {
+        self.shard(&key).lock().insert(key, value);
+    }
+
+    /// Returns the decision for `key`, making it with `decide` and caching it
+    /// if it isn't cached.
+    pub fn get_or_insert_with(&self, key: K, decide: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+
+        let value = decide();
+        self.insert(key, value.clone());
+        value
+    }
+
+    /// Removes every decision, such as when what they were based on changed.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().indices.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Marks the end of a shard's list.
+const NONE: usize = usize::MAX;
+
+/// Part of a cache's decisions, kept in a list from the most recently used
+/// to the least, linked by their index in `entries`.
+struct Shard<K, V> {
+    capacity: usize,
+    /// The index of each key's entry.
+    indices: HashMap<K, usize>,
+    entries: Vec<Entry<K, V>>,
+    /// The indices of entries that were removed, to be reused.
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+}
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    inserted: Instant,
+    previous: usize,
+    next: usize,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Shard<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            indices: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            head: NONE,
+            tail: NONE,
+        }
+    }
+
+    fn get(&mut self, key: &K, ttl: Duration) -> Option<V> {
+        let index = *self.indices.get(key)?;
+        if self.entries[index].inserted.elapsed() >= ttl {
+            self.remove(index);
+            return None;
+        }
+
+        self.unlink(index);
+        self.push_front(index);
+        Some(self.entries[index].value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if let Some(&index) = self.indices.get(&key) {
+            let entry = &mut self.entries[index];
+            entry.value = value;
+            entry.inserted = Instant::now();
+            self.unlink(index);
+            self.push_front(index);
+            return;
+        }
+
+        if self.indices.len() >= self.capacity {
+            self.remove(self.tail);
+        }
+
+        let entry = Entry {
+            key: key.clone(),
+            value,
+            inserted: Instant::now(),
+            previous: NONE,
+            next: NONE,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = entry;
+                index
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.indices.insert(key, index);
+        self.push_front(index);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.unlink(index);
+        self.indices.remove(&self.entries[index].key);
+        self.free.push(index);
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let Entry { previous, next, .. } = self.entries[index];
+        match previous {
+            NONE => self.head = next,
+            previous => self.entries[previous].next = next,
+        }
+        match next {
+            NONE => self.tail = previous,
+            next => self.entries[next].previous = previous,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        let head = self.head;
+        let entry = &mut self.entries[index];
+        entry.previous = NONE;
+        entry.next = head;
+        match head {
+            NONE => self.tail = index,
+            head => self.entries[head].previous = index,
+        }
+        self.head = index;
+    }
+
+    fn clear(&mut self) {
+        self.indices.clear();
+        self.entries.clear();
+        self.free.clear();
+        self.head = NONE;
+        self.tail = NONE;
+    }
+}
+
+fn decision_cache_lookups_total(cache: &str, result: &str) -> IntCounter {
+    static LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "decision_cache_lookups_total",
+                "Total number of lookups in decision caches, by whether the decision was cached",
+            },
+            &["cache", "result"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    LOOKUPS.with_label_values(&[cache, result])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = DecisionCache::new("test_lru", 2, Duration::from_secs(60));
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+
+        // Using `1` leaves `2` as the least recently used.
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.insert(3, "c");
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some("c"));
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn bounds_sharded_caches() {
+        let cache = DecisionCache::new("test_sharded", 4096, Duration::from_secs(60));
+        assert_eq!(cache.shards.len(), 4);
+
+        for key in 0..10_000 {
+            cache.insert(key, key);
+        }
+        assert!(cache.len() <= 4096, "{}", cache.len());
+        // The most recent decisions are kept, and replacing one doesn't add
+        // another.
+        assert_eq!(cache.get(&9_999), Some(9_999));
+        let len = cache.len();
+        cache.insert(9_999, 0);
+        assert_eq!(cache.len(), len);
+        assert_eq!(cache.get(&9_999), Some(0));
+    }
+
+    #[test]
+    fn expires_decisions() {
+        let cache = DecisionCache::new("test_expiry", 8, Duration::ZERO);
+        let mut decisions = 0;
+        for _ in 0..3 {
+            cache.get_or_insert_with(1, || {
+                decisions += 1;
+                decisions
+            });
+        }
+        assert_eq!(decisions, 3);
+
+        let cache = DecisionCache::new("test_expiry", 8, Duration::from_secs(60));
+        let hits = decision_cache_lookups_total("test_expiry", "hit").get();
+        let mut decisions = 0;
+        for _ in 0..3 {
+            cache.get_or_insert_with(1, || {
+                decisions += 1;
+                decisions
+            });
+        }
+        assert_eq!(decisions, 1);
+        assert_eq!(
+            decision_cache_lookups_total("test_expiry", "hit").get(),
+            hits + 2
+        );
+    }
+}
@@ -21,7 +21,7 @@ use tracing::warn;
 
 use std::hash::Hash;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 use tokio::sync::oneshot::{channel, Receiver, Sender};
 
@@ -38,6 +38,9 @@ use tokio::time::Instant;
 pub struct Value<V> {
     pub value: V,
     expires_at: Arc<AtomicU64>,
+    /// The ttl of this value in milliseconds, overriding the map's ttl when
+    /// non-zero.
+    ttl_millis: AtomicU64,
     clock: Clock,
 }
 
@@ -46,12 +49,37 @@ impl<V> Value<V> {
         let value = Value {
             value,
             expires_at: Arc::new(AtomicU64::new(0)),
+            ttl_millis: AtomicU64::new(0),
             clock,
         };
         value.update_expiration(ttl);
         value
     }
 
+    /// Overrides the map's ttl for this value, resetting it to expire at
+    /// `ttl` from now. The override is kept whenever the value is read.
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.ttl_millis
+            .store((ttl.as_millis() as u64).max(1), Ordering::Relaxed);
+        self.update_expiration(ttl);
+    }
+
+    /// Returns the ttl of this value, `default` unless it was overridden
+    /// with [`Self::set_ttl`].
+    #[inline]
+    pub fn ttl(&self, default: Duration) -> Duration {
+        match self.ttl_millis.load(Ordering::Relaxed) {
+            0 => default,
+            millis => Duration::from_millis(millis),
+        }
+    }
+
+    /// Resets the value to expire at its ttl from now.
+    #[inline]
+    fn refresh(&self, default: Duration) {
+        self.update_expiration(self.ttl(default));
+    }
+
     /// Get the expiration time for this value. The returned value is the
     /// number of seconds relative to some reference point (e.g UNIX_EPOCH), based
     /// on the clock being used.
@@ -151,7 +179,7 @@ where
             clock: Clock::new(),
         }));
         spawn_cleanup_task(
-            map.0.clone(),
+            Arc::downgrade(&map.0),
             poll_interval,
             map.0.clock.clone(),
             shutdown_rx,
@@ -178,17 +206,23 @@ where
     pub fn get(&self, key: &K) -> Option<Ref<K, Value<V>>> {
         let value = self.0.inner.get(key);
         if let Some(ref value) = value {
-            value.update_expiration(self.0.ttl)
+            value.refresh(self.0.ttl)
         }
 
         value
     }
 
+    /// Returns a reference to value corresponding to key, without resetting
+    /// when it expires.
+    pub fn peek(&self, key: &K) -> Option<Ref<K, Value<V>>> {
+        self.0.inner.get(key)
+    }
+
     /// Returns a reference to value corresponding to key.
     pub fn try_get(&self, key: &K) -> TryResult<Ref<K, Value<V>>> {
         let value = self.0.inner.try_get(key);
         if let TryResult::Present(ref value) = value {
-            value.update_expiration(self.0.ttl)
+            value.refresh(self.0.ttl)
         }
 
         value
@@ -199,7 +233,7 @@ where
     pub fn get_mut(&self, key: &K) -> Option<RefMut<K, Value<V>>> {
         let value = self.0.inner.get_mut(key);
         if let Some(ref value) = value {
-            value.update_expiration(self.0.ttl);
+            value.refresh(self.0.ttl);
         }
 
         value
@@ -225,6 +259,30 @@ where
         self.0.inner.contains_key(key)
     }
 
+    /// Returns a snapshot of the keys currently in the map.
+    /// Unlike [`Self::get`], this does not refresh the expiration of any entry.
+    pub fn keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.0
+            .inner
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Returns a snapshot of the entries currently in the map, as mapped by
+    /// `func`. Unlike [`Self::get`], this does not refresh the expiration of
+    /// any entry.
+    pub fn snapshot<T>(&self, func: impl Fn(&K, &V) -> T) -> Vec<T> {
+        self.0
+            .inner
+            .iter()
+            .map(|entry| func(entry.key(), &entry.value().value))
+            .collect()
+    }
+
     /// Inserts a key-value pair into the map.
     /// The value will be set to expire at the configured TTL after the time of insertion.
     /// If a previous value existed for this key, that value is returned.
@@ -240,6 +298,12 @@ where
         self.0.inner.remove(&key).is_some()
     }
 
+    /// Removes the entries that have expired, rather than waiting for the
+    /// cleanup task to.
+    pub fn remove_expired(&self) {
+        prune_entries(&self.0, &self.0.clock);
+    }
+
     /// Removes all entries from the map
     #[inline]
     pub fn clear(&self) {
@@ -323,7 +387,7 @@ where
         match &self.inner {
             DashMapEntry::Occupied(entry) => {
                 let value = entry.get();
-                value.update_expiration(self.ttl);
+                value.refresh(self.ttl);
                 value
             }
             _ => unreachable!("BUG: entry type should be occupied"),
@@ -337,7 +401,7 @@ where
         match &mut self.inner {
             DashMapEntry::Occupied(entry) => {
                 let value = entry.get_mut();
-                value.update_expiration(self.ttl);
+                value.refresh(self.ttl);
                 value
             }
             _ => unreachable!("BUG: entry type should be occupied"),
@@ -373,8 +437,12 @@ where
     }
 }
 
+/// Spawns the task removing expired entries from `map`. The task only holds a
+/// weak reference, so the map is dropped along with its last [`TtlMap`], such
+/// as when the filter owning it is replaced by a config change, which then
+/// stops the task.
 fn spawn_cleanup_task<K, V>(
-    map: Arc<Map<K, V>>,
+    map: Weak<Map<K, V>>,
     poll_interval: Duration,
     clock: Clock,
     mut shutdown_rx: Receiver<()>,
@@ -388,7 +456,10 @@ fn spawn_cleanup_task<K, V>(
         loop {
             tokio::select! {
                 _ = interval.tick() => {
-                    prune_entries( &map, &clock).await;
+                    let Some(map) = map.upgrade() else {
+                        return;
+                    };
+                    prune_entries(&map, &clock);
                 }
                 _ = &mut shutdown_rx => {
                     return;
@@ -398,7 +469,7 @@ fn spawn_cleanup_task<K, V>(
     });
 }
 
-async fn prune_entries<K, V>(map: &Arc<Map<K, V>>, clock: &Clock)
+fn prune_entries<K, V>(map: &Arc<Map<K, V>>, clock: &Clock)
 where
     K: Hash + Eq + Send + Sync + 'static,
     V: Send + Sync + 'static,
@@ -510,6 +581,19 @@ mod tests {
         assert!(map.is_empty());
     }
 
+    #[tokio::test]
+    async fn cleanup_task_does_not_keep_map_alive() {
+        const POLL: Duration = Duration::from_millis(10);
+
+        let map = TtlMap::<EndpointAddress, usize>::new(Duration::from_secs(10), POLL);
+        map.insert(address_pair().0, 1);
+        let weak = Arc::downgrade(&map.0);
+        drop(map);
+
+        tokio::time::sleep(POLL * 2).await;
+        assert!(weak.upgrade().is_none());
+    }
+
     #[tokio::test]
     async fn insert_and_get() {
         let (one, two) = address_pair();
@@ -759,6 +843,27 @@ mod tests {
         assert_eq!(12, exp);
     }
 
+    #[tokio::test]
+    async fn value_ttl_override() {
+        // Test that a value's own ttl is used when it's read.
+        time::pause();
+
+        let (one, _) = address_pair();
+
+        let map = TtlMap::<EndpointAddress, usize>::new(
+            Duration::from_secs(12),
+            Duration::from_millis(10),
+        );
+        map.insert(one.clone(), 9);
+        map.get(&one).unwrap().set_ttl(Duration::from_secs(30));
+
+        time::advance(Duration::from_secs(2)).await;
+
+        let value = map.get(&one).unwrap();
+        assert_eq!(value.ttl(Duration::from_secs(12)), Duration::from_secs(30));
+        assert_eq!(32, value.expiration_secs());
+    }
+
     #[tokio::test]
     async fn cleanup_expired_entries() {
         // Test that we delete expired entries from the ttl map.
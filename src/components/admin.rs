@@ -20,9 +20,63 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use bytes::Bytes;
-use http_body_util::Full;
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
 use hyper::{Method, Request, Response, StatusCode};
-type Body = Full<Bytes>;
+type Body = UnsyncBoxBody<Bytes, std::convert::Infallible>;
+
+/// Returns a body containing all of `bytes` at once.
+fn full(bytes: impl Into<Bytes>) -> Body {
+    Full::new(bytes.into()).boxed_unsync()
+}
+
+/// Returns a `200 OK` response with `json` as its body.
+fn json_response(json: impl Into<Bytes>) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            "Content-Type",
+            hyper::header::HeaderValue::from_static("application/json"),
+        )
+        .body(full(json))
+        .unwrap()
+}
+
+/// Returns a `400 Bad Request` response explaining why in its body.
+fn bad_request(message: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(full(message))
+        .unwrap()
+}
+
+/// The most bytes read from a request's body, so clients can't have the admin
+/// server buffer requests of any size.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Reads all of `request`'s body, or returns the response to send if it can't
+/// be read or is larger than [`MAX_BODY_BYTES`].
+async fn read_body(request: Request<hyper::body::Incoming>) -> Result<Bytes, Response<Body>> {
+    match http_body_util::Limited::new(request.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(error) if error.is::<http_body_util::LengthLimitError>() => Err(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(full(format!(
+                "request body is larger than {MAX_BODY_BYTES} bytes"
+            )))
+            .unwrap()),
+        Err(error) => Err(bad_request(format!("failed to read request: {error}"))),
+    }
+}
+
+/// Returns an empty `404 Not Found` response.
+fn not_found() -> Response<Body> {
+    let mut response = Response::new(full(Bytes::new()));
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
 
 use crate::config::Config;
 use health::Health;
@@ -133,8 +187,10 @@ impl Admin {
     ) -> Response<Body> {
         match (request.method(), request.uri().path()) {
             (&Method::GET, "/metrics") => collect_metrics(),
-            (&Method::GET, "/live" | "/livez") => health.check_liveness(),
-            #[cfg(target_os = "linux")]
+            (&Method::GET, "/live" | "/livez") => {
+                health.check_liveness().map(BodyExt::boxed_unsync)
+            }
+            #[cfg(all(target_os = "linux", feature = "pprof"))]
             (&Method::GET, "/debug/pprof/profile") => {
                 let duration = request.uri().query().and_then(|query| {
                     form_urlencoded::parse(query.as_bytes())
@@ -149,49 +205,347 @@ impl Admin {
                         tracing::warn!(%error, "admin http server error");
                         Response::builder()
                             .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Body::new(Bytes::from("internal error")))
+                            .body(full("internal error"))
                             .unwrap()
                     }
                 }
             }
             (&Method::GET, "/ready" | "/readyz") => check_readiness(|| self.is_ready(&config)),
-            (&Method::GET, "/config") => match serde_json::to_string(&config) {
-                Ok(body) => Response::builder()
-                    .status(StatusCode::OK)
-                    .header(
-                        "Content-Type",
-                        hyper::header::HeaderValue::from_static("application/json"),
-                    )
-                    .body(Body::new(Bytes::from(body)))
-                    .unwrap(),
-                Err(err) => Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::new(Bytes::from(format!(
-                        "failed to create config dump: {err}"
-                    ))))
-                    .unwrap(),
+            (&Method::GET, "/config") => {
+                match crate::config::redact::scope(|| serde_json::to_string(&config)) {
+                    Ok(body) => json_response(body),
+                    Err(err) => Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(full(format!("failed to create config dump: {err}")))
+                        .unwrap(),
+                }
+            }
+            (&Method::GET, "/capacity") => match self {
+                Self::Proxy(proxy) => json_response(proxy.capacity.to_json().to_string()),
+                _ => not_found(),
             },
-            (_, _) => {
-                let mut response = Response::new(Body::new(Bytes::new()));
-                *response.status_mut() = StatusCode::NOT_FOUND;
-                response
+            (&Method::GET, "/history") => match self {
+                Self::Proxy(proxy) => {
+                    let history = proxy
+                        .history
+                        .read()
+                        .as_ref()
+                        .map_or(serde_json::Value::Null, |history| history.to_json());
+                    json_response(history.to_string())
+                }
+                _ => not_found(),
+            },
+            (&Method::GET, "/heatmap") => match self {
+                Self::Proxy(proxy) => {
+                    let heatmap = proxy
+                        .heatmap
+                        .read()
+                        .as_ref()
+                        .map_or(serde_json::Value::Null, |heatmap| heatmap.to_json());
+                    json_response(heatmap.to_string())
+                }
+                _ => not_found(),
+            },
+            (&Method::GET, "/drain") => match self {
+                Self::Proxy(proxy) => json_response(proxy.drain.to_json().to_string()),
+                _ => not_found(),
+            },
+            (&Method::GET, "/events") => match self {
+                Self::Proxy(proxy) => match &*proxy.events.read() {
+                    Some(events) => stream_events(events),
+                    None => {
+                        let mut response = Response::new(full(Bytes::new()));
+                        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        response
+                    }
+                },
+                _ => not_found(),
+            },
+            (&Method::GET, "/maintenance") => {
+                json_response(maintenance_to_json(&config).to_string())
+            }
+            #[cfg(feature = "filter-routing")]
+            (&Method::GET, "/traffic-split") => match traffic_split(&config) {
+                Some(split) => json_response(split.to_string()),
+                None => not_found(),
+            },
+            #[cfg(feature = "filter-routing")]
+            (&Method::POST, "/traffic-split") => update_traffic_split(&config, request).await,
+            #[cfg(feature = "filter-routing")]
+            (&Method::GET, "/filters/source_ip_router/routes") => match source_ip_routes() {
+                Some(routes) => json_response(routes.to_string()),
+                None => not_found(),
+            },
+            #[cfg(feature = "filter-routing")]
+            (&Method::POST | &Method::PATCH, "/filters/source_ip_router/routes") => {
+                update_source_ip_routes(&config, request).await
             }
+            (&Method::POST, "/sessions/migrate") => match self {
+                Self::Proxy(proxy) => migrate_sessions(proxy, &config, request).await,
+                _ => not_found(),
+            },
+            (_, _) => not_found(),
         }
     }
 }
 
 fn check_readiness(check: impl Fn() -> bool) -> Response<Body> {
     if (check)() {
-        return Response::new("ok".into());
+        return Response::new(full("ok"));
     }
 
-    let mut response = Response::new(Body::new(Bytes::new()));
+    let mut response = Response::new(full(Bytes::new()));
     *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
     response
 }
 
+/// Streams the proxy's events to the client as Server-Sent Events, until the
+/// client disconnects.
+fn stream_events(events: &proxy::Events) -> Response<Body> {
+    use futures::StreamExt;
+
+    let stream = events
+        .subscribe()
+        .map(|event| Ok::<_, std::convert::Infallible>(hyper::body::Frame::data(event.to_sse())));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("text/event-stream"),
+        )
+        .header(
+            hyper::header::CACHE_CONTROL,
+            hyper::header::HeaderValue::from_static("no-cache"),
+        )
+        .body(StreamBody::new(stream).boxed_unsync())
+        .unwrap()
+}
+
+/// Returns the configured maintenance windows, and whether each is in effect.
+fn maintenance_to_json(config: &Config) -> serde_json::Value {
+    let now = time::OffsetDateTime::now_utc();
+    let windows: Vec<_> = config
+        .maintenance
+        .load()
+        .iter()
+        .map(|window| {
+            let mut json = serde_json::to_value(window).unwrap_or_default();
+            json["active"] = window.is_active(now).into();
+            json
+        })
+        .collect();
+
+    serde_json::json!({
+        "now": now.unix_timestamp(),
+        "windows": windows,
+    })
+}
+
+/// Returns the configuration of the filter chain's
+/// [`TrafficSplit`](crate::filters::TrafficSplit) filter, if it has one.
+#[cfg(feature = "filter-routing")]
+fn traffic_split(config: &Config) -> Option<serde_json::Value> {
+    use crate::filters::StaticFilter;
+
+    config
+        .filters
+        .load()
+        .iter()
+        .find(|filter| filter.name == crate::filters::TrafficSplit::NAME)
+        .map(|filter| filter.config.unwrap_or_default())
+}
+
+/// Replaces the configuration of the filter chain's
+/// [`TrafficSplit`](crate::filters::TrafficSplit) filter with the JSON
+/// request body, moving clients between clusters without a restart. Changes
+/// made to the filter chain while the new one is created are kept.
+#[cfg(feature = "filter-routing")]
+async fn update_traffic_split(
+    config: &Config,
+    request: Request<hyper::body::Incoming>,
+) -> Response<Body> {
+    use crate::filters::{traffic_split, StaticFilter};
+
+    let body = match read_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let split: traffic_split::Config = match serde_json::from_slice(&body) {
+        Ok(split) => split,
+        Err(error) => return bad_request(format!("invalid traffic split: {error}")),
+    };
+    let split = match serde_json::to_value(&split) {
+        Ok(split) => split,
+        Err(error) => return bad_request(format!("invalid traffic split: {error}")),
+    };
+
+    let updated = config.update_filters(|chain| {
+        let mut filters: Vec<_> = chain.iter().collect();
+        let Some(filter) = filters
+            .iter_mut()
+            .find(|filter| filter.name == traffic_split::TrafficSplit::NAME)
+        else {
+            return Err(not_found());
+        };
+        filter.config = Some(split.clone());
+
+        config
+            .create_filters(filters)
+            .map_err(|error| bad_request(format!("invalid traffic split: {error}")))
+    });
+
+    match updated {
+        Ok(_) => {
+            tracing::info!(%split, "updated traffic split");
+            json_response(split.to_string())
+        }
+        Err(response) => response,
+    }
+}
+
+/// Returns the routes of the first live
+/// [`SourceIpRouter`](crate::filters::SourceIpRouter) filter, wherever it is
+/// in the filter chain, if there is one.
+#[cfg(feature = "filter-routing")]
+fn source_ip_routes() -> Option<serde_json::Value> {
+    crate::filters::source_ip_router::routes().and_then(|routes| serde_json::to_value(routes).ok())
+}
+
+/// Updates the routes of every live
+/// [`SourceIpRouter`](crate::filters::SourceIpRouter) filter in place, with
+/// the JSON request body. `POST` replaces the routes, while `PATCH` removes
+/// routes by name and adds others. The new routes take effect together once
+/// they're valid, so packets are routed by either the old routes or the new
+/// ones. The routes given may only send packets to the clusters' endpoints.
+#[cfg(feature = "filter-routing")]
+async fn update_source_ip_routes(
+    config: &Config,
+    request: Request<hyper::body::Incoming>,
+) -> Response<Body> {
+    use crate::filters::source_ip_router;
+
+    // Otherwise anyone reaching the admin server could have the proxy send
+    // its clients' packets anywhere.
+    let check_endpoints = |routes: &[source_ip_router::Route]| {
+        let clusters = config.clusters.read();
+        for endpoint in routes.iter().flat_map(|route| &route.endpoints) {
+            let is_endpoint = endpoint
+                .parse::<crate::net::EndpointAddress>()
+                .is_ok_and(|address| clusters.contains_address(&address));
+            if !is_endpoint {
+                return Err(bad_request(format!("`{endpoint}` is not an endpoint")));
+            }
+        }
+        Ok(())
+    };
+
+    let replace = request.method() == Method::POST;
+    let body = match read_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let updated = if replace {
+        let routes: Vec<source_ip_router::Route> = match serde_json::from_slice(&body) {
+            Ok(routes) => routes,
+            Err(error) => return bad_request(format!("invalid routes: {error}")),
+        };
+        if let Err(response) = check_endpoints(&routes) {
+            return response;
+        }
+        source_ip_router::update_routes(|_| Ok(routes.clone()))
+    } else {
+        let patch: source_ip_router::RoutesPatch = match serde_json::from_slice(&body) {
+            Ok(patch) => patch,
+            Err(error) => return bad_request(format!("invalid routes: {error}")),
+        };
+        if let Err(response) = check_endpoints(&patch.add) {
+            return response;
+        }
+        source_ip_router::update_routes(|routes| patch.apply(routes))
+    };
+
+    match updated {
+        Ok(Some(routes)) => {
+            let routes = serde_json::to_value(routes).unwrap_or_default();
+            tracing::info!(%routes, "updated source ip routes");
+            json_response(routes.to_string())
+        }
+        Ok(None) => not_found(),
+        Err(error) => bad_request(format!("invalid routes: {error}")),
+    }
+}
+
+/// Re-points the proxy's sessions from one endpoint to another, as described
+/// by the JSON request body.
+async fn migrate_sessions(
+    proxy: &proxy::Ready,
+    config: &Config,
+    request: Request<hyper::body::Incoming>,
+) -> Response<Body> {
+    #[derive(serde::Deserialize)]
+    struct Migration {
+        from: std::net::SocketAddr,
+        to: std::net::SocketAddr,
+        /// Base64 encoded.
+        #[serde(default)]
+        token: Option<String>,
+        /// Base64 encoded.
+        #[serde(default)]
+        notification: Option<String>,
+    }
+
+    fn decode(field: &str, value: Option<String>) -> Result<Option<Bytes>, Response<Body>> {
+        value
+            .map(|value| {
+                crate::codec::base64::decode(&value)
+                    .map(Bytes::from)
+                    .map_err(|error| bad_request(format!("`{field}` is not valid base64: {error}")))
+            })
+            .transpose()
+    }
+
+    let sessions = proxy
+        .sessions
+        .read()
+        .as_ref()
+        .and_then(std::sync::Weak::upgrade);
+    let Some(sessions) = sessions else {
+        let mut response = Response::new(full(Bytes::new()));
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        return response;
+    };
+
+    let body = match read_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let migration: Migration = match serde_json::from_slice(&body) {
+        Ok(migration) => migration,
+        Err(error) => return bad_request(format!("invalid migration: {error}")),
+    };
+    let (token, notification) = match (
+        decode("token", migration.token),
+        decode("notification", migration.notification),
+    ) {
+        (Ok(token), Ok(notification)) => (token, notification),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+
+    if !config
+        .clusters
+        .read()
+        .contains_address(&migration.to.into())
+    {
+        return bad_request(format!("`{}` is not an endpoint", migration.to));
+    }
+
+    let migrated = sessions.migrate(migration.from, migration.to, token, notification);
+    json_response(serde_json::json!({ "sessions": migrated }).to_string())
+}
+
 fn collect_metrics() -> Response<Body> {
-    let mut response = Response::new(Body::new(Bytes::new()));
+    let mut response = Response::new(full(Bytes::new()));
     let mut buffer = vec![];
     let encoder = prometheus::TextEncoder::new();
     let body =
@@ -205,7 +559,7 @@ fn collect_metrics() -> Response<Body> {
 
     match body {
         Ok(body) => {
-            *response.body_mut() = Body::new(body);
+            *response.body_mut() = full(body);
         }
         Err(_) => {
             *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
@@ -217,7 +571,7 @@ fn collect_metrics() -> Response<Body> {
 
 /// Collects profiling information using `prof` for an optional `duration` or
 /// the default if `None`.
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "pprof"))]
 async fn collect_pprof(
     duration: Option<std::time::Duration>,
 ) -> Result<Response<Body>, eyre::Error> {
@@ -245,7 +599,7 @@ async fn collect_pprof(
         .header(hyper::header::CONTENT_LENGTH, gzip_body.len() as u64)
         .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
         .header(hyper::header::CONTENT_ENCODING, "gzip")
-        .body(Body::new(Bytes::from(gzip_body)))
+        .body(full(gzip_body))
         .map_err(From::from)
 }
 
@@ -260,7 +614,7 @@ mod tests {
         assert_eq!(response.status(), hyper::StatusCode::OK);
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "pprof"))]
     #[tokio::test]
     async fn collect_pprof() {
         // Custom time to make the test fast.
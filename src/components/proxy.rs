@@ -14,9 +14,35 @@
  *  limitations under the License.
  */
 
+pub(crate) mod admission;
+mod affinity;
+mod builder;
+mod capacity;
+pub mod checkpoint;
+mod coalesce;
+mod drop_log;
+mod duplicate;
+pub(crate) mod ejection;
 mod error;
+mod events;
+pub(crate) mod fair_queue;
+pub(crate) mod handshake;
+pub mod heatmap;
+pub(crate) mod history;
+mod migration;
+mod overload;
 pub mod packet_router;
+mod quota;
+mod redis;
+pub mod response_timeout;
 mod sessions;
+pub mod shared_sessions;
+mod stages;
+pub mod synthetic;
+mod tombstone;
+mod warm;
+pub(crate) mod worker_metrics;
+mod write_errors;
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -63,6 +89,12 @@ impl PendingSends {
         self.packets.lock().capacity()
     }
 
+    /// The number of packets waiting to be sent
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.packets.lock().len()
+    }
+
     /// Pushes a packet onto the queue to be sent, signalling a sender that
     /// it's available
     #[inline]
@@ -93,15 +125,35 @@ impl PendingSends {
 }
 
 use super::RunArgs;
+pub use admission::AdmissionConfig;
+pub use affinity::SessionAffinity;
+pub use builder::ProxyBuilder;
+pub use capacity::CapacityStatus;
+pub use checkpoint::{CheckpointConfig, CheckpointStore};
+pub use coalesce::CoalesceConfig;
+pub use duplicate::DuplicateConfig;
+pub use ejection::EjectionConfig;
 pub use error::{ErrorMap, PipelineError};
-pub use sessions::SessionPool;
+pub use events::{Event, Events};
+pub use fair_queue::FairnessConfig;
+pub use handshake::HandshakeConfig;
+pub use heatmap::{LatencyHeatmap, LatencyHeatmapConfig};
+pub use history::{PacketHistory, PacketHistoryConfig};
+pub use overload::{OverloadConfig, OverloadReason};
+pub use response_timeout::ResponseTimeoutConfig;
+pub use sessions::{SessionKey, SessionPool, SessionSettings};
+pub use shared_sessions::{SessionStore, SharedSessionsConfig};
 use std::{
     net::SocketAddr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
 };
+pub use synthetic::SyntheticProbeConfig;
+pub use tombstone::TombstoneConfig;
+pub use warm::WarmSocketsConfig;
+pub use write_errors::{WriteErrorConfig, WriteErrorPolicy};
 
 pub struct SendPacket {
     /// The destination address of the packet
@@ -110,6 +162,8 @@ pub struct SendPacket {
     pub data: crate::pool::FrozenPoolBuffer,
     /// The asn info for the sender, used for metrics
     pub asn_info: Option<crate::net::maxmind_db::MetricsIpNetEntry>,
+    /// The DSCP to mark the packet with, if any
+    pub dscp: Option<crate::net::dscp::Dscp>,
 }
 
 pub struct RecvPacket {
@@ -122,6 +176,17 @@ pub struct Ready {
     pub idle_request_interval: std::time::Duration,
     // RwLock as this check is conditional on the proxy using xDS.
     pub xds_is_healthy: Arc<parking_lot::RwLock<Option<Arc<AtomicBool>>>>,
+    pub drain: Arc<DrainStatus>,
+    pub capacity: Arc<CapacityStatus>,
+    // RwLock as the history is only created once the proxy is running.
+    pub history: Arc<parking_lot::RwLock<Option<Arc<PacketHistory>>>>,
+    // RwLock as the heatmap is only created once the proxy is running.
+    pub heatmap: Arc<parking_lot::RwLock<Option<Arc<LatencyHeatmap>>>>,
+    // RwLock as the events are only emitted once the proxy is running.
+    pub events: Arc<parking_lot::RwLock<Option<Events>>>,
+    // RwLock as the sessions are only created once the proxy is running, and
+    // weak so they're still dropped when it stops.
+    pub sessions: Arc<parking_lot::RwLock<Option<std::sync::Weak<SessionPool>>>>,
 }
 
 impl Default for Ready {
@@ -129,6 +194,12 @@ impl Default for Ready {
         Self {
             idle_request_interval: crate::components::admin::IDLE_REQUEST_INTERVAL,
             xds_is_healthy: Default::default(),
+            drain: Default::default(),
+            capacity: Default::default(),
+            history: Default::default(),
+            heatmap: Default::default(),
+            events: Default::default(),
+            sessions: Default::default(),
         }
     }
 }
@@ -136,6 +207,10 @@ impl Default for Ready {
 impl Ready {
     #[inline]
     pub fn is_ready(&self) -> Option<bool> {
+        if self.drain.is_draining() || self.capacity.is_over_capacity() {
+            return Some(false);
+        }
+
         self.xds_is_healthy
             .read()
             .as_ref()
@@ -143,6 +218,49 @@ impl Ready {
     }
 }
 
+/// Progress of draining active sessions during a graceful shutdown, exposed
+/// through the admin server's `/drain` endpoint.
+#[derive(Debug, Default)]
+pub struct DrainStatus {
+    draining: AtomicBool,
+    initial_sessions: AtomicUsize,
+    remaining_sessions: AtomicUsize,
+    started_at: AtomicI64,
+    timeout_secs: AtomicU64,
+}
+
+impl DrainStatus {
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn start(&self, sessions: usize, timeout: std::time::Duration) {
+        self.initial_sessions.store(sessions, Ordering::SeqCst);
+        self.remaining_sessions.store(sessions, Ordering::SeqCst);
+        self.started_at
+            .store(crate::time::UtcTimestamp::now().unix(), Ordering::SeqCst);
+        self.timeout_secs.store(timeout.as_secs(), Ordering::SeqCst);
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub(crate) fn update(&self, remaining: usize) {
+        self.remaining_sessions.store(remaining, Ordering::SeqCst);
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let draining = self.is_draining();
+        serde_json::json!({
+            "draining": draining,
+            "initial_sessions": self.initial_sessions.load(Ordering::SeqCst),
+            "remaining_sessions": self.remaining_sessions.load(Ordering::SeqCst),
+            "started_at": draining.then(|| self.started_at.load(Ordering::SeqCst)),
+            "timeout_secs": self.timeout_secs.load(Ordering::SeqCst),
+        })
+    }
+}
+
 pub struct ToTokens {
     /// The number of tokens to assign to each `to` address
     pub count: usize,
@@ -153,13 +271,90 @@ pub struct ToTokens {
 pub struct Proxy {
     pub num_workers: std::num::NonZeroUsize,
     pub mmdb: Option<crate::net::maxmind_db::Source>,
+    /// How often the MaxMind database is loaded again after it was first
+    /// loaded, never when `None`.
+    pub mmdb_refresh: Option<std::time::Duration>,
     pub management_servers: Vec<tonic::transport::Endpoint>,
-    pub to: Vec<SocketAddr>,
+    pub to: Vec<crate::net::EndpointAddress>,
     pub to_tokens: Option<ToTokens>,
     pub socket: socket2::Socket,
+    /// Other ports clients send packets to, such as those of a
+    /// [`Listeners`](crate::filters::Listeners) filter's listeners. The
+    /// ports of the `Listeners` filters in the configuration when the proxy
+    /// starts are received on as well.
+    pub listener_ports: Vec<u16>,
     pub qcmp: socket2::Socket,
     pub phoenix: crate::net::TcpListener,
     pub notifier: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+    /// How long to keep forwarding traffic for active sessions after a
+    /// shutdown signal, a zero duration disables draining.
+    pub drain_timeout: std::time::Duration,
+    /// An optional payload sent to every client with an active session when
+    /// draining begins.
+    pub drain_notification: Option<Vec<u8>>,
+    /// When set, the proxy listens for a new process requesting to take over
+    /// its sockets, and drains once the handoff has completed.
+    pub hot_restart: Option<crate::net::hot_restart::HotRestart>,
+    /// The default DSCP marking of forwarded packets.
+    pub dscp: crate::net::dscp::DscpConfig,
+    /// The limits beyond which packets are shed rather than processed.
+    pub overload: OverloadConfig,
+    /// Whether to forward packets to upstreams from the client's own address,
+    /// so they see the real address of each client.
+    pub transparent: bool,
+    /// How errors handling packets on their way back to clients are handled.
+    pub write_errors: WriteErrorConfig,
+    /// Whether small packets sent back to the same client are merged.
+    pub coalesce: CoalesceConfig,
+    /// The number of active sessions beyond which the proxy reports itself
+    /// as not ready, existing and new sessions are still served.
+    pub max_sessions: Option<usize>,
+    /// How many of the most recent packets from clients are kept in memory
+    /// for post-incident dumps.
+    pub history: PacketHistoryConfig,
+    /// Whether the latency of packets is tracked for each source prefix.
+    pub heatmap: LatencyHeatmapConfig,
+    /// Whether packets from clients are held back until a filter has marked
+    /// them as established.
+    pub handshake: HandshakeConfig,
+    /// Whether the first packets of each session are sent to its upstream
+    /// twice.
+    pub duplicate: DuplicateConfig,
+    /// Whether packets from clients are queued per client and handled in
+    /// turn.
+    pub fairness: FairnessConfig,
+    /// Whether upstreams reported unreachable through ICMP errors stop being
+    /// sent packets for a while.
+    pub ejection: EjectionConfig,
+    /// Whether sessions whose upstream doesn't respond to their packets are
+    /// flagged.
+    pub response_timeout: ResponseTimeoutConfig,
+    /// Whether new sessions are only admitted to upstreams below the capacity
+    /// they advertise in their metadata.
+    pub admission: AdmissionConfig,
+    /// One in how many dropped packets of each drop reason are logged, none
+    /// when zero.
+    pub drop_log_sample: u32,
+    /// Whether clients established on this proxy are shared with other
+    /// proxies, so they don't have to handshake again if moved to another.
+    pub shared_sessions: SharedSessionsConfig,
+    /// Whether endpoints removed from the config are remembered for a while,
+    /// so their clients' packets are dropped with a reason of their own.
+    pub tombstones: TombstoneConfig,
+    /// Whether upstream sockets are created before sessions need them, so
+    /// new sessions don't wait for their socket to be created.
+    pub warm: WarmSocketsConfig,
+    /// How a client's packets are matched to its sessions.
+    pub affinity: SessionAffinity,
+    /// Whether the state of the filters is checkpointed, so it's restored
+    /// when the proxy restarts.
+    pub checkpoint: CheckpointConfig,
+    /// Whether synthetic probes are run through the filters to a canary
+    /// endpoint, so whether packets are routed is monitored.
+    pub synthetic: SyntheticProbeConfig,
+    /// The service replying to clients with the public address they're seen
+    /// from, if enabled.
+    pub address_discovery: Option<crate::net::address_discovery::AddressDiscovery>,
 }
 
 impl Default for Proxy {
@@ -170,20 +365,52 @@ impl Default for Proxy {
         Self {
             num_workers: std::num::NonZeroUsize::new(1).unwrap(),
             mmdb: None,
+            mmdb_refresh: None,
             management_servers: Vec::new(),
             to: Vec::new(),
             to_tokens: None,
             socket: crate::net::raw_socket_with_reuse(0).unwrap(),
+            listener_ports: Vec::new(),
             qcmp,
             phoenix,
             notifier: None,
+            drain_timeout: std::time::Duration::ZERO,
+            drain_notification: None,
+            hot_restart: None,
+            dscp: Default::default(),
+            overload: Default::default(),
+            transparent: false,
+            write_errors: Default::default(),
+            coalesce: Default::default(),
+            max_sessions: None,
+            history: Default::default(),
+            heatmap: Default::default(),
+            handshake: Default::default(),
+            duplicate: Default::default(),
+            fairness: Default::default(),
+            ejection: Default::default(),
+            response_timeout: Default::default(),
+            admission: Default::default(),
+            drop_log_sample: 0,
+            shared_sessions: Default::default(),
+            tombstones: Default::default(),
+            warm: Default::default(),
+            affinity: Default::default(),
+            checkpoint: Default::default(),
+            synthetic: Default::default(),
+            address_discovery: None,
         }
     }
 }
 
 impl Proxy {
+    /// Returns a builder for a proxy with its own set of filters.
+    pub fn builder() -> ProxyBuilder {
+        ProxyBuilder::default()
+    }
+
     pub async fn run(
-        self,
+        mut self,
         RunArgs {
             config,
             ready,
@@ -191,6 +418,15 @@ impl Proxy {
         }: RunArgs<Ready>,
         initialized: Option<tokio::sync::oneshot::Sender<()>>,
     ) -> crate::Result<()> {
+        if self.transparent && !cfg!(target_os = "linux") {
+            eyre::bail!("transparent proxying is only supported on Linux");
+        }
+
+        let started = std::time::Instant::now();
+
+        let drain_status = ready.drain.clone();
+        ready.capacity.set_max_sessions(self.max_sessions);
+        let mmdb_refresh = self.mmdb_refresh;
         let _mmdb_task = self.mmdb.map(|source| {
             tokio::spawn(async move {
                 while let Err(error) =
@@ -201,6 +437,17 @@ impl Proxy {
                 {
                     tracing::warn!(%error, "error updating maxmind database");
                 }
+
+                // The previous database is kept until a newer one loads.
+                let Some(refresh) = mmdb_refresh else {
+                    return;
+                };
+                loop {
+                    tokio::time::sleep(refresh).await;
+                    if let Err(error) = crate::MaxmindDb::update(source.clone()).await {
+                        tracing::warn!(%error, "error refreshing maxmind database");
+                    }
+                }
             })
         });
 
@@ -226,22 +473,24 @@ impl Proxy {
                 {
                     use crate::filters::StaticFilter as _;
                     config.filters.store(Arc::new(
-                        crate::filters::FilterChain::try_create([
-                            crate::filters::Capture::as_filter_config(
-                                crate::filters::capture::Config {
-                                    metadata_key: crate::filters::capture::CAPTURED_BYTES.into(),
-                                    strategy: crate::filters::capture::Strategy::Suffix(
-                                        crate::filters::capture::Suffix {
-                                            size: tt.length as _,
-                                            remove: true,
-                                        },
-                                    ),
-                                },
-                            )
+                        config
+                            .create_filters([
+                                crate::filters::Capture::as_filter_config(
+                                    crate::filters::capture::Config {
+                                        metadata_key: crate::filters::capture::CAPTURED_BYTES
+                                            .into(),
+                                        strategy: crate::filters::capture::Strategy::Suffix(
+                                            crate::filters::capture::Suffix {
+                                                size: tt.length as _,
+                                                remove: true,
+                                            },
+                                        ),
+                                    },
+                                )
+                                .unwrap(),
+                                crate::filters::TokenRouter::as_filter_config(None).unwrap(),
+                            ])
                             .unwrap(),
-                            crate::filters::TokenRouter::as_filter_config(None).unwrap(),
-                        ])
-                        .unwrap(),
                     ));
                 }
 
@@ -258,8 +507,11 @@ impl Proxy {
                         }
 
                         crate::net::endpoint::Endpoint::with_metadata(
-                            (*sa).into(),
-                            crate::net::endpoint::Metadata { tokens },
+                            sa.clone(),
+                            crate::net::endpoint::Metadata {
+                                tokens,
+                                ..<_>::default()
+                            },
                         )
                     })
                     .collect()
@@ -267,7 +519,7 @@ impl Proxy {
                 self.to
                     .iter()
                     .cloned()
-                    .map(crate::net::endpoint::Endpoint::from)
+                    .map(crate::net::endpoint::Endpoint::new)
                     .collect()
             };
 
@@ -282,6 +534,8 @@ impl Proxy {
              ));
         }
 
+        let _dns_task = crate::net::dns::spawn(config.clone(), shutdown_rx.clone());
+
         #[allow(clippy::type_complexity)]
         const SUBS: &[(&str, &[(&str, Vec<String>)])] = &[
             (
@@ -366,18 +620,135 @@ impl Proxy {
             worker_sends.push(psends);
         }
 
-        let sessions = SessionPool::new(config.clone(), session_sends, buffer_pool.clone());
+        let port = crate::net::socket_port(&self.socket);
+        let mut listener_ports: std::collections::BTreeSet<_> =
+            crate::filters::listeners::ports(&config.filters.load());
+        listener_ports.extend(self.listener_ports.iter().copied());
+        listener_ports.remove(&port);
+
+        let mut listener_sends = Vec::with_capacity(listener_ports.len());
+        let mut listener_session_sends = std::collections::HashMap::new();
+        for listener_port in listener_ports {
+            let mut sends = Vec::with_capacity(num_workers);
+            for _ in 0..num_workers {
+                sends.push(PendingSends::new(15)?);
+            }
+            listener_session_sends.insert(
+                listener_port,
+                sends.iter().map(|psends| psends.0.clone()).collect(),
+            );
+            listener_sends.push((listener_port, sends));
+        }
+        if !listener_sends.is_empty() {
+            tracing::info!(
+                ports = ?listener_sends.iter().map(|(port, _)| port).collect::<Vec<_>>(),
+                "receiving packets on listener ports"
+            );
+        }
+
+        let sessions = SessionPool::with_listeners(
+            config.clone(),
+            session_sends,
+            listener_session_sends,
+            buffer_pool.clone(),
+            SessionSettings {
+                dscp: self.dscp,
+                overload: self.overload,
+                transparent: self.transparent,
+                write_errors: self.write_errors,
+                coalesce: self.coalesce,
+                history: self.history,
+                heatmap: self.heatmap,
+                handshake: self.handshake,
+                duplicate: self.duplicate,
+                fairness: self.fairness,
+                ejection: self.ejection,
+                response_timeout: self.response_timeout,
+                admission: self.admission.clone(),
+                drop_log_sample: self.drop_log_sample,
+                shared_sessions: self.shared_sessions.clone(),
+                tombstones: self.tombstones.clone(),
+                warm: self.warm.clone(),
+                affinity: self.affinity,
+            },
+        );
+        *ready.history.write() = Some(sessions.history().clone());
+        *ready.heatmap.write() = Some(sessions.heatmap().clone());
+        *ready.events.write() = Some(sessions.events().clone());
+        *ready.sessions.write() = Some(Arc::downgrade(&sessions));
+
+        let handoff = if let Some(hot_restart) = self.hot_restart {
+            sessions.restore_upstreams(
+                hot_restart
+                    .state
+                    .upstreams
+                    .into_iter()
+                    .zip(hot_restart.upstreams)
+                    .collect(),
+            );
+            sessions.restore(&hot_restart.state.sessions);
+            config
+                .filters
+                .load()
+                .import_state(hot_restart.state.filters);
+
+            let phoenix = std::net::TcpListener::from(self.phoenix);
+            let sockets = crate::net::hot_restart::Sockets {
+                socket: self.socket.try_clone()?,
+                qcmp: self.qcmp.try_clone()?,
+                phoenix: phoenix.try_clone()?,
+            };
+            self.phoenix = phoenix.into();
+
+            Some(crate::net::hot_restart::spawn(
+                hot_restart.path,
+                sockets,
+                sessions.clone(),
+                config.clone(),
+            )?)
+        } else {
+            None
+        };
+
+        // A hot restart hands off fresher state than any checkpoint.
+        let checkpoints = self
+            .checkpoint
+            .store
+            .map(|store| Arc::new(checkpoint::Checkpoints::new(store, config.clone())));
+        if let Some(checkpoints) = &checkpoints {
+            if handoff.is_none() {
+                checkpoints.restore().await;
+            }
+        }
+        let _checkpoint_task = checkpoints
+            .clone()
+            .map(|checkpoints| checkpoints.spawn(self.checkpoint.interval, shutdown_rx.clone()));
+
+        let _capacity_task = ready
+            .capacity
+            .clone()
+            .spawn_sampler(sessions.clone(), shutdown_rx.clone());
+        let _maintenance_task = sessions.spawn_maintenance(shutdown_rx.clone());
+        let _tombstone_task = sessions.spawn_tombstones(shutdown_rx.clone());
+        let _warm_task = sessions.spawn_warm_sockets(shutdown_rx.clone());
+        let _synthetic_task =
+            synthetic::spawn(self.synthetic.clone(), config.clone(), shutdown_rx.clone());
 
         packet_router::spawn_receivers(
             config.clone(),
             self.socket,
             worker_sends,
+            listener_sends,
             &sessions,
             buffer_pool,
         )
         .await?;
+        worker_metrics::spawn();
 
         crate::codec::qcmp::spawn(self.qcmp, shutdown_rx.clone())?;
+        if let Some(address_discovery) = self.address_discovery {
+            address_discovery.spawn(shutdown_rx.clone())?;
+        }
         crate::net::phoenix::spawn(
             self.phoenix,
             config.clone(),
@@ -385,17 +756,53 @@ impl Proxy {
             crate::net::phoenix::Phoenix::new(crate::codec::qcmp::QcmpMeasurement::new()?),
         )?;
 
+        crate::metrics::startup_phase("proxy", started.elapsed());
         tracing::info!("Quilkin is ready");
         if let Some(initialized) = initialized {
             let _ = initialized.send(());
         }
 
-        shutdown_rx
-            .changed()
-            .await
-            .map_err(|error| eyre::eyre!(error))?;
+        let handed_off = async move {
+            match handoff {
+                Some(rx) if rx.await.is_ok() => {}
+                // If the hot restart listener failed, wait for a regular shutdown.
+                _ => std::future::pending().await,
+            }
+        };
+
+        let handed_off = tokio::select! {
+            result = shutdown_rx.changed() => {
+                result.map_err(|error| eyre::eyre!(error))?;
+                false
+            }
+            () = handed_off => true,
+        };
+
+        // The next process has taken over the sockets along with the
+        // sessions, and reads from them instead.
+        if handed_off {
+            sessions.stop_reading();
+        }
+
+        let graceful = handed_off || *shutdown_rx.borrow() == crate::ShutdownKind::Normal;
+        if graceful && !handed_off && !self.drain_timeout.is_zero() {
+            sessions
+                .drain(
+                    self.drain_timeout,
+                    self.drain_notification.as_deref(),
+                    &drain_status,
+                )
+                .await;
+        }
+
+        // The next process already has the latest state after a handoff.
+        if let Some(checkpoints) = checkpoints.filter(|_| !handed_off) {
+            if let Err(error) = checkpoints.write().await {
+                tracing::warn!(%error, "failed to write final filter state checkpoint");
+            }
+        }
 
-        sessions.shutdown(*shutdown_rx.borrow() == crate::ShutdownKind::Normal);
+        sessions.shutdown(graceful);
 
         Ok(())
     }
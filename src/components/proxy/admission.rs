@@ -0,0 +1,196 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use parking_lot::Mutex;
+
+use crate::net::{endpoint::EndpointAddress, ClusterMap};
+
+/// The CPU score at which an endpoint is considered saturated, and isn't
+/// sent new sessions.
+pub const MAX_CPU_SCORE: u8 = 100;
+
+/// Whether endpoints stop being sent new sessions once they've reached the
+/// capacity advertised in their metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Whether new sessions are only admitted to endpoints with capacity.
+    pub enabled: bool,
+    /// A payload sent back to clients whose new session was rejected, so
+    /// they can try another proxy or server rather than waiting to time out.
+    pub nack: Option<Vec<u8>>,
+}
+
+/// Why a new session was rejected by an endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Rejection {
+    /// The endpoint has as many sessions as its `max_sessions`.
+    MaxSessions,
+    /// The endpoint's `cpu_score` has reached [`MAX_CPU_SCORE`].
+    Cpu,
+}
+
+impl Rejection {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::MaxSessions => "max_sessions",
+            Self::Cpu => "cpu",
+        }
+    }
+}
+
+/// Tracks how many sessions each endpoint has been assigned, and admits new
+/// sessions only to endpoints below their advertised capacity.
+pub(crate) struct Admission {
+    config: AdmissionConfig,
+    sessions: Mutex<HashMap<SocketAddr, usize>>,
+}
+
+impl Admission {
+    /// Returns `None` if new sessions are admitted regardless of capacity.
+    pub(crate) fn new(config: AdmissionConfig) -> Option<Self> {
+        config.enabled.then(|| Self {
+            config,
+            sessions: <_>::default(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn nack(&self) -> Option<&[u8]> {
+        self.config.nack.as_deref()
+    }
+
+    /// Records a new session assigned to `endpoint`.
+    #[inline]
+    pub(crate) fn opened(&self, endpoint: SocketAddr) {
+        *self.sessions.lock().entry(endpoint).or_default() += 1;
+    }
+
+    /// Records a session assigned to `endpoint` that has closed.
+    #[inline]
+    pub(crate) fn closed(&self, endpoint: SocketAddr) {
+        let mut sessions = self.sessions.lock();
+        if let Some(count) = sessions.get_mut(&endpoint) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                sessions.remove(&endpoint);
+            }
+        }
+    }
+
+    /// The number of sessions currently assigned to `endpoint`.
+    #[inline]
+    pub(crate) fn sessions(&self, endpoint: SocketAddr) -> usize {
+        self.sessions
+            .lock()
+            .get(&endpoint)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns why a new session can't be assigned to `endpoint`, going by
+    /// the capacity it advertises in `clusters`, if it can't. Endpoints that
+    /// don't advertise a capacity are always admitted.
+    pub(crate) fn check(&self, clusters: &ClusterMap, endpoint: SocketAddr) -> Option<Rejection> {
+        let address = EndpointAddress::from(endpoint);
+        let (max_sessions, cpu_score) = clusters.iter().find_map(|cluster| {
+            cluster
+                .value()
+                .endpoints
+                .iter()
+                .find(|ep| ep.address == address)
+                .map(|ep| {
+                    let known = &ep.metadata.known;
+                    (known.max_sessions, known.cpu_score)
+                })
+        })?;
+
+        if cpu_score.is_some_and(|score| score >= MAX_CPU_SCORE) {
+            Some(Rejection::Cpu)
+        } else if max_sessions.is_some_and(|max| self.sessions(endpoint) as u64 >= max) {
+            Some(Rejection::MaxSessions)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::net::endpoint::{Endpoint, Metadata};
+
+    fn clusters() -> ClusterMap {
+        let clusters = ClusterMap::default();
+        let endpoint = |port, max_sessions, cpu_score| {
+            Endpoint::with_metadata(
+                (std::net::Ipv4Addr::LOCALHOST, port).into(),
+                Metadata {
+                    max_sessions,
+                    cpu_score,
+                    ..<_>::default()
+                },
+            )
+        };
+        clusters.insert_default(BTreeSet::from([
+            endpoint(7001, Some(2), None),
+            endpoint(7002, None, Some(MAX_CPU_SCORE)),
+            endpoint(7003, None, Some(50)),
+        ]));
+        clusters
+    }
+
+    fn address(port: u16) -> SocketAddr {
+        (std::net::Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    #[test]
+    fn admits_up_to_capacity() {
+        let admission = Admission::new(AdmissionConfig {
+            enabled: true,
+            nack: None,
+        })
+        .unwrap();
+        let clusters = clusters();
+
+        assert_eq!(admission.check(&clusters, address(7001)), None);
+        admission.opened(address(7001));
+        admission.opened(address(7001));
+        assert_eq!(
+            admission.check(&clusters, address(7001)),
+            Some(Rejection::MaxSessions)
+        );
+        admission.closed(address(7001));
+        assert_eq!(admission.sessions(address(7001)), 1);
+        assert_eq!(admission.check(&clusters, address(7001)), None);
+
+        assert_eq!(
+            admission.check(&clusters, address(7002)),
+            Some(Rejection::Cpu)
+        );
+        assert_eq!(admission.check(&clusters, address(7003)), None);
+        // Endpoints that aren't in the config are admitted.
+        assert_eq!(admission.check(&clusters, address(7004)), None);
+    }
+
+    #[test]
+    fn disabled() {
+        assert!(Admission::new(AdmissionConfig::default()).is_none());
+    }
+}
@@ -0,0 +1,53 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+
+/// How a client's packets are matched to its sessions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SessionAffinity {
+    /// By the client's IP address and port, a client whose port changes is
+    /// given new sessions.
+    #[default]
+    Address,
+    /// By the client's IP address only, a client whose port changes, such as
+    /// when a NAT rebinds it, takes its sessions over to the new port, so
+    /// upstreams keep seeing it from the same proxy socket.
+    Ip,
+}
+
+impl fmt::Display for SessionAffinity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Address => "address",
+            Self::Ip => "ip",
+        })
+    }
+}
+
+impl std::str::FromStr for SessionAffinity {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "address" => Ok(Self::Address),
+            "ip" => Ok(Self::Ip),
+            _ => Err(eyre::eyre!(
+                "unknown session affinity `{s}`, expected `address` or `ip`"
+            )),
+        }
+    }
+}
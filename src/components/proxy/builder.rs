@@ -0,0 +1,342 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+use std::sync::Arc;
+
+use crate::{
+    components::RunArgs,
+    config::Config,
+    filters::{DynFilterFactory, FilterSet},
+    ShutdownRx,
+};
+
+use super::{Proxy, Ready};
+
+/// Builds a [`Proxy`] with its own set of filters, for embedding Quilkin as a
+/// library without relying on the global
+/// [`FilterRegistry`](crate::filters::FilterRegistry).
+///
+/// ```no_run
+/// # async fn run(factory: quilkin::filters::DynFilterFactory) -> quilkin::Result<()> {
+/// let (_tx, shutdown_rx) = quilkin::make_shutdown_channel(Default::default());
+///
+/// quilkin::components::proxy::Proxy::builder()
+///     .with_filter_factory(factory)
+///     .with_config_reader(std::fs::File::open("quilkin.yaml")?)?
+///     .run(Default::default(), shutdown_rx)
+///     .await
+/// # }
+/// ```
+pub struct ProxyBuilder {
+    proxy: Proxy,
+    filters: FilterSet,
+    config: Option<Config>,
+}
+
+impl Default for ProxyBuilder {
+    fn default() -> Self {
+        Self {
+            proxy: Proxy::default(),
+            filters: FilterSet::default(),
+            config: None,
+        }
+    }
+}
+
+impl ProxyBuilder {
+    /// Adds a filter factory, replacing any existing factory with the same name.
+    pub fn with_filter_factory(mut self, factory: DynFilterFactory) -> Self {
+        self.filters.insert(factory);
+        self
+    }
+
+    /// Replaces the set of filters available to the proxy, including the
+    /// default filters.
+    pub fn with_filter_set(mut self, filters: FilterSet) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Sets the configuration of the proxy. Any filters in the configuration
+    /// have already been created, use [`Self::with_config_reader`] to create
+    /// them from this builder's filters. Filter chains it receives later are
+    /// created from this builder's filters either way.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Reads the configuration of the proxy from YAML, creating its filters
+    /// from this builder's filters.
+    pub fn with_config_reader<R: std::io::Read>(
+        mut self,
+        reader: R,
+    ) -> Result<Self, serde_yaml::Error> {
+        let filters = Arc::new(self.filters.clone());
+        self.config = Some(Config::from_reader_with(filters, reader)?);
+        Ok(self)
+    }
+
+    /// Sets the addresses to forward packets to, either IP addresses or
+    /// hostnames.
+    pub fn with_endpoints(
+        mut self,
+        to: impl IntoIterator<Item = impl Into<crate::net::EndpointAddress>>,
+    ) -> Self {
+        self.proxy.to = to.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the management servers to receive configuration from.
+    pub fn with_management_servers(
+        mut self,
+        servers: impl IntoIterator<Item = tonic::transport::Endpoint>,
+    ) -> Self {
+        self.proxy.management_servers = servers.into_iter().collect();
+        self
+    }
+
+    /// Sets the socket to receive packets from clients on.
+    pub fn with_socket(mut self, socket: socket2::Socket) -> Self {
+        self.proxy.socket = socket;
+        self
+    }
+
+    /// Sets other ports to receive packets from clients on, such as those of
+    /// a [`Listeners`](crate::filters::Listeners) filter's listeners.
+    pub fn with_listener_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.proxy.listener_ports = ports.into_iter().collect();
+        self
+    }
+
+    /// Sets the socket to respond to QCMP pings on.
+    pub fn with_qcmp_socket(mut self, qcmp: socket2::Socket) -> Self {
+        self.proxy.qcmp = qcmp;
+        self
+    }
+
+    /// Sets the listener to serve phoenix requests on.
+    pub fn with_phoenix_listener(mut self, phoenix: crate::net::TcpListener) -> Self {
+        self.proxy.phoenix = phoenix;
+        self
+    }
+
+    /// Sets the default DSCP marking of forwarded packets.
+    pub fn with_dscp(mut self, dscp: crate::net::dscp::DscpConfig) -> Self {
+        self.proxy.dscp = dscp;
+        self
+    }
+
+    /// Sets the limits beyond which packets are shed.
+    pub fn with_overload(mut self, overload: super::OverloadConfig) -> Self {
+        self.proxy.overload = overload;
+        self
+    }
+
+    /// Sets whether packets are forwarded to upstreams from the client's own
+    /// address.
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.proxy.transparent = transparent;
+        self
+    }
+
+    /// Sets how errors handling packets on their way back to clients are
+    /// handled.
+    pub fn with_write_errors(mut self, write_errors: super::WriteErrorConfig) -> Self {
+        self.proxy.write_errors = write_errors;
+        self
+    }
+
+    /// Sets whether small packets sent back to the same client are merged.
+    pub fn with_coalesce(mut self, coalesce: super::CoalesceConfig) -> Self {
+        self.proxy.coalesce = coalesce;
+        self
+    }
+
+    /// Sets the number of active sessions beyond which the proxy reports
+    /// itself as not ready.
+    pub fn with_max_sessions(mut self, max_sessions: Option<usize>) -> Self {
+        self.proxy.max_sessions = max_sessions;
+        self
+    }
+
+    /// Sets how many of the most recent packets from clients are kept in
+    /// memory.
+    pub fn with_history(mut self, history: super::PacketHistoryConfig) -> Self {
+        self.proxy.history = history;
+        self
+    }
+
+    /// Sets whether the latency of packets is tracked for each source prefix.
+    pub fn with_heatmap(mut self, heatmap: super::LatencyHeatmapConfig) -> Self {
+        self.proxy.heatmap = heatmap;
+        self
+    }
+
+    /// Sets whether packets from clients are held back until a filter has
+    /// marked them as established.
+    pub fn with_handshake(mut self, handshake: super::HandshakeConfig) -> Self {
+        self.proxy.handshake = handshake;
+        self
+    }
+
+    /// Sets whether the first packets of each session are sent to its
+    /// upstream twice.
+    pub fn with_duplicate(mut self, duplicate: super::DuplicateConfig) -> Self {
+        self.proxy.duplicate = duplicate;
+        self
+    }
+
+    /// Sets whether packets from clients are queued per client and handled
+    /// in turn.
+    pub fn with_fairness(mut self, fairness: super::FairnessConfig) -> Self {
+        self.proxy.fairness = fairness;
+        self
+    }
+
+    /// Sets whether upstreams reported unreachable through ICMP errors stop
+    /// being sent packets for a while.
+    pub fn with_ejection(mut self, ejection: super::EjectionConfig) -> Self {
+        self.proxy.ejection = ejection;
+        self
+    }
+
+    /// Sets whether sessions whose upstream doesn't respond to their packets
+    /// are flagged.
+    pub fn with_response_timeout(mut self, response_timeout: super::ResponseTimeoutConfig) -> Self {
+        self.proxy.response_timeout = response_timeout;
+        self
+    }
+
+    /// Sets whether new sessions are only admitted to upstreams below the
+    /// capacity they advertise in their metadata.
+    pub fn with_admission(mut self, admission: super::AdmissionConfig) -> Self {
+        self.proxy.admission = admission;
+        self
+    }
+
+    /// Sets one in how many dropped packets of each drop reason are logged,
+    /// none when zero.
+    pub fn with_drop_log_sample(mut self, drop_log_sample: u32) -> Self {
+        self.proxy.drop_log_sample = drop_log_sample;
+        self
+    }
+
+    /// Sets whether established clients are shared with other proxies.
+    pub fn with_shared_sessions(mut self, shared_sessions: super::SharedSessionsConfig) -> Self {
+        self.proxy.shared_sessions = shared_sessions;
+        self
+    }
+
+    /// Sets whether endpoints removed from the config are remembered for a
+    /// while.
+    pub fn with_tombstones(mut self, tombstones: super::TombstoneConfig) -> Self {
+        self.proxy.tombstones = tombstones;
+        self
+    }
+
+    /// Sets whether upstream sockets are created before sessions need them.
+    pub fn with_warm_sockets(mut self, warm: super::WarmSocketsConfig) -> Self {
+        self.proxy.warm = warm;
+        self
+    }
+
+    /// Sets how a client's packets are matched to its sessions.
+    pub fn with_session_affinity(mut self, affinity: super::SessionAffinity) -> Self {
+        self.proxy.affinity = affinity;
+        self
+    }
+
+    /// Sets whether the state of the filters is checkpointed.
+    pub fn with_checkpoint(mut self, checkpoint: super::CheckpointConfig) -> Self {
+        self.proxy.checkpoint = checkpoint;
+        self
+    }
+
+    /// Sets whether synthetic probes are run through the filters.
+    pub fn with_synthetic_probes(mut self, synthetic: super::SyntheticProbeConfig) -> Self {
+        self.proxy.synthetic = synthetic;
+        self
+    }
+
+    /// Sets the number of workers used to process packets.
+    pub fn with_num_workers(mut self, num_workers: std::num::NonZeroUsize) -> Self {
+        self.proxy.num_workers = num_workers;
+        self
+    }
+
+    /// Returns the proxy and its configuration, ready to be run with
+    /// [`Proxy::run`].
+    pub fn build(self) -> (Proxy, Arc<Config>) {
+        let mut config = self.config.unwrap_or_else(Config::default_non_agent);
+        config.filter_set = Some(Arc::new(self.filters));
+        (self.proxy, Arc::new(config))
+    }
+
+    /// Builds and runs the proxy until `shutdown_rx` is signalled.
+    pub async fn run(self, ready: Ready, shutdown_rx: ShutdownRx) -> crate::Result<()> {
+        let (proxy, config) = self.build();
+        proxy
+            .run(
+                RunArgs {
+                    config,
+                    ready,
+                    shutdown_rx,
+                },
+                None,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::StaticFilter;
+
+    #[test]
+    fn config_uses_builder_filters() {
+        let yaml = "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.pass.v1alpha1.Pass
+";
+        let (_, config) = Proxy::builder()
+            .with_filter_set(FilterSet::with([crate::filters::Pass::factory()]))
+            .with_config_reader(yaml.as_bytes())
+            .unwrap()
+            .build();
+
+        assert_eq!(config.filters.load().len(), 1);
+        assert!(config
+            .filter_set()
+            .get(crate::filters::Drop::NAME)
+            .is_none());
+        assert!(config
+            .create_filters([crate::filters::Drop::as_filter_config(None).unwrap()])
+            .is_err());
+
+        let yaml = "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.drop.v1alpha1.Drop
+";
+        assert!(Proxy::builder()
+            .with_filter_set(FilterSet::with([crate::filters::Pass::factory()]))
+            .with_config_reader(yaml.as_bytes())
+            .is_err());
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use prometheus::{IntGauge, Opts};
+
+use super::SessionPool;
+use crate::metrics::{self, register};
+
+/// How often the load of the proxy is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+const SUBSYSTEM: &str = "capacity";
+
+/// The load of the proxy relative to its capacity, exposed through the admin
+/// server's `/capacity` endpoint and metrics so it can be used to autoscale
+/// proxies.
+#[derive(Debug, Default)]
+pub struct CapacityStatus {
+    /// The soft limit on active sessions, zero if there is none.
+    max_sessions: AtomicUsize,
+    active_sessions: AtomicUsize,
+    packets_per_second: AtomicU64,
+}
+
+impl CapacityStatus {
+    /// Sets the number of active sessions beyond which the proxy reports
+    /// itself as not ready, so no new clients are sent to it.
+    pub fn set_max_sessions(&self, max_sessions: Option<usize>) {
+        self.max_sessions
+            .store(max_sessions.unwrap_or_default(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn max_sessions(&self) -> Option<usize> {
+        Some(self.max_sessions.load(Ordering::Relaxed)).filter(|max| *max > 0)
+    }
+
+    #[inline]
+    pub fn active_sessions(&self) -> usize {
+        self.active_sessions.load(Ordering::Relaxed)
+    }
+
+    /// The packets received from clients per second, over the last sample.
+    #[inline]
+    pub fn packets_per_second(&self) -> u64 {
+        self.packets_per_second.load(Ordering::Relaxed)
+    }
+
+    /// How many more sessions the proxy can take before reaching its limit,
+    /// negative once it has been exceeded.
+    pub fn session_headroom(&self) -> Option<i64> {
+        self.max_sessions()
+            .map(|max| max as i64 - self.active_sessions() as i64)
+    }
+
+    /// Whether there are more active sessions than the limit.
+    #[inline]
+    pub fn is_over_capacity(&self) -> bool {
+        self.session_headroom().is_some_and(|headroom| headroom < 0)
+    }
+
+    pub(crate) fn update(&self, active_sessions: usize, packets_per_second: u64) {
+        self.active_sessions
+            .store(active_sessions, Ordering::Relaxed);
+        self.packets_per_second
+            .store(packets_per_second, Ordering::Relaxed);
+
+        active_sessions_gauge().set(active_sessions as i64);
+        packets_per_second_gauge().set(packets_per_second as i64);
+        if let Some(max) = self.max_sessions() {
+            max_sessions_gauge().set(max as i64);
+            session_headroom_gauge().set(max as i64 - active_sessions as i64);
+        }
+    }
+
+    /// Samples the number of sessions in `sessions` and the rate of packets
+    /// received until `shutdown_rx` signals.
+    pub(crate) fn spawn_sampler(
+        self: Arc<Self>,
+        sessions: Arc<SessionPool>,
+        mut shutdown_rx: crate::ShutdownRx,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            let mut last = (
+                tokio::time::Instant::now(),
+                metrics::packets_total_sum(metrics::READ),
+            );
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown_rx.changed() => return,
+                }
+
+                let now = (
+                    tokio::time::Instant::now(),
+                    metrics::packets_total_sum(metrics::READ),
+                );
+                let elapsed = now.0.duration_since(last.0).as_secs_f64();
+                let packets_per_second = if elapsed > 0. {
+                    (now.1.saturating_sub(last.1) as f64 / elapsed) as u64
+                } else {
+                    0
+                };
+
+                self.update(sessions.sessions().len(), packets_per_second);
+                last = now;
+            }
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "active_sessions": self.active_sessions(),
+            "max_sessions": self.max_sessions(),
+            "session_headroom": self.session_headroom(),
+            "packets_per_second": self.packets_per_second(),
+            "over_capacity": self.is_over_capacity(),
+        })
+    }
+}
+
+fn gauge(name: &str, description: &str) -> IntGauge {
+    register(IntGauge::with_opts(Opts::new(name, description).subsystem(SUBSYSTEM)).unwrap())
+}
+
+fn active_sessions_gauge() -> &'static IntGauge {
+    static ACTIVE_SESSIONS: Lazy<IntGauge> =
+        Lazy::new(|| gauge("active_sessions", "number of sessions currently active"));
+    &ACTIVE_SESSIONS
+}
+
+fn max_sessions_gauge() -> &'static IntGauge {
+    static MAX_SESSIONS: Lazy<IntGauge> = Lazy::new(|| {
+        gauge(
+            "max_sessions",
+            "number of active sessions beyond which the proxy is not ready",
+        )
+    });
+    &MAX_SESSIONS
+}
+
+fn session_headroom_gauge() -> &'static IntGauge {
+    static SESSION_HEADROOM: Lazy<IntGauge> = Lazy::new(|| {
+        gauge(
+            "session_headroom",
+            "number of sessions the proxy can take before reaching its maximum",
+        )
+    });
+    &SESSION_HEADROOM
+}
+
+fn packets_per_second_gauge() -> &'static IntGauge {
+    static PACKETS_PER_SECOND: Lazy<IntGauge> = Lazy::new(|| {
+        gauge(
+            "packets_per_second",
+            "packets received from clients per second",
+        )
+    });
+    &PACKETS_PER_SECOND
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headroom() {
+        let status = CapacityStatus::default();
+        status.update(10, 100);
+        assert_eq!(status.session_headroom(), None);
+        assert!(!status.is_over_capacity());
+
+        status.set_max_sessions(Some(10));
+        assert_eq!(status.session_headroom(), Some(0));
+        assert!(!status.is_over_capacity());
+
+        status.update(12, 100);
+        assert_eq!(status.session_headroom(), Some(-2));
+        assert!(status.is_over_capacity());
+        assert_eq!(status.to_json()["session_headroom"], -2);
+    }
+}
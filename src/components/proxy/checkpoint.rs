@@ -0,0 +1,304 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Checkpoints the state filters have built up for clients, such as their
+//! rate limit buckets and quotas, so a proxy that's restarted carries on
+//! from it rather than letting every client start afresh.
+//!
+//! The state is the same as is handed off during a hot restart, see
+//! [`Filter::export_state`](crate::filters::Filter::export_state), written
+//! periodically to a file or Redis, and read back once on startup.
+
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use super::redis::Redis;
+use crate::{
+    filters::FilterState,
+    net::circuit_breaker::{self, CircuitBreaker},
+    time::UtcTimestamp,
+};
+
+/// How often the state is checkpointed by default.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Checkpoints older than this are ignored on startup, as the state in them
+/// would have expired anyway.
+const MAX_AGE: Duration = Duration::from_secs(600);
+
+/// The key the state is written to in Redis by default.
+const DEFAULT_REDIS_KEY: &str = "quilkin:filter_state";
+
+/// Stops writing checkpoints to Redis for a while when it's down, rather than
+/// waiting on it every interval.
+static BREAKER: Lazy<CircuitBreaker> = Lazy::new(|| {
+    CircuitBreaker::new(
+        "checkpoint",
+        circuit_breaker::Config {
+            max_concurrent: 1,
+            min_calls: 3,
+            ..<_>::default()
+        },
+    )
+});
+
+/// Where filter state is checkpointed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckpointStore {
+    /// A file, replaced on each checkpoint.
+    File { path: PathBuf },
+    /// A key of a Redis server, at `host:port`.
+    Redis { address: String, key: String },
+}
+
+impl FromStr for CheckpointStore {
+    type Err = eyre::Error;
+
+    /// Parses `redis://host:port[/key]`, `file://path`, or a path.
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, location)) = url.split_once("://") else {
+            eyre::ensure!(!url.is_empty(), "the checkpoint path is empty");
+            return Ok(Self::File { path: url.into() });
+        };
+        eyre::ensure!(!location.is_empty(), "`{url}` is missing its location");
+
+        match scheme {
+            "file" => Ok(Self::File {
+                path: location.into(),
+            }),
+            "redis" => {
+                let (address, key) = location
+                    .split_once('/')
+                    .filter(|(_, key)| !key.is_empty())
+                    .unwrap_or((location, DEFAULT_REDIS_KEY));
+                Ok(Self::Redis {
+                    address: address.into(),
+                    key: key.into(),
+                })
+            }
+            scheme => eyre::bail!("unsupported checkpoint store `{scheme}`"),
+        }
+    }
+}
+
+/// Whether filter state is checkpointed, and how often.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckpointConfig {
+    /// Where the state is checkpointed, not checkpointed when `None`.
+    pub store: Option<CheckpointStore>,
+    pub interval: Duration,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            store: None,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+/// A checkpoint, as written to the store.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct Checkpoint {
+    /// When the checkpoint was written, in seconds since the epoch.
+    written_at: i64,
+    filters: Vec<FilterState>,
+}
+
+enum Backend {
+    File(PathBuf),
+    Redis { redis: Redis, key: String },
+}
+
+/// Writes and restores checkpoints of the state of a proxy's filters.
+pub(crate) struct Checkpoints {
+    backend: Backend,
+    config: Arc<crate::Config>,
+}
+
+impl Checkpoints {
+    pub(crate) fn new(store: CheckpointStore, config: Arc<crate::Config>) -> Self {
+        let backend = match store {
+            CheckpointStore::File { path } => Backend::File(path),
+            CheckpointStore::Redis { address, key } => Backend::Redis {
+                redis: Redis::new(address),
+                key,
+            },
+        };
+
+        Self { backend, config }
+    }
+
+    /// Restores the state of the filter chain from the last checkpoint, if
+    /// there is a recent one.
+    pub(crate) async fn restore(&self) {
+        let checkpoint = match self.read().await {
+            Ok(Some(checkpoint)) => checkpoint,
+            Ok(None) => {
+                tracing::info!("no filter state checkpoint to restore");
+                return;
+            }
+            Err(error) => {
+                tracing::warn!(%error, "failed to read filter state checkpoint");
+                return;
+            }
+        };
+
+        let age = UtcTimestamp::now().unix() - checkpoint.written_at;
+        let age = Duration::from_secs(u64::try_from(age).unwrap_or_default());
+        if age > MAX_AGE {
+            tracing::info!(?age, "ignoring stale filter state checkpoint");
+            return;
+        }
+
+        tracing::info!(
+            ?age,
+            filters = checkpoint.filters.len(),
+            "restoring filter state checkpoint"
+        );
+        self.config.filters.load().import_state(checkpoint.filters);
+    }
+
+    /// Writes the current state of the filter chain.
+    pub(crate) async fn write(&self) -> eyre::Result<()> {
+        let checkpoint = serde_json::to_vec(&Checkpoint {
+            written_at: UtcTimestamp::now().unix(),
+            filters: self.config.filters.load().export_state(),
+        })?;
+
+        match &self.backend {
+            Backend::File(path) => {
+                // Written beside the checkpoint and moved over it, so a crash
+                // midway never leaves a truncated checkpoint.
+                let mut partial = path.clone().into_os_string();
+                partial.push(".partial");
+                tokio::fs::write(&partial, &checkpoint).await?;
+                tokio::fs::rename(&partial, path).await?;
+            }
+            Backend::Redis { redis, key } => BREAKER
+                .call(|| redis.write(key, &checkpoint))
+                .await
+                .map_err(circuit_breaker::CallError::into_report)?,
+        }
+
+        Ok(())
+    }
+
+    async fn read(&self) -> eyre::Result<Option<Checkpoint>> {
+        let checkpoint = match &self.backend {
+            Backend::File(path) => match tokio::fs::read(path).await {
+                Ok(checkpoint) => Some(checkpoint),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+                Err(error) => return Err(error.into()),
+            },
+            Backend::Redis { redis, key } => BREAKER
+                .call(|| redis.read(key))
+                .await
+                .map_err(circuit_breaker::CallError::into_report)?,
+        };
+
+        checkpoint
+            .map(|checkpoint| serde_json::from_slice(&checkpoint))
+            .transpose()
+            .map_err(From::from)
+    }
+
+    /// Spawns a task writing a checkpoint every `interval` until shutdown.
+    pub(crate) fn spawn(
+        self: Arc<Self>,
+        interval: Duration,
+        mut shutdown_rx: crate::ShutdownRx,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            // The first tick completes immediately, before there's any state.
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown_rx.changed() => return,
+                }
+
+                if let Err(error) = self.write().await {
+                    crate::log_throttle::throttled!(warn!(
+                        %error,
+                        "failed to write filter state checkpoint"
+                    ));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_store() {
+        assert_eq!(
+            "/var/lib/quilkin/state.json"
+                .parse::<CheckpointStore>()
+                .unwrap(),
+            CheckpointStore::File {
+                path: "/var/lib/quilkin/state.json".into()
+            }
+        );
+        assert_eq!(
+            "file://state.json".parse::<CheckpointStore>().unwrap(),
+            CheckpointStore::File {
+                path: "state.json".into()
+            }
+        );
+        assert_eq!(
+            "redis://localhost:6379".parse::<CheckpointStore>().unwrap(),
+            CheckpointStore::Redis {
+                address: "localhost:6379".into(),
+                key: DEFAULT_REDIS_KEY.into(),
+            }
+        );
+        assert_eq!(
+            "redis://localhost:6379/proxy-0"
+                .parse::<CheckpointStore>()
+                .unwrap(),
+            CheckpointStore::Redis {
+                address: "localhost:6379".into(),
+                key: "proxy-0".into(),
+            }
+        );
+        assert!("ftp://host".parse::<CheckpointStore>().is_err());
+        assert!("redis://".parse::<CheckpointStore>().is_err());
+    }
+
+    #[tokio::test]
+    async fn file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let checkpoints = Checkpoints::new(
+            CheckpointStore::File { path: path.clone() },
+            Arc::new(crate::Config::default_non_agent()),
+        );
+
+        assert!(checkpoints.read().await.unwrap().is_none());
+        checkpoints.write().await.unwrap();
+        let checkpoint = checkpoints.read().await.unwrap().unwrap();
+        assert!(checkpoint.filters.is_empty());
+        assert!(UtcTimestamp::now().unix() - checkpoint.written_at < 60);
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+use std::{sync::Arc, time::Duration};
+
+use super::{PendingSends, SendPacket};
+use crate::{metrics, pool::BufferPool};
+
+/// Merging of small packets sent back to the same client into a single
+/// datagram, trading a little latency for fewer sends.
+///
+/// The merged datagram is the concatenation of the packets, so this is only
+/// suitable for protocols where clients can split it back into packets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    /// The largest a merged datagram may be, packets are only merged when
+    /// this is set.
+    pub max_size: Option<usize>,
+    /// How long a send loop waits after being woken for more packets to be
+    /// queued, so they can be merged with the first.
+    pub max_delay: Duration,
+}
+
+impl CoalesceConfig {
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.max_size.is_some_and(|max_size| max_size > 0)
+    }
+
+    /// The time to wait before sending queued packets, if any.
+    #[inline]
+    pub(crate) fn delay(&self) -> Option<Duration> {
+        (self.is_enabled() && !self.max_delay.is_zero()).then_some(self.max_delay)
+    }
+}
+
+impl PendingSends {
+    /// Pushes a packet onto the queue to be sent, appending it to the last
+    /// packet already queued for the same destination instead if both fit
+    /// within `max_size` bytes.
+    pub(crate) fn push_coalesced(
+        &self,
+        packet: SendPacket,
+        max_size: usize,
+        buffer_pool: &Arc<BufferPool>,
+    ) {
+        {
+            let mut packets = self.packets.lock();
+            let queued = packets
+                .iter_mut()
+                .rev()
+                .find(|queued| queued.destination == packet.destination);
+
+            match queued {
+                Some(queued)
+                    if queued.dscp == packet.dscp
+                        && queued.data.len() + packet.data.len() <= max_size =>
+                {
+                    let mut data = buffer_pool
+                        .clone()
+                        .alloc_sized(queued.data.len() + packet.data.len());
+                    data.extend_from_slice(&queued.data);
+                    data.extend_from_slice(&packet.data);
+                    queued.data = data.freeze();
+                    metrics::packets_coalesced_total(metrics::WRITE).inc();
+
+                    // The sender is already due to send the queued packet.
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        self.push(packet);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(pool: &Arc<BufferPool>, port: u16, data: &[u8]) -> SendPacket {
+        SendPacket {
+            destination: std::net::SocketAddr::from(([127, 0, 0, 1], port)).into(),
+            data: pool.clone().alloc_slice(data).freeze(),
+            asn_info: None,
+            dscp: None,
+        }
+    }
+
+    #[test]
+    fn config() {
+        assert!(!CoalesceConfig::default().is_enabled());
+        assert_eq!(
+            CoalesceConfig {
+                max_size: None,
+                max_delay: Duration::from_micros(100),
+            }
+            .delay(),
+            None
+        );
+        assert_eq!(
+            CoalesceConfig {
+                max_size: Some(1200),
+                max_delay: Duration::from_micros(100),
+            }
+            .delay(),
+            Some(Duration::from_micros(100))
+        );
+    }
+
+    #[test]
+    fn merges_packets_to_same_destination() {
+        let pool = Arc::new(BufferPool::new(1, 64));
+        let (sends, _rx) = PendingSends::new(4).unwrap();
+
+        sends.push_coalesced(packet(&pool, 1000, b"ab"), 5, &pool);
+        sends.push_coalesced(packet(&pool, 2000, b"cd"), 5, &pool);
+        sends.push_coalesced(packet(&pool, 1000, b"ef"), 5, &pool);
+        // Would exceed the maximum size.
+        sends.push_coalesced(packet(&pool, 1000, b"gh"), 5, &pool);
+
+        let packets = sends.swap(Vec::new());
+        let contents: Vec<_> = packets.iter().map(|packet| &*packet.data).collect();
+        assert_eq!(contents, [&b"abef"[..], b"cd", b"gh"]);
+    }
+}
@@ -0,0 +1,93 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{filters::DropReason, metrics};
+
+/// Counts every dropped packet by its [`DropReason`], and logs a sample of
+/// them per reason.
+#[derive(Debug, Default)]
+pub(crate) struct DropLog {
+    /// One in how many drops of each reason are logged, none when zero.
+    sample: u32,
+    drops: [AtomicU64; DropReason::ALL.len()],
+}
+
+impl DropLog {
+    pub(crate) fn new(sample: u32) -> Self {
+        Self {
+            sample,
+            ..Self::default()
+        }
+    }
+
+    /// Records a packet from `address` travelling in `direction` that was
+    /// dropped for `reason`, by the error described by `detail`. Returns
+    /// whether the drop was logged.
+    #[inline]
+    pub(crate) fn record(
+        &self,
+        direction: metrics::Direction,
+        reason: DropReason,
+        detail: &str,
+        address: SocketAddr,
+    ) -> bool {
+        metrics::packet_drops_total(direction, reason.as_str()).inc();
+        if self.sample == 0 {
+            return false;
+        }
+
+        let drops = self.drops[reason.index()].fetch_add(1, Ordering::Relaxed);
+        if drops % u64::from(self.sample) != 0 {
+            return false;
+        }
+
+        tracing::warn!(
+            direction = direction.label(),
+            %reason,
+            detail,
+            %address,
+            drops = drops + 1,
+            "packet dropped"
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_per_reason() {
+        let log = DropLog::new(3);
+        let address = (std::net::Ipv4Addr::LOCALHOST, 7777).into();
+        let record = |reason| log.record(metrics::READ, reason, "test", address);
+
+        assert!(record(DropReason::Denied));
+        assert!(!record(DropReason::Denied));
+        assert!(record(DropReason::RateLimited));
+        assert!(!record(DropReason::Denied));
+        assert!(record(DropReason::Denied));
+
+        let disabled = DropLog::default();
+        assert!(!disabled.record(metrics::READ, DropReason::Denied, "test", address));
+    }
+}
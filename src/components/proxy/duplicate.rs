@@ -0,0 +1,151 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use tokio::{sync::mpsc, time::Instant};
+
+use super::{PendingSends, SendPacket};
+use crate::metrics;
+
+/// How long after the original a duplicate is sent by default.
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(10);
+
+/// Sending the first packets of each session to its upstream twice, so a
+/// lost handshake packet doesn't cost the client a timeout and retry.
+///
+/// Upstreams receive both copies, so this is only suitable for protocols
+/// whose servers ignore duplicate packets, or behind a filter removing them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DuplicateConfig {
+    /// The number of packets at the start of each session that are sent
+    /// twice, zero disables duplication.
+    pub count: u32,
+    /// How long after the original each duplicate is sent, so both aren't
+    /// lost to the same burst.
+    pub delay: Duration,
+}
+
+impl Default for DuplicateConfig {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            delay: DEFAULT_DELAY,
+        }
+    }
+}
+
+/// A duplicate waiting to be queued.
+struct Delayed {
+    send_at: Instant,
+    sends: PendingSends,
+    packet: SendPacket,
+}
+
+/// Queues duplicates once their delay has elapsed.
+///
+/// Every duplicate has the same delay, so they're due in the order they're
+/// scheduled, and a single task can wait for each in turn.
+pub(crate) struct Duplicator {
+    config: DuplicateConfig,
+    delayed: mpsc::UnboundedSender<Delayed>,
+}
+
+impl Duplicator {
+    /// Creates the duplicator, spawning the task queueing duplicates, or
+    /// returns `None` if duplication is disabled. The task stops once the
+    /// duplicator is dropped.
+    pub(crate) fn new(config: DuplicateConfig) -> Option<Self> {
+        if config.count == 0 {
+            return None;
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<Delayed>();
+        tokio::spawn(async move {
+            while let Some(delayed) = rx.recv().await {
+                tokio::time::sleep_until(delayed.send_at).await;
+                delayed.sends.push(delayed.packet);
+                metrics::packets_duplicated_total(metrics::READ).inc();
+            }
+        });
+
+        Some(Self {
+            config,
+            delayed: tx,
+        })
+    }
+
+    /// The number of packets at the start of each session that are sent
+    /// twice.
+    #[inline]
+    pub(crate) fn count(&self) -> u32 {
+        self.config.count
+    }
+
+    /// Queues a copy of `packet` on `sends` once the configured delay has
+    /// elapsed.
+    #[inline]
+    pub(crate) fn schedule(&self, sends: PendingSends, packet: &SendPacket) {
+        // Only fails once the task has stopped, in which case the proxy is
+        // shutting down.
+        let _ = self.delayed.send(Delayed {
+            send_at: Instant::now() + self.config.delay,
+            sends,
+            packet: SendPacket {
+                destination: packet.destination.clone(),
+                data: packet.data.clone(),
+                asn_info: packet.asn_info.clone(),
+                dscp: packet.dscp,
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn queues_duplicates_after_delay() {
+        let duplicator = Duplicator::new(DuplicateConfig {
+            count: 1,
+            delay: Duration::from_millis(10),
+        })
+        .unwrap();
+        let (sends, _rx) = PendingSends::new(1).unwrap();
+        let pool = std::sync::Arc::new(crate::pool::BufferPool::new(1, 10));
+        let packet = SendPacket {
+            destination: std::net::SocketAddr::from(([127, 0, 0, 1], 1000)).into(),
+            data: pool.alloc_slice(b"hello").freeze(),
+            asn_info: None,
+            dscp: None,
+        };
+
+        duplicator.schedule(sends.clone(), &packet);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(sends.len(), 0);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let queued = sends.swap(Vec::new());
+        assert_eq!(queued.len(), 1);
+        assert_eq!(&*queued[0].data, b"hello");
+    }
+
+    #[tokio::test]
+    async fn disabled() {
+        assert!(Duplicator::new(DuplicateConfig::default()).is_none());
+    }
+}
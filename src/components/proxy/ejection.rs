@@ -0,0 +1,179 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+
+/// The number of unreachable errors within the ejection duration after which
+/// an upstream is ejected by default.
+pub const DEFAULT_THRESHOLD: u32 = 3;
+
+/// How long an upstream is ejected for by default.
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(30);
+
+/// Whether upstreams reported unreachable through ICMP errors, such as a
+/// game server that has exited, stop being sent packets for a while.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EjectionConfig {
+    /// Whether upstreams are ejected.
+    pub enabled: bool,
+    /// The number of errors within `duration` of each other after which an
+    /// upstream is ejected.
+    pub threshold: u32,
+    /// How long an upstream is ejected for, after which it's sent packets
+    /// again until it's reported unreachable enough times.
+    pub duration: Duration,
+}
+
+impl Default for EjectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: DEFAULT_THRESHOLD,
+            duration: DEFAULT_DURATION,
+        }
+    }
+}
+
+/// The errors reported for an upstream.
+struct UpstreamState {
+    errors: u32,
+    last_error: Instant,
+    ejected_until: Option<Instant>,
+}
+
+/// Tracks which upstreams have been reported unreachable, and are ejected.
+pub(crate) struct Ejections {
+    config: EjectionConfig,
+    /// Only upstreams with recent errors are kept.
+    upstreams: RwLock<HashMap<SocketAddr, UpstreamState>>,
+}
+
+impl Ejections {
+    /// Returns `None` if upstreams aren't ejected.
+    pub(crate) fn new(config: EjectionConfig) -> Option<Self> {
+        config.enabled.then(|| Self {
+            config,
+            upstreams: <_>::default(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn duration(&self) -> Duration {
+        self.config.duration
+    }
+
+    /// Records an unreachable error for `upstream`, returning whether it has
+    /// just been ejected.
+    pub(crate) fn report(&self, upstream: SocketAddr, now: Instant) -> bool {
+        let duration = self.config.duration;
+        let mut upstreams = self.upstreams.write();
+        upstreams.retain(|_, state| {
+            now.duration_since(state.last_error) < duration
+                || state.ejected_until.is_some_and(|until| now < until)
+        });
+
+        let state = upstreams.entry(upstream).or_insert(UpstreamState {
+            errors: 0,
+            last_error: now,
+            ejected_until: None,
+        });
+        state.last_error = now;
+
+        match state.ejected_until {
+            Some(until) if now < until => return false,
+            Some(_) => {
+                state.errors = 0;
+                state.ejected_until = None;
+            }
+            None => {}
+        }
+
+        state.errors += 1;
+        if state.errors < self.config.threshold {
+            return false;
+        }
+
+        state.ejected_until = Some(now + duration);
+        true
+    }
+
+    /// Returns whether `upstream` is currently ejected.
+    #[inline]
+    pub(crate) fn is_ejected(&self, upstream: SocketAddr, now: Instant) -> bool {
+        let upstreams = self.upstreams.read();
+        !upstreams.is_empty()
+            && upstreams
+                .get(&upstream)
+                .and_then(|state| state.ejected_until)
+                .is_some_and(|until| now < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ejects_after_threshold() {
+        let ejections = Ejections::new(EjectionConfig {
+            enabled: true,
+            threshold: 2,
+            duration: Duration::from_secs(10),
+        })
+        .unwrap();
+        let upstream = (std::net::Ipv4Addr::LOCALHOST, 7777).into();
+        let other = (std::net::Ipv4Addr::LOCALHOST, 7778).into();
+        let now = Instant::now();
+
+        assert!(!ejections.report(upstream, now));
+        assert!(!ejections.is_ejected(upstream, now));
+        assert!(ejections.report(upstream, now + Duration::from_secs(1)));
+        assert!(ejections.is_ejected(upstream, now + Duration::from_secs(1)));
+        assert!(!ejections.is_ejected(other, now + Duration::from_secs(1)));
+
+        // Errors while ejected don't extend the ejection.
+        assert!(!ejections.report(upstream, now + Duration::from_secs(5)));
+        assert!(ejections.is_ejected(upstream, now + Duration::from_secs(10)));
+        assert!(!ejections.is_ejected(upstream, now + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn forgets_old_errors() {
+        let ejections = Ejections::new(EjectionConfig {
+            enabled: true,
+            threshold: 2,
+            duration: Duration::from_secs(10),
+        })
+        .unwrap();
+        let upstream = (std::net::Ipv4Addr::LOCALHOST, 7777).into();
+        let now = Instant::now();
+
+        assert!(!ejections.report(upstream, now));
+        assert!(!ejections.report(upstream, now + Duration::from_secs(20)));
+        assert!(!ejections.is_ejected(upstream, now + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn disabled() {
+        assert!(Ejections::new(EjectionConfig::default()).is_none());
+    }
+}
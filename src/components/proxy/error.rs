@@ -16,6 +16,8 @@
 
 use std::{fmt, hash::Hash};
 
+use crate::filters::DropReason;
+
 #[derive(Debug)]
 pub enum PipelineError {
     NoUpstreamEndpoints,
@@ -24,6 +26,31 @@ pub enum PipelineError {
     Io(std::io::Error),
     ChannelClosed,
     ChannelFull,
+    /// The packet was shed because the proxy is overloaded
+    Overloaded(super::OverloadReason),
+    /// A filter selected an endpoint that isn't configured
+    SelectedEndpointUnavailable,
+    /// The packet's source hasn't been marked as established by a filter
+    NotEstablished,
+    /// The packet's source sent more packets than allowed before being
+    /// marked as established
+    HandshakeBudgetExceeded,
+    /// Every endpoint the packet was for has been ejected after being
+    /// reported unreachable
+    UpstreamUnreachable,
+    /// Every endpoint the packet was for has been drained by a maintenance
+    /// window without a fallback
+    Maintenance,
+    /// The packet's session has exceeded its quota, and was terminated
+    SessionQuotaExceeded,
+    /// The packet's session has exceeded its quota, and was throttled
+    SessionThrottled,
+    /// Every endpoint the packet was for is at the capacity it advertises,
+    /// so no new session was admitted
+    AdmissionRejected,
+    /// The endpoint the packet's session was with has been removed from the
+    /// config within the tombstone window
+    EndpointRemoved,
     /// This occurs if a receive task has accumulated so many errors that the
     /// error details had to be dropped in order to reduce memory pressure
     AccumulatorOverflow,
@@ -38,9 +65,41 @@ impl PipelineError {
             Self::Io(_) => "io",
             Self::ChannelClosed => "channel closed",
             Self::ChannelFull => "channel full",
+            Self::Overloaded(_) => "overloaded",
+            Self::SelectedEndpointUnavailable => "selected endpoint unavailable",
+            Self::NotEstablished => "session not established",
+            Self::HandshakeBudgetExceeded => "handshake budget exceeded",
+            Self::UpstreamUnreachable => "upstream unreachable",
+            Self::Maintenance => "maintenance",
+            Self::SessionQuotaExceeded => "session quota exceeded",
+            Self::SessionThrottled => "session throttled",
+            Self::AdmissionRejected => "admission rejected",
+            Self::EndpointRemoved => "endpoint removed",
             Self::AccumulatorOverflow => "error accumulator overflow",
         }
     }
+
+    /// Why the packet was dropped, out of the reasons every drop is counted
+    /// under.
+    pub fn drop_reason(&self) -> DropReason {
+        match self {
+            Self::NoUpstreamEndpoints | Self::SelectedEndpointUnavailable => {
+                DropReason::NoEndpoints
+            }
+            Self::Filter(fe) => fe.drop_reason(),
+            Self::Session(super::sessions::SessionError::Draining) => DropReason::Unavailable,
+            Self::Session(_) | Self::Io(_) | Self::ChannelClosed | Self::AccumulatorOverflow => {
+                DropReason::Internal
+            }
+            Self::ChannelFull | Self::Overloaded(_) => DropReason::Overloaded,
+            Self::NotEstablished | Self::HandshakeBudgetExceeded => DropReason::Denied,
+            Self::UpstreamUnreachable
+            | Self::Maintenance
+            | Self::AdmissionRejected
+            | Self::EndpointRemoved => DropReason::Unavailable,
+            Self::SessionQuotaExceeded | Self::SessionThrottled => DropReason::RateLimited,
+        }
+    }
 }
 
 impl std::error::Error for PipelineError {}
@@ -54,6 +113,20 @@ impl fmt::Display for PipelineError {
             Self::Io(io) => write!(f, "OS level error: {io}"),
             Self::ChannelClosed => f.write_str("channel closed"),
             Self::ChannelFull => f.write_str("channel full"),
+            Self::Overloaded(reason) => write!(f, "overloaded: {reason}"),
+            Self::SelectedEndpointUnavailable => {
+                f.write_str("no endpoint matches the selected endpoint")
+            }
+            Self::NotEstablished => f.write_str("session not established"),
+            Self::HandshakeBudgetExceeded => {
+                f.write_str("too many packets before the session was established")
+            }
+            Self::UpstreamUnreachable => f.write_str("upstream endpoints unreachable"),
+            Self::Maintenance => f.write_str("upstream endpoints drained for maintenance"),
+            Self::SessionQuotaExceeded => f.write_str("session quota exceeded"),
+            Self::SessionThrottled => f.write_str("session throttled after exceeding its quota"),
+            Self::AdmissionRejected => f.write_str("upstream endpoints at capacity"),
+            Self::EndpointRemoved => f.write_str("upstream endpoint was removed"),
             Self::AccumulatorOverflow => f.write_str("error accumulator overflow"),
         }
     }
@@ -80,6 +153,16 @@ impl PartialEq for PipelineError {
             (Self::Io(ia), Self::Io(ib)) => ia.kind().eq(&ib.kind()),
             (Self::ChannelClosed, Self::ChannelClosed) => true,
             (Self::ChannelFull, Self::ChannelFull) => true,
+            (Self::Overloaded(ra), Self::Overloaded(rb)) => ra.eq(rb),
+            (Self::SelectedEndpointUnavailable, Self::SelectedEndpointUnavailable) => true,
+            (Self::NotEstablished, Self::NotEstablished) => true,
+            (Self::HandshakeBudgetExceeded, Self::HandshakeBudgetExceeded) => true,
+            (Self::UpstreamUnreachable, Self::UpstreamUnreachable) => true,
+            (Self::Maintenance, Self::Maintenance) => true,
+            (Self::SessionQuotaExceeded, Self::SessionQuotaExceeded) => true,
+            (Self::SessionThrottled, Self::SessionThrottled) => true,
+            (Self::AdmissionRejected, Self::AdmissionRejected) => true,
+            (Self::EndpointRemoved, Self::EndpointRemoved) => true,
             (Self::AccumulatorOverflow, Self::AccumulatorOverflow) => true,
             _ => false,
         }
@@ -97,9 +180,19 @@ impl Hash for PipelineError {
             Self::Filter(fe) => Hash::hash(&fe, state),
             Self::Session(se) => Hash::hash(&se, state),
             Self::Io(io) => Hash::hash(&io.kind(), state),
+            Self::Overloaded(reason) => Hash::hash(&reason, state),
             Self::NoUpstreamEndpoints
             | Self::ChannelClosed
             | Self::ChannelFull
+            | Self::SelectedEndpointUnavailable
+            | Self::NotEstablished
+            | Self::HandshakeBudgetExceeded
+            | Self::UpstreamUnreachable
+            | Self::Maintenance
+            | Self::SessionQuotaExceeded
+            | Self::SessionThrottled
+            | Self::AdmissionRejected
+            | Self::EndpointRemoved
             | Self::AccumulatorOverflow => {}
         }
     }
@@ -0,0 +1,243 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::net::SocketAddr;
+
+use tokio::sync::broadcast;
+
+use crate::time::UtcTimestamp;
+
+/// The number of events buffered for each subscriber, subscribers falling
+/// further behind miss the oldest events.
+const CAPACITY: usize = 1024;
+
+/// Something that happened in the proxy, streamed to subscribers of the
+/// admin server's `/events` endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A session was created for a client and upstream.
+    SessionEstablished {
+        source: SocketAddr,
+        destination: SocketAddr,
+        /// When the session was created, in seconds since the unix epoch.
+        timestamp: i64,
+    },
+    /// A session was removed, either having expired or while draining.
+    SessionExpired {
+        source: SocketAddr,
+        destination: SocketAddr,
+        /// When the session was removed, in seconds since the unix epoch.
+        timestamp: i64,
+        /// How long the session lasted, in seconds.
+        duration_secs: u64,
+    },
+    /// A packet from a client was dropped.
+    PacketDropped {
+        source: SocketAddr,
+        /// Why the packet was dropped, the same as the `reason` label of
+        /// `quilkin_packets_dropped_total`.
+        reason: &'static str,
+        /// When the packet was dropped, in seconds since the unix epoch.
+        timestamp: i64,
+    },
+    /// An upstream stopped being sent packets after being reported
+    /// unreachable.
+    UpstreamEjected {
+        destination: SocketAddr,
+        /// Why the upstream was last reported unreachable.
+        reason: &'static str,
+        /// When the upstream was ejected, in seconds since the unix epoch.
+        timestamp: i64,
+        /// How long the upstream is ejected for, in seconds.
+        duration_secs: u64,
+    },
+    /// A session exceeded its quota.
+    SessionQuotaExceeded {
+        source: SocketAddr,
+        destination: SocketAddr,
+        /// The limit exceeded, either `bytes`, `packets` or `duration`.
+        limit: &'static str,
+        /// What happens to the session, either `terminate`, `throttle` or
+        /// `event`.
+        action: &'static str,
+        /// When the quota was exceeded, in seconds since the unix epoch.
+        timestamp: i64,
+    },
+    /// Events were missed as the subscriber fell behind.
+    Lagged { missed: u64 },
+}
+
+impl Event {
+    pub fn session_established(source: SocketAddr, destination: SocketAddr) -> Self {
+        Self::SessionEstablished {
+            source,
+            destination,
+            timestamp: UtcTimestamp::now().unix(),
+        }
+    }
+
+    pub fn session_expired(
+        source: SocketAddr,
+        destination: SocketAddr,
+        duration: std::time::Duration,
+    ) -> Self {
+        Self::SessionExpired {
+            source,
+            destination,
+            timestamp: UtcTimestamp::now().unix(),
+            duration_secs: duration.as_secs(),
+        }
+    }
+
+    pub fn packet_dropped(source: SocketAddr, reason: &'static str) -> Self {
+        Self::PacketDropped {
+            source,
+            reason,
+            timestamp: UtcTimestamp::now().unix(),
+        }
+    }
+
+    pub fn upstream_ejected(
+        destination: SocketAddr,
+        reason: &'static str,
+        duration: std::time::Duration,
+    ) -> Self {
+        Self::UpstreamEjected {
+            destination,
+            reason,
+            timestamp: UtcTimestamp::now().unix(),
+            duration_secs: duration.as_secs(),
+        }
+    }
+
+    pub fn session_quota_exceeded(
+        source: SocketAddr,
+        destination: SocketAddr,
+        limit: &'static str,
+        action: &'static str,
+    ) -> Self {
+        Self::SessionQuotaExceeded {
+            source,
+            destination,
+            limit,
+            action,
+            timestamp: UtcTimestamp::now().unix(),
+        }
+    }
+
+    /// The event as a Server-Sent Events message.
+    pub fn to_sse(&self) -> bytes::Bytes {
+        let name = match self {
+            Self::SessionEstablished { .. } => "session_established",
+            Self::SessionExpired { .. } => "session_expired",
+            Self::PacketDropped { .. } => "packet_dropped",
+            Self::UpstreamEjected { .. } => "upstream_ejected",
+            Self::SessionQuotaExceeded { .. } => "session_quota_exceeded",
+            Self::Lagged { .. } => "lagged",
+        };
+
+        // Serializing an event can't fail, and JSON is never multi-line.
+        let data = serde_json::to_string(self).unwrap();
+        format!("event: {name}\ndata: {data}\n\n").into()
+    }
+}
+
+/// Broadcasts events to every subscriber.
+///
+/// Events are only created while there are subscribers, so a proxy nobody
+/// is watching doesn't pay for them.
+#[derive(Clone, Debug)]
+pub struct Events {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl Events {
+    /// Sends the event created by `event` to subscribers, if there are any.
+    #[inline]
+    pub fn emit(&self, event: impl FnOnce() -> Event) {
+        if self.tx.receiver_count() > 0 {
+            // Only fails when the last subscriber has just gone.
+            let _ = self.tx.send(event());
+        }
+    }
+
+    /// Returns a stream of events emitted from now on, with a
+    /// [`Event::Lagged`] in place of any missed by falling behind.
+    pub fn subscribe(&self) -> impl futures::Stream<Item = Event> + Send + 'static {
+        use tokio_stream::{
+            wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+            StreamExt,
+        };
+
+        BroadcastStream::new(self.tx.subscribe()).map(|event| match event {
+            Ok(event) => event,
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Event::Lagged { missed },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn only_emits_to_subscribers() {
+        let events = Events::default();
+        let source: SocketAddr = ([127, 0, 0, 1], 1000).into();
+
+        events.emit(|| panic!("created an event without subscribers"));
+
+        let mut stream = Box::pin(events.subscribe());
+        events.emit(|| Event::packet_dropped(source, "filter::drop::dropped"));
+
+        let Some(Event::PacketDropped { reason, .. }) = stream.next().await else {
+            panic!("expected a dropped packet");
+        };
+        assert_eq!(reason, "filter::drop::dropped");
+    }
+
+    #[tokio::test]
+    async fn reports_lagging() {
+        let events = Events::default();
+        let source: SocketAddr = ([127, 0, 0, 1], 1000).into();
+        let mut stream = Box::pin(events.subscribe());
+
+        for _ in 0..CAPACITY + 10 {
+            events.emit(|| Event::packet_dropped(source, "filter::drop::dropped"));
+        }
+
+        assert_eq!(stream.next().await, Some(Event::Lagged { missed: 10 }));
+    }
+
+    #[test]
+    fn sse() {
+        let event = Event::Lagged { missed: 2 };
+        assert_eq!(
+            &*event.to_sse(),
+            b"event: lagged\ndata: {\"type\":\"lagged\",\"missed\":2}\n\n"
+        );
+    }
+}
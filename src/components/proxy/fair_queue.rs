@@ -0,0 +1,256 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+};
+
+use super::packet_router::DownstreamPacket;
+use crate::{metrics, net::maxmind_db::MetricsIpNetEntry};
+
+/// The number of bytes each client can have handled per round by default.
+pub const DEFAULT_QUANTUM: usize = 1500;
+
+/// The most packets queued from a single client by default.
+pub const DEFAULT_MAX_PER_SOURCE: usize = 64;
+
+/// The most packets queued from all clients by default.
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// The number of packets received, and handled, at once by each worker when
+/// the fair queue is enabled.
+pub(crate) const BURST: usize = 32;
+
+/// Whether packets from clients are queued per client and handled in turn,
+/// so a single client sending more than its share can't starve the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FairnessConfig {
+    /// Whether packets are queued per client.
+    pub enabled: bool,
+    /// The number of bytes each client can have handled per round.
+    pub quantum: usize,
+    /// The most packets queued from a single client, any more from it are
+    /// dropped until it has been served.
+    pub max_per_source: usize,
+    /// The most packets queued from all clients, any more are dropped.
+    pub capacity: usize,
+}
+
+impl Default for FairnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quantum: DEFAULT_QUANTUM,
+            max_per_source: DEFAULT_MAX_PER_SOURCE,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+/// The packets queued from a single client.
+#[derive(Default)]
+struct SourceQueue {
+    packets: VecDeque<DownstreamPacket>,
+    /// The number of bytes the client can still have handled this round.
+    deficit: usize,
+}
+
+/// Queues packets per client, and hands them out using deficit round
+/// robin, so every client gets an equal share of bytes handled regardless
+/// of how many packets it sends.
+///
+/// Clients are only tracked while they have packets queued, so memory is
+/// bounded by the queue's capacity.
+pub(crate) struct FairQueue {
+    config: FairnessConfig,
+    sources: HashMap<SocketAddr, SourceQueue>,
+    /// The clients with queued packets, in the order they're served.
+    active: VecDeque<SocketAddr>,
+    /// Whether the client at the front of `active` has been given its
+    /// quantum for this round.
+    turn_started: bool,
+    len: usize,
+}
+
+impl FairQueue {
+    pub(crate) fn new(config: FairnessConfig) -> Self {
+        Self {
+            config,
+            sources: HashMap::new(),
+            active: VecDeque::new(),
+            turn_started: false,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of packets queued from all clients.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Queues `packet` behind the others from its client, dropping it if
+    /// either the client's or the whole queue is full.
+    pub(crate) fn push(&mut self, packet: DownstreamPacket) {
+        if self.len >= self.config.capacity {
+            Self::drop_packet(&packet, "queue full");
+            return;
+        }
+
+        let source = packet.source;
+        let queue = self.sources.entry(source).or_default();
+        if queue.packets.len() >= self.config.max_per_source {
+            Self::drop_packet(&packet, "source queue full");
+            return;
+        }
+
+        if queue.packets.is_empty() {
+            self.active.push_back(source);
+        }
+        queue.packets.push_back(packet);
+        self.len += 1;
+    }
+
+    /// Returns the next packet to handle, if any are queued.
+    pub(crate) fn pop(&mut self) -> Option<DownstreamPacket> {
+        loop {
+            let source = *self.active.front()?;
+            let queue = self
+                .sources
+                .get_mut(&source)
+                .expect("active clients always have a queue");
+
+            if !self.turn_started {
+                queue.deficit += self.config.quantum;
+                self.turn_started = true;
+            }
+
+            let len = queue
+                .packets
+                .front()
+                .map_or(0, |packet| packet.contents.len());
+            if len > queue.deficit {
+                // The client's turn is over, its deficit carries over to
+                // the next round.
+                self.active.rotate_left(1);
+                self.turn_started = false;
+                continue;
+            }
+
+            queue.deficit -= len;
+            let packet = queue.packets.pop_front();
+            self.len -= 1;
+
+            if queue.packets.is_empty() {
+                self.sources.remove(&source);
+                self.active.pop_front();
+                self.turn_started = false;
+            }
+
+            return packet;
+        }
+    }
+
+    fn drop_packet(packet: &DownstreamPacket, reason: &'static str) {
+        let asn_info = crate::net::maxmind_db::MaxmindDb::lookup(packet.source.ip());
+        let asn_info = asn_info.as_ref().map(MetricsIpNetEntry::from);
+        let asn_info = asn_info.as_ref().into();
+        metrics::fair_queue_dropped_total(reason, &asn_info).inc();
+        metrics::packets_dropped_total(metrics::READ, reason, &asn_info).inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(port: u16, len: usize) -> DownstreamPacket {
+        let pool = std::sync::Arc::new(crate::pool::BufferPool::new(1, 64));
+        DownstreamPacket {
+            contents: pool.alloc_slice(&vec![0; len]),
+            received_at: crate::time::UtcTimestamp::now(),
+            source: (std::net::Ipv4Addr::LOCALHOST, port).into(),
+            destination_port: 7777,
+        }
+    }
+
+    fn pop_sources(queue: &mut FairQueue) -> Vec<u16> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|packet| packet.source.port())
+            .collect()
+    }
+
+    #[test]
+    fn alternates_between_sources() {
+        let mut queue = FairQueue::new(FairnessConfig {
+            quantum: 100,
+            ..<_>::default()
+        });
+
+        for _ in 0..4 {
+            queue.push(packet(1, 100));
+        }
+        queue.push(packet(2, 100));
+        queue.push(packet(3, 100));
+
+        assert_eq!(pop_sources(&mut queue), [1, 2, 3, 1, 1, 1]);
+        assert!(queue.is_empty());
+        assert!(queue.sources.is_empty());
+    }
+
+    #[test]
+    fn shares_bytes_rather_than_packets() {
+        let mut queue = FairQueue::new(FairnessConfig {
+            quantum: 100,
+            ..<_>::default()
+        });
+
+        for _ in 0..2 {
+            queue.push(packet(1, 200));
+        }
+        for _ in 0..4 {
+            queue.push(packet(2, 50));
+        }
+
+        // The larger packets have to wait a round for enough deficit.
+        assert_eq!(pop_sources(&mut queue), [2, 2, 1, 2, 2, 1]);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut queue = FairQueue::new(FairnessConfig {
+            enabled: true,
+            quantum: 100,
+            max_per_source: 2,
+            capacity: 3,
+        });
+
+        for _ in 0..4 {
+            queue.push(packet(1, 10));
+        }
+        queue.push(packet(2, 10));
+        queue.push(packet(3, 10));
+
+        assert_eq!(queue.len, 3);
+        assert_eq!(pop_sources(&mut queue), [1, 1, 2]);
+    }
+}
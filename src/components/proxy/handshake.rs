@@ -0,0 +1,280 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    collections::ttl::{Entry, TtlMap},
+    net::{endpoint::metadata::DynamicMetadata, established},
+};
+
+use super::{shared_sessions::SharedSessions, PipelineError, SharedSessionsConfig};
+
+/// The number of packets a source can send before being established by
+/// default.
+pub const DEFAULT_PACKET_BUDGET: u32 = 32;
+
+/// How long a source's handshake state is kept after its last packet.
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often expired sources are removed.
+const SOURCE_EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Whether packets from a source are only forwarded once a filter has marked
+/// it as [established](crate::net::established).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandshakeConfig {
+    /// Whether sources have to be established before their packets are
+    /// forwarded.
+    pub required: bool,
+    /// The number of packets a source can send while it isn't established,
+    /// any more are dropped before reaching the filters.
+    pub packet_budget: u32,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            packet_budget: DEFAULT_PACKET_BUDGET,
+        }
+    }
+}
+
+/// The handshake state of a source.
+#[derive(Debug, Default)]
+struct SourceState {
+    established: bool,
+    /// The number of packets received while not established.
+    packets: u32,
+    /// Whether the source was last shared as established, and when.
+    published: Option<(bool, Instant)>,
+}
+
+/// Holds back packets from sources that haven't been established yet.
+pub(crate) struct HandshakeGate {
+    packet_budget: u32,
+    sources: TtlMap<SocketAddr, SourceState>,
+    /// `None` when established sources aren't shared with other proxies.
+    shared: Option<SharedSessions>,
+}
+
+impl HandshakeGate {
+    /// Returns `None` if the handshake isn't required.
+    pub(crate) fn new(config: HandshakeConfig, shared: SharedSessionsConfig) -> Option<Self> {
+        if !config.required {
+            if shared.store.is_some() {
+                tracing::warn!("sessions are only shared when the handshake is required");
+            }
+            return None;
+        }
+
+        let sources = TtlMap::new(SOURCE_TIMEOUT, SOURCE_EXPIRY_POLL_INTERVAL);
+        let shared = {
+            let sources = sources.clone();
+            SharedSessions::spawn(shared, move |source| {
+                // Established by another proxy, so it's already shared.
+                let published = Some((true, Instant::now()));
+                match sources.entry(source) {
+                    Entry::Occupied(mut entry) => {
+                        let state = &mut entry.get_mut().value;
+                        state.established = true;
+                        state.published = published;
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(SourceState {
+                            established: true,
+                            packets: 0,
+                            published,
+                        });
+                    }
+                }
+            })
+        };
+
+        Some(Self {
+            packet_budget: config.packet_budget,
+            sources,
+            shared,
+        })
+    }
+
+    /// Counts a packet from `source` before it's handled by the filters,
+    /// returning an error if `source` isn't established and has used up its
+    /// packet budget.
+    #[inline]
+    pub(crate) fn check(&self, source: SocketAddr) -> Result<(), PipelineError> {
+        match self.sources.entry(source) {
+            Entry::Occupied(mut entry) => {
+                let state = &mut entry.get_mut().value;
+                if state.established {
+                    return Ok(());
+                }
+                if state.packets >= self.packet_budget {
+                    return Err(PipelineError::HandshakeBudgetExceeded);
+                }
+                state.packets += 1;
+            }
+            Entry::Vacant(entry) => {
+                if let Some(shared) = &self.shared {
+                    shared.lookup(source);
+                }
+                if self.packet_budget == 0 {
+                    return Err(PipelineError::HandshakeBudgetExceeded);
+                }
+                entry.insert(SourceState {
+                    established: false,
+                    packets: 1,
+                    published: None,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies whether the filters marked `source` as established in
+    /// `metadata`, returning an error if its packet mustn't be forwarded.
+    #[inline]
+    pub(crate) fn admit(
+        &self,
+        source: SocketAddr,
+        metadata: &DynamicMetadata,
+    ) -> Result<(), PipelineError> {
+        let is_established = match (established::get(metadata), self.sources.entry(source)) {
+            (Some(established), Entry::Occupied(mut entry)) => {
+                let state = &mut entry.get_mut().value;
+                state.established = established;
+                self.publish(source, state);
+                established
+            }
+            (Some(established), Entry::Vacant(entry)) => {
+                let mut entry = entry.insert(SourceState {
+                    established,
+                    packets: 0,
+                    published: None,
+                });
+                self.publish(source, &mut entry.value);
+                established
+            }
+            (None, Entry::Occupied(mut entry)) => {
+                let state = &mut entry.get_mut().value;
+                self.publish(source, state);
+                state.established
+            }
+            (None, Entry::Vacant(_)) => false,
+        };
+
+        if is_established {
+            Ok(())
+        } else {
+            Err(PipelineError::NotEstablished)
+        }
+    }
+
+    /// Shares whether `source` is established with other proxies, if it's
+    /// changed since it was last shared, or it's still established and due
+    /// to be refreshed.
+    #[inline]
+    fn publish(&self, source: SocketAddr, state: &mut SourceState) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+
+        let due = match state.published {
+            None => state.established,
+            Some((established, _)) if established != state.established => true,
+            Some((_, at)) => state.established && at.elapsed() >= shared.refresh_interval(),
+        };
+
+        if due && shared.publish(source, state.established) {
+            state.published = Some((state.established, Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn required(packet_budget: u32) -> HandshakeGate {
+        HandshakeGate::new(
+            HandshakeConfig {
+                required: true,
+                packet_budget,
+            },
+            <_>::default(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn not_required() {
+        assert!(HandshakeGate::new(HandshakeConfig::default(), <_>::default()).is_none());
+    }
+
+    #[tokio::test]
+    async fn holds_packets_until_established() {
+        let gate = required(2);
+        let source = (std::net::Ipv4Addr::LOCALHOST, 1000).into();
+        let mut metadata = DynamicMetadata::default();
+
+        assert_eq!(gate.check(source), Ok(()));
+        assert_eq!(
+            gate.admit(source, &metadata),
+            Err(PipelineError::NotEstablished)
+        );
+
+        established::set(&mut metadata, true);
+        assert_eq!(gate.check(source), Ok(()));
+        assert_eq!(gate.admit(source, &metadata), Ok(()));
+
+        // Established sources are no longer counted against the budget.
+        for _ in 0..10 {
+            assert_eq!(gate.check(source), Ok(()));
+            assert_eq!(gate.admit(source, &DynamicMetadata::default()), Ok(()));
+        }
+
+        established::set(&mut metadata, false);
+        assert_eq!(
+            gate.admit(source, &metadata),
+            Err(PipelineError::NotEstablished)
+        );
+    }
+
+    #[tokio::test]
+    async fn packet_budget() {
+        let gate = required(2);
+        let source = (std::net::Ipv4Addr::LOCALHOST, 1000).into();
+        let other = (std::net::Ipv4Addr::LOCALHOST, 2000).into();
+
+        assert_eq!(gate.check(source), Ok(()));
+        assert_eq!(gate.check(source), Ok(()));
+        assert_eq!(
+            gate.check(source),
+            Err(PipelineError::HandshakeBudgetExceeded)
+        );
+        assert_eq!(gate.check(other), Ok(()));
+
+        assert_eq!(
+            required(0).check(other),
+            Err(PipelineError::HandshakeBudgetExceeded)
+        );
+    }
+}
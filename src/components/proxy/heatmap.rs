@@ -0,0 +1,416 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+use crate::time::UtcTimestamp;
+
+/// The most source prefixes tracked by default.
+pub const DEFAULT_MAX_PREFIXES: usize = 1024;
+
+/// The number of slices the window is split into, the oldest of which is
+/// dropped as the window slides.
+const SLICES: u64 = 4;
+
+/// The number of buckets of each histogram, two for every power of two
+/// nanoseconds up to about eighteen minutes.
+const BUCKETS: usize = 82;
+
+/// Whether the latency of packets is tracked for each source prefix, to be
+/// reported through the admin server's `/heatmap` endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencyHeatmapConfig {
+    /// How far back latencies are reported, disabled when unset.
+    pub window: Option<Duration>,
+    /// The most source prefixes tracked at once, packets from any others
+    /// aren't tracked until one has been idle for the whole window.
+    pub max_prefixes: usize,
+}
+
+impl Default for LatencyHeatmapConfig {
+    fn default() -> Self {
+        Self {
+            window: None,
+            max_prefixes: DEFAULT_MAX_PREFIXES,
+        }
+    }
+}
+
+/// The prefix of a source address that latencies are grouped by, a `/16`
+/// for IPv4 and a `/32` for IPv6, roughly an ISP or a region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Prefix(IpAddr);
+
+impl Prefix {
+    fn of(ip: IpAddr) -> Self {
+        Self(match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let [a, b, ..] = ip.octets();
+                Ipv4Addr::new(a, b, 0, 0).into()
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, ..] = ip.segments();
+                Ipv6Addr::new(a, b, 0, 0, 0, 0, 0, 0).into()
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            IpAddr::V4(ip) => write!(f, "{ip}/16"),
+            IpAddr::V6(ip) => write!(f, "{ip}/32"),
+        }
+    }
+}
+
+/// The number of latencies in each bucket, each bucket covering half of a
+/// power of two nanoseconds, so percentiles are within about 40% of the
+/// latencies recorded.
+#[derive(Clone, Debug)]
+struct Histogram {
+    counts: [u32; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    fn bucket(nanos: u64) -> usize {
+        if nanos < 2 {
+            return nanos as usize;
+        }
+
+        let log = 63 - nanos.leading_zeros() as usize;
+        let half = (nanos >> (log - 1)) as usize & 1;
+        (log * 2 + half).min(BUCKETS - 1)
+    }
+
+    /// The largest latency that falls in `bucket`.
+    fn upper_bound(bucket: usize) -> u64 {
+        if bucket < 2 {
+            return bucket as u64;
+        }
+
+        let (log, half) = (bucket / 2, bucket as u64 & 1);
+        ((2 + half + 1) << (log - 1)) - 1
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let bucket = Self::bucket(latency.as_nanos().min(u64::MAX as u128) as u64);
+        self.counts[bucket] = self.counts[bucket].saturating_add(1);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count = count.saturating_add(other);
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.counts.iter().map(|count| *count as u64).sum()
+    }
+
+    /// The latency below which `quantile` of the latencies recorded fall,
+    /// in nanoseconds, if any were.
+    fn percentile(&self, quantile: f64) -> Option<u64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((total as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.counts.iter().enumerate().find_map(|(bucket, count)| {
+            seen += *count as u64;
+            (seen >= rank).then(|| Self::upper_bound(bucket))
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        if self.total() == 0 {
+            return serde_json::Value::Null;
+        }
+
+        serde_json::json!({
+            "samples": self.total(),
+            "p50_ns": self.percentile(0.5),
+            "p90_ns": self.percentile(0.9),
+            "p99_ns": self.percentile(0.99),
+        })
+    }
+}
+
+/// The latencies recorded for a prefix during one slice of the window.
+#[derive(Clone, Debug, Default)]
+struct Slice {
+    /// The slice's number since the epoch.
+    index: u64,
+    processing: Histogram,
+    rtt: Histogram,
+}
+
+/// The latencies recorded for a prefix, in a ring of slices.
+#[derive(Debug)]
+struct Latencies {
+    slices: [Slice; SLICES as usize],
+}
+
+impl Latencies {
+    fn slice(&mut self, index: u64) -> &mut Slice {
+        let slice = &mut self.slices[(index % SLICES) as usize];
+        if slice.index != index {
+            *slice = Slice {
+                index,
+                ..<_>::default()
+            };
+        }
+        slice
+    }
+
+    /// Whether any latency was recorded within the window ending with the
+    /// slice `current`.
+    fn is_active(&self, current: u64) -> bool {
+        self.slices
+            .iter()
+            .any(|slice| slice.index + SLICES > current)
+    }
+
+    /// Merges the slices within the window ending with the slice `current`.
+    fn merged(&self, current: u64) -> (Histogram, Histogram) {
+        let mut processing = Histogram::default();
+        let mut rtt = Histogram::default();
+        for slice in &self.slices {
+            if slice.index + SLICES > current {
+                processing.merge(&slice.processing);
+                rtt.merge(&slice.rtt);
+            }
+        }
+        (processing, rtt)
+    }
+}
+
+/// The latency of packets over a sliding window, grouped by the prefix of
+/// their source address so ISPs or regions having trouble stand out.
+///
+/// Prefixes are spread across shards by hash, so recording rarely contends.
+#[derive(Debug)]
+pub struct LatencyHeatmap {
+    config: LatencyHeatmapConfig,
+    /// The length of each slice of the window, in nanoseconds.
+    slice_len: u64,
+    shards: Box<[Mutex<HashMap<Prefix, Latencies>>]>,
+}
+
+impl LatencyHeatmap {
+    pub(crate) fn new(config: LatencyHeatmapConfig, shards: usize) -> Self {
+        let slice_len = config
+            .window
+            .map_or(0, |window| (window.as_nanos() as u64 / SLICES).max(1));
+
+        Self {
+            config,
+            slice_len,
+            shards: (0..shards.max(1)).map(|_| <_>::default()).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.config.window.is_some()
+    }
+
+    /// Records how long a packet from `source` received at `received_at`
+    /// took to process.
+    #[inline]
+    pub(crate) fn record_processing(
+        &self,
+        source: IpAddr,
+        received_at: UtcTimestamp,
+        latency: Duration,
+    ) {
+        if self.is_enabled() {
+            self.record(source, received_at, |slice| {
+                slice.processing.record(latency)
+            });
+        }
+    }
+
+    /// Records how long the upstream took to respond to a packet from
+    /// `source`, at `received_at`.
+    #[inline]
+    pub(crate) fn record_rtt(&self, source: IpAddr, received_at: UtcTimestamp, rtt: Duration) {
+        if self.is_enabled() {
+            self.record(source, received_at, |slice| slice.rtt.record(rtt));
+        }
+    }
+
+    fn slice_index(&self, at: UtcTimestamp) -> u64 {
+        at.unix_nanos().max(0) as u64 / self.slice_len
+    }
+
+    fn shard(&self, prefix: Prefix) -> &Mutex<HashMap<Prefix, Latencies>> {
+        let mut hasher = DefaultHasher::new();
+        prefix.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    fn record(&self, source: IpAddr, at: UtcTimestamp, record: impl FnOnce(&mut Slice)) {
+        let prefix = Prefix::of(source);
+        let current = self.slice_index(at);
+        let max_per_shard = self.config.max_prefixes.div_ceil(self.shards.len()).max(1);
+
+        let mut shard = self.shard(prefix).lock();
+        if !shard.contains_key(&prefix) && shard.len() >= max_per_shard {
+            shard.retain(|_, latencies| latencies.is_active(current));
+            if shard.len() >= max_per_shard {
+                return;
+            }
+        }
+
+        let latencies = shard.entry(prefix).or_insert_with(|| Latencies {
+            slices: <_>::default(),
+        });
+        record(latencies.slice(current));
+    }
+
+    /// Returns the latency percentiles of each prefix over the window, the
+    /// prefixes with the slowest upstreams first.
+    pub fn to_json(&self) -> serde_json::Value {
+        let Some(window) = self.config.window else {
+            return serde_json::Value::Null;
+        };
+
+        let current = self.slice_index(UtcTimestamp::now());
+        let mut prefixes = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock();
+            prefixes.extend(shard.iter().filter_map(|(prefix, latencies)| {
+                let (processing, rtt) = latencies.merged(current);
+                (processing.total() > 0 || rtt.total() > 0).then_some((*prefix, processing, rtt))
+            }));
+        }
+        prefixes.sort_by_key(|(_, processing, rtt)| {
+            std::cmp::Reverse((rtt.percentile(0.99), processing.percentile(0.99)))
+        });
+
+        let prefixes: Vec<_> = prefixes
+            .into_iter()
+            .map(|(prefix, processing, rtt)| {
+                serde_json::json!({
+                    "prefix": prefix.to_string(),
+                    "processing": processing.to_json(),
+                    "rtt": rtt.to_json(),
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "window_secs": window.as_secs_f64(),
+            "prefixes": prefixes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> UtcTimestamp {
+        UtcTimestamp::from_nanos(secs * 1_000_000_000)
+    }
+
+    #[test]
+    fn buckets_cover_their_latencies() {
+        for nanos in [0, 1, 2, 3, 4, 5, 6, 7, 8, 1000, 123_456, 1 << 39] {
+            let bucket = Histogram::bucket(nanos);
+            assert!(Histogram::upper_bound(bucket) >= nanos, "{nanos}");
+            if bucket > 0 {
+                assert!(Histogram::upper_bound(bucket - 1) < nanos, "{nanos}");
+            }
+        }
+    }
+
+    #[test]
+    fn groups_by_prefix() {
+        let heatmap = LatencyHeatmap::new(
+            LatencyHeatmapConfig {
+                window: Some(Duration::from_secs(60)),
+                ..<_>::default()
+            },
+            2,
+        );
+
+        let now = UtcTimestamp::now();
+        for host in 0..10u8 {
+            let source = Ipv4Addr::new(203, 0, 113, host).into();
+            heatmap.record_processing(source, now, Duration::from_micros(10));
+            heatmap.record_rtt(source, now, Duration::from_millis(80));
+        }
+        heatmap.record_processing(
+            Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 3, 4, 5, 6).into(),
+            now,
+            Duration::from_micros(10),
+        );
+
+        let json = heatmap.to_json();
+        let prefixes = json["prefixes"].as_array().unwrap();
+        assert_eq!(prefixes.len(), 2);
+        assert_eq!(prefixes[0]["prefix"], "203.0.0.0/16");
+        assert_eq!(prefixes[0]["processing"]["samples"], 10);
+        let p99 = prefixes[0]["rtt"]["p99_ns"].as_u64().unwrap();
+        assert!((80_000_000..=120_000_000).contains(&p99), "{p99}");
+        assert_eq!(prefixes[1]["prefix"], "2001:db8::/32");
+        assert!(prefixes[1]["rtt"].is_null());
+    }
+
+    #[test]
+    fn slides_and_bounds_prefixes() {
+        let heatmap = LatencyHeatmap::new(
+            LatencyHeatmapConfig {
+                window: Some(Duration::from_secs(60)),
+                max_prefixes: 1,
+            },
+            1,
+        );
+
+        let first = Ipv4Addr::new(198, 51, 100, 1).into();
+        let second = Ipv4Addr::new(192, 0, 2, 1).into();
+        heatmap.record_processing(first, at(600), Duration::from_micros(10));
+        heatmap.record_processing(second, at(601), Duration::from_micros(10));
+        assert_eq!(heatmap.shards[0].lock().len(), 1);
+
+        // Once the first prefix has been idle for the whole window, it makes
+        // way for the second.
+        heatmap.record_processing(second, at(661), Duration::from_micros(10));
+        let shard = heatmap.shards[0].lock();
+        assert_eq!(shard.len(), 1);
+        assert!(shard.contains_key(&Prefix::of(second)));
+    }
+}
@@ -0,0 +1,284 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    net::SocketAddr,
+};
+
+use parking_lot::Mutex;
+
+use crate::time::UtcTimestamp;
+
+/// The number of packets kept per worker by default.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The most packets kept per worker, regardless of the configured capacity.
+pub const MAX_CAPACITY: usize = 1 << 16;
+
+/// How many of the first bytes of each packet are kept.
+const PREFIX_LEN: usize = 16;
+
+/// How many of the most recent packets received from clients are kept in
+/// memory, to be dumped through the admin server's `/history` endpoint after
+/// an incident.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketHistoryConfig {
+    /// The number of packets kept per worker, zero disables the history.
+    pub capacity: usize,
+    /// Whether the first bytes of each packet are kept as a hash, rather
+    /// than as is.
+    pub hash_contents: bool,
+}
+
+impl Default for PacketHistoryConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            hash_contents: false,
+        }
+    }
+}
+
+/// The first bytes of a packet.
+#[derive(Clone, Copy, Debug)]
+enum Prefix {
+    Bytes { buf: [u8; PREFIX_LEN], len: u8 },
+    Hash(u64),
+}
+
+/// What happened to a packet received from a client.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PacketSummary {
+    received_at: i64,
+    source: SocketAddr,
+    length: usize,
+    prefix: Prefix,
+    /// The first endpoint the packet was sent to.
+    destination: Option<SocketAddr>,
+    /// Why the packet was dropped, if it was.
+    drop_reason: Option<&'static str>,
+}
+
+/// A fixed size ring of the most recent packets handled by a worker.
+#[derive(Debug)]
+struct Ring {
+    entries: Vec<PacketSummary>,
+    next: usize,
+}
+
+/// The most recent packets received from clients, kept in a fixed size ring
+/// for each worker so recording never allocates, and workers never contend.
+#[derive(Debug)]
+pub struct PacketHistory {
+    config: PacketHistoryConfig,
+    rings: Box<[Mutex<Ring>]>,
+}
+
+impl PacketHistory {
+    pub(crate) fn new(config: PacketHistoryConfig, workers: usize) -> Self {
+        let config = PacketHistoryConfig {
+            capacity: config.capacity.min(MAX_CAPACITY),
+            ..config
+        };
+
+        let rings = (0..workers.max(1))
+            .map(|_| {
+                Mutex::new(Ring {
+                    entries: Vec::with_capacity(config.capacity),
+                    next: 0,
+                })
+            })
+            .collect();
+
+        Self { config, rings }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.config.capacity > 0
+    }
+
+    /// Summarizes a packet before it's handled, returning `None` when the
+    /// history is disabled.
+    #[inline]
+    pub(crate) fn summarize(
+        &self,
+        received_at: UtcTimestamp,
+        source: SocketAddr,
+        contents: &[u8],
+    ) -> Option<PacketSummary> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let head = &contents[..contents.len().min(PREFIX_LEN)];
+        let prefix = if self.config.hash_contents {
+            let mut hasher = DefaultHasher::new();
+            head.hash(&mut hasher);
+            Prefix::Hash(hasher.finish())
+        } else {
+            let mut buf = [0; PREFIX_LEN];
+            buf[..head.len()].copy_from_slice(head);
+            Prefix::Bytes {
+                buf,
+                len: head.len() as u8,
+            }
+        };
+
+        Some(PacketSummary {
+            received_at: received_at.unix_nanos(),
+            source,
+            length: contents.len(),
+            prefix,
+            destination: None,
+            drop_reason: None,
+        })
+    }
+
+    /// Records what happened to a packet summarized with [`Self::summarize`]
+    /// in the ring of `worker_id`, replacing the oldest packet once full.
+    #[inline]
+    pub(crate) fn record(
+        &self,
+        worker_id: usize,
+        mut summary: PacketSummary,
+        destination: Option<SocketAddr>,
+        drop_reason: Option<&'static str>,
+    ) {
+        summary.destination = destination;
+        summary.drop_reason = drop_reason;
+
+        let mut ring = self.rings[worker_id % self.rings.len()].lock();
+        if ring.entries.len() < self.config.capacity {
+            ring.entries.push(summary);
+        } else {
+            let next = ring.next;
+            ring.entries[next] = summary;
+        }
+        ring.next = (ring.next + 1) % self.config.capacity;
+    }
+
+    /// Returns the recorded packets of every worker, oldest first.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut packets = Vec::new();
+        for (worker, ring) in self.rings.iter().enumerate() {
+            let ring = ring.lock();
+            packets.extend(ring.entries.iter().map(|summary| (worker, *summary)));
+        }
+        packets.sort_by_key(|(_, summary)| summary.received_at);
+
+        let packets: Vec<_> = packets
+            .into_iter()
+            .map(|(worker, summary)| {
+                let mut json = serde_json::json!({
+                    "worker": worker,
+                    "received_at": summary.received_at,
+                    "source": summary.source,
+                    "length": summary.length,
+                    "destination": summary.destination,
+                    "drop_reason": summary.drop_reason,
+                });
+
+                match summary.prefix {
+                    Prefix::Bytes { buf, len } => {
+                        use base64::Engine as _;
+                        json["prefix"] = base64::engine::general_purpose::STANDARD
+                            .encode(&buf[..len as usize])
+                            .into();
+                    }
+                    Prefix::Hash(hash) => json["prefix_hash"] = format!("{hash:016x}").into(),
+                }
+
+                json
+            })
+            .collect();
+
+        serde_json::json!({
+            "capacity_per_worker": self.config.capacity,
+            "packets": packets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(history: &PacketHistory, worker_id: usize, nanos: i64, contents: &[u8]) {
+        let summary = history
+            .summarize(
+                UtcTimestamp::from_nanos(nanos),
+                (std::net::Ipv4Addr::LOCALHOST, 1000).into(),
+                contents,
+            )
+            .unwrap();
+        history.record(worker_id, summary, None, Some("dropped"));
+    }
+
+    #[test]
+    fn keeps_most_recent_packets() {
+        let history = PacketHistory::new(
+            PacketHistoryConfig {
+                capacity: 2,
+                hash_contents: false,
+            },
+            2,
+        );
+
+        record(&history, 0, 1, b"a");
+        record(&history, 1, 2, b"b");
+        record(&history, 0, 3, b"c");
+        record(&history, 0, 4, b"this is longer than the prefix");
+
+        let json = history.to_json();
+        let packets = json["packets"].as_array().unwrap();
+        let received_at: Vec<_> = packets
+            .iter()
+            .map(|packet| packet["received_at"].as_i64().unwrap())
+            .collect();
+        assert_eq!(received_at, [2, 3, 4]);
+        assert_eq!(packets[2]["length"], 30);
+        assert_eq!(packets[2]["prefix"], "dGhpcyBpcyBsb25nZXIgdA==");
+        assert_eq!(packets[2]["drop_reason"], "dropped");
+    }
+
+    #[test]
+    fn disabled_and_hashed() {
+        let history = PacketHistory::new(
+            PacketHistoryConfig {
+                capacity: 0,
+                hash_contents: false,
+            },
+            1,
+        );
+        assert!(history
+            .summarize(UtcTimestamp::now(), ([127, 0, 0, 1], 1000).into(), b"a")
+            .is_none());
+
+        let history = PacketHistory::new(
+            PacketHistoryConfig {
+                capacity: 1,
+                hash_contents: true,
+            },
+            1,
+        );
+        record(&history, 0, 1, b"secret");
+        let json = history.to_json();
+        assert!(json["packets"][0].get("prefix").is_none());
+        assert!(json["packets"][0]["prefix_hash"].is_string());
+    }
+}
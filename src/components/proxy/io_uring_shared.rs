@@ -21,7 +21,11 @@
 //! enough that it doesn't make sense to share the same code
 
 use crate::{
-    components::proxy::{self, PendingSends, PipelineError, SendPacket},
+    components::proxy::{
+        self,
+        fair_queue::{self, FairQueue},
+        PendingSends, PipelineError, SendPacket,
+    },
     metrics,
     pool::PoolBuffer,
     time::UtcTimestamp,
@@ -124,6 +128,8 @@ struct LoopPacket {
     addr: libc::sockaddr_storage,
     packet: Option<LoopPacketInner>,
     io_vec: libc::iovec,
+    /// Ancillary data for sends, large enough for a single `c_int` value
+    control: [u64; 4],
 }
 
 impl LoopPacket {
@@ -139,6 +145,7 @@ impl LoopPacket {
             },
             // SAFETY: sockaddr_storage is POD
             addr: unsafe { std::mem::zeroed() },
+            control: [0; 4],
         }
     }
 
@@ -165,6 +172,28 @@ impl LoopPacket {
                         1,
                     );
                 }
+
+                if let Some(dscp) = send.dscp {
+                    let (level, kind) = crate::net::dscp::cmsg_type(&send.destination);
+                    let len = std::mem::size_of::<libc::c_int>() as u32;
+
+                    self.msghdr.msg_control = self.control.as_mut_ptr().cast();
+                    // SAFETY: CMSG_SPACE is a pure size calculation
+                    self.msghdr.msg_controllen = unsafe { libc::CMSG_SPACE(len) } as _;
+
+                    // SAFETY: the control buffer is aligned and large enough to
+                    // hold a single header with a c_int, so the first header is
+                    // non-null and its data is within the buffer
+                    unsafe {
+                        let cmsg = libc::CMSG_FIRSTHDR(&self.msghdr);
+                        (*cmsg).cmsg_level = level;
+                        (*cmsg).cmsg_type = kind;
+                        (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+                        libc::CMSG_DATA(cmsg)
+                            .cast::<libc::c_int>()
+                            .write_unaligned(dscp.tos().into());
+                    }
+                }
             }
         }
 
@@ -216,11 +245,14 @@ pub enum PacketProcessorCtx {
         sessions: Arc<crate::components::proxy::SessionPool>,
         error_acc: super::error::ErrorAccumulator,
         worker_id: usize,
+        port: u16,
         destinations: Vec<crate::net::EndpointAddress>,
     },
     SessionPool {
         pool: Arc<crate::components::proxy::SessionPool>,
         port: u16,
+        /// The client a transparent socket is bound to, if any.
+        downstream: Option<std::net::SocketAddr>,
     },
 }
 
@@ -228,12 +260,14 @@ fn process_packet(
     ctx: &mut PacketProcessorCtx,
     packet: RecvPacket,
     last_received_at: &mut Option<UtcTimestamp>,
+    fair_queue: &mut Option<FairQueue>,
 ) {
     match ctx {
         PacketProcessorCtx::Router {
             config,
             sessions,
             worker_id,
+            port,
             error_acc,
             destinations,
         } => {
@@ -246,9 +280,16 @@ fn process_packet(
 
             let ds_packet = proxy::packet_router::DownstreamPacket {
                 contents: packet.buffer,
+                received_at,
                 source: packet.source,
+                destination_port: *port,
             };
 
+            if let Some(fair_queue) = fair_queue {
+                fair_queue.push(ds_packet);
+                return;
+            }
+
             crate::components::proxy::packet_router::DownstreamReceiveWorkerConfig::process_task(
                 ds_packet,
                 *worker_id,
@@ -258,19 +299,73 @@ fn process_packet(
                 destinations,
             );
         }
-        PacketProcessorCtx::SessionPool { pool, port, .. } => {
+        PacketProcessorCtx::SessionPool {
+            pool,
+            port,
+            downstream,
+        } => {
             let mut last_received_at = None;
 
             pool.process_received_upstream_packet(
                 packet.buffer,
                 packet.source,
                 *port,
+                *downstream,
                 &mut last_received_at,
             );
         }
     }
 }
 
+/// Reads the ICMP errors queued on an upstream socket when `error` reports
+/// that there are some, returning whether it did.
+fn report_icmp_errors(ctx: &PacketProcessorCtx, fd: Fd, error: &std::io::Error) -> bool {
+    let PacketProcessorCtx::SessionPool { pool, .. } = ctx else {
+        return false;
+    };
+
+    if pool.stages().ejections.is_none()
+        || !matches!(
+            error.raw_os_error(),
+            Some(libc::ECONNREFUSED | libc::EHOSTUNREACH | libc::ENETUNREACH)
+        )
+    {
+        return false;
+    }
+
+    crate::net::icmp_errors::drain(fd.0, |destination, reason| {
+        pool.report_unreachable(destination, reason.label());
+    });
+    true
+}
+
+/// Handles up to a burst of the packets waiting in the fair queue, leaving
+/// the rest for the next iteration so receiving isn't held up.
+fn process_queued(ctx: &mut PacketProcessorCtx, fair_queue: &mut FairQueue) {
+    let PacketProcessorCtx::Router {
+        config,
+        sessions,
+        worker_id,
+        error_acc,
+        destinations,
+        ..
+    } = ctx
+    else {
+        return;
+    };
+
+    for packet in std::iter::from_fn(|| fair_queue.pop()).take(fair_queue::BURST) {
+        crate::components::proxy::packet_router::DownstreamReceiveWorkerConfig::process_task(
+            packet,
+            *worker_id,
+            config,
+            sessions,
+            error_acc,
+            destinations,
+        );
+    }
+}
+
 #[inline]
 fn empty_net_addr() -> std::net::SocketAddr {
     std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0)
@@ -279,10 +374,12 @@ fn empty_net_addr() -> std::net::SocketAddr {
 enum Token {
     /// Packet received
     Recv { key: usize },
-    /// Packet sent
-    Send { key: usize },
+    /// Packet sent, and whether it is being sent again after failing
+    Send { key: usize, retried: bool },
     /// One or more packets are ready to be sent OR shutdown of the loop is requested
     PendingsSends,
+    /// The delay waiting for more packets to merge before sending has elapsed
+    CoalesceTimeout,
 }
 
 struct LoopCtx<'uring> {
@@ -326,7 +423,7 @@ impl<'uring> LoopCtx<'uring> {
 
     /// Enqueues a send_to on the socket
     #[inline]
-    fn enqueue_send(&mut self, packet: SendPacket) {
+    fn enqueue_send(&mut self, packet: SendPacket, retried: bool) {
         // We rely on sends using state with stable addresses, but realistically we should
         // never be at capacity
         if self.loop_packets.capacity() - self.loop_packets.len() == 0 {
@@ -346,7 +443,7 @@ impl<'uring> LoopCtx<'uring> {
             (key, std::ptr::addr_of!(pp.msghdr))
         };
 
-        let token = self.tokens.insert(Token::Send { key });
+        let token = self.tokens.insert(Token::Send { key, retried });
         self.push(
             io_uring::opcode::SendMsg::new(self.socket_fd, msghdr)
                 .build()
@@ -437,7 +534,29 @@ impl IoUringLoop {
         let socket = self.socket;
         let concurrent_sends = self.concurrent_sends;
 
-        let mut ring = io_uring::IoUring::new((concurrent_sends + 3) as _)?;
+        // Packets from clients are only queued fairly when enabled, in which
+        // case a burst of packets is received at once for the queue to
+        // choose between.
+        let mut fair_queue = match &ctx {
+            PacketProcessorCtx::Router { sessions, .. } => {
+                let fairness = sessions.fairness();
+                fairness.enabled.then(|| FairQueue::new(fairness))
+            }
+            PacketProcessorCtx::SessionPool { .. } => None,
+        };
+        let queue_depth = match (&ctx, &fair_queue) {
+            (PacketProcessorCtx::Router { worker_id, .. }, Some(_)) => {
+                Some(metrics::worker_queue_depth(*worker_id))
+            }
+            _ => None,
+        };
+        let concurrent_recvs = if fair_queue.is_some() {
+            fair_queue::BURST
+        } else {
+            1
+        };
+
+        let mut ring = io_uring::IoUring::new((concurrent_sends + concurrent_recvs + 2) as _)?;
 
         let mut pending_sends_event = pending_sends.1;
         let pending_sends = pending_sends.0;
@@ -448,8 +567,8 @@ impl IoUringLoop {
                 crate::metrics::game_traffic_tasks().inc();
                 let _guard = tracing::dispatcher::set_default(&dispatcher);
 
-                let tokens = slab::Slab::with_capacity(concurrent_sends + 1 + 1);
-                let loop_packets = slab::Slab::with_capacity(concurrent_sends + 1);
+                let tokens = slab::Slab::with_capacity(concurrent_sends + concurrent_recvs + 1 + 1);
+                let loop_packets = slab::Slab::with_capacity(concurrent_sends + concurrent_recvs);
 
                 // Just double buffer the pending writes for simplicity
                 let mut double_pending_sends = Vec::with_capacity(pending_sends.capacity());
@@ -461,6 +580,20 @@ impl IoUringLoop {
                     metrics::READ
                 };
 
+                // Only sends back to clients are retried and reported
+                let write_errors = match &ctx {
+                    PacketProcessorCtx::Router { sessions, .. } => Some(sessions.clone()),
+                    PacketProcessorCtx::SessionPool { .. } => None,
+                };
+
+                // Only sends back to clients wait for packets to merge, the
+                // timespec needs a stable address until the timeout completes
+                let coalesce_timeout = write_errors
+                    .as_deref()
+                    .and_then(|pool| pool.coalesce_delay())
+                    .map(io_uring::types::Timespec::from);
+                let mut coalescing = false;
+
                 let (submitter, sq, mut cq) = ring.split();
 
                 let mut loop_ctx = LoopCtx {
@@ -471,7 +604,9 @@ impl IoUringLoop {
                     tokens,
                 };
 
-                loop_ctx.enqueue_recv(buffer_pool.clone().alloc());
+                for _ in 0..concurrent_recvs {
+                    loop_ctx.enqueue_recv(buffer_pool.clone().alloc());
+                }
                 loop_ctx
                     .push_with_token(pending_sends_event.io_uring_entry(), Token::PendingsSends);
 
@@ -483,7 +618,15 @@ impl IoUringLoop {
 
                 // The core io uring loop
                 'io: loop {
-                    match submitter.submit_and_wait(1) {
+                    // Don't wait for more packets while there are still
+                    // queued packets to handle.
+                    let wait_for = if fair_queue.as_ref().is_some_and(|queue| !queue.is_empty()) {
+                        0
+                    } else {
+                        1
+                    };
+
+                    match submitter.submit_and_wait(wait_for) {
                         Ok(_) => {}
                         Err(ref err) if err.raw_os_error() == Some(libc::EBUSY) => {}
                         Err(ref err) if err.raw_os_error() == Some(libc::EINTR) => {
@@ -515,28 +658,51 @@ impl IoUringLoop {
 
                                 if ret < 0 {
                                     let error = std::io::Error::from_raw_os_error(-ret);
-                                    tracing::error!(%error, "error receiving packet");
+                                    if !report_icmp_errors(&ctx, loop_ctx.socket_fd, &error) {
+                                        crate::log_throttle::throttled!(error!(
+                                            %error,
+                                            "error receiving packet"
+                                        ));
+                                    }
                                     loop_ctx.enqueue_recv(buffer_pool.clone().alloc());
                                     continue;
                                 }
 
                                 let packet = packet.finalize_recv(ret as usize);
-                                process_packet(&mut ctx, packet, &mut last_received_at);
+                                process_packet(
+                                    &mut ctx,
+                                    packet,
+                                    &mut last_received_at,
+                                    &mut fair_queue,
+                                );
 
                                 loop_ctx.enqueue_recv(buffer_pool.clone().alloc());
                             }
                             Token::PendingsSends => {
                                 if pending_sends_event.val < 0xdeadbeef {
-                                    double_pending_sends = pending_sends.swap(double_pending_sends);
                                     loop_ctx.push_with_token(
                                         pending_sends_event.io_uring_entry(),
                                         Token::PendingsSends,
                                     );
 
-                                    for pending in
-                                        double_pending_sends.drain(0..double_pending_sends.len())
-                                    {
-                                        loop_ctx.enqueue_send(pending);
+                                    match &coalesce_timeout {
+                                        Some(timeout) if !coalescing => {
+                                            coalescing = true;
+                                            loop_ctx.push_with_token(
+                                                io_uring::opcode::Timeout::new(timeout).build(),
+                                                Token::CoalesceTimeout,
+                                            );
+                                        }
+                                        Some(_) => {}
+                                        None => {
+                                            double_pending_sends =
+                                                pending_sends.swap(double_pending_sends);
+                                            for pending in double_pending_sends
+                                                .drain(0..double_pending_sends.len())
+                                            {
+                                                loop_ctx.enqueue_send(pending, false);
+                                            }
+                                        }
                                     }
                                 } else {
                                     if matches!(ctx, PacketProcessorCtx::Router { .. }) {
@@ -549,17 +715,44 @@ impl IoUringLoop {
                                     break 'io;
                                 }
                             }
-                            Token::Send { key } => {
+                            Token::CoalesceTimeout => {
+                                coalescing = false;
+                                double_pending_sends = pending_sends.swap(double_pending_sends);
+                                for pending in
+                                    double_pending_sends.drain(0..double_pending_sends.len())
+                                {
+                                    loop_ctx.enqueue_send(pending, false);
+                                }
+                            }
+                            Token::Send { key, retried } => {
                                 let packet = loop_ctx.pop_packet(key).finalize_send();
-                                let asn_info = packet.asn_info.as_ref().into();
 
                                 if ret < 0 {
-                                    let source =
-                                        std::io::Error::from_raw_os_error(-ret).to_string();
+                                    let write_errors =
+                                        write_errors.as_deref().map(|pool| pool.write_errors());
+                                    if !retried
+                                        && write_errors.is_some_and(|errors| errors.config.retry)
+                                    {
+                                        metrics::send_retries_total(send_dir).inc();
+                                        loop_ctx.enqueue_send(packet, true);
+                                        continue;
+                                    }
+
+                                    let asn_info = packet.asn_info.as_ref().into();
+                                    let error = std::io::Error::from_raw_os_error(-ret);
+                                    report_icmp_errors(&ctx, loop_ctx.socket_fd, &error);
+                                    let source = error.to_string();
                                     metrics::errors_total(send_dir, &source, &asn_info).inc();
                                     metrics::packets_dropped_total(send_dir, &source, &asn_info)
                                         .inc();
-                                } else if ret as usize != packet.data.len() {
+                                    if let Some(write_errors) = write_errors {
+                                        write_errors.report(&error);
+                                    }
+                                    continue;
+                                }
+
+                                let asn_info = packet.asn_info.as_ref().into();
+                                if ret as usize != packet.data.len() {
                                     metrics::packets_total(send_dir, &asn_info).inc();
                                     metrics::errors_total(
                                         send_dir,
@@ -575,6 +768,13 @@ impl IoUringLoop {
                         }
                     }
 
+                    if let Some(fair_queue) = &mut fair_queue {
+                        process_queued(&mut ctx, fair_queue);
+                        if let Some(queue_depth) = &queue_depth {
+                            queue_depth.set(fair_queue.len() as i64);
+                        }
+                    }
+
                     loop_ctx.sync();
                 }
 
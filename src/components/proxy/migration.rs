@@ -0,0 +1,252 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{net::SocketAddr, time::Duration};
+
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+
+use super::sessions::SessionKey;
+use crate::{
+    collections::ttl::TtlMap,
+    filters::capture::CAPTURED_BYTES,
+    net::endpoint::metadata::{DynamicMetadata, Key, Value},
+};
+
+/// How long a client's migration is kept after its last packet.
+const TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most migrations followed for a single packet, in case endpoints have
+/// been migrated back and forth.
+const MAX_HOPS: usize = 8;
+
+/// The key of the client's token, as captured for the token router.
+static TOKEN: Lazy<Key> = Lazy::new(|| Key::from_static(CAPTURED_BYTES));
+
+/// A client's sessions to an endpoint, re-pointed to another endpoint.
+struct Migration {
+    to: SocketAddr,
+    /// The token the client has to have sent to be migrated, until it has
+    /// been checked.
+    token: Option<Bytes>,
+    /// The message sent to the client the first time it's migrated.
+    notification: Option<Bytes>,
+}
+
+/// A packet re-pointed to another endpoint.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Redirect {
+    pub(crate) to: SocketAddr,
+    /// The message to send to the client, if it's the first of its packets
+    /// to be migrated.
+    pub(crate) notification: Option<Bytes>,
+}
+
+/// The sessions re-pointed from one endpoint to another, such as when a
+/// game server moves a match to a new host.
+///
+/// Clients are migrated without noticing, as they keep sending to the same
+/// proxy address, and stay migrated while they keep sending packets.
+pub(crate) struct Migrations {
+    clients: TtlMap<SessionKey, Migration>,
+}
+
+impl Default for Migrations {
+    fn default() -> Self {
+        Self {
+            clients: TtlMap::new(TIMEOUT, POLL_INTERVAL),
+        }
+    }
+}
+
+impl Migrations {
+    /// Migrates the clients of `sessions` to `to`, replacing any previous
+    /// migration of the same session.
+    ///
+    /// If `token` is set, only clients whose packets have that token are
+    /// migrated. If `notification` is set, it's sent to each client the
+    /// first time one of its packets is migrated.
+    pub(crate) fn migrate(
+        &self,
+        sessions: impl IntoIterator<Item = SessionKey>,
+        to: SocketAddr,
+        token: Option<Bytes>,
+        notification: Option<Bytes>,
+    ) {
+        for key in sessions {
+            if key.dest == to {
+                continue;
+            }
+
+            self.clients.insert(
+                key,
+                Migration {
+                    to,
+                    token: token.clone(),
+                    notification: notification.clone(),
+                },
+            );
+        }
+    }
+
+    /// Returns where a packet from `source` to `dest` is sent instead, if
+    /// its session has been migrated. `metadata` is the packet's metadata
+    /// after being run through the filters, holding its token.
+    #[inline]
+    pub(crate) fn redirect(
+        &self,
+        source: SocketAddr,
+        dest: SocketAddr,
+        metadata: &DynamicMetadata,
+    ) -> Option<Redirect> {
+        if self.clients.is_empty() {
+            return None;
+        }
+
+        let mut redirect = None;
+        let mut notification = None;
+        let mut current = dest;
+        for _ in 0..MAX_HOPS {
+            let key = SessionKey {
+                source,
+                dest: current,
+            };
+            let Some(mut entry) = self.clients.get_mut(&key) else {
+                break;
+            };
+            let migration = &mut entry.value;
+
+            if let Some(token) = migration.token.take() {
+                let captured = metadata.get(&TOKEN).and_then(Value::as_bytes);
+                if captured != Some(&token) {
+                    // The lock on the entry has to be released first.
+                    drop(entry);
+                    self.clients.remove(key);
+                    break;
+                }
+            }
+
+            current = migration.to;
+            notification = notification.or_else(|| migration.notification.take());
+            redirect = Some(current);
+        }
+
+        redirect.map(|to| Redirect { to, notification })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(source: u16, dest: u16) -> SessionKey {
+        SessionKey {
+            source: (std::net::Ipv4Addr::LOCALHOST, source).into(),
+            dest: (std::net::Ipv4Addr::LOCALHOST, dest).into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn redirects_migrated_sessions() {
+        let migrations = Migrations::default();
+        let metadata = DynamicMetadata::default();
+        let to = key(0, 2).dest;
+
+        migrations.migrate(
+            [key(10, 1), key(11, 1)],
+            to,
+            None,
+            Some(Bytes::from_static(b"moved")),
+        );
+
+        let redirect = migrations.redirect(key(10, 1).source, key(10, 1).dest, &metadata);
+        assert_eq!(
+            redirect,
+            Some(Redirect {
+                to,
+                notification: Some(Bytes::from_static(b"moved")),
+            })
+        );
+
+        // The notification is only sent once.
+        let redirect = migrations.redirect(key(10, 1).source, key(10, 1).dest, &metadata);
+        assert_eq!(
+            redirect,
+            Some(Redirect {
+                to,
+                notification: None,
+            })
+        );
+
+        assert_eq!(
+            migrations.redirect(key(12, 1).source, key(12, 1).dest, &metadata),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn only_redirects_matching_tokens() {
+        let migrations = Migrations::default();
+        let to = key(0, 2).dest;
+        migrations.migrate(
+            [key(10, 1), key(11, 1)],
+            to,
+            Some(Bytes::from_static(b"abc")),
+            None,
+        );
+
+        let mut matching = DynamicMetadata::default();
+        matching.insert(
+            CAPTURED_BYTES.into(),
+            Value::Bytes(Bytes::from_static(b"abc")),
+        );
+        let mut other = DynamicMetadata::default();
+        other.insert(
+            CAPTURED_BYTES.into(),
+            Value::Bytes(Bytes::from_static(b"xyz")),
+        );
+
+        assert!(migrations
+            .redirect(key(10, 1).source, key(10, 1).dest, &matching)
+            .is_some());
+        assert!(migrations
+            .redirect(key(11, 1).source, key(11, 1).dest, &other)
+            .is_none());
+        // Once checked, the client stays migrated or not.
+        assert!(migrations
+            .redirect(key(10, 1).source, key(10, 1).dest, &other)
+            .is_some());
+        assert!(migrations
+            .redirect(key(11, 1).source, key(11, 1).dest, &matching)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn follows_repeated_migrations() {
+        let migrations = Migrations::default();
+        let metadata = DynamicMetadata::default();
+
+        migrations.migrate([key(10, 1)], key(0, 2).dest, None, None);
+        migrations.migrate([key(10, 2)], key(0, 3).dest, None, None);
+        // Migrating back to the original endpoint is bounded.
+        migrations.migrate([key(10, 3)], key(0, 1).dest, None, None);
+
+        assert!(migrations
+            .redirect(key(10, 1).source, key(10, 1).dest, &metadata)
+            .is_some());
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+use std::{fmt, time::Duration};
+
+use super::{PendingSends, PipelineError};
+use crate::{metrics, time::UtcTimestamp};
+
+/// Limits on the work the proxy takes on for each packet, beyond which
+/// packets are shed so latency stays bounded under overload rather than
+/// building up an unbounded backlog.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OverloadConfig {
+    /// The longest a packet may take from being received to being queued
+    /// for sending, including running it through the filter chain.
+    pub packet_budget: Option<Duration>,
+    /// The most packets that may be waiting in a send queue, any more are
+    /// shed instead of being queued.
+    pub max_queue_depth: Option<usize>,
+}
+
+impl OverloadConfig {
+    /// Returns an error if more than the packet budget has elapsed since
+    /// `received_at`.
+    #[inline]
+    pub(crate) fn check_budget(
+        &self,
+        direction: metrics::Direction,
+        received_at: UtcTimestamp,
+    ) -> Result<(), PipelineError> {
+        let Some(budget) = self.packet_budget else {
+            return Ok(());
+        };
+
+        let elapsed = (UtcTimestamp::now() - received_at).nanos();
+        if elapsed > budget.as_nanos() as i64 {
+            Err(Self::shed(direction, OverloadReason::Deadline))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error if `queue` is already at the maximum depth.
+    #[inline]
+    pub(crate) fn check_queue(
+        &self,
+        direction: metrics::Direction,
+        queue: &PendingSends,
+    ) -> Result<(), PipelineError> {
+        match self.max_queue_depth {
+            Some(max) if queue.len() >= max => {
+                Err(Self::shed(direction, OverloadReason::QueueDepth))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn shed(direction: metrics::Direction, reason: OverloadReason) -> PipelineError {
+        metrics::packets_shed_total(direction, reason.label()).inc();
+        PipelineError::Overloaded(reason)
+    }
+}
+
+/// Why a packet was shed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OverloadReason {
+    /// The packet exceeded the per-packet time budget.
+    Deadline,
+    /// The queue the packet would have been sent from was full.
+    QueueDepth,
+}
+
+impl OverloadReason {
+    #[inline]
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Deadline => "deadline",
+            Self::QueueDepth => "queue depth",
+        }
+    }
+}
+
+impl fmt::Display for OverloadReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Deadline => f.write_str("packet exceeded its processing budget"),
+            Self::QueueDepth => f.write_str("send queue is full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let config = OverloadConfig::default();
+        let received_at = UtcTimestamp::from_nanos(0);
+        assert!(config.check_budget(metrics::READ, received_at).is_ok());
+
+        let config = OverloadConfig {
+            packet_budget: Some(Duration::from_millis(10)),
+            ..<_>::default()
+        };
+        assert!(config
+            .check_budget(metrics::READ, UtcTimestamp::now())
+            .is_ok());
+        assert_eq!(
+            config.check_budget(metrics::READ, received_at),
+            Err(PipelineError::Overloaded(OverloadReason::Deadline))
+        );
+    }
+
+    #[test]
+    fn queue_depth() {
+        let (queue, _rx) = PendingSends::new(1).unwrap();
+        let config = OverloadConfig {
+            max_queue_depth: Some(1),
+            ..<_>::default()
+        };
+
+        assert!(config.check_queue(metrics::WRITE, &queue).is_ok());
+        queue.push(super::super::SendPacket {
+            destination: std::net::SocketAddr::from(([127, 0, 0, 1], 80)).into(),
+            data: crate::test::alloc_buffer(b"hello").freeze(),
+            asn_info: None,
+            dscp: None,
+        });
+        assert_eq!(
+            config.check_queue(metrics::WRITE, &queue),
+            Err(PipelineError::Overloaded(OverloadReason::QueueDepth))
+        );
+    }
+}
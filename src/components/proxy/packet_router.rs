@@ -32,8 +32,10 @@ mod reference;
 /// Packet received from local port
 pub(crate) struct DownstreamPacket {
     pub(crate) contents: PoolBuffer,
-    //received_at: UtcTimestamp,
+    pub(crate) received_at: crate::time::UtcTimestamp,
     pub(crate) source: SocketAddr,
+    /// The port the packet was received on.
+    pub(crate) destination_port: u16,
 }
 
 /// Represents the required arguments to run a worker task that
@@ -66,35 +68,64 @@ impl DownstreamReceiveWorkerConfig {
         );
 
         let timer = metrics::processing_time(metrics::READ).start_timer();
+        let history = sessions.history();
+        let summary = history.summarize(packet.received_at, packet.source, &packet.contents);
+        let source = packet.source;
+        let received_at = packet.received_at;
+
         match Self::process_downstream_received_packet(packet, config, sessions, destinations) {
-            Ok(()) => {
+            Ok(destination) => {
+                if let Some(summary) = summary {
+                    history.record(worker_id, summary, destination, None);
+                }
                 error_acc.maybe_send();
             }
             Err(error) => {
+                let error = sessions.explain_removed(source, error);
                 let discriminant = error.discriminant();
                 metrics::errors_total(metrics::READ, discriminant, &metrics::EMPTY).inc();
                 metrics::packets_dropped_total(metrics::READ, discriminant, &metrics::EMPTY).inc();
+                if let Some(summary) = summary {
+                    history.record(worker_id, summary, None, Some(discriminant));
+                }
+                sessions
+                    .events()
+                    .emit(|| super::Event::packet_dropped(source, discriminant));
+                sessions.record_drop(metrics::READ, &error, source);
 
                 error_acc.push_error(error);
             }
         }
 
-        timer.stop_and_record();
+        let elapsed = timer.stop_and_record();
+        metrics::worker_processing_time(worker_id).observe(elapsed);
+        sessions.heatmap().record_processing(
+            source.ip(),
+            received_at,
+            std::time::Duration::from_secs_f64(elapsed),
+        );
     }
 
-    /// Processes a packet by running it through the filter chain.
+    /// Processes a packet by running it through the filter chain, returning
+    /// the first endpoint it was sent to, if any.
     #[inline]
     fn process_downstream_received_packet(
         packet: DownstreamPacket,
         config: &Arc<Config>,
         sessions: &Arc<SessionPool>,
         destinations: &mut Vec<crate::net::EndpointAddress>,
-    ) -> Result<(), PipelineError> {
+    ) -> Result<Option<SocketAddr>, PipelineError> {
         if !config.clusters.read().has_endpoints() {
             tracing::trace!("no upstream endpoints");
             return Err(PipelineError::NoUpstreamEndpoints);
         }
 
+        sessions.record_listener_port(packet.source, packet.destination_port);
+        let stages = sessions.stages();
+        if let Some(handshake) = &stages.handshake {
+            handshake.check(packet.source)?;
+        }
+
         let filters = config.filters.load();
         let mut context = ReadContext::new(
             config.clusters.clone_value(),
@@ -102,25 +133,162 @@ impl DownstreamReceiveWorkerConfig {
             packet.contents,
             destinations,
         );
+        context.destination_port = Some(packet.destination_port);
+        if let Some(response_timeouts) = &stages.response_timeouts {
+            response_timeouts.mark(packet.source, &mut context.metadata);
+        }
         filters.read(&mut context).map_err(PipelineError::Filter)?;
 
-        let ReadContext { contents, .. } = context;
+        if let Some(reply) = context.reply.take() {
+            sessions.reply(packet.source, &reply);
+            return Ok(None);
+        }
+
+        if !crate::net::selected_endpoint::apply(
+            &context.metadata,
+            &context.endpoints,
+            context.destinations,
+        ) {
+            return Err(PipelineError::SelectedEndpointUnavailable);
+        }
+
+        if let Some(handshake) = &stages.handshake {
+            handshake.admit(packet.source, &context.metadata)?;
+        }
+
+        sessions.check_budget(packet.received_at)?;
+
+        let ReadContext {
+            contents, metadata, ..
+        } = context;
+        let dscp = crate::net::dscp::Dscp::select(&metadata, None);
+        let timeout = crate::net::session_timeout::get(&metadata);
+        let quota = crate::net::session_quota::get(&metadata);
 
         // Similar to bytes::BytesMut::freeze, we turn the mutable pool buffer
         // into an immutable one with its own internal arc so it can be cloned
         // cheaply and returned to the pool once all references are dropped
         let contents = contents.freeze();
 
+        let now = std::time::Instant::now();
+        let maintenance = stages.maintenance();
+        let mut first = None;
+        let mut admission = Admission {
+            sessions,
+            maintenance: maintenance.as_deref(),
+            source: packet.source,
+            metadata: &metadata,
+            now,
+            drained: false,
+            removed: None,
+            ejected: false,
+            rejected: false,
+        };
         for epa in destinations.drain(0..) {
-            let session_key = SessionKey {
-                source: packet.source,
-                dest: epa.to_socket_addr()?,
+            let dest = epa.to_socket_addr()?;
+            let Some(session_key) = admission.admit(dest) else {
+                continue;
             };
+            first.get_or_insert(session_key.dest);
+
+            sessions.send(session_key, contents.clone(), dscp, timeout, quota)?;
+        }
+
+        if let (None, true, Some(maintenance)) = (first, admission.drained, &*maintenance) {
+            for &dest in maintenance.fallback() {
+                let Some(session_key) = admission.admit(dest) else {
+                    continue;
+                };
+                first.get_or_insert(session_key.dest);
+
+                sessions.send(session_key, contents.clone(), dscp, timeout, quota)?;
+            }
+
+            if first.is_none() && !admission.ejected {
+                return Err(PipelineError::Maintenance);
+            }
+        }
+
+        if first.is_none() && admission.ejected {
+            return Err(PipelineError::UpstreamUnreachable);
+        }
+
+        if first.is_none() && admission.rejected {
+            sessions.reject(packet.source);
+            return Err(PipelineError::AdmissionRejected);
+        }
+
+        if let (None, Some(endpoint)) = (first, admission.removed) {
+            return Err(sessions.endpoint_removed(packet.source, endpoint));
+        }
+
+        Ok(first)
+    }
+}
+
+/// Checks the destinations of a packet from `source`, both those the filters
+/// chose and the maintenance fallback, against the pool's stages, recording
+/// why any were skipped to report when the packet isn't sent to any.
+struct Admission<'a> {
+    sessions: &'a SessionPool,
+    /// `None` outside of maintenance windows.
+    maintenance: Option<&'a crate::net::maintenance::ActiveMaintenance>,
+    source: SocketAddr,
+    metadata: &'a crate::net::endpoint::metadata::DynamicMetadata,
+    now: std::time::Instant,
+    drained: bool,
+    removed: Option<SocketAddr>,
+    ejected: bool,
+    rejected: bool,
+}
 
-            sessions.send(session_key, contents.clone())?;
+impl Admission<'_> {
+    /// Returns the session the packet is sent to `dest` through, following
+    /// its client's migrations, or `None` if the destination is drained,
+    /// removed, ejected, or rejects the client.
+    fn admit(&mut self, dest: SocketAddr) -> Option<SessionKey> {
+        let sessions = self.sessions;
+        let stages = sessions.stages();
+        let mut session_key = SessionKey {
+            source: self.source,
+            dest,
+        };
+        if let Some(redirect) = stages
+            .migrations
+            .get()
+            .and_then(|migrations| migrations.redirect(self.source, dest, self.metadata))
+        {
+            session_key.dest = redirect.to;
+            if let Some(notification) = redirect.notification {
+                sessions.reply(self.source, &notification);
+            }
         }
 
-        Ok(())
+        if self
+            .maintenance
+            .is_some_and(|maintenance| maintenance.is_drained(session_key.dest))
+        {
+            self.drained = true;
+            return None;
+        }
+        if sessions.is_removed(session_key.dest, self.now) {
+            self.removed = Some(session_key.dest);
+            return None;
+        }
+        if stages
+            .ejections
+            .as_ref()
+            .is_some_and(|ejections| ejections.is_ejected(session_key.dest, self.now))
+        {
+            self.ejected = true;
+            return None;
+        }
+        if sessions.is_rejected(session_key) {
+            self.rejected = true;
+            return None;
+        }
+
+        Some(session_key)
     }
 }
 
@@ -133,6 +301,7 @@ pub async fn spawn_receivers(
     config: Arc<Config>,
     socket: socket2::Socket,
     worker_sends: Vec<(super::PendingSends, super::PacketSendReceiver)>,
+    listener_sends: Vec<(u16, Vec<(super::PendingSends, super::PacketSendReceiver)>)>,
     sessions: &Arc<SessionPool>,
     buffer_pool: Arc<crate::pool::BufferPool>,
 ) -> crate::Result<()> {
@@ -140,17 +309,21 @@ pub async fn spawn_receivers(
 
     let port = crate::net::socket_port(&socket);
 
-    for (worker_id, ws) in worker_sends.into_iter().enumerate() {
-        let worker = DownstreamReceiveWorkerConfig {
-            worker_id,
-            port,
-            config: config.clone(),
-            sessions: sessions.clone(),
-            error_sender: error_sender.clone(),
-            buffer_pool: buffer_pool.clone(),
-        };
+    // Each listener port gets as many workers as the main port, each
+    // sending packets back to clients from its own port.
+    for (port, worker_sends) in std::iter::once((port, worker_sends)).chain(listener_sends) {
+        for (worker_id, ws) in worker_sends.into_iter().enumerate() {
+            let worker = DownstreamReceiveWorkerConfig {
+                worker_id,
+                port,
+                config: config.clone(),
+                sessions: sessions.clone(),
+                error_sender: error_sender.clone(),
+                buffer_pool: buffer_pool.clone(),
+            };
 
-        worker.spawn(ws).await?;
+            worker.spawn(ws).await?;
+        }
     }
 
     drop(error_sender);
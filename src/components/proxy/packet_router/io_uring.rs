@@ -35,6 +35,7 @@ impl super::DownstreamReceiveWorkerConfig {
 
         let socket =
             crate::net::DualStackLocalSocket::new(port).context("failed to bind socket")?;
+        proxy::worker_metrics::register_socket(worker_id, socket.raw_fd().0);
 
         let io_loop = io_uring_shared::IoUringLoop::new(2000, socket)?;
         io_loop
@@ -45,6 +46,7 @@ impl super::DownstreamReceiveWorkerConfig {
                     sessions,
                     error_acc: super::super::error::ErrorAccumulator::new(error_sender),
                     worker_id,
+                    port,
                     destinations: Vec::with_capacity(1),
                 },
                 pending_sends,
@@ -45,6 +45,7 @@ impl super::DownstreamReceiveWorkerConfig {
 
             tracing::trace!(port, "bound worker");
             let send_socket = socket.clone();
+            let send_sessions = sessions.clone();
 
             let inner_task = async move {
                 let (pending_sends, mut sends_rx) = pending_sends;
@@ -56,12 +57,26 @@ impl super::DownstreamReceiveWorkerConfig {
                         break;
                     }
 
+                    // Give more packets a chance to be queued and merged with
+                    // the ones already waiting.
+                    if let Some(delay) = send_sessions.coalesce_delay() {
+                        tokio::time::sleep(delay).await;
+                    }
+
                     sends_double_buffer = pending_sends.swap(sends_double_buffer);
 
                     for packet in sends_double_buffer.drain(..sends_double_buffer.len()) {
-                        let (result, _) = send_socket
-                            .send_to(packet.data, packet.destination.as_socket().unwrap())
+                        let destination = packet.destination.as_socket().unwrap();
+                        let (mut result, data) = send_socket
+                            .send_to_marked(packet.data, destination, packet.dscp)
                             .await;
+                        if result.is_err() && send_sessions.write_errors().config.retry {
+                            crate::metrics::send_retries_total(crate::metrics::WRITE).inc();
+                            (result, _) = send_socket
+                                .send_to_marked(data, destination, packet.dscp)
+                                .await;
+                        }
+
                         let asn_info = packet.asn_info.as_ref().into();
                         match result {
                             Ok(size) => {
@@ -84,6 +99,7 @@ impl super::DownstreamReceiveWorkerConfig {
                                     &asn_info,
                                 )
                                 .inc();
+                                send_sessions.write_errors().report(&error);
                             }
                         }
                     }
@@ -117,7 +133,12 @@ impl super::DownstreamReceiveWorkerConfig {
                         match result {
                             Ok((_size, mut source)) => {
                                 source.set_ip(source.ip().to_canonical());
-                                let packet = super::DownstreamPacket { contents: buffer, source };
+                                let packet = super::DownstreamPacket {
+                                    contents: buffer,
+                                    received_at,
+                                    source,
+                                    destination_port: port,
+                                };
 
                                 if let Some(last_received_at) = last_received_at {
                                     crate::metrics::packet_jitter(
@@ -0,0 +1,146 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use once_cell::sync::OnceCell;
+
+use crate::net::session_quota::SessionQuota;
+
+/// How a packet's session stands against its quota.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Usage<'quota> {
+    /// The session has no quota, or is within it.
+    Within,
+    /// The packet has just taken the session over the named limit of its
+    /// quota.
+    Exceeded(&'quota SessionQuota, &'static str),
+    /// The session had already exceeded its quota.
+    Over(&'quota SessionQuota),
+}
+
+/// How much a single session has been used, against its quota.
+#[derive(Default)]
+pub(crate) struct SessionUsage {
+    /// The first quota set on one of the session's packets.
+    quota: OnceCell<SessionQuota>,
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    exceeded: AtomicBool,
+    /// The second of the session's lifetime packets from the client are
+    /// being throttled in, and how many have been forwarded in it, packed as
+    /// `second << 32 | count`.
+    throttle: AtomicU64,
+}
+
+impl SessionUsage {
+    /// Records a packet of `len` bytes sent in either direction `elapsed`
+    /// after the session was created. The session is limited to `quota`,
+    /// unless it already has one.
+    #[inline]
+    pub(crate) fn record(
+        &self,
+        quota: Option<&SessionQuota>,
+        len: usize,
+        elapsed: Duration,
+    ) -> Usage<'_> {
+        let quota = match (self.quota.get(), quota) {
+            (Some(quota), _) => quota,
+            (None, Some(quota)) => self.quota.get_or_init(|| *quota),
+            (None, None) => return Usage::Within,
+        };
+
+        let bytes = self.bytes.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        let packets = self.packets.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.exceeded.load(Ordering::Relaxed) {
+            return Usage::Over(quota);
+        }
+
+        match quota.exceeded(bytes, packets, elapsed) {
+            None => Usage::Within,
+            Some(limit) if !self.exceeded.swap(true, Ordering::Relaxed) => {
+                Usage::Exceeded(quota, limit)
+            }
+            Some(_) => Usage::Over(quota),
+        }
+    }
+
+    /// Returns whether a packet from the client, sent `elapsed` after the
+    /// session was created, is within `packets_per_sec`.
+    #[inline]
+    pub(crate) fn throttle(&self, elapsed: Duration, packets_per_sec: u32) -> bool {
+        let second = elapsed.as_secs();
+        let mut admitted = false;
+        let _ = self
+            .throttle
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+                let count = if state >> 32 == second {
+                    state as u32
+                } else {
+                    0
+                };
+                admitted = count < packets_per_sec;
+                admitted.then(|| (second << 32) | u64::from(count + 1))
+            });
+        admitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_usage_against_the_first_quota() {
+        let usage = SessionUsage::default();
+        let quota = SessionQuota {
+            max_packets: Some(2),
+            ..<_>::default()
+        };
+        let other = SessionQuota {
+            max_packets: Some(10),
+            ..<_>::default()
+        };
+
+        assert_eq!(usage.record(None, 10, Duration::ZERO), Usage::Within);
+        assert_eq!(
+            usage.record(Some(&quota), 10, Duration::ZERO),
+            Usage::Within
+        );
+        assert_eq!(
+            usage.record(Some(&other), 10, Duration::ZERO),
+            Usage::Within
+        );
+        assert_eq!(
+            usage.record(None, 10, Duration::ZERO),
+            Usage::Exceeded(&quota, "packets")
+        );
+        assert_eq!(usage.record(None, 10, Duration::ZERO), Usage::Over(&quota));
+    }
+
+    #[test]
+    fn throttles_per_second() {
+        let usage = SessionUsage::default();
+
+        assert!(usage.throttle(Duration::from_millis(100), 2));
+        assert!(usage.throttle(Duration::from_millis(200), 2));
+        assert!(!usage.throttle(Duration::from_millis(300), 2));
+        assert!(usage.throttle(Duration::from_millis(1100), 2));
+    }
+}
@@ -0,0 +1,228 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The Redis client used by [shared handshake status](super::shared_sessions)
+//! and [checkpoints](super::checkpoint).
+//!
+//! Both only ever `GET`, `SET` and `DEL` a single key at a time, from a
+//! background task behind a circuit breaker, so this speaks just those
+//! commands of RESP rather than depending on the `redis` crate. What they
+//! need that a general client doesn't give by default is a fixed number of
+//! connections, so a slow server can't open more of them than there are
+//! calls to it in flight, and a timeout on connecting and on each command, so
+//! calls fail well within the circuit breaker's own timeout.
+
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+/// How many connections are opened to each server.
+pub(super) const CONNECTIONS: usize = 4;
+
+/// How long connecting or a single command can take before it's abandoned.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A reply from Redis.
+#[derive(Debug, PartialEq, Eq)]
+enum Reply {
+    Status,
+    Integer,
+    Bulk(Option<Vec<u8>>),
+}
+
+/// A connection pool to a Redis server, whose connections are reopened after
+/// errors.
+pub(super) struct Redis {
+    address: String,
+    connections: [tokio::sync::Mutex<Option<BufStream<TcpStream>>>; CONNECTIONS],
+    timeout: Duration,
+}
+
+impl Redis {
+    pub(super) fn new(address: String) -> Self {
+        Self {
+            address,
+            connections: <_>::default(),
+            timeout: TIMEOUT,
+        }
+    }
+
+    /// Returns the value of `key`, if it's set.
+    pub(super) async fn read(&self, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(value) => Ok(value),
+            reply => eyre::bail!("unexpected reply to GET: {reply:?}"),
+        }
+    }
+
+    /// Sets `key` to `value`, without expiring.
+    pub(super) async fn write(&self, key: &str, value: &[u8]) -> eyre::Result<()> {
+        self.command(&[b"SET", key.as_bytes(), value])
+            .await
+            .map(drop)
+    }
+
+    /// Sets `key` to `value`, expiring after `ttl`, rounded down to whole
+    /// seconds but at least one.
+    pub(super) async fn write_expiring(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl: Duration,
+    ) -> eyre::Result<()> {
+        let ttl = ttl.as_secs().max(1).to_string();
+        self.command(&[b"SET", key.as_bytes(), value, b"EX", ttl.as_bytes()])
+            .await
+            .map(drop)
+    }
+
+    /// Removes `key`, if it's set.
+    pub(super) async fn delete(&self, key: &str) -> eyre::Result<()> {
+        self.command(&[b"DEL", key.as_bytes()]).await.map(drop)
+    }
+
+    /// Sends a command over the first idle connection, waiting for one when
+    /// they're all busy. Connecting and the command each time out, failing
+    /// the command.
+    async fn command(&self, args: &[&[u8]]) -> eyre::Result<Reply> {
+        let mut connection = match self
+            .connections
+            .iter()
+            .find_map(|connection| connection.try_lock().ok())
+        {
+            Some(connection) => connection,
+            None => self.connections[0].lock().await,
+        };
+
+        // Taken out of the slot for the command, so a connection that's
+        // midway through a reply, because the command failed or was
+        // cancelled, is never used again.
+        let mut stream = match connection.take() {
+            Some(stream) => stream,
+            None => {
+                let stream = tokio::time::timeout(self.timeout, TcpStream::connect(&self.address))
+                    .await
+                    .map_err(|_| eyre::eyre!("timed out connecting to redis"))??;
+                BufStream::new(stream)
+            }
+        };
+
+        let reply = tokio::time::timeout(self.timeout, Self::round_trip(&mut stream, args))
+            .await
+            .map_err(|_| eyre::eyre!("timed out waiting for redis"))??;
+        *connection = Some(stream);
+        Ok(reply)
+    }
+
+    async fn round_trip(stream: &mut BufStream<TcpStream>, args: &[&[u8]]) -> eyre::Result<Reply> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        let line = line.trim_end_matches("\r\n");
+        let mut chars = line.chars();
+        let kind = chars.next();
+        let rest = chars.as_str();
+        match kind {
+            Some('+') => Ok(Reply::Status),
+            Some('-') => eyre::bail!("redis error: {rest}"),
+            Some(':') => Ok(Reply::Integer),
+            Some('$') => {
+                let Ok(len) = usize::try_from(rest.parse::<i64>()?) else {
+                    return Ok(Reply::Bulk(None));
+                };
+                let mut value = vec![0; len + 2];
+                stream.read_exact(&mut value).await?;
+                value.truncate(len);
+                Ok(Reply::Bulk(Some(value)))
+            }
+            _ => eyre::bail!("unsupported redis reply `{line}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let listener = tokio::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // Replies to each command in turn, regardless of what it is.
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufStream::new(stream);
+            for reply in [&b"+OK\r\n"[..], b"$1\r\n1\r\n", b":1\r\n", b"$-1\r\n"] {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let args: usize = line.trim()[1..].parse().unwrap();
+                for _ in 0..args * 2 {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                }
+                stream.write_all(reply).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+        });
+
+        let redis = Redis::new(address);
+        redis
+            .write_expiring("key", b"1", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(redis.read("key").await.unwrap(), Some(b"1".to_vec()));
+        redis.delete("key").await.unwrap();
+        assert_eq!(redis.read("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let listener = tokio::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // Accepts connections, but never replies.
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                streams.push(stream);
+            }
+        });
+
+        let redis = Redis {
+            timeout: Duration::from_millis(50),
+            ..Redis::new(address)
+        };
+        assert!(redis.read("key").await.is_err());
+        assert!(redis.connections[0].lock().await.is_none());
+    }
+}
@@ -0,0 +1,207 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+
+use super::sessions::SessionKey;
+use crate::net::{endpoint::metadata::DynamicMetadata, unresponsive};
+
+/// Whether sessions whose upstream doesn't respond to their client's packets
+/// are flagged, catching endpoints that black hole traffic but still pass
+/// health checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResponseTimeoutConfig {
+    /// How long a session can go without a response after a packet is sent
+    /// to its upstream before it's flagged, disabled if unset.
+    pub window: Option<Duration>,
+    /// Whether packets from clients with a flagged session have
+    /// [`unresponsive::METADATA_KEY`] set, so filters can route them
+    /// elsewhere.
+    pub metadata: bool,
+}
+
+/// Whether a single session's upstream has responded to its packets.
+#[derive(Default)]
+pub(crate) struct SessionHealth {
+    /// When the oldest packet without a response was sent, as milliseconds
+    /// since the session was created plus one, or zero if there's none.
+    awaiting_since: AtomicU64,
+    flagged: AtomicBool,
+}
+
+impl SessionHealth {
+    /// Records a packet sent `elapsed` after the session was created,
+    /// returning whether the session has just been flagged as having had no
+    /// response within `window`.
+    #[inline]
+    pub(crate) fn sent(&self, elapsed: Duration, window: Duration) -> bool {
+        let now = elapsed.as_millis() as u64 + 1;
+        match self
+            .awaiting_since
+            .compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => false,
+            Err(since) => {
+                now.saturating_sub(since) >= window.as_millis() as u64
+                    && !self.flagged.load(Ordering::Relaxed)
+                    && !self.flagged.swap(true, Ordering::Relaxed)
+            }
+        }
+    }
+
+    /// Returns how long the oldest packet without a response has been waiting
+    /// for one, `elapsed` after the session was created, if there is one.
+    #[inline]
+    pub(crate) fn awaited(&self, elapsed: Duration) -> Option<Duration> {
+        match self.awaiting_since.load(Ordering::Relaxed) {
+            0 => None,
+            since => Some(Duration::from_millis(
+                (elapsed.as_millis() as u64 + 1).saturating_sub(since),
+            )),
+        }
+    }
+
+    /// Records a response from the upstream, returning whether the session
+    /// was flagged.
+    #[inline]
+    pub(crate) fn responded(&self) -> bool {
+        if self.awaiting_since.load(Ordering::Relaxed) != 0 {
+            self.awaiting_since.store(0, Ordering::Relaxed);
+        }
+
+        self.flagged.load(Ordering::Relaxed) && self.flagged.swap(false, Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn is_flagged(&self) -> bool {
+        self.flagged.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks which sessions have been flagged as unresponsive.
+pub(crate) struct ResponseTimeouts {
+    window: Duration,
+    metadata: bool,
+    /// The upstreams of each client's flagged sessions.
+    unresponsive: RwLock<HashMap<SocketAddr, HashSet<SocketAddr>>>,
+}
+
+impl ResponseTimeouts {
+    /// Returns `None` if sessions aren't flagged.
+    pub(crate) fn new(config: ResponseTimeoutConfig) -> Option<Self> {
+        Some(Self {
+            window: config.window?,
+            metadata: config.metadata,
+            unresponsive: <_>::default(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub(crate) fn flag(&self, key: SessionKey) {
+        self.unresponsive
+            .write()
+            .entry(key.source)
+            .or_default()
+            .insert(key.dest);
+    }
+
+    pub(crate) fn unflag(&self, key: SessionKey) {
+        let mut unresponsive = self.unresponsive.write();
+        if let Some(upstreams) = unresponsive.get_mut(&key.source) {
+            upstreams.remove(&key.dest);
+            if upstreams.is_empty() {
+                unresponsive.remove(&key.source);
+            }
+        }
+    }
+
+    /// Sets [`unresponsive::METADATA_KEY`] in `metadata` if enabled and one of
+    /// `client`'s sessions is flagged.
+    #[inline]
+    pub(crate) fn mark(&self, client: SocketAddr, metadata: &mut DynamicMetadata) {
+        if !self.metadata {
+            return;
+        }
+
+        let unresponsive = self.unresponsive.read();
+        if !unresponsive.is_empty() && unresponsive.contains_key(&client) {
+            unresponsive::set(metadata);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_sessions_without_responses() {
+        let health = SessionHealth::default();
+        let window = Duration::from_secs(5);
+
+        assert!(!health.sent(Duration::ZERO, window));
+        assert!(!health.sent(Duration::from_secs(4), window));
+        assert!(health.sent(Duration::from_secs(5), window));
+        // Only flagged once.
+        assert!(!health.sent(Duration::from_secs(6), window));
+        assert!(health.is_flagged());
+
+        assert!(health.responded());
+        assert!(!health.is_flagged());
+        assert!(!health.responded());
+
+        // The window starts again from the next packet.
+        assert!(!health.sent(Duration::from_secs(10), window));
+        assert!(!health.sent(Duration::from_secs(14), window));
+    }
+
+    #[test]
+    fn marks_unresponsive_clients() {
+        let timeouts = ResponseTimeouts::new(ResponseTimeoutConfig {
+            window: Some(Duration::from_secs(5)),
+            metadata: true,
+        })
+        .unwrap();
+        let key = SessionKey {
+            source: (std::net::Ipv4Addr::LOCALHOST, 9000).into(),
+            dest: (std::net::Ipv4Addr::LOCALHOST, 7001).into(),
+        };
+
+        let mut metadata = DynamicMetadata::default();
+        timeouts.mark(key.source, &mut metadata);
+        assert!(!unresponsive::get(&metadata));
+
+        timeouts.flag(key);
+        timeouts.mark(key.source, &mut metadata);
+        assert!(unresponsive::get(&metadata));
+
+        timeouts.unflag(key);
+        let mut metadata = DynamicMetadata::default();
+        timeouts.mark(key.source, &mut metadata);
+        assert!(!unresponsive::get(&metadata));
+    }
+}
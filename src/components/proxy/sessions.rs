@@ -27,9 +27,15 @@ use tokio::time::Instant;
 use crate::{
     components::proxy::SendPacket,
     config::Config,
-    filters::Filter,
+    filters::{DropReason, Filter},
     metrics,
-    net::maxmind_db::{IpNetEntry, MetricsIpNetEntry},
+    net::{
+        dscp::{Dscp, DscpConfig},
+        maintenance::ActiveMaintenance,
+        maxmind_db::{IpNetEntry, MetricsIpNetEntry},
+        session_quota::{QuotaAction, SessionQuota},
+        upstream::UpstreamBinding,
+    },
     pool::{BufferPool, FrozenPoolBuffer, PoolBuffer},
     time::UtcTimestamp,
     Loggable,
@@ -37,12 +43,22 @@ use crate::{
 
 use parking_lot::RwLock;
 
-use super::PendingSends;
+use super::{
+    quota::{SessionUsage, Usage},
+    PendingSends,
+};
 
 pub(crate) mod inner_metrics;
 
 pub type SessionMap = crate::collections::ttl::TtlMap<SessionKey, Session>;
 
+/// How often the maintenance windows in the config are applied.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long sessions are kept without traffic once the pool is draining, so
+/// the drain ends as soon as clients go quiet rather than at its timeout.
+const DRAIN_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
         mod io_uring;
@@ -56,6 +72,7 @@ pub enum SessionError {
     SocketAddressUnavailable,
     MissingAllocatedSocket,
     MissingDestinationSocket,
+    Draining,
 }
 
 impl std::error::Error for SessionError {}
@@ -72,6 +89,7 @@ impl fmt::Display for SessionError {
             Self::MissingDestinationSocket => {
                 f.write_str("couldn't obtain any socket for destination, should be unreachable")
             }
+            Self::Draining => f.write_str("proxy is draining, new sessions are not accepted"),
         }
     }
 }
@@ -90,13 +108,106 @@ impl fmt::Debug for SessionError {
 /// Traffic from different gameservers is then demuxed using their address to
 /// send back to the original client.
 pub struct SessionPool {
-    ports_to_sockets: RwLock<HashMap<u16, PendingSends>>,
+    ports_to_sockets: RwLock<HashMap<u16, UpstreamSocket>>,
     storage: Arc<RwLock<SocketStorage>>,
     session_map: SessionMap,
     buffer_pool: Arc<BufferPool>,
     config: Arc<Config>,
     downstream_sends: Vec<PendingSends>,
+    /// The queues of packets sent back to clients from each of the proxy's
+    /// other listener ports.
+    listener_sends: HashMap<u16, Vec<PendingSends>>,
+    /// The listener port each client last sent a packet to, for those that
+    /// didn't send it to the main port.
+    listener_clients: crate::collections::ttl::TtlMap<SocketAddr, u16>,
     downstream_index: atomic::AtomicUsize,
+    draining: atomic::AtomicBool,
+    dscp: DscpConfig,
+    overload: super::OverloadConfig,
+    transparent: bool,
+    /// The sockets of each client when proxying transparently.
+    transparent_sockets: RwLock<HashMap<SocketAddr, TransparentSocket>>,
+    write_errors: super::write_errors::WriteErrors,
+    coalesce: super::CoalesceConfig,
+    history: Arc<super::PacketHistory>,
+    heatmap: Arc<super::LatencyHeatmap>,
+    events: super::Events,
+    fairness: super::FairnessConfig,
+    /// The optional stages packets go through, only built when configured.
+    stages: super::stages::Stages,
+    drops: super::drop_log::DropLog,
+    warm: super::WarmSocketsConfig,
+    affinity: super::SessionAffinity,
+    /// Whether any session has been given a quota, so packets are only
+    /// counted against quotas, and packets from upstreams only look up their
+    /// session, when needed.
+    quotas: atomic::AtomicBool,
+    /// The index of the next address assigned from a binding's address pool.
+    next_pool_address: atomic::AtomicUsize,
+}
+
+/// A socket used to send packets to upstreams, shared between sessions.
+#[derive(Clone)]
+struct UpstreamSocket {
+    pending_sends: PendingSends,
+    /// How the socket is bound, sessions only share sockets bound the same
+    /// way.
+    binding: Option<UpstreamBinding>,
+    /// A duplicate of the socket's descriptor, handed off to the next process
+    /// during a hot restart.
+    socket: socket2::Socket,
+}
+
+/// A socket bound to a client's address, shared by all of its sessions.
+struct TransparentSocket {
+    pending_sends: PendingSends,
+    sessions: usize,
+}
+
+/// Settings applied to every session in a [`SessionPool`].
+#[derive(Clone, Debug, Default)]
+pub struct SessionSettings {
+    /// The default DSCP marking of forwarded packets, unless overridden by a
+    /// filter.
+    pub dscp: DscpConfig,
+    /// The limits beyond which packets are shed.
+    pub overload: super::OverloadConfig,
+    /// Whether to send packets to upstreams from the client's own address,
+    /// rather than one of the proxy's, see
+    /// [`raw_socket_transparent`](crate::net::raw_socket_transparent).
+    pub transparent: bool,
+    /// How errors handling packets from upstreams are handled.
+    pub write_errors: super::WriteErrorConfig,
+    /// Whether small packets sent back to the same client are merged.
+    pub coalesce: super::CoalesceConfig,
+    /// How many of the most recent packets from clients are kept.
+    pub history: super::PacketHistoryConfig,
+    /// Whether the latency of packets is tracked for each source prefix.
+    pub heatmap: super::LatencyHeatmapConfig,
+    /// Whether packets from clients are held back until they're established.
+    pub handshake: super::HandshakeConfig,
+    /// Whether the first packets of each session are sent twice.
+    pub duplicate: super::DuplicateConfig,
+    /// Whether packets from clients are queued per client and handled in
+    /// turn.
+    pub fairness: super::FairnessConfig,
+    /// Whether upstreams reported unreachable stop being sent packets for a
+    /// while.
+    pub ejection: super::EjectionConfig,
+    /// Whether sessions without responses from their upstream are flagged.
+    pub response_timeout: super::ResponseTimeoutConfig,
+    /// Whether new sessions are only admitted to upstreams with capacity.
+    pub admission: super::AdmissionConfig,
+    /// One in how many dropped packets of each drop reason are logged.
+    pub drop_log_sample: u32,
+    /// Whether established clients are shared with other proxies.
+    pub shared_sessions: super::SharedSessionsConfig,
+    /// Whether endpoints removed from the config are remembered for a while.
+    pub tombstones: super::TombstoneConfig,
+    /// Whether upstream sockets are created before sessions need them.
+    pub warm: super::WarmSocketsConfig,
+    /// How a client's packets are matched to its sessions.
+    pub affinity: super::SessionAffinity,
 }
 
 /// The wrapper struct responsible for holding all of the socket related mappings.
@@ -116,6 +227,60 @@ impl SessionPool {
         config: Arc<Config>,
         downstream_sends: Vec<PendingSends>,
         buffer_pool: Arc<BufferPool>,
+    ) -> Arc<Self> {
+        Self::with_settings(
+            config,
+            downstream_sends,
+            buffer_pool,
+            SessionSettings::default(),
+        )
+    }
+
+    /// Constructs a new session pool with the DSCP marking, overload limits
+    /// and transparency in `settings`.
+    pub fn with_settings(
+        config: Arc<Config>,
+        downstream_sends: Vec<PendingSends>,
+        buffer_pool: Arc<BufferPool>,
+        settings: SessionSettings,
+    ) -> Arc<Self> {
+        Self::with_listeners(
+            config,
+            downstream_sends,
+            HashMap::new(),
+            buffer_pool,
+            settings,
+        )
+    }
+
+    /// Constructs a new session pool that also receives packets from clients
+    /// on other ports, sending packets back to each client from the port it
+    /// last sent a packet to through the queues in `listener_sends`.
+    pub fn with_listeners(
+        config: Arc<Config>,
+        downstream_sends: Vec<PendingSends>,
+        listener_sends: HashMap<u16, Vec<PendingSends>>,
+        buffer_pool: Arc<BufferPool>,
+        SessionSettings {
+            dscp,
+            overload,
+            transparent,
+            write_errors,
+            coalesce,
+            history,
+            heatmap,
+            handshake,
+            duplicate,
+            fairness,
+            ejection,
+            response_timeout,
+            admission,
+            drop_log_sample,
+            shared_sessions,
+            tombstones,
+            warm,
+            affinity,
+        }: SessionSettings,
     ) -> Arc<Self> {
         const SESSION_TIMEOUT_SECONDS: Duration = Duration::from_secs(60);
         const SESSION_EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(60);
@@ -126,49 +291,237 @@ impl SessionPool {
             storage: <_>::default(),
             session_map: SessionMap::new(SESSION_TIMEOUT_SECONDS, SESSION_EXPIRY_POLL_INTERVAL),
             buffer_pool,
-            downstream_sends,
             downstream_index: atomic::AtomicUsize::new(0),
+            draining: atomic::AtomicBool::new(false),
+            dscp,
+            overload,
+            transparent,
+            transparent_sockets: <_>::default(),
+            write_errors: super::write_errors::WriteErrors::new(write_errors),
+            coalesce,
+            history: Arc::new(super::PacketHistory::new(history, downstream_sends.len())),
+            heatmap: Arc::new(super::LatencyHeatmap::new(heatmap, downstream_sends.len())),
+            events: <_>::default(),
+            fairness,
+            stages: super::stages::Stages {
+                handshake: super::handshake::HandshakeGate::new(handshake, shared_sessions),
+                response_timeouts: super::response_timeout::ResponseTimeouts::new(response_timeout),
+                ejections: super::ejection::Ejections::new(ejection),
+                admission: super::admission::Admission::new(admission),
+                tombstones: super::tombstone::Tombstones::new(tombstones),
+                duplicator: super::duplicate::Duplicator::new(duplicate),
+                migrations: <_>::default(),
+                maintenance: <_>::default(),
+            },
+            drops: super::drop_log::DropLog::new(drop_log_sample),
+            warm,
+            affinity,
+            quotas: atomic::AtomicBool::new(false),
+            next_pool_address: atomic::AtomicUsize::new(0),
+            downstream_sends,
+            listener_sends,
+            listener_clients: crate::collections::ttl::TtlMap::new(
+                SESSION_TIMEOUT_SECONDS,
+                SESSION_EXPIRY_POLL_INTERVAL,
+            ),
         })
     }
 
-    /// Allocates a new upstream socket from a new socket from the system.
+    /// Records that `source` sent a packet to `port`, so the packets sent
+    /// back to it come from the same port.
+    #[inline]
+    pub(crate) fn record_listener_port(&self, source: SocketAddr, port: u16) {
+        if self.listener_sends.is_empty() {
+            return;
+        }
+
+        if !self.listener_sends.contains_key(&port) {
+            self.listener_clients.remove(source);
+        } else if !self
+            .listener_clients
+            .get(&source)
+            .is_some_and(|current| current.value == port)
+        {
+            self.listener_clients.insert(source, port);
+        }
+    }
+
+    /// Returns the queues of packets sent back to `destination`, those of the
+    /// port it last sent a packet to.
+    #[inline]
+    fn downstream_sends_for(&self, destination: &SocketAddr) -> &[PendingSends] {
+        if !self.listener_sends.is_empty() {
+            let port = self
+                .listener_clients
+                .get(destination)
+                .map(|port| port.value);
+            if let Some(sends) = port.and_then(|port| self.listener_sends.get(&port)) {
+                return sends;
+            }
+        }
+
+        &self.downstream_sends
+    }
+
+    /// Returns the queues of packets sent back to clients from every port.
+    fn all_downstream_sends(&self) -> impl Iterator<Item = &PendingSends> {
+        self.downstream_sends
+            .iter()
+            .chain(self.listener_sends.values().flatten())
+    }
+
+    /// Allocates a new upstream socket from a new socket from the system,
+    /// bound according to `binding` if set.
     fn create_new_session_from_new_socket<'pool>(
         self: &'pool Arc<Self>,
         key: SessionKey,
+        binding: Option<UpstreamBinding>,
     ) -> Result<(Option<MetricsIpNetEntry>, PendingSends), super::PipelineError> {
         tracing::trace!(source=%key.source, dest=%key.dest, "creating new socket for session");
-        let raw_socket = crate::net::raw_socket_with_reuse(0)?;
+        let (port, pending_sends) = self.create_socket(binding)?;
+        self.create_session_from_existing_socket(key, pending_sends, port)
+    }
+
+    /// Creates a new upstream socket bound according to `binding` if set,
+    /// and adds it to the pool, returning its port.
+    fn create_socket(
+        self: &Arc<Self>,
+        binding: Option<UpstreamBinding>,
+    ) -> Result<(u16, PendingSends), super::PipelineError> {
+        let raw_socket = match &binding {
+            Some(binding) => binding.socket()?,
+            None => crate::net::raw_socket_with_reuse(0)?,
+        };
+        self.add_socket(raw_socket, binding)
+    }
+
+    /// Starts sending and receiving packets through `raw_socket`, bound
+    /// according to `binding`, and adds it to the pool, returning its port.
+    fn add_socket(
+        self: &Arc<Self>,
+        raw_socket: socket2::Socket,
+        binding: Option<UpstreamBinding>,
+    ) -> Result<(u16, PendingSends), super::PipelineError> {
         let port = raw_socket
             .local_addr()?
             .as_socket()
             .ok_or(SessionError::SocketAddressUnavailable)?
             .port();
+        let socket = raw_socket.try_clone()?;
 
         let (pending_sends, srecv) = super::PendingSends::new(15)?;
         self.clone()
-            .spawn_session(raw_socket, port, (pending_sends.clone(), srecv))?;
+            .spawn_session(raw_socket, port, None, (pending_sends.clone(), srecv))?;
+
+        self.ports_to_sockets.write().insert(
+            port,
+            UpstreamSocket {
+                pending_sends: pending_sends.clone(),
+                binding,
+                socket,
+            },
+        );
+        Ok((port, pending_sends))
+    }
 
+    /// Returns the sockets bound as `binding` that no session is using.
+    fn idle_sockets(&self, binding: &Option<UpstreamBinding>) -> Vec<PendingSends> {
+        let storage = self.storage.read();
         self.ports_to_sockets
-            .write()
-            .insert(port, pending_sends.clone());
-        self.create_session_from_existing_socket(key, pending_sends, port)
+            .read()
+            .iter()
+            .filter(|(port, socket)| {
+                socket.binding == *binding && !storage.sockets_to_destination.contains_key(port)
+            })
+            .map(|(_, socket)| socket.pending_sends.clone())
+            .collect()
     }
 
+    /// Creates a session whose upstream socket is bound to the client's own
+    /// address, so upstreams see the client as the source of its packets.
+    /// Every session from the same client shares a socket, which is closed
+    /// once the last of them is released.
+    fn create_transparent_session(
+        self: &Arc<Self>,
+        key: SessionKey,
+    ) -> Result<(Option<MetricsIpNetEntry>, PendingSends), super::PipelineError> {
+        let pending_sends = {
+            let mut sockets = self.transparent_sockets.write();
+            let socket = match sockets.entry(key.source) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    tracing::trace!(source=%key.source, "creating transparent socket for client");
+                    let raw_socket = crate::net::raw_socket_transparent(key.source)?;
+                    let (pending_sends, srecv) = super::PendingSends::new(15)?;
+                    self.clone().spawn_session(
+                        raw_socket,
+                        key.source.port(),
+                        Some(key.source),
+                        (pending_sends.clone(), srecv),
+                    )?;
+
+                    entry.insert(TransparentSocket {
+                        pending_sends,
+                        sessions: 0,
+                    })
+                }
+            };
+
+            socket.sessions += 1;
+            socket.pending_sends.clone()
+        };
+
+        let asn_info = crate::net::maxmind_db::MaxmindDb::lookup(key.source.ip());
+        if let Some(asn_info) = &asn_info {
+            self.storage
+                .write()
+                .sources_to_asn_info
+                .insert(key.source, asn_info.clone());
+        }
+
+        let asn_metrics_info = asn_info.as_ref().map(MetricsIpNetEntry::from);
+        let session = Session::new(
+            key,
+            pending_sends.clone(),
+            key.source.port(),
+            self.clone(),
+            asn_info,
+        );
+        self.session_map.insert(key, session);
+        Ok((asn_metrics_info, pending_sends))
+    }
+
+    /// Processes a packet received from an upstream on the socket bound to
+    /// `port`, or for transparent sockets, bound to the `downstream` client.
     pub(crate) fn process_received_upstream_packet(
         self: &Arc<Self>,
         packet: PoolBuffer,
         mut recv_addr: SocketAddr,
         port: u16,
+        downstream: Option<SocketAddr>,
         last_received_at: &mut Option<UtcTimestamp>,
     ) {
         let received_at = UtcTimestamp::now();
         recv_addr.set_ip(recv_addr.ip().to_canonical());
         let (downstream_addr, asn_info): (SocketAddr, Option<MetricsIpNetEntry>) = {
             let storage = self.storage.read();
-            let Some(downstream_addr) = storage.destination_to_sources.get(&(recv_addr, port))
-            else {
-                tracing::debug!(address=%recv_addr, "received traffic from a server that has no downstream");
-                return;
+            let downstream_addr = match &downstream {
+                Some(downstream_addr) => downstream_addr,
+                None => {
+                    let Some(downstream_addr) =
+                        storage.destination_to_sources.get(&(recv_addr, port))
+                    else {
+                        tracing::debug!(address=%recv_addr, "received traffic from a server that has no downstream");
+                        self.drops.record(
+                            metrics::WRITE,
+                            DropReason::NoSession,
+                            "no downstream",
+                            recv_addr,
+                        );
+                        return;
+                    };
+                    downstream_addr
+                }
             };
             let asn_info = storage.sources_to_asn_info.get(downstream_addr);
 
@@ -177,6 +530,34 @@ impl SessionPool {
 
         let asn_metric_info = asn_info.as_ref().into();
 
+        if self.stages.response_timeouts.is_some() {
+            self.record_response(
+                SessionKey {
+                    source: downstream_addr,
+                    dest: recv_addr,
+                },
+                received_at,
+            );
+        }
+
+        if self.quotas.load(atomic::Ordering::Relaxed) {
+            let key = SessionKey {
+                source: downstream_addr,
+                dest: recv_addr,
+            };
+            if let Err(error) = self.record_upstream_usage(key, packet.len()) {
+                tracing::trace!(%error, "dropping packet from upstream");
+                metrics::packets_dropped_total(
+                    metrics::WRITE,
+                    error.discriminant(),
+                    &asn_metric_info,
+                )
+                .inc();
+                self.record_drop(metrics::WRITE, &error, recv_addr);
+                return;
+            }
+        }
+
         if let Some(last_received_at) = last_received_at {
             metrics::packet_jitter(metrics::WRITE, &asn_metric_info)
                 .set((received_at - *last_received_at).nanos());
@@ -190,28 +571,65 @@ impl SessionPool {
                 recv_addr,
                 downstream_addr,
                 asn_info,
+                self.dscp.downstream,
                 packet,
             )
         };
 
         match result {
             Ok(packet) => {
-                let index = self
-                    .downstream_index
-                    .fetch_add(1, atomic::Ordering::Relaxed)
-                    % self.downstream_sends.len();
+                // Packets can only be merged with those in the same queue, so
+                // all of a client's packets are sent from one queue.
+                let index = if self.coalesce.is_enabled() {
+                    let mut hasher = std::hash::DefaultHasher::new();
+                    std::hash::Hash::hash(&downstream_addr, &mut hasher);
+                    std::hash::Hasher::finish(&hasher) as usize
+                } else {
+                    self.downstream_index
+                        .fetch_add(1, atomic::Ordering::Relaxed)
+                };
+                let downstream_sends = self.downstream_sends_for(&downstream_addr);
+                let index = index % downstream_sends.len();
                 // SAFETY: we've ensured it's within bounds via the %
-                unsafe {
-                    self.downstream_sends.get_unchecked(index).push(packet);
+                let sends = unsafe { downstream_sends.get_unchecked(index) };
+
+                let shed = self
+                    .overload
+                    .check_budget(metrics::WRITE, received_at)
+                    .and_then(|()| self.overload.check_queue(metrics::WRITE, sends));
+                if let Err(error) = shed {
+                    tracing::trace!(%error, "shedding packet from upstream");
+                    metrics::packets_dropped_total(
+                        metrics::WRITE,
+                        error.discriminant(),
+                        &packet.asn_info.as_ref().into(),
+                    )
+                    .inc();
+                    self.record_drop(metrics::WRITE, &error, recv_addr);
+                    return;
+                }
+
+                match self.coalesce.max_size {
+                    Some(max_size) if max_size > 0 => {
+                        sends.push_coalesced(packet, max_size, &self.buffer_pool);
+                    }
+                    _ => sends.push(packet),
                 }
             }
             Err((asn_info, error)) => {
-                error.log();
+                self.write_errors.report(&error);
                 let label = format!("proxy::Session::process_recv_packet: {error}");
                 let asn_metric_info = asn_info.as_ref().into();
 
                 metrics::packets_dropped_total(metrics::WRITE, &label, &asn_metric_info).inc();
                 metrics::errors_total(metrics::WRITE, &label, &asn_metric_info).inc();
+                let Error::Filter(filter_error) = &error;
+                self.drops.record(
+                    metrics::WRITE,
+                    filter_error.drop_reason(),
+                    filter_error.discriminant(),
+                    recv_addr,
+                );
             }
         }
     }
@@ -222,61 +640,174 @@ impl SessionPool {
     /// existing socket.
     pub fn get<'pool>(
         self: &'pool Arc<Self>,
-        key @ SessionKey { dest, .. }: SessionKey,
+        key: SessionKey,
     ) -> Result<(Option<MetricsIpNetEntry>, PendingSends), super::PipelineError> {
+        self.get_with_timeout(key, None, None, 0)
+            .map(|(asn_info, sends, _)| (asn_info, sends))
+    }
+
+    /// Like [`Self::get`], but also overrides the session's idle timeout
+    /// with `timeout`, if set, records the `len` bytes being sent against
+    /// the session's quota, limiting it to `quota` if it doesn't have one,
+    /// and returns whether the packet being sent should be duplicated.
+    fn get_with_timeout<'pool>(
+        self: &'pool Arc<Self>,
+        key: SessionKey,
+        timeout: Option<Duration>,
+        quota: Option<&SessionQuota>,
+        len: usize,
+    ) -> Result<(Option<MetricsIpNetEntry>, PendingSends, bool), super::PipelineError> {
         tracing::trace!(source=%key.source, dest=%key.dest, "SessionPool::get");
+        if quota.is_some() && !self.quotas.load(atomic::Ordering::Relaxed) {
+            self.quotas.store(true, atomic::Ordering::Relaxed);
+        }
+        // Filters can't keep sessions alive for longer while draining.
+        let timeout = if self.is_draining() {
+            Some(timeout.map_or(DRAIN_IDLE_TIMEOUT, |timeout| {
+                timeout.min(DRAIN_IDLE_TIMEOUT)
+            }))
+        } else {
+            timeout
+        };
+
         // If we already have a session for the key pairing, return that session.
         if let Some(entry) = self.session_map.get(&key) {
             tracing::trace!("returning existing session");
+            if let Some(timeout) = timeout {
+                entry.set_ttl(timeout);
+            }
+            self.record_usage(&entry, quota, len)?;
+            self.record_sent(&entry);
+
             return Ok((
                 entry.asn_info.as_ref().map(MetricsIpNetEntry::from),
                 entry.pending_sends.clone(),
+                entry.take_duplicate(),
             ));
         }
 
-        // If there's a socket_set available, it means there are sockets
-        // allocated to the address that we want to avoid.
-        let storage = self.storage.read();
-        let Some(socket_set) = storage.destination_to_sockets.get(&dest) else {
-            drop(storage);
-            let no_sockets = self.ports_to_sockets.read().is_empty();
-            return if no_sockets {
-                // Initial case where we have no allocated or reserved sockets.
-                self.create_new_session_from_new_socket(key)
-            } else {
-                // Where we have no allocated sockets for a destination, assign
-                // the first available one.
-                let (port, sender) = self
-                    .ports_to_sockets
-                    .read()
-                    .iter()
-                    .next()
-                    .map(|(port, socket)| (*port, socket.clone()))
-                    .ok_or(SessionError::MissingAllocatedSocket)?;
-
-                self.create_session_from_existing_socket(key, sender, port)
-            };
+        let (asn_info, sends) = match self.rebind(key) {
+            Some(rebound) => rebound,
+            None => self.create_session(key)?,
+        };
+        let mut duplicate = false;
+        if let Some(entry) = self.session_map.get(&key) {
+            if let Some(timeout) = timeout {
+                entry.set_ttl(timeout);
+            }
+            self.record_usage(&entry, quota, len)?;
+            self.record_sent(&entry);
+            duplicate = entry.take_duplicate();
+        }
+
+        Ok((asn_info, sends, duplicate))
+    }
+
+    /// With IP affinity, takes the session of `key`'s client from another of
+    /// its ports to the same destination over to `key`, keeping its upstream
+    /// socket, so a client whose port was changed by a NAT isn't seen as a
+    /// new client by the upstream.
+    fn rebind(
+        self: &Arc<Self>,
+        key: SessionKey,
+    ) -> Option<(Option<MetricsIpNetEntry>, PendingSends)> {
+        // Transparent sockets are bound to each client's address.
+        if self.affinity != super::SessionAffinity::Ip || self.transparent {
+            return None;
+        }
+
+        let (previous, port) = {
+            let storage = self.storage.read();
+            storage
+                .destination_to_sockets
+                .get(&key.dest)?
+                .iter()
+                .find_map(|port| {
+                    storage
+                        .destination_to_sources
+                        .get(&(key.dest, *port))
+                        .filter(|source| source.ip() == key.source.ip())
+                        .map(|source| (*source, *port))
+                })?
+        };
+        let previous = SessionKey {
+            source: previous,
+            dest: key.dest,
+        };
+        let (pending_sends, asn_info) = {
+            let session = self.session_map.get(&previous)?;
+            (session.pending_sends.clone(), session.asn_info.clone())
         };
 
-        let available_socket = self
-            .ports_to_sockets
-            .read()
-            .iter()
-            .find(|(port, _)| !socket_set.contains(port))
-            .map(|(port, socket)| (*port, socket.clone()));
+        {
+            let mut storage = self.storage.write();
+            // Another packet may have taken the socket over first.
+            match storage.destination_to_sources.get_mut(&(key.dest, port)) {
+                Some(source) if *source == previous.source => *source = key.source,
+                _ => return None,
+            }
+            if let Some(asn_info) = storage.sources_to_asn_info.remove(&previous.source) {
+                storage.sources_to_asn_info.insert(key.source, asn_info);
+            }
+        }
+
+        // The socket now belongs to `key`, so it isn't released along with
+        // the previous session.
+        self.session_map.remove(previous);
+        tracing::debug!(
+            previous = %previous.source,
+            source = %key.source,
+            dest = %key.dest,
+            "rebinding session"
+        );
+        inner_metrics::rebound_total().inc();
+
+        let asn_metrics_info = asn_info.as_ref().map(MetricsIpNetEntry::from);
+        let session = Session::new(key, pending_sends.clone(), port, self.clone(), asn_info);
+        self.session_map.insert(key, session);
+        Some((asn_metrics_info, pending_sends))
+    }
+
+    /// Creates a new session for `key`.
+    fn create_session<'pool>(
+        self: &'pool Arc<Self>,
+        key @ SessionKey { source, dest }: SessionKey,
+    ) -> Result<(Option<MetricsIpNetEntry>, PendingSends), super::PipelineError> {
+        if self.is_draining() {
+            return Err(SessionError::Draining.into());
+        }
+
+        if self.transparent {
+            return self.create_transparent_session(key);
+        }
+
+        let binding = crate::net::upstream::find_binding(
+            &self.config.upstreams.load(),
+            &self.config.clusters.read(),
+            dest,
+        )
+        .map(|binding| binding.for_session(source, &self.next_pool_address));
+
+        // If there's a socket_set available, it means there are sockets
+        // allocated to the address that we want to avoid, otherwise assign the
+        // first available socket bound the same way.
+        let available_socket = {
+            let storage = self.storage.read();
+            let socket_set = storage.destination_to_sockets.get(&dest);
+            self.ports_to_sockets
+                .read()
+                .iter()
+                .find(|(port, socket)| {
+                    socket.binding == binding
+                        && socket_set.map_or(true, |socket_set| !socket_set.contains(port))
+                })
+                .map(|(port, socket)| (*port, socket.pending_sends.clone()))
+        };
 
         if let Some((port, socket)) = available_socket {
-            drop(storage);
-            self.storage
-                .write()
-                .destination_to_sockets
-                .get_mut(&dest)
-                .ok_or(SessionError::MissingDestinationSocket)?
-                .insert(port);
             self.create_session_from_existing_socket(key, socket, port)
         } else {
-            drop(storage);
-            self.create_new_session_from_new_socket(key)
+            self.create_new_session_from_new_socket(key, binding)
         }
     }
 
@@ -336,6 +867,7 @@ impl SessionPool {
         source: SocketAddr,
         dest: SocketAddr,
         asn_info: Option<MetricsIpNetEntry>,
+        dscp: Option<Dscp>,
         packet: PoolBuffer,
     ) -> Result<SendPacket, (Option<MetricsIpNetEntry>, Error)> {
         tracing::trace!(%source, %dest, length = packet.len(), "received packet from upstream");
@@ -346,7 +878,10 @@ impl SessionPool {
             return Err((asn_info, err.into()));
         }
 
+        // A filter may have sent the packet to another client.
+        let dest = context.dest.to_socket_addr().unwrap_or(dest);
         Ok(SendPacket {
+            dscp: Dscp::select(&context.metadata, dscp),
             data: context.contents.freeze(),
             destination: dest.into(),
             asn_info,
@@ -358,14 +893,570 @@ impl SessionPool {
         &self.session_map
     }
 
-    /// Sends packet data to the appropiate session based on its `key`.
+    /// Returns how long to wait for packets to merge before sending packets
+    /// back to clients, if at all.
+    #[inline]
+    pub(crate) fn coalesce_delay(&self) -> Option<Duration> {
+        self.coalesce.delay()
+    }
+
+    /// Returns the most recent packets received from clients.
+    #[inline]
+    pub(crate) fn history(&self) -> &Arc<super::PacketHistory> {
+        &self.history
+    }
+
+    /// The latency of packets for each source prefix.
+    #[inline]
+    pub(crate) fn heatmap(&self) -> &Arc<super::LatencyHeatmap> {
+        &self.heatmap
+    }
+
+    /// The events emitted to subscribers of the admin server's `/events`
+    /// endpoint.
+    #[inline]
+    pub(crate) fn events(&self) -> &super::Events {
+        &self.events
+    }
+
+    /// Whether packets from clients are queued per client and handled in
+    /// turn.
+    #[inline]
+    pub(crate) fn fairness(&self) -> super::FairnessConfig {
+        self.fairness
+    }
+
+    /// The optional stages packets go through.
+    #[inline]
+    pub(crate) fn stages(&self) -> &super::stages::Stages {
+        &self.stages
+    }
+
+    /// Records that `destination` was reported unreachable by an ICMP error,
+    /// ejecting it once it has been reported enough times.
+    pub(crate) fn report_unreachable(&self, destination: SocketAddr, reason: &'static str) {
+        metrics::upstream_unreachable_total(reason).inc();
+        self.report_ejectable(destination, reason);
+    }
+
+    /// Records a failure of `destination`, ejecting it once it has failed
+    /// enough times.
+    fn report_ejectable(&self, destination: SocketAddr, reason: &'static str) {
+        let Some(ejections) = &self.stages.ejections else {
+            return;
+        };
+        if !ejections.report(destination, std::time::Instant::now()) {
+            return;
+        }
+
+        let duration = ejections.duration();
+        tracing::warn!(%destination, reason, ?duration, "ejecting unreachable upstream");
+        metrics::upstream_ejections_total().inc();
+        self.events
+            .emit(|| super::Event::upstream_ejected(destination, reason, duration));
+    }
+
+    /// Records a packet sent to the upstream of `session`, flagging the
+    /// session if its upstream hasn't responded within the response timeout.
+    #[inline]
+    fn record_sent(&self, session: &Session) {
+        let Some(response_timeouts) = &self.stages.response_timeouts else {
+            return;
+        };
+        let window = response_timeouts.window();
+        if !session.health.sent(session.created_at.elapsed(), window) {
+            return;
+        }
+
+        let key = session.key;
+        crate::log_throttle::throttled!(warn!(
+            source = %key.source,
+            dest = %key.dest,
+            ?window,
+            "session has had no responses from its upstream"
+        ));
+        inner_metrics::unresponsive().inc();
+        inner_metrics::unresponsive_total().inc();
+        response_timeouts.flag(key);
+    }
+
+    /// Records a packet of `len` bytes from the client of `session`, limiting
+    /// the session to `quota` if it doesn't have one, returning an error if
+    /// the packet is over the session's quota and shouldn't be sent.
+    #[inline]
+    fn record_usage(
+        &self,
+        session: &Session,
+        quota: Option<&SessionQuota>,
+        len: usize,
+    ) -> Result<(), super::PipelineError> {
+        if !self.quotas.load(atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let elapsed = session.created_at.elapsed();
+        let quota = match session.usage.record(quota, len, elapsed) {
+            Usage::Within => return Ok(()),
+            Usage::Exceeded(quota, limit) => {
+                self.report_quota_exceeded(session.key, quota, limit);
+                quota
+            }
+            Usage::Over(quota) => quota,
+        };
+
+        match quota.action {
+            QuotaAction::Event => Ok(()),
+            QuotaAction::Terminate => Err(super::PipelineError::SessionQuotaExceeded),
+            QuotaAction::Throttle => {
+                if session
+                    .usage
+                    .throttle(elapsed, quota.throttle_packets_per_sec)
+                {
+                    Ok(())
+                } else {
+                    Err(super::PipelineError::SessionThrottled)
+                }
+            }
+        }
+    }
+
+    /// Records a packet of `len` bytes from the upstream of the session for
+    /// `key`, returning an error if the session has been terminated for
+    /// exceeding its quota.
+    #[inline]
+    fn record_upstream_usage(
+        &self,
+        key: SessionKey,
+        len: usize,
+    ) -> Result<(), super::PipelineError> {
+        let Some(session) = self.session_map.peek(&key) else {
+            return Ok(());
+        };
+
+        let quota = match session
+            .usage
+            .record(None, len, session.created_at.elapsed())
+        {
+            Usage::Within => return Ok(()),
+            Usage::Exceeded(quota, limit) => {
+                self.report_quota_exceeded(key, quota, limit);
+                quota
+            }
+            Usage::Over(quota) => quota,
+        };
+
+        match quota.action {
+            QuotaAction::Terminate => Err(super::PipelineError::SessionQuotaExceeded),
+            QuotaAction::Throttle | QuotaAction::Event => Ok(()),
+        }
+    }
+
+    fn report_quota_exceeded(&self, key: SessionKey, quota: &SessionQuota, limit: &'static str) {
+        let action = quota.action.as_str();
+        tracing::info!(
+            source = %key.source,
+            dest = %key.dest,
+            limit,
+            action,
+            "session exceeded its quota"
+        );
+        inner_metrics::quota_exceeded_total(limit, action).inc();
+        self.events
+            .emit(|| super::Event::session_quota_exceeded(key.source, key.dest, limit, action));
+    }
+
+    /// Records a response from the upstream of the session for `key`,
+    /// received at `received_at`.
+    #[inline]
+    fn record_response(&self, key: SessionKey, received_at: UtcTimestamp) {
+        let responded = self.session_map.peek(&key).is_some_and(|session| {
+            if self.heatmap.is_enabled() {
+                if let Some(rtt) = session.health.awaited(session.created_at.elapsed()) {
+                    self.heatmap.record_rtt(key.source.ip(), received_at, rtt);
+                }
+            }
+            session.health.responded()
+        });
+        if responded {
+            tracing::info!(
+                source = %key.source,
+                dest = %key.dest,
+                "session's upstream is responding again"
+            );
+            self.clear_unresponsive(key);
+        }
+    }
+
+    fn clear_unresponsive(&self, key: SessionKey) {
+        inner_metrics::unresponsive().dec();
+        if let Some(response_timeouts) = &self.stages.response_timeouts {
+            response_timeouts.unflag(key);
+        }
+    }
+
+    /// Applies the maintenance windows in the config every
+    /// [`MAINTENANCE_INTERVAL`] until shutdown.
+    pub(crate) fn spawn_maintenance(
+        self: &Arc<Self>,
+        mut shutdown_rx: crate::ShutdownRx,
+    ) -> tokio::task::JoinHandle<()> {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown_rx.changed() => return,
+                }
+
+                pool.update_maintenance(time::OffsetDateTime::now_utc());
+            }
+        })
+    }
+
+    fn update_maintenance(&self, now: time::OffsetDateTime) {
+        let windows = self.config.maintenance.load();
+        let previous = self.stages.maintenance();
+        let previous_windows = previous
+            .as_deref()
+            .map_or(&[][..], |previous| &previous.windows);
+        if windows.is_empty() && previous_windows.is_empty() {
+            return;
+        }
+
+        let active = ActiveMaintenance::new(&windows, now);
+        if previous
+            .as_deref()
+            .map_or(active.windows.is_empty(), |previous| *previous == active)
+        {
+            return;
+        }
+
+        for window in active.windows.iter() {
+            if !previous_windows.contains(window) {
+                tracing::info!(%window, "maintenance window started");
+            }
+        }
+        for window in previous_windows.iter() {
+            if !active.windows.contains(window) {
+                tracing::info!(%window, "maintenance window ended");
+            }
+        }
+
+        // Packets are only checked against maintenance windows while some
+        // are in effect.
+        let active = (!active.windows.is_empty()).then(|| Arc::new(active));
+        self.stages.maintenance.store(active);
+    }
+
+    /// Keeps the tombstones of endpoints removed from the config up to date
+    /// until shutdown, if enabled.
+    pub(crate) fn spawn_tombstones(
+        self: &Arc<Self>,
+        mut shutdown_rx: crate::ShutdownRx,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        self.stages.tombstones.as_ref()?;
+
+        let pool = self.clone();
+        let mut clusters = self.config.clusters.watch();
+        Some(tokio::spawn(async move {
+            // Expired tombstones are also cleared while the config is unchanged.
+            let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    result = clusters.changed() => {
+                        if result.is_err() {
+                            return;
+                        }
+                    }
+                    _ = shutdown_rx.changed() => return,
+                }
+
+                let current = clusters.borrow_and_update().clone();
+                if let Some(tombstones) = &pool.stages.tombstones {
+                    tombstones.update(&current, std::time::Instant::now());
+                }
+            }
+        }))
+    }
+
+    /// Keeps spare upstream sockets ready for new sessions to the endpoints
+    /// in the config until shutdown, if enabled.
+    pub(crate) fn spawn_warm_sockets(
+        self: &Arc<Self>,
+        mut shutdown_rx: crate::ShutdownRx,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        // Transparent sockets are bound to each client's address.
+        if !self.warm.enabled() || self.transparent {
+            return None;
+        }
+
+        let pool = self.clone();
+        let mut clusters = self.config.clusters.watch();
+        Some(tokio::spawn(async move {
+            // Spare sockets taken by new sessions are also replaced while
+            // the config is unchanged.
+            let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+            let mut known = std::collections::HashSet::new();
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    result = clusters.changed() => {
+                        if result.is_err() {
+                            return;
+                        }
+                    }
+                    _ = shutdown_rx.changed() => return,
+                }
+
+                let current = clusters.borrow_and_update().clone();
+                known = pool.warm_sockets(&current, &known);
+            }
+        }))
+    }
+
+    /// Creates sockets until there are enough spare sockets for each way the
+    /// sockets to the endpoints in `clusters` are bound, and sends the probe
+    /// to the endpoints that aren't `known`. Returns the endpoints in
+    /// `clusters`.
+    fn warm_sockets(
+        self: &Arc<Self>,
+        clusters: &crate::net::ClusterMap,
+        known: &std::collections::HashSet<SocketAddr>,
+    ) -> std::collections::HashSet<SocketAddr> {
+        let upstreams = self.config.upstreams.load();
+        for binding in super::warm::bindings(&upstreams, clusters) {
+            let spare = self.idle_sockets(&binding).len();
+            for _ in spare..self.warm.spare {
+                if let Err(error) = self.create_socket(binding.clone()) {
+                    crate::log_throttle::throttled!(warn!(
+                        %error,
+                        "failed to create spare upstream socket"
+                    ));
+                    break;
+                }
+            }
+        }
+
+        let endpoints = super::warm::endpoints(clusters);
+        let Some(probe) = &self.warm.probe else {
+            return endpoints;
+        };
+
+        for &endpoint in endpoints.difference(known) {
+            let binding = crate::net::upstream::find_binding(&upstreams, clusters, endpoint)
+                .map(|binding| binding.for_session(endpoint, &self.next_pool_address));
+            let Some(socket) = self.idle_sockets(&binding).pop() else {
+                continue;
+            };
+
+            tracing::debug!(%endpoint, "sending warm-up probe");
+            socket.push(SendPacket {
+                destination: endpoint.into(),
+                data: self.buffer_pool.clone().alloc_slice(probe).freeze(),
+                asn_info: None,
+                dscp: self.dscp.upstream,
+            });
+        }
+
+        endpoints
+    }
+
+    /// Returns whether `endpoint` was removed from the config within the
+    /// tombstone window.
+    #[inline]
+    pub(crate) fn is_removed(&self, endpoint: SocketAddr, now: std::time::Instant) -> bool {
+        self.stages
+            .tombstones
+            .as_ref()
+            .is_some_and(|tombstones| tombstones.is_removed(endpoint, now))
+    }
+
+    /// Returns the error dropping a packet from `client` that was for
+    /// `endpoint`, which has been removed, sending the client the
+    /// notification if it hasn't been sent it yet.
+    pub(crate) fn endpoint_removed(
+        &self,
+        client: SocketAddr,
+        endpoint: SocketAddr,
+    ) -> super::PipelineError {
+        if let Some(notification) = self
+            .stages
+            .tombstones
+            .as_ref()
+            .and_then(|tombstones| tombstones.notification(client, endpoint))
+        {
+            self.reply(client, notification);
+        }
+        super::PipelineError::EndpointRemoved
+    }
+
+    /// Replaces `error` with [`PipelineError::EndpointRemoved`] when a packet
+    /// from `client` was dropped for lack of endpoints while it has a session
+    /// with an endpoint that has been removed, as the endpoint being removed
+    /// is why.
+    ///
+    /// [`PipelineError::EndpointRemoved`]: super::PipelineError::EndpointRemoved
+    pub(crate) fn explain_removed(
+        &self,
+        client: SocketAddr,
+        error: super::PipelineError,
+    ) -> super::PipelineError {
+        let Some(tombstones) = &self.stages.tombstones else {
+            return error;
+        };
+        if error.drop_reason() != DropReason::NoEndpoints {
+            return error;
+        }
+
+        let removed = tombstones.removed_session(std::time::Instant::now(), |endpoint| {
+            self.session_map
+                .peek(&SessionKey {
+                    source: client,
+                    dest: endpoint,
+                })
+                .is_some()
+        });
+        match removed {
+            Some(endpoint) => self.endpoint_removed(client, endpoint),
+            None => error,
+        }
+    }
+
+    /// Records a packet dropped for `error`, travelling in `direction` to or
+    /// from `address`.
+    #[inline]
+    pub(crate) fn record_drop(
+        &self,
+        direction: metrics::Direction,
+        error: &super::PipelineError,
+        address: SocketAddr,
+    ) {
+        self.drops.record(
+            direction,
+            error.drop_reason(),
+            error.discriminant(),
+            address,
+        );
+    }
+
+    /// Returns whether a new session for `key` is rejected, because its
+    /// upstream is at the capacity it advertises. Packets for existing
+    /// sessions are always admitted.
+    #[inline]
+    pub(crate) fn is_rejected(&self, key: SessionKey) -> bool {
+        let Some(admission) = &self.stages.admission else {
+            return false;
+        };
+        if self.session_map.peek(&key).is_some() {
+            return false;
+        }
+
+        let Some(rejection) = admission.check(&self.config.clusters.read(), key.dest) else {
+            return false;
+        };
+
+        tracing::debug!(
+            source = %key.source,
+            dest = %key.dest,
+            reason = rejection.as_str(),
+            "new session not admitted"
+        );
+        metrics::admission_rejected_total(rejection.as_str()).inc();
+        true
+    }
+
+    /// Tells `client` its new session was rejected, if the pool has been
+    /// configured with a payload to do so.
+    pub(crate) fn reject(&self, client: SocketAddr) {
+        if let Some(nack) = self
+            .stages
+            .admission
+            .as_ref()
+            .and_then(|admission| admission.nack())
+        {
+            self.reply(client, nack);
+        }
+    }
+
+    /// Re-points the active sessions to `from` to `to`, returning how many
+    /// sessions were re-pointed. Clients keep sending to the proxy as
+    /// before, so they don't have to handshake again.
+    ///
+    /// If `token` is set, only the sessions of clients whose packets have
+    /// that token are re-pointed, once their next packet is received. If
+    /// `notification` is set, it's sent to each client the first time one of
+    /// its packets is re-pointed.
+    pub fn migrate(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+        token: Option<bytes::Bytes>,
+        notification: Option<bytes::Bytes>,
+    ) -> usize {
+        let keys: Vec<_> = self
+            .session_map
+            .keys()
+            .into_iter()
+            .filter(|key| key.dest == from)
+            .collect();
+
+        tracing::info!(%from, %to, sessions = keys.len(), "migrating sessions");
+        let migrated = keys.len();
+        self.stages
+            .migrations
+            .get_or_init(super::migration::Migrations::default)
+            .migrate(keys, to, token, notification);
+        migrated
+    }
+
+    /// Returns how errors sending packets back to clients are handled.
+    #[inline]
+    pub(crate) fn write_errors(&self) -> &super::write_errors::WriteErrors {
+        &self.write_errors
+    }
+
+    /// Queues `data` to be sent back to `destination` from the proxy itself,
+    /// such as a filter's reply to a control message.
+    pub(crate) fn reply(&self, destination: SocketAddr, data: &[u8]) {
+        let downstream_sends = self.downstream_sends_for(&destination);
+        let index = self
+            .downstream_index
+            .fetch_add(1, atomic::Ordering::Relaxed)
+            % downstream_sends.len();
+        downstream_sends[index].push(SendPacket {
+            destination: destination.into(),
+            data: self.buffer_pool.clone().alloc_slice(data).freeze(),
+            asn_info: None,
+            dscp: self.dscp.downstream,
+        });
+    }
+
+    /// Returns an error if more than the packet budget has elapsed since a
+    /// packet from downstream was received at `received_at`.
+    #[inline]
+    pub(crate) fn check_budget(
+        &self,
+        received_at: UtcTimestamp,
+    ) -> Result<(), super::PipelineError> {
+        self.overload.check_budget(metrics::READ, received_at)
+    }
+
+    /// Sends packet data to the appropiate session based on its `key`, marked
+    /// with `dscp` if set, otherwise the pool's default. If `timeout` is set
+    /// it overrides the idle timeout of the session, and if `quota` is set
+    /// it limits a session that doesn't already have a quota.
     #[inline]
     pub fn send(
         self: &Arc<Self>,
         key: SessionKey,
         packet: FrozenPoolBuffer,
+        dscp: Option<Dscp>,
+        timeout: Option<Duration>,
+        quota: Option<&SessionQuota>,
     ) -> Result<(), super::PipelineError> {
-        self.send_inner(key, packet)?;
+        self.send_inner(key, packet, dscp, timeout, quota)?;
         Ok(())
     }
 
@@ -374,14 +1465,24 @@ impl SessionPool {
         self: &Arc<Self>,
         key: SessionKey,
         packet: FrozenPoolBuffer,
+        dscp: Option<Dscp>,
+        timeout: Option<Duration>,
+        quota: Option<&SessionQuota>,
     ) -> Result<PendingSends, super::PipelineError> {
-        let (asn_info, sender) = self.get(key)?;
+        let (asn_info, sender, duplicate) =
+            self.get_with_timeout(key, timeout, quota, packet.len())?;
+        self.overload.check_queue(metrics::READ, &sender)?;
 
-        sender.push(SendPacket {
+        let packet = SendPacket {
             destination: key.dest.into(),
             data: packet,
             asn_info,
-        });
+            dscp: dscp.or(self.dscp.upstream),
+        };
+        if let (true, Some(duplicator)) = (duplicate, &self.stages.duplicator) {
+            duplicator.schedule(sender.clone(), &packet);
+        }
+        sender.push(packet);
         Ok(sender)
     }
 
@@ -414,7 +1515,22 @@ impl SessionPool {
         port: u16,
     ) {
         tracing::trace!("releasing socket");
+        if self.transparent {
+            self.release_transparent_socket(source);
+            self.storage.write().sources_to_asn_info.remove(source);
+            return;
+        }
+
         let mut storage = self.storage.write();
+        // The socket was taken over by a rebound session.
+        if storage
+            .destination_to_sources
+            .get(&(*dest, port))
+            .is_some_and(|owner| owner != source)
+        {
+            return;
+        }
+
         let Some(socket_set) = storage.destination_to_sockets.get_mut(dest) else {
             return;
         };
@@ -441,11 +1557,251 @@ impl SessionPool {
         tracing::trace!("socket released");
     }
 
+    /// Closes the transparent socket for `source` once no sessions use it.
+    fn release_transparent_socket(&self, source: &SocketAddr) {
+        let mut sockets = self.transparent_sockets.write();
+        let Some(socket) = sockets.get_mut(source) else {
+            return;
+        };
+
+        socket.sessions -= 1;
+        if socket.sessions == 0 {
+            tracing::trace!(%source, "closing transparent socket for client");
+            socket.pending_sends.shutdown_receiver();
+            sockets.remove(source);
+        }
+    }
+
+    /// Returns what's handed off to the next process during a hot restart:
+    /// the sessions it recreates, and the upstream sockets with the sessions
+    /// using them, along with a duplicate of their descriptors.
+    pub(crate) fn handoff(
+        &self,
+    ) -> (
+        Vec<SessionKey>,
+        Vec<(crate::net::hot_restart::Upstream, socket2::Socket)>,
+    ) {
+        let sockets = self.ports_to_sockets.read();
+        let mut upstreams: HashMap<u16, crate::net::hot_restart::Upstream> = sockets
+            .iter()
+            .map(|(port, socket)| {
+                let upstream = crate::net::hot_restart::Upstream {
+                    port: *port,
+                    binding: socket.binding.clone(),
+                    sessions: Vec::new(),
+                };
+                (*port, upstream)
+            })
+            .collect();
+
+        // Transparent sessions have a socket bound to their client instead.
+        let mut recreated = Vec::new();
+        for (key, port) in self
+            .session_map
+            .snapshot(|key, session| (*key, session.socket_port))
+        {
+            match upstreams.get_mut(&port) {
+                Some(upstream) => upstream.sessions.push(key),
+                None => recreated.push(key),
+            }
+        }
+
+        let upstreams = upstreams
+            .into_values()
+            .filter_map(|upstream| {
+                let socket = &sockets[&upstream.port].socket;
+                match socket.try_clone() {
+                    Ok(socket) => Some((upstream, socket)),
+                    Err(error) => {
+                        tracing::warn!(
+                            port = upstream.port,
+                            %error,
+                            "failed to hand off upstream socket"
+                        );
+                        recreated.extend(upstream.sessions);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        (recreated, upstreams)
+    }
+
+    /// Takes over the upstream sockets handed off from a previous process
+    /// during a hot restart, along with the sessions using them, so
+    /// upstreams keep receiving their packets from the same port.
+    pub(crate) fn restore_upstreams(
+        self: &Arc<Self>,
+        upstreams: Vec<(crate::net::hot_restart::Upstream, socket2::Socket)>,
+    ) {
+        for (upstream, socket) in upstreams {
+            let pending_sends = match self.add_socket(socket, upstream.binding) {
+                Ok((_, pending_sends)) => pending_sends,
+                Err(error) => {
+                    tracing::warn!(
+                        port = upstream.port,
+                        %error,
+                        "failed to restore upstream socket"
+                    );
+                    // Fall back to sending their packets from a new socket.
+                    self.restore(&upstream.sessions);
+                    continue;
+                }
+            };
+
+            for key in upstream.sessions {
+                match self.create_session_from_existing_socket(
+                    key,
+                    pending_sends.clone(),
+                    upstream.port,
+                ) {
+                    Ok(_) => self.restored(&key),
+                    Err(error) => {
+                        tracing::warn!(
+                            source = %key.source,
+                            dest = %key.dest,
+                            %error,
+                            "failed to restore session"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Recreates sessions handed off from a previous process during a hot
+    /// restart.
+    pub(crate) fn restore(self: &Arc<Self>, keys: &[SessionKey]) {
+        for key in keys {
+            if let Err(error) = self.get(*key) {
+                tracing::warn!(source=%key.source, dest=%key.dest, %error, "failed to restore session");
+            } else {
+                self.restored(key);
+            }
+        }
+    }
+
+    fn restored(&self, key: &SessionKey) {
+        if let Some(session) = self.session_map.get(key) {
+            // Restored sessions are past their handshake.
+            session.duplicates.store(0, atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Stops reading from the downstream and upstream sockets once they've
+    /// been handed off to the next process during a hot restart, so the
+    /// packets of a client aren't split between both processes.
+    pub(crate) fn stop_reading(&self) {
+        for downstream_listener in self.all_downstream_sends() {
+            downstream_listener.shutdown_receiver();
+        }
+        for socket in self.ports_to_sockets.read().values() {
+            socket.pending_sends.shutdown_receiver();
+        }
+        for socket in self.transparent_sockets.read().values() {
+            socket.pending_sends.shutdown_receiver();
+        }
+    }
+
+    /// Returns whether the pool has stopped accepting new sessions.
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Stops the pool from accepting new sessions, existing sessions continue
+    /// to forward traffic until they've been idle for [`DRAIN_IDLE_TIMEOUT`].
+    /// If a `notification` is provided, it is sent once to every client that
+    /// currently has an active session.
+    pub(crate) fn start_draining(&self, notification: Option<&[u8]>) {
+        if self.draining.swap(true, atomic::Ordering::SeqCst) {
+            return;
+        }
+
+        for key in self.session_map.keys() {
+            if let Some(session) = self.session_map.peek(&key) {
+                session.set_ttl(DRAIN_IDLE_TIMEOUT);
+            }
+        }
+
+        let Some(notification) = notification else {
+            return;
+        };
+
+        let sources: std::collections::BTreeSet<_> = self
+            .session_map
+            .keys()
+            .into_iter()
+            .map(|key| key.source)
+            .collect();
+
+        tracing::info!(
+            clients = sources.len(),
+            "notifying clients that the proxy is draining"
+        );
+
+        for source in sources {
+            let downstream_sends = self.downstream_sends_for(&source);
+            let index = self
+                .downstream_index
+                .fetch_add(1, atomic::Ordering::Relaxed)
+                % downstream_sends.len();
+            downstream_sends[index].push(SendPacket {
+                destination: source.into(),
+                data: self.buffer_pool.clone().alloc_slice(notification).freeze(),
+                asn_info: None,
+                dscp: self.dscp.downstream,
+            });
+        }
+    }
+
+    /// Stops accepting new sessions and waits until either all active
+    /// sessions have expired, or `timeout` has elapsed, updating `status`
+    /// with the progress as sessions are removed.
+    pub(crate) async fn drain(
+        &self,
+        timeout: Duration,
+        notification: Option<&[u8]>,
+        status: &super::DrainStatus,
+    ) {
+        const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+        let initial = self.session_map.len();
+        status.start(initial, timeout);
+        self.start_draining(notification);
+        tracing::info!(sessions = initial, ?timeout, "draining active sessions");
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Idle sessions expire within seconds while draining, far sooner
+            // than the map's cleanup task would remove them.
+            self.session_map.remove_expired();
+            let remaining = self.session_map.len();
+            status.update(remaining);
+
+            if remaining == 0 {
+                tracing::info!("all sessions drained");
+                break;
+            }
+
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    sessions = remaining,
+                    "drain timeout elapsed with active sessions"
+                );
+                break;
+            }
+
+            tokio::time::sleep(DRAIN_POLL_INTERVAL.min(deadline - Instant::now())).await;
+        }
+    }
+
     /// Closes all active sessions, and all downstream listeners
     pub(crate) fn shutdown(self: Arc<Self>, wait: bool) {
         // Disable downstream listeners first so sessions aren't spawned while
         // we are trying to reap the active sessions
-        for downstream_listener in &self.downstream_sends {
+        for downstream_listener in self.all_downstream_sends() {
             downstream_listener.shutdown_receiver();
         }
 
@@ -479,6 +1835,12 @@ pub struct Session {
     asn_info: Option<IpNetEntry>,
     /// The socket pool of the session.
     pool: Arc<SessionPool>,
+    /// The number of packets left to duplicate at the start of the session.
+    duplicates: atomic::AtomicU32,
+    /// Whether the upstream has responded to the session's packets.
+    health: super::response_timeout::SessionHealth,
+    /// How much the session has been used, against its quota.
+    usage: SessionUsage,
 }
 
 impl Session {
@@ -492,6 +1854,14 @@ impl Session {
         let s = Self {
             key,
             pending_sends,
+            duplicates: atomic::AtomicU32::new(
+                pool.stages
+                    .duplicator
+                    .as_ref()
+                    .map_or(0, |duplicator| duplicator.count()),
+            ),
+            health: <_>::default(),
+            usage: <_>::default(),
             pool,
             socket_port,
             asn_info,
@@ -512,10 +1882,31 @@ impl Session {
 
         inner_metrics::total_sessions().inc();
         s.active_session_metric().inc();
+        if let Some(admission) = &s.pool.stages.admission {
+            admission.opened(key.dest);
+        }
         tracing::debug!(source = %key.source, dest = %key.dest, "Session created");
+        s.pool
+            .events
+            .emit(|| super::Event::session_established(key.source, key.dest));
         s
     }
 
+    /// Returns whether the packet being sent is among the first of the
+    /// session, and should be duplicated.
+    #[inline]
+    fn take_duplicate(&self) -> bool {
+        self.duplicates.load(atomic::Ordering::Relaxed) > 0
+            && self
+                .duplicates
+                .fetch_update(
+                    atomic::Ordering::Relaxed,
+                    atomic::Ordering::Relaxed,
+                    |left| left.checked_sub(1),
+                )
+                .is_ok()
+    }
+
     fn active_session_metric(&self) -> prometheus::IntGauge {
         inner_metrics::active_sessions(self.asn_info.as_ref())
     }
@@ -524,6 +1915,15 @@ impl Session {
         self.active_session_metric().dec();
         inner_metrics::duration_secs().observe(self.created_at.elapsed().as_secs() as f64);
         tracing::debug!(source = %self.key.source, dest_address = %self.key.dest, "Session closed");
+        self.pool.events.emit(|| {
+            super::Event::session_expired(self.key.source, self.key.dest, self.created_at.elapsed())
+        });
+        if self.health.is_flagged() {
+            self.pool.clear_unresponsive(self.key);
+        }
+        if let Some(admission) = &self.pool.stages.admission {
+            admission.closed(self.key.dest);
+        }
         SessionPool::release_socket(self.pool.clone(), self.key, self.socket_port);
     }
 }
@@ -535,7 +1935,9 @@ impl Drop for Session {
 }
 
 // A (source, destination) address pair that uniquely identifies a session.
-#[derive(Clone, Copy, Eq, Hash, PartialEq, Debug, PartialOrd, Ord)]
+#[derive(
+    Clone, Copy, Eq, Hash, PartialEq, Debug, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
 pub struct SessionKey {
     pub source: SocketAddr,
     pub dest: SocketAddr,
@@ -644,6 +2046,41 @@ mod tests {
         assert!(pool.drop_session(key2).await);
     }
 
+    #[tokio::test]
+    async fn ip_affinity_rebinds_sessions() {
+        let (pending_sends, _srecv) = PendingSends::new(1).unwrap();
+        let pool = SessionPool::with_settings(
+            Arc::new(Config::default_agent()),
+            vec![pending_sends],
+            Arc::new(BufferPool::default()),
+            SessionSettings {
+                affinity: crate::components::proxy::SessionAffinity::Ip,
+                ..<_>::default()
+            },
+        );
+        let dest: SocketAddr = (std::net::Ipv4Addr::UNSPECIFIED, 8080u16).into();
+        let key1 = ((std::net::Ipv4Addr::LOCALHOST, 8080u16).into(), dest).into();
+        let key2 = ((std::net::Ipv4Addr::LOCALHOST, 8081u16).into(), dest).into();
+
+        let _socket1 = pool.get(key1).unwrap();
+        let port = pool.session_map.get(&key1).unwrap().socket_port;
+
+        // The client's new port takes over its session and socket.
+        let _socket2 = pool.get(key2).unwrap();
+        assert!(pool.session_map.get(&key1).is_none());
+        assert_eq!(pool.session_map.get(&key2).unwrap().socket_port, port);
+        assert_eq!(
+            pool.storage
+                .read()
+                .destination_to_sources
+                .get(&(dest, port)),
+            Some(&key2.source)
+        );
+
+        assert!(pool.drop_session(key2).await);
+        assert!(pool.has_no_allocated_sockets());
+    }
+
     #[tokio::test]
     async fn different_addresses_uses_same_socket() {
         let (pool, _receiver) = new_pool().await;
@@ -667,6 +2104,166 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn bound_clusters_use_separate_sockets() {
+        let port = crate::net::socket_port(&crate::net::raw_socket_with_reuse(0).unwrap());
+        let bound: SocketAddr = (std::net::Ipv4Addr::LOCALHOST, 8081u16).into();
+
+        let config = Config::default_agent();
+        config.clusters.modify(|clusters| {
+            clusters.insert(
+                Some("us:east1".parse().unwrap()),
+                [crate::net::Endpoint::new(bound.into())].into(),
+            );
+        });
+        config.upstreams.store(Arc::new(vec![UpstreamBinding {
+            locality: Some("us:east1".parse().unwrap()),
+            address: Some(std::net::Ipv4Addr::LOCALHOST.into()),
+            ports: Some(crate::net::upstream::PortRange {
+                start: port,
+                end: port,
+            }),
+            ..<_>::default()
+        }]));
+
+        let (pending_sends, _srecv) = PendingSends::new(1).unwrap();
+        let pool = SessionPool::new(
+            Arc::new(config),
+            vec![pending_sends],
+            Arc::new(BufferPool::default()),
+        );
+
+        let key1 = (
+            (std::net::Ipv4Addr::LOCALHOST, 8080u16).into(),
+            (std::net::Ipv4Addr::LOCALHOST, 8080u16).into(),
+        )
+            .into();
+        let key2 = ((std::net::Ipv4Addr::LOCALHOST, 8080u16).into(), bound).into();
+
+        let _socket1 = pool.get(key1).unwrap();
+        let _socket2 = pool.get(key2).unwrap();
+
+        let port1 = pool.session_map.get(&key1).unwrap().socket_port;
+        let port2 = pool.session_map.get(&key2).unwrap().socket_port;
+        assert_ne!(port1, port2);
+        assert_eq!(port2, port);
+    }
+
+    #[tokio::test]
+    async fn keeps_spare_sockets() {
+        let endpoint: SocketAddr = (std::net::Ipv4Addr::LOCALHOST, 8080u16).into();
+        let config = Config::default_agent();
+        config.clusters.modify(|clusters| {
+            clusters.insert_default([crate::net::Endpoint::new(endpoint.into())].into())
+        });
+
+        let (pending_sends, _srecv) = PendingSends::new(1).unwrap();
+        let pool = SessionPool::with_settings(
+            Arc::new(config),
+            vec![pending_sends],
+            Arc::new(BufferPool::default()),
+            SessionSettings {
+                warm: crate::components::proxy::WarmSocketsConfig {
+                    spare: 2,
+                    probe: None,
+                },
+                ..<_>::default()
+            },
+        );
+
+        let clusters = pool.config.clusters.clone_value();
+        let known = pool.warm_sockets(&clusters, &<_>::default());
+        assert!(known.contains(&endpoint));
+        assert_eq!(pool.idle_sockets(&None).len(), 2);
+
+        // A new session takes a spare socket rather than creating one.
+        let key = ((std::net::Ipv4Addr::LOCALHOST, 9000u16).into(), endpoint).into();
+        let _socket = pool.get(key).unwrap();
+        assert_eq!(pool.ports_to_sockets.read().len(), 2);
+        assert_eq!(pool.idle_sockets(&None).len(), 1);
+
+        pool.warm_sockets(&clusters, &known);
+        assert_eq!(pool.idle_sockets(&None).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn draining_rejects_new_sessions() {
+        let (pool, downstream) = new_pool().await;
+        let existing = (
+            (std::net::Ipv4Addr::LOCALHOST, 8080u16).into(),
+            (std::net::Ipv4Addr::UNSPECIFIED, 8080u16).into(),
+        )
+            .into();
+        let new = (
+            (std::net::Ipv4Addr::LOCALHOST, 8081u16).into(),
+            (std::net::Ipv4Addr::UNSPECIFIED, 8080u16).into(),
+        )
+            .into();
+
+        let _session = pool.get(existing).unwrap();
+        pool.start_draining(Some(b"migrating"));
+
+        assert!(pool.is_draining());
+        assert!(pool.get(existing).is_ok());
+        // Existing sessions only last while they're in use.
+        assert_eq!(
+            pool.session_map
+                .peek(&existing)
+                .unwrap()
+                .ttl(Duration::from_secs(60)),
+            DRAIN_IDLE_TIMEOUT
+        );
+        assert!(matches!(
+            pool.get(new),
+            Err(super::super::PipelineError::Session(SessionError::Draining))
+        ));
+
+        let notifications = downstream.swap(Vec::new());
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(&*notifications[0].data, b"migrating");
+        assert_eq!(
+            notifications[0].destination.as_socket().unwrap(),
+            existing.source
+        );
+    }
+
+    #[tokio::test]
+    async fn only_builds_configured_stages() {
+        let (pool, _) = new_pool().await;
+        let stages = pool.stages();
+        assert!(stages.handshake.is_none());
+        assert!(stages.response_timeouts.is_none());
+        assert!(stages.ejections.is_none());
+        assert!(stages.admission.is_none());
+        assert!(stages.tombstones.is_none());
+        assert!(stages.duplicator.is_none());
+        assert!(stages.migrations.get().is_none());
+
+        pool.update_maintenance(time::OffsetDateTime::now_utc());
+        assert!(stages.maintenance().is_none());
+
+        let from = (std::net::Ipv4Addr::LOCALHOST, 8080).into();
+        let to = (std::net::Ipv4Addr::LOCALHOST, 8081).into();
+        assert_eq!(pool.migrate(from, to, None, None), 0);
+        assert!(stages.migrations.get().is_some());
+
+        let (pending_sends, _srecv) = PendingSends::new(1).unwrap();
+        let pool = SessionPool::with_settings(
+            Arc::new(Config::default_agent()),
+            vec![pending_sends],
+            Arc::new(BufferPool::default()),
+            SessionSettings {
+                ejection: super::super::EjectionConfig {
+                    enabled: true,
+                    ..<_>::default()
+                },
+                ..<_>::default()
+            },
+        );
+        assert!(pool.stages().ejections.is_some());
+        assert!(pool.stages().admission.is_none());
+    }
+
     #[tokio::test]
     async fn spawn_safe_same_destination() {
         let (pool, _receiver) = new_pool().await;
@@ -733,7 +2330,9 @@ mod tests {
         let key: SessionKey = (source, dest).into();
         let msg = b"helloworld";
 
-        let pending = pool.send_inner(key, alloc_buffer(msg).freeze()).unwrap();
+        let pending = pool
+            .send_inner(key, alloc_buffer(msg).freeze(), None, None, None)
+            .unwrap();
         let pending = pending.swap(Vec::new());
 
         assert_eq!(msg, &*pending[0].data);
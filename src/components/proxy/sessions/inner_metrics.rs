@@ -15,7 +15,7 @@
  */
 
 use once_cell::sync::Lazy;
-use prometheus::{Histogram, IntCounter, IntGauge, IntGaugeVec, Opts};
+use prometheus::{Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
 
 use crate::metrics::{histogram_opts, register};
 
@@ -67,6 +67,23 @@ pub(crate) fn total_sessions() -> &'static IntCounter {
     &TOTAL_SESSIONS
 }
 
+pub(crate) fn rebound_total() -> &'static IntCounter {
+    static REBOUND_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+        register(
+            IntCounter::with_opts(
+                Opts::new(
+                    "rebound_total",
+                    "total number of sessions taken over by another port of their client",
+                )
+                .subsystem(SUBSYSTEM),
+            )
+            .unwrap(),
+        )
+    });
+
+    &REBOUND_TOTAL
+}
+
 pub(crate) fn duration_secs() -> &'static Histogram {
     static DURATION_SECS: Lazy<Histogram> = Lazy::new(|| {
         register(
@@ -84,3 +101,54 @@ pub(crate) fn duration_secs() -> &'static Histogram {
 
     &DURATION_SECS
 }
+
+pub(crate) fn unresponsive() -> &'static IntGauge {
+    static UNRESPONSIVE: Lazy<IntGauge> = Lazy::new(|| {
+        register(
+            IntGauge::with_opts(
+                Opts::new(
+                    "unresponsive",
+                    "number of sessions currently without responses from their upstream",
+                )
+                .subsystem(SUBSYSTEM),
+            )
+            .unwrap(),
+        )
+    });
+
+    &UNRESPONSIVE
+}
+
+pub(crate) fn unresponsive_total() -> &'static IntCounter {
+    static UNRESPONSIVE_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+        register(
+            IntCounter::with_opts(
+                Opts::new(
+                    "unresponsive_total",
+                    "total number of sessions flagged as without responses from their upstream",
+                )
+                .subsystem(SUBSYSTEM),
+            )
+            .unwrap(),
+        )
+    });
+
+    &UNRESPONSIVE_TOTAL
+}
+
+pub(crate) fn quota_exceeded_total(limit: &str, action: &str) -> IntCounter {
+    static QUOTA_EXCEEDED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            Opts::new(
+                "quota_exceeded_total",
+                "total number of sessions that exceeded their quota",
+            )
+            .subsystem(SUBSYSTEM),
+            &["limit", "action"],
+            crate::metrics::registry(),
+        }
+        .unwrap()
+    });
+
+    QUOTA_EXCEEDED_TOTAL.with_label_values(&[limit, action])
+}
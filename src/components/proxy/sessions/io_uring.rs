@@ -24,11 +24,16 @@ impl super::SessionPool {
         self: Arc<Self>,
         raw_socket: socket2::Socket,
         port: u16,
+        downstream: Option<std::net::SocketAddr>,
         pending_sends: (proxy::PendingSends, proxy::io_uring_shared::EventFd),
     ) -> Result<(), proxy::PipelineError> {
         use proxy::io_uring_shared;
 
         let pool = self;
+        if pool.stages.ejections.is_some() {
+            crate::net::icmp_errors::enable(&raw_socket)?;
+        }
+
         let id = SESSION_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let _thread_span = uring_span!(tracing::debug_span!("session", id).or_current());
 
@@ -40,7 +45,11 @@ impl super::SessionPool {
 
         io_loop.spawn(
             format!("session-{id}"),
-            io_uring_shared::PacketProcessorCtx::SessionPool { pool, port },
+            io_uring_shared::PacketProcessorCtx::SessionPool {
+                pool,
+                port,
+                downstream,
+            },
             pending_sends,
             buffer_pool,
         )
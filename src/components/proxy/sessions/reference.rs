@@ -21,6 +21,7 @@ impl super::SessionPool {
         self: std::sync::Arc<Self>,
         raw_socket: socket2::Socket,
         port: u16,
+        downstream: Option<std::net::SocketAddr>,
         pending_sends: (proxy::PendingSends, proxy::PacketSendReceiver),
     ) -> Result<(), proxy::PipelineError> {
         let pool = self;
@@ -54,7 +55,9 @@ impl super::SessionPool {
                                 length = packet.data.len(),
                                 "sending packet upstream"
                             );
-                            let (result, _) = socket2.send_to(packet.data, destination).await;
+                            let (result, _) = socket2
+                                .send_to_marked(packet.data, destination, packet.dscp)
+                                .await;
                             let asn_info = packet.asn_info.as_ref().into();
                             match result {
                                 Ok(size) => {
@@ -96,7 +99,7 @@ impl super::SessionPool {
                                     tracing::trace!(%error, "error receiving packet");
                                     crate::metrics::errors_total(crate::metrics::WRITE, &error.to_string(), &crate::metrics::EMPTY).inc();
                                 },
-                                Ok((_size, recv_addr)) => pool.process_received_upstream_packet(buf, recv_addr, port, &mut last_received_at),
+                                Ok((_size, recv_addr)) => pool.process_received_upstream_packet(buf, recv_addr, port, downstream, &mut last_received_at),
                             }
                         }
                         _ = &mut rx => {
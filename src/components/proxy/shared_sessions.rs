@@ -0,0 +1,366 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Shares the handshake status of clients between proxies, so a client
+//! moved to another proxy by a load balancer doesn't have to handshake again.
+//!
+//! Only whether each client has completed its handshake is shared. Sessions
+//! themselves, their upstream sockets and any filter state, stay local to the
+//! proxy, and are created afresh by a proxy that's handed an established
+//! client, as they would be for a session that had expired.
+//!
+//! Each proxy keeps its own clients in memory as before, and writes through
+//! to an external store whenever a client becomes established. The first
+//! packet from a client a proxy hasn't seen looks the client up in the store
+//! in the background, while the client's packets count against its handshake
+//! budget as usual.
+
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+
+use once_cell::sync::Lazy;
+use tokio::sync::mpsc;
+
+use super::redis::{self, Redis};
+use crate::{
+    metrics,
+    net::circuit_breaker::{self, CircuitBreaker},
+};
+
+/// How long a client stays established in the store by default, after the
+/// last time it was written.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// The most lookups and writes waiting for the store, any more are dropped.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// The prefix of each client's key in Redis.
+const REDIS_PREFIX: &str = "quilkin:established:";
+
+/// How many calls are made to the store at once, one for each connection
+/// to Redis.
+const CONNECTIONS: usize = redis::CONNECTIONS;
+
+/// Stops calling the store for a while when it's down, rather than queueing
+/// every client behind it.
+static BREAKER: Lazy<CircuitBreaker> = Lazy::new(|| {
+    CircuitBreaker::new(
+        "shared_sessions",
+        circuit_breaker::Config {
+            max_concurrent: 256,
+            ..<_>::default()
+        },
+    )
+});
+
+/// Where established clients are shared between proxies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionStore {
+    /// A Redis server, at `host:port`.
+    Redis { address: String },
+    /// A DynamoDB table, whose partition key is the `source` string
+    /// attribute. The `expires_at` attribute can be used as the table's TTL
+    /// attribute.
+    #[cfg(feature = "aws")]
+    DynamoDb { table: String },
+}
+
+impl FromStr for SessionStore {
+    type Err = eyre::Error;
+
+    /// Parses `redis://host:port` or `dynamodb://table`.
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let (scheme, location) = url
+            .split_once("://")
+            .ok_or_else(|| eyre::eyre!("`{url}` isn't a URL"))?;
+        eyre::ensure!(!location.is_empty(), "`{url}` is missing its location");
+
+        match scheme {
+            "redis" => Ok(Self::Redis {
+                address: location.into(),
+            }),
+            #[cfg(feature = "aws")]
+            "dynamodb" => Ok(Self::DynamoDb {
+                table: location.into(),
+            }),
+            #[cfg(not(feature = "aws"))]
+            "dynamodb" => eyre::bail!("DynamoDB requires the `aws` feature"),
+            scheme => eyre::bail!("unsupported session store `{scheme}`"),
+        }
+    }
+}
+
+/// Whether established clients are shared with other proxies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedSessionsConfig {
+    /// The store clients are shared through, not shared when `None`.
+    pub store: Option<SessionStore>,
+    /// How long a client stays established in the store after it was last
+    /// written. Clients that are still established are written again
+    /// whenever half of it has passed.
+    pub ttl: Duration,
+}
+
+impl Default for SharedSessionsConfig {
+    fn default() -> Self {
+        Self {
+            store: None,
+            ttl: DEFAULT_TTL,
+        }
+    }
+}
+
+enum Operation {
+    Lookup(SocketAddr),
+    Publish(SocketAddr, bool),
+}
+
+impl Operation {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Lookup(_) => "lookup",
+            Self::Publish(..) => "publish",
+        }
+    }
+}
+
+/// The proxy's side of the store, queueing lookups and writes for a
+/// background task so they're never waited on by the packet path.
+pub(crate) struct SharedSessions {
+    operations: mpsc::Sender<Operation>,
+    ttl: Duration,
+}
+
+impl SharedSessions {
+    /// Starts sharing established clients as configured, calling
+    /// `established` with each client found established in the store.
+    /// Returns `None` if clients aren't shared.
+    pub(crate) fn spawn(
+        config: SharedSessionsConfig,
+        established: impl Fn(SocketAddr) + Send + Sync + 'static,
+    ) -> Option<Self> {
+        let store = config.store?;
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!("not sharing sessions outside of a runtime");
+            return None;
+        };
+
+        let (operations, mut rx) = mpsc::channel(QUEUE_CAPACITY);
+        let ttl = config.ttl;
+        runtime.spawn(async move {
+            use futures::StreamExt;
+
+            let backend = Backend::new(store).await;
+            let (backend, established) = (&backend, &established);
+            tokio_stream::wrappers::ReceiverStream::new(rx)
+                .for_each_concurrent(CONNECTIONS, |operation| async move {
+                    let label = operation.label();
+                    let result = BREAKER
+                        .call(|| backend.execute(&operation, ttl))
+                        .await
+                        .map_err(|error| {
+                            tracing::debug!(%error, operation = label, "session store call failed");
+                        });
+
+                    let result = match (&operation, result) {
+                        (Operation::Lookup(source), Ok(true)) => {
+                            established(*source);
+                            "hit"
+                        }
+                        (Operation::Lookup(_), Ok(false)) => "miss",
+                        (Operation::Publish(..), Ok(_)) => "ok",
+                        (_, Err(())) => "error",
+                    };
+                    metrics::shared_sessions_total(label, result).inc();
+                })
+                .await;
+        });
+
+        Some(Self { operations, ttl })
+    }
+
+    /// How often clients that are still established are written again.
+    #[inline]
+    pub(crate) fn refresh_interval(&self) -> Duration {
+        self.ttl / 2
+    }
+
+    /// Looks up whether `source` was established by another proxy.
+    #[inline]
+    pub(crate) fn lookup(&self, source: SocketAddr) {
+        self.enqueue(Operation::Lookup(source));
+    }
+
+    /// Writes whether `source` is established, returning whether it was
+    /// queued to be written.
+    #[inline]
+    pub(crate) fn publish(&self, source: SocketAddr, established: bool) -> bool {
+        self.enqueue(Operation::Publish(source, established))
+    }
+
+    fn enqueue(&self, operation: Operation) -> bool {
+        let label = operation.label();
+        let queued = self.operations.try_send(operation).is_ok();
+        if !queued {
+            metrics::shared_sessions_total(label, "dropped").inc();
+        }
+        queued
+    }
+}
+
+/// Checks that `store` can be read, by looking up a client that's never
+/// established.
+pub(crate) async fn probe(store: SessionStore) -> eyre::Result<()> {
+    let unspecified = (std::net::Ipv6Addr::UNSPECIFIED, 0).into();
+    Backend::new(store)
+        .await
+        .execute(&Operation::Lookup(unspecified), DEFAULT_TTL)
+        .await
+        .map(drop)
+}
+
+enum Backend {
+    Redis(Redis),
+    #[cfg(feature = "aws")]
+    DynamoDb(DynamoDb),
+}
+
+impl Backend {
+    async fn new(store: SessionStore) -> Self {
+        match store {
+            SessionStore::Redis { address } => Self::Redis(Redis::new(address)),
+            #[cfg(feature = "aws")]
+            SessionStore::DynamoDb { table } => Self::DynamoDb(DynamoDb::new(table).await),
+        }
+    }
+
+    /// Returns whether the client was found established, always `false`
+    /// for writes.
+    async fn execute(&self, operation: &Operation, ttl: Duration) -> eyre::Result<bool> {
+        match (self, operation) {
+            (Self::Redis(redis), Operation::Lookup(source)) => {
+                Ok(redis.read(&redis_key(*source)).await?.is_some())
+            }
+            (Self::Redis(redis), Operation::Publish(source, true)) => redis
+                .write_expiring(&redis_key(*source), b"1", ttl)
+                .await
+                .map(|()| false),
+            (Self::Redis(redis), Operation::Publish(source, false)) => {
+                redis.delete(&redis_key(*source)).await.map(|()| false)
+            }
+            #[cfg(feature = "aws")]
+            (Self::DynamoDb(dynamo), Operation::Lookup(source)) => dynamo.get(*source).await,
+            #[cfg(feature = "aws")]
+            (Self::DynamoDb(dynamo), Operation::Publish(source, established)) => {
+                dynamo.set(*source, *established, ttl).await.map(|()| false)
+            }
+        }
+    }
+}
+
+/// The key `source`'s handshake status is written to in Redis.
+fn redis_key(source: SocketAddr) -> String {
+    format!("{REDIS_PREFIX}{source}")
+}
+
+#[cfg(feature = "aws")]
+struct DynamoDb {
+    client: aws_sdk_dynamodb::Client,
+    table: String,
+}
+
+#[cfg(feature = "aws")]
+impl DynamoDb {
+    async fn new(table: String) -> Self {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Self {
+            client: aws_sdk_dynamodb::Client::new(&config),
+            table,
+        }
+    }
+
+    fn key(source: SocketAddr) -> aws_sdk_dynamodb::types::AttributeValue {
+        aws_sdk_dynamodb::types::AttributeValue::S(source.to_string())
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    async fn get(&self, source: SocketAddr) -> eyre::Result<bool> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key("source", Self::key(source))
+            .send()
+            .await?;
+
+        // DynamoDB only removes expired items eventually, so they're checked
+        // here as well.
+        let expires_at = output
+            .item()
+            .and_then(|item| item.get("expires_at"))
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        Ok(expires_at.is_some_and(|expires_at| expires_at > Self::now()))
+    }
+
+    async fn set(&self, source: SocketAddr, established: bool, ttl: Duration) -> eyre::Result<()> {
+        if established {
+            let expires_at = Self::now() + ttl.as_secs().max(1);
+            self.client
+                .put_item()
+                .table_name(&self.table)
+                .item("source", Self::key(source))
+                .item(
+                    "expires_at",
+                    aws_sdk_dynamodb::types::AttributeValue::N(expires_at.to_string()),
+                )
+                .send()
+                .await?;
+        } else {
+            self.client
+                .delete_item()
+                .table_name(&self.table)
+                .key("source", Self::key(source))
+                .send()
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_store() {
+        assert_eq!(
+            "redis://localhost:6379".parse::<SessionStore>().unwrap(),
+            SessionStore::Redis {
+                address: "localhost:6379".into()
+            }
+        );
+        assert!("redis://".parse::<SessionStore>().is_err());
+        assert!("localhost:6379".parse::<SessionStore>().is_err());
+        assert!("memcached://localhost".parse::<SessionStore>().is_err());
+    }
+}
@@ -0,0 +1,63 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The optional stages a [`SessionPool`](super::SessionPool) runs packets
+//! through on their way to and from sessions.
+//!
+//! Each stage is only built when it's configured, and packets only go
+//! through the stages that were built, so the packet path doesn't pay for
+//! features that aren't in use. Migrations and maintenance windows start at
+//! runtime instead, so they're only checked once there are any.
+
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+
+use super::{
+    admission::Admission, duplicate::Duplicator, ejection::Ejections, handshake::HandshakeGate,
+    migration::Migrations, response_timeout::ResponseTimeouts, tombstone::Tombstones,
+};
+use crate::net::maintenance::ActiveMaintenance;
+
+/// The stages of a [`SessionPool`](super::SessionPool), `None` for those that
+/// aren't configured.
+pub(crate) struct Stages {
+    /// Holds back packets from clients that aren't established.
+    pub(crate) handshake: Option<HandshakeGate>,
+    /// Flags sessions without responses from their upstream.
+    pub(crate) response_timeouts: Option<ResponseTimeouts>,
+    /// Stops sending packets to upstreams reported unreachable.
+    pub(crate) ejections: Option<Ejections>,
+    /// Rejects new sessions to upstreams at their advertised capacity.
+    pub(crate) admission: Option<Admission>,
+    /// Remembers endpoints removed from the config.
+    pub(crate) tombstones: Option<Tombstones>,
+    /// Sends the first packets of each session twice.
+    pub(crate) duplicator: Option<Duplicator>,
+    /// Built by the first migration.
+    pub(crate) migrations: OnceCell<Migrations>,
+    /// The endpoints drained by the maintenance windows in effect, `None`
+    /// while there are none.
+    pub(crate) maintenance: arc_swap::ArcSwapOption<ActiveMaintenance>,
+}
+
+impl Stages {
+    /// The endpoints drained by the maintenance windows in effect, if any.
+    #[inline]
+    pub(crate) fn maintenance(&self) -> arc_swap::Guard<Option<Arc<ActiveMaintenance>>> {
+        self.maintenance.load()
+    }
+}
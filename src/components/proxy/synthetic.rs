@@ -0,0 +1,353 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Synthetic probes, run periodically through the proxy's filters to a canary
+//! endpoint and back, so whether packets are actually routed is monitored
+//! rather than only whether the proxy is running.
+//!
+//! Each probe is sent from a loopback source, and marked with the
+//! [`quilkin.dev/synthetic`](crate::net::synthetic) dynamic metadata key.
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use prometheus::{IntCounterVec, IntGauge};
+use tokio::net::UdpSocket;
+
+use crate::{
+    filters::{Filter as _, ReadContext, WriteContext},
+    metrics::registry,
+    pool::{BufferPool, PoolBuffer},
+};
+
+/// How often a probe is run by default.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the canary has to reply to each payload by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether synthetic probes are run, and what they send.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntheticProbeConfig {
+    /// The payloads of each probe, run through the filters in order as one
+    /// client would send them, such as a handshake followed by data.
+    pub payloads: Vec<Vec<u8>>,
+    /// The endpoint each payload must be routed to, and which must reply to
+    /// it, no probes are run when unset.
+    pub canary: Option<SocketAddr>,
+    /// How often a probe is run.
+    pub interval: Duration,
+    /// How long the canary has to reply to each payload.
+    pub timeout: Duration,
+}
+
+impl Default for SyntheticProbeConfig {
+    fn default() -> Self {
+        Self {
+            payloads: Vec::new(),
+            canary: None,
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// How a probe went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// Each payload was routed to the canary, and its reply made it back
+    /// through the filters.
+    Success,
+    /// A filter dropped a payload or its reply.
+    Dropped,
+    /// A payload wasn't routed to the canary.
+    NotRouted,
+    /// The canary didn't reply to a payload in time.
+    NoReply,
+}
+
+impl Outcome {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Dropped => "dropped",
+            Self::NotRouted => "not_routed",
+            Self::NoReply => "no_reply",
+        }
+    }
+}
+
+/// Runs probes through the filters of a proxy's config.
+pub(crate) struct Prober {
+    config: SyntheticProbeConfig,
+    canary: SocketAddr,
+    proxy: Arc<crate::Config>,
+    socket: UdpSocket,
+    /// The loopback address probes are sent from, as far as the filters are
+    /// concerned.
+    source: SocketAddr,
+    buffer_pool: Arc<BufferPool>,
+}
+
+impl Prober {
+    /// Binds the socket probes are sent to `canary` from.
+    pub(crate) async fn bind(
+        config: SyntheticProbeConfig,
+        canary: SocketAddr,
+        proxy: Arc<crate::Config>,
+    ) -> std::io::Result<Self> {
+        let unspecified: SocketAddr = if canary.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(unspecified).await?;
+        let source = (Ipv4Addr::LOCALHOST, socket.local_addr()?.port()).into();
+
+        Ok(Self {
+            config,
+            canary,
+            proxy,
+            socket,
+            source,
+            buffer_pool: Arc::new(BufferPool::new(1, 2 * 1024)),
+        })
+    }
+
+    /// Runs each payload through the filters to the canary, and its reply
+    /// back through them.
+    pub(crate) async fn probe(&self) -> Outcome {
+        for payload in &self.config.payloads {
+            // A filter may answer a payload itself, such as a health check.
+            let Some(contents) = (match self.route(payload) {
+                Ok(contents) => contents,
+                Err(outcome) => return outcome,
+            }) else {
+                continue;
+            };
+
+            if let Err(error) = self.socket.send_to(&contents, self.canary).await {
+                tracing::debug!(%error, canary = %self.canary, "failed to send synthetic probe");
+                return Outcome::NoReply;
+            }
+
+            let Ok(reply) = tokio::time::timeout(self.config.timeout, self.receive()).await else {
+                return Outcome::NoReply;
+            };
+            let mut ctx = WriteContext::new(
+                self.canary.into(),
+                self.source.into(),
+                self.buffer_pool.clone().alloc_slice(&reply),
+            );
+            if self.proxy.filters.load().write(&mut ctx).is_err() {
+                return Outcome::Dropped;
+            }
+        }
+
+        Outcome::Success
+    }
+
+    /// Runs `payload` through the read filters, returning its contents as
+    /// they'd be sent to the canary, or `None` when a filter replied to it.
+    fn route(&self, payload: &[u8]) -> Result<Option<PoolBuffer>, Outcome> {
+        let mut destinations = Vec::new();
+        let mut ctx = ReadContext::new(
+            self.proxy.clusters.clone_value(),
+            self.source.into(),
+            self.buffer_pool.clone().alloc_slice(payload),
+            &mut destinations,
+        );
+        crate::net::synthetic::set(&mut ctx.metadata);
+
+        if self.proxy.filters.load().read(&mut ctx).is_err() {
+            return Err(Outcome::Dropped);
+        }
+        if ctx.reply.is_some() {
+            return Ok(None);
+        }
+        if !crate::net::selected_endpoint::apply(&ctx.metadata, &ctx.endpoints, ctx.destinations) {
+            return Err(Outcome::NotRouted);
+        }
+
+        let routed = ctx
+            .destinations
+            .iter()
+            .any(|address| address.to_socket_addr().ok() == Some(self.canary));
+        if !routed {
+            return Err(Outcome::NotRouted);
+        }
+
+        Ok(Some(ctx.contents))
+    }
+
+    /// Receives the next packet from the canary, ignoring any others.
+    async fn receive(&self) -> Vec<u8> {
+        let mut buf = vec![0; usize::from(u16::MAX)];
+        loop {
+            match self.socket.recv_from(&mut buf).await {
+                Ok((len, from)) if from == self.canary => {
+                    buf.truncate(len);
+                    return buf;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    tracing::debug!(%error, "failed to receive synthetic probe reply");
+                }
+            }
+        }
+    }
+}
+
+/// Spawns a task running a probe every interval until shutdown, if a canary
+/// and payloads are set.
+pub(crate) fn spawn(
+    config: SyntheticProbeConfig,
+    proxy: Arc<crate::Config>,
+    mut shutdown_rx: crate::ShutdownRx,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let canary = config.canary.filter(|_| !config.payloads.is_empty())?;
+    Some(tokio::spawn(async move {
+        let prober = match Prober::bind(config, canary, proxy).await {
+            Ok(prober) => prober,
+            Err(error) => {
+                tracing::warn!(%error, "failed to bind synthetic probe socket, not probing");
+                return;
+            }
+        };
+
+        let mut interval = tokio::time::interval(prober.config.interval);
+        let mut last = Outcome::Success;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown_rx.changed() => return,
+            }
+
+            let outcome = prober.probe().await;
+            probes_total(outcome).inc();
+            healthy_gauge().set((outcome == Outcome::Success).into());
+            match (last, outcome) {
+                (Outcome::Success, Outcome::Success) => {}
+                (_, Outcome::Success) => {
+                    tracing::info!(%canary, "synthetic probes succeeding again")
+                }
+                (_, outcome) => crate::log_throttle::throttled!(warn!(
+                    %canary,
+                    result = outcome.label(),
+                    "synthetic probe failed"
+                )),
+            }
+            last = outcome;
+        }
+    }))
+}
+
+fn probes_total(outcome: Outcome) -> prometheus::IntCounter {
+    static PROBES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "synthetic_probes_total",
+                "Total number of synthetic probes run through the filters, by result",
+            },
+            &["result"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PROBES_TOTAL.with_label_values(&[outcome.label()])
+}
+
+fn healthy_gauge() -> &'static IntGauge {
+    static HEALTHY: Lazy<IntGauge> = Lazy::new(|| {
+        prometheus::register_int_gauge_with_registry! {
+            prometheus::opts! {
+                "synthetic_probe_healthy",
+                "Whether the last synthetic probe was routed to the canary and back",
+            },
+            registry(),
+        }
+        .unwrap()
+    });
+
+    &HEALTHY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::{FilterChain, StaticFilter as _};
+
+    async fn prober(canary: SocketAddr, endpoint: SocketAddr) -> Prober {
+        let proxy = crate::Config::default_non_agent();
+        proxy
+            .clusters
+            .modify(|clusters| clusters.insert_default([endpoint.into()].into()));
+
+        Prober::bind(
+            SyntheticProbeConfig {
+                payloads: vec![b"hello".to_vec(), b"world".to_vec()],
+                canary: Some(canary),
+                timeout: Duration::from_millis(100),
+                ..<_>::default()
+            },
+            canary,
+            Arc::new(proxy),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn probes_through_filters() {
+        let canary = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let canary_addr = canary.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            while let Ok((len, from)) = canary.recv_from(&mut buf).await {
+                canary.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+
+        let prober = prober(canary_addr, canary_addr).await;
+        assert_eq!(prober.probe().await, Outcome::Success);
+
+        prober.proxy.filters.store(Arc::new(
+            FilterChain::try_create([crate::filters::Drop::as_filter_config(None).unwrap()])
+                .unwrap(),
+        ));
+        assert_eq!(prober.probe().await, Outcome::Dropped);
+
+        let elsewhere = (Ipv4Addr::LOCALHOST, 1).into();
+        assert_eq!(
+            prober(canary_addr, elsewhere).await.probe().await,
+            Outcome::NotRouted
+        );
+    }
+
+    #[tokio::test]
+    async fn canary_must_reply() {
+        let canary = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let canary_addr = canary.local_addr().unwrap();
+
+        let prober = prober(canary_addr, canary_addr).await;
+        assert_eq!(prober.probe().await, Outcome::NoReply);
+    }
+}
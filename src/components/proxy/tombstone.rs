@@ -0,0 +1,235 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::net::{endpoint::AddressKind, ClusterMap};
+
+/// Whether endpoints removed from the config are remembered for a while, so
+/// their clients' packets are dropped with a reason of their own rather
+/// than silently.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TombstoneConfig {
+    /// How long a removed endpoint is remembered for, disabled when unset.
+    pub window: Option<Duration>,
+    /// A payload sent back to each client of a removed endpoint, once, so it
+    /// can reconnect rather than waiting to time out.
+    pub notification: Option<Vec<u8>>,
+}
+
+/// A removed endpoint.
+struct Tombstone {
+    expires: Instant,
+    /// The clients already sent the notification.
+    notified: HashSet<SocketAddr>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The endpoints in the config as of the last update.
+    known: HashSet<SocketAddr>,
+    removed: HashMap<SocketAddr, Tombstone>,
+}
+
+/// The endpoints removed from the config within the tombstone window.
+pub(crate) struct Tombstones {
+    window: Duration,
+    notification: Option<Vec<u8>>,
+    state: Mutex<State>,
+    /// Whether any endpoint has a tombstone, so packets only take the lock
+    /// when one might.
+    any: AtomicBool,
+}
+
+impl Tombstones {
+    /// Returns `None` if removed endpoints aren't remembered.
+    pub(crate) fn new(config: TombstoneConfig) -> Option<Self> {
+        Some(Self {
+            window: config.window?,
+            notification: config.notification,
+            state: <_>::default(),
+            any: AtomicBool::new(false),
+        })
+    }
+
+    /// Compares the endpoints in `clusters` with those of the last update,
+    /// adding a tombstone for each endpoint that was removed, and removing
+    /// the tombstones of endpoints that were added back or have expired.
+    pub(crate) fn update(&self, clusters: &ClusterMap, now: Instant) {
+        let window = self.window;
+        let current: HashSet<SocketAddr> = clusters
+            .iter()
+            .flat_map(|cluster| {
+                cluster
+                    .value()
+                    .endpoints
+                    .iter()
+                    .filter_map(|ep| match ep.address.host {
+                        AddressKind::Ip(ip) => Some((ip, ep.address.port).into()),
+                        AddressKind::Name(_) => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut state = self.state.lock();
+        let State { known, removed } = &mut *state;
+        for &endpoint in known.difference(&current) {
+            tracing::info!(%endpoint, ?window, "endpoint removed, keeping its tombstone");
+            removed.insert(
+                endpoint,
+                Tombstone {
+                    expires: now + window,
+                    notified: HashSet::new(),
+                },
+            );
+        }
+        removed
+            .retain(|endpoint, tombstone| tombstone.expires > now && !current.contains(endpoint));
+        *known = current;
+
+        self.any.store(!removed.is_empty(), Ordering::Relaxed);
+    }
+
+    /// Returns whether `endpoint` has been removed within the window.
+    #[inline]
+    pub(crate) fn is_removed(&self, endpoint: SocketAddr, now: Instant) -> bool {
+        self.any.load(Ordering::Relaxed)
+            && self
+                .state
+                .lock()
+                .removed
+                .get(&endpoint)
+                .is_some_and(|tombstone| tombstone.expires > now)
+    }
+
+    /// Returns a removed endpoint that a client has a session with, going by
+    /// `has_session`, if any.
+    pub(crate) fn removed_session(
+        &self,
+        now: Instant,
+        has_session: impl Fn(SocketAddr) -> bool,
+    ) -> Option<SocketAddr> {
+        if !self.any.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        self.state
+            .lock()
+            .removed
+            .iter()
+            .find(|(endpoint, tombstone)| tombstone.expires > now && has_session(**endpoint))
+            .map(|(endpoint, _)| *endpoint)
+    }
+
+    /// Returns the notification to send `client` about `endpoint` being
+    /// removed, if it hasn't already been sent it.
+    pub(crate) fn notification(&self, client: SocketAddr, endpoint: SocketAddr) -> Option<&[u8]> {
+        let notification = self.notification.as_deref()?;
+        self.state
+            .lock()
+            .removed
+            .get_mut(&endpoint)?
+            .notified
+            .insert(client)
+            .then_some(notification)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::net::endpoint::Endpoint;
+
+    fn address(port: u16) -> SocketAddr {
+        (std::net::Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    fn clusters(ports: &[u16]) -> ClusterMap {
+        let clusters = ClusterMap::default();
+        clusters.insert_default(
+            ports
+                .iter()
+                .map(|port| Endpoint::new(address(*port).into()))
+                .collect::<BTreeSet<_>>(),
+        );
+        clusters
+    }
+
+    #[test]
+    fn remembers_removed_endpoints() {
+        let tombstones = Tombstones::new(TombstoneConfig {
+            window: Some(Duration::from_secs(30)),
+            notification: Some(b"reconnect".to_vec()),
+        })
+        .unwrap();
+        let now = Instant::now();
+        let client = address(9000);
+
+        tombstones.update(&clusters(&[7001, 7002]), now);
+        assert!(!tombstones.is_removed(address(7001), now));
+
+        tombstones.update(&clusters(&[7002]), now);
+        assert!(tombstones.is_removed(address(7001), now));
+        assert!(!tombstones.is_removed(address(7002), now));
+        assert_eq!(
+            tombstones.removed_session(now, |endpoint| endpoint == address(7001)),
+            Some(address(7001))
+        );
+        assert_eq!(tombstones.removed_session(now, |_| false), None);
+
+        // The notification is only sent once to each client.
+        assert_eq!(
+            tombstones.notification(client, address(7001)),
+            Some(&b"reconnect"[..])
+        );
+        assert_eq!(tombstones.notification(client, address(7001)), None);
+        assert_eq!(tombstones.notification(client, address(7002)), None);
+
+        // Expired after the window.
+        let later = now + Duration::from_secs(31);
+        assert!(!tombstones.is_removed(address(7001), later));
+        tombstones.update(&clusters(&[7002]), later);
+        assert!(tombstones.state.lock().removed.is_empty());
+    }
+
+    #[test]
+    fn forgets_endpoints_added_back() {
+        let tombstones = Tombstones::new(TombstoneConfig {
+            window: Some(Duration::from_secs(30)),
+            notification: None,
+        })
+        .unwrap();
+        let now = Instant::now();
+
+        tombstones.update(&clusters(&[7001]), now);
+        tombstones.update(&clusters(&[]), now);
+        assert!(tombstones.is_removed(address(7001), now));
+        assert_eq!(tombstones.notification(address(9000), address(7001)), None);
+
+        tombstones.update(&clusters(&[7001]), now);
+        assert!(!tombstones.is_removed(address(7001), now));
+    }
+}
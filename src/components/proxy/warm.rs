@@ -0,0 +1,149 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Creates upstream sockets before the sessions that will use them, so the
+//! first packet of a new session doesn't wait for its socket to be created
+//! and its IO loop spawned.
+
+use std::{collections::HashSet, net::SocketAddr};
+
+use crate::net::{endpoint::AddressKind, upstream::UpstreamBinding, ClusterMap};
+
+/// Whether upstream sockets are created before sessions need them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WarmSocketsConfig {
+    /// How many sockets no session is using are kept ready for each way the
+    /// sockets to the endpoints are bound, none when zero.
+    pub spare: usize,
+    /// A payload sent to each endpoint added to the config from one of the
+    /// spare sockets, such as to open the path to it through firewalls.
+    pub probe: Option<Vec<u8>>,
+}
+
+impl WarmSocketsConfig {
+    #[inline]
+    pub(crate) fn enabled(&self) -> bool {
+        self.spare > 0
+    }
+}
+
+/// Returns each way the sockets of sessions to the endpoints of `clusters`
+/// are bound, with `None` for unbound sockets, as
+/// [`find_binding`](crate::net::upstream::find_binding) would bind them.
+/// Bindings with an address pool are bound once for each of its addresses.
+pub(crate) fn bindings(
+    upstreams: &[UpstreamBinding],
+    clusters: &ClusterMap,
+) -> Vec<Option<UpstreamBinding>> {
+    let mut bindings = Vec::new();
+    for cluster in clusters.iter() {
+        if cluster.value().endpoints.is_empty() {
+            continue;
+        }
+
+        let binding = upstreams
+            .iter()
+            .find(|binding| binding.locality == *cluster.key());
+        let each = match binding {
+            None => vec![None],
+            Some(binding) if binding.address_pool.is_empty() => vec![Some(binding.clone())],
+            Some(binding) => binding
+                .address_pool
+                .iter()
+                .map(|address| {
+                    Some(UpstreamBinding {
+                        address: Some(*address),
+                        address_pool: Vec::new(),
+                        ..binding.clone()
+                    })
+                })
+                .collect(),
+        };
+
+        for binding in each {
+            if !bindings.contains(&binding) {
+                bindings.push(binding);
+            }
+        }
+    }
+
+    bindings
+}
+
+/// Returns the addresses of the endpoints in `clusters`, endpoints with a
+/// hostname are skipped as packets are never sent to them.
+pub(crate) fn endpoints(clusters: &ClusterMap) -> HashSet<SocketAddr> {
+    clusters
+        .iter()
+        .flat_map(|cluster| {
+            cluster
+                .value()
+                .endpoints
+                .iter()
+                .filter_map(|ep| match ep.address.host {
+                    AddressKind::Ip(ip) => Some((ip, ep.address.port).into()),
+                    AddressKind::Name(_) => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, net::IpAddr};
+
+    use super::*;
+    use crate::net::endpoint::{Endpoint, Locality};
+
+    #[test]
+    fn finds_each_binding() {
+        let clusters = ClusterMap::default();
+        let endpoint = |port| Endpoint::new((std::net::Ipv4Addr::LOCALHOST, port).into());
+        let eu: Locality = "eu:west1:a".parse().unwrap();
+        let us: Locality = "us:east1:a".parse().unwrap();
+        clusters.insert_default([endpoint(7000)].into());
+        clusters.insert(Some(eu.clone()), [endpoint(7001)].into());
+        // Clusters without endpoints have no sessions to bind.
+        clusters.insert(Some(us.clone()), BTreeSet::new());
+
+        let pool: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let upstreams = [
+            UpstreamBinding {
+                locality: Some(eu.clone()),
+                address_pool: pool.clone(),
+                ..<_>::default()
+            },
+            UpstreamBinding {
+                locality: Some(us),
+                ..<_>::default()
+            },
+        ];
+
+        let found = bindings(&upstreams, &clusters);
+        assert_eq!(found.len(), 3);
+        assert!(found.contains(&None));
+        for address in pool {
+            assert!(found.contains(&Some(UpstreamBinding {
+                locality: Some(eu.clone()),
+                address: Some(address),
+                ..<_>::default()
+            })));
+        }
+
+        assert_eq!(endpoints(&clusters).len(), 2);
+    }
+}
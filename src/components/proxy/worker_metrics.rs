@@ -0,0 +1,223 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Samples the state of the async runtime and of each worker's socket, so a
+//! single saturated worker can be told apart from the whole proxy running
+//! out of CPU.
+//!
+//! Packets dropped by a worker's socket were received by the kernel but
+//! never read, so when they start being dropped it's logged, telling the
+//! proxy being too slow apart from packets lost on the network.
+
+use std::time::Duration;
+
+use crate::metrics;
+
+/// How often the runtime and sockets are sampled.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// The inode of each worker's socket, identifying it in `/proc/net/udp6`.
+#[cfg(target_os = "linux")]
+static SOCKETS: once_cell::sync::Lazy<parking_lot::Mutex<Vec<(usize, u64)>>> =
+    once_cell::sync::Lazy::new(<_>::default);
+
+/// Registers the socket `fd` of `worker`, so its backlog is sampled.
+#[cfg(target_os = "linux")]
+pub(crate) fn register_socket(worker: usize, fd: std::os::fd::RawFd) {
+    use std::os::unix::fs::MetadataExt;
+
+    match std::fs::metadata(format!("/proc/self/fd/{fd}")) {
+        Ok(metadata) => SOCKETS.lock().push((worker, metadata.ino())),
+        Err(error) => tracing::debug!(%error, worker, "failed to find inode of worker socket"),
+    }
+}
+
+/// Spawns a task sampling the runtime and worker sockets for as long as the
+/// proxy runs.
+pub(crate) fn spawn() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(INTERVAL);
+        #[cfg(target_os = "linux")]
+        let mut drops = DropTracker::default();
+        loop {
+            interval.tick().await;
+            sample_runtime();
+            #[cfg(target_os = "linux")]
+            sample_sockets(&mut drops);
+        }
+    });
+}
+
+fn sample_runtime() {
+    let runtime = tokio::runtime::Handle::current().metrics();
+    let workers = runtime.num_workers();
+    metrics::runtime_workers().set(workers as i64);
+    metrics::runtime_alive_tasks().set(runtime.num_alive_tasks() as i64);
+    metrics::runtime_global_queue_depth().set(runtime.global_queue_depth() as i64);
+
+    for worker in 0..workers {
+        metrics::runtime_worker_busy_seconds(worker)
+            .set(runtime.worker_total_busy_duration(worker).as_secs_f64());
+        metrics::runtime_worker_parks(worker).set(runtime.worker_park_count(worker) as i64);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sample_sockets(drops: &mut DropTracker) {
+    let sockets = SOCKETS.lock().clone();
+    if sockets.is_empty() {
+        return;
+    }
+
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let Ok(table) = std::fs::read_to_string(table) else {
+            continue;
+        };
+
+        for socket in table.lines().skip(1).filter_map(SocketStats::parse) {
+            for &(worker, _) in sockets.iter().filter(|(_, inode)| *inode == socket.inode) {
+                metrics::worker_socket_backlog_bytes(worker).set(socket.backlog as i64);
+                metrics::worker_socket_drops(worker).set(socket.drops as i64);
+
+                match drops.observe(socket.inode, socket.drops) {
+                    Some(DropChange::Began(dropped)) => crate::log_throttle::throttled!(warn!(
+                        worker,
+                        dropped,
+                        backlog_bytes = socket.backlog,
+                        "worker socket started dropping packets, they're arriving faster than \
+                        the worker reads them"
+                    )),
+                    Some(DropChange::Ended(dropped)) => {
+                        tracing::info!(worker, dropped, "worker socket stopped dropping packets");
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+/// A change in whether a socket is dropping packets, with the packets it
+/// dropped.
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+enum DropChange {
+    /// The socket dropped packets since the last sample, after not dropping
+    /// any.
+    Began(u64),
+    /// The socket dropped no packets since the last sample, after dropping
+    /// this many in all since it began.
+    Ended(u64),
+}
+
+/// Tracks when each socket starts and stops dropping packets.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct DropTracker {
+    /// The drops of each socket by inode as of the last sample, and the
+    /// packets dropped since it began dropping, if it is.
+    sockets: std::collections::HashMap<u64, (u64, Option<u64>)>,
+}
+
+#[cfg(target_os = "linux")]
+impl DropTracker {
+    /// Records the drops of the socket `inode` since it was opened.
+    fn observe(&mut self, inode: u64, drops: u64) -> Option<DropChange> {
+        let Some((last, dropping)) = self.sockets.get_mut(&inode) else {
+            // Packets dropped before the first sample aren't news.
+            self.sockets.insert(inode, (drops, None));
+            return None;
+        };
+
+        let dropped = drops.saturating_sub(*last);
+        *last = drops;
+        if dropped == 0 {
+            return dropping.take().map(DropChange::Ended);
+        }
+
+        match dropping {
+            Some(total) => {
+                *total += dropped;
+                None
+            }
+            None => {
+                *dropping = Some(dropped);
+                Some(DropChange::Began(dropped))
+            }
+        }
+    }
+}
+
+/// A socket's line of `/proc/net/udp` or `/proc/net/udp6`.
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+struct SocketStats {
+    inode: u64,
+    /// The bytes waiting to be read.
+    backlog: u64,
+    /// The packets dropped since the socket was opened.
+    drops: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl SocketStats {
+    /// Parses a line of the table, whose columns are `sl local_address
+    /// rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout
+    /// inode ref pointer drops`.
+    fn parse(line: &str) -> Option<Self> {
+        let columns: Vec<_> = line.split_whitespace().collect();
+        let (_, backlog) = columns.get(4)?.split_once(':')?;
+
+        Some(Self {
+            inode: columns.get(9)?.parse().ok()?,
+            backlog: u64::from_str_radix(backlog, 16).ok()?,
+            drops: columns.get(12)?.parse().ok()?,
+        })
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_socket_stats() {
+        let line = "  391: 00000000000000000000000000000000:1E61 \
+            00000000000000000000000000000000:0000 07 00000000:00000A40 00:00000000 00000000 \
+            1000        0 5432101 2 0000000000000000 17";
+        assert_eq!(
+            SocketStats::parse(line),
+            Some(SocketStats {
+                inode: 5432101,
+                backlog: 0xA40,
+                drops: 17,
+            })
+        );
+        assert_eq!(SocketStats::parse("  sl  local_address rem_address"), None);
+    }
+
+    #[test]
+    fn tracks_drop_episodes() {
+        let mut drops = DropTracker::default();
+        assert_eq!(drops.observe(1, 10), None);
+        assert_eq!(drops.observe(1, 10), None);
+        assert_eq!(drops.observe(1, 15), Some(DropChange::Began(5)));
+        assert_eq!(drops.observe(1, 20), None);
+        assert_eq!(drops.observe(2, 0), None);
+        assert_eq!(drops.observe(1, 20), Some(DropChange::Ended(10)));
+        assert_eq!(drops.observe(1, 20), None);
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{fmt, time::Duration};
+
+use crate::log_throttle::LogThrottle;
+
+/// How often a diagnostic is logged at most for errors handling packets from
+/// upstream endpoints.
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How errors handling packets from upstream endpoints, on their way back to
+/// clients, are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteErrorConfig {
+    /// What to do with the packets that fail.
+    pub policy: WriteErrorPolicy,
+    /// Whether a packet that couldn't be sent to its client is sent once
+    /// more before being dropped.
+    pub retry: bool,
+}
+
+/// What is done with a packet from an upstream endpoint that fails a filter
+/// or couldn't be sent. In either case the packet is dropped and counted in
+/// the metrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteErrorPolicy {
+    /// The error is logged, at most once every few seconds along with how
+    /// many errors weren't.
+    #[default]
+    Log,
+    /// The error is only counted in the metrics.
+    Drop,
+}
+
+impl fmt::Display for WriteErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Log => "log",
+            Self::Drop => "drop",
+        })
+    }
+}
+
+impl std::str::FromStr for WriteErrorPolicy {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(Self::Log),
+            "drop" => Ok(Self::Drop),
+            _ => Err(eyre::eyre!(
+                "unknown write error policy `{s}`, expected `log` or `drop`"
+            )),
+        }
+    }
+}
+
+/// Reports errors handling packets from upstream endpoints according to a
+/// [`WriteErrorConfig`], limiting how often they're logged.
+#[derive(Debug)]
+pub(crate) struct WriteErrors {
+    pub(crate) config: WriteErrorConfig,
+    throttle: LogThrottle,
+}
+
+impl Default for WriteErrors {
+    fn default() -> Self {
+        Self::new(WriteErrorConfig::default())
+    }
+}
+
+impl WriteErrors {
+    pub(crate) fn new(config: WriteErrorConfig) -> Self {
+        Self {
+            config,
+            throttle: LogThrottle::new(1, LOG_INTERVAL),
+        }
+    }
+
+    /// Logs `error` if the policy allows it and no other error was logged
+    /// recently, returning whether it was logged.
+    pub(crate) fn report(&self, error: &dyn fmt::Display) -> bool {
+        if self.config.policy == WriteErrorPolicy::Drop {
+            return false;
+        }
+
+        let Some(suppressed) = self.throttle.check() else {
+            return false;
+        };
+
+        tracing::warn!(%error, suppressed, "failed to handle packet from upstream");
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            "log".parse::<WriteErrorPolicy>().unwrap(),
+            WriteErrorPolicy::Log
+        );
+        assert_eq!(
+            "drop".parse::<WriteErrorPolicy>().unwrap(),
+            WriteErrorPolicy::Drop
+        );
+        assert!("retry".parse::<WriteErrorPolicy>().is_err());
+    }
+
+    #[test]
+    fn rate_limited() {
+        let errors = WriteErrors::new(WriteErrorConfig::default());
+        assert!(errors.report(&"first"));
+        assert!(!errors.report(&"second"));
+        assert_eq!(errors.throttle.suppressed(), 1);
+
+        let errors = WriteErrors::new(WriteErrorConfig {
+            policy: WriteErrorPolicy::Drop,
+            ..<_>::default()
+        });
+        assert!(!errors.report(&"first"));
+    }
+}
@@ -73,6 +73,7 @@ impl Relay {
             let provider_is_healthy = ready.provider_is_healthy.clone();
 
             match provider {
+                #[cfg(feature = "agones")]
                 Providers::Agones {
                     config_namespace, ..
                 } => {
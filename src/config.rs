@@ -43,6 +43,7 @@ pub use self::{
 mod config_type;
 mod error;
 pub mod providers;
+pub mod redact;
 mod slot;
 pub mod watch;
 
@@ -80,8 +81,28 @@ pub struct Config {
     pub id: Slot<String>,
     #[serde(default)]
     pub version: Slot<Version>,
+    /// How the sockets used to send packets to each cluster are bound. These
+    /// are specific to the host, so are only read from the local
+    /// configuration and never distributed over xDS.
+    #[serde(default)]
+    pub upstreams: Slot<Vec<crate::net::upstream::UpstreamBinding>>,
+    /// The scheduled windows during which endpoints are drained. Like
+    /// `upstreams`, these are only read from the local configuration.
+    #[serde(default)]
+    pub maintenance: Slot<Vec<crate::net::maintenance::MaintenanceWindow>>,
+    /// Named compositions of filters that filter chains can reference in
+    /// place of a filter. Filter chains are expanded before being
+    /// distributed, so these are also only read from the local configuration.
+    #[serde(default)]
+    pub macros: Slot<std::collections::BTreeMap<String, crate::filters::FilterMacro>>,
     #[serde(flatten)]
     pub datacenter: DatacenterConfig,
+    /// The filters that filter chains are created from when the
+    /// configuration changes, the global
+    /// [`FilterRegistry`](crate::filters::FilterRegistry) when unset.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub filter_set: Option<Arc<crate::filters::FilterSet>>,
 }
 
 impl quilkin_xds::config::Configuration for Config {
@@ -175,7 +196,63 @@ use crate::net::xds::config::DeltaDiscoveryRes;
 impl Config {
     /// Attempts to deserialize `input` as a YAML object representing `Self`.
     pub fn from_reader<R: std::io::Read>(input: R) -> Result<Self, serde_yaml::Error> {
-        serde_yaml::from_reader(input)
+        let value: serde_yaml::Value = serde_yaml::from_reader(input)?;
+        // Macros are registered first, as filter chains are created as
+        // they're deserialized.
+        if let Some(macros) = value.get("macros") {
+            crate::filters::FilterMacros::register(serde_yaml::from_value(macros.clone())?);
+        }
+
+        serde_yaml::from_value(value)
+    }
+
+    /// Like [`Self::from_reader`], creating the filter chain from `filters`,
+    /// which are also used whenever the filter chain changes afterwards.
+    pub fn from_reader_with<R: std::io::Read>(
+        filters: Arc<crate::filters::FilterSet>,
+        input: R,
+    ) -> Result<Self, serde_yaml::Error> {
+        let mut config =
+            crate::filters::FilterRegistry::scope(filters.clone(), || Self::from_reader(input))?;
+        config.filter_set = Some(filters);
+        Ok(config)
+    }
+
+    /// Returns the filters that filter chains are created from.
+    pub fn filter_set(&self) -> Arc<crate::filters::FilterSet> {
+        self.filter_set
+            .clone()
+            .unwrap_or_else(crate::filters::FilterRegistry::filters)
+    }
+
+    /// Creates a filter chain from `filters` with [`Self::filter_set`].
+    pub fn create_filters(
+        &self,
+        filters: impl IntoIterator<Item = Filter>,
+    ) -> Result<crate::filters::FilterChain, CreationError> {
+        crate::filters::FilterChain::try_create_with(&self.filter_set(), filters)
+    }
+
+    /// Replaces the filter chain with the one `update` creates from the
+    /// current chain, carrying the state of the current chain's filters over
+    /// to it. The chain is created again from the new chain if it was
+    /// replaced in the meantime, so concurrent updates aren't lost.
+    pub fn update_filters<E>(
+        &self,
+        mut update: impl FnMut(&crate::filters::FilterChain) -> Result<crate::filters::FilterChain, E>,
+    ) -> Result<Arc<crate::filters::FilterChain>, E> {
+        self.filters.try_rcu(|current| {
+            let filters = update(current)?;
+            filters.carry_over_state(current);
+            Ok(filters)
+        })
+    }
+
+    /// Replaces the filter chain with `filters`, carrying the state of the
+    /// current chain's filters over to it.
+    pub fn replace_filters(&self, filters: crate::filters::FilterChain) {
+        self.update_filters(|_| Ok::<_, std::convert::Infallible>(filters.clone()))
+            .unwrap_or_else(|never| match never {});
     }
 
     fn update_from_json(
@@ -187,14 +264,32 @@ impl Config {
             ($($field:ident),+) => {
                 $(
                     if let Some(value) = map.remove(stringify!($field)) {
-                        tracing::trace!(%value, "replacing {}", stringify!($field));
                         self.$field.try_replace(serde_json::from_value(value)?);
+                        // Logged once parsed, so sensitive values are redacted.
+                        tracing::trace!(value = ?self.$field, "replaced {}", stringify!($field));
                     }
                 )+
             }
         }
 
-        replace_if_present!(filters, id);
+        if let Some(macros) = map.get("macros") {
+            crate::filters::FilterMacros::register(serde_json::from_value(macros.clone())?);
+        }
+
+        replace_if_present!(macros);
+
+        if let Some(value) = map.remove("filters") {
+            let filters: Option<Vec<Filter>> = serde_json::from_value(value)?;
+            let filters = filters
+                .map(|filters| self.create_filters(filters))
+                .transpose()?;
+            if let Some(filters) = filters.filter(|filters| *self.filters.load() != *filters) {
+                self.replace_filters(filters);
+                tracing::trace!(value = ?self.filters, "replaced filters");
+            }
+        }
+
+        replace_if_present!(id, upstreams, maintenance);
 
         if let Some(value) = map.remove("clusters") {
             let cmd: cluster::ClusterMapDeser = serde_json::from_value(value)?;
@@ -433,10 +528,12 @@ impl Config {
                     }
                 };
 
-                let fc =
-                    crate::filters::FilterChain::try_create_fallible(resource.filters.into_iter())?;
+                let fc = crate::filters::FilterChain::try_create_fallible_with(
+                    &self.filter_set(),
+                    resource.filters.into_iter(),
+                )?;
 
-                self.filters.store(Arc::new(fc));
+                self.replace_filters(fc);
             }
             crate::xds::ResourceType::Datacenter => {
                 let DatacenterConfig::NonAgent { datacenters } = &self.datacenter else {
@@ -560,10 +657,14 @@ impl Config {
             filters: Default::default(),
             id: default_proxy_id(),
             version: Slot::with_default(),
+            upstreams: Default::default(),
+            maintenance: Default::default(),
+            macros: Default::default(),
             datacenter: DatacenterConfig::Agent {
                 icao_code: Default::default(),
                 qcmp_port: Default::default(),
             },
+            filter_set: None,
         }
     }
 
@@ -573,9 +674,13 @@ impl Config {
             filters: Default::default(),
             id: default_proxy_id(),
             version: Slot::with_default(),
+            upstreams: Default::default(),
+            maintenance: Default::default(),
+            macros: Default::default(),
             datacenter: DatacenterConfig::NonAgent {
                 datacenters: Default::default(),
             },
+            filter_set: None,
         }
     }
 
@@ -829,6 +934,8 @@ fn default_proxy_id() -> Slot<String> {
 pub struct Filter {
     pub name: String,
     pub label: Option<String>,
+    #[serde(default)]
+    #[schemars(schema_with = "redact::filter_config")]
     pub config: Option<serde_json::Value>,
 }
 
@@ -998,6 +1105,95 @@ id: server-proxy
         assert_eq!(*config.version.load(), Version::V1Alpha1);
     }
 
+    #[tokio::test]
+    async fn parse_macros() {
+        let yaml = "
+version: v1alpha1
+filters:
+  - name: config_test_ingress
+    config:
+      max_packets: 10
+  - name: quilkin.filters.pass.v1alpha1.Pass
+macros:
+  config_test_ingress:
+    params:
+      max_packets: null
+    filters:
+      - name: quilkin.filters.local_rate_limit.v1alpha1.LocalRateLimit
+        config:
+          max_packets: ${max_packets}
+          period: 1
+      - name: quilkin.filters.debug.v1alpha1.Debug
+  ";
+        let config = parse_config(yaml);
+
+        let filters: Vec<_> = config.filters.load().iter().collect();
+        assert_eq!(
+            filters
+                .iter()
+                .map(|filter| filter.name.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "quilkin.filters.local_rate_limit.v1alpha1.LocalRateLimit",
+                "quilkin.filters.debug.v1alpha1.Debug",
+                "quilkin.filters.pass.v1alpha1.Pass",
+            ]
+        );
+        assert_eq!(
+            filters[0].config.as_ref().unwrap()["max_packets"],
+            json!(10)
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_state_survives_reload() {
+        let config = Config::default_non_agent();
+        let reload = |period: u32| {
+            let json = json!({
+                "filters": [{
+                    "name": "quilkin.filters.local_rate_limit.v1alpha1.LocalRateLimit",
+                    "config": { "max_packets": 1, "period": period },
+                }],
+            });
+            config
+                .update_from_json(json.as_object().unwrap().clone(), None)
+                .unwrap();
+        };
+        let read = || {
+            let mut destinations = Vec::new();
+            let mut ctx = crate::filters::ReadContext::new(
+                config.clusters.clone_value(),
+                (std::net::Ipv4Addr::LOCALHOST, 9000).into(),
+                crate::test::alloc_buffer(b"hello"),
+                &mut destinations,
+            );
+            config.filters.load().read(&mut ctx).is_ok()
+        };
+
+        reload(60);
+        assert!(read());
+
+        // The exhausted bucket carries over to the new chain.
+        reload(120);
+        assert!(!read());
+    }
+
+    #[test]
+    fn reload_uses_filter_set() {
+        let mut config = Config::default_non_agent();
+        config.filter_set = Some(Arc::new(crate::filters::FilterSet::with([
+            crate::filters::Pass::factory(),
+        ])));
+        let reload = |name: &str| {
+            let json = json!({ "filters": [{ "name": name }] });
+            config.update_from_json(json.as_object().unwrap().clone(), None)
+        };
+
+        assert!(reload(crate::filters::Drop::NAME).is_err());
+        reload(crate::filters::Pass::NAME).unwrap();
+        assert_eq!(config.filters.load().len(), 1);
+    }
+
     #[test]
     fn parse_client() {
         let config: Config = serde_json::from_value(json!({
@@ -1088,6 +1284,7 @@ id: server-proxy
                                 .into_iter()
                                 .map(From::from)
                                 .collect(),
+                            ..<_>::default()
                         },
                     ),
                     Endpoint::with_metadata(
@@ -1096,6 +1293,7 @@ id: server-proxy
                             .unwrap(),
                         Metadata {
                             tokens: vec!["nkuy70x"].into_iter().map(From::from).collect(),
+                            ..<_>::default()
                         },
                     ),
                 ]
@@ -18,6 +18,7 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
+#[cfg(feature = "agones")]
 pub mod k8s;
 
 const RETRIES: u32 = 25;
@@ -29,6 +30,8 @@ const MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
 pub enum Providers {
     /// Watches Agones' game server CRDs for `Allocated` game server endpoints,
     /// and for a `ConfigMap` that specifies the filter configuration.
+    /// Requires the `agones` feature.
+    #[cfg(feature = "agones")]
     Agones {
         /// The namespace under which the configmap is stored.
         #[clap(short, long, env = "QUILKIN_AGONES_CONFIG_NAMESPACE")]
@@ -53,6 +56,7 @@ pub enum Providers {
 
 impl Providers {
     #[tracing::instrument(level = "trace", skip_all)]
+    #[cfg_attr(not(feature = "agones"), allow(unused_variables))]
     pub fn spawn(
         self,
         config: std::sync::Arc<crate::Config>,
@@ -62,6 +66,7 @@ impl Providers {
         is_agent: bool,
     ) -> tokio::task::JoinHandle<crate::Result<()>> {
         match self {
+            #[cfg(feature = "agones")]
             Self::Agones {
                 gameservers_namespace,
                 config_namespace,
@@ -76,10 +76,10 @@ pub fn update_filters_from_configmap(
             if let Some(filters) = data
                 .get("filters")
                     .cloned()
-                    .map(serde_json::from_value)
+                    .map(serde_json::from_value::<Vec<crate::config::Filter>>)
                     .transpose()?
             {
-                config.filters.store(Arc::new(filters));
+                config.replace_filters(config.create_filters(filters)?);
             }
 
             yield Ok(());
@@ -29,6 +29,8 @@ use serde::{Deserialize, Serialize};
 use crate::net::endpoint::Endpoint;
 
 const QUILKIN_TOKEN_LABEL: &str = "quilkin.dev/tokens";
+const QUILKIN_MAX_SESSIONS_LABEL: &str = "quilkin.dev/max-sessions";
+const QUILKIN_CPU_SCORE_LABEL: &str = "quilkin.dev/cpu-score";
 
 /// Auto-generated derived type for GameServerSpec via `CustomResource`
 #[derive(Clone, Debug, JsonSchema)]
@@ -82,7 +84,11 @@ impl GameServer {
             let ep = Endpoint::with_metadata(
                 (address, port).into(),
                 crate::net::endpoint::metadata::MetadataView::with_unknown(
-                    crate::net::endpoint::Metadata { tokens },
+                    crate::net::endpoint::Metadata {
+                        tokens,
+                        max_sessions: self.annotation(QUILKIN_MAX_SESSIONS_LABEL),
+                        cpu_score: self.annotation(QUILKIN_CPU_SCORE_LABEL),
+                    },
                     extra_metadata,
                 ),
             );
@@ -107,6 +113,16 @@ impl GameServer {
             })
             .unwrap_or_default()
     }
+
+    /// Returns the value of the `key` annotation, if it's set and valid.
+    #[inline]
+    fn annotation<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.metadata
+            .annotations
+            .as_ref()
+            .and_then(|anno| anno.get(key))
+            .and_then(|value| value.trim().parse().ok())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, JsonSchema)]
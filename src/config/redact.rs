@@ -0,0 +1,239 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Redaction of sensitive configuration values, such as keys and tokens,
+//! from configuration dumps and logs.
+//!
+//! Fields are marked as sensitive in their JSON schema, either by adding
+//! `#[schemars(schema_with = "crate::config::redact::sensitive::<T>")]` to the
+//! field, or by setting `writeOnly` in the schema of a plugin's configuration.
+
+use std::cell::Cell;
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{Schema, SchemaObject, SingleOrVec},
+    JsonSchema, Map,
+};
+use serde_json::Value;
+
+/// The value sensitive fields are replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// The schema extension marking a field as holding the configuration of the
+/// filter named by its sibling `name` field.
+const FILTER_CONFIG_EXTENSION: &str = "x-quilkin-filter-config";
+
+thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Generates the schema of `T`, marking it as sensitive, for use with
+/// `#[schemars(schema_with = "...")]`.
+pub fn sensitive<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = T::json_schema(gen).into_object();
+    schema.metadata().write_only = true;
+    Schema::Object(schema)
+}
+
+/// Generates the schema of a filter's configuration, so it can be redacted
+/// using the schema of the filter it belongs to.
+pub(crate) fn filter_config(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = <Option<Value>>::json_schema(gen).into_object();
+    schema
+        .extensions
+        .insert(FILTER_CONFIG_EXTENSION.into(), Value::Bool(true));
+    Schema::Object(schema)
+}
+
+/// Runs `func` with redaction enabled on the current thread, so that any
+/// filter configuration serialized within it has its sensitive fields
+/// replaced with [`REDACTED`].
+pub fn scope<R>(func: impl FnOnce() -> R) -> R {
+    struct Reset(bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            ACTIVE.set(self.0);
+        }
+    }
+
+    let _reset = Reset(ACTIVE.replace(true));
+    func()
+}
+
+/// Whether redaction is enabled on the current thread, see [`scope`].
+pub fn is_active() -> bool {
+    ACTIVE.get()
+}
+
+/// Replaces the sensitive fields in the configuration of the filter `name`
+/// with [`REDACTED`]. Configurations of unknown filters are left as is.
+pub fn filter(name: &str, config: &mut Value) {
+    let Some(factory) = crate::filters::FilterRegistry::get_factory(name) else {
+        return;
+    };
+
+    let root = factory.config_schema();
+    redact(config, &Schema::Object(root.schema), &root.definitions);
+}
+
+/// Replaces the values of `value` marked as sensitive in `schema` with
+/// [`REDACTED`].
+pub fn redact(value: &mut Value, schema: &Schema, definitions: &Map<String, Schema>) {
+    let Schema::Object(schema) = schema else {
+        return;
+    };
+
+    if schema
+        .metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.write_only)
+    {
+        if !value.is_null() {
+            *value = Value::String(REDACTED.into());
+        }
+        return;
+    }
+
+    if let Some(reference) = &schema.reference {
+        let name = reference.trim_start_matches("#/definitions/");
+        if let Some(definition) = definitions.get(name) {
+            redact(value, definition, definitions);
+        }
+    }
+
+    // Which variant applies isn't known here, so every variant's sensitive
+    // fields are redacted.
+    if let Some(subschemas) = &schema.subschemas {
+        for subschema in [&subschemas.all_of, &subschemas.any_of, &subschemas.one_of]
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            redact(value, subschema, definitions);
+        }
+    }
+
+    match value {
+        Value::Object(map) => redact_object(map, schema, definitions),
+        Value::Array(items) => {
+            let Some(array) = &schema.array else {
+                return;
+            };
+
+            match &array.items {
+                Some(SingleOrVec::Single(item)) => {
+                    for value in items {
+                        redact(value, item, definitions);
+                    }
+                }
+                Some(SingleOrVec::Vec(item_schemas)) => {
+                    for (value, item) in items.iter_mut().zip(item_schemas) {
+                        redact(value, item, definitions);
+                    }
+                }
+                None => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redact_object(
+    map: &mut serde_json::Map<String, Value>,
+    schema: &SchemaObject,
+    definitions: &Map<String, Schema>,
+) {
+    let Some(object) = &schema.object else {
+        return;
+    };
+
+    let filter_name = map.get("name").and_then(Value::as_str).map(String::from);
+
+    for (key, value) in map.iter_mut() {
+        let Some(property) = object
+            .properties
+            .get(key)
+            .or(object.additional_properties.as_deref())
+        else {
+            continue;
+        };
+
+        let is_filter_config = matches!(
+            property,
+            Schema::Object(property) if property.extensions.contains_key(FILTER_CONFIG_EXTENSION)
+        );
+
+        match &filter_name {
+            Some(name) if is_filter_config => filter(name, value),
+            _ => redact(value, property, definitions),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize, schemars::JsonSchema)]
+    struct Config {
+        name: String,
+        #[schemars(schema_with = "sensitive::<String>")]
+        key: String,
+        nested: Vec<Nested>,
+    }
+
+    #[derive(serde::Serialize, schemars::JsonSchema)]
+    struct Nested {
+        #[schemars(schema_with = "sensitive::<Option<String>>")]
+        token: Option<String>,
+    }
+
+    #[test]
+    fn redacts_sensitive_fields() {
+        let root = schemars::schema_for!(Config);
+        let mut value = serde_json::to_value(Config {
+            name: "test".into(),
+            key: "secret".into(),
+            nested: vec![
+                Nested {
+                    token: Some("secret".into()),
+                },
+                Nested { token: None },
+            ],
+        })
+        .unwrap();
+
+        redact(&mut value, &Schema::Object(root.schema), &root.definitions);
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "name": "test",
+                "key": REDACTED,
+                "nested": [{ "token": REDACTED }, { "token": null }],
+            })
+        );
+    }
+
+    #[test]
+    fn scoped() {
+        assert!(!is_active());
+        assert!(scope(is_active));
+        assert!(!is_active());
+    }
+}
@@ -101,6 +101,33 @@ impl<T: Default> Slot<T> {
             self.store(value);
         }
     }
+
+    /// Replaces the data in the slot with what `update` makes of it, calling
+    /// `update` again with the new data if the slot changed in the meantime,
+    /// so concurrent updates are never lost. The slot is left untouched if
+    /// `update` fails.
+    pub fn try_rcu<E>(&self, mut update: impl FnMut(&T) -> Result<T, E>) -> Result<Arc<T>, E> {
+        let mut current = self.inner.load_full();
+        loop {
+            let next = Arc::new(match &current {
+                Some(value) => update(value)?,
+                None => update(&T::default())?,
+            });
+
+            let previous = self.inner.compare_and_swap(&current, Some(next.clone()));
+            let swapped = match (&*previous, &current) {
+                (Some(previous), Some(current)) => Arc::ptr_eq(previous, current),
+                (None, None) => true,
+                _ => false,
+            };
+            if swapped {
+                self.call_watcher();
+                return Ok(next);
+            }
+
+            current = arc_swap::Guard::into_inner(previous);
+        }
+    }
 }
 
 impl<T: Default + PartialEq> Slot<T> {
@@ -224,4 +251,26 @@ mod tests {
             *slot.load()
         );
     }
+
+    #[test]
+    fn try_rcu() {
+        let slot = Slot::new(1);
+
+        // A value stored during the update is updated again, not lost.
+        let mut raced = false;
+        let value = slot
+            .try_rcu(|value| {
+                if !raced {
+                    raced = true;
+                    slot.store(Arc::new(10));
+                }
+                Ok::<_, ()>(value + 1)
+            })
+            .unwrap();
+        assert_eq!(*value, 11);
+        assert_eq!(*slot.load(), 11);
+
+        assert!(slot.try_rcu(|_| Err(())).is_err());
+        assert_eq!(*slot.load(), 11);
+    }
 }
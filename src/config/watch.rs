@@ -14,10 +14,13 @@
  * limitations under the License.
  */
 
+#[cfg(feature = "agones")]
 pub mod agones;
 mod fs;
 
-pub use self::{agones::watch as agones, fs::watch as fs};
+#[cfg(feature = "agones")]
+pub use self::agones::watch as agones;
+pub use self::fs::watch as fs;
 use std::sync::Arc;
 
 use tokio::sync::watch;
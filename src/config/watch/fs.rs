@@ -112,6 +112,7 @@ mod tests {
                     (std::net::Ipv4Addr::LOCALHOST, 4321).into(),
                     crate::net::endpoint::Metadata {
                         tokens: <_>::from([Vec::from(*b"1x7ijy6")]),
+                        ..<_>::default()
                     },
                 )]
                 .into(),
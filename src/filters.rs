@@ -18,83 +18,151 @@
 
 //! Filters for processing packets.
 
+mod capabilities;
 mod chain;
+mod drop_reason;
 mod error;
 mod factory;
+mod overrides;
 mod read;
 mod registry;
 mod set;
 mod write;
 
+pub mod address_rewrite;
 pub mod capture;
+#[cfg(feature = "filter-compress")]
 pub mod compress;
 pub mod concatenate;
+#[cfg(feature = "filter-routing")]
+pub mod content_router;
+pub mod control;
 pub mod debug;
 pub mod drop;
+pub mod dscp;
 pub mod firewall;
+pub mod health_probe;
+pub mod listeners;
 pub mod load_balancer;
 pub mod local_rate_limit;
+pub mod macros;
 pub mod r#match;
 pub mod metrics;
 pub mod pass;
+#[cfg(feature = "filter-plugins")]
+pub mod plugin;
+#[cfg(feature = "filter-reassembly")]
+pub mod reassembly;
+#[cfg(feature = "filter-routing")]
+pub mod source_ip_router;
+pub mod tenants;
 pub mod timestamp;
+pub mod token_push;
 pub mod token_router;
-pub mod source_ip_router;
+#[cfg(feature = "filter-routing")]
+pub mod traffic_split;
 
 /// Prelude containing all types and traits required to implement [`Filter`] and
 /// [`FilterFactory`].
 pub mod prelude {
     pub use super::{
-        ConvertProtoConfigError, CreateFilterArgs, CreationError, Filter, FilterError,
-        FilterInstance, ReadContext, StaticFilter, WriteContext, SourceIpRouter,
+        Capabilities, ConvertProtoConfigError, CreateFilterArgs, CreationError, DropReason, Filter,
+        FilterError, FilterInstance, Overrides, ReadContext, StaticFilter, WriteContext,
     };
 }
 
 // Core Filter types
 #[doc(inline)]
 pub use self::{
+    address_rewrite::AddressRewrite,
+    capabilities::Capabilities,
     capture::Capture,
-    compress::Compress,
     concatenate::Concatenate,
+    control::Control,
     debug::Debug,
     drop::Drop,
+    drop_reason::DropReason,
+    dscp::Dscp,
     error::{ConvertProtoConfigError, CreationError, FilterError},
-    factory::{CreateFilterArgs, DynFilterFactory, FilterFactory, FilterInstance},
+    factory::{
+        config_to_json, config_to_protobuf, CreateFilterArgs, DynFilterFactory, FilterFactory,
+        FilterInstance,
+    },
     firewall::Firewall,
+    health_probe::HealthProbe,
+    listeners::Listeners,
     load_balancer::LoadBalancer,
     local_rate_limit::LocalRateLimit,
+    macros::{FilterMacro, FilterMacros},
+    overrides::Overrides,
     pass::Pass,
     r#match::Match,
     read::ReadContext,
     registry::FilterRegistry,
     set::{FilterMap, FilterSet},
+    tenants::Tenants,
     timestamp::Timestamp,
+    token_push::TokenPush,
     token_router::{HashedTokenRouter, TokenRouter},
     write::WriteContext,
-    source_ip_router::SourceIpRouter,
+};
+
+#[cfg(feature = "filter-compress")]
+#[doc(inline)]
+pub use self::compress::Compress;
+
+#[cfg(feature = "filter-plugins")]
+#[doc(inline)]
+pub use self::plugin::PluginFilter;
+
+#[cfg(feature = "filter-reassembly")]
+#[doc(inline)]
+pub use self::reassembly::Reassembly;
+
+#[cfg(feature = "filter-routing")]
+#[doc(inline)]
+pub use self::{
+    content_router::ContentRouter, source_ip_router::SourceIpRouter, traffic_split::TrafficSplit,
 };
 
 use crate::test::TestFilter;
 
-pub use self::chain::FilterChain;
+pub use self::chain::{FilterChain, FilterState};
 
 #[enum_dispatch::enum_dispatch(Filter)]
 pub enum FilterKind {
+    AddressRewrite,
     Capture,
+    #[cfg(feature = "filter-compress")]
     Compress,
     Concatenate,
+    #[cfg(feature = "filter-routing")]
+    ContentRouter,
+    Control,
     Debug,
     Drop,
+    Dscp,
     Firewall,
+    HealthProbe,
+    Listeners,
     LoadBalancer,
     LocalRateLimit,
     Pass,
     Match,
+    #[cfg(feature = "filter-reassembly")]
+    Reassembly,
+    Tenants,
     Timestamp,
+    TokenPush,
     TokenRouter,
     HashedTokenRouter,
+    #[cfg(feature = "filter-routing")]
+    TrafficSplit,
     TestFilter,
+    #[cfg(feature = "filter-routing")]
     SourceIpRouter,
+    #[cfg(feature = "filter-plugins")]
+    PluginFilter,
 }
 
 /// Statically safe version of [`Filter`], if you're writing a Rust filter, you
@@ -226,4 +294,25 @@ pub trait Filter: Send + Sync {
     fn write(&self, _: &mut WriteContext) -> Result<(), FilterError> {
         Ok(())
     }
+
+    /// Returns what the filter's `read` needs from the filters before it and
+    /// provides to the filters after it, used to validate the order of filters
+    /// when a [`FilterChain`] is created. By default, the filter has no
+    /// requirements on its position in the chain.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Returns the state the filter has built up for clients, such as their
+    /// rate limit buckets, so a replacement proxy can carry on from it with
+    /// [`Filter::import_state`]. By default, the filter has no state.
+    fn export_state(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Restores state returned by [`Filter::export_state`] from the same
+    /// filter in another proxy.
+    fn import_state(&self, _: serde_json::Value) -> Result<(), serde_json::Error> {
+        Ok(())
+    }
 }
@@ -0,0 +1,348 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::net::{IpAddr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{filters::prelude::*, net::endpoint::AddressKind, pool::PoolBuffer};
+
+use crate::generated::quilkin::filters::address_rewrite::v1alpha1 as proto;
+
+/// Rewrites the address an upstream advertises as its own in the packets it
+/// sends back to clients, so clients reach it through the proxy instead.
+pub struct AddressRewrite {
+    address: SocketAddr,
+    /// Sorted by descending offset, so rewriting a field that changes length
+    /// doesn't move the fields yet to be rewritten.
+    fields: Vec<Field>,
+}
+
+impl AddressRewrite {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let address = canonical(config.address);
+        if address.is_ipv6()
+            && config
+                .fields
+                .iter()
+                .any(|field| field.format == Format::Ipv4)
+        {
+            return Err(CreationError::FieldInvalid {
+                field: "address".into(),
+                reason: "an IPv6 address can't be written to an `ipv4` field".into(),
+            });
+        }
+
+        let mut fields = config.fields;
+        fields.sort_by(|a, b| b.offset.cmp(&a.offset));
+        Ok(Self { address, fields })
+    }
+}
+
+impl Filter for AddressRewrite {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let AddressKind::Ip(ip) = ctx.source.host else {
+            return Ok(());
+        };
+
+        let source = canonical((ip, ctx.source.port).into());
+        for field in &self.fields {
+            if field.rewrite(&mut ctx.contents, source, self.address) {
+                tracing::trace!(offset = field.offset, %source, "rewrote advertised address");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StaticFilter for AddressRewrite {
+    const NAME: &'static str = "quilkin.filters.address_rewrite.v1alpha1.AddressRewrite";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::AddressRewrite;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// Treats IPv4-mapped IPv6 addresses as the IPv4 addresses they map.
+fn canonical(address: SocketAddr) -> SocketAddr {
+    SocketAddr::new(address.ip().to_canonical(), address.port())
+}
+
+/// `address_rewrite` filter's configuration.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The address to advertise instead, usually the proxy's public address.
+    #[schemars(with = "String")]
+    pub address: SocketAddr,
+    /// The fields of the packets holding the upstream's address.
+    pub fields: Vec<Field>,
+}
+
+/// A field of a packet holding an address, which is only rewritten when it
+/// holds the address of the upstream that sent the packet.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    /// The offset of the field from the start of the packet.
+    pub offset: usize,
+    pub format: Format,
+}
+
+/// How an address is written in a packet.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// The 4 bytes of an IPv4 address, then the port as 2 big endian bytes.
+    Ipv4,
+    /// The 16 bytes of an IPv6 address, then the port as 2 big endian bytes.
+    /// IPv4 addresses are written as IPv4-mapped IPv6 addresses.
+    Ipv6,
+    /// The address as text, such as `192.0.2.1:7777`, after its length as 1
+    /// byte.
+    TextU8,
+    /// The address as text, such as `192.0.2.1:7777`, after its length as 2
+    /// big endian bytes.
+    TextU16,
+}
+
+impl Field {
+    /// Writes `address` over the field if it holds `source`, returning
+    /// whether it was rewritten.
+    fn rewrite(&self, contents: &mut PoolBuffer, source: SocketAddr, address: SocketAddr) -> bool {
+        match self.format {
+            Format::Ipv4 => self.rewrite_binary(contents, 4, source, address),
+            Format::Ipv6 => self.rewrite_binary(contents, 16, source, address),
+            Format::TextU8 => self.rewrite_text(contents, 1, source, address),
+            Format::TextU16 => self.rewrite_text(contents, 2, source, address),
+        }
+    }
+
+    /// Rewrites an address of `len` bytes followed by its port.
+    fn rewrite_binary(
+        &self,
+        contents: &mut PoolBuffer,
+        len: usize,
+        source: SocketAddr,
+        address: SocketAddr,
+    ) -> bool {
+        let range = self.offset..self.offset + len + 2;
+        let Some((ip, port)) = contents.get(range.clone()).map(|field| field.split_at(len)) else {
+            return false;
+        };
+
+        let ip = match len {
+            4 => <[u8; 4]>::try_from(ip).map(IpAddr::from).ok(),
+            _ => <[u8; 16]>::try_from(ip).map(IpAddr::from).ok(),
+        };
+        let port = u16::from_be_bytes([port[0], port[1]]);
+        if ip.map(|ip| canonical((ip, port).into())) != Some(source) {
+            return false;
+        }
+
+        let field = contents.as_mut_slice(range);
+        match address.ip() {
+            IpAddr::V4(ip) if len == 4 => field[..len].copy_from_slice(&ip.octets()),
+            IpAddr::V4(ip) => field[..len].copy_from_slice(&ip.to_ipv6_mapped().octets()),
+            IpAddr::V6(ip) => field[..len].copy_from_slice(&ip.octets()),
+        }
+        field[len..].copy_from_slice(&address.port().to_be_bytes());
+        true
+    }
+
+    /// Rewrites an address as text after a big endian length of
+    /// `prefix_len` bytes, moving the rest of the packet if its length
+    /// changes.
+    fn rewrite_text(
+        &self,
+        contents: &mut PoolBuffer,
+        prefix_len: usize,
+        source: SocketAddr,
+        address: SocketAddr,
+    ) -> bool {
+        let start = self.offset + prefix_len;
+        let Some(prefix) = contents.get(self.offset..start) else {
+            return false;
+        };
+
+        let len = prefix
+            .iter()
+            .fold(0, |len, byte| (len << 8) | usize::from(*byte));
+        let end = start + len;
+        let found = contents
+            .get(start..end)
+            .and_then(|text| std::str::from_utf8(text).ok())
+            .and_then(|text| text.parse::<SocketAddr>().ok());
+        if found.map(canonical) != Some(source) {
+            return false;
+        }
+
+        let text = address.to_string();
+        let prefix = &text.len().to_be_bytes()[std::mem::size_of::<usize>() - prefix_len..];
+        let rest = contents[end..].to_vec();
+        contents.truncate(self.offset);
+        contents.extend_from_slice(prefix);
+        contents.extend_from_slice(text.as_bytes());
+        contents.extend_from_slice(&rest);
+        true
+    }
+}
+
+impl From<Config> for proto::AddressRewrite {
+    fn from(config: Config) -> Self {
+        Self {
+            address: config.address.to_string(),
+            fields: config
+                .fields
+                .into_iter()
+                .map(|field| proto::address_rewrite::Field {
+                    offset: field.offset as u32,
+                    format: match field.format {
+                        Format::Ipv4 => proto::address_rewrite::Format::Ipv4,
+                        Format::Ipv6 => proto::address_rewrite::Format::Ipv6,
+                        Format::TextU8 => proto::address_rewrite::Format::TextU8,
+                        Format::TextU16 => proto::address_rewrite::Format::TextU16,
+                    }
+                    .into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::AddressRewrite> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::AddressRewrite) -> Result<Self, Self::Error> {
+        let address = p.address.parse().map_err(|error| {
+            ConvertProtoConfigError::new(
+                format!("invalid address `{}`: {error}", p.address),
+                Some("address".into()),
+            )
+        })?;
+
+        let fields = p
+            .fields
+            .into_iter()
+            .map(|field| {
+                let format = match proto::address_rewrite::Format::try_from(field.format) {
+                    Ok(proto::address_rewrite::Format::Ipv4) => Format::Ipv4,
+                    Ok(proto::address_rewrite::Format::Ipv6) => Format::Ipv6,
+                    Ok(proto::address_rewrite::Format::TextU8) => Format::TextU8,
+                    Ok(proto::address_rewrite::Format::TextU16) => Format::TextU16,
+                    Err(_) => {
+                        return Err(ConvertProtoConfigError::new(
+                            format!("invalid format `{}`", field.format),
+                            Some("fields.format".into()),
+                        ))
+                    }
+                };
+
+                Ok(Field {
+                    offset: field.offset as usize,
+                    format,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { address, fields })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    fn write(filter: &AddressRewrite, source: SocketAddr, contents: &[u8]) -> Vec<u8> {
+        let mut ctx = WriteContext::new(
+            source.into(),
+            (Ipv4Addr::LOCALHOST, 9000).into(),
+            alloc_buffer(contents),
+        );
+        filter.write(&mut ctx).unwrap();
+        ctx.contents.to_vec()
+    }
+
+    #[test]
+    fn rewrites_binary_fields() {
+        let config: Config = serde_yaml::from_str(
+            "address: 203.0.113.1:7777\nfields:\n  - { offset: 1, format: ipv4 }",
+        )
+        .unwrap();
+        let filter = AddressRewrite::from_config(Some(config));
+        let upstream: SocketAddr = (Ipv4Addr::new(10, 0, 0, 2), 26000).into();
+
+        let packet = [0xff, 10, 0, 0, 2, 0x65, 0x90, 0xee];
+        assert_eq!(
+            write(&filter, upstream, &packet),
+            [0xff, 203, 0, 113, 1, 0x1e, 0x61, 0xee]
+        );
+
+        // Another upstream's address, or a packet too short for the field.
+        let other: SocketAddr = (Ipv4Addr::new(10, 0, 0, 3), 26000).into();
+        assert_eq!(write(&filter, other, &packet), packet);
+        assert_eq!(write(&filter, upstream, &packet[..4]), &packet[..4]);
+    }
+
+    #[test]
+    fn rewrites_text_fields() {
+        let config: Config = serde_yaml::from_str(
+            "
+address: 203.0.113.1:7777
+fields:
+  - { offset: 0, format: text_u8 }
+  - { offset: 15, format: text_u16 }
+",
+        )
+        .unwrap();
+        let filter = AddressRewrite::from_config(Some(config));
+        let upstream: SocketAddr = (Ipv4Addr::new(10, 0, 0, 2), 26000).into();
+
+        let packet = [
+            &[14][..],
+            b"10.0.0.2:26000",
+            &[0, 14],
+            b"10.0.0.2:26000",
+            b"!",
+        ]
+        .concat();
+        let expected = [
+            &[16][..],
+            b"203.0.113.1:7777",
+            &[0, 16],
+            b"203.0.113.1:7777",
+            b"!",
+        ]
+        .concat();
+        assert_eq!(write(&filter, upstream, &packet), expected);
+    }
+
+    #[test]
+    fn rejects_ipv6_address_for_ipv4_field() {
+        let config: Config = serde_yaml::from_str(
+            "address: '[2001:db8::1]:7777'\nfields:\n  - { offset: 0, format: ipv4 }",
+        )
+        .unwrap();
+        assert!(AddressRewrite::try_from_config(Some(config)).is_err());
+    }
+}
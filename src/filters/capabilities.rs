@@ -0,0 +1,168 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Validation of the order of filters in a chain.
+
+use crate::{
+    filters::{CreationError, Filter, FilterInstance},
+    net::endpoint::metadata,
+};
+
+/// What a filter needs from, and provides to, the filters after it when
+/// reading a packet, used to reject filter chains whose order can't work.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The metadata keys the filter expects an earlier filter to have set.
+    pub consumes: Vec<metadata::Key>,
+    /// The metadata keys the filter sets.
+    pub produces: Vec<metadata::Key>,
+    /// Whether the filter adds destinations for the packet.
+    pub sets_destinations: bool,
+    /// Whether the filter discards the destinations set by earlier filters.
+    pub clears_destinations: bool,
+}
+
+impl Capabilities {
+    /// Merges the capabilities of a filter that may run instead of `self`.
+    pub fn union(mut self, other: Self) -> Self {
+        self.consumes.extend(other.consumes);
+        self.produces.extend(other.produces);
+        self.sets_destinations |= other.sets_destinations;
+        self.clears_destinations |= other.clears_destinations;
+        self
+    }
+}
+
+/// Checks that each filter in `filters` can work with the ones before it,
+/// returning an error for orderings that can never be correct, and the
+/// descriptions of orderings that are likely mistakes.
+///
+/// Missing metadata is only a warning, as it may be set by a filter in an
+/// enclosing chain.
+pub fn validate(filters: &[(String, FilterInstance)]) -> Result<Vec<String>, CreationError> {
+    let mut warnings = Vec::new();
+    let mut produced = Vec::new();
+    let mut destinations_set_by: Option<&str> = None;
+
+    for (name, instance) in filters {
+        let capabilities = instance.filter().capabilities();
+
+        for key in &capabilities.consumes {
+            if !produced.contains(key) {
+                warnings.push(format!(
+                    "filter `{name}` reads metadata key `{key}`, which no filter before it sets"
+                ));
+            }
+        }
+
+        if let Some(previous) = destinations_set_by {
+            if capabilities.clears_destinations {
+                return Err(CreationError::IncompatibleFilters {
+                    filter: name.clone(),
+                    reason: format!("it discards the destinations set by `{previous}` before it"),
+                });
+            }
+
+            if capabilities.sets_destinations {
+                warnings.push(format!(
+                    "filter `{name}` adds destinations to those set by `{previous}`, \
+                     packets will be sent to both"
+                ));
+            }
+        }
+
+        if capabilities.sets_destinations || capabilities.clears_destinations {
+            destinations_set_by = Some(name);
+        }
+
+        produced.extend(capabilities.produces);
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::{Capture, LoadBalancer, StaticFilter, TokenRouter};
+
+    fn instance(name: &str, config: serde_json::Value) -> (String, FilterInstance) {
+        (
+            name.into(),
+            crate::filters::FilterRegistry::get(
+                name,
+                crate::filters::CreateFilterArgs::fixed(Some(config)),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn capture() -> (String, FilterInstance) {
+        instance(
+            Capture::NAME,
+            serde_json::json!({ "suffix": { "size": 3 } }),
+        )
+    }
+
+    fn token_router() -> (String, FilterInstance) {
+        instance(TokenRouter::NAME, serde_json::json!({}))
+    }
+
+    fn load_balancer() -> (String, FilterInstance) {
+        instance(
+            LoadBalancer::NAME,
+            serde_json::json!({ "policy": "ROUND_ROBIN" }),
+        )
+    }
+
+    #[test]
+    fn valid_chain() {
+        assert!(validate(&[capture(), token_router()]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_metadata_warns() {
+        let warnings = validate(&[token_router(), capture()]).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(TokenRouter::NAME));
+    }
+
+    #[test]
+    fn repeated_destinations_warn() {
+        let warnings = validate(&[capture(), load_balancer(), token_router()]).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(LoadBalancer::NAME));
+    }
+
+    #[cfg(feature = "filter-routing")]
+    #[test]
+    fn discarded_destinations_rejected() {
+        use crate::filters::SourceIpRouter;
+
+        let router = instance(SourceIpRouter::NAME, serde_json::json!({ "routes": [] }));
+        assert_eq!(
+            validate(&[load_balancer(), router.clone()]),
+            Err(CreationError::IncompatibleFilters {
+                filter: SourceIpRouter::NAME.into(),
+                reason: format!(
+                    "it discards the destinations set by `{}` before it",
+                    LoadBalancer::NAME
+                ),
+            })
+        );
+        assert!(validate(&[router, load_balancer()]).is_ok());
+    }
+}
@@ -43,14 +43,18 @@ pub trait CaptureStrategy {
 pub struct Capture {
     capture: Box<dyn CaptureStrategy + Sync + Send>,
     metadata_key: metadata::Key,
-    is_present_key: metadata::Key,
+    is_present_key: metadata::TypedKey<bool>,
 }
 
 impl Capture {
     fn new(config: Config) -> Self {
+        metadata::TypedKey::<bytes::Bytes>::new(config.metadata_key)
+            .register("the value the capture filter captured from the packet");
+
         Self {
             capture: config.strategy.into_capture(),
-            is_present_key: format!("{}/is_present", config.metadata_key).into(),
+            is_present_key: metadata::TypedKey::new(format!("{}/is_present", config.metadata_key))
+                .register("whether the capture filter captured a value from the packet"),
             metadata_key: config.metadata_key,
         }
     }
@@ -60,10 +64,8 @@ impl Filter for Capture {
     #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
     fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
         let capture = self.capture.capture(&mut ctx.contents);
-        ctx.metadata.insert(
-            self.is_present_key,
-            metadata::Value::Bool(capture.is_some()),
-        );
+        ctx.metadata
+            .insert_typed(&self.is_present_key, capture.is_some());
 
         if let Some(value) = capture {
             tracing::trace!(key=%self.metadata_key, %value, "captured value");
@@ -74,6 +76,13 @@ impl Filter for Capture {
             Err(FilterError::NoValueCaptured)
         }
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            produces: vec![self.metadata_key],
+            ..<_>::default()
+        }
+    }
 }
 
 impl StaticFilter for Capture {
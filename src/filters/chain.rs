@@ -14,16 +14,27 @@
  * limitations under the License.
  */
 
+use std::sync::Arc;
+
 use prometheus::{exponential_buckets, Histogram};
 
 use crate::{
     config::Filter as FilterConfig,
-    filters::{prelude::*, FilterRegistry},
+    filters::{prelude::*, FilterMacros, FilterRegistry, FilterSet},
     metrics::{histogram_opts, CollectorExt},
 };
 
 const FILTER_LABEL: &str = "filter";
 
+/// The state exported from a filter in a [`FilterChain`].
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct FilterState {
+    /// The position of the filter in the chain.
+    pub index: usize,
+    pub name: String,
+    pub state: serde_json::Value,
+}
+
 /// Start the histogram bucket at an eighth of a millisecond, as we bucketed the full filter
 /// chain processing starting at a quarter of a millisecond, so we we will want finer granularity
 /// here.
@@ -53,6 +64,10 @@ impl FilterChain {
     pub fn new(filters: Vec<(String, FilterInstance)>) -> Result<Self, CreationError> {
         let subsystem = "filter";
 
+        for warning in crate::filters::capabilities::validate(&filters)? {
+            tracing::warn!("{warning}");
+        }
+
         Ok(Self {
             filter_read_duration_seconds: filters
                 .iter()
@@ -114,6 +129,59 @@ impl FilterChain {
             })
     }
 
+    /// Returns the state of every filter in the chain that has any, see
+    /// [`Filter::export_state`].
+    pub fn export_state(&self) -> Vec<FilterState> {
+        self.filters
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (name, instance))| {
+                Some(FilterState {
+                    index,
+                    name: name.clone(),
+                    state: instance.filter().export_state()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Restores the state exported from another chain into the filters at
+    /// the same position with the same name, the state of filters that
+    /// aren't in this chain is discarded.
+    pub fn import_state(&self, states: Vec<FilterState>) {
+        for FilterState { index, name, state } in states {
+            let Some((_, instance)) = self
+                .filters
+                .get(index)
+                .filter(|(existing, _)| *existing == name)
+            else {
+                tracing::warn!(index, %name, "discarding state of filter not in the chain");
+                continue;
+            };
+
+            if let Err(error) = instance.filter().import_state(state) {
+                tracing::warn!(index, %name, %error, "failed to import filter state");
+            }
+        }
+    }
+
+    /// Carries the state of the filters in `previous` over to the filters at
+    /// the same position with the same name in this chain, such as when this
+    /// chain replaces `previous` after a config change, so clients keep their
+    /// rate limit buckets and routes.
+    pub fn carry_over_state(&self, previous: &FilterChain) {
+        let states = previous
+            .export_state()
+            .into_iter()
+            .filter(|state| {
+                self.filters
+                    .get(state.index)
+                    .is_some_and(|(name, _)| *name == state.name)
+            })
+            .collect();
+        self.import_state(states);
+    }
+
     /// Validates the filter configurations in the provided config and constructs
     /// a FilterChain if all configurations are valid, including the conversion
     /// into a [`Filter`]
@@ -123,19 +191,35 @@ impl FilterChain {
     where
         Item: TryInto<FilterConfig, Error = CreationError>,
     {
-        let mut filters = Vec::new();
+        let filter_configs = filter_configs
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<_>, _>>()?;
 
-        for filter_config in filter_configs {
-            let filter_config = filter_config.try_into()?;
-            let filter = FilterRegistry::get(
-                &filter_config.name,
-                CreateFilterArgs::fixed(filter_config.config),
-            )?;
+        Self::try_create(filter_configs)
+    }
 
-            filters.push((filter_config.name, filter));
-        }
+    /// Like [`Self::try_create_fallible`], creating the filters from `filters`
+    /// rather than the global registry.
+    pub fn try_create_fallible_with<Item>(
+        filters: &Arc<FilterSet>,
+        filter_configs: impl IntoIterator<Item = Item>,
+    ) -> Result<Self, CreationError>
+    where
+        Item: TryInto<FilterConfig, Error = CreationError>,
+    {
+        FilterRegistry::scope(filters.clone(), || {
+            Self::try_create_fallible(filter_configs)
+        })
+    }
 
-        Self::new(filters)
+    /// Like [`Self::try_create`], creating the filters, and any filters they
+    /// create themselves, from `filters` rather than the global registry.
+    pub fn try_create_with(
+        filters: &Arc<FilterSet>,
+        filter_configs: impl IntoIterator<Item = FilterConfig>,
+    ) -> Result<Self, CreationError> {
+        FilterRegistry::scope(filters.clone(), || Self::try_create(filter_configs))
     }
 
     /// Validates the filter configurations in the provided config and constructs
@@ -145,7 +229,7 @@ impl FilterChain {
     ) -> Result<Self, CreationError> {
         let mut filters = Vec::new();
 
-        for filter_config in filter_configs {
+        for filter_config in FilterMacros::expand(filter_configs)? {
             let filter = FilterRegistry::get(
                 &filter_config.name,
                 CreateFilterArgs::fixed(filter_config.config),
@@ -163,7 +247,9 @@ impl std::fmt::Debug for FilterChain {
         let mut filters = f.debug_struct("Filters");
 
         for (id, instance) in &self.filters {
-            filters.field(id, instance.config());
+            let mut config = instance.config().clone();
+            crate::config::redact::filter(id, &mut config);
+            filters.field(id, &config);
         }
 
         filters.finish()
@@ -248,10 +334,17 @@ impl serde::Serialize for FilterChain {
         let filters = self
             .filters
             .iter()
-            .map(|(name, instance)| crate::config::Filter {
-                name: name.clone(),
-                label: instance.label().map(String::from),
-                config: Some(serde_json::Value::clone(instance.config())),
+            .map(|(name, instance)| {
+                let mut config = serde_json::Value::clone(instance.config());
+                if crate::config::redact::is_active() {
+                    crate::config::redact::filter(name, &mut config);
+                }
+
+                crate::config::Filter {
+                    name: name.clone(),
+                    label: instance.label().map(String::from),
+                    config: Some(config),
+                }
             })
             .collect::<Vec<_>>();
 
@@ -281,12 +374,28 @@ impl Filter for FilterChain {
         {
             tracing::trace!(%id, "read filtering packet");
             let timer = histogram.start_timer();
-            let result = instance.filter().read(ctx);
+            let result = instance
+                .filter()
+                .read(ctx)
+                .and_then(|()| match ctx.drop_reason {
+                    Some(reason) => Err(FilterError::Discarded(reason)),
+                    None => Ok(()),
+                });
             timer.stop_and_record();
             match result {
+                Ok(()) if ctx.reply.is_some() => {
+                    tracing::trace!(%id, "read replying to packet");
+                    return Ok(());
+                }
                 Ok(()) => tracing::trace!(%id, "read passing packet"),
                 Err(error) => {
                     tracing::trace!(%id, "read dropping packet");
+                    crate::metrics::filter_errors_total(
+                        crate::metrics::READ,
+                        id,
+                        error.discriminant(),
+                    )
+                    .inc();
                     return Err(error);
                 }
             }
@@ -318,6 +427,12 @@ impl Filter for FilterChain {
                 Ok(()) => tracing::trace!(%id, "write passing packet"),
                 Err(error) => {
                     tracing::trace!(%id, "write dropping packet");
+                    crate::metrics::filter_errors_total(
+                        crate::metrics::WRITE,
+                        id,
+                        error.discriminant(),
+                    )
+                    .inc();
                     return Err(error);
                 }
             }
@@ -0,0 +1,389 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Base64Standard,
+    filters::prelude::*,
+    metrics::Direction,
+    net::endpoint::{metadata, Locality},
+};
+
+use crate::generated::quilkin::filters::content_router::v1alpha1 as proto;
+
+/// The dynamic metadata key the name of the matching route is set to by
+/// default.
+pub const METADATA_KEY: &str = "quilkin.dev/content_route";
+
+/// Routes or tags packets by the bytes at the start of their payload, such as
+/// to send clients of a legacy protocol version sharing a port with the
+/// current one to a fleet of their own.
+///
+/// Only the first `scan_limit` bytes of each packet are matched against, so
+/// large packets cost no more than small ones. The first route that matches
+/// sets its name in the packet's dynamic metadata, and narrows its
+/// destinations to the route's cluster if it has one.
+pub struct ContentRouter {
+    config: Config,
+    /// The compiled pattern of each route, in the same order.
+    patterns: Vec<Pattern>,
+    matched_total: IntCounter,
+}
+
+/// What a route matches against.
+enum Pattern {
+    Bytes { bytes: Vec<u8>, offset: usize },
+    Regex(regex::bytes::Regex),
+}
+
+impl Pattern {
+    fn matches(&self, contents: &[u8]) -> bool {
+        match self {
+            Self::Bytes { bytes, offset } => contents
+                .get(*offset..)
+                .is_some_and(|contents| contents.starts_with(bytes)),
+            Self::Regex(regex) => regex.is_match(contents),
+        }
+    }
+}
+
+impl ContentRouter {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let invalid = |field: &str, reason: String| CreationError::FieldInvalid {
+            field: field.into(),
+            reason,
+        };
+
+        let patterns = config
+            .routes
+            .iter()
+            .map(|route| match (route.bytes.is_empty(), &route.regex) {
+                (false, None) => {
+                    if route.offset + route.bytes.len() > config.scan_limit {
+                        return Err(invalid(
+                            "routes.bytes",
+                            format!("route `{}` matches past the scan limit", route.name),
+                        ));
+                    }
+
+                    Ok(Pattern::Bytes {
+                        bytes: route.bytes.clone(),
+                        offset: route.offset,
+                    })
+                }
+                // Anchored to the start of the packet, rather than searching
+                // through it.
+                (true, Some(regex)) => regex::bytes::Regex::new(&format!("^(?:{regex})"))
+                    .map(Pattern::Regex)
+                    .map_err(|error| invalid("routes.regex", error.to_string())),
+                _ => Err(invalid(
+                    "routes",
+                    format!(
+                        "route `{}` must have exactly one of `bytes` or `regex`",
+                        route.name
+                    ),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            patterns,
+            matched_total: super::metrics::counter(
+                Self::NAME,
+                "matched_total",
+                "Total number of packets that matched a route",
+                Direction::Read,
+            ),
+            config,
+        })
+    }
+}
+
+impl Filter for ContentRouter {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let scanned = &ctx.contents[..ctx.contents.len().min(self.config.scan_limit)];
+        let Some(route) = self
+            .patterns
+            .iter()
+            .position(|pattern| pattern.matches(scanned))
+            .map(|index| &self.config.routes[index])
+        else {
+            return Ok(());
+        };
+
+        self.matched_total.inc();
+        ctx.metadata.insert(
+            self.config.metadata_key,
+            metadata::Value::String(route.name.clone()),
+        );
+
+        let Some(locality) = &route.cluster else {
+            return Ok(());
+        };
+        let Some(cluster) = ctx.endpoints.get(&Some(locality.clone())) else {
+            return Err(FilterError::Custom("filter::content_router::no cluster"));
+        };
+
+        // Like the TrafficSplit filter, narrow down the endpoints chosen by
+        // previous filters, otherwise send to the whole cluster.
+        if ctx.destinations.is_empty() {
+            ctx.destinations.extend(
+                cluster
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.address.clone()),
+            );
+        } else {
+            ctx.destinations.retain(|address| {
+                cluster
+                    .endpoints
+                    .iter()
+                    .any(|endpoint| endpoint.address == *address)
+            });
+            if ctx.destinations.is_empty() {
+                ctx.drop_reason = Some(DropReason::NoEndpoints);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            produces: vec![self.config.metadata_key],
+            sets_destinations: self
+                .config
+                .routes
+                .iter()
+                .any(|route| route.cluster.is_some()),
+            ..<_>::default()
+        }
+    }
+}
+
+impl StaticFilter for ContentRouter {
+    const NAME: &'static str = "quilkin.filters.content_router.v1alpha1.ContentRouter";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::ContentRouter;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// A pattern packets are matched against, and where they're sent when they
+/// match.
+///
+/// Routes match either `bytes` at `offset` into the packet, or `regex` from
+/// the start of the packet.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// The name the filter's metadata key is set to when the route matches.
+    pub name: String,
+    /// The bytes the packet must contain at `offset`, base64 encoded.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "Base64Standard::deserialize",
+        serialize_with = "Base64Standard::serialize"
+    )]
+    pub bytes: Vec<u8>,
+    /// Where `bytes` start in the packet.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offset: usize,
+    /// A regular expression over the bytes of the packet, which must match
+    /// from its start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    /// The locality of the cluster matching packets are sent to, when unset
+    /// they're only tagged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<Locality>,
+}
+
+fn is_zero(offset: &usize) -> bool {
+    *offset == 0
+}
+
+/// `content_router` filter's configuration.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The key the name of the matching route is set to in the packet's
+    /// dynamic metadata.
+    #[serde(rename = "metadataKey", default = "default_metadata_key")]
+    pub metadata_key: metadata::Key,
+    /// The most bytes at the start of each packet that are matched against.
+    #[serde(default = "default_scan_limit")]
+    pub scan_limit: usize,
+    /// The routes packets are matched against, in order.
+    pub routes: Vec<Route>,
+}
+
+/// Default value for [`Config::metadata_key`]
+fn default_metadata_key() -> metadata::Key {
+    metadata::Key::from_static(METADATA_KEY)
+}
+
+/// Default value for [`Config::scan_limit`]
+fn default_scan_limit() -> usize {
+    64
+}
+
+impl From<Config> for proto::ContentRouter {
+    fn from(config: Config) -> Self {
+        Self {
+            metadata_key: Some(config.metadata_key.to_string()),
+            scan_limit: Some(config.scan_limit as u32),
+            routes: config
+                .routes
+                .into_iter()
+                .map(|route| proto::content_router::Route {
+                    name: route.name,
+                    bytes: route.bytes,
+                    offset: route.offset as u32,
+                    regex: route.regex,
+                    cluster: route.cluster.map(|cluster| cluster.to_string()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::ContentRouter> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::ContentRouter) -> Result<Self, Self::Error> {
+        let routes = p
+            .routes
+            .into_iter()
+            .map(|route| {
+                let cluster = route
+                    .cluster
+                    .map(|cluster| cluster.parse())
+                    .transpose()
+                    .map_err(|error| {
+                        ConvertProtoConfigError::new(
+                            format!("invalid cluster: {error}"),
+                            Some("routes.cluster".into()),
+                        )
+                    })?;
+
+                Ok(Route {
+                    name: route.name,
+                    bytes: route.bytes,
+                    offset: route.offset as usize,
+                    regex: route.regex,
+                    cluster,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            metadata_key: p
+                .metadata_key
+                .map(metadata::Key::new)
+                .unwrap_or_else(default_metadata_key),
+            scan_limit: p
+                .scan_limit
+                .map_or_else(default_scan_limit, |limit| limit as usize),
+            routes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::Arc};
+
+    use super::*;
+    use crate::{
+        net::{
+            endpoint::{Endpoint, EndpointAddress},
+            ClusterMap,
+        },
+        test::alloc_buffer,
+    };
+
+    const CONFIG: &str = "
+routes:
+  - name: legacy
+    bytes: AAE=
+    offset: 2
+    cluster: legacy
+  - name: current
+    regex: 'V[2-9]'
+";
+
+    crate::config_round_trip_test!(config_round_trip: ContentRouter => CONFIG);
+
+    fn read(filter: &ContentRouter, contents: &[u8]) -> (Option<String>, Vec<u16>) {
+        let clusters = ClusterMap::default();
+        clusters.insert(
+            Some("legacy".parse().unwrap()),
+            BTreeSet::from([Endpoint::new((std::net::Ipv4Addr::LOCALHOST, 7001).into())]),
+        );
+
+        let mut destinations = Vec::new();
+        let mut ctx = ReadContext::new(
+            Arc::new(clusters),
+            (std::net::Ipv4Addr::LOCALHOST, 9000).into(),
+            alloc_buffer(contents),
+            &mut destinations,
+        );
+        filter.read(&mut ctx).unwrap();
+        let route = ctx
+            .metadata
+            .get(&default_metadata_key())
+            .and_then(|value| value.as_string())
+            .map(String::from);
+        (
+            route,
+            destinations.iter().map(EndpointAddress::port).collect(),
+        )
+    }
+
+    #[test]
+    fn routes_by_content() {
+        let filter = ContentRouter::from_config(Some(serde_yaml::from_str(CONFIG).unwrap()));
+
+        assert_eq!(
+            read(&filter, b"hi\x00\x01hello"),
+            (Some("legacy".into()), vec![7001])
+        );
+        assert_eq!(read(&filter, b"V3hello"), (Some("current".into()), vec![]));
+        // The regex is anchored to the start of the packet.
+        assert_eq!(read(&filter, b"hello V3"), (None, vec![]));
+        assert_eq!(read(&filter, b"\x00\x01"), (None, vec![]));
+    }
+
+    #[test]
+    fn rejects_invalid_routes() {
+        for routes in [
+            "[{ name: both, bytes: AAE=, regex: a }]",
+            "[{ name: neither }]",
+            "[{ name: invalid, regex: '(' }]",
+            "[{ name: far, bytes: AAE=, offset: 64 }]",
+        ] {
+            let config: Config = serde_yaml::from_str(&format!("routes: {routes}")).unwrap();
+            assert!(ContentRouter::try_from_config(Some(config)).is_err());
+        }
+    }
+}
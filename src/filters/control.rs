@@ -0,0 +1,399 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::control::{AckCode, Frame, Message},
+    collections::ttl::{Entry, TtlMap},
+    filters::{capture::CAPTURED_BYTES, prelude::*},
+    net::{
+        cluster::ClusterMap,
+        endpoint::{
+            metadata::{self, Value},
+            EndpointAddress,
+        },
+        reroute,
+    },
+};
+
+use crate::generated::quilkin::filters::control::v1alpha1 as proto;
+
+/// How long a client's refreshed token and reroutes are kept after its last
+/// packet.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often expired clients are removed.
+const CLIENT_EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What clients changed through control messages.
+#[derive(Debug, Default)]
+struct ClientState {
+    /// The token to route the client's packets with, instead of the one they
+    /// carry.
+    token: Option<bytes::Bytes>,
+    /// How many times the client asked to be rerouted.
+    reroutes: u64,
+}
+
+/// A client's [`ClientState`] exported to another proxy.
+#[derive(Debug, Deserialize, Serialize)]
+struct ExportedClient {
+    source: EndpointAddress,
+    /// The refreshed token, base64 encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    reroutes: u64,
+}
+
+/// Handles in-band [control messages](crate::codec::control) from clients,
+/// replying to them directly, and applies what clients changed through them
+/// to the rest of their packets.
+///
+/// Control messages are never forwarded, so this filter should come after
+/// the filter capturing the routing token, and before the filters routing
+/// packets.
+pub struct Control {
+    state: TtlMap<EndpointAddress, ClientState>,
+    config: Config,
+}
+
+impl Control {
+    fn new(config: Config) -> Self {
+        Self {
+            state: TtlMap::new(CLIENT_TIMEOUT, CLIENT_EXPIRY_POLL_INTERVAL),
+            config,
+        }
+    }
+
+    /// Updates the state of `source`, creating it if it doesn't exist.
+    fn update(&self, source: &EndpointAddress, func: impl FnOnce(&mut ClientState)) {
+        match self.state.entry(source.clone()) {
+            Entry::Occupied(mut entry) => func(&mut entry.get_mut().value),
+            Entry::Vacant(entry) => {
+                let mut state = ClientState::default();
+                func(&mut state);
+                entry.insert(state);
+            }
+        }
+    }
+
+    /// Handles a control message from `source`, returning the reply.
+    fn handle(
+        &self,
+        source: &EndpointAddress,
+        endpoints: &ClusterMap,
+        message: Message<'_>,
+    ) -> Message<'static> {
+        match message {
+            Message::RefreshToken(token) => {
+                if !is_routable(endpoints, token) {
+                    return Message::Ack(AckCode::UnknownToken);
+                }
+
+                self.update(source, |state| {
+                    state.token = Some(bytes::Bytes::copy_from_slice(token));
+                });
+                Message::Ack(AckCode::Ok)
+            }
+            Message::Reroute => {
+                self.update(source, |state| state.reroutes += 1);
+                Message::Ack(AckCode::Ok)
+            }
+            Message::Status => {
+                let (token, reroutes) = self
+                    .state
+                    .get(source)
+                    .map(|state| (state.value.token.clone(), state.value.reroutes))
+                    .unwrap_or_default();
+
+                Message::StatusReply {
+                    token_refreshed: token.is_some(),
+                    token_routable: token.is_some_and(|token| is_routable(endpoints, &token)),
+                    reroutes: reroutes.try_into().unwrap_or(u32::MAX),
+                }
+            }
+            // Only sent by the proxy.
+            Message::Ack(_) | Message::StatusReply { .. } => Message::Ack(AckCode::Invalid),
+        }
+    }
+}
+
+/// Whether any endpoint in `endpoints` has `token`.
+fn is_routable(endpoints: &ClusterMap, token: &[u8]) -> bool {
+    let mut addresses = Vec::new();
+    endpoints.addresses_for_token_or_wildcard(token, &mut addresses);
+    !addresses.is_empty()
+}
+
+impl Filter for Control {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let frame = match Frame::parse(&ctx.contents) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                if let Some(state) = self.state.get(&ctx.source) {
+                    if let Some(token) = &state.value.token {
+                        ctx.metadata
+                            .insert(self.config.metadata_key, Value::Bytes(token.clone()));
+                    }
+                    if state.value.reroutes > 0 {
+                        reroute::set(&mut ctx.metadata, state.value.reroutes);
+                    }
+                }
+
+                return Ok(());
+            }
+            Err(error) => {
+                tracing::debug!(%error, source = %ctx.source, "invalid control frame");
+                return Err(FilterError::Custom("filter::control::invalid frame"));
+            }
+        };
+
+        let reply = Frame::new(
+            frame.sequence,
+            self.handle(&ctx.source, &ctx.endpoints, frame.message),
+        );
+        let mut buf = Vec::new();
+        // Replies never carry a token, so always fit in a frame.
+        if reply.encode(&mut buf).is_ok() {
+            ctx.reply = Some(buf);
+        }
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            produces: vec![
+                self.config.metadata_key,
+                metadata::Key::from_static(reroute::METADATA_KEY),
+            ],
+            ..<_>::default()
+        }
+    }
+
+    fn export_state(&self) -> Option<serde_json::Value> {
+        let clients = self.state.snapshot(|source, state| ExportedClient {
+            source: source.clone(),
+            token: state.token.as_ref().map(crate::codec::base64::encode),
+            reroutes: state.reroutes,
+        });
+        serde_json::to_value(clients).ok()
+    }
+
+    fn import_state(&self, state: serde_json::Value) -> Result<(), serde_json::Error> {
+        let clients: Vec<ExportedClient> = serde_json::from_value(state)?;
+        for client in clients {
+            let token = client
+                .token
+                .map(|token| crate::codec::base64::decode(token).map(bytes::Bytes::from))
+                .transpose()
+                .map_err(<serde_json::Error as serde::de::Error>::custom)?;
+            self.state.insert(
+                client.source,
+                ClientState {
+                    token,
+                    reroutes: client.reroutes,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+impl StaticFilter for Control {
+    const NAME: &'static str = "quilkin.filters.control.v1alpha1.Control";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Control;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Ok(Self::new(config.unwrap_or_default()))
+    }
+}
+
+/// `control` filter's configuration.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(default)]
+pub struct Config {
+    /// The key of the routing token in the filter's dynamic metadata, which
+    /// is replaced with the token the client refreshed, if any.
+    #[serde(rename = "metadataKey", default = "default_metadata_key")]
+    pub metadata_key: metadata::Key,
+}
+
+/// Default value for [`Config::metadata_key`]
+fn default_metadata_key() -> metadata::Key {
+    metadata::Key::from_static(CAPTURED_BYTES)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            metadata_key: default_metadata_key(),
+        }
+    }
+}
+
+impl From<Config> for proto::Control {
+    fn from(config: Config) -> Self {
+        Self {
+            metadata_key: Some(config.metadata_key.to_string()),
+        }
+    }
+}
+
+impl TryFrom<proto::Control> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::Control) -> Result<Self, Self::Error> {
+        Ok(Self {
+            metadata_key: p
+                .metadata_key
+                .map(metadata::Key::new)
+                .unwrap_or_else(default_metadata_key),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::endpoint::{Endpoint, Metadata},
+        test::alloc_buffer,
+    };
+
+    fn endpoints() -> std::sync::Arc<ClusterMap> {
+        let endpoint = Endpoint::with_metadata(
+            "127.0.0.1:80".parse().unwrap(),
+            Metadata {
+                tokens: vec!["abc".into()].into_iter().collect(),
+                ..<_>::default()
+            },
+        );
+        ClusterMap::new_default([endpoint].into()).into()
+    }
+
+    /// Sends `contents` through `filter`, returning the context's reply and
+    /// metadata.
+    fn read(filter: &Control, contents: &[u8]) -> (Option<Vec<u8>>, metadata::DynamicMetadata) {
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            endpoints(),
+            "127.0.0.1:100".parse().unwrap(),
+            alloc_buffer(contents),
+            &mut dest,
+        );
+        filter.read(&mut ctx).unwrap();
+        (ctx.reply, ctx.metadata)
+    }
+
+    fn request(sequence: u16, message: Message<'_>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Frame::new(sequence, message).encode(&mut buf).unwrap();
+        buf
+    }
+
+    fn reply(reply: Option<Vec<u8>>) -> Frame<'static> {
+        let reply = reply.expect("control messages are replied to");
+        Frame::parse(reply.leak()).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn refresh_token() {
+        let filter = Control::from_config(None);
+
+        let (response, _) = read(&filter, &request(1, Message::RefreshToken(b"xyz")));
+        assert_eq!(
+            reply(response),
+            Frame::new(1, Message::Ack(AckCode::UnknownToken))
+        );
+
+        let (response, _) = read(&filter, &request(2, Message::RefreshToken(b"abc")));
+        assert_eq!(reply(response), Frame::new(2, Message::Ack(AckCode::Ok)));
+
+        let (response, metadata) = read(&filter, b"hello");
+        assert!(response.is_none());
+        assert_eq!(
+            metadata.get(&metadata::Key::from_static(CAPTURED_BYTES)),
+            Some(&Value::Bytes(b"abc".to_vec().into()))
+        );
+        assert_eq!(reroute::get(&metadata), None);
+    }
+
+    #[tokio::test]
+    async fn export_and_import_state() {
+        let previous = Control::from_config(None);
+        read(&previous, &request(1, Message::RefreshToken(b"abc")));
+        read(&previous, &request(2, Message::Reroute));
+
+        let next = Control::from_config(None);
+        next.import_state(previous.export_state().unwrap()).unwrap();
+
+        let (_, metadata) = read(&next, b"hello");
+        assert_eq!(
+            metadata.get(&metadata::Key::from_static(CAPTURED_BYTES)),
+            Some(&Value::Bytes(b"abc".to_vec().into()))
+        );
+        assert_eq!(reroute::get(&metadata), Some(1));
+    }
+
+    #[tokio::test]
+    async fn reroute_and_status() {
+        let filter = Control::from_config(None);
+
+        for sequence in 0..2 {
+            let (response, _) = read(&filter, &request(sequence, Message::Reroute));
+            assert_eq!(
+                reply(response),
+                Frame::new(sequence, Message::Ack(AckCode::Ok))
+            );
+        }
+
+        let (_, metadata) = read(&filter, b"hello");
+        assert_eq!(reroute::get(&metadata), Some(2));
+
+        let (response, _) = read(&filter, &request(3, Message::Status));
+        assert_eq!(
+            reply(response),
+            Frame::new(
+                3,
+                Message::StatusReply {
+                    token_refreshed: false,
+                    token_routable: false,
+                    reroutes: 2,
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_frame() {
+        let filter = Control::from_config(None);
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            endpoints(),
+            "127.0.0.1:100".parse().unwrap(),
+            alloc_buffer(b"QCTL\x07"),
+            &mut dest,
+        );
+        assert!(filter.read(&mut ctx).is_err());
+        assert!(ctx.reply.is_none());
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+
+/// Why a packet was dropped, whether by a filter or by the proxy itself.
+///
+/// Every drop is counted under one of these reasons, so drops can be
+/// compared across filters and the proxy regardless of the specific error
+/// behind them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DropReason {
+    /// There was no endpoint to send the packet to.
+    NoEndpoints,
+    /// The packet was from an upstream without a session to a client.
+    NoSession,
+    /// The packet's source isn't allowed to send it, such as by a firewall.
+    Denied,
+    /// The packet's source has sent more than it's allowed to.
+    RateLimited,
+    /// The packet didn't have the contents or metadata expected of it.
+    Malformed,
+    /// Every endpoint the packet was for can't currently be sent to.
+    Unavailable,
+    /// The proxy was too busy to handle the packet.
+    Overloaded,
+    /// The packet was explicitly dropped.
+    Dropped,
+    /// An error in the proxy itself, such as a socket error.
+    Internal,
+    /// A reason not covered by the others, such as from a custom filter.
+    Other,
+}
+
+impl DropReason {
+    /// Every reason, in order.
+    pub const ALL: [Self; 10] = [
+        Self::NoEndpoints,
+        Self::NoSession,
+        Self::Denied,
+        Self::RateLimited,
+        Self::Malformed,
+        Self::Unavailable,
+        Self::Overloaded,
+        Self::Dropped,
+        Self::Internal,
+        Self::Other,
+    ];
+
+    /// The label of the reason in metrics and logs.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NoEndpoints => "no_endpoints",
+            Self::NoSession => "no_session",
+            Self::Denied => "denied",
+            Self::RateLimited => "rate_limited",
+            Self::Malformed => "malformed",
+            Self::Unavailable => "unavailable",
+            Self::Overloaded => "overloaded",
+            Self::Dropped => "dropped",
+            Self::Internal => "internal",
+            Self::Other => "other",
+        }
+    }
+
+    /// The discriminant of a packet discarded by a filter for this reason,
+    /// see [`FilterError::Discarded`](super::FilterError::Discarded).
+    pub(crate) fn discriminant(self) -> &'static str {
+        match self {
+            Self::NoEndpoints => "filter::discarded::no endpoints",
+            Self::NoSession => "filter::discarded::no session",
+            Self::Denied => "filter::discarded::denied",
+            Self::RateLimited => "filter::discarded::rate limited",
+            Self::Malformed => "filter::discarded::malformed",
+            Self::Unavailable => "filter::discarded::unavailable",
+            Self::Overloaded => "filter::discarded::overloaded",
+            Self::Dropped => "filter::discarded::dropped",
+            Self::Internal => "filter::discarded::internal",
+            Self::Other => "filter::discarded::other",
+        }
+    }
+
+    /// The position of the reason in [`Self::ALL`].
+    #[inline]
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_match_all() {
+        for (index, reason) in DropReason::ALL.into_iter().enumerate() {
+            assert_eq!(reason.index(), index, "{reason}");
+        }
+    }
+}
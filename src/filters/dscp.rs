@@ -0,0 +1,139 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{filters::prelude::*, net::dscp};
+
+use crate::generated::quilkin::filters::dscp::v1alpha1 as proto;
+
+/// Marks packets with a DSCP, overriding the proxy's default marking.
+pub struct Dscp {
+    config: Config,
+}
+
+impl Filter for Dscp {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        if let Some(value) = self.config.upstream {
+            value.insert(&mut ctx.metadata);
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        if let Some(value) = self.config.downstream {
+            value.insert(&mut ctx.metadata);
+        }
+
+        Ok(())
+    }
+}
+
+impl StaticFilter for Dscp {
+    const NAME: &'static str = "quilkin.filters.dscp.v1alpha1.Dscp";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Dscp;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Ok(Self {
+            config: Self::ensure_config_exists(config)?,
+        })
+    }
+}
+
+/// `dscp` filter's configuration.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The DSCP to mark packets sent to upstream endpoints with.
+    #[serde(default)]
+    pub upstream: Option<dscp::Dscp>,
+    /// The DSCP to mark packets sent back to clients with.
+    #[serde(default)]
+    pub downstream: Option<dscp::Dscp>,
+}
+
+impl From<Config> for proto::Dscp {
+    fn from(config: Config) -> Self {
+        Self {
+            upstream: config.upstream.map(|value| value.value().into()),
+            downstream: config.downstream.map(|value| value.value().into()),
+        }
+    }
+}
+
+impl TryFrom<proto::Dscp> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::Dscp) -> Result<Self, Self::Error> {
+        let convert = |value: Option<u32>, field: &'static str| {
+            value
+                .map(|value| {
+                    u8::try_from(value)
+                        .ok()
+                        .and_then(dscp::Dscp::new)
+                        .ok_or_else(|| {
+                            ConvertProtoConfigError::new(
+                                format!("{value} is not between 0 and 63"),
+                                Some(field.into()),
+                            )
+                        })
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            upstream: convert(p.upstream, "upstream")?,
+            downstream: convert(p.downstream, "downstream")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    #[test]
+    fn marks_packets() {
+        let config: Config = serde_yaml::from_str("upstream: EF\ndownstream: 10").unwrap();
+        let filter = Dscp::from_config(Some(config));
+
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            (std::net::Ipv4Addr::LOCALHOST, 80).into(),
+            alloc_buffer(b"hello"),
+            &mut dest,
+        );
+        filter.read(&mut ctx).unwrap();
+        assert_eq!(
+            dscp::Dscp::select(&ctx.metadata, None),
+            Some(dscp::Dscp::EF)
+        );
+
+        let mut ctx = WriteContext::new(
+            (std::net::Ipv4Addr::LOCALHOST, 80).into(),
+            (std::net::Ipv4Addr::LOCALHOST, 81).into(),
+            alloc_buffer(b"hello"),
+        );
+        filter.write(&mut ctx).unwrap();
+        assert_eq!(dscp::Dscp::select(&ctx.metadata, None), dscp::Dscp::new(10));
+    }
+}
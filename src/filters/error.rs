@@ -27,12 +27,16 @@ use filters::{Filter, FilterFactory};
 pub enum FilterError {
     NoValueCaptured,
     TokenRouter(filters::token_router::RouterError),
+    #[cfg(feature = "filter-compress")]
     Compression(filters::compress::CompressionError),
     Io(std::io::Error),
     FirewallDenied,
     MatchNoMetadata,
     Dropped,
     RateLimitExceeded,
+    /// A filter discarded the packet by setting
+    /// [`ReadContext::drop_reason`](crate::filters::ReadContext::drop_reason).
+    Discarded(filters::DropReason),
     Custom(&'static str),
 }
 
@@ -41,15 +45,41 @@ impl FilterError {
         match self {
             Self::NoValueCaptured => "filter::capture::no value captured",
             Self::TokenRouter(tr) => tr.discriminant(),
+            #[cfg(feature = "filter-compress")]
             Self::Compression(_) => "filter::compression::io",
             Self::Io(..) => "filter::io",
             Self::FirewallDenied => "filter::firewall::denied",
             Self::MatchNoMetadata => "filter::match::no metadata",
             Self::Dropped => "filter::drop::dropped",
             Self::RateLimitExceeded => "filter::rate_limit::dropped",
+            Self::Discarded(reason) => reason.discriminant(),
             Self::Custom(custom) => custom,
         }
     }
+
+    /// Why the packet was dropped, out of the reasons every drop is counted
+    /// under.
+    pub fn drop_reason(&self) -> filters::DropReason {
+        use filters::DropReason;
+
+        match self {
+            Self::NoValueCaptured | Self::MatchNoMetadata => DropReason::Malformed,
+            Self::TokenRouter(filters::token_router::RouterError::NoTokenFound) => {
+                DropReason::Malformed
+            }
+            Self::TokenRouter(filters::token_router::RouterError::NoEndpointMatch { .. }) => {
+                DropReason::NoEndpoints
+            }
+            #[cfg(feature = "filter-compress")]
+            Self::Compression(_) => DropReason::Malformed,
+            Self::Io(_) => DropReason::Internal,
+            Self::FirewallDenied => DropReason::Denied,
+            Self::Dropped => DropReason::Dropped,
+            Self::RateLimitExceeded => DropReason::RateLimited,
+            Self::Discarded(reason) => *reason,
+            Self::Custom(_) => DropReason::Other,
+        }
+    }
 }
 
 impl std::error::Error for FilterError {}
@@ -59,12 +89,14 @@ impl fmt::Display for FilterError {
         match self {
             Self::NoValueCaptured => f.write_str("no value captured"),
             Self::TokenRouter(tr) => write!(f, "{tr}"),
+            #[cfg(feature = "filter-compress")]
             Self::Compression(comp) => write!(f, "{comp}"),
             Self::Io(io) => write!(f, "{io}"),
             Self::FirewallDenied => f.write_str("packet denied by firewall"),
             Self::MatchNoMetadata => f.write_str("expected metadata key for match not present"),
             Self::Dropped => f.write_str("dropped"),
             Self::RateLimitExceeded => f.write_str("rate limit exceeded"),
+            Self::Discarded(reason) => write!(f, "discarded: {reason}"),
             Self::Custom(custom) => f.write_str(custom),
         }
     }
@@ -83,12 +115,14 @@ impl PartialEq for FilterError {
         match (self, other) {
             (Self::NoValueCaptured, Self::NoValueCaptured) => true,
             (Self::TokenRouter(tra), Self::TokenRouter(trb)) => tra.eq(trb),
+            #[cfg(feature = "filter-compress")]
             (Self::Compression(ca), Self::Compression(cb)) => ca.eq(cb),
             (Self::Io(ia), Self::Io(ib)) => ia.kind().eq(&ib.kind()),
             (Self::FirewallDenied, Self::FirewallDenied) => true,
             (Self::MatchNoMetadata, Self::MatchNoMetadata) => true,
             (Self::Dropped, Self::Dropped) => true,
             (Self::RateLimitExceeded, Self::RateLimitExceeded) => true,
+            (Self::Discarded(a), Self::Discarded(b)) => a == b,
             (Self::Custom(a), Self::Custom(b)) => a == b,
             _ => false,
         }
@@ -104,8 +138,10 @@ impl Hash for FilterError {
 
         match self {
             Self::TokenRouter(re) => Hash::hash(&re, state),
+            #[cfg(feature = "filter-compress")]
             Self::Compression(ce) => Hash::hash(&ce, state),
             Self::Io(io) => Hash::hash(&io.kind(), state),
+            Self::Discarded(reason) => Hash::hash(reason, state),
             Self::Custom(ce) => state.write(ce.as_bytes()),
             Self::NoValueCaptured
             | Self::FirewallDenied
@@ -134,6 +170,12 @@ pub enum CreationError {
     InitializeMetricsFailed(String),
     #[error("Protobuf error: {}", .0)]
     ConvertProtoConfig(ConvertProtoConfigError),
+    #[error(
+        "filter `{}` can't be used in this position in the chain, reason: {}",
+        filter,
+        reason
+    )]
+    IncompatibleFilters { filter: String, reason: String },
     #[error("Infallible! This should never occur")]
     Infallible,
 }
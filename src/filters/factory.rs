@@ -137,30 +137,56 @@ where
         &self,
         config: serde_json::Value,
     ) -> Result<prost_types::Any, CreationError> {
-        let config: F::Configuration = serde_json::from_value(config)?;
-
-        Ok(prost_types::Any {
-            type_url: self.name().into(),
-            value: crate::codec::prost::encode::<F::BinaryConfiguration>(&config.try_into()?)?,
-        })
+        config_to_protobuf::<F>(config)
     }
 
     fn encode_config_to_json(
         &self,
         config: prost_types::Any,
     ) -> Result<serde_json::Value, CreationError> {
-        if self.name() != config.type_url {
-            return Err(crate::filters::CreationError::MismatchedTypes {
-                expected: self.name().into(),
-                actual: config.type_url,
-            });
-        }
+        config_to_json::<F>(config)
+    }
+}
+
+/// Converts the JSON configuration of `F` into its Protobuf equivalent,
+/// through its [`StaticFilter::Configuration`] and
+/// [`StaticFilter::BinaryConfiguration`].
+///
+/// Factories for filters that aren't registered as a [`StaticFilter`] can
+/// use this to implement [`FilterFactory::encode_config_to_protobuf`].
+pub fn config_to_protobuf<F>(config: serde_json::Value) -> Result<prost_types::Any, CreationError>
+where
+    F: StaticFilter,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    let config: F::Configuration = serde_json::from_value(config)?;
 
-        let message = <F::BinaryConfiguration as prost::Message>::decode(&*config.value)?;
-        let config = F::Configuration::try_from(message)?;
+    Ok(prost_types::Any {
+        type_url: F::NAME.into(),
+        value: crate::codec::prost::encode::<F::BinaryConfiguration>(&config.try_into()?)?,
+    })
+}
 
-        Ok(serde_json::to_value(&config)?)
+/// Converts the Protobuf configuration of `F` into its JSON equivalent, the
+/// reverse of [`config_to_protobuf`].
+pub fn config_to_json<F>(config: prost_types::Any) -> Result<serde_json::Value, CreationError>
+where
+    F: StaticFilter,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    if F::NAME != config.type_url {
+        return Err(crate::filters::CreationError::MismatchedTypes {
+            expected: F::NAME.into(),
+            actual: config.type_url,
+        });
     }
+
+    let message = <F::BinaryConfiguration as prost::Message>::decode(&*config.value)?;
+    let config = F::Configuration::try_from(message)?;
+
+    Ok(serde_json::to_value(&config)?)
 }
 
 /// Arguments needed to create a new filter.
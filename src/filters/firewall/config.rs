@@ -80,6 +80,12 @@ impl FromStr for Cidr {
     }
 }
 
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 impl Cidr {
     /// Does this Address match the netmask?
     /// If the mask is ipv4 and the address is ipv6, this will attempt to see if it's a
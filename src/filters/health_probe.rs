@@ -0,0 +1,228 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Base64Standard,
+    filters::{firewall::Cidr, prelude::*},
+    metrics::Direction,
+};
+
+use crate::generated::quilkin::filters::health_probe::v1alpha1 as proto;
+
+/// Answers health check probes, such as those sent by network load balancers,
+/// from the proxy itself so they never reach an upstream endpoint.
+pub struct HealthProbe {
+    config: Config,
+    probes_total: IntCounter,
+}
+
+impl HealthProbe {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        if config.payloads.is_empty() && config.sources.is_empty() {
+            return Err(CreationError::FieldInvalid {
+                field: "payloads".into(),
+                reason: "at least one payload or source must be set".into(),
+            });
+        }
+
+        Ok(Self {
+            config,
+            probes_total: super::metrics::counter(
+                Self::NAME,
+                "probes_total",
+                "Total number of health check probes answered",
+                Direction::Read,
+            ),
+        })
+    }
+
+    /// Whether a packet is a health check probe, matching both the payloads
+    /// and the sources, when set.
+    fn is_probe(&self, ctx: &ReadContext<'_>) -> bool {
+        let payload_matches = self.config.payloads.is_empty()
+            || self
+                .config
+                .payloads
+                .iter()
+                .any(|payload| payload.0 == *ctx.contents);
+
+        payload_matches
+            && (self.config.sources.is_empty()
+                || ctx.source.to_socket_addr().is_ok_and(|source| {
+                    self.config
+                        .sources
+                        .iter()
+                        .any(|cidr| cidr.contains(source.ip()))
+                }))
+    }
+}
+
+impl Filter for HealthProbe {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        if !self.is_probe(ctx) {
+            return Ok(());
+        }
+
+        self.probes_total.inc();
+        ctx.reply = Some(match &self.config.response {
+            Some(response) => response.0.clone(),
+            None => ctx.contents.to_vec(),
+        });
+
+        Ok(())
+    }
+}
+
+impl StaticFilter for HealthProbe {
+    const NAME: &'static str = "quilkin.filters.health_probe.v1alpha1.HealthProbe";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::HealthProbe;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// A base64 encoded payload.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+pub struct Payload(
+    #[serde(with = "Base64Standard")]
+    #[schemars(with = "String")]
+    pub Vec<u8>,
+);
+
+/// `health_probe` filter's configuration.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The payloads of probes, a packet is only a probe if it's exactly one
+    /// of them. Any packet can be a probe when empty.
+    #[serde(default)]
+    pub payloads: Vec<Payload>,
+    /// The addresses probes are sent from, a packet is only a probe if it's
+    /// from one of them. Packets from any address can be probes when empty.
+    #[serde(default)]
+    pub sources: Vec<Cidr>,
+    /// The payload probes are answered with, by default probes are echoed
+    /// back.
+    #[serde(default)]
+    pub response: Option<Payload>,
+}
+
+impl From<Config> for proto::HealthProbe {
+    fn from(config: Config) -> Self {
+        Self {
+            payloads: config
+                .payloads
+                .into_iter()
+                .map(|payload| payload.0)
+                .collect(),
+            sources: config.sources.iter().map(ToString::to_string).collect(),
+            response: config.response.map(|payload| payload.0),
+        }
+    }
+}
+
+impl TryFrom<proto::HealthProbe> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::HealthProbe) -> Result<Self, Self::Error> {
+        let sources = p
+            .sources
+            .iter()
+            .map(|source| {
+                source.parse().map_err(|error| {
+                    ConvertProtoConfigError::new(
+                        format!("invalid source: {error:?}"),
+                        Some("sources".into()),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            payloads: p.payloads.into_iter().map(Payload).collect(),
+            sources,
+            response: p.response.map(Payload),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    fn read(filter: &HealthProbe, source: [u8; 4], contents: &[u8]) -> Option<Vec<u8>> {
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            (source, 9000).into(),
+            alloc_buffer(contents),
+            &mut dest,
+        );
+        filter.read(&mut ctx).unwrap();
+        ctx.reply
+    }
+
+    #[test]
+    fn answers_probes() {
+        let config: Config = serde_yaml::from_str(
+            "
+payloads: [aGVhbHRo]
+sources: [10.0.0.0/8]
+response: b2s=
+",
+        )
+        .unwrap();
+        let filter = HealthProbe::from_config(Some(config));
+
+        assert_eq!(
+            read(&filter, [10, 0, 0, 1], b"health"),
+            Some(b"ok".to_vec())
+        );
+        assert_eq!(read(&filter, [192, 168, 0, 1], b"health"), None);
+        assert_eq!(read(&filter, [10, 0, 0, 1], b"game data"), None);
+    }
+
+    #[test]
+    fn echoes_without_response() {
+        let config: Config = serde_yaml::from_str("payloads: [aGVhbHRo]").unwrap();
+        let filter = HealthProbe::from_config(Some(config));
+        assert_eq!(
+            read(&filter, [127, 0, 0, 1], b"health"),
+            Some(b"health".to_vec())
+        );
+    }
+
+    #[test]
+    fn requires_payloads_or_sources() {
+        assert!(HealthProbe::try_from_config(Some(Config::default())).is_err());
+    }
+
+    #[test]
+    fn convert_proto_config() {
+        let config: Config =
+            serde_yaml::from_str("payloads: [aGVhbHRo]\nsources: [10.0.0.0/8]").unwrap();
+        let proto = proto::HealthProbe::from(config.clone());
+        assert_eq!(proto.sources, ["10.0.0.0/8"]);
+        assert_eq!(Config::try_from(proto).unwrap(), config);
+    }
+}
@@ -0,0 +1,374 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod config;
+mod metrics;
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    collections::ttl::TtlMap,
+    filters::{prelude::*, FilterChain},
+    metrics::Direction,
+    net::{endpoint::EndpointAddress, session_quota::SessionQuota},
+};
+
+use crate::generated::quilkin::filters::listeners::v1alpha1 as proto;
+
+pub use config::{Config, Listener, ListenerMetrics};
+
+/// How long a client is associated with a listener after its last packet.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+const CLIENT_EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A filter that runs a different filter chain for each virtual listener,
+/// selected by the port each packet was sent to, so a single proxy can serve
+/// several kinds of traffic with their own pipelines.
+///
+/// Packets from upstream are run through the chain of the listener the client
+/// last sent a packet to.
+pub struct Listeners {
+    ports: std::collections::HashMap<u16, usize>,
+    chains: Vec<FilterChain>,
+    /// The session timeout of each chain's listener, if overridden.
+    session_timeouts: Vec<Option<Duration>>,
+    /// The session quota of each chain's listener, if any.
+    session_quotas: Vec<Option<SessionQuota>>,
+    /// The metrics of each chain's listener, if it has its own.
+    metrics: Vec<Option<Arc<metrics::Metrics>>>,
+    fallthrough: FilterChain,
+    /// The index of the chain each client was last routed through.
+    clients: TtlMap<EndpointAddress, usize>,
+}
+
+impl Listeners {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let mut ports = std::collections::HashMap::new();
+        let mut chains = Vec::with_capacity(config.listeners.len());
+        let mut session_timeouts = Vec::with_capacity(config.listeners.len());
+        let mut session_quotas = Vec::with_capacity(config.listeners.len());
+        let mut listener_metrics = Vec::with_capacity(config.listeners.len());
+
+        for (index, listener) in config.listeners.into_iter().enumerate() {
+            for port in listener.ports {
+                if ports.insert(port, index).is_some() {
+                    return Err(CreationError::FieldInvalid {
+                        field: "listeners.ports".into(),
+                        reason: format!("port {port} is used by more than one listener"),
+                    });
+                }
+            }
+
+            chains.push(FilterChain::try_create(listener.filters)?);
+            session_timeouts.push(listener.session_timeout_secs.map(Duration::from_secs));
+            session_quotas.push(listener.session_quota);
+            listener_metrics.push(
+                listener
+                    .metrics
+                    .as_ref()
+                    .map(metrics::Metrics::get)
+                    .transpose()?,
+            );
+        }
+
+        Ok(Self {
+            ports,
+            chains,
+            session_timeouts,
+            session_quotas,
+            metrics: listener_metrics,
+            fallthrough: FilterChain::try_create(config.fallthrough)?,
+            clients: TtlMap::new(CLIENT_TIMEOUT, CLIENT_EXPIRY_POLL_INTERVAL),
+        })
+    }
+
+    #[inline]
+    fn chain(&self, index: usize) -> &FilterChain {
+        self.chains.get(index).unwrap_or(&self.fallthrough)
+    }
+
+    /// Counts the packet handled by the listener at `index` in its metrics,
+    /// if it has its own.
+    #[inline]
+    fn record(&self, index: usize, direction: Direction, result: &Result<(), FilterError>) {
+        let Some(Some(metrics)) = self.metrics.get(index) else {
+            return;
+        };
+
+        metrics.packets_total(direction).inc();
+        if let Err(error) = result {
+            metrics
+                .packets_dropped_total(direction, error.drop_reason())
+                .inc();
+        }
+    }
+}
+
+impl Filter for Listeners {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let index = ctx
+            .destination_port
+            .and_then(|port| self.ports.get(&port).copied())
+            .unwrap_or(self.chains.len());
+
+        tracing::trace!(port = ?ctx.destination_port, listener = index, "selected listener");
+
+        let known = self
+            .clients
+            .get(&ctx.source)
+            .is_some_and(|entry| entry.value == index);
+        if !known {
+            self.clients.insert(ctx.source.clone(), index);
+        }
+
+        // Set before running the chain, so its filters can override it.
+        if let Some(Some(timeout)) = self.session_timeouts.get(index) {
+            crate::net::session_timeout::set(&mut ctx.metadata, *timeout);
+        }
+        if let Some(Some(quota)) = self.session_quotas.get(index) {
+            crate::net::session_quota::set(&mut ctx.metadata, *quota);
+        }
+
+        let result = self.chain(index).read(ctx);
+        self.record(index, Direction::Read, &result);
+        result
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let index = self
+            .clients
+            .get(&ctx.dest)
+            .map(|entry| entry.value)
+            .unwrap_or(self.chains.len());
+
+        let result = self.chain(index).write(ctx);
+        self.record(index, Direction::Write, &result);
+        result
+    }
+}
+
+impl StaticFilter for Listeners {
+    const NAME: &'static str = "quilkin.filters.listeners.v1alpha1.Listeners";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Listeners;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// Returns the ports of the listeners of the [`Listeners`] filters in
+/// `chain`, which the proxy binds besides its own port so packets sent to
+/// them are received at all.
+pub fn ports(chain: &FilterChain) -> std::collections::BTreeSet<u16> {
+    chain
+        .iter()
+        .filter(|filter| filter.name == Listeners::NAME)
+        .filter_map(|filter| serde_json::from_value::<Config>(filter.config?).ok())
+        .flat_map(|config| config.listeners)
+        .flat_map(|listener| listener.ports)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::endpoint::{metadata::Value, Endpoint},
+        test::alloc_buffer,
+    };
+
+    fn config() -> Config {
+        serde_yaml::from_str(
+            "
+listeners:
+    - ports: [7777]
+      filters:
+        - name: quilkin.filters.capture.v1alpha1.Capture
+          config:
+              metadataKey: game
+              suffix:
+                  size: 1
+                  remove: false
+      session_timeout_secs: 10
+      session_quota:
+          max_packets: 100
+    - ports: [7778]
+      filters:
+        - name: quilkin.filters.drop.v1alpha1.Drop
+fallthrough:
+    - name: quilkin.filters.capture.v1alpha1.Capture
+      config:
+          metadataKey: other
+          prefix:
+              size: 1
+              remove: false
+",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn selects_listener_by_port() {
+        let filter = Listeners::from_config(Some(config()));
+        let endpoints = crate::net::cluster::ClusterMap::new_default(
+            [Endpoint::new("127.0.0.1:81".parse().unwrap())].into(),
+        );
+        let source: EndpointAddress = "127.0.0.1:70".parse().unwrap();
+
+        let read = |port| {
+            let mut dest = Vec::new();
+            let mut ctx = ReadContext::new(
+                endpoints.clone().into(),
+                source.clone(),
+                alloc_buffer(b"abc"),
+                &mut dest,
+            );
+            ctx.destination_port = port;
+            filter.read(&mut ctx).map(|()| ctx.metadata)
+        };
+
+        let metadata = read(Some(7777)).unwrap();
+        assert_eq!(
+            metadata.get(&"game".into()),
+            Some(&Value::Bytes(b"c".to_vec().into()))
+        );
+        assert_eq!(
+            crate::net::session_timeout::get(&metadata),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            crate::net::session_quota::get(&metadata).and_then(|quota| quota.max_packets),
+            Some(100)
+        );
+
+        assert!(read(Some(7778)).is_err());
+
+        for port in [Some(9999), None] {
+            let metadata = read(port).unwrap();
+            assert_eq!(
+                metadata.get(&"other".into()),
+                Some(&Value::Bytes(b"a".to_vec().into()))
+            );
+            assert_eq!(crate::net::session_timeout::get(&metadata), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn write_uses_client_listener() {
+        let filter = Listeners::from_config(Some(config()));
+        let source: EndpointAddress = "127.0.0.1:70".parse().unwrap();
+
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            source.clone(),
+            alloc_buffer(b"abc"),
+            &mut dest,
+        );
+        ctx.destination_port = Some(7778);
+        assert!(filter.read(&mut ctx).is_err());
+
+        let mut ctx = WriteContext::new(
+            "127.0.0.1:81".parse().unwrap(),
+            source,
+            alloc_buffer(b"abc"),
+        );
+        assert!(filter.write(&mut ctx).is_err());
+
+        // Unknown clients use the fallthrough.
+        let mut ctx = WriteContext::new(
+            "127.0.0.1:81".parse().unwrap(),
+            "127.0.0.1:71".parse().unwrap(),
+            alloc_buffer(b"abc"),
+        );
+        assert!(filter.write(&mut ctx).is_ok());
+    }
+
+    #[test]
+    fn counts_listener_metrics() {
+        let metrics = ListenerMetrics {
+            namespace: Some("listener_test".into()),
+            labels: [("tenant".into(), "a".into())].into(),
+        };
+        let mut config = config();
+        config.listeners[1].metrics = Some(metrics.clone());
+        let filter = Listeners::from_config(Some(config));
+
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            "127.0.0.1:70".parse().unwrap(),
+            alloc_buffer(b"abc"),
+            &mut dest,
+        );
+        ctx.destination_port = Some(7778);
+        assert!(filter.read(&mut ctx).is_err());
+
+        let counters = metrics::Metrics::get(&metrics).unwrap();
+        assert_eq!(counters.packets_total(Direction::Read).get(), 1);
+        assert_eq!(
+            counters
+                .packets_dropped_total(Direction::Read, DropReason::Dropped)
+                .get(),
+            1
+        );
+
+        let invalid = ListenerMetrics {
+            labels: [("not-a-label".into(), "a".into())].into(),
+            ..<_>::default()
+        };
+        assert!(metrics::Metrics::get(&invalid).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_ports() {
+        let config = Config {
+            listeners: vec![
+                Listener {
+                    ports: vec![7777],
+                    filters: Vec::new(),
+                    session_timeout_secs: None,
+                    session_quota: None,
+                    metrics: None,
+                },
+                Listener {
+                    ports: vec![7777],
+                    filters: Vec::new(),
+                    session_timeout_secs: None,
+                    session_quota: None,
+                    metrics: None,
+                },
+            ],
+            fallthrough: Vec::new(),
+        };
+
+        assert!(Listeners::try_from_config(Some(config)).is_err());
+    }
+
+    #[test]
+    fn bound_ports() {
+        let chain = FilterChain::try_create([
+            crate::filters::Pass::as_filter_config(None).unwrap(),
+            Listeners::as_filter_config(config()).unwrap(),
+        ])
+        .unwrap();
+
+        assert_eq!(ports(&chain).into_iter().collect::<Vec<_>>(), [7777, 7778]);
+    }
+}
@@ -0,0 +1,281 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::proto;
+use crate::{
+    config::Filter,
+    filters::{ConvertProtoConfigError, CreationError},
+    net::session_quota::{QuotaAction, SessionQuota},
+};
+
+/// Configuration for [`Listeners`][super::Listeners].
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The virtual listeners, the first listener that contains the port a
+    /// packet was sent to is used.
+    pub listeners: Vec<Listener>,
+    /// The filters to run when no listener matches the port a packet was
+    /// sent to. Defaults to no filters.
+    #[serde(default)]
+    pub fallthrough: Vec<Filter>,
+}
+
+impl TryFrom<Config> for proto::Listeners {
+    type Error = CreationError;
+
+    fn try_from(config: Config) -> Result<Self, Self::Error> {
+        Ok(Self {
+            listeners: config
+                .listeners
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+            fallthrough: config
+                .fallthrough
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<proto::Listeners> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(value: proto::Listeners) -> Result<Self, Self::Error> {
+        Ok(Self {
+            listeners: value
+                .listeners
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+            fallthrough: value
+                .fallthrough
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, CreationError>>()
+                .map_err(|error| ConvertProtoConfigError::new(error, Some("fallthrough".into())))?,
+        })
+    }
+}
+
+/// A virtual listener, with the filters to run for packets sent to any of
+/// its ports.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Listener {
+    /// The destination ports handled by this listener.
+    pub ports: Vec<u16>,
+    /// The filters to run for packets sent to one of `ports`.
+    pub filters: Vec<Filter>,
+    /// How long in seconds the sessions of packets sent to this listener are
+    /// kept after their last packet, overriding the proxy's default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_timeout_secs: Option<u64>,
+    /// The limits on the lifetime usage of the sessions of packets sent to
+    /// this listener.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_quota: Option<SessionQuota>,
+    /// Counts the packets handled by this listener in metrics of their own,
+    /// named and labelled so they can be told apart from other listeners'.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<ListenerMetrics>,
+}
+
+/// The name and static labels of a listener's metrics.
+#[derive(
+    Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash, schemars::JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerMetrics {
+    /// Inserted after the proxy's namespace in the name of each of the
+    /// listener's metrics, such as `quilkin_<namespace>_listener_packets_total`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Static labels added to each of the listener's metrics, such as its
+    /// `tenant`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl TryFrom<Listener> for proto::listeners::Listener {
+    type Error = CreationError;
+
+    fn try_from(listener: Listener) -> Result<Self, Self::Error> {
+        Ok(Self {
+            ports: listener.ports.into_iter().map(u32::from).collect(),
+            filters: listener
+                .filters
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+            session_timeout_secs: listener.session_timeout_secs,
+            session_quota: listener.session_quota.map(From::from),
+            metrics: listener.metrics.map(From::from),
+        })
+    }
+}
+
+impl TryFrom<proto::listeners::Listener> for Listener {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(listener: proto::listeners::Listener) -> Result<Self, Self::Error> {
+        Ok(Self {
+            ports: listener
+                .ports
+                .into_iter()
+                .map(|port| {
+                    u16::try_from(port).map_err(|_| {
+                        ConvertProtoConfigError::new(
+                            format!("{port} is not a valid port"),
+                            Some("listeners.ports".into()),
+                        )
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            filters: listener
+                .filters
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, CreationError>>()
+                .map_err(|error| {
+                    ConvertProtoConfigError::new(error, Some("listeners.filters".into()))
+                })?,
+            session_timeout_secs: listener.session_timeout_secs,
+            session_quota: listener.session_quota.map(From::from),
+            metrics: listener.metrics.map(From::from),
+        })
+    }
+}
+
+impl From<ListenerMetrics> for proto::listeners::Metrics {
+    fn from(metrics: ListenerMetrics) -> Self {
+        Self {
+            namespace: metrics.namespace,
+            labels: metrics.labels.into_iter().collect(),
+        }
+    }
+}
+
+impl From<proto::listeners::Metrics> for ListenerMetrics {
+    fn from(metrics: proto::listeners::Metrics) -> Self {
+        Self {
+            namespace: metrics.namespace,
+            labels: metrics.labels.into_iter().collect(),
+        }
+    }
+}
+
+impl From<SessionQuota> for proto::listeners::SessionQuota {
+    fn from(quota: SessionQuota) -> Self {
+        let action = match quota.action {
+            QuotaAction::Terminate => proto::listeners::session_quota::Action::Terminate,
+            QuotaAction::Throttle => proto::listeners::session_quota::Action::Throttle,
+            QuotaAction::Event => proto::listeners::session_quota::Action::Event,
+        };
+
+        Self {
+            max_bytes: quota.max_bytes,
+            max_packets: quota.max_packets,
+            max_duration_secs: quota.max_duration_secs,
+            action: action as i32,
+            throttle_packets_per_sec: Some(quota.throttle_packets_per_sec),
+        }
+    }
+}
+
+impl From<proto::listeners::SessionQuota> for SessionQuota {
+    fn from(quota: proto::listeners::SessionQuota) -> Self {
+        let action = match quota.action() {
+            proto::listeners::session_quota::Action::Terminate => QuotaAction::Terminate,
+            proto::listeners::session_quota::Action::Throttle => QuotaAction::Throttle,
+            proto::listeners::session_quota::Action::Event => QuotaAction::Event,
+        };
+
+        Self {
+            max_bytes: quota.max_bytes,
+            max_packets: quota.max_packets,
+            max_duration_secs: quota.max_duration_secs,
+            action,
+            throttle_packets_per_sec: quota
+                .throttle_packets_per_sec
+                .unwrap_or(crate::net::session_quota::DEFAULT_THROTTLE_PACKETS_PER_SEC),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::StaticFilter;
+
+    #[test]
+    fn serde() {
+        let yaml = "
+listeners:
+    - ports: [7777, 7778]
+      filters:
+        - name: quilkin.filters.debug.v1alpha1.Debug
+      session_timeout_secs: 10
+      session_quota:
+        max_bytes: 1000000
+        action: throttle
+fallthrough:
+    - name: quilkin.filters.drop.v1alpha1.Drop
+        ";
+
+        let config = serde_yaml::from_str::<Config>(yaml).unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                listeners: vec![Listener {
+                    ports: vec![7777, 7778],
+                    filters: vec![crate::filters::Debug::as_filter_config(None).unwrap()],
+                    session_timeout_secs: Some(10),
+                    session_quota: Some(SessionQuota {
+                        max_bytes: Some(1_000_000),
+                        action: QuotaAction::Throttle,
+                        ..<_>::default()
+                    }),
+                    metrics: None,
+                }],
+                fallthrough: vec![crate::filters::Drop::as_filter_config(None).unwrap()],
+            }
+        );
+    }
+
+    #[test]
+    fn proto_rejects_invalid_ports() {
+        let proto = proto::Listeners {
+            listeners: vec![proto::listeners::Listener {
+                ports: vec![70000],
+                filters: Vec::new(),
+                session_timeout_secs: None,
+                session_quota: None,
+                metrics: None,
+            }],
+            fallthrough: Vec::new(),
+        };
+
+        assert!(Config::try_from(proto).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::{IntCounter, IntCounterVec, Opts};
+
+use super::config::ListenerMetrics;
+use crate::{
+    filters::{CreationError, DropReason},
+    metrics::{registry, Direction},
+};
+
+/// The metrics of every listener configuration, so listeners recreated by a
+/// configuration change keep counting in the metrics already registered.
+static METRICS: Lazy<Mutex<HashMap<ListenerMetrics, Arc<Metrics>>>> = Lazy::new(<_>::default);
+
+/// The metrics of a single listener.
+pub(super) struct Metrics {
+    packets: IntCounterVec,
+    dropped: IntCounterVec,
+}
+
+impl Metrics {
+    /// Returns the metrics named and labelled as in `config`, registering
+    /// them if they're new.
+    pub(super) fn get(config: &ListenerMetrics) -> Result<Arc<Self>, CreationError> {
+        let mut metrics = METRICS.lock();
+        if let Some(existing) = metrics.get(config) {
+            return Ok(existing.clone());
+        }
+
+        let invalid = |reason: String| CreationError::FieldInvalid {
+            field: "listeners.metrics".into(),
+            reason,
+        };
+        if let Some(name) = config
+            .namespace
+            .iter()
+            .chain(config.labels.keys())
+            .find(|name| !crate::metrics::is_valid_name(name))
+        {
+            return Err(invalid(format!("`{name}` is not a valid metric name")));
+        }
+
+        let opts = |name: &str, help: &str| {
+            Opts::new(name, help)
+                .namespace(config.namespace.clone().unwrap_or_default())
+                .const_labels(config.labels.clone().into_iter().collect())
+        };
+        let register = |opts: Opts, labels: &[&str]| {
+            let counter = IntCounterVec::new(opts, labels)?;
+            registry().register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+
+        let created = Arc::new(Self {
+            packets: register(
+                opts(
+                    "listener_packets_total",
+                    "Total number of packets handled by the listener",
+                ),
+                &[Direction::LABEL],
+            )
+            .map_err(|error: prometheus::Error| invalid(error.to_string()))?,
+            dropped: register(
+                opts(
+                    "listener_packets_dropped_total",
+                    "Total number of packets dropped by the listener's filters",
+                ),
+                &[Direction::LABEL, "reason"],
+            )
+            .map_err(|error: prometheus::Error| invalid(error.to_string()))?,
+        });
+        metrics.insert(config.clone(), created.clone());
+        Ok(created)
+    }
+
+    #[inline]
+    pub(super) fn packets_total(&self, direction: Direction) -> IntCounter {
+        self.packets.with_label_values(&[direction.label()])
+    }
+
+    #[inline]
+    pub(super) fn packets_dropped_total(
+        &self,
+        direction: Direction,
+        reason: DropReason,
+    ) -> IntCounter {
+        self.dropped
+            .with_label_values(&[direction.label(), reason.as_str()])
+    }
+}
@@ -19,7 +19,7 @@ use crate::generated::quilkin::filters::load_balancer::v1alpha1 as proto;
 mod config;
 mod endpoint_chooser;
 
-use crate::filters::prelude::*;
+use crate::{filters::prelude::*, net::endpoint::metadata::Key};
 use endpoint_chooser::EndpointChooser;
 
 pub use config::{Config, Policy};
@@ -27,21 +27,50 @@ pub use config::{Config, Policy};
 /// Balances packets over the upstream endpoints.
 pub struct LoadBalancer {
     endpoint_chooser: Box<dyn EndpointChooser>,
+    /// The metadata key overriding the policy, with a chooser for every
+    /// policy it can be overridden with.
+    policy_override: Option<(Key, Vec<(Policy, Box<dyn EndpointChooser>)>)>,
 }
 
 impl LoadBalancer {
-    fn new(config: Config) -> Self {
-        Self {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        config.overrides.validate(config::OVERRIDABLE)?;
+
+        Ok(Self {
             endpoint_chooser: config.policy.as_endpoint_chooser(),
-        }
+            policy_override: config.overrides.key("policy").map(|key| {
+                let choosers = Policy::ALL
+                    .into_iter()
+                    .map(|policy| (policy, policy.as_endpoint_chooser()))
+                    .collect();
+                (key, choosers)
+            }),
+        })
     }
 }
 
 impl Filter for LoadBalancer {
     fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
-        self.endpoint_chooser.choose_endpoints(ctx);
+        let overridden = self.policy_override.as_ref().and_then(|(key, choosers)| {
+            let policy = Policy::from_name(Overrides::string(Some(*key), &ctx.metadata)?)?;
+            choosers
+                .iter()
+                .find_map(|(candidate, chooser)| (*candidate == policy).then_some(chooser))
+        });
+
+        overridden
+            .unwrap_or(&self.endpoint_chooser)
+            .choose_endpoints(ctx);
         Ok(())
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            consumes: self.policy_override.iter().map(|(key, _)| *key).collect(),
+            sets_destinations: true,
+            ..<_>::default()
+        }
+    }
 }
 
 impl StaticFilter for LoadBalancer {
@@ -50,7 +79,7 @@ impl StaticFilter for LoadBalancer {
     type BinaryConfiguration = proto::LoadBalancer;
 
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
-        Ok(LoadBalancer::new(Self::ensure_config_exists(config)?))
+        LoadBalancer::new(Self::ensure_config_exists(config)?)
     }
 }
 
@@ -117,6 +146,53 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn weighted_round_robin_load_balancer_policy() {
+        let endpoints = crate::net::cluster::ClusterMap::new_default(
+            [
+                Endpoint {
+                    weight: 2,
+                    ..Endpoint::new(([127, 0, 0, 1], 8080).into())
+                },
+                Endpoint {
+                    weight: 0,
+                    ..Endpoint::new(([127, 0, 0, 2], 8080).into())
+                },
+                Endpoint::new(([127, 0, 0, 3], 8080).into()),
+            ]
+            .into(),
+        );
+        let endpoints = std::sync::Arc::new(endpoints);
+
+        let yaml = "policy: ROUND_ROBIN";
+        let filter = LoadBalancer::from_config(serde_yaml::from_str(yaml).unwrap());
+
+        let mut dest = Vec::new();
+        for _ in 0..6 {
+            let mut context = ReadContext::new(
+                endpoints.clone(),
+                "127.0.0.1:8080".parse().unwrap(),
+                alloc_buffer([]),
+                &mut dest,
+            );
+            filter.read(&mut context).unwrap();
+        }
+
+        let first: EndpointAddress = ([127, 0, 0, 1], 8080).into();
+        let third: EndpointAddress = ([127, 0, 0, 3], 8080).into();
+        assert_eq!(
+            dest,
+            vec![
+                first.clone(),
+                first.clone(),
+                third.clone(),
+                first.clone(),
+                first,
+                third
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn random_load_balancer_policy() {
         let addresses = vec![
@@ -256,4 +332,58 @@ policy: RANDOM
             "the same sequence of addresses were chosen for hash load balancer"
         );
     }
+
+    #[tokio::test]
+    async fn overridden_policy() {
+        let endpoints = std::sync::Arc::new(crate::net::cluster::ClusterMap::new_default(
+            [
+                Endpoint::new(([127, 0, 0, 1], 8080).into()),
+                Endpoint::new(([127, 0, 0, 2], 8080).into()),
+            ]
+            .into(),
+        ));
+
+        let yaml = "
+policy: ROUND_ROBIN
+overrides:
+  policy: myapp.com/policy
+";
+        let filter = LoadBalancer::from_config(serde_yaml::from_str(yaml).unwrap());
+        assert_eq!(
+            filter.capabilities().consumes,
+            vec![Key::from("myapp.com/policy")]
+        );
+
+        let read = |policy: Option<&str>| {
+            let mut dest = Vec::new();
+            let mut context = ReadContext::new(
+                endpoints.clone(),
+                (Ipv4Addr::LOCALHOST, 9999).into(),
+                alloc_buffer([]),
+                &mut dest,
+            );
+            if let Some(policy) = policy {
+                context.metadata.insert(
+                    "myapp.com/policy".into(),
+                    crate::net::endpoint::metadata::Value::String(policy.into()),
+                );
+            }
+            filter.read(&mut context).unwrap();
+            dest
+        };
+
+        // The same source is always hashed to the same endpoint.
+        let hashed = read(Some("HASH"));
+        for _ in 0..4 {
+            assert_eq!(read(Some("HASH")), hashed);
+        }
+
+        // Packets without a valid policy use the configured one.
+        assert_ne!(read(None), read(Some("UNKNOWN")));
+
+        assert!(LoadBalancer::try_from_config(Some(
+            serde_yaml::from_str("overrides:\n  weights: myapp.com/weights").unwrap()
+        ))
+        .is_err());
+    }
 }
@@ -21,6 +21,7 @@ use super::endpoint_chooser::{
     EndpointChooser, HashEndpointChooser, RandomEndpointChooser, RoundRobinEndpointChooser,
 };
 use super::proto;
+use crate::filters::Overrides;
 
 /// The configuration for [`load_balancer`][super].
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, JsonSchema)]
@@ -28,12 +29,20 @@ use super::proto;
 pub struct Config {
     #[serde(default)]
     pub policy: Policy,
+    /// The metadata key overriding `policy` for each packet, holding the
+    /// name of the policy such as `HASH`.
+    #[serde(default, skip_serializing_if = "Overrides::is_empty")]
+    pub overrides: Overrides,
 }
 
+/// The fields of [`Config`] that can be overridden.
+pub(super) const OVERRIDABLE: &[&str] = &["policy"];
+
 impl From<Config> for super::proto::LoadBalancer {
     fn from(config: Config) -> Self {
         Self {
             policy: Some(config.policy.into()),
+            overrides: config.overrides.into(),
         }
     }
 }
@@ -46,13 +55,14 @@ impl From<proto::LoadBalancer> for Config {
                 .map(|p| p.value())
                 .map(Policy::from)
                 .unwrap_or_default(),
+            overrides: p.overrides.into(),
         }
     }
 }
 
 /// Policy represents how a [`load_balancer`][super] distributes
 /// packets across endpoints.
-#[derive(Debug, Default, Deserialize, Serialize, Eq, PartialEq, JsonSchema)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, JsonSchema)]
 pub enum Policy {
     /// Send packets to endpoints in turns.
     #[serde(rename = "ROUND_ROBIN")]
@@ -61,12 +71,26 @@ pub enum Policy {
     /// Send packets to endpoints chosen at random.
     #[serde(rename = "RANDOM")]
     Random,
-    /// Send packets to endpoints based on hash of source IP and port.
+    /// Send packets to endpoints based on hash of source IP and port, and the
+    /// number of times the source asked to be rerouted.
     #[serde(rename = "HASH")]
     Hash,
 }
 
 impl Policy {
+    /// Every policy.
+    pub const ALL: [Self; 3] = [Self::RoundRobin, Self::Random, Self::Hash];
+
+    /// The policy with the name used in its configuration, such as `HASH`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ROUND_ROBIN" => Some(Self::RoundRobin),
+            "RANDOM" => Some(Self::Random),
+            "HASH" => Some(Self::Hash),
+            _ => None,
+        }
+    }
+
     pub fn as_endpoint_chooser(&self) -> Box<dyn EndpointChooser> {
         match self {
             Policy::RoundRobin => Box::new(RoundRobinEndpointChooser::new()),
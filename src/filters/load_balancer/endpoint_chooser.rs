@@ -31,7 +31,8 @@ pub trait EndpointChooser: Send + Sync {
     fn choose_endpoints(&self, endpoints: &mut ReadContext<'_>);
 }
 
-/// RoundRobinEndpointChooser chooses endpoints in round-robin order.
+/// RoundRobinEndpointChooser chooses endpoints in round-robin order, each
+/// endpoint being chosen as many times in a row as its weight.
 pub struct RoundRobinEndpointChooser {
     next_endpoint: AtomicUsize,
 }
@@ -47,42 +48,37 @@ impl RoundRobinEndpointChooser {
 impl EndpointChooser for RoundRobinEndpointChooser {
     fn choose_endpoints(&self, ctx: &mut ReadContext<'_>) {
         let count = self.next_endpoint.fetch_add(1, Ordering::Relaxed);
-        // Note: The index is guaranteed to be in range.
-        ctx.destinations.push(
-            ctx.endpoints
-                .nth_endpoint(count % ctx.endpoints.num_of_endpoints())
-                .unwrap()
-                .address
-                .clone(),
-        );
+        if let Some(endpoint) = ctx.endpoints.weighted_endpoint(count as u64) {
+            ctx.destinations.push(endpoint.address);
+        }
     }
 }
 
-/// RandomEndpointChooser chooses endpoints in random order.
+/// RandomEndpointChooser chooses endpoints in random order, in proportion to
+/// their weights.
 pub struct RandomEndpointChooser;
 
 impl EndpointChooser for RandomEndpointChooser {
     fn choose_endpoints(&self, ctx: &mut ReadContext<'_>) {
-        // The index is guaranteed to be in range.
-        let index = thread_rng().gen_range(0..ctx.endpoints.num_of_endpoints());
-        ctx.destinations
-            .push(ctx.endpoints.nth_endpoint(index).unwrap().address.clone());
+        if let Some(endpoint) = ctx.endpoints.weighted_endpoint(thread_rng().gen()) {
+            ctx.destinations.push(endpoint.address);
+        }
     }
 }
 
-/// HashEndpointChooser chooses endpoints based on a hash of source IP and port.
+/// HashEndpointChooser chooses endpoints based on a hash of source IP and port,
+/// along with the number of times the source asked to be rerouted, if any.
 pub struct HashEndpointChooser;
 
 impl EndpointChooser for HashEndpointChooser {
     fn choose_endpoints(&self, ctx: &mut ReadContext<'_>) {
         let mut hasher = DefaultHasher::new();
         ctx.source.hash(&mut hasher);
-        ctx.destinations.push(
-            ctx.endpoints
-                .nth_endpoint(hasher.finish() as usize % ctx.endpoints.num_of_endpoints())
-                .unwrap()
-                .address
-                .clone(),
-        );
+        if let Some(reroutes) = crate::net::reroute::get(&ctx.metadata) {
+            reroutes.hash(&mut hasher);
+        }
+        if let Some(endpoint) = ctx.endpoints.weighted_endpoint(hasher.finish()) {
+            ctx.destinations.push(endpoint.address);
+        }
     }
 }
@@ -23,7 +23,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     collections::ttl::{Entry, TtlMap},
     filters::prelude::*,
-    net::endpoint::EndpointAddress,
+    net::endpoint::{metadata::Key, EndpointAddress},
 };
 
 use crate::generated::quilkin::filters::local_rate_limit::v1alpha1 as proto;
@@ -54,6 +54,15 @@ struct Bucket {
     window_start_time_secs: Arc<AtomicU64>,
 }
 
+/// A [`Bucket`] exported to another proxy, whose clock may differ, so the
+/// start of its window is kept as how long ago it started.
+#[derive(Debug, Deserialize, Serialize)]
+struct ExportedBucket {
+    source: EndpointAddress,
+    packets: usize,
+    window_age_secs: u64,
+}
+
 /// A filter that implements rate limiting on packets based on the token-bucket
 /// algorithm.  Packets that violate the rate limit are dropped.  It only
 /// applies rate limiting on packets received from a downstream connection (processed
@@ -64,6 +73,9 @@ pub struct LocalRateLimit {
     state: TtlMap<EndpointAddress, Bucket>,
     /// Filter configuration.
     config: Config,
+    /// The metadata keys overriding `max_packets` and `period`.
+    max_packets_override: Option<Key>,
+    period_override: Option<Key>,
 }
 
 impl LocalRateLimit {
@@ -76,9 +88,12 @@ impl LocalRateLimit {
                 reason: "value must be at least 1 second".into(),
             });
         }
+        config.overrides.validate(OVERRIDABLE)?;
 
         Ok(LocalRateLimit {
             state: TtlMap::new(SESSION_TIMEOUT_SECONDS, SESSION_EXPIRY_POLL_INTERVAL),
+            max_packets_override: config.overrides.key("max_packets"),
+            period_override: config.overrides.key("period"),
             config,
         })
     }
@@ -89,8 +104,8 @@ impl LocalRateLimit {
     /// It returns whether there exists a token for the corresponding address in
     /// the current period - determining whether or not the packet should be
     /// forwarded or dropped.
-    fn acquire_token(&self, address: &EndpointAddress) -> bool {
-        if self.config.max_packets == 0 {
+    fn acquire_token(&self, address: &EndpointAddress, max_packets: usize, period: u64) -> bool {
+        if max_packets == 0 {
             return false;
         }
 
@@ -101,10 +116,10 @@ impl LocalRateLimit {
             let window_start_secs = bucket.value.window_start_time_secs.load(Ordering::Relaxed);
 
             let elapsed_secs = now_secs - window_start_secs;
-            let start_new_window = elapsed_secs > self.config.period as u64;
+            let start_new_window = elapsed_secs > period;
 
             // Check if allowing this packet will put us over the maximum.
-            if prev_count >= self.config.max_packets {
+            if prev_count >= max_packets {
                 // If so, then we can only allow the packet if the current time
                 // window has ended.
                 if !start_new_window {
@@ -149,12 +164,53 @@ impl LocalRateLimit {
 
 impl Filter for LocalRateLimit {
     fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
-        if self.acquire_token(&ctx.source) {
+        let max_packets = Overrides::number(self.max_packets_override, &ctx.metadata)
+            .map_or(self.config.max_packets, |max_packets| max_packets as usize);
+        let period = Overrides::number(self.period_override, &ctx.metadata)
+            .filter(|period| *period >= 1)
+            .unwrap_or(u64::from(self.config.period));
+
+        if self.acquire_token(&ctx.source, max_packets, period) {
             Ok(())
         } else {
             Err(FilterError::RateLimitExceeded)
         }
     }
+
+    fn export_state(&self) -> Option<serde_json::Value> {
+        let now_secs = self.state.now_relative_secs();
+        let buckets = self.state.snapshot(|source, bucket| ExportedBucket {
+            source: source.clone(),
+            packets: bucket.counter.load(Ordering::Relaxed),
+            window_age_secs: now_secs
+                .saturating_sub(bucket.window_start_time_secs.load(Ordering::Relaxed)),
+        });
+        serde_json::to_value(buckets).ok()
+    }
+
+    fn import_state(&self, state: serde_json::Value) -> Result<(), serde_json::Error> {
+        let buckets: Vec<ExportedBucket> = serde_json::from_value(state)?;
+        let now_secs = self.state.now_relative_secs();
+        for bucket in buckets {
+            self.state.insert(
+                bucket.source,
+                Bucket {
+                    counter: Arc::new(AtomicUsize::new(bucket.packets)),
+                    window_start_time_secs: Arc::new(AtomicU64::new(
+                        now_secs.saturating_sub(bucket.window_age_secs),
+                    )),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            consumes: self.config.overrides.keys().collect(),
+            ..<_>::default()
+        }
+    }
 }
 
 impl StaticFilter for LocalRateLimit {
@@ -176,8 +232,14 @@ pub struct Config {
     /// The duration in seconds during which max_packets applies. If none is provided, it
     /// defaults to one second.
     pub period: u32,
+    /// The metadata keys overriding `max_packets` or `period` for each packet.
+    #[serde(default, skip_serializing_if = "Overrides::is_empty")]
+    pub overrides: Overrides,
 }
 
+/// The fields of [`Config`] that can be overridden.
+const OVERRIDABLE: &[&str] = &["max_packets", "period"];
+
 /// default value for [`Config::period`]
 fn default_period() -> u32 {
     1
@@ -188,6 +250,7 @@ impl From<Config> for proto::LocalRateLimit {
         Self {
             max_packets: config.max_packets as u64,
             period: Some(config.period),
+            overrides: config.overrides.into(),
         }
     }
 }
@@ -199,6 +262,7 @@ impl TryFrom<proto::LocalRateLimit> for Config {
         Ok(Self {
             max_packets: p.max_packets as usize,
             period: p.period.unwrap_or_else(default_period),
+            overrides: p.overrides.into(),
         })
     }
 }
@@ -276,10 +340,12 @@ period: 0
                 proto::LocalRateLimit {
                     max_packets: 10,
                     period: Some(2),
+                    overrides: <_>::default(),
                 },
                 Some(Config {
                     max_packets: 10,
                     period: 2,
+                    overrides: <_>::default(),
                 }),
             ),
             (
@@ -287,10 +353,12 @@ period: 0
                 proto::LocalRateLimit {
                     max_packets: 10,
                     period: None,
+                    overrides: <_>::default(),
                 },
                 Some(Config {
                     max_packets: 10,
                     period: 1,
+                    overrides: <_>::default(),
                 }),
             ),
         ];
@@ -314,6 +382,7 @@ period: 0
         let r = rate_limiter(Config {
             max_packets: 3,
             period: 1,
+            overrides: <_>::default(),
         });
 
         let (address, _) = address_pair();
@@ -324,11 +393,32 @@ period: 0
         read(&r, &address, false);
     }
 
+    #[tokio::test]
+    async fn export_and_import_state() {
+        let config = || Config {
+            max_packets: 2,
+            period: 60,
+            overrides: <_>::default(),
+        };
+        let previous = rate_limiter(config());
+        let (address, other) = address_pair();
+        read(&previous, &address, true);
+        read(&previous, &address, true);
+
+        let next = rate_limiter(config());
+        next.import_state(previous.export_state().unwrap()).unwrap();
+
+        // The exhausted bucket carries over, other sources are unaffected.
+        read(&next, &address, false);
+        read(&next, &other, true);
+    }
+
     #[tokio::test]
     async fn filter_with_no_available_tokens() {
         let r = rate_limiter(Config {
             max_packets: 0,
             period: 1,
+            overrides: <_>::default(),
         });
 
         let (address, _) = address_pair();
@@ -347,6 +437,7 @@ period: 0
         let r = rate_limiter(Config {
             max_packets: 2,
             period: 1,
+            overrides: <_>::default(),
         });
 
         let (address1, address2) = address_pair();
@@ -382,6 +473,52 @@ period: 0
         assert_write_no_change(&r);
     }
 
+    #[tokio::test]
+    async fn overridden_max_packets() {
+        let r = rate_limiter(Config {
+            max_packets: 1,
+            period: 1,
+            overrides: serde_yaml::from_str("max_packets: myapp.com/max_packets").unwrap(),
+        });
+        assert!(LocalRateLimit::new(Config {
+            max_packets: 1,
+            period: 1,
+            overrides: serde_yaml::from_str("max_bytes: myapp.com/max_bytes").unwrap(),
+        })
+        .is_err());
+
+        let (premium, address) = address_pair();
+        let endpoints = std::sync::Arc::new(crate::net::cluster::ClusterMap::new_default(
+            [crate::net::endpoint::Endpoint::new(
+                (Ipv4Addr::LOCALHOST, 8089).into(),
+            )]
+            .into(),
+        ));
+        let read_premium = || {
+            let mut dest = Vec::new();
+            let mut context = ReadContext::new(
+                endpoints.clone(),
+                premium.clone(),
+                alloc_buffer([9]),
+                &mut dest,
+            );
+            context.metadata.insert(
+                "myapp.com/max_packets".into(),
+                crate::net::endpoint::metadata::Value::Number(3),
+            );
+            r.read(&mut context).is_ok()
+        };
+
+        assert!(read_premium());
+        assert!(read_premium());
+        assert!(read_premium());
+        assert!(!read_premium());
+
+        // Packets without the metadata use the configured limit.
+        read(&r, &address, true);
+        read(&r, &address, false);
+    }
+
     #[tokio::test]
     async fn max_token_refills_is_never_exceeded_for_partially_filled_buckets() {
         // Check that if a token bucket isn't being used up, continuous
@@ -391,6 +528,7 @@ period: 0
         let r = rate_limiter(Config {
             max_packets: 2,
             period: 1,
+            overrides: <_>::default(),
         });
 
         let (address, _) = address_pair();
@@ -0,0 +1,291 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Named, parameterized compositions of filters defined in the
+//! configuration, which can be referenced in any filter chain in place of a
+//! filter.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Filter as FilterConfig,
+    filters::{CreationError, FilterRegistry},
+};
+
+/// How deeply macros can reference other macros, so a macro referencing
+/// itself fails rather than expanding forever.
+const MAX_DEPTH: usize = 8;
+
+static MACROS: Lazy<ArcSwap<BTreeMap<String, FilterMacro>>> = Lazy::new(<_>::default);
+
+/// A named composition of filters, such as a firewall, rate limit and
+/// authentication filter used together, with parameters substituted into
+/// their configuration wherever it's referenced.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FilterMacro {
+    /// The parameters of the macro, with their default values. Parameters
+    /// without a default must be set wherever the macro is referenced.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, Option<serde_json::Value>>,
+    /// The filters the macro expands to. `${param}` in their configuration is
+    /// replaced with the value of `param`.
+    pub filters: Vec<FilterConfig>,
+}
+
+impl FilterMacro {
+    /// Returns the macro's filters, with `args` substituted for their
+    /// parameters.
+    fn instantiate(
+        &self,
+        name: &str,
+        args: Option<serde_json::Value>,
+    ) -> Result<Vec<FilterConfig>, CreationError> {
+        let invalid = |reason: String| CreationError::FieldInvalid {
+            field: name.into(),
+            reason,
+        };
+
+        let mut args = match args {
+            None | Some(serde_json::Value::Null) => serde_json::Map::new(),
+            Some(serde_json::Value::Object(args)) => args,
+            Some(_) => return Err(invalid("parameters must be a map".into())),
+        };
+
+        if let Some(unknown) = args.keys().find(|arg| !self.params.contains_key(*arg)) {
+            return Err(invalid(format!("unknown parameter `{unknown}`")));
+        }
+
+        let mut values = Vec::with_capacity(self.params.len());
+        for (param, default) in &self.params {
+            let value = args
+                .remove(param)
+                .or_else(|| default.clone())
+                .ok_or_else(|| invalid(format!("missing parameter `{param}`")))?;
+            values.push((format!("${{{param}}}"), value));
+        }
+
+        Ok(self
+            .filters
+            .iter()
+            .cloned()
+            .map(|mut filter| {
+                if let Some(config) = &mut filter.config {
+                    substitute(config, &values);
+                }
+                filter
+            })
+            .collect())
+    }
+}
+
+/// Replaces each placeholder in `value` with its value. Strings that are
+/// only a placeholder are replaced with the value itself, so parameters
+/// aren't limited to strings.
+fn substitute(value: &mut serde_json::Value, values: &[(String, serde_json::Value)]) {
+    match value {
+        serde_json::Value::String(string) => {
+            if let Some((_, replacement)) = values.iter().find(|(param, _)| param == string) {
+                *value = replacement.clone();
+                return;
+            }
+
+            for (param, replacement) in values {
+                if string.contains(param.as_str()) {
+                    let replacement = match replacement {
+                        serde_json::Value::String(replacement) => replacement.clone(),
+                        replacement => replacement.to_string(),
+                    };
+                    *string = string.replace(param.as_str(), &replacement);
+                }
+            }
+        }
+        serde_json::Value::Array(array) => {
+            for value in array {
+                substitute(value, values);
+            }
+        }
+        serde_json::Value::Object(object) => {
+            for value in object.values_mut() {
+                substitute(value, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The registry of the [`FilterMacro`]s filter chains can reference.
+#[derive(Debug)]
+pub struct FilterMacros;
+
+impl FilterMacros {
+    /// Replaces the registered macros with `macros`.
+    pub fn register(macros: BTreeMap<String, FilterMacro>) {
+        MACROS.store(Arc::new(macros));
+    }
+
+    /// Replaces each reference to a macro in `filters` with the filters it
+    /// expands to. Filters take precedence over macros with the same name.
+    pub fn expand(
+        filters: impl IntoIterator<Item = FilterConfig>,
+    ) -> Result<Vec<FilterConfig>, CreationError> {
+        let macros = MACROS.load();
+        let mut expanded = Vec::new();
+        for filter in filters {
+            Self::expand_into(&macros, filter, &mut expanded, 0)?;
+        }
+
+        Ok(expanded)
+    }
+
+    fn expand_into(
+        macros: &BTreeMap<String, FilterMacro>,
+        filter: FilterConfig,
+        expanded: &mut Vec<FilterConfig>,
+        depth: usize,
+    ) -> Result<(), CreationError> {
+        let Some(filter_macro) = macros
+            .get(&filter.name)
+            .filter(|_| FilterRegistry::get_factory(&filter.name).is_none())
+        else {
+            expanded.push(filter);
+            return Ok(());
+        };
+
+        if depth >= MAX_DEPTH {
+            return Err(CreationError::FieldInvalid {
+                field: filter.name,
+                reason: format!("macros can only be nested {MAX_DEPTH} deep"),
+            });
+        }
+
+        for mut inner in filter_macro.instantiate(&filter.name, filter.config)? {
+            if inner.label.is_none() {
+                inner.label.clone_from(&filter.label);
+            }
+            Self::expand_into(macros, inner, expanded, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn macros() -> BTreeMap<String, FilterMacro> {
+        serde_yaml::from_str(
+            "
+secure_ingress:
+  params:
+    max_packets: 100
+    token_key: null
+  filters:
+    - name: quilkin.filters.local_rate_limit.v1alpha1.LocalRateLimit
+      config:
+        max_packets: ${max_packets}
+        period: 1
+    - name: quilkin.filters.capture.v1alpha1.Capture
+      config:
+        metadataKey: ${token_key}
+        suffix:
+          size: 3
+          remove: true
+token_ingress:
+  filters:
+    - name: secure_ingress
+      config:
+        token_key: myapp.com/${token_key}
+  params:
+    token_key: token
+looping:
+  filters:
+    - name: looping
+",
+        )
+        .unwrap()
+    }
+
+    fn filter(yaml: &str) -> FilterConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn substitutes_parameters() {
+        let macros = macros();
+        let filters = macros["secure_ingress"]
+            .instantiate(
+                "secure_ingress",
+                Some(serde_json::json!({ "token_key": "myapp.com/token" })),
+            )
+            .unwrap();
+
+        assert_eq!(
+            filters[0].config.as_ref().unwrap()["max_packets"],
+            serde_json::json!(100)
+        );
+        assert_eq!(
+            filters[1].config.as_ref().unwrap()["metadataKey"],
+            serde_json::json!("myapp.com/token")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let macros = macros();
+        let secure_ingress = &macros["secure_ingress"];
+
+        assert!(secure_ingress.instantiate("secure_ingress", None).is_err());
+        assert!(secure_ingress
+            .instantiate(
+                "secure_ingress",
+                Some(serde_json::json!({ "token_key": "token", "other": 1 })),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn expands_nested_macros() {
+        let macros = macros();
+        let mut expanded = Vec::new();
+
+        FilterMacros::expand_into(
+            &macros,
+            filter("name: token_ingress\nlabel: game"),
+            &mut expanded,
+            0,
+        )
+        .unwrap();
+        assert_eq!(expanded.len(), 2);
+        assert_eq!(
+            expanded[1].config.as_ref().unwrap()["metadataKey"],
+            serde_json::json!("myapp.com/token")
+        );
+        assert!(expanded
+            .iter()
+            .all(|filter| filter.label.as_deref() == Some("game")));
+
+        assert!(
+            FilterMacros::expand_into(&macros, filter("name: looping"), &mut Vec::new(), 0)
+                .is_err()
+        );
+    }
+}
@@ -140,6 +140,29 @@ impl Filter for Match {
             |ctx, instance| instance.filter().write(ctx),
         )
     }
+
+    fn capabilities(&self) -> Capabilities {
+        let Some(config) = &self.on_read_filters else {
+            return Capabilities::default();
+        };
+
+        // Only one branch runs, so the match may need or provide anything
+        // that any of its branches do.
+        let branches = config
+            .branches
+            .iter()
+            .map(|(_, (_, instance))| instance)
+            .chain(std::iter::once(&config.fallthrough.1))
+            .fold(Capabilities::default(), |capabilities, instance| {
+                capabilities.union(instance.filter().capabilities())
+            });
+
+        Capabilities {
+            consumes: vec![config.metadata_key],
+            ..<_>::default()
+        }
+        .union(branches)
+    }
 }
 
 impl StaticFilter for Match {
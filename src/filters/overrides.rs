@@ -0,0 +1,135 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    filters::CreationError,
+    net::endpoint::metadata::{DynamicMetadata, Key, Value},
+};
+
+/// The fields of a filter's configuration that are overridden for each
+/// packet by the dynamic metadata set by earlier filters, mapping each field
+/// to the metadata key holding its value, such as a higher rate limit for
+/// clients whose token marks them as premium.
+///
+/// Filters only allow the fields they list to be overridden, see
+/// [`Overrides::validate`]. Packets without a valid value under a field's key
+/// use the value in the configuration.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, schemars::JsonSchema)]
+#[serde(transparent)]
+pub struct Overrides(BTreeMap<String, Key>);
+
+impl Overrides {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns an error if a field is overridden that isn't in `overridable`.
+    pub fn validate(&self, overridable: &[&str]) -> Result<(), CreationError> {
+        let unknown = |field: &&String| !overridable.contains(&field.as_str());
+        match self.0.keys().find(unknown) {
+            Some(field) => Err(CreationError::FieldInvalid {
+                field: "overrides".into(),
+                reason: format!(
+                    "`{field}` can't be overridden, only {}",
+                    overridable.join(", ")
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The metadata key overriding `field`, if it's overridden.
+    #[inline]
+    pub fn key(&self, field: &str) -> Option<Key> {
+        self.0.get(field).copied()
+    }
+
+    /// The metadata keys of every overridden field.
+    pub fn keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.0.values().copied()
+    }
+
+    /// The number under `key` in `metadata`, parsing strings, if `key` is
+    /// set.
+    #[inline]
+    pub fn number(key: Option<Key>, metadata: &DynamicMetadata) -> Option<u64> {
+        match metadata.get(&key?)? {
+            Value::Number(number) => Some(*number),
+            Value::String(string) => string.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// The string under `key` in `metadata`, if `key` is set.
+    #[inline]
+    pub fn string(key: Option<Key>, metadata: &DynamicMetadata) -> Option<&str> {
+        metadata.get(&key?)?.as_string()
+    }
+}
+
+impl From<HashMap<String, String>> for Overrides {
+    fn from(overrides: HashMap<String, String>) -> Self {
+        Self(
+            overrides
+                .into_iter()
+                .map(|(field, key)| (field, key.into()))
+                .collect(),
+        )
+    }
+}
+
+impl From<Overrides> for HashMap<String, String> {
+    fn from(overrides: Overrides) -> Self {
+        overrides
+            .0
+            .into_iter()
+            .map(|(field, key)| (field, key.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let overrides: Overrides =
+            serde_yaml::from_str("max_packets: myapp.com/max_packets").unwrap();
+        assert!(overrides.validate(&["max_packets", "period"]).is_ok());
+        assert!(overrides.validate(&["period"]).is_err());
+    }
+
+    #[test]
+    fn values() {
+        let overrides: Overrides =
+            serde_yaml::from_str("max_packets: myapp.com/max_packets\nperiod: myapp.com/period")
+                .unwrap();
+        let mut metadata = DynamicMetadata::default();
+        metadata.insert("myapp.com/max_packets".into(), Value::Number(100));
+        metadata.insert("myapp.com/period".into(), Value::String("5".into()));
+
+        let number = |field| Overrides::number(overrides.key(field), &metadata);
+        assert_eq!(number("max_packets"), Some(100));
+        assert_eq!(number("period"), Some(5));
+        assert_eq!(number("policy"), None);
+    }
+}
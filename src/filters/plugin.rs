@@ -0,0 +1,839 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+//! Loading filters from shared libraries at runtime.
+//!
+//! A plugin is a shared library (`.so`, `.dylib`, or `.dll`) exporting a
+//! function named [`ENTRYPOINT`], which returns a pointer to a
+//! [`PluginDescriptor`] that lives for the lifetime of the library. All of the
+//! types shared with plugins use the C ABI, so plugins can be written in any
+//! language, or with a different version of the Rust compiler.
+
+use std::{
+    ffi::{c_char, c_void, CStr},
+    net::SocketAddr,
+    path::Path,
+    ptr::NonNull,
+    sync::Arc,
+};
+
+use crate::config::ConfigType;
+use crate::filters::{prelude::*, DynFilterFactory, FilterFactory, FilterRegistry};
+use crate::net::{
+    endpoint::{metadata, metadata::DynamicMetadata, EndpointAddress},
+    ClusterMap,
+};
+use crate::pool::PoolBuffer;
+
+/// The version of the plugin ABI, plugins should set
+/// [`PluginDescriptor::abi_version`] to this value.
+///
+/// Version 2 added the fields after [`Packet::set_contents`], which give
+/// plugins access to the endpoints, destinations and dynamic metadata of a
+/// packet. Plugins built for version 1 are still loaded, as they only use the
+/// fields before them.
+pub const ABI_VERSION: u32 = 2;
+
+/// The oldest version of the plugin ABI that is still loaded.
+pub const MIN_ABI_VERSION: u32 = 1;
+
+/// The name of the function plugins export to provide their
+/// [`PluginDescriptor`], with the signature
+/// `extern "C" fn() -> *const PluginDescriptor`. The descriptor hasn't
+/// changed since version 1 of the ABI.
+pub const ENTRYPOINT: &str = "quilkin_filter_plugin_v1";
+
+/// The maximum length of an error message returned when creating a filter.
+const ERROR_CAPACITY: usize = 1024;
+
+/// Describes the filter provided by a plugin.
+#[repr(C)]
+pub struct PluginDescriptor {
+    /// The version of the ABI the plugin was built for, from
+    /// [`MIN_ABI_VERSION`] to [`ABI_VERSION`].
+    pub abi_version: u32,
+    /// The NUL terminated name of the filter, e.g. `acme.filters.router.v1.Router`.
+    pub name: *const c_char,
+    /// An optional NUL terminated JSON schema for the filter's configuration.
+    pub config_schema: *const c_char,
+    /// Creates a new instance of the filter from its JSON configuration, which
+    /// is `null` if no configuration was provided. Returns a null pointer on
+    /// failure, after writing a NUL terminated reason into `error`.
+    pub create: unsafe extern "C" fn(
+        config: *const u8,
+        config_len: usize,
+        error: *mut c_char,
+        error_capacity: usize,
+    ) -> *mut c_void,
+    /// Destroys an instance created by `create`.
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+    /// Called for every packet received from a downstream client.
+    pub read: unsafe extern "C" fn(instance: *mut c_void, packet: *mut Packet) -> Action,
+    /// Called for every packet received from an upstream endpoint.
+    pub write: unsafe extern "C" fn(instance: *mut c_void, packet: *mut Packet) -> Action,
+}
+
+// SAFETY: the descriptor only references data and functions that live as
+// long as the library, and the plugin ABI requires its functions to be safe to
+// call from any thread.
+unsafe impl Send for PluginDescriptor {}
+// SAFETY: see above.
+unsafe impl Sync for PluginDescriptor {}
+
+/// The outcome of a plugin processing a packet.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Continue processing the packet.
+    Pass = 0,
+    /// Drop the packet.
+    Drop = 1,
+}
+
+/// An IP address and port, IPv4 addresses are represented as IPv4-mapped
+/// IPv6 addresses.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Address {
+    pub ip: [u8; 16],
+    pub port: u16,
+}
+
+impl From<Address> for EndpointAddress {
+    fn from(address: Address) -> Self {
+        let ip = std::net::Ipv6Addr::from(address.ip).to_canonical();
+        (ip, address.port).into()
+    }
+}
+
+impl From<&EndpointAddress> for Address {
+    fn from(address: &EndpointAddress) -> Self {
+        match address.to_socket_addr() {
+            Ok(SocketAddr::V4(addr)) => Self {
+                ip: addr.ip().to_ipv6_mapped().octets(),
+                port: addr.port(),
+            },
+            Ok(SocketAddr::V6(addr)) => Self {
+                ip: addr.ip().octets(),
+                port: addr.port(),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// A packet being processed by a plugin.
+#[repr(C)]
+pub struct Packet {
+    /// The address the packet was received from.
+    pub source: Address,
+    /// The address the packet is being sent to, zeroed when reading as the
+    /// destination hasn't been decided yet.
+    pub dest: Address,
+    /// The contents of the packet, valid until `set_contents` is called or
+    /// the callback returns.
+    pub contents: *const u8,
+    pub contents_len: usize,
+    /// Opaque handle to pass to `set_contents`.
+    pub handle: *mut c_void,
+    /// Replaces the contents of the packet.
+    pub set_contents: unsafe extern "C" fn(handle: *mut c_void, data: *const u8, len: usize),
+    /// Opaque handle to pass to the functions below. Added in version 2 of
+    /// the ABI.
+    pub context: *mut c_void,
+    /// Returns the addresses of every endpoint, writing how many there are
+    /// into `len`, none when writing. Valid until the callback returns.
+    pub endpoints: unsafe extern "C" fn(context: *mut c_void, len: *mut usize) -> *const Address,
+    /// Returns the addresses the packet is sent to, writing how many there
+    /// are into `len`, none when writing. Valid until the destinations are
+    /// changed or the callback returns.
+    pub destinations: unsafe extern "C" fn(context: *mut c_void, len: *mut usize) -> *const Address,
+    /// Adds an address the packet is sent to, returning false when writing,
+    /// as packets are only sent to their `dest`.
+    pub add_destination:
+        unsafe extern "C" fn(context: *mut c_void, address: *const Address) -> bool,
+    /// Removes every address the packet is sent to.
+    pub clear_destinations: unsafe extern "C" fn(context: *mut c_void),
+    /// Returns the string or bytes value of the NUL terminated `key` in the
+    /// packet's dynamic metadata, writing its length into `len`, or null if
+    /// there is none. Valid until the metadata is changed or the callback
+    /// returns.
+    pub get_metadata: unsafe extern "C" fn(
+        context: *mut c_void,
+        key: *const c_char,
+        len: *mut usize,
+    ) -> *const u8,
+    /// Sets the NUL terminated `key` in the packet's dynamic metadata to the
+    /// bytes of `data`, which other filters can read.
+    pub set_metadata:
+        unsafe extern "C" fn(context: *mut c_void, key: *const c_char, data: *const u8, len: usize),
+}
+
+unsafe extern "C" fn set_contents(handle: *mut c_void, data: *const u8, len: usize) {
+    // SAFETY: the handle is always the buffer passed in `Packet::new`, and
+    // the plugin guarantees `data` is valid for `len` bytes. The data is
+    // copied first as it may point into the buffer being replaced.
+    unsafe {
+        let contents = &mut *handle.cast::<PoolBuffer>();
+        let data = std::slice::from_raw_parts(data, len).to_vec();
+        contents.truncate(0);
+        contents.extend_from_slice(&data);
+    }
+}
+
+/// What plugins can access of a packet besides its contents, through the
+/// functions in [`Packet`].
+struct Context<'ctx> {
+    endpoints: Option<&'ctx ClusterMap>,
+    destinations: Option<&'ctx mut Vec<EndpointAddress>>,
+    metadata: &'ctx mut DynamicMetadata,
+    /// The addresses last returned to the plugin, kept until the callback
+    /// returns.
+    endpoint_addresses: Option<Vec<Address>>,
+    destination_addresses: Vec<Address>,
+}
+
+impl<'ctx> Context<'ctx> {
+    /// # Safety
+    /// `context` must be the context passed in `Packet::new`.
+    unsafe fn from_raw<'a>(context: *mut c_void) -> &'a mut Self {
+        // SAFETY: see above.
+        unsafe { &mut *context.cast::<Self>() }
+    }
+}
+
+/// # Safety
+/// `key` must be NUL terminated.
+unsafe fn metadata_key(key: *const c_char) -> Option<metadata::Key> {
+    // SAFETY: see above.
+    unsafe { CStr::from_ptr(key) }
+        .to_str()
+        .ok()
+        .map(metadata::Key::new)
+}
+
+unsafe extern "C" fn endpoints(context: *mut c_void, len: *mut usize) -> *const Address {
+    // SAFETY: the context is always the one passed in `Packet::new`, and the
+    // plugin guarantees `len` is valid.
+    unsafe {
+        let context = Context::from_raw(context);
+        let addresses = context.endpoint_addresses.get_or_insert_with(|| {
+            context
+                .endpoints
+                .map(|endpoints| {
+                    endpoints
+                        .endpoints()
+                        .iter()
+                        .map(|endpoint| Address::from(&endpoint.address))
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+        *len = addresses.len();
+        addresses.as_ptr()
+    }
+}
+
+unsafe extern "C" fn destinations(context: *mut c_void, len: *mut usize) -> *const Address {
+    // SAFETY: see `endpoints`.
+    unsafe {
+        let context = Context::from_raw(context);
+        context.destination_addresses = context
+            .destinations
+            .iter()
+            .flat_map(|destinations| destinations.iter())
+            .map(Address::from)
+            .collect();
+        *len = context.destination_addresses.len();
+        context.destination_addresses.as_ptr()
+    }
+}
+
+unsafe extern "C" fn add_destination(context: *mut c_void, address: *const Address) -> bool {
+    // SAFETY: see `endpoints`, and the plugin guarantees `address` is valid.
+    unsafe {
+        let Some(destinations) = &mut Context::from_raw(context).destinations else {
+            return false;
+        };
+        destinations.push((*address).into());
+        true
+    }
+}
+
+unsafe extern "C" fn clear_destinations(context: *mut c_void) {
+    // SAFETY: see `endpoints`.
+    if let Some(destinations) = unsafe { &mut Context::from_raw(context).destinations } {
+        destinations.clear();
+    }
+}
+
+unsafe extern "C" fn get_metadata(
+    context: *mut c_void,
+    key: *const c_char,
+    len: *mut usize,
+) -> *const u8 {
+    // SAFETY: see `endpoints`, and the plugin guarantees `key` is NUL
+    // terminated.
+    unsafe {
+        let context = Context::from_raw(context);
+        let value = metadata_key(key).and_then(|key| context.metadata.get(&key));
+        let bytes: &[u8] = match value {
+            Some(metadata::Value::String(value)) => value.as_bytes(),
+            Some(metadata::Value::Bytes(value)) => value,
+            _ => return std::ptr::null(),
+        };
+        *len = bytes.len();
+        bytes.as_ptr()
+    }
+}
+
+unsafe extern "C" fn set_metadata(
+    context: *mut c_void,
+    key: *const c_char,
+    data: *const u8,
+    len: usize,
+) {
+    // SAFETY: see `get_metadata`, and the plugin guarantees `data` is valid
+    // for `len` bytes.
+    unsafe {
+        let context = Context::from_raw(context);
+        if let Some(key) = metadata_key(key) {
+            let data = bytes::Bytes::copy_from_slice(std::slice::from_raw_parts(data, len));
+            context.metadata.insert(key, metadata::Value::Bytes(data));
+        }
+    }
+}
+
+impl Packet {
+    fn new(
+        source: Address,
+        dest: Address,
+        contents: &mut PoolBuffer,
+        context: &mut Context<'_>,
+    ) -> Self {
+        Self {
+            source,
+            dest,
+            contents: contents.as_ptr(),
+            contents_len: contents.len(),
+            handle: (contents as *mut PoolBuffer).cast(),
+            set_contents,
+            context: (context as *mut Context<'_>).cast(),
+            endpoints,
+            destinations,
+            add_destination,
+            clear_destinations,
+            get_metadata,
+            set_metadata,
+        }
+    }
+}
+
+/// A loaded plugin library.
+struct Plugin {
+    descriptor: &'static PluginDescriptor,
+    // Kept last so it is only unloaded after everything referencing it.
+    _library: libloading::Library,
+}
+
+/// Loads every plugin in `paths`, and registers the filters they provide in
+/// the [`FilterRegistry`].
+pub fn load(paths: &[impl AsRef<Path>]) -> eyre::Result<()> {
+    let factories = paths
+        .iter()
+        .map(|path| PluginFactory::load(path.as_ref()).map(|f| Box::new(f) as DynFilterFactory))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    FilterRegistry::register(factories);
+    Ok(())
+}
+
+/// A [`FilterFactory`] for a filter loaded from a shared library.
+pub struct PluginFactory {
+    plugin: Arc<Plugin>,
+    name: &'static str,
+    schema: schemars::schema::RootSchema,
+}
+
+impl PluginFactory {
+    /// Loads the plugin at `path`.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        // SAFETY: loading a library runs its initialisers, plugins are
+        // trusted in the same way as the quilkin binary itself.
+        let library = unsafe { libloading::Library::new(path) }
+            .map_err(|error| eyre::eyre!("failed to load plugin `{}`: {error}", path.display()))?;
+
+        // SAFETY: the entrypoint's signature is defined by the plugin ABI, and
+        // the descriptor lives as long as the library, which is kept alive by
+        // every factory and filter referencing it.
+        let descriptor: &'static PluginDescriptor = unsafe {
+            let entrypoint = library
+                .get::<unsafe extern "C" fn() -> *const PluginDescriptor>(ENTRYPOINT.as_bytes())
+                .map_err(|error| {
+                    eyre::eyre!(
+                        "plugin `{}` does not export `{ENTRYPOINT}`: {error}",
+                        path.display()
+                    )
+                })?;
+            entrypoint().as_ref()
+        }
+        .ok_or_else(|| eyre::eyre!("plugin `{}` returned no descriptor", path.display()))?;
+
+        let factory = Self::from_descriptor(descriptor, library)
+            .map_err(|error| eyre::eyre!("invalid plugin `{}`: {error}", path.display()))?;
+        tracing::info!(path = %path.display(), filter = %factory.name, "loaded filter plugin");
+        Ok(factory)
+    }
+
+    fn from_descriptor(
+        descriptor: &'static PluginDescriptor,
+        library: libloading::Library,
+    ) -> eyre::Result<Self> {
+        eyre::ensure!(
+            (MIN_ABI_VERSION..=ABI_VERSION).contains(&descriptor.abi_version),
+            "uses ABI version {}, expected {MIN_ABI_VERSION} to {ABI_VERSION}",
+            descriptor.abi_version,
+        );
+        eyre::ensure!(!descriptor.name.is_null(), "no filter name provided");
+
+        // SAFETY: checked for null above, and the plugin guarantees the name
+        // is NUL terminated.
+        let name = unsafe { CStr::from_ptr(descriptor.name) }
+            .to_str()?
+            .to_owned();
+
+        let schema = if descriptor.config_schema.is_null() {
+            schemars::schema_for!(serde_json::Value)
+        } else {
+            // SAFETY: checked for null above, and the plugin guarantees the
+            // schema is NUL terminated.
+            serde_json::from_slice(unsafe { CStr::from_ptr(descriptor.config_schema) }.to_bytes())?
+        };
+
+        Ok(Self {
+            plugin: Arc::new(Plugin {
+                descriptor,
+                _library: library,
+            }),
+            // Factories are registered for the lifetime of the process.
+            name: Box::leak(name.into_boxed_str()),
+            schema,
+        })
+    }
+}
+
+impl FilterFactory for PluginFactory {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn config_schema(&self) -> schemars::schema::RootSchema {
+        self.schema.clone()
+    }
+
+    fn create_filter(&self, args: CreateFilterArgs) -> Result<FilterInstance, CreationError> {
+        let config = match args.config {
+            Some(ConfigType::Static(config)) => config,
+            Some(ConfigType::Dynamic(config)) => self.encode_config_to_json(config)?,
+            None => serde_json::Value::Null,
+        };
+
+        let encoded = serde_json::to_vec(&config)?;
+        let mut error = vec![0 as c_char; ERROR_CAPACITY];
+
+        // SAFETY: the configuration and error buffers are valid for the
+        // duration of the call.
+        let instance = unsafe {
+            (self.plugin.descriptor.create)(
+                encoded.as_ptr(),
+                encoded.len(),
+                error.as_mut_ptr(),
+                error.len(),
+            )
+        };
+
+        let Some(instance) = NonNull::new(instance) else {
+            // Guarantee the reason is terminated even if the plugin didn't.
+            error[ERROR_CAPACITY - 1] = 0;
+            // SAFETY: the buffer is NUL terminated.
+            let reason = unsafe { CStr::from_ptr(error.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            return Err(CreationError::FieldInvalid {
+                field: self.name.into(),
+                reason,
+            });
+        };
+
+        Ok(FilterInstance::new(
+            config,
+            PluginFilter {
+                instance,
+                plugin: self.plugin.clone(),
+            }
+            .into(),
+        ))
+    }
+
+    fn encode_config_to_protobuf(
+        &self,
+        config: serde_json::Value,
+    ) -> Result<prost_types::Any, CreationError> {
+        Ok(prost_types::Any {
+            type_url: self.name.into(),
+            value: serde_json::to_vec(&config)?,
+        })
+    }
+
+    fn encode_config_to_json(
+        &self,
+        config: prost_types::Any,
+    ) -> Result<serde_json::Value, CreationError> {
+        if self.name != config.type_url {
+            return Err(CreationError::MismatchedTypes {
+                expected: self.name.into(),
+                actual: config.type_url,
+            });
+        }
+
+        Ok(serde_json::from_slice(&config.value)?)
+    }
+}
+
+/// A filter instance created by a plugin.
+pub struct PluginFilter {
+    instance: NonNull<c_void>,
+    plugin: Arc<Plugin>,
+}
+
+// SAFETY: the plugin ABI requires filter instances to be safe to use from
+// multiple threads concurrently.
+unsafe impl Send for PluginFilter {}
+// SAFETY: see above.
+unsafe impl Sync for PluginFilter {}
+
+impl PluginFilter {
+    #[inline]
+    fn call(
+        &self,
+        callback: unsafe extern "C" fn(*mut c_void, *mut Packet) -> Action,
+        source: &EndpointAddress,
+        dest: Address,
+        contents: &mut PoolBuffer,
+        mut context: Context<'_>,
+    ) -> Result<(), FilterError> {
+        let mut packet = Packet::new(source.into(), dest, contents, &mut context);
+        // SAFETY: the instance is valid until dropped, and the packet only
+        // references `contents` and `context` which outlive the call.
+        match unsafe { callback(self.instance.as_ptr(), &mut packet) } {
+            Action::Pass => Ok(()),
+            Action::Drop => Err(FilterError::Dropped),
+        }
+    }
+}
+
+impl Filter for PluginFilter {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip_all))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let context = Context {
+            endpoints: Some(&*ctx.endpoints),
+            destinations: Some(&mut *ctx.destinations),
+            metadata: &mut ctx.metadata,
+            endpoint_addresses: None,
+            destination_addresses: Vec::new(),
+        };
+        self.call(
+            self.plugin.descriptor.read,
+            &ctx.source,
+            Address::default(),
+            &mut ctx.contents,
+            context,
+        )
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip_all))]
+    fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let dest = Address::from(&ctx.dest);
+        let context = Context {
+            endpoints: None,
+            destinations: None,
+            metadata: &mut ctx.metadata,
+            endpoint_addresses: None,
+            destination_addresses: Vec::new(),
+        };
+        self.call(
+            self.plugin.descriptor.write,
+            &ctx.source,
+            dest,
+            &mut ctx.contents,
+            context,
+        )
+    }
+}
+
+impl Drop for PluginFilter {
+    fn drop(&mut self) {
+        // SAFETY: the instance was created by this plugin and is destroyed
+        // exactly once.
+        unsafe { (self.plugin.descriptor.destroy)(self.instance.as_ptr()) }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    /// A plugin that drops empty packets and reverses everything else,
+    /// configured with `{ "suffix": <string> }` appended on read.
+    mod reverse {
+        use super::*;
+
+        pub struct Instance {
+            pub suffix: Vec<u8>,
+        }
+
+        pub unsafe extern "C" fn create(
+            config: *const u8,
+            config_len: usize,
+            error: *mut c_char,
+            error_capacity: usize,
+        ) -> *mut c_void {
+            let config: serde_json::Value =
+                serde_json::from_slice(std::slice::from_raw_parts(config, config_len)).unwrap();
+            let Some(suffix) = config.get("suffix").and_then(|s| s.as_str()) else {
+                let reason = b"missing suffix\0";
+                std::ptr::copy_nonoverlapping(
+                    reason.as_ptr().cast(),
+                    error,
+                    reason.len().min(error_capacity),
+                );
+                return std::ptr::null_mut();
+            };
+
+            Box::into_raw(Box::new(Instance {
+                suffix: suffix.as_bytes().to_vec(),
+            }))
+            .cast()
+        }
+
+        pub unsafe extern "C" fn destroy(instance: *mut c_void) {
+            drop(Box::from_raw(instance.cast::<Instance>()));
+        }
+
+        pub unsafe extern "C" fn read(instance: *mut c_void, packet: *mut Packet) -> Action {
+            let instance = &*instance.cast::<Instance>();
+            let packet = &mut *packet;
+            if packet.contents_len == 0 {
+                return Action::Drop;
+            }
+
+            let mut contents =
+                std::slice::from_raw_parts(packet.contents, packet.contents_len).to_vec();
+            contents.reverse();
+            contents.extend_from_slice(&instance.suffix);
+            (packet.set_contents)(packet.handle, contents.as_ptr(), contents.len());
+            Action::Pass
+        }
+
+        pub unsafe extern "C" fn write(_: *mut c_void, packet: *mut Packet) -> Action {
+            let packet = &mut *packet;
+            assert_eq!(packet.dest.port, 7000);
+            Action::Pass
+        }
+    }
+
+    /// A plugin that sends packets to the last endpoint, tagging them with
+    /// the `test.plugin.routed` metadata key.
+    mod router {
+        use super::*;
+
+        pub unsafe extern "C" fn create(
+            _: *const u8,
+            _: usize,
+            _: *mut c_char,
+            _: usize,
+        ) -> *mut c_void {
+            Box::into_raw(Box::new(())).cast()
+        }
+
+        pub unsafe extern "C" fn destroy(instance: *mut c_void) {
+            drop(Box::from_raw(instance.cast::<()>()));
+        }
+
+        pub unsafe extern "C" fn read(_: *mut c_void, packet: *mut Packet) -> Action {
+            let packet = &mut *packet;
+            let mut len = 0;
+            let endpoints = (packet.endpoints)(packet.context, &mut len);
+            let Some(last) = std::slice::from_raw_parts(endpoints, len).last() else {
+                return Action::Drop;
+            };
+
+            (packet.clear_destinations)(packet.context);
+            assert!((packet.add_destination)(packet.context, last));
+            let destinations = (packet.destinations)(packet.context, &mut len);
+            assert_eq!(std::slice::from_raw_parts(destinations, len), [*last]);
+
+            let key = c"test.plugin.routed".as_ptr();
+            (packet.set_metadata)(packet.context, key, b"yes".as_ptr(), 3);
+            assert!(!(packet.get_metadata)(packet.context, key, &mut len).is_null());
+            assert_eq!(len, 3);
+            Action::Pass
+        }
+
+        pub unsafe extern "C" fn write(_: *mut c_void, packet: *mut Packet) -> Action {
+            let packet = &mut *packet;
+            let mut len = 0;
+            (packet.endpoints)(packet.context, &mut len);
+            assert_eq!(len, 0);
+            let address = Address::default();
+            assert!(!(packet.add_destination)(packet.context, &address));
+            Action::Pass
+        }
+    }
+
+    static ROUTER: PluginDescriptor = PluginDescriptor {
+        abi_version: ABI_VERSION,
+        name: c"test.filters.router.v1.Router".as_ptr(),
+        config_schema: std::ptr::null(),
+        create: router::create,
+        destroy: router::destroy,
+        read: router::read,
+        write: router::write,
+    };
+
+    static DESCRIPTOR: PluginDescriptor = PluginDescriptor {
+        abi_version: ABI_VERSION,
+        name: c"test.filters.reverse.v1.Reverse".as_ptr(),
+        config_schema: std::ptr::null(),
+        create: reverse::create,
+        destroy: reverse::destroy,
+        read: reverse::read,
+        write: reverse::write,
+    };
+
+    fn factory() -> PluginFactory {
+        PluginFactory::from_descriptor(&DESCRIPTOR, libloading::os::unix::Library::this().into())
+            .unwrap()
+    }
+
+    #[test]
+    fn read_and_write() {
+        let factory = factory();
+        assert_eq!(factory.name(), "test.filters.reverse.v1.Reverse");
+
+        let instance = factory
+            .create_filter(CreateFilterArgs::fixed(Some(
+                serde_json::json!({ "suffix": "!" }),
+            )))
+            .unwrap();
+        let filter = instance.filter();
+
+        let endpoints = Arc::new(crate::net::ClusterMap::default());
+        let source: EndpointAddress = (std::net::Ipv4Addr::LOCALHOST, 8000).into();
+        let mut dest = Vec::new();
+
+        let mut ctx = ReadContext::new(
+            endpoints.clone(),
+            source.clone(),
+            alloc_buffer(b"abc"),
+            &mut dest,
+        );
+        filter.read(&mut ctx).unwrap();
+        assert_eq!(&*ctx.contents, b"cba!");
+
+        let mut ctx = ReadContext::new(endpoints, source.clone(), alloc_buffer([]), &mut dest);
+        assert_eq!(filter.read(&mut ctx), Err(FilterError::Dropped));
+
+        let mut ctx = WriteContext::new(
+            source,
+            (std::net::Ipv4Addr::LOCALHOST, 7000).into(),
+            alloc_buffer(b"abc"),
+        );
+        filter.write(&mut ctx).unwrap();
+        assert_eq!(&*ctx.contents, b"abc");
+    }
+
+    #[test]
+    fn endpoints_destinations_and_metadata() {
+        let factory =
+            PluginFactory::from_descriptor(&ROUTER, libloading::os::unix::Library::this().into())
+                .unwrap();
+        let instance = factory
+            .create_filter(CreateFilterArgs::fixed(None))
+            .unwrap();
+        let filter = instance.filter();
+
+        let first: EndpointAddress = (std::net::Ipv4Addr::LOCALHOST, 7000).into();
+        let last: EndpointAddress = (std::net::Ipv4Addr::LOCALHOST, 7001).into();
+        let endpoints = crate::net::ClusterMap::default();
+        endpoints.insert_default(
+            [
+                crate::net::endpoint::Endpoint::new(first.clone()),
+                crate::net::endpoint::Endpoint::new(last.clone()),
+            ]
+            .into(),
+        );
+        let source: EndpointAddress = (std::net::Ipv4Addr::LOCALHOST, 8000).into();
+        let mut dest = vec![first];
+
+        let mut ctx = ReadContext::new(
+            Arc::new(endpoints),
+            source.clone(),
+            alloc_buffer(b"abc"),
+            &mut dest,
+        );
+        filter.read(&mut ctx).unwrap();
+        assert_eq!(
+            ctx.metadata
+                .get(&metadata::Key::from_static("test.plugin.routed"))
+                .and_then(|value| value.as_bytes()),
+            Some(&bytes::Bytes::from_static(b"yes"))
+        );
+        assert_eq!(dest, [last]);
+
+        let mut ctx = ReadContext::new(
+            Arc::new(crate::net::ClusterMap::default()),
+            source.clone(),
+            alloc_buffer(b"abc"),
+            &mut dest,
+        );
+        assert_eq!(filter.read(&mut ctx), Err(FilterError::Dropped));
+
+        let mut ctx = WriteContext::new(
+            source,
+            (std::net::Ipv4Addr::LOCALHOST, 7000).into(),
+            alloc_buffer(b"abc"),
+        );
+        filter.write(&mut ctx).unwrap();
+    }
+
+    #[test]
+    fn creation_error() {
+        let error = factory()
+            .create_filter(CreateFilterArgs::fixed(None))
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error,
+            CreationError::FieldInvalid {
+                field: "test.filters.reverse.v1.Reverse".into(),
+                reason: "missing suffix".into(),
+            }
+        );
+    }
+}
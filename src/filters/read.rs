@@ -19,6 +19,7 @@ use std::sync::Arc;
 #[cfg(doc)]
 use crate::filters::Filter;
 use crate::{
+    filters::DropReason,
     net::{
         endpoint::{metadata::DynamicMetadata, EndpointAddress},
         ClusterMap,
@@ -34,10 +35,20 @@ pub struct ReadContext<'ctx> {
     pub destinations: &'ctx mut Vec<EndpointAddress>,
     /// The source of the received packet.
     pub source: EndpointAddress,
+    /// The port the packet was sent to, if known.
+    pub destination_port: Option<u16>,
     /// Contents of the received packet.
     pub contents: PoolBuffer,
     /// Arbitrary values that can be passed from one filter to another.
     pub metadata: DynamicMetadata,
+    /// A reply to send back to the source instead of forwarding the packet.
+    /// Once a filter sets this, the rest of the chain is skipped.
+    pub reply: Option<Vec<u8>>,
+    /// Why the packet is being discarded, without an error. Once a filter
+    /// sets this, the rest of the chain is skipped and the packet is
+    /// dropped, rather than sent to every endpoint for lack of
+    /// destinations.
+    pub drop_reason: Option<DropReason>,
 }
 
 impl<'ctx> ReadContext<'ctx> {
@@ -53,8 +64,11 @@ impl<'ctx> ReadContext<'ctx> {
             endpoints,
             destinations,
             source,
+            destination_port: None,
             contents,
             metadata: <_>::default(),
+            reply: None,
+            drop_reason: None,
         }
     }
 }
@@ -0,0 +1,385 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+
+use crate::{filters::prelude::*, metrics::Direction, net::endpoint::EndpointAddress};
+
+use crate::generated::quilkin::filters::reassembly::v1alpha1 as proto;
+
+/// The error of fragments held until the rest of their message arrives.
+const BUFFERED: FilterError = FilterError::Custom("filter::reassembly::buffered");
+
+/// The error of packets with an invalid fragment header, or messages larger
+/// than allowed.
+const MALFORMED: FilterError = FilterError::Discarded(DropReason::Malformed);
+
+/// Reassembles messages split across several packets, so the filters after it
+/// see each message whole.
+pub struct Reassembly {
+    config: Config,
+    timeout: Duration,
+    header_len: usize,
+    /// The messages whose fragments haven't all arrived, by client and
+    /// message ID.
+    pending: Mutex<HashMap<(EndpointAddress, u64), Pending>>,
+    reassembled_total: IntCounter,
+}
+
+/// The fragment header of a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Header {
+    id: u64,
+    index: usize,
+    count: usize,
+}
+
+/// A message whose fragments haven't all arrived.
+struct Pending {
+    started: Instant,
+    /// The contents of each fragment without its header, the first keeps the
+    /// bytes before its header.
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    len: usize,
+}
+
+impl Reassembly {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let invalid = |field: &str, reason: &str| CreationError::FieldInvalid {
+            field: field.into(),
+            reason: reason.into(),
+        };
+
+        if config.id_size > 8 {
+            return Err(invalid("id_size", "must be at most 8 bytes"));
+        }
+        if !(1..=2).contains(&config.field_size) {
+            return Err(invalid("field_size", "must be 1 or 2 bytes"));
+        }
+        if config.max_fragments < 1 {
+            return Err(invalid("max_fragments", "must be at least 1"));
+        }
+        if config.timeout_ms < 1 {
+            return Err(invalid("timeout_ms", "must be at least 1 millisecond"));
+        }
+
+        Ok(Self {
+            timeout: Duration::from_millis(config.timeout_ms),
+            header_len: usize::from(config.id_size) + 2 * usize::from(config.field_size),
+            pending: <_>::default(),
+            reassembled_total: super::metrics::counter(
+                Self::NAME,
+                "reassembled_total",
+                "Total number of messages reassembled from their fragments",
+                Direction::Read,
+            ),
+            config,
+        })
+    }
+
+    /// Parses the fragment header of `contents`, if it has a valid one.
+    fn header(&self, contents: &[u8]) -> Option<Header> {
+        let offset = self.config.offset;
+        let header = contents.get(offset..offset + self.header_len)?;
+        let (id, fields) = header.split_at(usize::from(self.config.id_size));
+        let (index, count) = fields.split_at(usize::from(self.config.field_size));
+
+        let int = |bytes: &[u8]| bytes.iter().fold(0, |n, b| (n << 8) | u64::from(*b));
+        let header = Header {
+            id: int(id),
+            index: int(index) as usize,
+            count: int(count) as usize,
+        };
+
+        (header.index < header.count && header.count <= usize::from(self.config.max_fragments))
+            .then_some(header)
+    }
+}
+
+impl Filter for Reassembly {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let header = self.header(&ctx.contents).ok_or(MALFORMED)?;
+        let start = self.config.offset;
+        let end = start + self.header_len;
+
+        if header.count == 1 {
+            let rest = ctx.contents[end..].to_vec();
+            ctx.contents.truncate(start);
+            ctx.contents.extend_from_slice(&rest);
+            return Ok(());
+        }
+
+        let fragment = if header.index == 0 {
+            [&ctx.contents[..start], &ctx.contents[end..]].concat()
+        } else {
+            ctx.contents[end..].to_vec()
+        };
+
+        let now = Instant::now();
+        let expired = |message: &Pending| now.duration_since(message.started) > self.timeout;
+        let key = (ctx.source.clone(), header.id);
+        let mut pending = self.pending.lock();
+
+        // A message is started over when its fragments are too old, or when
+        // the client reused its ID for a message with a different count.
+        if pending
+            .get(&key)
+            .is_some_and(|message| expired(message) || message.fragments.len() != header.count)
+        {
+            pending.remove(&key);
+        }
+
+        if !pending.contains_key(&key) && pending.len() >= self.config.max_pending {
+            pending.retain(|_, message| !expired(message));
+            if pending.len() >= self.config.max_pending {
+                return Err(FilterError::Discarded(DropReason::Overloaded));
+            }
+        }
+
+        let message = pending.entry(key.clone()).or_insert_with(|| Pending {
+            started: now,
+            fragments: vec![None; header.count],
+            received: 0,
+            len: 0,
+        });
+
+        let previous = message.fragments[header.index].as_ref().map_or(0, Vec::len);
+        let len = message.len - previous + fragment.len();
+        if len > self.config.max_message_size {
+            pending.remove(&key);
+            return Err(MALFORMED);
+        }
+
+        if message.fragments[header.index].is_none() {
+            message.received += 1;
+        }
+        message.len = len;
+        message.fragments[header.index] = Some(fragment);
+        if message.received < header.count {
+            return Err(BUFFERED);
+        }
+
+        let fragments = std::mem::take(&mut message.fragments);
+        pending.remove(&key);
+        drop(pending);
+
+        ctx.contents.truncate(0);
+        for fragment in fragments.into_iter().flatten() {
+            ctx.contents.extend_from_slice(&fragment);
+        }
+        self.reassembled_total.inc();
+        Ok(())
+    }
+}
+
+impl StaticFilter for Reassembly {
+    const NAME: &'static str = "quilkin.filters.reassembly.v1alpha1.Reassembly";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Reassembly;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(config.unwrap_or_default())
+    }
+}
+
+/// `reassembly` filter's configuration.
+///
+/// Every packet has a fragment header of the message ID, then the index of
+/// the fragment in its message and the number of fragments in the message,
+/// each a big endian integer.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The offset of the fragment header from the start of the packet.
+    pub offset: usize,
+    /// The size in bytes of the message ID, which every fragment of a message
+    /// shares, up to 8. When zero, each client can only send one fragmented
+    /// message at a time.
+    pub id_size: u8,
+    /// The size in bytes of the fragment index and count, 1 or 2.
+    pub field_size: u8,
+    /// The most fragments a message can be split into.
+    pub max_fragments: u16,
+    /// The largest message that is reassembled in bytes, the fragments of
+    /// larger messages are dropped.
+    pub max_message_size: usize,
+    /// The most messages waiting for the rest of their fragments at once,
+    /// across every client.
+    pub max_pending: usize,
+    /// How long in milliseconds the fragments of a message wait for the rest
+    /// of its fragments.
+    pub timeout_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            id_size: 2,
+            field_size: 1,
+            max_fragments: 8,
+            max_message_size: 16 * 1024,
+            max_pending: 1024,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl From<Config> for proto::Reassembly {
+    fn from(config: Config) -> Self {
+        Self {
+            offset: config.offset as u32,
+            id_size: Some(config.id_size.into()),
+            field_size: Some(config.field_size.into()),
+            max_fragments: Some(config.max_fragments.into()),
+            max_message_size: Some(config.max_message_size as u32),
+            max_pending: Some(config.max_pending as u32),
+            timeout_ms: Some(config.timeout_ms),
+        }
+    }
+}
+
+impl TryFrom<proto::Reassembly> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::Reassembly) -> Result<Self, Self::Error> {
+        fn narrow<T: TryFrom<u32>>(
+            value: Option<u32>,
+            default: T,
+            field: &str,
+        ) -> Result<T, ConvertProtoConfigError> {
+            match value {
+                Some(value) => value.try_into().map_err(|_| {
+                    ConvertProtoConfigError::new(
+                        format!("`{value}` is out of range"),
+                        Some(field.into()),
+                    )
+                }),
+                None => Ok(default),
+            }
+        }
+
+        let default = Self::default();
+        Ok(Self {
+            offset: p.offset as usize,
+            id_size: narrow(p.id_size, default.id_size, "id_size")?,
+            field_size: narrow(p.field_size, default.field_size, "field_size")?,
+            max_fragments: narrow(p.max_fragments, default.max_fragments, "max_fragments")?,
+            max_message_size: p
+                .max_message_size
+                .map_or(default.max_message_size, |size| size as usize),
+            max_pending: p
+                .max_pending
+                .map_or(default.max_pending, |pending| pending as usize),
+            timeout_ms: p.timeout_ms.unwrap_or(default.timeout_ms),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    fn read(filter: &Reassembly, port: u16, contents: &[u8]) -> Result<Vec<u8>, FilterError> {
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            (std::net::Ipv4Addr::LOCALHOST, port).into(),
+            alloc_buffer(contents),
+            &mut dest,
+        );
+        filter.read(&mut ctx).map(|()| ctx.contents.to_vec())
+    }
+
+    #[test]
+    fn reassembles_fragments() {
+        let filter = Reassembly::from_config(Some(Config {
+            offset: 1,
+            ..<_>::default()
+        }));
+
+        // Unfragmented messages only have their header removed.
+        assert_eq!(read(&filter, 1, b"!\x00\x01\x00\x01abc").unwrap(), b"!abc");
+
+        // Fragments can arrive out of order, and interleaved with the
+        // fragments of other clients.
+        assert_eq!(read(&filter, 1, b"!\x00\x02\x01\x02def"), Err(BUFFERED));
+        assert_eq!(read(&filter, 2, b"!\x00\x02\x00\x02xyz"), Err(BUFFERED));
+        assert_eq!(
+            read(&filter, 1, b"!\x00\x02\x00\x02abc").unwrap(),
+            b"!abcdef"
+        );
+        assert!(filter
+            .pending
+            .lock()
+            .contains_key(&((std::net::Ipv4Addr::LOCALHOST, 2).into(), 2)));
+
+        // Invalid headers.
+        assert_eq!(read(&filter, 1, b"!\x00\x03\x02\x02abc"), Err(MALFORMED));
+        assert_eq!(read(&filter, 1, b"!\x00\x03\x00\x09abc"), Err(MALFORMED));
+        assert_eq!(read(&filter, 1, b"!\x00"), Err(MALFORMED));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let filter = Reassembly::from_config(Some(Config {
+            max_message_size: 4,
+            max_pending: 1,
+            timeout_ms: 10,
+            ..<_>::default()
+        }));
+
+        assert_eq!(read(&filter, 1, b"\x00\x01\x00\x02abc"), Err(BUFFERED));
+        assert_eq!(read(&filter, 1, b"\x00\x01\x01\x02def"), Err(MALFORMED));
+        assert!(filter.pending.lock().is_empty());
+
+        assert_eq!(read(&filter, 1, b"\x00\x02\x00\x02abc"), Err(BUFFERED));
+        assert_eq!(
+            read(&filter, 2, b"\x00\x02\x00\x02abc"),
+            Err(FilterError::Discarded(DropReason::Overloaded))
+        );
+
+        // Expired messages don't complete, and make room for new ones.
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(read(&filter, 1, b"\x00\x02\x01\x02d"), Err(BUFFERED));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(read(&filter, 2, b"\x00\x02\x00\x02abc"), Err(BUFFERED));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        for config in [
+            "id_size: 9",
+            "field_size: 0",
+            "field_size: 3",
+            "max_fragments: 0",
+            "timeout_ms: 0",
+        ] {
+            let config: Config = serde_yaml::from_str(config).unwrap();
+            assert!(Reassembly::try_from_config(Some(config)).is_err());
+        }
+    }
+}
@@ -26,6 +26,14 @@ use crate::filters::{
 static REGISTRY: Lazy<ArcSwap<FilterSet>> =
     Lazy::new(|| ArcSwap::new(std::sync::Arc::new(FilterSet::default())));
 
+thread_local! {
+    /// A registry that takes precedence over the global one on this thread
+    /// while filters are created from an explicit [`FilterSet`], see
+    /// [`FilterRegistry::get_with`].
+    static SCOPED: std::cell::RefCell<Option<std::sync::Arc<FilterSet>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
 /// Registry of all [`Filter`][crate::filters::Filter]s that can be applied in the system.
 ///
 /// **Note:** Cloning [`FilterRegistry`], clones a new reference to the data and
@@ -45,11 +53,16 @@ impl FilterRegistry {
         REGISTRY.store(std::sync::Arc::from(registry));
     }
 
+    /// Returns the filters in the global registry.
+    pub fn filters() -> std::sync::Arc<FilterSet> {
+        REGISTRY.load_full()
+    }
+
     /// Creates and returns a new dynamic instance of [`Filter`][crate::filters::Filter] for a given
     /// `key`. Errors if the filter cannot be found, or if there is a
     /// configuration issue.
     pub fn get(key: &str, args: CreateFilterArgs) -> Result<FilterInstance, CreationError> {
-        match REGISTRY.load().get(key).map(|p| p.create_filter(args)) {
+        match Self::get_factory(key).map(|p| p.create_filter(args)) {
             None => Err(CreationError::NotFound(key.to_owned())),
             Some(filter) => filter,
         }
@@ -58,7 +71,37 @@ impl FilterRegistry {
     /// Returns a [`DynFilterFactory`] for a given `key`. Returning `None` if the
     /// factory cannot be found.
     pub fn get_factory(key: &str) -> Option<std::sync::Arc<DynFilterFactory>> {
-        REGISTRY.load().get(key).cloned()
+        SCOPED.with_borrow(|scoped| match scoped {
+            Some(filters) => filters.get(key).cloned(),
+            None => REGISTRY.load().get(key).cloned(),
+        })
+    }
+
+    /// Like [`Self::get`], creating the filter from `filters` rather than the
+    /// global registry, along with any filters it creates itself, such as the
+    /// chains of a [`Listeners`][crate::filters::Listeners] filter.
+    pub fn get_with(
+        filters: &std::sync::Arc<FilterSet>,
+        key: &str,
+        args: CreateFilterArgs,
+    ) -> Result<FilterInstance, CreationError> {
+        Self::scope(filters.clone(), || Self::get(key, args))
+    }
+
+    /// Runs `func` with `filters` used in place of the global registry for any
+    /// filters created on the current thread while it runs, allowing multiple
+    /// proxies with different sets of filters to run in the same process.
+    pub fn scope<R>(filters: std::sync::Arc<FilterSet>, func: impl FnOnce() -> R) -> R {
+        struct Reset(Option<std::sync::Arc<FilterSet>>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                SCOPED.set(self.0.take());
+            }
+        }
+
+        let _reset = Reset(SCOPED.replace(Some(filters)));
+        func()
     }
 }
 
@@ -120,4 +163,29 @@ mod tests {
             .write(&mut WriteContext::new(addr.clone(), addr, alloc_buffer([])))
             .is_ok());
     }
+
+    #[test]
+    fn scoped_registry() {
+        use crate::filters::StaticFilter;
+
+        let filters = std::sync::Arc::new(FilterSet::with([crate::filters::Pass::factory()]));
+
+        FilterRegistry::scope(filters, || {
+            assert!(FilterRegistry::get_factory(crate::filters::Pass::NAME).is_some());
+            assert!(FilterRegistry::get_factory(crate::filters::Drop::NAME).is_none());
+        });
+
+        assert!(FilterRegistry::get_factory(crate::filters::Drop::NAME).is_some());
+
+        let filters = std::sync::Arc::new(FilterSet::with([crate::filters::Pass::factory()]));
+        assert!(FilterRegistry::get_with(
+            &filters,
+            crate::filters::Drop::NAME,
+            CreateFilterArgs::fixed(None)
+        )
+        .is_err());
+        assert!(
+            FilterRegistry::get(crate::filters::Drop::NAME, CreateFilterArgs::fixed(None)).is_ok()
+        );
+    }
 }
@@ -34,7 +34,7 @@ pub type FilterMap = std::collections::HashMap<&'static str, Arc<DynFilterFactor
 /// - [`capture`][filters::capture]
 /// - [`token_router`][filters::token_router]
 /// - [`hashed_token_router`][filters::token_router]
-/// - [`compress`][filters::compress]
+/// - [`compress`][filters::compress], with the `filter-compress` feature
 #[derive(Clone)]
 pub struct FilterSet(FilterMap);
 
@@ -45,19 +45,34 @@ impl FilterSet {
     pub fn default_with(filters: impl IntoIterator<Item = DynFilterFactory>) -> Self {
         Self::with(
             [
+                filters::AddressRewrite::factory(),
                 filters::Capture::factory(),
+                #[cfg(feature = "filter-compress")]
                 filters::Compress::factory(),
                 filters::Concatenate::factory(),
+                #[cfg(feature = "filter-routing")]
+                filters::ContentRouter::factory(),
+                filters::Control::factory(),
                 filters::Debug::factory(),
                 filters::Drop::factory(),
+                filters::Dscp::factory(),
                 filters::Firewall::factory(),
                 filters::HashedTokenRouter::factory(),
+                filters::HealthProbe::factory(),
+                filters::Listeners::factory(),
                 filters::LoadBalancer::factory(),
                 filters::LocalRateLimit::factory(),
                 filters::Match::factory(),
                 filters::Pass::factory(),
+                #[cfg(feature = "filter-reassembly")]
+                filters::Reassembly::factory(),
+                filters::Tenants::factory(),
                 filters::Timestamp::factory(),
+                filters::TokenPush::factory(),
                 filters::TokenRouter::factory(),
+                #[cfg(feature = "filter-routing")]
+                filters::TrafficSplit::factory(),
+                #[cfg(feature = "filter-routing")]
                 filters::SourceIpRouter::factory(),
             ]
             .into_iter()
@@ -91,6 +106,24 @@ impl FilterSet {
     }
 }
 
+impl std::fmt::Debug for FilterSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Sets are equal when they hold the same factories under the same names.
+impl PartialEq for FilterSet {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.0.len() == rhs.0.len()
+            && self.0.iter().all(|(name, factory)| {
+                rhs.0
+                    .get(name)
+                    .is_some_and(|other| Arc::ptr_eq(factory, other))
+            })
+    }
+}
+
 impl Default for FilterSet {
     fn default() -> Self {
         Self::default_with(Option::into_iter(None))
@@ -4,6 +4,9 @@
 //! the client source IP and rewrites `ctx.destinations`.
 
 mod config;
+mod metrics;
+mod resolver;
+mod trie;
 
 use crate::filters::prelude::*;
 use crate::filters::error::ConvertProtoConfigError;
@@ -11,13 +14,35 @@ use crate::net::endpoint::address::EndpointAddress; // for ctx.destinations
 use crate::filters::CreationError;
 use tracing::debug;
 
-use std::net::SocketAddr;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::IntCounter;
+
+use rand::Rng;
+
+use crate::collections::decision_cache::DecisionCache;
+use crate::filters::load_balancer::Policy;
+use crate::net::endpoint::metadata;
+use resolver::Destinations;
+use trie::CidrTrie;
 
 // Import our auto-generated Protobuf module, e.g.
 // `crates/quilkin-proto/src/generated/quilkin/filters/source_ip_router/v1alpha1/source_ip_router.rs`
 use crate::generated::quilkin::filters::source_ip_router::v1alpha1 as proto;
 
-pub use config::{Cidr, Config, Route};
+pub use config::{Action, Cache, Cidr, Config, PortRange, Route, RoutesPatch};
 
 ////////////////////////////////////////////////////////////////////////////////
 // 1) Conversions between Rust `Config` and `proto::SourceIpRouter`
@@ -30,14 +55,51 @@ impl From<Config> for proto::SourceIpRouter {
                 .routes
                 .into_iter()
                 .map(|r| proto::source_ip_router::Route {
+                    name: r.name,
                     sources: r
                         .sources
                         .into_iter()
                         .map(|cidr| cidr.0.to_string())
                         .collect(),
-                    endpoint: r.endpoint,
+                    exclude: r
+                        .exclude
+                        .into_iter()
+                        .map(|cidr| cidr.0.to_string())
+                        .collect(),
+                    as_countries: r.as_countries,
+                    asns: r.asns,
+                    endpoints: r.endpoints,
+                    priority: r.priority,
+                    weight: r.weight,
+                    policy: Some(proto::source_ip_router::PolicyValue {
+                        value: proto::source_ip_router::Policy::from(r.policy) as i32,
+                    }),
+                    port_ranges: r
+                        .port_ranges
+                        .into_iter()
+                        .map(|range| proto::source_ip_router::PortRange {
+                            start: range.start.into(),
+                            end: range.end.into(),
+                        })
+                        .collect(),
+                    action: match r.action {
+                        Action::Route => None,
+                        Action::SetMetadata(values) => {
+                            Some(proto::source_ip_router::route::Action::SetMetadata(
+                                proto::source_ip_router::SetMetadata {
+                                    values: values.into_iter().collect(),
+                                },
+                            ))
+                        }
+                    },
                 })
                 .collect(),
+            cache: cfg.cache.map(|cache| proto::source_ip_router::Cache {
+                capacity: cache.capacity as u64,
+                ttl_ms: cache.ttl_ms,
+            }),
+            dns_ttl_secs: Some(cfg.dns_ttl_secs),
+            log_sample: cfg.log_sample,
         }
     }
 }
@@ -50,24 +112,96 @@ impl TryFrom<proto::SourceIpRouter> for Config {
 
         for r in pb.routes.into_iter() {
             // Convert strings -> Cidr
-            let mut cidrs = Vec::new();
-            for s in r.sources {
-                let parsed = s
-                    .parse()
-                    .map_err(|err| ConvertProtoConfigError::new(
-                        format!("Invalid CIDR '{s}': {err}"),
-                        Some("routes.sources".to_string()),
-                    ))?;
-                cidrs.push(Cidr(parsed));
-            }
+            let parse_cidrs = |strings: Vec<String>, field: &str| {
+                let mut cidrs = Vec::new();
+                for s in strings {
+                    let parsed = s.parse().map_err(|err| {
+                        ConvertProtoConfigError::new(
+                            format!("Invalid CIDR '{s}': {err}"),
+                            Some(field.to_string()),
+                        )
+                    })?;
+                    cidrs.push(Cidr(parsed));
+                }
+                Ok::<_, ConvertProtoConfigError>(cidrs)
+            };
+            let cidrs = parse_cidrs(r.sources, "routes.sources")?;
+            let exclude = parse_cidrs(r.exclude, "routes.exclude")?;
+
+            let port = |port: u32| {
+                u16::try_from(port).map_err(|_err| {
+                    ConvertProtoConfigError::new(
+                        format!("Invalid port {port}"),
+                        Some("routes.port_ranges".to_string()),
+                    )
+                })
+            };
+            let port_ranges = r
+                .port_ranges
+                .into_iter()
+                .map(|range| {
+                    Ok(PortRange {
+                        start: port(range.start)?,
+                        end: port(range.end)?,
+                    })
+                })
+                .collect::<Result<_, ConvertProtoConfigError>>()?;
 
             routes.push(Route {
+                name: r.name,
                 sources: cidrs,
-                endpoint: r.endpoint,
+                as_countries: r.as_countries,
+                asns: r.asns,
+                exclude,
+                port_ranges,
+                endpoints: r.endpoints,
+                priority: r.priority,
+                weight: r.weight,
+                policy: r
+                    .policy
+                    .map(|policy| policy.value())
+                    .map(Policy::from)
+                    .unwrap_or_default(),
+                action: match r.action {
+                    None => Action::Route,
+                    Some(proto::source_ip_router::route::Action::SetMetadata(set)) => {
+                        Action::SetMetadata(set.values.into_iter().collect())
+                    }
+                },
             });
         }
 
-        Ok(Config { routes })
+        let cache = pb.cache.map(|cache| Cache {
+            capacity: cache.capacity as usize,
+            ttl_ms: cache.ttl_ms,
+        });
+
+        Ok(Config {
+            routes,
+            cache,
+            dns_ttl_secs: pb.dns_ttl_secs.unwrap_or_else(config::default_dns_ttl_secs),
+            log_sample: pb.log_sample,
+        })
+    }
+}
+
+impl From<Policy> for proto::source_ip_router::Policy {
+    fn from(policy: Policy) -> Self {
+        match policy {
+            Policy::RoundRobin => Self::RoundRobin,
+            Policy::Random => Self::Random,
+            Policy::Hash => Self::Hash,
+        }
+    }
+}
+
+impl From<proto::source_ip_router::Policy> for Policy {
+    fn from(policy: proto::source_ip_router::Policy) -> Self {
+        match policy {
+            proto::source_ip_router::Policy::RoundRobin => Self::RoundRobin,
+            proto::source_ip_router::Policy::Random => Self::Random,
+            proto::source_ip_router::Policy::Hash => Self::Hash,
+        }
     }
 }
 
@@ -75,15 +209,311 @@ impl TryFrom<proto::SourceIpRouter> for Config {
 // 2) The SourceIpRouter filter itself
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The routing of every live [`SourceIpRouter`], wherever it is in the filter
+/// chain, so the admin server can update their routes in place.
+static ROUTERS: Lazy<Mutex<Vec<Weak<Routing>>>> = Lazy::new(<_>::default);
+
+/// Returns the routes of the first live [`SourceIpRouter`], if there is one.
+pub(crate) fn routes() -> Option<Vec<Route>> {
+    ROUTERS
+        .lock()
+        .iter()
+        .find_map(Weak::upgrade)
+        .map(|routing| routing.table.load().routes.clone())
+}
+
+/// Replaces the routes of every live [`SourceIpRouter`] with those `update`
+/// returns for its current routes, once they're valid for all of them.
+/// Returns the new routes of the first, if there is one.
+pub(crate) fn update_routes(
+    update: impl Fn(&[Route]) -> Result<Vec<Route>, CreationError>,
+) -> Result<Option<Vec<Route>>, CreationError> {
+    // Held until the new routes are stored, so concurrent updates apply one
+    // after the other.
+    let mut registered = ROUTERS.lock();
+    registered.retain(|routing| routing.strong_count() > 0);
+    let routers: Vec<_> = registered.iter().filter_map(Weak::upgrade).collect();
+    update_all(&routers, update)
+}
+
+/// Updates the routes of `routers` as [`update_routes`] does.
+fn update_all(
+    routers: &[Arc<Routing>],
+    update: impl Fn(&[Route]) -> Result<Vec<Route>, CreationError>,
+) -> Result<Option<Vec<Route>>, CreationError> {
+    let tables = routers
+        .iter()
+        .map(|routing| routing.table(update(&routing.table.load().routes)?))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let routes = tables.first().map(|table| table.routes.clone());
+    for (routing, table) in routers.iter().zip(tables) {
+        routing.table.store(Arc::new(table));
+    }
+    Ok(routes)
+}
+
+/// A filter's routing table, replaced as a whole when its routes are updated
+/// so packets are routed by either the old routes or the new ones.
+struct Routing {
+    table: ArcSwap<Table>,
+    cache: Option<Cache>,
+    dns_ttl: Duration,
+}
+
+impl Routing {
+    /// Returns the table for `routes`, with this filter's caching and DNS
+    /// configuration.
+    fn table(&self, routes: Vec<Route>) -> Result<Table, CreationError> {
+        Table::new(routes, self.cache.as_ref(), self.dns_ttl)
+    }
+}
+
+/// The routes, and everything the filter derives from them.
+struct Table {
+    routes: Vec<Route>,
+    /// The routes containing each of their `sources`.
+    trie: CidrTrie,
+    /// The routes matching countries or ASNs, which can't be in the trie.
+    as_routes: Vec<usize>,
+    /// The number of packets sent by each route, for round robin.
+    sent: Vec<AtomicUsize>,
+    /// The addresses of each route's endpoints, updated as their hostnames
+    /// are resolved.
+    destinations: Arc<ArcSwap<Destinations>>,
+    /// The dynamic metadata set by each route whose action is `set_metadata`.
+    metadata: Vec<Option<Vec<(metadata::Key, metadata::Value)>>>,
+    /// The label of each route in its metrics and logs.
+    labels: Vec<String>,
+    /// The packets and bytes that matched each route.
+    matched_total: Vec<(IntCounter, IntCounter)>,
+    /// Whether any route matches source ports, otherwise routes are only
+    /// cached by source IP.
+    matches_ports: bool,
+    /// The index of the route each source matched, if caching is enabled.
+    /// Changing the routes creates a new table, and so a new cache.
+    cache: Option<DecisionCache<SocketAddr, Option<usize>>>,
+}
+
+impl Table {
+    fn new(
+        routes: Vec<Route>,
+        cache: Option<&Cache>,
+        dns_ttl: Duration,
+    ) -> Result<Self, CreationError> {
+        if routes
+            .iter()
+            .any(|route| route.action == Action::Route && route.endpoints.is_empty())
+        {
+            return Err(CreationError::FieldInvalid {
+                field: "routes.endpoints".into(),
+                reason: "every routing route must have at least one endpoint".into(),
+            });
+        }
+        if routes.iter().any(|route| {
+            route.sources.is_empty() && route.as_countries.is_empty() && route.asns.is_empty()
+        }) {
+            return Err(CreationError::FieldInvalid {
+                field: "routes.sources".into(),
+                reason: "every route must match some sources, countries or ASNs".into(),
+            });
+        }
+        let ranges = || routes.iter().flat_map(|route| &route.port_ranges);
+        if ranges().any(|range| range.start > range.end) {
+            return Err(CreationError::FieldInvalid {
+                field: "routes.port_ranges".into(),
+                reason: "ranges must not start after their end".into(),
+            });
+        }
+
+        let endpoints = routes
+            .iter()
+            .map(|route| {
+                route
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.parse::<EndpointAddress>())
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| CreationError::FieldInvalid {
+                field: "routes.endpoints".into(),
+                reason: error.to_string(),
+            })?;
+        // Endpoints with a hostname have no addresses until resolved.
+        let destinations = Arc::new(ArcSwap::from_pointee(resolver::destinations(
+            &endpoints,
+            &<_>::default(),
+        )));
+        resolver::spawn(endpoints, Arc::downgrade(&destinations), dns_ttl);
+
+        let labels: Vec<String> = routes
+            .iter()
+            .enumerate()
+            .map(|(index, route)| route.name.clone().unwrap_or_else(|| index.to_string()))
+            .collect();
+
+        let cache = cache.map(|cache| {
+            DecisionCache::new(
+                "source_ip_router",
+                cache.capacity,
+                Duration::from_millis(cache.ttl_ms),
+            )
+        });
+
+        Ok(Self {
+            sent: routes.iter().map(|_| AtomicUsize::new(0)).collect(),
+            destinations,
+            metadata: routes
+                .iter()
+                .map(|route| match &route.action {
+                    Action::Route => None,
+                    Action::SetMetadata(values) => Some(
+                        values
+                            .iter()
+                            .map(|(key, value)| {
+                                (
+                                    metadata::Key::new(key),
+                                    metadata::Value::String(value.clone()),
+                                )
+                            })
+                            .collect(),
+                    ),
+                })
+                .collect(),
+            matched_total: labels
+                .iter()
+                .map(|label| (metrics::packets_total(label), metrics::bytes_total(label)))
+                .collect(),
+            labels,
+            matches_ports: ranges().next().is_some(),
+            trie: CidrTrie::new(&routes),
+            as_routes: routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| !route.as_countries.is_empty() || !route.asns.is_empty())
+                .map(|(index, _)| index)
+                .collect(),
+            routes,
+            cache,
+        })
+    }
+
+    /// Returns the index of the route `source` is sent to, of those with the
+    /// highest priority that match it. The same source always gets the same
+    /// route, so it can be cached.
+    fn find_route(&self, source: SocketAddr) -> Option<usize> {
+        let mut matched = self.trie.matches(source.ip());
+        if !self.as_routes.is_empty() {
+            // Looked up once for every route matching autonomous systems.
+            let entry = crate::net::maxmind_db::MaxmindDb::lookup(source.ip());
+            matched.extend(
+                self.as_routes
+                    .iter()
+                    .filter(|index| self.routes[**index].matches_as(entry.as_ref())),
+            );
+        }
+        matched.sort_unstable();
+        matched.dedup();
+        matched.retain(|index| self.routes[*index].admits(source));
+
+        let matching = || matched.iter().map(|index| (*index, &self.routes[*index]));
+        let priority = matching().map(|(_, route)| route.priority).max()?;
+        let candidates = || matching().filter(move |(_, route)| route.priority == priority);
+
+        // Like the TrafficSplit filter, split sources by the hash of their
+        // IP, so each client keeps going to the same endpoint.
+        let total: u64 = candidates()
+            .filter_map(|(_, route)| route.weight)
+            .map(u64::from)
+            .sum();
+        if total == 0 {
+            return candidates().next().map(|(index, _)| index);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        source.ip().hash(&mut hasher);
+        let mut bucket = hasher.finish() % total;
+
+        candidates()
+            .find(|(_, route)| {
+                let weight = u64::from(route.weight.unwrap_or_default());
+                if bucket < weight {
+                    return true;
+                }
+
+                bucket -= weight;
+                false
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// Returns which of the route at `index`'s `destinations` a packet from
+    /// `source` is sent to, if it has any.
+    fn choose_endpoint<'destinations>(
+        &self,
+        index: usize,
+        source: &EndpointAddress,
+        destinations: &'destinations [EndpointAddress],
+    ) -> Option<&'destinations EndpointAddress> {
+        if destinations.is_empty() {
+            return None;
+        }
+
+        let choice = match self.routes[index].policy {
+            Policy::RoundRobin => self.sent[index].fetch_add(1, Ordering::Relaxed),
+            Policy::Random => rand::thread_rng().gen_range(0..destinations.len()),
+            Policy::Hash => {
+                let mut hasher = DefaultHasher::new();
+                source.hash(&mut hasher);
+                hasher.finish() as usize
+            }
+        };
+
+        Some(&destinations[choice % destinations.len()])
+    }
+}
+
 /// Filter that inspects `ctx.source` IP. If it matches any route,
-/// we rewrite `ctx.destinations` to a single endpoint from that route.
+/// we rewrite `ctx.destinations` to a single endpoint from that route, or set
+/// the route's metadata on the packet.
 pub struct SourceIpRouter {
-    routes: Vec<Route>,
+    routing: Arc<Routing>,
+    /// One in how many match decisions are logged, none when zero.
+    log_sample: u32,
+    decisions: AtomicU64,
 }
 
 impl SourceIpRouter {
-    fn new(cfg: Config) -> Self {
-        Self { routes: cfg.routes }
+    fn new(cfg: Config) -> Result<Self, CreationError> {
+        let dns_ttl = Duration::from_secs(cfg.dns_ttl_secs.max(1));
+        let routing = Arc::new(Routing {
+            table: ArcSwap::from_pointee(Table::new(cfg.routes, cfg.cache.as_ref(), dns_ttl)?),
+            cache: cfg.cache,
+            dns_ttl,
+        });
+
+        let mut routers = ROUTERS.lock();
+        routers.retain(|routing| routing.strong_count() > 0);
+        routers.push(Arc::downgrade(&routing));
+
+        Ok(Self {
+            routing,
+            log_sample: cfg.log_sample,
+            decisions: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the filter's current routing table.
+    fn table(&self) -> arc_swap::Guard<Arc<Table>> {
+        self.routing.table.load()
+    }
+
+    /// Returns whether the current match decision is one of the sample that
+    /// is logged.
+    fn sampled(&self) -> bool {
+        self.log_sample > 0
+            && self.decisions.fetch_add(1, Ordering::Relaxed) % u64::from(self.log_sample) == 0
     }
 }
 
@@ -101,39 +531,81 @@ impl StaticFilter for SourceIpRouter {
 
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
         let cfg = Self::ensure_config_exists(config)?;
-        Ok(Self::new(cfg))
+        Self::new(cfg)
     }
 }
 
 impl Filter for SourceIpRouter {
     fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
-        // convert EndpointAddress => SocketAddr => IpAddr
-        let src_ip = ctx.source.to_socket_addr()?.ip();
-
-        for route in &self.routes {
-            if route.sources.iter().any(|cidr| cidr.contains(src_ip)) {
-                debug!(
-                    "SourceIpRouter matched route: source={} => endpoint={}",
-                    ctx.source, route.endpoint
-                );
+        // convert EndpointAddress => SocketAddr
+        let source = ctx.source.to_socket_addr()?;
+        let table = self.table();
 
-                // parse route.endpoint => SocketAddr
-               let socket_addr: SocketAddr = route.endpoint.parse().map_err(|_err| {
-                    // Return a fixed, static error message
-                    FilterError::Custom("Invalid endpoint address")
-                })?;
+        let matched = match &table.cache {
+            Some(cache) => {
+                // Without port ranges, every port of an IP matches the same
+                // route, so they share its cache entry.
+                let key = if table.matches_ports {
+                    source
+                } else {
+                    SocketAddr::new(source.ip(), 0)
+                };
+                cache.get_or_insert_with(key, || table.find_route(source))
+            }
+            None => table.find_route(source),
+        };
 
+        if let Some(index) = matched {
+            let (packets, bytes) = &table.matched_total[index];
+            packets.inc();
+            bytes.inc_by(ctx.contents.len() as u64);
 
-                // Clear existing destinations, then push a single address
-                ctx.destinations.clear();
-                ctx.destinations.push(EndpointAddress::from(socket_addr));
+            // Tagging routes leave the destinations to later filters.
+            if let Some(values) = &table.metadata[index] {
+                debug!("SourceIpRouter tagged packet: source={}", ctx.source);
+                if self.sampled() {
+                    tracing::info!(
+                        source = %ctx.source,
+                        route = table.labels[index].as_str(),
+                        "source ip router tagged packet"
+                    );
+                }
 
-                // stop searching, first match wins
+                for (key, value) in values {
+                    ctx.metadata.insert(*key, value.clone());
+                }
                 return Ok(());
             }
+
+            let destinations = table.destinations.load();
+            let endpoint = table
+                .choose_endpoint(index, &ctx.source, &destinations[index])
+                .ok_or(FilterError::Custom("Unresolved endpoint address"))?;
+            debug!(
+                "SourceIpRouter matched route: source={} => endpoint={}",
+                ctx.source, endpoint
+            );
+            if self.sampled() {
+                tracing::info!(
+                    source = %ctx.source,
+                    route = table.labels[index].as_str(),
+                    %endpoint,
+                    "source ip router matched route"
+                );
+            }
+
+            // Clear existing destinations, then push a single address
+            ctx.destinations.clear();
+            ctx.destinations.push(endpoint.clone());
+
+            return Ok(());
         }
 
+        metrics::unmatched_total().inc();
         debug!("SourceIpRouter found no match for source={}", ctx.source);
+        if self.sampled() {
+            tracing::info!(source = %ctx.source, "source ip router matched no route");
+        }
         Ok(())
     }
 
@@ -141,4 +613,454 @@ impl Filter for SourceIpRouter {
         // Typically do nothing on the server->client path
         Ok(())
     }
+
+    fn capabilities(&self) -> Capabilities {
+        let table = self.table();
+        let routes = table.metadata.iter().any(Option::is_none);
+        Capabilities {
+            produces: table
+                .metadata
+                .iter()
+                .flatten()
+                .flatten()
+                .map(|(key, _)| *key)
+                .collect(),
+            sets_destinations: routes,
+            clears_destinations: routes,
+            ..<_>::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    crate::config_round_trip_test!(config_round_trip: SourceIpRouter => "
+routes:
+  - sources: [10.0.0.0/8, 192.168.1.0/24]
+    endpoint: 127.0.0.1:7001
+  - sources: [10.0.0.0/16]
+    exclude: [10.0.5.0/24]
+    endpoints: [127.0.0.1:7002, 127.0.0.1:7003]
+    policy: HASH
+    priority: 1
+    weight: 3
+  - as_countries: [DE, FR]
+    asns: [3320]
+    endpoint: 127.0.0.1:7004
+  - sources: [172.16.0.0/12]
+    action:
+      set_metadata:
+        quilkin.dev/region: eu
+cache:
+  capacity: 128
+  ttl_ms: 500
+");
+
+    #[test]
+    fn fans_out_to_endpoints() {
+        let filter = |policy: &str| {
+            let config: Config = serde_yaml::from_str(&format!(
+                "
+routes:
+  - sources: [10.0.0.0/8]
+    endpoints: [127.0.0.1:7001, 127.0.0.1:7002]
+    policy: {policy}
+"
+            ))
+            .unwrap();
+            SourceIpRouter::from_config(Some(config))
+        };
+        let read = |filter: &SourceIpRouter, source: &str| {
+            let mut destinations = Vec::new();
+            let mut ctx = ReadContext::new(
+                Default::default(),
+                source.parse().unwrap(),
+                alloc_buffer(b"hello"),
+                &mut destinations,
+            );
+            filter.read(&mut ctx).unwrap();
+            assert_eq!(destinations.len(), 1);
+            destinations[0].port
+        };
+
+        let round_robin = filter("ROUND_ROBIN");
+        let ports: Vec<_> = (0..4)
+            .map(|_| read(&round_robin, "10.0.0.1:9000"))
+            .collect();
+        assert_eq!(ports, [7001, 7002, 7001, 7002]);
+
+        // The same source always gets the same endpoint, and sources are
+        // spread across them.
+        let hash = filter("HASH");
+        let ports: std::collections::BTreeSet<_> = (0..32)
+            .map(|port| {
+                let source = format!("10.0.0.1:{}", 9000 + port);
+                let first = read(&hash, &source);
+                assert_eq!(read(&hash, &source), first);
+                first
+            })
+            .collect();
+        assert_eq!(ports.len(), 2);
+
+        let random = filter("RANDOM");
+        assert!([7001, 7002].contains(&read(&random, "10.0.0.1:9000")));
+
+        let config: Config =
+            serde_yaml::from_str("routes: [{ sources: [10.0.0.0/8], endpoints: [] }]").unwrap();
+        assert!(SourceIpRouter::try_from_config(Some(config)).is_err());
+    }
+
+    // Endpoints with a hostname spawn a task to resolve them.
+    #[tokio::test]
+    async fn waits_for_hostnames_to_resolve() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    endpoint: game.invalid:7001
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+
+        let mut destinations = Vec::new();
+        let mut ctx = ReadContext::new(
+            Default::default(),
+            "10.0.0.1:9000".parse().unwrap(),
+            alloc_buffer(b"hello"),
+            &mut destinations,
+        );
+        assert!(filter.read(&mut ctx).is_err());
+
+        let config: Config =
+            serde_yaml::from_str("routes: [{ sources: [10.0.0.0/8], endpoint: 127.0.0.1 }]")
+                .unwrap();
+        assert!(SourceIpRouter::try_from_config(Some(config)).is_err());
+    }
+
+    #[test]
+    fn resolves_overlapping_routes() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    endpoint: 127.0.0.1:7001
+  - sources: [10.1.0.0/16]
+    endpoint: 127.0.0.1:7002
+    priority: 1
+  - sources: [10.2.0.0/16]
+    endpoint: 127.0.0.1:7003
+    priority: 1
+    weight: 1
+  - sources: [10.2.0.0/16]
+    endpoint: 127.0.0.1:7004
+    priority: 1
+    weight: 3
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+        let table = filter.table();
+        let route = |ip: &str| table.find_route(format!("{ip}:9000").parse().unwrap());
+
+        // The higher priority route wins, even though it's listed later.
+        assert_eq!(route("10.0.0.1"), Some(0));
+        assert_eq!(route("10.1.0.1"), Some(1));
+
+        // Sources are split by weight, and each keeps its route.
+        let mut counts = [0; 4];
+        for i in 0..=255 {
+            for j in 0..4 {
+                let ip = format!("10.2.{j}.{i}");
+                let index = route(&ip).unwrap();
+                assert_eq!(route(&ip), Some(index));
+                counts[index] += 1;
+            }
+        }
+        assert_eq!(counts[0] + counts[1], 0);
+        assert!(counts[3] > counts[2] * 2, "{counts:?}");
+    }
+
+    #[test]
+    fn counts_matches_by_route() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - name: counted
+    sources: [10.0.0.0/8]
+    endpoint: 127.0.0.1:7001
+  - sources: [192.168.0.0/16]
+    endpoint: 127.0.0.1:7002
+log_sample: 2
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+        let table = filter.table();
+        assert_eq!(table.labels, ["counted", "1"]);
+
+        let (packets, bytes) = &table.matched_total[0];
+        let (before_packets, before_bytes) = (packets.get(), bytes.get());
+        let unmatched = metrics::unmatched_total().get();
+        for source in ["10.0.0.1:9000", "10.0.0.2:9000", "172.16.0.1:9000"] {
+            let mut destinations = Vec::new();
+            let mut ctx = ReadContext::new(
+                Default::default(),
+                source.parse().unwrap(),
+                alloc_buffer(b"hello"),
+                &mut destinations,
+            );
+            filter.read(&mut ctx).unwrap();
+        }
+
+        assert_eq!(packets.get() - before_packets, 2);
+        assert_eq!(bytes.get() - before_bytes, 10);
+        assert!(metrics::unmatched_total().get() > unmatched);
+        // Each decision counts towards the logged sample.
+        assert_eq!(filter.decisions.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn matches_autonomous_systems() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - countries: [DE]
+    endpoint: 127.0.0.1:7001
+  - sources: [10.0.0.0/8]
+    asns: [3320]
+    endpoint: 127.0.0.1:7002
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+
+        // Without a MaxMind database, only the CIDRs match.
+        let table = filter.table();
+        assert_eq!(table.find_route("10.0.0.1:9000".parse().unwrap()), Some(1));
+        assert_eq!(table.find_route("1.1.1.1:9000".parse().unwrap()), None);
+
+        // `countries` is still accepted for the registration countries.
+        let entry = crate::net::maxmind_db::IpNetEntry {
+            id: 3320,
+            as_cc: "de".into(),
+            ..<_>::default()
+        };
+        assert_eq!(table.routes[0].as_countries, ["DE"]);
+        assert!(table.routes[0].matches_as(Some(&entry)));
+        assert!(table.routes[1].matches_as(Some(&entry)));
+        assert!(!table.routes[1].matches_as(None));
+
+        let config: Config =
+            serde_yaml::from_str("routes: [{ endpoint: 127.0.0.1:7001 }]").unwrap();
+        assert!(SourceIpRouter::try_from_config(Some(config)).is_err());
+    }
+
+    #[test]
+    fn skips_excluded_sources() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    exclude: [10.0.5.0/24, 10.0.6.1/32]
+    endpoint: 127.0.0.1:7001
+  - sources: [10.0.5.0/24]
+    endpoint: 127.0.0.1:7002
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+
+        for (source, route) in [
+            ("10.0.0.1:9000", Some(0)),
+            ("10.0.5.1:9000", Some(1)),
+            ("10.0.6.1:9000", None),
+            ("10.0.6.2:9000", Some(0)),
+        ] {
+            assert_eq!(
+                filter.table().find_route(source.parse().unwrap()),
+                route,
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_port_ranges() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    port_ranges: [{ start: 9000, end: 9099 }, { start: 9200, end: 9200 }]
+    endpoint: 127.0.0.1:7001
+  - sources: [10.0.0.0/8]
+    endpoint: 127.0.0.1:7002
+cache: {}
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+
+        for (source, route) in [
+            ("10.0.0.1:9000", Some(0)),
+            ("10.0.0.1:9099", Some(0)),
+            ("10.0.0.1:9200", Some(0)),
+            ("10.0.0.1:9100", Some(1)),
+            ("192.168.1.1:9000", None),
+        ] {
+            assert_eq!(
+                filter.table().find_route(source.parse().unwrap()),
+                route,
+                "{source}"
+            );
+        }
+
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    port_ranges: [{ start: 2, end: 1 }]
+    endpoint: 127.0.0.1:7001
+",
+        )
+        .unwrap();
+        assert!(SourceIpRouter::try_from_config(Some(config)).is_err());
+    }
+
+    #[test]
+    fn sets_metadata_instead_of_routing() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    action:
+      set_metadata:
+        quilkin.dev/region: eu
+        quilkin.dev/tier: free
+  - sources: [192.168.0.0/16]
+    endpoint: 127.0.0.1:7001
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+        assert_eq!(filter.capabilities().produces.len(), 2);
+
+        let previous: EndpointAddress = "127.0.0.1:7002".parse().unwrap();
+        let mut destinations = vec![previous.clone()];
+        let mut ctx = ReadContext::new(
+            Default::default(),
+            "10.0.0.1:9000".parse().unwrap(),
+            alloc_buffer(b"hello"),
+            &mut destinations,
+        );
+        filter.read(&mut ctx).unwrap();
+
+        let value = |key: &str| {
+            ctx.metadata
+                .get(&metadata::Key::new(key))
+                .and_then(|value| value.as_string())
+                .map(String::from)
+        };
+        assert_eq!(value("quilkin.dev/region").as_deref(), Some("eu"));
+        assert_eq!(value("quilkin.dev/tier").as_deref(), Some("free"));
+        // The destinations are left as they were.
+        assert_eq!(destinations, [previous]);
+
+        let config: Config =
+            serde_yaml::from_str("routes: [{ sources: [10.0.0.0/8], action: route }]").unwrap();
+        assert!(SourceIpRouter::try_from_config(Some(config)).is_err());
+    }
+
+    #[test]
+    fn routes_cached_sources() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - sources: [10.0.0.0/8]
+    endpoint: 127.0.0.1:7001
+cache: {}
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+
+        for source in ["10.0.0.1:9000", "10.0.0.1:9001", "192.168.1.1:9000"] {
+            let mut destinations = Vec::new();
+            let mut ctx = ReadContext::new(
+                Default::default(),
+                source.parse().unwrap(),
+                alloc_buffer(b"hello"),
+                &mut destinations,
+            );
+            filter.read(&mut ctx).unwrap();
+
+            let expected: Vec<EndpointAddress> = if source.starts_with("10.") {
+                vec!["127.0.0.1:7001".parse().unwrap()]
+            } else {
+                Vec::new()
+            };
+            assert_eq!(destinations, expected, "{source}");
+        }
+
+        // Both packets from `10.0.0.1` share a cached route, along with the
+        // source that matched none.
+        assert_eq!(filter.table().cache.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn updates_routes_in_place() {
+        let config: Config = serde_yaml::from_str(
+            "
+routes:
+  - name: old
+    sources: [10.0.0.0/8]
+    endpoint: 127.0.0.1:7001
+cache: {}
+",
+        )
+        .unwrap();
+        let filter = SourceIpRouter::from_config(Some(config));
+        let read = || {
+            let mut destinations = Vec::new();
+            let mut ctx = ReadContext::new(
+                Default::default(),
+                "10.0.0.1:9000".parse().unwrap(),
+                alloc_buffer(b"hello"),
+                &mut destinations,
+            );
+            filter.read(&mut ctx).unwrap();
+            destinations
+        };
+        assert_eq!(read()[0].port, 7001);
+
+        let routers = [filter.routing.clone()];
+        let patch: RoutesPatch = serde_yaml::from_str(
+            "
+remove: [old]
+add:
+  - name: new
+    sources: [10.0.0.0/8]
+    endpoint: 127.0.0.1:7002
+",
+        )
+        .unwrap();
+        let routes = update_all(&routers, |routes| patch.apply(routes))
+            .unwrap()
+            .unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].name.as_deref(), Some("new"));
+        // The cached route of the old table isn't used.
+        assert_eq!(read()[0].port, 7002);
+
+        // Invalid routes, or removing a route that doesn't exist, leave the
+        // routes as they were.
+        let invalid: Vec<Route> =
+            serde_yaml::from_str("[{ sources: [10.0.0.0/8], endpoints: [] }]").unwrap();
+        assert!(update_all(&routers, |_| Ok(invalid.clone())).is_err());
+        assert!(update_all(&routers, |routes| patch.apply(routes)).is_err());
+        assert_eq!(filter.table().routes, routes);
+    }
 }
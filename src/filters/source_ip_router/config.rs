@@ -6,23 +6,246 @@
 use ipnetwork::IpNetwork;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{net::IpAddr, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use crate::{
+    filters::{load_balancer::Policy, CreationError},
+    net::maxmind_db::IpNetEntry,
+};
 
 /// The top-level static config for the SourceIpRouter filter.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct Config {
     /// A list of routes for matching source IPs.
     pub routes: Vec<Route>,
+    /// Caches the route matched by each source IP, rather than matching
+    /// every packet against the routes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<Cache>,
+    /// How often, in seconds, endpoints with a hostname are resolved again.
+    #[serde(default = "default_dns_ttl_secs")]
+    pub dns_ttl_secs: u64,
+    /// Logs one in every this many match decisions, none when zero.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub log_sample: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            cache: None,
+            dns_ttl_secs: default_dns_ttl_secs(),
+            log_sample: 0,
+        }
+    }
+}
+
+pub(super) fn default_dns_ttl_secs() -> u64 {
+    30
+}
+
+/// How many matched routes are cached, and for how long.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Cache {
+    /// The most source IPs cached, the least recently used are evicted first.
+    #[serde(default = "default_cache_capacity")]
+    pub capacity: usize,
+    /// How long a matched route is cached for, in milliseconds.
+    #[serde(default = "default_cache_ttl_ms")]
+    pub ttl_ms: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            capacity: default_cache_capacity(),
+            ttl_ms: default_cache_ttl_ms(),
+        }
+    }
+}
+
+fn default_cache_capacity() -> usize {
+    4096
+}
+
+fn default_cache_ttl_ms() -> u64 {
+    60_000
 }
 
 /// A single routing rule: if the source IP matches any of `sources`,
-/// we route to `endpoint`.
+/// `as_countries` or `asns` but none of `exclude`, and its port any of
+/// `port_ranges` when set, we route to one of its `endpoints`, chosen by
+/// its `policy`, or tag the packet instead, depending on its `action`.
+///
+/// When several routes match, only those with the highest `priority` are
+/// considered. Sources are split between those of them with a `weight` in
+/// proportion to it, otherwise the first of them in the list wins.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct Route {
+    /// The name the route's metrics are labelled with, its index in the
+    /// routes when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     /// One or more CIDR notations (e.g. `192.168.1.0/24`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub sources: Vec<Cidr>,
-    /// The endpoint (e.g. `127.0.0.1:6001`) to route to if matched.
-    pub endpoint: String,
+    /// The codes (e.g. `DE`) of the countries the autonomous systems matched
+    /// are registered in, their `as_cc` in the proxy's MaxMind database. This
+    /// is where the network's operator is registered, which isn't always
+    /// where its clients are.
+    #[serde(default, alias = "countries", skip_serializing_if = "Vec::is_empty")]
+    pub as_countries: Vec<String>,
+    /// The numbers of the autonomous systems matched, as found in the
+    /// proxy's MaxMind database.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub asns: Vec<u64>,
+    /// CIDRs carved out of `sources`, which the route never matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<Cidr>,
+    /// The source ports matched, any port when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub port_ranges: Vec<PortRange>,
+    /// The endpoints (e.g. `127.0.0.1:6001` or `game.example.com:6001`) to
+    /// route to if matched. A single `endpoint` is accepted as well.
+    #[serde(
+        default,
+        alias = "endpoint",
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "one_or_many::deserialize"
+    )]
+    pub endpoints: Vec<String>,
+    /// How the endpoint each packet is sent to is chosen, when there are
+    /// several.
+    #[serde(default)]
+    pub policy: Policy,
+    /// Routes with a higher priority win over the others matching a source.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: u32,
+    /// The share of the sources matching routes of the same priority sent
+    /// to this route, none when zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// What happens to the packets matching the route.
+    #[serde(default, skip_serializing_if = "Action::is_route")]
+    pub action: Action,
+}
+
+/// What a route does with the packets it matches.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Sends them to one of the route's `endpoints`.
+    #[default]
+    Route,
+    /// Sets each key to its value in their dynamic metadata, leaving their
+    /// destinations to the filters after this one, such as a `TokenRouter`
+    /// or `ContentRouter`.
+    SetMetadata(BTreeMap<String, String>),
+}
+
+impl Action {
+    fn is_route(&self) -> bool {
+        *self == Self::Route
+    }
+}
+
+impl Route {
+    /// Returns true if `source` is contained in any of the route's sources,
+    /// or its autonomous system in the route's countries or ASNs, and in none
+    /// of its exclusions, and its port in any of its port ranges.
+    pub fn matches(&self, source: SocketAddr) -> bool {
+        (self.sources.iter().any(|cidr| cidr.contains(source.ip()))
+            || self.matches_as(crate::net::maxmind_db::MaxmindDb::lookup(source.ip()).as_ref()))
+            && self.admits(source)
+    }
+
+    /// Returns true if `source` is in none of the route's exclusions, and its
+    /// port in any of its port ranges.
+    pub(super) fn admits(&self, source: SocketAddr) -> bool {
+        !self.exclude.iter().any(|cidr| cidr.contains(source.ip()))
+            && (self.port_ranges.is_empty()
+                || self
+                    .port_ranges
+                    .iter()
+                    .any(|range| range.contains(source.port())))
+    }
+
+    /// Returns true if the autonomous system `entry`, as looked up in the
+    /// MaxMind database, is in the route's countries or ASNs. Nothing matches
+    /// without an entry, such as before the database is loaded.
+    pub(super) fn matches_as(&self, entry: Option<&IpNetEntry>) -> bool {
+        entry.is_some_and(|entry| {
+            self.asns.contains(&entry.id)
+                || self
+                    .as_countries
+                    .iter()
+                    .any(|country| country.eq_ignore_ascii_case(&entry.as_cc))
+        })
+    }
+}
+
+/// Routes removed from and added to those of running filters, through the
+/// admin server.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutesPatch {
+    /// The names of the routes removed.
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// The routes added after the remaining ones.
+    #[serde(default)]
+    pub add: Vec<Route>,
+}
+
+impl RoutesPatch {
+    /// Returns `routes` without those named in `remove`, followed by `add`.
+    /// Fails if a name in `remove` matches none of `routes`.
+    pub fn apply(&self, routes: &[Route]) -> Result<Vec<Route>, CreationError> {
+        if let Some(name) = self.remove.iter().find(|name| {
+            !routes
+                .iter()
+                .any(|route| route.name.as_ref() == Some(*name))
+        }) {
+            return Err(CreationError::FieldInvalid {
+                field: "remove".into(),
+                reason: format!("no route is named `{name}`"),
+            });
+        }
+
+        Ok(routes
+            .iter()
+            .filter(|route| {
+                !route
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| self.remove.contains(name))
+            })
+            .chain(&self.add)
+            .cloned()
+            .collect())
+    }
+}
+
+/// A range of ports, including both `start` and `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Returns true if `port` is within this range.
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
 }
 
 /// A CIDR type wrapping `IpNetwork`, with JSON serialization logic.
@@ -44,11 +267,13 @@ impl JsonSchema for Cidr {
 }
 
 impl Cidr {
-    /// Returns true if `ip` is contained in this CIDR range.
+    /// Returns true if `ip` is contained in this CIDR range. IPv4 ranges also
+    /// contain the IPv4 addresses mapped to IPv6 (`::ffff:a.b.c.d`), but not
+    /// the deprecated IPv4-compatible ones such as `::1`.
     pub fn contains(&self, ip: IpAddr) -> bool {
         match (self.0, ip) {
             (IpNetwork::V4(v4net), IpAddr::V6(v6)) => {
-                if let Some(mapped_v4) = v6.to_ipv4() {
+                if let Some(mapped_v4) = v6.to_ipv4_mapped() {
                     v4net.contains(mapped_v4)
                 } else {
                     false
@@ -67,6 +292,28 @@ impl FromStr for Cidr {
     }
 }
 
+/// Deserializes a list of strings, or a single string as a list of one.
+mod one_or_many {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OneOrMany {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        Ok(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        })
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // (De)serialization helpers for IpNetwork (to treat it as a string in JSON).
 ////////////////////////////////////////////////////////////////////////////////
@@ -0,0 +1,57 @@
+//! src/filters/source_ip_router/metrics.rs
+//!
+//! The metrics of the routes packets matched.
+
+use once_cell::sync::Lazy;
+use prometheus::{IntCounter, IntCounterVec};
+
+use crate::metrics::registry;
+
+const ROUTE_LABEL: &str = "route";
+
+pub(super) fn packets_total(route: &str) -> IntCounter {
+    static PACKETS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "source_ip_router_packets_total",
+                "Total number of packets that matched each route",
+            },
+            &[ROUTE_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS.with_label_values(&[route])
+}
+
+pub(super) fn bytes_total(route: &str) -> IntCounter {
+    static BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "source_ip_router_bytes_total",
+                "Total number of bytes of the packets that matched each route",
+            },
+            &[ROUTE_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    BYTES.with_label_values(&[route])
+}
+
+pub(super) fn unmatched_total() -> &'static IntCounter {
+    static UNMATCHED: Lazy<IntCounter> = Lazy::new(|| {
+        prometheus::register_int_counter_with_registry! {
+            prometheus::opts! {
+                "source_ip_router_unmatched_total",
+                "Total number of packets that matched no route",
+            },
+            registry(),
+        }
+        .unwrap()
+    });
+
+    &UNMATCHED
+}
@@ -0,0 +1,153 @@
+//! src/filters/source_ip_router/resolver.rs
+//!
+//! Resolves the routes' endpoints that are hostnames, and resolves them
+//! again periodically so changes to their addresses reach the filter without
+//! recreating it.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::IpAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+
+use crate::net::endpoint::{AddressKind, EndpointAddress};
+
+/// The addresses packets matching each route are sent to, by route index.
+pub(super) type Destinations = Vec<Vec<EndpointAddress>>;
+
+/// Returns the addresses of `endpoints`, with each hostname replaced by the
+/// addresses it resolved to in `names`, if any.
+pub(super) fn destinations(
+    endpoints: &[Vec<EndpointAddress>],
+    names: &HashMap<String, Vec<IpAddr>>,
+) -> Destinations {
+    endpoints
+        .iter()
+        .map(|addresses| {
+            addresses
+                .iter()
+                .flat_map(|address| match &address.host {
+                    AddressKind::Ip(_) => vec![address.clone()],
+                    AddressKind::Name(name) => names
+                        .get(name)
+                        .into_iter()
+                        .flatten()
+                        .map(|ip| (*ip, address.port).into())
+                        .collect(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Spawns a task resolving the hostnames in `endpoints` every `interval`,
+/// storing the addresses they resolve to in `destinations` until it's
+/// dropped. A name that fails to resolve keeps the addresses it last
+/// resolved to. Outside of a runtime the names are never resolved.
+pub(super) fn spawn(
+    endpoints: Vec<Vec<EndpointAddress>>,
+    destinations: Weak<ArcSwap<Destinations>>,
+    interval: Duration,
+) {
+    let names: BTreeSet<String> = endpoints
+        .iter()
+        .flatten()
+        .filter_map(|address| match &address.host {
+            AddressKind::Name(name) => Some(name.clone()),
+            AddressKind::Ip(_) => None,
+        })
+        .collect();
+    if names.is_empty() {
+        return;
+    }
+
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        tracing::warn!("not resolving source ip router endpoints outside of a runtime");
+        return;
+    };
+
+    runtime.spawn(async move {
+        let mut resolved = HashMap::new();
+        loop {
+            for name in &names {
+                match crate::net::dns::resolver().lookup_ip(name.as_str()).await {
+                    Ok(lookup) => {
+                        // DNS servers often rotate the order of their
+                        // answers, which would move sources hashed to an
+                        // endpoint between its addresses.
+                        let mut addresses: Vec<IpAddr> = lookup.iter().collect();
+                        addresses.sort_unstable();
+                        addresses.dedup();
+                        resolved.insert(name.clone(), addresses);
+                    }
+                    Err(error) => {
+                        tracing::warn!(
+                            %name,
+                            %error,
+                            "failed to resolve source ip router endpoint"
+                        );
+                    }
+                }
+            }
+
+            let Some(destinations) = destinations.upgrade() else {
+                return;
+            };
+            destinations.store(Arc::new(self::destinations(&endpoints, &resolved)));
+            drop(destinations);
+
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_names_with_addresses() {
+        let endpoints: Vec<Vec<EndpointAddress>> = vec![
+            vec!["127.0.0.1:7001".parse().unwrap()],
+            vec![
+                "game.example.com:7002".parse().unwrap(),
+                "127.0.0.1:7003".parse().unwrap(),
+            ],
+        ];
+
+        let unresolved = destinations(&endpoints, &HashMap::new());
+        assert_eq!(unresolved[0], endpoints[0]);
+        assert_eq!(unresolved[1], [endpoints[1][1].clone()]);
+
+        let names = HashMap::from([(
+            "game.example.com".to_owned(),
+            vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+        )]);
+        let addresses: Vec<String> = destinations(&endpoints, &names)[1]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            addresses,
+            ["10.0.0.1:7002", "10.0.0.2:7002", "127.0.0.1:7003"]
+        );
+    }
+
+    #[test]
+    fn skips_resolving_outside_a_runtime() {
+        let endpoints = vec![vec!["game.example.com:7001".parse().unwrap()]];
+        let destinations = Arc::new(ArcSwap::from_pointee(destinations(
+            &endpoints,
+            &HashMap::new(),
+        )));
+        spawn(
+            endpoints,
+            Arc::downgrade(&destinations),
+            Duration::from_secs(1),
+        );
+        assert!(destinations.load()[0].is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! src/filters/source_ip_router/trie.rs
+//!
+//! A binary radix trie of the routes' CIDRs, so finding the routes a source
+//! IP matches walks at most one node per bit of the address, rather than
+//! every CIDR of every route.
+
+use ipnetwork::IpNetwork;
+use std::net::IpAddr;
+
+use super::Route;
+
+/// The routes whose CIDRs end at this node, and the nodes for the next bit.
+#[derive(Default)]
+struct Node {
+    children: [Option<u32>; 2],
+    routes: Vec<usize>,
+}
+
+/// The trie of one address family.
+struct Family {
+    nodes: Vec<Node>,
+    /// The number of bits in the family's addresses.
+    width: u32,
+}
+
+impl Family {
+    fn new(width: u32) -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            width,
+        }
+    }
+
+    fn bit(&self, bits: u128, index: u32) -> usize {
+        ((bits >> (self.width - 1 - index)) & 1) as usize
+    }
+
+    fn insert(&mut self, bits: u128, prefix: u8, route: usize) {
+        let mut node = 0;
+        for index in 0..u32::from(prefix) {
+            let bit = self.bit(bits, index);
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child as usize,
+                None => {
+                    self.nodes.push(Node::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[bit] = Some(child as u32);
+                    child
+                }
+            };
+        }
+
+        let routes = &mut self.nodes[node].routes;
+        if routes.last() != Some(&route) {
+            routes.push(route);
+        }
+    }
+
+    /// Adds the routes of every CIDR containing `bits` to `matched`.
+    fn matches(&self, bits: u128, matched: &mut Vec<usize>) {
+        let mut node = &self.nodes[0];
+        matched.extend(&node.routes);
+        for index in 0..self.width {
+            let Some(child) = node.children[self.bit(bits, index)] else {
+                break;
+            };
+            node = &self.nodes[child as usize];
+            matched.extend(&node.routes);
+        }
+    }
+}
+
+/// The routes containing each CIDR, across both address families.
+pub(super) struct CidrTrie {
+    v4: Family,
+    v6: Family,
+}
+
+impl CidrTrie {
+    pub(super) fn new(routes: &[Route]) -> Self {
+        let mut trie = Self {
+            v4: Family::new(32),
+            v6: Family::new(128),
+        };
+
+        for (index, route) in routes.iter().enumerate() {
+            for cidr in &route.sources {
+                match cidr.0 {
+                    IpNetwork::V4(net) => {
+                        trie.v4
+                            .insert(u32::from(net.network()).into(), net.prefix(), index)
+                    }
+                    IpNetwork::V6(net) => {
+                        trie.v6
+                            .insert(u128::from(net.network()), net.prefix(), index)
+                    }
+                }
+            }
+        }
+
+        trie
+    }
+
+    /// Returns the indices of the routes with a CIDR containing `ip`, in
+    /// order and without duplicates.
+    pub(super) fn matches(&self, ip: IpAddr) -> Vec<usize> {
+        let mut matched = Vec::new();
+        match ip {
+            IpAddr::V4(v4) => self.v4.matches(u32::from(v4).into(), &mut matched),
+            IpAddr::V6(v6) => {
+                self.v6.matches(u128::from(v6), &mut matched);
+                // IPv4 CIDRs also contain the IPv4 addresses mapped to IPv6,
+                // as in `Cidr::contains`.
+                if let Some(v4) = v6.to_ipv4_mapped() {
+                    self.v4.matches(u32::from(v4).into(), &mut matched);
+                }
+            }
+        }
+
+        matched.sort_unstable();
+        matched.dedup();
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_like_a_scan() {
+        let routes: Vec<Route> = serde_yaml::from_str(
+            "
+- sources: [10.0.0.0/8, 10.1.0.0/16]
+  endpoint: 127.0.0.1:7001
+- sources: [10.1.2.0/24, 192.168.0.0/16]
+  endpoint: 127.0.0.1:7002
+- sources: [0.0.0.0/0]
+  endpoint: 127.0.0.1:7003
+- sources: ['2001:db8::/32', 10.1.2.3/32]
+  endpoint: 127.0.0.1:7004
+",
+        )
+        .unwrap();
+        let trie = CidrTrie::new(&routes);
+
+        for ip in [
+            "10.0.0.1",
+            "10.1.2.3",
+            "10.1.2.4",
+            "192.168.1.1",
+            "172.16.0.1",
+            "2001:db8::1",
+            "2001:db9::1",
+            "::ffff:10.1.2.3",
+            "::1",
+            "::",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            let scanned: Vec<usize> = routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.sources.iter().any(|cidr| cidr.contains(ip)))
+                .map(|(index, _)| index)
+                .collect();
+            assert_eq!(trie.matches(ip), scanned, "{ip}");
+        }
+
+        // IPv4-compatible addresses aren't IPv4 addresses.
+        assert!(trie.matches("::1".parse().unwrap()).is_empty());
+        assert!(trie.matches("::".parse().unwrap()).is_empty());
+        assert_eq!(trie.matches("::ffff:172.16.0.1".parse().unwrap()), [2]);
+    }
+}
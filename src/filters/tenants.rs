@@ -0,0 +1,540 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod config;
+mod metrics;
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{
+    collections::ttl::TtlMap,
+    filters::{prelude::*, FilterChain, FilterState},
+    metrics::Direction,
+    net::{
+        endpoint::{metadata, EndpointAddress},
+        tenant,
+    },
+};
+
+use crate::generated::quilkin::filters::tenants::v1alpha1 as proto;
+
+pub use config::{Config, Tenant, TokenPrefix};
+
+/// How long a client is associated with a tenant after its last packet.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+const CLIENT_EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+const CLIENT_QUOTA_EXCEEDED: FilterError =
+    FilterError::Custom("filter::tenants::client quota exceeded");
+const RATE_QUOTA_EXCEEDED: FilterError =
+    FilterError::Custom("filter::tenants::rate quota exceeded");
+
+/// A tenant's filters, and the state of its quotas.
+struct TenantState {
+    name: String,
+    chain: FilterChain,
+    max_packets_per_second: Option<u64>,
+    max_clients: Option<usize>,
+    /// The second the current rate window started, relative to the filter's
+    /// creation.
+    window: AtomicU64,
+    /// The number of packets accepted in the current rate window.
+    packets: AtomicU64,
+    /// The tenant's active clients, only tracked when its clients are
+    /// limited.
+    clients: Option<TtlMap<EndpointAddress, ()>>,
+}
+
+impl TenantState {
+    /// Returns an error if accepting a packet from `source` at `now`, in
+    /// seconds since the filter's creation, would exceed the tenant's quotas.
+    ///
+    /// Like [`LocalRateLimit`](crate::filters::LocalRateLimit), the window and
+    /// counter are independent atomics, so a few packets more than the limit
+    /// can be accepted when a window starts.
+    fn admit(&self, source: &EndpointAddress, now: u64) -> Result<(), FilterError> {
+        if let (Some(clients), Some(max_clients)) = (&self.clients, self.max_clients) {
+            if clients.get(source).is_none() {
+                if clients.len() >= max_clients {
+                    return Err(CLIENT_QUOTA_EXCEEDED);
+                }
+                clients.insert(source.clone(), ());
+            }
+        }
+
+        if let Some(max_packets) = self.max_packets_per_second {
+            if self.window.load(Ordering::Relaxed) != now {
+                self.window.store(now, Ordering::Relaxed);
+                self.packets.store(0, Ordering::Relaxed);
+            }
+
+            if self.packets.fetch_add(1, Ordering::Relaxed) >= max_packets {
+                return Err(RATE_QUOTA_EXCEEDED);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of a tenant exported to another proxy.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ExportedTenant {
+    /// The state of the tenant's filters.
+    filters: Vec<FilterState>,
+    /// The clients counted against the tenant's `max_clients`.
+    clients: Vec<EndpointAddress>,
+}
+
+/// The state of [`Tenants`] exported to another proxy, where tenants are
+/// kept by name as their order may differ.
+#[derive(Debug, Deserialize, Serialize)]
+struct ExportedState {
+    tenants: HashMap<String, ExportedTenant>,
+    fallthrough: Vec<FilterState>,
+    /// The tenant each client last sent a packet for, `None` for the
+    /// fallthrough.
+    clients: Vec<(EndpointAddress, Option<String>)>,
+}
+
+/// A filter that assigns packets to one of several tenants sharing the
+/// proxy, running each tenant's own filter chain and holding it to its own
+/// quotas, so one tenant's traffic can't starve another's.
+///
+/// A packet belongs to the tenant named by `quilkin.dev/tenant` in its
+/// dynamic metadata if set, otherwise to the first tenant with the port it
+/// was sent to, otherwise to the first tenant with a prefix of its routing
+/// token. Packets from upstream are run through the chain of the tenant the
+/// client last sent a packet to.
+pub struct Tenants {
+    metadata_key: metadata::Key,
+    tenants: Vec<TenantState>,
+    names: HashMap<String, usize>,
+    ports: HashMap<u16, usize>,
+    /// The token prefixes of every tenant, in configuration order.
+    token_prefixes: Vec<(Vec<u8>, usize)>,
+    fallthrough: FilterChain,
+    /// The index of the tenant each client last sent a packet for.
+    clients: TtlMap<EndpointAddress, usize>,
+    created_at: Instant,
+}
+
+impl Tenants {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let mut tenants = Vec::with_capacity(config.tenants.len());
+        let mut names = HashMap::new();
+        let mut ports = HashMap::new();
+        let mut token_prefixes = Vec::new();
+
+        for (index, tenant) in config.tenants.into_iter().enumerate() {
+            if tenant.name.is_empty() {
+                return Err(CreationError::FieldInvalid {
+                    field: "tenants.name".into(),
+                    reason: "tenants must have a name".into(),
+                });
+            }
+
+            if names.insert(tenant.name.clone(), index).is_some() {
+                return Err(CreationError::FieldInvalid {
+                    field: "tenants.name".into(),
+                    reason: format!("tenant `{}` is configured more than once", tenant.name),
+                });
+            }
+
+            for port in tenant.ports {
+                if ports.insert(port, index).is_some() {
+                    return Err(CreationError::FieldInvalid {
+                        field: "tenants.ports".into(),
+                        reason: format!("port {port} is used by more than one tenant"),
+                    });
+                }
+            }
+
+            token_prefixes.extend(
+                tenant
+                    .token_prefixes
+                    .into_iter()
+                    .map(|prefix| (prefix.0, index)),
+            );
+
+            let max_clients = tenant
+                .max_clients
+                .map(|max| usize::try_from(max).unwrap_or(usize::MAX));
+            tenants.push(TenantState {
+                name: tenant.name,
+                chain: FilterChain::try_create(tenant.filters)?,
+                max_packets_per_second: tenant.max_packets_per_second,
+                max_clients,
+                window: AtomicU64::new(0),
+                packets: AtomicU64::new(0),
+                clients: max_clients
+                    .map(|_| TtlMap::new(CLIENT_TIMEOUT, CLIENT_EXPIRY_POLL_INTERVAL)),
+            });
+        }
+
+        Ok(Self {
+            metadata_key: config.metadata_key,
+            tenants,
+            names,
+            ports,
+            token_prefixes,
+            fallthrough: FilterChain::try_create(config.fallthrough)?,
+            clients: TtlMap::new(CLIENT_TIMEOUT, CLIENT_EXPIRY_POLL_INTERVAL),
+            created_at: Instant::now(),
+        })
+    }
+
+    /// Returns the index of the tenant the packet belongs to, if any.
+    fn classify(&self, ctx: &ReadContext<'_>) -> Option<usize> {
+        if let Some(name) = tenant::get(&ctx.metadata) {
+            return self.names.get(name).copied();
+        }
+
+        if let Some(index) = ctx
+            .destination_port
+            .and_then(|port| self.ports.get(&port).copied())
+        {
+            return Some(index);
+        }
+
+        if self.token_prefixes.is_empty() {
+            return None;
+        }
+
+        let key = metadata::TypedKey::<bytes::Bytes>::from(self.metadata_key);
+        let token = ctx.metadata.get_typed(&key)?;
+        self.token_prefixes
+            .iter()
+            .find(|(prefix, _)| token.starts_with(prefix))
+            .map(|(_, index)| *index)
+    }
+}
+
+impl Filter for Tenants {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let index = self.classify(ctx).unwrap_or(self.tenants.len());
+
+        let known = self
+            .clients
+            .get(&ctx.source)
+            .is_some_and(|entry| entry.value == index);
+        if !known {
+            self.clients.insert(ctx.source.clone(), index);
+        }
+
+        let Some(tenant) = self.tenants.get(index) else {
+            return self.fallthrough.read(ctx);
+        };
+
+        tracing::trace!(tenant = %tenant.name, "selected tenant");
+        metrics::packets_total(&tenant.name, Direction::Read).inc();
+        tenant::set(&mut ctx.metadata, &tenant.name);
+
+        let result = tenant
+            .admit(&ctx.source, self.created_at.elapsed().as_secs())
+            .and_then(|()| tenant.chain.read(ctx));
+        if let Err(error) = &result {
+            metrics::packets_dropped_total(&tenant.name, Direction::Read, error.discriminant())
+                .inc();
+        }
+
+        result
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let tenant = self
+            .clients
+            .get(&ctx.dest)
+            .and_then(|entry| self.tenants.get(entry.value));
+
+        let Some(tenant) = tenant else {
+            return self.fallthrough.write(ctx);
+        };
+
+        metrics::packets_total(&tenant.name, Direction::Write).inc();
+        let result = tenant.chain.write(ctx);
+        if let Err(error) = &result {
+            metrics::packets_dropped_total(&tenant.name, Direction::Write, error.discriminant())
+                .inc();
+        }
+
+        result
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            consumes: if self.token_prefixes.is_empty() {
+                Vec::new()
+            } else {
+                vec![self.metadata_key]
+            },
+            produces: vec![metadata::Key::from_static(tenant::METADATA_KEY)],
+            ..<_>::default()
+        }
+    }
+
+    fn export_state(&self) -> Option<serde_json::Value> {
+        let tenants = self
+            .tenants
+            .iter()
+            .map(|tenant| {
+                let exported = ExportedTenant {
+                    filters: tenant.chain.export_state(),
+                    clients: tenant
+                        .clients
+                        .as_ref()
+                        .map(|clients| clients.snapshot(|source, ()| source.clone()))
+                        .unwrap_or_default(),
+                };
+                (tenant.name.clone(), exported)
+            })
+            .collect();
+        let clients = self.clients.snapshot(|source, index| {
+            let name = self.tenants.get(*index).map(|tenant| tenant.name.clone());
+            (source.clone(), name)
+        });
+
+        serde_json::to_value(ExportedState {
+            tenants,
+            fallthrough: self.fallthrough.export_state(),
+            clients,
+        })
+        .ok()
+    }
+
+    fn import_state(&self, state: serde_json::Value) -> Result<(), serde_json::Error> {
+        let state: ExportedState = serde_json::from_value(state)?;
+        for (name, exported) in state.tenants {
+            let Some(tenant) = self.names.get(&name).map(|index| &self.tenants[*index]) else {
+                continue;
+            };
+
+            tenant.chain.import_state(exported.filters);
+            if let Some(clients) = &tenant.clients {
+                for source in exported.clients {
+                    clients.insert(source, ());
+                }
+            }
+        }
+        self.fallthrough.import_state(state.fallthrough);
+
+        for (source, name) in state.clients {
+            let index = match name {
+                Some(name) => match self.names.get(&name) {
+                    Some(index) => *index,
+                    None => continue,
+                },
+                None => self.tenants.len(),
+            };
+            self.clients.insert(source, index);
+        }
+
+        Ok(())
+    }
+}
+
+impl StaticFilter for Tenants {
+    const NAME: &'static str = "quilkin.filters.tenants.v1alpha1.Tenants";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Tenants;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{net::endpoint::metadata::Value, test::alloc_buffer};
+
+    fn config() -> Config {
+        serde_yaml::from_str(
+            "
+tenants:
+    - name: title-a
+      ports: [7777]
+      filters:
+        - name: quilkin.filters.pass.v1alpha1.Pass
+      max_packets_per_second: 2
+    - name: title-b
+      token_prefixes: [Yg==]
+      filters:
+        - name: quilkin.filters.drop.v1alpha1.Drop
+    - name: title-c
+      filters:
+        - name: quilkin.filters.pass.v1alpha1.Pass
+      max_clients: 1
+fallthrough:
+    - name: quilkin.filters.pass.v1alpha1.Pass
+",
+        )
+        .unwrap()
+    }
+
+    /// Runs a packet from `source` through `filter`, returning the tenant
+    /// it was assigned to.
+    fn read(
+        filter: &Tenants,
+        source: &str,
+        port: Option<u16>,
+        token: Option<&[u8]>,
+        name: Option<&str>,
+    ) -> Result<Option<String>, FilterError> {
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            source.parse().unwrap(),
+            alloc_buffer(b"hello"),
+            &mut dest,
+        );
+        ctx.destination_port = port;
+        if let Some(token) = token {
+            ctx.metadata.insert(
+                crate::filters::capture::CAPTURED_BYTES.into(),
+                Value::Bytes(token.to_vec().into()),
+            );
+        }
+        if let Some(name) = name {
+            tenant::set(&mut ctx.metadata, name);
+        }
+
+        filter
+            .read(&mut ctx)
+            .map(|()| tenant::get(&ctx.metadata).map(String::from))
+    }
+
+    #[tokio::test]
+    async fn classifies_packets() {
+        let filter = Tenants::from_config(Some(config()));
+
+        assert_eq!(
+            read(&filter, "127.0.0.1:70", Some(7777), None, None),
+            Ok(Some("title-a".into()))
+        );
+        assert_eq!(
+            read(&filter, "127.0.0.1:71", None, Some(b"bcd"), None),
+            Err(FilterError::Dropped)
+        );
+        assert_eq!(
+            read(&filter, "127.0.0.1:72", Some(9999), None, Some("title-c")),
+            Ok(Some("title-c".into()))
+        );
+        assert_eq!(
+            read(&filter, "127.0.0.1:73", Some(9999), Some(b"xyz"), None),
+            Ok(None)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_quota() {
+        let filter = Tenants::from_config(Some(config()));
+
+        for _ in 0..2 {
+            assert!(read(&filter, "127.0.0.1:70", Some(7777), None, None).is_ok());
+        }
+        assert_eq!(
+            read(&filter, "127.0.0.1:71", Some(7777), None, None),
+            Err(RATE_QUOTA_EXCEEDED)
+        );
+
+        // Other tenants are unaffected.
+        assert!(read(&filter, "127.0.0.1:72", None, None, Some("title-c")).is_ok());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(read(&filter, "127.0.0.1:70", Some(7777), None, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn client_quota() {
+        let filter = Tenants::from_config(Some(config()));
+
+        assert!(read(&filter, "127.0.0.1:70", None, None, Some("title-c")).is_ok());
+        assert!(read(&filter, "127.0.0.1:70", None, None, Some("title-c")).is_ok());
+        assert_eq!(
+            read(&filter, "127.0.0.1:71", None, None, Some("title-c")),
+            Err(CLIENT_QUOTA_EXCEEDED)
+        );
+    }
+
+    #[tokio::test]
+    async fn export_and_import_state() {
+        let previous = Tenants::from_config(Some(config()));
+        assert!(read(&previous, "127.0.0.1:70", None, None, Some("title-c")).is_ok());
+
+        let next = Tenants::from_config(Some(config()));
+        next.import_state(previous.export_state().unwrap()).unwrap();
+        assert_eq!(
+            read(&next, "127.0.0.1:71", None, None, Some("title-c")),
+            Err(CLIENT_QUOTA_EXCEEDED)
+        );
+        assert!(read(&next, "127.0.0.1:70", None, None, Some("title-c")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn write_uses_client_tenant() {
+        let filter = Tenants::from_config(Some(config()));
+        assert!(read(&filter, "127.0.0.1:70", None, Some(b"b"), None).is_err());
+
+        let mut ctx = WriteContext::new(
+            "127.0.0.1:81".parse().unwrap(),
+            "127.0.0.1:70".parse().unwrap(),
+            alloc_buffer(b"abc"),
+        );
+        assert!(filter.write(&mut ctx).is_err());
+
+        // Unknown clients use the fallthrough.
+        let mut ctx = WriteContext::new(
+            "127.0.0.1:81".parse().unwrap(),
+            "127.0.0.1:71".parse().unwrap(),
+            alloc_buffer(b"abc"),
+        );
+        assert!(filter.write(&mut ctx).is_ok());
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let tenant = |name: &str, port| Tenant {
+            name: name.into(),
+            ports: vec![port],
+            token_prefixes: Vec::new(),
+            filters: Vec::new(),
+            max_packets_per_second: None,
+            max_clients: None,
+        };
+
+        for tenants in [
+            vec![tenant("a", 7777), tenant("a", 7778)],
+            vec![tenant("a", 7777), tenant("b", 7777)],
+        ] {
+            let config = Config {
+                metadata_key: crate::filters::capture::CAPTURED_BYTES.into(),
+                tenants,
+                fallthrough: Vec::new(),
+            };
+            assert!(Tenants::try_from_config(Some(config)).is_err());
+        }
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use serde::{Deserialize, Serialize};
+
+use super::proto;
+use crate::{
+    config::{Base64Standard, Filter},
+    filters::{capture::CAPTURED_BYTES, ConvertProtoConfigError, CreationError},
+    net::endpoint::metadata,
+};
+
+/// Configuration for [`Tenants`][super::Tenants].
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The key of the routing token in the filter's dynamic metadata, which
+    /// is matched against the tenants' `token_prefixes`.
+    #[serde(rename = "metadataKey", default = "default_metadata_key")]
+    pub metadata_key: metadata::Key,
+    /// The tenants served by the proxy.
+    pub tenants: Vec<Tenant>,
+    /// The filters to run for packets that don't belong to any tenant.
+    /// Defaults to no filters.
+    #[serde(default)]
+    pub fallthrough: Vec<Filter>,
+}
+
+/// Default value for [`Config::metadata_key`]
+fn default_metadata_key() -> metadata::Key {
+    metadata::Key::from_static(CAPTURED_BYTES)
+}
+
+impl TryFrom<Config> for proto::Tenants {
+    type Error = CreationError;
+
+    fn try_from(config: Config) -> Result<Self, Self::Error> {
+        Ok(Self {
+            metadata_key: Some(config.metadata_key.to_string()),
+            tenants: config
+                .tenants
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+            fallthrough: config
+                .fallthrough
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<proto::Tenants> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(value: proto::Tenants) -> Result<Self, Self::Error> {
+        Ok(Self {
+            metadata_key: value
+                .metadata_key
+                .map(metadata::Key::new)
+                .unwrap_or_else(default_metadata_key),
+            tenants: value
+                .tenants
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+            fallthrough: value
+                .fallthrough
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, CreationError>>()
+                .map_err(|error| ConvertProtoConfigError::new(error, Some("fallthrough".into())))?,
+        })
+    }
+}
+
+/// A tenant, with how its packets are recognised, the filters run for them,
+/// and the quotas they're held to.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    /// The name of the tenant, used in metrics and dynamic metadata.
+    pub name: String,
+    /// The destination ports of the tenant's packets.
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    /// The base64 encoded prefixes of the tenant's routing tokens.
+    #[serde(default)]
+    pub token_prefixes: Vec<TokenPrefix>,
+    /// The filters to run for the tenant's packets.
+    pub filters: Vec<Filter>,
+    /// The most packets per second accepted from the tenant's clients
+    /// altogether, any more are dropped before running its filters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_packets_per_second: Option<u64>,
+    /// The most clients the tenant can have at once, packets from new
+    /// clients are dropped until another has been idle for a minute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_clients: Option<u64>,
+}
+
+/// A base64 encoded routing token prefix.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+pub struct TokenPrefix(
+    #[serde(with = "Base64Standard")]
+    #[schemars(with = "String")]
+    pub Vec<u8>,
+);
+
+impl TryFrom<Tenant> for proto::tenants::Tenant {
+    type Error = CreationError;
+
+    fn try_from(tenant: Tenant) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: tenant.name,
+            ports: tenant.ports.into_iter().map(u32::from).collect(),
+            token_prefixes: tenant
+                .token_prefixes
+                .into_iter()
+                .map(|prefix| prefix.0)
+                .collect(),
+            filters: tenant
+                .filters
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
+            max_packets_per_second: tenant.max_packets_per_second,
+            max_clients: tenant.max_clients,
+        })
+    }
+}
+
+impl TryFrom<proto::tenants::Tenant> for Tenant {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(tenant: proto::tenants::Tenant) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: tenant.name,
+            ports: tenant
+                .ports
+                .into_iter()
+                .map(|port| {
+                    u16::try_from(port).map_err(|_| {
+                        ConvertProtoConfigError::new(
+                            format!("{port} is not a valid port"),
+                            Some("tenants.ports".into()),
+                        )
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            token_prefixes: tenant.token_prefixes.into_iter().map(TokenPrefix).collect(),
+            filters: tenant
+                .filters
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, CreationError>>()
+                .map_err(|error| {
+                    ConvertProtoConfigError::new(error, Some("tenants.filters".into()))
+                })?,
+            max_packets_per_second: tenant.max_packets_per_second,
+            max_clients: tenant.max_clients,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::StaticFilter;
+
+    #[test]
+    fn serde() {
+        let yaml = "
+tenants:
+    - name: title-a
+      ports: [7777]
+      token_prefixes: [YQ==]
+      filters:
+        - name: quilkin.filters.debug.v1alpha1.Debug
+      max_packets_per_second: 1000
+fallthrough:
+    - name: quilkin.filters.drop.v1alpha1.Drop
+        ";
+
+        let config = serde_yaml::from_str::<Config>(yaml).unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                metadata_key: default_metadata_key(),
+                tenants: vec![Tenant {
+                    name: "title-a".into(),
+                    ports: vec![7777],
+                    token_prefixes: vec![TokenPrefix(b"a".to_vec())],
+                    filters: vec![crate::filters::Debug::as_filter_config(None).unwrap()],
+                    max_packets_per_second: Some(1000),
+                    max_clients: None,
+                }],
+                fallthrough: vec![crate::filters::Drop::as_filter_config(None).unwrap()],
+            }
+        );
+    }
+
+    #[test]
+    fn proto_rejects_invalid_ports() {
+        let proto = proto::Tenants {
+            metadata_key: None,
+            tenants: vec![proto::tenants::Tenant {
+                name: "title-a".into(),
+                ports: vec![70000],
+                token_prefixes: Vec::new(),
+                filters: Vec::new(),
+                max_packets_per_second: None,
+                max_clients: None,
+            }],
+            fallthrough: Vec::new(),
+        };
+
+        assert!(Config::try_from(proto).is_err());
+    }
+}
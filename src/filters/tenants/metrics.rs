@@ -0,0 +1,58 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use once_cell::sync::Lazy;
+use prometheus::{IntCounter, IntCounterVec};
+
+use crate::metrics::{registry, Direction};
+
+const TENANT_LABEL: &str = "tenant";
+
+pub(super) fn packets_total(tenant: &str, direction: Direction) -> IntCounter {
+    static PACKETS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "tenant_packets_total",
+                "Total number of packets handled for each tenant",
+            },
+            &[TENANT_LABEL, Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS.with_label_values(&[tenant, direction.label()])
+}
+
+pub(super) fn packets_dropped_total(
+    tenant: &str,
+    direction: Direction,
+    reason: &str,
+) -> IntCounter {
+    static PACKETS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "tenant_packets_dropped_total",
+                "Total number of packets dropped for each tenant",
+            },
+            &[TENANT_LABEL, Direction::LABEL, "reason"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS_DROPPED.with_label_values(&[tenant, direction.label(), reason])
+}
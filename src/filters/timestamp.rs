@@ -47,11 +47,11 @@ impl Timestamp {
         };
 
         let Ok(datetime) = time::OffsetDateTime::from_unix_timestamp(value) else {
-            tracing::warn!(
+            crate::log_throttle::throttled!(warn!(
                 timestamp = value,
                 metadata_key = %self.config.metadata_key,
                 "invalid unix timestamp"
-            );
+            ));
             return;
         };
 
@@ -96,6 +96,13 @@ impl Filter for Timestamp {
         self.observe(&ctx.metadata, Direction::Write);
         Ok(())
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            consumes: vec![self.config.metadata_key],
+            ..<_>::default()
+        }
+    }
 }
 
 impl StaticFilter for Timestamp {
@@ -0,0 +1,287 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collections::ttl::TtlMap,
+    config::Base64Standard,
+    filters::{capture::CAPTURED_BYTES, prelude::*},
+    metrics::Direction,
+    net::endpoint::{metadata, EndpointAddress},
+};
+
+use crate::generated::quilkin::filters::token_push::v1alpha1 as proto;
+
+/// How often clients whose token hasn't been seen for the timeout are
+/// forgotten.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The error of pushed packets with a truncated frame.
+const MALFORMED: FilterError = FilterError::Discarded(DropReason::Malformed);
+
+/// The error of pushed packets whose token no client has sent recently.
+const UNKNOWN_TOKEN: FilterError = FilterError::Discarded(DropReason::NoSession);
+
+/// Sends packets that upstreams push to a client by its token, rather than
+/// as a reply to one of its sessions, such as a match invite.
+///
+/// On the read path the filter remembers which client sent each token, as
+/// captured by an earlier filter. On the write path, packets starting with
+/// the configured prefix are framed with a token, and are sent to the client
+/// that last sent it, with the frame removed.
+pub struct TokenPush {
+    config: Config,
+    /// The client that last sent each token.
+    clients: TtlMap<Bytes, EndpointAddress>,
+    pushed_total: IntCounter,
+}
+
+impl TokenPush {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let invalid = |field: &str, reason: &str| CreationError::FieldInvalid {
+            field: field.into(),
+            reason: reason.into(),
+        };
+
+        if config.prefix.is_empty() {
+            return Err(invalid(
+                "prefix",
+                "must not be empty, packets from upstreams would all be pushed",
+            ));
+        }
+        if config.timeout_secs < 1 {
+            return Err(invalid("timeout_secs", "must be at least 1 second"));
+        }
+
+        Ok(Self {
+            clients: TtlMap::new(Duration::from_secs(config.timeout_secs), POLL_INTERVAL),
+            pushed_total: super::metrics::counter(
+                Self::NAME,
+                "pushed_total",
+                "Total number of packets pushed by upstreams to a client by its token",
+                Direction::Write,
+            ),
+            config,
+        })
+    }
+
+    /// Returns the length of the frame at the start of `contents` and the
+    /// token in it, if `contents` starts with the prefix.
+    fn frame<'contents>(
+        &self,
+        contents: &'contents [u8],
+    ) -> Option<Result<(usize, &'contents [u8]), FilterError>> {
+        let rest = contents.strip_prefix(&self.config.prefix[..])?;
+        let Some((&len, rest)) = rest.split_first() else {
+            return Some(Err(MALFORMED));
+        };
+
+        let len = usize::from(len);
+        Some(
+            rest.get(..len)
+                .map(|token| (self.config.prefix.len() + 1 + len, token))
+                .ok_or(MALFORMED),
+        )
+    }
+}
+
+impl Filter for TokenPush {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let key = metadata::TypedKey::<Bytes>::from(self.config.metadata_key);
+        if let Some(token) = ctx.metadata.get_typed(&key) {
+            // Reading the entry keeps it alive, so it's only written when the
+            // client changed.
+            let known = self
+                .clients
+                .get(token)
+                .is_some_and(|client| **client == ctx.source);
+            if !known {
+                self.clients.insert(token.clone(), ctx.source.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let Some(frame) = self.frame(&ctx.contents) else {
+            return Ok(());
+        };
+        let (len, token) = frame?;
+
+        let client = self
+            .clients
+            .get(&Bytes::copy_from_slice(token))
+            .map(|client| (**client).clone())
+            .ok_or(UNKNOWN_TOKEN)?;
+
+        ctx.contents.split_prefix(len);
+        ctx.dest = client;
+        self.pushed_total.inc();
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            consumes: vec![self.config.metadata_key],
+            ..<_>::default()
+        }
+    }
+}
+
+impl StaticFilter for TokenPush {
+    const NAME: &'static str = "quilkin.filters.token_push.v1alpha1.TokenPush";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::TokenPush;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(config.ok_or(CreationError::MissingConfig(Self::NAME))?)
+    }
+}
+
+/// `token_push` filter's configuration.
+///
+/// Pushed packets start with `prefix`, then the length of the token in one
+/// byte, then the token, followed by the packet to send to the client.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+pub struct Config {
+    /// The key of the client's token in the dynamic metadata of its packets.
+    #[serde(rename = "metadataKey", default = "default_metadata_key")]
+    pub metadata_key: metadata::Key,
+    /// The bytes marking packets from upstreams as pushed, base64 encoded.
+    #[serde(
+        deserialize_with = "Base64Standard::deserialize",
+        serialize_with = "Base64Standard::serialize"
+    )]
+    pub prefix: Vec<u8>,
+    /// How long in seconds a client's token is remembered after its last
+    /// packet.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Default value for [`Config::metadata_key`]
+fn default_metadata_key() -> metadata::Key {
+    metadata::Key::from_static(CAPTURED_BYTES)
+}
+
+/// Default value for [`Config::timeout_secs`]
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+impl From<Config> for proto::TokenPush {
+    fn from(config: Config) -> Self {
+        Self {
+            metadata_key: Some(config.metadata_key.to_string()),
+            prefix: config.prefix,
+            timeout_secs: Some(config.timeout_secs),
+        }
+    }
+}
+
+impl TryFrom<proto::TokenPush> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::TokenPush) -> Result<Self, Self::Error> {
+        Ok(Self {
+            metadata_key: p
+                .metadata_key
+                .map(metadata::Key::new)
+                .unwrap_or_else(default_metadata_key),
+            prefix: p.prefix,
+            timeout_secs: p.timeout_secs.unwrap_or_else(default_timeout_secs),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_buffer;
+
+    fn address(port: u16) -> EndpointAddress {
+        (std::net::Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    fn write(
+        filter: &TokenPush,
+        contents: &[u8],
+    ) -> Result<(EndpointAddress, Vec<u8>), FilterError> {
+        let mut ctx = WriteContext::new(address(7000), address(9000), alloc_buffer(contents));
+        filter
+            .write(&mut ctx)
+            .map(|()| (ctx.dest, ctx.contents.to_vec()))
+    }
+
+    // Creating the filter spawns a task to forget old tokens.
+    #[tokio::test]
+    async fn round_trip() {
+        crate::test::assert_config_round_trip::<TokenPush>(
+            "{ prefix: UFVTSA==, timeout_secs: 30 }",
+        );
+    }
+
+    #[tokio::test]
+    async fn pushes_by_token() {
+        let filter = TokenPush::from_config(Some(Config {
+            metadata_key: default_metadata_key(),
+            prefix: b"PUSH".to_vec(),
+            timeout_secs: 60,
+        }));
+
+        let mut dest = Vec::new();
+        let mut ctx = ReadContext::new(
+            <_>::default(),
+            address(9001),
+            alloc_buffer(b"hi"),
+            &mut dest,
+        );
+        ctx.metadata.insert(
+            CAPTURED_BYTES.into(),
+            metadata::Value::Bytes(Bytes::from_static(b"abc")),
+        );
+        filter.read(&mut ctx).unwrap();
+
+        // Replies to the session are left alone.
+        assert_eq!(
+            write(&filter, b"reply").unwrap(),
+            (address(9000), b"reply".to_vec())
+        );
+        assert_eq!(
+            write(&filter, b"PUSH\x03abcinvite").unwrap(),
+            (address(9001), b"invite".to_vec())
+        );
+        assert_eq!(write(&filter, b"PUSH\x03xyzinvite"), Err(UNKNOWN_TOKEN));
+        assert_eq!(write(&filter, b"PUSH\x09abc"), Err(MALFORMED));
+        assert_eq!(write(&filter, b"PUSH"), Err(MALFORMED));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        for config in ["prefix: ''", "{ prefix: UFVTSA==, timeout_secs: 0 }"] {
+            let config: Config = serde_yaml::from_str(config).unwrap();
+            assert!(TokenPush::try_from_config(Some(config)).is_err());
+        }
+    }
+}
@@ -20,7 +20,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     filters::{capture::CAPTURED_BYTES, prelude::*},
-    net::endpoint::metadata,
+    net::{endpoint::metadata, established},
 };
 
 use quilkin_xds::generated::quilkin::filters::token_router::v1alpha1 as proto;
@@ -36,24 +36,22 @@ impl TokenRouter {
     /// Non-async version of [`Filter::read`], as this filter does no actual async
     /// operations. Used in benchmarking.
     pub fn sync_read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
-        match ctx.metadata.get(&self.config.metadata_key) {
-            Some(metadata::Value::Bytes(token)) => {
-                let tok = crate::net::cluster::Token::new(token);
-
-                ctx.endpoints.addresses_for_token(tok, ctx.destinations);
-
-                if ctx.destinations.is_empty() {
-                    Err(FilterError::TokenRouter(RouterError::NoEndpointMatch {
-                        token: token.clone(),
-                    }))
-                } else {
-                    Ok(())
-                }
-            }
-            Some(_value) => unreachable!(
-                "this means the capture filter has regressed, it only ever captures byte slices"
-            ),
-            None => Err(FilterError::TokenRouter(RouterError::NoTokenFound)),
+        let key = metadata::TypedKey::<bytes::Bytes>::from(self.config.metadata_key);
+        let Some(token) = ctx.metadata.get_typed(&key) else {
+            return Err(FilterError::TokenRouter(RouterError::NoTokenFound));
+        };
+
+        ctx.endpoints
+            .addresses_for_token_or_wildcard(token, ctx.destinations);
+
+        if ctx.destinations.is_empty() {
+            Err(FilterError::TokenRouter(RouterError::NoEndpointMatch {
+                token: token.clone(),
+            }))
+        } else {
+            // A matching token authenticates the client.
+            established::set(&mut ctx.metadata, true);
+            Ok(())
         }
     }
 }
@@ -74,6 +72,15 @@ impl Filter for TokenRouter {
     fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
         self.sync_read(ctx)
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            consumes: vec![self.config.metadata_key],
+            produces: vec![metadata::Key::from_static(established::METADATA_KEY)],
+            sets_destinations: true,
+            ..<_>::default()
+        }
+    }
 }
 
 pub struct HashedTokenRouter(TokenRouter);
@@ -94,6 +101,10 @@ impl Filter for HashedTokenRouter {
     fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
         self.0.sync_read(ctx)
     }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
 }
 
 pub enum RouterError {
@@ -290,6 +301,13 @@ mod tests {
         assert_read(&filter, ctx);
         dest.clear();
 
+        let mut ctx = new_ctx(&mut dest);
+        ctx.metadata
+            .insert(CAPTURED_BYTES.into(), Value::Bytes(b"123".to_vec().into()));
+        filter.read(&mut ctx).unwrap();
+        assert_eq!(established::get(&ctx.metadata), Some(true));
+        dest.clear();
+
         // invalid key
         let mut ctx = new_ctx(&mut dest);
         ctx.metadata
@@ -317,12 +335,14 @@ mod tests {
             "127.0.0.1:80".parse().unwrap(),
             Metadata {
                 tokens: vec!["123".into()].into_iter().collect(),
+                ..<_>::default()
             },
         );
         let endpoint2 = Endpoint::with_metadata(
             "127.0.0.1:90".parse().unwrap(),
             Metadata {
                 tokens: vec!["456".into()].into_iter().collect(),
+                ..<_>::default()
             },
         );
 
@@ -0,0 +1,293 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{filters::prelude::*, net::endpoint::Locality};
+
+use crate::generated::quilkin::filters::traffic_split::v1alpha1 as proto;
+
+/// Splits clients between clusters by weight, such as 95% to a `blue` fleet
+/// and 5% to a `green` one, so traffic can be moved between fleets
+/// gradually.
+///
+/// Each client is always sent to the same cluster while the weights and
+/// clusters stay the same.
+pub struct TrafficSplit {
+    splits: Vec<Split>,
+}
+
+impl TrafficSplit {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        if config.splits.iter().all(|split| split.weight == 0) {
+            return Err(CreationError::FieldInvalid {
+                field: "splits".into(),
+                reason: "at least one split must have a weight".into(),
+            });
+        }
+
+        Ok(Self {
+            splits: config.splits,
+        })
+    }
+
+    /// Returns the cluster `ctx`'s client is sent to, out of those that
+    /// currently have endpoints.
+    fn choose<'split>(&'split self, ctx: &ReadContext<'_>) -> Option<&'split Split> {
+        let available = || {
+            self.splits.iter().filter(|split| {
+                split.weight > 0
+                    && ctx
+                        .endpoints
+                        .get(&Some(split.cluster.clone()))
+                        .is_some_and(|cluster| !cluster.endpoints.is_empty())
+            })
+        };
+
+        let total: u64 = available().map(|split| u64::from(split.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        ctx.source.hash(&mut hasher);
+        let mut bucket = hasher.finish() % total;
+
+        available().find(|split| {
+            let weight = u64::from(split.weight);
+            if bucket < weight {
+                return true;
+            }
+
+            bucket -= weight;
+            false
+        })
+    }
+}
+
+impl Filter for TrafficSplit {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    fn read(&self, ctx: &mut ReadContext<'_>) -> Result<(), FilterError> {
+        let Some(split) = self.choose(ctx) else {
+            return Err(FilterError::Custom("filter::traffic_split::no cluster"));
+        };
+        let Some(cluster) = ctx.endpoints.get(&Some(split.cluster.clone())) else {
+            return Err(FilterError::Custom("filter::traffic_split::no cluster"));
+        };
+
+        // Narrow down endpoints chosen by previous filters, otherwise send to
+        // the whole cluster.
+        if ctx.destinations.is_empty() {
+            ctx.destinations.extend(
+                cluster
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.address.clone()),
+            );
+        } else {
+            ctx.destinations.retain(|address| {
+                cluster
+                    .endpoints
+                    .iter()
+                    .any(|endpoint| endpoint.address == *address)
+            });
+            if ctx.destinations.is_empty() {
+                ctx.drop_reason = Some(DropReason::NoEndpoints);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sets_destinations: true,
+            ..<_>::default()
+        }
+    }
+}
+
+impl StaticFilter for TrafficSplit {
+    const NAME: &'static str = "quilkin.filters.traffic_split.v1alpha1.TrafficSplit";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::TrafficSplit;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// The share of clients sent to a cluster.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Split {
+    /// The locality of the cluster, such as `blue`.
+    pub cluster: Locality,
+    /// The cluster's weight, relative to the weights of the other clusters.
+    pub weight: u32,
+}
+
+/// `traffic_split` filter's configuration.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The clusters clients are split between. Clusters without endpoints
+    /// are skipped, and their share of clients split between the others.
+    pub splits: Vec<Split>,
+}
+
+impl From<Config> for proto::TrafficSplit {
+    fn from(config: Config) -> Self {
+        Self {
+            splits: config
+                .splits
+                .into_iter()
+                .map(|split| proto::traffic_split::Split {
+                    cluster: split.cluster.to_string(),
+                    weight: split.weight,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::TrafficSplit> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::TrafficSplit) -> Result<Self, Self::Error> {
+        let splits = p
+            .splits
+            .into_iter()
+            .map(|split| {
+                let cluster = split.cluster.parse().map_err(|error| {
+                    ConvertProtoConfigError::new(
+                        format!("invalid cluster: {error}"),
+                        Some("splits.cluster".into()),
+                    )
+                })?;
+
+                Ok(Split {
+                    cluster,
+                    weight: split.weight,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { splits })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::Arc};
+
+    use super::*;
+    use crate::{
+        net::{
+            endpoint::{Endpoint, EndpointAddress},
+            ClusterMap,
+        },
+        test::alloc_buffer,
+    };
+
+    fn clusters() -> Arc<ClusterMap> {
+        let clusters = ClusterMap::default();
+        for (cluster, port) in [("blue", 7001), ("green", 7002)] {
+            clusters.insert(
+                Some(cluster.parse().unwrap()),
+                BTreeSet::from([Endpoint::new((std::net::Ipv4Addr::LOCALHOST, port).into())]),
+            );
+        }
+        Arc::new(clusters)
+    }
+
+    fn filter(blue: u32, green: u32) -> TrafficSplit {
+        TrafficSplit::from_config(Some(
+            serde_yaml::from_str(&format!(
+                "
+splits:
+  - cluster: blue
+    weight: {blue}
+  - cluster: green
+    weight: {green}
+"
+            ))
+            .unwrap(),
+        ))
+    }
+
+    fn read(filter: &TrafficSplit, clusters: &Arc<ClusterMap>, port: u16) -> Vec<u16> {
+        let mut destinations = Vec::new();
+        let mut ctx = ReadContext::new(
+            clusters.clone(),
+            (std::net::Ipv4Addr::new(10, 0, 0, 1), port).into(),
+            alloc_buffer(b"hello"),
+            &mut destinations,
+        );
+        filter.read(&mut ctx).unwrap();
+        destinations.iter().map(EndpointAddress::port).collect()
+    }
+
+    #[test]
+    fn splits_by_weight() {
+        let clusters = clusters();
+        let filter = filter(90, 10);
+
+        let mut green = 0;
+        for port in 1000..3000 {
+            let destinations = read(&filter, &clusters, port);
+            assert_eq!(destinations.len(), 1);
+            // Clients stay on the same cluster.
+            assert_eq!(destinations, read(&filter, &clusters, port));
+            green += usize::from(destinations[0] == 7002);
+        }
+
+        assert!((100..300).contains(&green), "{green} clients sent to green");
+    }
+
+    #[test]
+    fn skips_clusters_without_endpoints() {
+        let clusters = clusters();
+        let filter = filter(0, 1);
+        assert_eq!(read(&filter, &clusters, 1000), [7002]);
+
+        clusters.remove_locality(&Some("green".parse().unwrap()));
+        let mut destinations = Vec::new();
+        let mut ctx = ReadContext::new(
+            clusters.clone(),
+            (std::net::Ipv4Addr::new(10, 0, 0, 1), 1000).into(),
+            alloc_buffer(b"hello"),
+            &mut destinations,
+        );
+        assert!(filter.read(&mut ctx).is_err());
+    }
+
+    #[test]
+    fn requires_a_weight() {
+        assert!(TrafficSplit::try_from_config(Some(Config {
+            splits: vec![Split {
+                cluster: "blue".parse().unwrap(),
+                weight: 0,
+            }],
+        }))
+        .is_err());
+    }
+}
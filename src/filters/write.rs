@@ -27,7 +27,8 @@ use crate::filters::Filter;
 pub struct WriteContext {
     /// The source of the received packet.
     pub source: EndpointAddress,
-    /// The destination of the received packet.
+    /// The destination of the received packet, filters can change it to send
+    /// the packet to another client.
     pub dest: EndpointAddress,
     /// Contents of the received packet.
     pub contents: PoolBuffer,
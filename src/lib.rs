@@ -18,6 +18,7 @@
 
 pub mod alloc;
 pub(crate) mod collections;
+pub(crate) mod log_throttle;
 pub(crate) mod metrics;
 pub mod pool;
 pub mod time;
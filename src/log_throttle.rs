@@ -0,0 +1,134 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Limits how often messages are logged on the packet path, where a message
+//! logged for every packet could be logged millions of times a second under
+//! attack.
+//!
+//! Each call site of [`throttled!`] gets its own [`LogThrottle`], letting
+//! through a burst of messages every period. Messages past the burst are
+//! suppressed, and counted in the `suppressed` field of the next message
+//! logged from the same call site.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::time::UtcTimestamp;
+
+/// The most messages logged from a call site of [`throttled!`] each
+/// [`DEFAULT_PERIOD`].
+pub(crate) const DEFAULT_BURST: u64 = 5;
+pub(crate) const DEFAULT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Lets through up to `burst` messages every `period`, counting the rest.
+#[derive(Debug)]
+pub(crate) struct LogThrottle {
+    burst: u64,
+    period: u64,
+    /// When the current period started, in nanoseconds since the epoch.
+    period_start: AtomicU64,
+    /// The messages let through in the current period.
+    logged: AtomicU64,
+    /// The messages suppressed since the last one let through.
+    suppressed: AtomicU64,
+}
+
+impl LogThrottle {
+    pub(crate) const fn new(burst: u64, period: Duration) -> Self {
+        Self {
+            burst,
+            period: period.as_nanos() as u64,
+            period_start: AtomicU64::new(0),
+            logged: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns whether a message can be logged now, with how many messages
+    /// were suppressed since the last one was, or `None` if it's suppressed.
+    #[inline]
+    pub(crate) fn check(&self) -> Option<u64> {
+        self.check_at(UtcTimestamp::now().unix_nanos() as u64)
+    }
+
+    fn check_at(&self, now: u64) -> Option<u64> {
+        let start = self.period_start.load(Ordering::Relaxed);
+        if now.saturating_sub(start) >= self.period
+            && self
+                .period_start
+                .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.logged.store(0, Ordering::Relaxed);
+        }
+
+        if self.logged.fetch_add(1, Ordering::Relaxed) < self.burst {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// The messages suppressed since the last one was let through.
+    #[inline]
+    pub(crate) fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+}
+
+/// Logs with a `tracing` macro, such as `throttled!(warn!(%error, "..."))`,
+/// at most [`DEFAULT_BURST`] times every [`DEFAULT_PERIOD`] from this call
+/// site, adding a `suppressed` field with how many messages weren't logged
+/// since the last one was.
+macro_rules! throttled {
+    ($level:ident!($($args:tt)+)) => {{
+        static THROTTLE: $crate::log_throttle::LogThrottle = $crate::log_throttle::LogThrottle::new(
+            $crate::log_throttle::DEFAULT_BURST,
+            $crate::log_throttle::DEFAULT_PERIOD,
+        );
+        if let Some(suppressed) = THROTTLE.check() {
+            tracing::$level!(suppressed, $($args)+);
+        }
+    }};
+}
+
+pub(crate) use throttled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lets_through_bursts() {
+        let throttle = LogThrottle::new(2, Duration::from_secs(1));
+        let second = Duration::from_secs(1).as_nanos() as u64;
+        let start = 10 * second;
+
+        assert_eq!(throttle.check_at(start), Some(0));
+        assert_eq!(throttle.check_at(start + 1), Some(0));
+        assert_eq!(throttle.check_at(start + 2), None);
+        assert_eq!(throttle.check_at(start + 3), None);
+        assert_eq!(throttle.suppressed(), 2);
+
+        // The next period reports what was suppressed in the last one.
+        assert_eq!(throttle.check_at(start + second), Some(2));
+        assert_eq!(throttle.check_at(start + second + 1), Some(0));
+        assert_eq!(throttle.check_at(start + second + 2), None);
+    }
+}
@@ -14,11 +14,13 @@
  * limitations under the License.
  */
 
+use std::collections::HashMap;
+
 use crate::net::maxmind_db::MetricsIpNetEntry;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use prometheus::{
-    core::Collector, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
-    IntGaugeVec, Opts, Registry, DEFAULT_BUCKETS,
+    core::Collector, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, DEFAULT_BUCKETS,
 };
 
 pub use prometheus::Result;
@@ -31,17 +33,60 @@ pub(crate) const READ: Direction = Direction::Read;
 pub(crate) const WRITE: Direction = Direction::Write;
 pub(crate) const ASN_LABEL: &str = "asn";
 pub(crate) const PREFIX_LABEL: &str = "ip_prefix";
+pub(crate) const WORKER_LABEL: &str = "worker";
 
 /// Label value for [DIRECTION_LABEL] for `read` events
 pub const READ_DIRECTION_LABEL: &str = "read";
 /// Label value for [DIRECTION_LABEL] for `write` events
 pub const WRITE_DIRECTION_LABEL: &str = "write";
 
+/// The namespace of every metric's name, unless configured otherwise.
+pub const DEFAULT_NAMESPACE: &str = "quilkin";
+
+/// The namespace and static labels of every metric, see [`configure`].
+static SETTINGS: OnceCell<(String, HashMap<String, String>)> = OnceCell::new();
+
+/// Sets the namespace every metric's name starts with, and the static labels
+/// added to every metric, such as the region or fleet of the proxy, so
+/// proxies scraped into the same Prometheus can be told apart. Must be called
+/// before any metric is registered, returning an error otherwise unless the
+/// settings are unchanged.
+pub fn configure(namespace: &str, labels: HashMap<String, String>) -> eyre::Result<()> {
+    if let Some(name) = std::iter::once(namespace)
+        .chain(labels.keys().map(String::as_str))
+        .find(|name| !is_valid_name(name))
+    {
+        eyre::bail!("`{name}` is not a valid metric namespace or label name");
+    }
+
+    let settings = (namespace.to_owned(), labels);
+    let current = SETTINGS.get_or_init(|| settings.clone());
+    eyre::ensure!(
+        *current == settings,
+        "metrics were registered before their namespace and labels were configured"
+    );
+    Ok(())
+}
+
+/// Whether `name` can be used as a Prometheus metric namespace or label name.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
 /// Returns the [prometheus::Registry] containing all the metrics
 /// registered in Quilkin.
 pub fn registry() -> &'static Registry {
-    static REGISTRY: Lazy<Registry> =
-        Lazy::new(|| Registry::new_custom(Some("quilkin".into()), None).unwrap());
+    static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+        let (namespace, labels) =
+            SETTINGS.get_or_init(|| (DEFAULT_NAMESPACE.into(), HashMap::new()));
+        let labels = (!labels.is_empty()).then(|| labels.clone());
+        Registry::new_custom(Some(namespace.clone()), labels).unwrap()
+    });
 
     &REGISTRY
 }
@@ -150,6 +195,48 @@ pub(crate) fn shutdown_initiated() -> &'static IntGauge {
     &SHUTDOWN_INITATED
 }
 
+/// Records that the `phase` of starting up took `elapsed`, so slow starts can
+/// be narrowed down from the logs or metrics.
+pub(crate) fn startup_phase(phase: &str, elapsed: std::time::Duration) {
+    static STARTUP_PHASE: Lazy<GaugeVec> = Lazy::new(|| {
+        prometheus::register_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "startup_phase_duration_seconds",
+                "How long each phase of starting up took",
+            },
+            &["phase"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    tracing::info!(
+        phase,
+        elapsed_ms = elapsed.as_millis() as u64,
+        "startup phase completed"
+    );
+    STARTUP_PHASE
+        .with_label_values(&[phase])
+        .set(elapsed.as_secs_f64());
+}
+
+/// The optional features Quilkin was built with, which largely determine the
+/// size of the binary.
+pub(crate) fn enabled_features() -> Vec<&'static str> {
+    [
+        ("filter-compress", cfg!(feature = "filter-compress")),
+        ("filter-plugins", cfg!(feature = "filter-plugins")),
+        ("pprof", cfg!(feature = "pprof")),
+        ("aws", cfg!(feature = "aws")),
+        ("mimalloc", cfg!(feature = "mimalloc")),
+        ("heap-stats", cfg!(feature = "heap-stats")),
+        ("instrument", cfg!(feature = "instrument")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
 pub(crate) fn game_traffic_tasks() -> &'static IntCounter {
     static GAME_TRAFFIC_TASKS: Lazy<IntCounter> = Lazy::new(|| {
         prometheus::register_int_counter_with_registry! {
@@ -243,6 +330,168 @@ pub(crate) fn processing_time(direction: Direction) -> Histogram {
     PROCESSING_TIME.with_label_values(&[direction.label()])
 }
 
+/// Calls `f` with the label of `worker`, without allocating.
+#[inline]
+fn with_worker_label<T>(worker: usize, f: impl FnOnce(&str) -> T) -> T {
+    let mut label = [0u8; 20];
+    let len = itoa(worker as u64, &mut label);
+    f(std::str::from_utf8(&label[..len as usize]).unwrap_or_default())
+}
+
+pub(crate) fn worker_processing_time(worker: usize) -> Histogram {
+    static WORKER_PROCESSING_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+        prometheus::register_histogram_vec_with_registry! {
+            prometheus::histogram_opts! {
+                "worker_packet_processing_duration_seconds",
+                "Processing time for a packet from a client, by the worker that handled it",
+                prometheus::exponential_buckets(BUCKET_START, BUCKET_FACTOR, BUCKET_COUNT).unwrap(),
+            },
+            &[WORKER_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    with_worker_label(worker, |worker| {
+        WORKER_PROCESSING_TIME.with_label_values(&[worker])
+    })
+}
+
+pub(crate) fn worker_queue_depth(worker: usize) -> IntGauge {
+    static WORKER_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+        prometheus::register_int_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "worker_queue_depth",
+                "Number of packets from clients queued by each worker",
+            },
+            &[WORKER_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    with_worker_label(worker, |worker| {
+        WORKER_QUEUE_DEPTH.with_label_values(&[worker])
+    })
+}
+
+pub(crate) fn worker_socket_backlog_bytes(worker: usize) -> IntGauge {
+    static WORKER_SOCKET_BACKLOG: Lazy<IntGaugeVec> = Lazy::new(|| {
+        prometheus::register_int_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "worker_socket_backlog_bytes",
+                "Number of bytes received by each worker's socket that haven't been read yet",
+            },
+            &[WORKER_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    with_worker_label(worker, |worker| {
+        WORKER_SOCKET_BACKLOG.with_label_values(&[worker])
+    })
+}
+
+pub(crate) fn worker_socket_drops(worker: usize) -> IntGauge {
+    static WORKER_SOCKET_DROPS: Lazy<IntGaugeVec> = Lazy::new(|| {
+        prometheus::register_int_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "worker_socket_drops",
+                "Number of packets dropped by each worker's socket since it was opened",
+            },
+            &[WORKER_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    with_worker_label(worker, |worker| {
+        WORKER_SOCKET_DROPS.with_label_values(&[worker])
+    })
+}
+
+pub(crate) fn runtime_workers() -> &'static IntGauge {
+    static RUNTIME_WORKERS: Lazy<IntGauge> = Lazy::new(|| {
+        prometheus::register_int_gauge_with_registry! {
+            prometheus::opts! {
+                "runtime_workers",
+                "Number of worker threads of the async runtime",
+            },
+            registry(),
+        }
+        .unwrap()
+    });
+
+    &RUNTIME_WORKERS
+}
+
+pub(crate) fn runtime_alive_tasks() -> &'static IntGauge {
+    static RUNTIME_ALIVE_TASKS: Lazy<IntGauge> = Lazy::new(|| {
+        prometheus::register_int_gauge_with_registry! {
+            prometheus::opts! {
+                "runtime_alive_tasks",
+                "Number of tasks alive in the async runtime",
+            },
+            registry(),
+        }
+        .unwrap()
+    });
+
+    &RUNTIME_ALIVE_TASKS
+}
+
+pub(crate) fn runtime_global_queue_depth() -> &'static IntGauge {
+    static RUNTIME_GLOBAL_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+        prometheus::register_int_gauge_with_registry! {
+            prometheus::opts! {
+                "runtime_global_queue_depth",
+                "Number of tasks waiting in the async runtime's global queue",
+            },
+            registry(),
+        }
+        .unwrap()
+    });
+
+    &RUNTIME_GLOBAL_QUEUE_DEPTH
+}
+
+pub(crate) fn runtime_worker_busy_seconds(worker: usize) -> Gauge {
+    static RUNTIME_WORKER_BUSY: Lazy<GaugeVec> = Lazy::new(|| {
+        prometheus::register_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "runtime_worker_busy_seconds",
+                "Total time each worker thread of the async runtime has been busy",
+            },
+            &[WORKER_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    with_worker_label(worker, |worker| {
+        RUNTIME_WORKER_BUSY.with_label_values(&[worker])
+    })
+}
+
+pub(crate) fn runtime_worker_parks(worker: usize) -> IntGauge {
+    static RUNTIME_WORKER_PARKS: Lazy<IntGaugeVec> = Lazy::new(|| {
+        prometheus::register_int_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "runtime_worker_parks",
+                "Number of times each worker thread of the async runtime has parked",
+            },
+            &[WORKER_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    with_worker_label(worker, |worker| {
+        RUNTIME_WORKER_PARKS.with_label_values(&[worker])
+    })
+}
+
 pub(crate) fn bytes_total(direction: Direction, asn: &AsnInfo) -> IntCounter {
     static BYTES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
         prometheus::register_int_counter_vec_with_registry! {
@@ -291,22 +540,37 @@ pub(crate) fn packet_jitter(direction: Direction, asn: &AsnInfo) -> IntGauge {
     PACKET_JITTER.with_label_values(&[direction.label(), asn.asn_str(), asn.prefix])
 }
 
-pub(crate) fn packets_total(direction: Direction, asn: &AsnInfo) -> IntCounter {
-    static PACKETS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
-        prometheus::register_int_counter_vec_with_registry! {
-            prometheus::opts! {
-                "packets_total",
-                "Total number of packets",
-            },
-            &[Direction::LABEL, ASN_LABEL, PREFIX_LABEL],
-            registry(),
-        }
-        .unwrap()
-    });
+static PACKETS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    prometheus::register_int_counter_vec_with_registry! {
+        prometheus::opts! {
+            "packets_total",
+            "Total number of packets",
+        },
+        &[Direction::LABEL, ASN_LABEL, PREFIX_LABEL],
+        registry(),
+    }
+    .unwrap()
+});
 
+pub(crate) fn packets_total(direction: Direction, asn: &AsnInfo) -> IntCounter {
     PACKETS_TOTAL.with_label_values(&[direction.label(), asn.asn_str(), asn.prefix])
 }
 
+/// Returns the total number of packets in `direction`, across all ASNs.
+pub(crate) fn packets_total_sum(direction: Direction) -> u64 {
+    PACKETS_TOTAL
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter(|metric| {
+            metric.get_label().iter().any(|label| {
+                label.get_name() == Direction::LABEL && label.get_value() == direction.label()
+            })
+        })
+        .map(|metric| metric.get_counter().get_value() as u64)
+        .sum()
+}
+
 pub(crate) fn packets_dropped_total(
     direction: Direction,
     source: &str,
@@ -327,6 +591,181 @@ pub(crate) fn packets_dropped_total(
     PACKETS_DROPPED.with_label_values(&[direction.label(), source, asn.asn_str(), asn.prefix])
 }
 
+pub(crate) fn packet_drops_total(direction: Direction, reason: &str) -> IntCounter {
+    static PACKET_DROPS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "packet_drops_total",
+                "Total number of packets dropped by filters or the proxy, by reason",
+            },
+            &[Direction::LABEL, "reason"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKET_DROPS.with_label_values(&[direction.label(), reason])
+}
+
+pub(crate) fn packets_shed_total(direction: Direction, reason: &str) -> IntCounter {
+    static PACKETS_SHED: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "packets_shed_total",
+                "Total number of packets shed because the proxy was overloaded",
+            },
+            &[Direction::LABEL, "reason"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS_SHED.with_label_values(&[direction.label(), reason])
+}
+
+pub(crate) fn filter_errors_total(direction: Direction, filter: &str, reason: &str) -> IntCounter {
+    static FILTER_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_errors_total",
+                "Total number of packets dropped by each filter, by reason",
+            },
+            &[Direction::LABEL, "filter", "reason"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    FILTER_ERRORS.with_label_values(&[direction.label(), filter, reason])
+}
+
+pub(crate) fn packets_coalesced_total(direction: Direction) -> IntCounter {
+    static PACKETS_COALESCED: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "packets_coalesced_total",
+                "Total number of packets merged into an earlier packet to the same destination",
+            },
+            &[Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS_COALESCED.with_label_values(&[direction.label()])
+}
+
+pub(crate) fn packets_duplicated_total(direction: Direction) -> IntCounter {
+    static PACKETS_DUPLICATED: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "packets_duplicated_total",
+                "Total number of duplicates sent of the first packets of a session",
+            },
+            &[Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS_DUPLICATED.with_label_values(&[direction.label()])
+}
+
+pub(crate) fn fair_queue_dropped_total(reason: &str, asn: &AsnInfo) -> IntCounter {
+    static FAIR_QUEUE_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "fair_queue_dropped_total",
+                "Total number of packets from clients dropped by the fair queue",
+            },
+            &["reason", ASN_LABEL, PREFIX_LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    FAIR_QUEUE_DROPPED.with_label_values(&[reason, asn.asn_str(), asn.prefix])
+}
+
+pub(crate) fn upstream_unreachable_total(reason: &str) -> IntCounter {
+    static UPSTREAM_UNREACHABLE: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "upstream_unreachable_total",
+                "Total number of ICMP errors reporting an upstream as unreachable",
+            },
+            &["reason"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    UPSTREAM_UNREACHABLE.with_label_values(&[reason])
+}
+
+pub(crate) fn upstream_ejections_total() -> &'static IntCounter {
+    static UPSTREAM_EJECTIONS: Lazy<IntCounter> = Lazy::new(|| {
+        prometheus::register_int_counter_with_registry! {
+            prometheus::opts! {
+                "upstream_ejections_total",
+                "Total number of times an upstream was ejected after being reported unreachable",
+            },
+            registry(),
+        }
+        .unwrap()
+    });
+
+    &UPSTREAM_EJECTIONS
+}
+
+pub(crate) fn admission_rejected_total(reason: &str) -> IntCounter {
+    static ADMISSION_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "admission_rejected_total",
+                "Total number of new sessions not admitted to an endpoint at capacity",
+            },
+            &["reason"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    ADMISSION_REJECTED.with_label_values(&[reason])
+}
+
+pub(crate) fn shared_sessions_total(operation: &str, result: &str) -> IntCounter {
+    static SHARED_SESSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "shared_sessions_total",
+                "Total number of lookups and writes of established clients in the shared store",
+            },
+            &["operation", "result"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    SHARED_SESSIONS.with_label_values(&[operation, result])
+}
+
+pub(crate) fn send_retries_total(direction: Direction) -> IntCounter {
+    static SEND_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "send_retries_total",
+                "Total number of packets sent again after failing to be sent",
+            },
+            &[Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    SEND_RETRIES.with_label_values(&[direction.label()])
+}
+
 /// Create a generic metrics options.
 /// Use [filter_opts] instead if the intended target is a filter.
 pub fn opts(name: &str, subsystem: &str, description: &str) -> Opts {
@@ -385,6 +824,16 @@ mod test {
         assert_eq!(asn_str, exp);
     }
 
+    #[test]
+    fn valid_names() {
+        assert!(super::is_valid_name("quilkin"));
+        assert!(super::is_valid_name("fleet_2"));
+        assert!(!super::is_valid_name("2fleet"));
+        assert!(!super::is_valid_name("eu-west"));
+        assert!(!super::is_valid_name("__reserved"));
+        assert!(!super::is_valid_name(""));
+    }
+
     #[test]
     fn itoa() {
         check(0, "0");
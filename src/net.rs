@@ -60,10 +60,27 @@ macro_rules! uring_span {
     }};
 }
 
+pub mod address_discovery;
+pub mod circuit_breaker;
 pub mod cluster;
+pub(crate) mod dns;
+pub mod dscp;
 pub mod endpoint;
+pub mod established;
+pub mod hot_restart;
+#[cfg(target_os = "linux")]
+pub(crate) mod icmp_errors;
+pub mod maintenance;
 pub(crate) mod maxmind_db;
 pub mod phoenix;
+pub mod reroute;
+pub mod selected_endpoint;
+pub mod session_quota;
+pub mod session_timeout;
+pub mod synthetic;
+pub mod tenant;
+pub mod unresponsive;
+pub mod upstream;
 
 pub use quilkin_xds as xds;
 pub use xds::net::TcpListener;
@@ -134,6 +151,54 @@ pub fn raw_socket_with_reuse_and_address(addr: SocketAddr) -> std::io::Result<So
     Ok(sock)
 }
 
+/// Creates a dual stack socket bound to the non-local address `addr`, such as
+/// the address of a client, so packets sent from it appear to come from that
+/// address, and packets routed back to that address are received on it.
+///
+/// Requires `CAP_NET_ADMIN` to set `IP_TRANSPARENT` on the socket.
+#[cfg(target_os = "linux")]
+pub fn raw_socket_transparent(addr: SocketAddr) -> io::Result<Socket> {
+    use std::os::fd::AsRawFd as _;
+
+    let sock = Socket::new(socket2::Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    // A client's socket may be recreated while its previous one is still
+    // closing.
+    sock.set_reuse_address(true)?;
+    sock.set_nonblocking(true)?;
+    sock.set_only_v6(false)?;
+
+    let enable: libc::c_int = 1;
+    // SAFETY: the socket is valid, and the option value is a c_int.
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TRANSPARENT,
+            (&enable as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    sock.bind(&SocketAddr::from((ip, addr.port())).into())?;
+
+    Ok(sock)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn raw_socket_transparent(_addr: SocketAddr) -> io::Result<Socket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "transparent proxying is only supported on Linux",
+    ))
+}
+
 #[inline]
 pub fn socket_port(socket: &socket2::Socket) -> u16 {
     match socket.local_addr().unwrap().as_socket().unwrap() {
@@ -215,6 +280,24 @@ impl DualStackLocalSocket {
                 let result = self.socket.send_to(&buf, target).await;
                 (result, buf)
             }
+
+            /// Sends `buf` to `target` marked with `dscp`, when set. Windows
+            /// doesn't let the traffic class be set per packet, so packets
+            /// are always sent unmarked there.
+            pub async fn send_to_marked<B: std::ops::Deref<Target = [u8]>>(&self, buf: B, target: SocketAddr, dscp: Option<dscp::Dscp>) -> (io::Result<usize>, B) {
+                let result = match dscp {
+                    #[cfg(unix)]
+                    Some(dscp) => {
+                        self.socket
+                            .async_io(tokio::io::Interest::WRITABLE, || {
+                                dscp::send_marked(&self.socket, &buf, target, dscp)
+                            })
+                            .await
+                    }
+                    _ => self.socket.send_to(&buf, target).await,
+                };
+                (result, buf)
+            }
         } else {
             #[inline]
             pub fn raw_fd(&self) -> io_uring::types::Fd {
@@ -0,0 +1,256 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A STUN-like service replying to each request with the address it was
+//! sent from, so clients behind a NAT can learn their public address from the
+//! proxies themselves.
+//!
+//! A request is the magic number, the version, and an 8 byte transaction ID
+//! chosen by the client. The reply is the request followed by the address
+//! family (`4` or `6`), the IP address, the port as 2 big endian bytes, and
+//! the proxy's region as a 4 letter ICAO code.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{config::IcaoCode, net::DualStackEpollSocket};
+
+const MAGIC_NUMBER: &[u8] = b"QLKA";
+const VERSION: u8 = 0;
+/// The length of a request.
+pub const REQUEST_LEN: usize = MAGIC_NUMBER.len() + 1 /* VERSION */ + 8 /* TRANSACTION */;
+/// The maximum length of a reply, to an IPv6 address.
+pub const MAX_REPLY_LEN: usize = REQUEST_LEN + 1 /* FAMILY */ + 16 + 2 /* PORT */ + 4 /* REGION */;
+
+/// The address discovery service of a proxy.
+#[derive(Debug)]
+pub struct AddressDiscovery {
+    pub socket: socket2::Socket,
+    /// The region included in every reply, `XXXX` when unknown.
+    pub region: IcaoCode,
+}
+
+/// A reply to a request, as parsed by a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub transaction: u64,
+    /// The address the request was observed coming from.
+    pub address: SocketAddr,
+    pub region: IcaoCode,
+}
+
+/// Encodes a request with the given transaction ID.
+pub fn request(transaction: u64) -> [u8; REQUEST_LEN] {
+    let mut packet = [0; REQUEST_LEN];
+    packet[..MAGIC_NUMBER.len()].copy_from_slice(MAGIC_NUMBER);
+    packet[MAGIC_NUMBER.len()] = VERSION;
+    packet[MAGIC_NUMBER.len() + 1..].copy_from_slice(&transaction.to_be_bytes());
+    packet
+}
+
+/// Encodes the reply to `request` into `buf`, returning its length, or
+/// `None` if `request` isn't a valid request.
+fn reply(
+    request: &[u8],
+    source: SocketAddr,
+    region: IcaoCode,
+    buf: &mut [u8; MAX_REPLY_LEN],
+) -> Option<usize> {
+    if request.len() != REQUEST_LEN
+        || &request[..MAGIC_NUMBER.len()] != MAGIC_NUMBER
+        || request[MAGIC_NUMBER.len()] != VERSION
+    {
+        return None;
+    }
+
+    buf[..REQUEST_LEN].copy_from_slice(request);
+    let mut offset = REQUEST_LEN;
+    let mut push = |bytes: &[u8]| {
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        offset += bytes.len();
+    };
+
+    // Clients on IPv4 are seen through the dual stack socket as IPv4-mapped
+    // IPv6 addresses.
+    match source.ip().to_canonical() {
+        IpAddr::V4(ip) => {
+            push(&[4]);
+            push(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            push(&[6]);
+            push(&ip.octets());
+        }
+    }
+    push(&source.port().to_be_bytes());
+    push(region.as_ref().as_bytes());
+    Some(offset)
+}
+
+/// Parses a reply, returning `None` if `packet` isn't a valid reply.
+pub fn parse_reply(packet: &[u8]) -> Option<Reply> {
+    if packet.len() < REQUEST_LEN
+        || &packet[..MAGIC_NUMBER.len()] != MAGIC_NUMBER
+        || packet[MAGIC_NUMBER.len()] != VERSION
+    {
+        return None;
+    }
+
+    let transaction = u64::from_be_bytes(
+        packet[MAGIC_NUMBER.len() + 1..REQUEST_LEN]
+            .try_into()
+            .ok()?,
+    );
+    let rest = &packet[REQUEST_LEN..];
+    let (ip, rest): (IpAddr, _) = match rest.first()? {
+        4 if rest.len() == 1 + 4 + 2 + 4 => {
+            let octets: [u8; 4] = rest[1..5].try_into().ok()?;
+            (Ipv4Addr::from(octets).into(), &rest[5..])
+        }
+        6 if rest.len() == 1 + 16 + 2 + 4 => {
+            let octets: [u8; 16] = rest[1..17].try_into().ok()?;
+            (Ipv6Addr::from(octets).into(), &rest[17..])
+        }
+        _ => return None,
+    };
+
+    let port = u16::from_be_bytes([rest[0], rest[1]]);
+    let region = std::str::from_utf8(&rest[2..]).ok()?.parse().ok()?;
+    Some(Reply {
+        transaction,
+        address: (ip, port).into(),
+        region,
+    })
+}
+
+impl AddressDiscovery {
+    pub fn spawn(self, mut shutdown_rx: crate::ShutdownRx) -> crate::Result<()> {
+        use tracing::{instrument::WithSubscriber as _, Instrument as _};
+
+        let socket = DualStackEpollSocket::new(crate::net::socket_port(&self.socket))?;
+        let region = self.region;
+
+        tokio::task::spawn(
+            async move {
+                let mut input_buf = [0u8; REQUEST_LEN + 1];
+                let mut output_buf = [0u8; MAX_REPLY_LEN];
+
+                loop {
+                    let result = tokio::select! {
+                        result = socket.recv_from(&mut input_buf) => result,
+                        _ = shutdown_rx.changed() => return,
+                    };
+
+                    let (size, source) = match result {
+                        Ok(received) => received,
+                        Err(error) => {
+                            tracing::warn!(%error, "error receiving packet");
+                            continue;
+                        }
+                    };
+
+                    let Some(len) = reply(&input_buf[..size], source, region, &mut output_buf)
+                    else {
+                        tracing::debug!("rejected invalid address discovery request");
+                        continue;
+                    };
+
+                    if let Err(error) = socket.send_to(&output_buf[..len], source).await {
+                        tracing::warn!(%error, "error responding to address discovery request");
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("address_discovery"))
+            .with_current_subscriber(),
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_round_trip() {
+        let region: IcaoCode = "EGLL".parse().unwrap();
+        let mut buf = [0; MAX_REPLY_LEN];
+
+        for source in [
+            SocketAddr::from((Ipv4Addr::new(203, 0, 113, 7), 40000)),
+            SocketAddr::from((Ipv4Addr::new(203, 0, 113, 7).to_ipv6_mapped(), 40000)),
+            SocketAddr::from(("2001:db8::7".parse::<Ipv6Addr>().unwrap(), 40000)),
+        ] {
+            let len = reply(&request(42), source, region, &mut buf).unwrap();
+            assert_eq!(
+                parse_reply(&buf[..len]),
+                Some(Reply {
+                    transaction: 42,
+                    address: SocketAddr::new(source.ip().to_canonical(), source.port()),
+                    region,
+                })
+            );
+        }
+
+        // The wrong magic number, or a truncated request.
+        let source = (Ipv4Addr::LOCALHOST, 1).into();
+        let mut invalid = request(42);
+        invalid[0] = b'X';
+        assert!(reply(&invalid, source, region, &mut buf).is_none());
+        assert!(reply(&request(42)[1..], source, region, &mut buf).is_none());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(target_os = "macos", ignore)]
+    async fn replies_with_observed_address() {
+        let socket = crate::net::raw_socket_with_reuse(0).unwrap();
+        let port = crate::net::socket_port(&socket);
+        let (_tx, rx) = crate::make_shutdown_channel(Default::default());
+        AddressDiscovery {
+            socket,
+            region: "EGLL".parse().unwrap(),
+        }
+        .spawn(rx)
+        .unwrap();
+
+        let client = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let mut buf = [0; MAX_REPLY_LEN];
+        let reply = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            // Retry until the service is ready.
+            loop {
+                client
+                    .send_to(&request(7), (Ipv4Addr::LOCALHOST, port))
+                    .await
+                    .unwrap();
+                let received = tokio::time::timeout(
+                    std::time::Duration::from_millis(100),
+                    client.recv(&mut buf),
+                );
+                if let Ok(Ok(len)) = received.await {
+                    break parse_reply(&buf[..len]).unwrap();
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(reply.transaction, 7);
+        assert_eq!(reply.address, client.local_addr().unwrap());
+        assert_eq!(reply.region.as_ref(), "EGLL");
+    }
+}
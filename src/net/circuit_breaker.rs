@@ -0,0 +1,501 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Protects the proxy from slow or failing external systems, such as a
+//! database or webhook a filter depends on.
+//!
+//! A [`CircuitBreaker`] limits how many calls to a dependency can be in
+//! flight at once, and stops calling it for a while once too many calls have
+//! failed, so the proxy fails fast rather than piling up work behind it.
+
+use std::{
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::{IntCounterVec, IntGaugeVec};
+
+use crate::metrics::registry;
+
+/// When a [`CircuitBreaker`] rejects calls.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// The most calls in flight at once, any more are rejected.
+    pub max_concurrent: usize,
+    /// The share of calls within `window` that have to fail for the breaker
+    /// to open.
+    pub failure_rate: f64,
+    /// The fewest calls within `window` before the breaker can open, so a
+    /// single failure doesn't open it.
+    pub min_calls: u32,
+    /// How long calls are counted for, after which counting starts over.
+    pub window: Duration,
+    /// How long the breaker stays open, after which a single call is let
+    /// through to check whether the dependency has recovered.
+    pub open_for: Duration,
+    /// How long a call made with [`CircuitBreaker::call`] can take before
+    /// it's abandoned and counted as failed, unlimited when `None`.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 64,
+            failure_rate: 0.5,
+            min_calls: 10,
+            window: Duration::from_secs(10),
+            open_for: Duration::from_secs(30),
+            timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+/// Why a call was rejected without being made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Rejected {
+    #[error("too many calls in flight")]
+    Concurrency,
+    #[error("circuit breaker is open")]
+    Open,
+}
+
+impl Rejected {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Concurrency => "rejected_concurrency",
+            Self::Open => "rejected_open",
+        }
+    }
+}
+
+/// The error of a call made through a [`CircuitBreaker`].
+#[derive(Debug, thiserror::Error)]
+pub enum CallError<E> {
+    #[error(transparent)]
+    Rejected(Rejected),
+    #[error(transparent)]
+    Failed(E),
+    #[error("call timed out after {0:?}")]
+    TimedOut(Duration),
+}
+
+impl<E: Into<eyre::Report>> CallError<E> {
+    /// Converts the error into a report, keeping a failed call's own error.
+    pub fn into_report(self) -> eyre::Report {
+        match self {
+            Self::Rejected(rejected) => rejected.into(),
+            Self::Failed(error) => error.into(),
+            Self::TimedOut(timeout) => eyre::eyre!("call timed out after {timeout:?}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Closed {
+        window_start: Instant,
+        calls: u32,
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single call is being let through to check the dependency.
+    HalfOpen,
+}
+
+impl State {
+    fn closed(now: Instant) -> Self {
+        Self::Closed {
+            window_start: now,
+            calls: 0,
+            failures: 0,
+        }
+    }
+
+    fn gauge(&self) -> i64 {
+        match self {
+            Self::Closed { .. } => 0,
+            Self::Open { .. } => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+/// Limits and trips calls to a single external dependency.
+pub struct CircuitBreaker {
+    dependency: &'static str,
+    config: Config,
+    state: Mutex<State>,
+    in_flight: AtomicUsize,
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker for calls to `dependency`, which labels its metrics.
+    pub fn new(dependency: &'static str, config: Config) -> Self {
+        Self {
+            dependency,
+            config,
+            state: Mutex::new(State::closed(Instant::now())),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns a permit to make a call, or why it's rejected. The call's
+    /// outcome is recorded with [`Permit::success`] or [`Permit::failure`].
+    pub fn try_acquire(&self) -> Result<Permit<'_>, Rejected> {
+        self.try_acquire_at(Instant::now())
+            .inspect_err(|rejected| self.count(rejected.label()))
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Result<Permit<'_>, Rejected> {
+        let mut probe = false;
+        {
+            let mut state = self.state.lock();
+            match *state {
+                State::Closed { .. } => {}
+                State::Open { until } if now >= until => {
+                    self.set_state(&mut state, State::HalfOpen);
+                    probe = true;
+                }
+                State::Open { .. } | State::HalfOpen => return Err(Rejected::Open),
+            }
+        }
+
+        if self.in_flight.fetch_add(1, Ordering::AcqRel) >= self.config.max_concurrent {
+            self.in_flight.fetch_sub(1, Ordering::AcqRel);
+            if probe {
+                self.set_state(&mut self.state.lock(), State::Open { until: now });
+            }
+            return Err(Rejected::Concurrency);
+        }
+
+        Ok(Permit {
+            breaker: self,
+            probe,
+            recorded: false,
+        })
+    }
+
+    /// Makes the call `call` returns, unless it's rejected, recording its
+    /// outcome. Calls taking longer than the configured timeout are
+    /// abandoned and count as failures.
+    pub async fn call<T, E, F>(&self, call: impl FnOnce() -> F) -> Result<T, CallError<E>>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let permit = self.try_acquire().map_err(CallError::Rejected)?;
+        let result = match self.config.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, call()).await {
+                Ok(result) => result,
+                Err(_) => {
+                    permit.timed_out();
+                    return Err(CallError::TimedOut(timeout));
+                }
+            },
+            None => call().await,
+        };
+
+        match result {
+            Ok(value) => {
+                permit.success();
+                Ok(value)
+            }
+            Err(error) => {
+                permit.failure();
+                Err(CallError::Failed(error))
+            }
+        }
+    }
+
+    /// Makes the call `call` returns, using `fallback` if it's rejected or
+    /// fails.
+    pub async fn call_or_else<T, E, F>(
+        &self,
+        call: impl FnOnce() -> F,
+        fallback: impl FnOnce(CallError<E>) -> T,
+    ) -> T
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        match self.call(call).await {
+            Ok(value) => value,
+            Err(error) => {
+                self.count("fallback");
+                fallback(error)
+            }
+        }
+    }
+
+    /// Returns whether the breaker is rejecting calls.
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.lock(), State::Closed { .. })
+    }
+
+    fn record(&self, success: bool, probe: bool, now: Instant) {
+        let mut state = self.state.lock();
+        if probe {
+            let next = if success {
+                State::closed(now)
+            } else {
+                State::Open {
+                    until: now + self.config.open_for,
+                }
+            };
+            self.set_state(&mut state, next);
+            return;
+        }
+
+        let State::Closed {
+            window_start,
+            calls,
+            failures,
+        } = &mut *state
+        else {
+            return;
+        };
+
+        if now.duration_since(*window_start) >= self.config.window {
+            *window_start = now;
+            *calls = 0;
+            *failures = 0;
+        }
+
+        *calls += 1;
+        *failures += u32::from(!success);
+
+        let rate = f64::from(*failures) / f64::from(*calls);
+        if *calls >= self.config.min_calls && rate >= self.config.failure_rate {
+            tracing::warn!(
+                dependency = self.dependency,
+                calls = *calls,
+                failures = *failures,
+                "opening circuit breaker"
+            );
+            self.set_state(
+                &mut state,
+                State::Open {
+                    until: now + self.config.open_for,
+                },
+            );
+        }
+    }
+
+    fn set_state(&self, state: &mut State, next: State) {
+        *state = next;
+        circuit_breaker_state(self.dependency).set(next.gauge());
+    }
+
+    fn count(&self, result: &str) {
+        circuit_breaker_calls_total(self.dependency, result).inc();
+    }
+}
+
+/// Permission to make a single call through a [`CircuitBreaker`].
+///
+/// Dropping the permit without recording an outcome, such as when the call is
+/// cancelled, doesn't count towards the failure rate.
+pub struct Permit<'breaker> {
+    breaker: &'breaker CircuitBreaker,
+    /// Whether the call is checking whether the dependency has recovered.
+    probe: bool,
+    recorded: bool,
+}
+
+impl Permit<'_> {
+    /// Records that the call succeeded.
+    pub fn success(self) {
+        self.finish(true, "success");
+    }
+
+    /// Records that the call failed.
+    pub fn failure(self) {
+        self.finish(false, "failure");
+    }
+
+    /// Records that the call took too long, which counts as a failure.
+    pub fn timed_out(self) {
+        self.finish(false, "timeout");
+    }
+
+    fn finish(mut self, success: bool, result: &str) {
+        self.recorded = true;
+        self.breaker.record(success, self.probe, Instant::now());
+        self.breaker.count(result);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.breaker.in_flight.fetch_sub(1, Ordering::AcqRel);
+        if self.probe && !self.recorded {
+            // Let another call check the dependency.
+            self.breaker.set_state(
+                &mut self.breaker.state.lock(),
+                State::Open {
+                    until: Instant::now(),
+                },
+            );
+        }
+    }
+}
+
+fn circuit_breaker_calls_total(dependency: &str, result: &str) -> prometheus::IntCounter {
+    static CALLS: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "circuit_breaker_calls_total",
+                "Total number of calls to external dependencies, by their result",
+            },
+            &["dependency", "result"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    CALLS.with_label_values(&[dependency, result])
+}
+
+fn circuit_breaker_state(dependency: &str) -> prometheus::IntGauge {
+    static STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+        prometheus::register_int_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "circuit_breaker_state",
+                "State of external dependencies' circuit breakers, 0 closed, 1 open, 2 half open",
+            },
+            &["dependency"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    STATE.with_label_values(&[dependency])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test",
+            Config {
+                max_concurrent: 2,
+                failure_rate: 0.5,
+                min_calls: 4,
+                window: Duration::from_secs(10),
+                open_for: Duration::from_secs(5),
+                timeout: Some(Duration::from_secs(1)),
+            },
+        )
+    }
+
+    #[test]
+    fn limits_concurrency() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        let first = breaker.try_acquire_at(now).unwrap();
+        let _second = breaker.try_acquire_at(now).unwrap();
+        assert_eq!(
+            breaker.try_acquire_at(now).err(),
+            Some(Rejected::Concurrency)
+        );
+
+        first.success();
+        assert!(breaker.try_acquire_at(now).is_ok());
+    }
+
+    #[test]
+    fn opens_and_recovers() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record(true, false, now);
+        for _ in 0..3 {
+            breaker.record(false, false, now);
+        }
+        assert_eq!(breaker.try_acquire_at(now).err(), Some(Rejected::Open));
+
+        // A single call is let through once open for long enough.
+        let later = now + Duration::from_secs(5);
+        let probe = breaker.try_acquire_at(later).unwrap();
+        assert_eq!(breaker.try_acquire_at(later).err(), Some(Rejected::Open));
+
+        probe.success();
+        assert!(!breaker.is_open());
+        assert!(breaker.try_acquire_at(later).is_ok());
+    }
+
+    #[test]
+    fn needs_enough_calls() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            breaker.record(false, false, now);
+        }
+        assert!(!breaker.is_open());
+
+        // Old failures are forgotten.
+        breaker.record(false, false, now + Duration::from_secs(10));
+        assert!(!breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn falls_back() {
+        let breaker = breaker();
+        let value = breaker
+            .call_or_else(
+                || async { Err::<u32, _>("unavailable") },
+                |error| {
+                    assert!(matches!(error, CallError::Failed("unavailable")));
+                    7
+                },
+            )
+            .await;
+
+        assert_eq!(value, 7);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out() {
+        let breaker = breaker();
+        let result = breaker
+            .call(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                Ok::<_, ()>(())
+            })
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(CallError::TimedOut(timeout)) if timeout == Duration::from_secs(1)
+        ));
+        assert_eq!(breaker.in_flight.load(Ordering::Acquire), 0);
+
+        // Timeouts count towards the failure rate like any other failure.
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.record(false, false, now);
+        }
+        assert!(breaker.is_open());
+    }
+}
@@ -63,6 +63,14 @@ pub(crate) fn active_endpoints() -> &'static prometheus::IntGauge {
 
 pub type TokenAddressMap = gxhash::HashMap<u64, gxhash::HashSet<EndpointAddress>>;
 
+/// The separator between the segments of a hierarchical token, such as
+/// `region/match-id/player`.
+pub const TOKEN_SEPARATOR: u8 = b'/';
+
+/// The last segment of a wildcard token, such as `region/match-id/*`, which
+/// matches every token starting with its other segments.
+pub const TOKEN_WILDCARD: u8 = b'*';
+
 #[derive(Copy, Clone)]
 pub struct Token(u64);
 
@@ -449,6 +457,12 @@ where
         endpoints
     }
 
+    /// Whether any locality has an endpoint with `address`.
+    pub fn contains_address(&self, address: &EndpointAddress) -> bool {
+        let needle = Endpoint::new(address.clone());
+        self.iter().any(|set| set.value().contains(&needle))
+    }
+
     pub fn nth_endpoint(&self, mut index: usize) -> Option<Endpoint> {
         for set in self.iter() {
             let set = &set.value().endpoints;
@@ -462,6 +476,38 @@ where
         None
     }
 
+    /// Returns the endpoint at `index` when each endpoint is repeated as many
+    /// times as its weight, wrapping `index` around the total weight, so
+    /// endpoints are chosen in proportion to their weights.
+    pub fn weighted_endpoint(&self, index: u64) -> Option<Endpoint> {
+        let mut total_weight = 0;
+        for set in self.iter() {
+            for endpoint in &set.value().endpoints {
+                total_weight += u64::from(endpoint.weight);
+            }
+        }
+
+        if total_weight == 0 {
+            let len = self.num_of_endpoints();
+            return (len != 0)
+                .then(|| self.nth_endpoint((index % len as u64) as usize))
+                .flatten();
+        }
+
+        let mut index = index % total_weight;
+        for set in self.iter() {
+            for endpoint in &set.value().endpoints {
+                let weight = u64::from(endpoint.weight);
+                if index < weight {
+                    return Some(endpoint.clone());
+                }
+                index -= weight;
+            }
+        }
+
+        None
+    }
+
     pub fn filter_endpoints(&self, f: impl Fn(&Endpoint) -> bool) -> Vec<Endpoint> {
         let mut endpoints = Vec::new();
 
@@ -510,6 +556,32 @@ where
             addrs.extend(ma.value().iter().cloned());
         }
     }
+
+    /// Appends the addresses of the endpoints with `token` to `addrs`, or if
+    /// there are none, those with the longest wildcard token matching it.
+    ///
+    /// For example the token `eu/match-1/player-2` matches endpoints with
+    /// that token, otherwise with `eu/match-1/*`, otherwise with `eu/*`.
+    pub fn addresses_for_token_or_wildcard(&self, token: &[u8], addrs: &mut Vec<EndpointAddress>) {
+        if let Some(ma) = self.token_map.get(&Token::new(token).0) {
+            addrs.extend(ma.value().iter().cloned());
+            return;
+        }
+
+        let mut wildcard = Vec::new();
+        let mut prefix = token;
+        while let Some(end) = prefix.iter().rposition(|b| *b == TOKEN_SEPARATOR) {
+            prefix = &prefix[..end];
+
+            wildcard.clear();
+            wildcard.extend_from_slice(prefix);
+            wildcard.extend_from_slice(&[TOKEN_SEPARATOR, TOKEN_WILDCARD]);
+            if let Some(ma) = self.token_map.get(&Token::new(&wildcard).0) {
+                addrs.extend(ma.value().iter().cloned());
+                return;
+            }
+        }
+    }
 }
 
 impl<S> crate::config::watch::Watchable for ClusterMap<S> {
@@ -705,6 +777,8 @@ impl From<&'_ Endpoint> for proto::Endpoint {
             port: endpoint.address.port.into(),
             metadata: Some((&endpoint.metadata).into()),
             host2: None,
+            weight: (endpoint.weight != crate::net::endpoint::DEFAULT_WEIGHT)
+                .then_some(endpoint.weight),
         }
     }
 }
@@ -753,4 +827,41 @@ mod tests {
         assert_eq!(cluster1.get(&Some(nl1.clone())).unwrap().len(), 1);
         assert!(cluster1.get(&Some(de1.clone())).unwrap().is_empty());
     }
+
+    #[test]
+    fn wildcard_tokens() {
+        let endpoint = |port: u16, token: &str| {
+            Endpoint::with_metadata(
+                (Ipv4Addr::LOCALHOST, port).into(),
+                crate::net::endpoint::Metadata {
+                    tokens: [token.as_bytes().to_vec()].into_iter().collect(),
+                    ..<_>::default()
+                },
+            )
+        };
+
+        let cluster = ClusterMap::new();
+        cluster.insert_default(
+            [
+                endpoint(1, "eu/match-1/player-1"),
+                endpoint(2, "eu/match-1/*"),
+                endpoint(3, "eu/*"),
+                endpoint(4, "player-2"),
+            ]
+            .into(),
+        );
+
+        let ports = |token: &str| {
+            let mut addrs = Vec::new();
+            cluster.addresses_for_token_or_wildcard(token.as_bytes(), &mut addrs);
+            addrs.iter().map(|addr| addr.port).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ports("eu/match-1/player-1"), [1]);
+        assert_eq!(ports("eu/match-1/player-2"), [2]);
+        assert_eq!(ports("eu/match-2/player-1"), [3]);
+        assert_eq!(ports("us/match-1/player-1"), Vec::<u16>::new());
+        assert_eq!(ports("player-2"), [4]);
+        assert_eq!(ports("eu"), Vec::<u16>::new());
+    }
 }
@@ -0,0 +1,352 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Resolution of endpoints configured with a hostname.
+//!
+//! Each endpoint whose address is a hostname is replaced in its locality by
+//! one endpoint for every A and AAAA record of the name, sharing its metadata,
+//! and resolved again when the records expire.
+//!
+//! Names of SRV records, such as `_game._udp.example.com`, are instead
+//! replaced by an endpoint for every address of each record's target, on the
+//! record's port, with the record's weight as the endpoint's weight, and its
+//! priority and weight in the endpoint's `srv` metadata.
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use hickory_resolver::{error::ResolveError, TokioAsyncResolver};
+use once_cell::sync::Lazy;
+use tokio::time::Instant;
+
+use crate::{
+    config::Config,
+    net::{
+        circuit_breaker::{self, CircuitBreaker},
+        endpoint::{AddressKind, Endpoint, Locality},
+    },
+};
+
+/// The shortest time resolved addresses are used for, regardless of their TTL.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before resolving a name again after it failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Stops resolving names for a while when the resolver keeps failing, keeping
+/// the addresses they last resolved to.
+static BREAKER: Lazy<CircuitBreaker> = Lazy::new(|| {
+    CircuitBreaker::new(
+        "dns",
+        circuit_breaker::Config {
+            max_concurrent: 16,
+            timeout: Some(Duration::from_secs(10)),
+            ..<_>::default()
+        },
+    )
+});
+
+/// Returns the resolver used for endpoint hostnames, configured from the
+/// system's settings, looking up both A and AAAA records.
+pub(crate) fn resolver() -> &'static TokioAsyncResolver {
+    static DNS: Lazy<TokioAsyncResolver> = Lazy::new(|| {
+        let (config, mut options) = hickory_resolver::system_conf::read_system_conf().unwrap();
+        options.ip_strategy = hickory_resolver::config::LookupIpStrategy::Ipv4AndIpv6;
+        TokioAsyncResolver::tokio(config, options)
+    });
+    &DNS
+}
+
+/// Whether `name` is the name of SRV records, such as
+/// `_game._udp.example.com`, rather than of addresses.
+pub(crate) fn is_srv_name(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+/// An endpoint configured with a hostname, and the endpoints it resolved to.
+struct Resolved {
+    locality: Option<Locality>,
+    endpoint: Endpoint,
+    addresses: BTreeSet<Endpoint>,
+    refresh_at: Instant,
+}
+
+/// Spawns a task that resolves the endpoints in `config` that have a hostname
+/// as their address, until `shutdown_rx` signals.
+pub(crate) fn spawn(
+    config: Arc<Config>,
+    mut shutdown_rx: crate::ShutdownRx,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut changes = config.clusters.watch();
+        let mut resolved = Vec::<Resolved>::new();
+
+        loop {
+            for (locality, endpoint) in named_endpoints(&config) {
+                // Names that failed to resolve stay in the map until retried.
+                if resolved.iter().any(|entry| {
+                    entry.addresses.is_empty()
+                        && entry.locality == locality
+                        && entry.endpoint == endpoint
+                }) {
+                    continue;
+                }
+
+                resolved.retain(|entry| entry.locality != locality || entry.endpoint != endpoint);
+
+                let mut entry = Resolved {
+                    locality,
+                    endpoint,
+                    addresses: BTreeSet::new(),
+                    refresh_at: Instant::now(),
+                };
+
+                if refresh(&config, &mut entry).await {
+                    resolved.push(entry);
+                }
+            }
+
+            let now = Instant::now();
+            let mut index = 0;
+            while index < resolved.len() {
+                if resolved[index].refresh_at > now || refresh(&config, &mut resolved[index]).await
+                {
+                    index += 1;
+                } else {
+                    resolved.swap_remove(index);
+                }
+            }
+
+            let next_refresh = resolved.iter().map(|entry| entry.refresh_at).min();
+            tokio::select! {
+                result = changes.changed() => if result.is_err() {
+                    return;
+                },
+                () = sleep_until(next_refresh) => {}
+                _ = shutdown_rx.changed() => return,
+            }
+        }
+    })
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Returns the endpoints in `config` that have a hostname as their address.
+fn named_endpoints(config: &Config) -> Vec<(Option<Locality>, Endpoint)> {
+    let clusters = config.clusters.read();
+    clusters
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .value()
+                .endpoints
+                .iter()
+                .filter(|endpoint| matches!(endpoint.address.host, AddressKind::Name(_)))
+                .map(|endpoint| (entry.key().clone(), endpoint.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Resolves the name of `entry` again, swapping the endpoints it previously
+/// resolved to for the new ones. Returns whether `entry` is still configured.
+async fn refresh(config: &Config, entry: &mut Resolved) -> bool {
+    let AddressKind::Name(name) = &entry.endpoint.address.host else {
+        return false;
+    };
+
+    let lookup = BREAKER
+        .call(|| async {
+            if is_srv_name(name) {
+                lookup_srv(&entry.endpoint, name).await
+            } else {
+                lookup_ip(&entry.endpoint, name).await
+            }
+        })
+        .await;
+
+    let addresses = match lookup {
+        Ok((addresses, valid_until)) => {
+            let ttl = valid_until.saturating_duration_since(std::time::Instant::now());
+            entry.refresh_at = Instant::now() + ttl.max(MIN_REFRESH_INTERVAL);
+            addresses
+        }
+        Err(error) => {
+            tracing::warn!(%name, %error, "failed to resolve endpoint");
+            entry.refresh_at = Instant::now() + RETRY_INTERVAL;
+            return is_configured(config, entry);
+        }
+    };
+
+    tracing::debug!(%name, addresses = addresses.len(), "resolved endpoint");
+    let configured = swap(config, entry, &addresses);
+    entry.addresses = addresses;
+    configured
+}
+
+/// Resolves the name of `endpoint` to an endpoint for every address, returning
+/// them with when they expire.
+async fn lookup_ip(
+    endpoint: &Endpoint,
+    name: &str,
+) -> Result<(BTreeSet<Endpoint>, std::time::Instant), ResolveError> {
+    let lookup = resolver().lookup_ip(name).await?;
+    let addresses = lookup
+        .iter()
+        .map(|ip| Endpoint {
+            address: (ip, endpoint.address.port).into(),
+            ..endpoint.clone()
+        })
+        .collect();
+
+    Ok((addresses, lookup.valid_until()))
+}
+
+/// Resolves the SRV records of `endpoint`'s name to an endpoint for every
+/// address of each record's target, returning them with when the first of the
+/// records or addresses expires.
+async fn lookup_srv(
+    endpoint: &Endpoint,
+    name: &str,
+) -> Result<(BTreeSet<Endpoint>, std::time::Instant), ResolveError> {
+    let lookup = resolver().srv_lookup(name).await?;
+    let mut valid_until = lookup.as_lookup().valid_until();
+    let mut addresses = BTreeSet::new();
+
+    for record in lookup.iter() {
+        // Targets that fail to resolve are left out, rather than losing the
+        // targets that did.
+        let ips = match resolver().lookup_ip(record.target().clone()).await {
+            Ok(ips) => ips,
+            Err(error) => {
+                let target = record.target();
+                tracing::warn!(%name, %target, %error, "failed to resolve srv target");
+                continue;
+            }
+        };
+        valid_until = valid_until.min(ips.valid_until());
+
+        let mut metadata = endpoint.metadata.clone();
+        metadata.unknown.insert(
+            "srv".into(),
+            serde_json::json!({
+                "priority": record.priority(),
+                "weight": record.weight(),
+            }),
+        );
+        addresses.extend(ips.iter().map(|ip| Endpoint {
+            address: (ip, record.port()).into(),
+            metadata: metadata.clone(),
+            weight: u32::from(record.weight()),
+        }));
+    }
+
+    Ok((addresses, valid_until))
+}
+
+/// Whether `entry`'s name, or the endpoints it resolved to, are still in its
+/// locality, rather than having been removed by a configuration change.
+fn is_configured(config: &Config, entry: &Resolved) -> bool {
+    config
+        .clusters
+        .read()
+        .get(&entry.locality)
+        .is_some_and(|set| {
+            set.contains(&entry.endpoint)
+                || entry.addresses.iter().any(|address| set.contains(address))
+        })
+}
+
+/// Replaces `entry`'s name, and the endpoints it previously resolved to, with
+/// `addresses` in a single update of its locality. Returns whether `entry`
+/// was still configured.
+fn swap(config: &Config, entry: &Resolved, addresses: &BTreeSet<Endpoint>) -> bool {
+    config.clusters.modify(|clusters| {
+        let Some(mut endpoints) = clusters
+            .get(&entry.locality)
+            .map(|set| set.endpoints.clone())
+        else {
+            return false;
+        };
+
+        let mut configured = endpoints.remove(&entry.endpoint);
+        for address in &entry.addresses {
+            configured |= endpoints.remove(address);
+        }
+
+        if !configured {
+            return false;
+        }
+
+        endpoints.extend(addresses.iter().cloned());
+        clusters.insert(entry.locality.clone(), endpoints);
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_resolved_addresses() {
+        let config = Config::default_non_agent();
+        let named = Endpoint::new("example.com:7777".parse().unwrap());
+        let other = Endpoint::new((std::net::Ipv4Addr::LOCALHOST, 8000).into());
+        config
+            .clusters
+            .modify(|clusters| clusters.insert_default([named.clone(), other.clone()].into()));
+
+        assert_eq!(
+            named_endpoints(&config),
+            [(None, named.clone())],
+            "only endpoints with a hostname are resolved"
+        );
+
+        let address =
+            |octet| Endpoint::new((std::net::Ipv4Addr::new(10, 0, 0, octet), 7777).into());
+        let mut entry = Resolved {
+            locality: None,
+            endpoint: named,
+            addresses: BTreeSet::new(),
+            refresh_at: Instant::now(),
+        };
+
+        let first = BTreeSet::from([address(1), address(2)]);
+        assert!(swap(&config, &entry, &first));
+        entry.addresses = first;
+
+        let second = BTreeSet::from([address(2), address(3)]);
+        assert!(swap(&config, &entry, &second));
+        entry.addresses = second;
+
+        assert_eq!(
+            config.clusters.read().get_default().unwrap().endpoints,
+            BTreeSet::from([other.clone(), address(2), address(3)])
+        );
+
+        // The configuration no longer has the name.
+        config
+            .clusters
+            .modify(|clusters| clusters.insert_default([other].into()));
+        assert!(!swap(&config, &entry, &BTreeSet::new()));
+        assert!(!is_configured(&config, &entry));
+    }
+}
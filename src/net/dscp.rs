@@ -0,0 +1,270 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Differentiated Services (DSCP) marking of forwarded packets.
+
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key filters can set to override the DSCP of a packet,
+/// as a number between `0` and `63`.
+pub const METADATA_KEY: &str = "quilkin.dev/dscp";
+
+static KEY: Lazy<TypedKey<u64>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY)
+        .register("the DSCP to mark the packet with when it is forwarded, overriding the default")
+});
+
+const NAMES: &[(&str, u8)] = &[
+    ("CS0", 0),
+    ("CS1", 8),
+    ("AF11", 10),
+    ("AF12", 12),
+    ("AF13", 14),
+    ("CS2", 16),
+    ("AF21", 18),
+    ("AF22", 20),
+    ("AF23", 22),
+    ("CS3", 24),
+    ("AF31", 26),
+    ("AF32", 28),
+    ("AF33", 30),
+    ("CS4", 32),
+    ("AF41", 34),
+    ("AF42", 36),
+    ("AF43", 38),
+    ("CS5", 40),
+    ("VA", 44),
+    ("EF", 46),
+    ("CS6", 48),
+    ("CS7", 56),
+];
+
+/// A Differentiated Services Code Point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Dscp(u8);
+
+impl Dscp {
+    /// Expedited Forwarding, for low latency traffic such as game state.
+    pub const EF: Self = Self(46);
+
+    /// Returns the code point for `value`, if it is within `0..=63`.
+    pub const fn new(value: u8) -> Option<Self> {
+        if value <= 0x3f {
+            Some(Self(value))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub const fn value(self) -> u8 {
+        self.0
+    }
+
+    /// The value of the IPv4 `TOS` or IPv6 `TCLASS` field for this code point.
+    #[inline]
+    pub const fn tos(self) -> u8 {
+        self.0 << 2
+    }
+
+    /// Returns the code point set in `metadata` by a filter, or `default`.
+    #[inline]
+    pub fn select(metadata: &DynamicMetadata, default: Option<Self>) -> Option<Self> {
+        match metadata.get_typed(&KEY) {
+            Some(value) => match u8::try_from(*value).ok().and_then(Self::new) {
+                Some(dscp) => Some(dscp),
+                None => {
+                    tracing::trace!(value, "ignoring invalid DSCP in metadata");
+                    default
+                }
+            },
+            None => default,
+        }
+    }
+
+    /// Sets the code point in `metadata`, overriding the default for the
+    /// packet.
+    #[inline]
+    pub fn insert(self, metadata: &mut DynamicMetadata) {
+        metadata.insert_typed(&KEY, self.0.into());
+    }
+}
+
+impl fmt::Display for Dscp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match NAMES.iter().find(|(_, value)| *value == self.0) {
+            Some((name, _)) => f.write_str(name),
+            None => self.0.fmt(f),
+        }
+    }
+}
+
+impl FromStr for Dscp {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, value)) = NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+            return Ok(Self(*value));
+        }
+
+        s.parse::<u8>()
+            .ok()
+            .and_then(Self::new)
+            .ok_or_else(|| eyre::eyre!("`{s}` is not a DSCP name or a number between 0 and 63"))
+    }
+}
+
+impl serde::Serialize for Dscp {
+    fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Dscp {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum NameOrValue {
+            Value(u8),
+            Name(String),
+        }
+
+        match NameOrValue::deserialize(de)? {
+            NameOrValue::Value(value) => Self::new(value)
+                .ok_or_else(|| serde::de::Error::custom("DSCP must be between 0 and 63")),
+            NameOrValue::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl schemars::JsonSchema for Dscp {
+    fn schema_name() -> String {
+        "Dscp".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        <String>::json_schema(gen)
+    }
+}
+
+/// The default DSCP marking of forwarded packets, which filters can override
+/// per packet by setting [`METADATA_KEY`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DscpConfig {
+    /// The marking of packets sent to upstream endpoints.
+    pub upstream: Option<Dscp>,
+    /// The marking of packets sent back to clients.
+    pub downstream: Option<Dscp>,
+}
+
+/// Returns the control message level and type used to set the traffic class
+/// of a packet sent to `destination` from a dual stack socket.
+#[cfg(unix)]
+#[inline]
+pub(crate) fn cmsg_type(destination: &socket2::SockAddr) -> (libc::c_int, libc::c_int) {
+    match destination.as_socket() {
+        Some(SocketAddr::V6(addr)) if addr.ip().to_ipv4_mapped().is_none() => {
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+        }
+        _ => (libc::IPPROTO_IP, libc::IP_TOS),
+    }
+}
+
+/// Sends `data` to `destination` from `socket` with its traffic class set
+/// to `dscp` by a control message, leaving the socket's own options alone.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn send_marked(
+    socket: &impl std::os::fd::AsRawFd,
+    data: &[u8],
+    destination: SocketAddr,
+    dscp: Dscp,
+) -> std::io::Result<usize> {
+    let destination = socket2::SockAddr::from(destination);
+    let (level, kind) = cmsg_type(&destination);
+    let len = std::mem::size_of::<libc::c_int>() as u32;
+    let mut control = [0u64; 4];
+    let mut io_vec = libc::iovec {
+        iov_base: data.as_ptr() as *mut u8 as *mut _,
+        iov_len: data.len(),
+    };
+
+    // SAFETY: msghdr is plain old data, for which all zeroes is valid
+    let mut msghdr: libc::msghdr = unsafe { std::mem::zeroed() };
+    msghdr.msg_name = destination.as_ptr() as *mut _;
+    msghdr.msg_namelen = destination.len();
+    msghdr.msg_iov = &mut io_vec;
+    msghdr.msg_iovlen = 1;
+    msghdr.msg_control = control.as_mut_ptr().cast();
+    // SAFETY: CMSG_SPACE is a pure size calculation
+    msghdr.msg_controllen = unsafe { libc::CMSG_SPACE(len) } as _;
+
+    // SAFETY: the control buffer is aligned and large enough to hold a single
+    // header with a c_int, so the first header is non-null and its data is
+    // within the buffer
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msghdr);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = kind;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+        libc::CMSG_DATA(cmsg)
+            .cast::<libc::c_int>()
+            .write_unaligned(dscp.tos().into());
+    }
+
+    // SAFETY: every pointer in the header is valid until sendmsg returns
+    match unsafe { libc::sendmsg(socket.as_raw_fd(), &msghdr, 0) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        sent => Ok(sent as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("EF".parse::<Dscp>().unwrap(), Dscp::EF);
+        assert_eq!("af41".parse::<Dscp>().unwrap(), Dscp(34));
+        assert_eq!("12".parse::<Dscp>().unwrap(), Dscp(12));
+        assert!("64".parse::<Dscp>().is_err());
+        assert!("XX".parse::<Dscp>().is_err());
+
+        assert_eq!(Dscp::EF.to_string(), "EF");
+        assert_eq!(Dscp(3).to_string(), "3");
+        assert_eq!(Dscp::EF.tos(), 0xb8);
+    }
+
+    #[test]
+    fn metadata_override() {
+        let mut metadata = DynamicMetadata::default();
+        assert_eq!(Dscp::select(&metadata, Some(Dscp::EF)), Some(Dscp::EF));
+
+        Dscp(8).insert(&mut metadata);
+        assert_eq!(Dscp::select(&metadata, Some(Dscp::EF)), Some(Dscp(8)));
+        assert_eq!(Dscp::select(&metadata, None), Some(Dscp(8)));
+
+        metadata.insert(
+            METADATA_KEY.into(),
+            crate::net::endpoint::metadata::Value::Number(99),
+        );
+        assert_eq!(Dscp::select(&metadata, Some(Dscp::EF)), Some(Dscp::EF));
+    }
+}
@@ -33,15 +33,32 @@ pub use quilkin_xds::locality::Locality;
 pub type EndpointMetadata = metadata::MetadataView<Metadata>;
 pub use base64_set::Set;
 
+/// The weight of endpoints that don't set one.
+pub const DEFAULT_WEIGHT: u32 = 1;
+
 /// A destination endpoint with any associated metadata.
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Eq, schemars::JsonSchema)]
 #[non_exhaustive]
 #[serde(deny_unknown_fields)]
 pub struct Endpoint {
     #[schemars(with = "String")]
+    #[serde(deserialize_with = "address::deserialize_with_srv")]
     pub address: EndpointAddress,
     #[serde(default)]
     pub metadata: EndpointMetadata,
+    /// The share of traffic the endpoint receives relative to the other
+    /// endpoints, when chosen by a load balancer. An endpoint with a weight
+    /// of zero is only chosen if every endpoint has a weight of zero.
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
+
+fn is_default_weight(weight: &u32) -> bool {
+    *weight == DEFAULT_WEIGHT
 }
 
 impl Endpoint {
@@ -85,6 +102,7 @@ impl Endpoint {
                 .map(TryFrom::try_from)
                 .transpose()?
                 .unwrap_or_default(),
+            weight: proto.weight.unwrap_or(DEFAULT_WEIGHT),
         })
     }
 
@@ -112,6 +130,7 @@ impl Endpoint {
             port: self.address.port.into(),
             metadata: Some(self.metadata.into()),
             host2: Some(proto::Host { inner: Some(host) }),
+            weight: (self.weight != DEFAULT_WEIGHT).then_some(self.weight),
         }
     }
 }
@@ -121,6 +140,7 @@ impl Default for Endpoint {
         Self {
             address: EndpointAddress::UNSPECIFIED,
             metadata: <_>::default(),
+            weight: DEFAULT_WEIGHT,
         }
     }
 }
@@ -130,7 +150,7 @@ impl std::str::FromStr for Endpoint {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(Self {
-            address: s.parse()?,
+            address: EndpointAddress::parse_with_srv(s)?,
             ..Self::default()
         })
     }
@@ -143,6 +163,7 @@ impl From<Endpoint> for proto::Endpoint {
             port: endpoint.address.port.into(),
             metadata: Some(endpoint.metadata.into()),
             host2: None,
+            weight: (endpoint.weight != DEFAULT_WEIGHT).then_some(endpoint.weight),
         }
     }
 }
@@ -163,6 +184,7 @@ impl TryFrom<proto::Endpoint> for Endpoint {
                 .map(TryFrom::try_from)
                 .transpose()?
                 .unwrap_or_default(),
+            weight: endpoint.weight.unwrap_or(DEFAULT_WEIGHT),
         })
     }
 }
@@ -195,6 +217,17 @@ impl std::hash::Hash for Endpoint {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.address.hash(state);
         self.metadata.known.tokens.hash(state);
+        // Only hashed when set, so versions of endpoint sets are unchanged
+        // for endpoints without a weight.
+        if self.weight != DEFAULT_WEIGHT {
+            self.weight.hash(state);
+        }
+        // Likewise for endpoints that don't advertise their capacity.
+        let known = &self.metadata.known;
+        if known.max_sessions.is_some() || known.cpu_score.is_some() {
+            known.max_sessions.hash(state);
+            known.cpu_score.hash(state);
+        }
     }
 }
 
@@ -207,7 +240,17 @@ pub struct Metadata {
         serialize_with = "base64_set::serialize",
         deserialize_with = "base64_set::deserialize"
     )]
+    #[schemars(schema_with = "crate::config::redact::sensitive::<base64_set::Set>")]
     pub tokens: base64_set::Set,
+    /// The most sessions the endpoint can be assigned, after which new
+    /// sessions aren't admitted when admission control is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sessions: Option<u64>,
+    /// How loaded the endpoint is, from `0` (idle) to `100` (saturated), at
+    /// which point new sessions aren't admitted when admission control is
+    /// enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_score: Option<u8>,
 }
 
 impl From<Metadata> for crate::net::endpoint::metadata::MetadataView<Metadata> {
@@ -235,9 +278,18 @@ impl From<Metadata> for prost_types::Struct {
             )),
         };
 
-        Self {
-            fields: <_>::from([("tokens".into(), tokens)]),
+        let mut fields = std::collections::BTreeMap::from([("tokens".into(), tokens)]);
+        let number = |number| prost_types::Value {
+            kind: Some(prost_types::value::Kind::NumberValue(number)),
+        };
+        if let Some(max_sessions) = metadata.max_sessions {
+            fields.insert("max_sessions".into(), number(max_sessions as f64));
         }
+        if let Some(cpu_score) = metadata.cpu_score {
+            fields.insert("cpu_score".into(), number(f64::from(cpu_score)));
+        }
+
+        Self { fields }
     }
 }
 
@@ -275,7 +327,26 @@ impl std::convert::TryFrom<prost_types::Struct> for Metadata {
                 <_>::default()
             };
 
-        Ok(Self { tokens })
+        let mut number = |field: &str, key: &'static str, max: f64| match value
+            .fields
+            .remove(field)
+            .and_then(|v| v.kind)
+        {
+            None | Some(Kind::NullValue(_)) => Ok(None),
+            Some(Kind::NumberValue(number)) if (0.0..=max).contains(&number) => Ok(Some(number)),
+            Some(_) => Err(MetadataError::InvalidType {
+                key,
+                expected: "non-negative number",
+            }),
+        };
+        let max_sessions = number("max_sessions", "quilkin.dev.max_sessions", u64::MAX as f64)?;
+        let cpu_score = number("cpu_score", "quilkin.dev.cpu_score", f64::from(u8::MAX))?;
+
+        Ok(Self {
+            tokens,
+            max_sessions: max_sessions.map(|max| max as u64),
+            cpu_score: cpu_score.map(|score| score as u8),
+        })
     }
 }
 
@@ -305,6 +376,10 @@ mod base64_set {
     where
         S: serde::Serializer,
     {
+        if crate::config::redact::is_active() {
+            return ser.collect_seq(set.iter().map(|_| crate::config::redact::REDACTED));
+        }
+
         ser.collect_seq(set.iter().map(crate::codec::base64::encode))
     }
 
@@ -354,6 +429,7 @@ mod tests {
     fn endpoint_metadata() {
         let metadata = Metadata {
             tokens: vec!["Man".into()].into_iter().collect(),
+            ..<_>::default()
         };
 
         assert_eq!(
@@ -411,4 +487,26 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn capacity_proto_conversion() {
+        let metadata = Metadata {
+            max_sessions: Some(64),
+            cpu_score: Some(40),
+            ..<_>::default()
+        };
+
+        let proto = prost_types::Struct::from(metadata.clone());
+        assert_eq!(Metadata::try_from(proto).unwrap(), metadata);
+
+        let invalid = prost_types::Struct {
+            fields: <_>::from([(
+                "cpu_score".into(),
+                prost_types::Value {
+                    kind: Some(prost_types::value::Kind::NumberValue(-1.0)),
+                },
+            )]),
+        };
+        assert!(Metadata::try_from(invalid).is_err());
+    }
 }
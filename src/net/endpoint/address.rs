@@ -21,7 +21,6 @@ use std::{
     str::FromStr,
 };
 
-use hickory_resolver::TokioAsyncResolver;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
@@ -61,9 +60,6 @@ impl EndpointAddress {
     /// Returns the socket address for the endpoint, resolving any DNS entries
     /// if present.
     pub fn to_socket_addr(&self) -> std::io::Result<SocketAddr> {
-        static DNS: Lazy<TokioAsyncResolver> =
-            Lazy::new(|| TokioAsyncResolver::tokio_from_system_conf().unwrap());
-
         let ip = match &self.host {
             AddressKind::Ip(ip) => *ip,
             AddressKind::Name(name) => {
@@ -75,7 +71,7 @@ impl EndpointAddress {
                     None => {
                         let handle = tokio::runtime::Handle::current();
                         let set = handle
-                            .block_on(DNS.lookup_ip(&**name))?
+                            .block_on(crate::net::dns::resolver().lookup_ip(&**name))?
                             .iter()
                             .collect::<std::collections::HashSet<_>>();
 
@@ -100,15 +96,25 @@ impl EndpointAddress {
 
         Ok(SocketAddr::from((ip, self.port)))
     }
-}
 
-/// Forwards the deserialisation to use [`std::net::ToSocketAddrs`] instead of
-/// [`FromStr`] for validation which allows us to resolve DNS hostnames such as
-/// `localhost` or container network names at parse-time.
-impl FromStr for EndpointAddress {
-    type Err = ParseError;
+    /// Parses `input` like [`FromStr`], additionally accepting the name of
+    /// SRV records without a port, such as `_game._udp.example.com`, with a
+    /// port of `0`. Only the endpoints of clusters are replaced by the
+    /// addresses and ports of their records, so only they're parsed with this.
+    pub fn parse_with_srv(input: &str) -> Result<Self, ParseError> {
+        let (host, port) = Self::parse_url(input)?;
+        // The port of SRV names comes from their records.
+        let port = match (port, &host) {
+            (Some(port), _) => port,
+            (None, AddressKind::Name(name)) if crate::net::dns::is_srv_name(name) => 0,
+            (None, _) => return Err(ParseError::EmptyPort),
+        };
 
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Ok(Self { host, port })
+    }
+
+    /// Parses `input` as a UDP URL into its host and port, if it has one.
+    fn parse_url(input: &str) -> Result<(AddressKind, Option<u16>), ParseError> {
         let url = if input.starts_with("udp://") {
             url::Url::parse(input)?
         } else if input.contains("://") {
@@ -132,9 +138,33 @@ impl FromStr for EndpointAddress {
             .ok_or(ParseError::EmptyHost)?;
         // Infallible
         let host = host.parse::<AddressKind>().unwrap();
-        let port = url.port().ok_or(ParseError::EmptyPort)?;
 
-        Ok(Self { host, port })
+        Ok((host, url.port()))
+    }
+}
+
+/// Deserializes an [`EndpointAddress`] with
+/// [`EndpointAddress::parse_with_srv`], for the endpoints of clusters.
+pub(crate) fn deserialize_with_srv<'de, D>(de: D) -> Result<EndpointAddress, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let string = <std::borrow::Cow<'de, str>>::deserialize(de)?;
+    EndpointAddress::parse_with_srv(&string).map_err(serde::de::Error::custom)
+}
+
+/// Forwards the deserialisation to use [`std::net::ToSocketAddrs`] instead of
+/// [`FromStr`] for validation which allows us to resolve DNS hostnames such as
+/// `localhost` or container network names at parse-time.
+impl FromStr for EndpointAddress {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (host, port) = Self::parse_url(input)?;
+        Ok(Self {
+            host,
+            port: port.ok_or(ParseError::EmptyPort)?,
+        })
     }
 }
 
@@ -427,4 +457,29 @@ mod tests {
             AddressKind::Ip(_) => panic!("shouldn't be an ip"),
         };
     }
+
+    #[test]
+    fn srv_name_without_port() {
+        let endpoint = EndpointAddress::parse_with_srv("_game._udp.example.com").unwrap();
+        assert_eq!(
+            endpoint.host,
+            AddressKind::Name("_game._udp.example.com".into())
+        );
+        assert_eq!(endpoint.port, 0);
+
+        assert_eq!(
+            EndpointAddress::parse_with_srv("_game._udp.example.com:7777").unwrap(),
+            "_game._udp.example.com:7777".parse().unwrap()
+        );
+        assert!(matches!(
+            EndpointAddress::parse_with_srv("example.com"),
+            Err(ParseError::EmptyPort)
+        ));
+
+        // Only the SRV aware parser leaves out the port.
+        assert!(matches!(
+            "_game._udp.example.com".parse::<EndpointAddress>(),
+            Err(ParseError::EmptyPort)
+        ));
+    }
 }
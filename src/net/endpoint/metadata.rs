@@ -15,6 +15,7 @@
  */
 
 pub(crate) mod symbol;
+mod typed;
 
 #[doc(hidden)]
 pub mod build {
@@ -22,9 +23,7 @@ pub mod build {
 }
 
 pub use symbol::{Key, Reference, Symbol};
-
-/// Shared state between [`Filter`][crate::filters::Filter]s during processing for a single packet.
-pub type DynamicMetadata = gxhash::HashMap<Key, Value>;
+pub use typed::{registered_keys, DynamicMetadata, KeySchema, MetadataType, TypedKey, ValueKind};
 
 pub const KEY: &str = "quilkin.dev";
 
@@ -0,0 +1,299 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{any::Any, collections::BTreeMap, marker::PhantomData, sync::Arc};
+
+use once_cell::sync::Lazy;
+
+use super::{Key, Value};
+
+static SCHEMA: Lazy<parking_lot::RwLock<BTreeMap<Key, KeySchema>>> = Lazy::new(<_>::default);
+
+/// The kind of value stored under a metadata key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ValueKind {
+    Bool,
+    Number,
+    List,
+    String,
+    Bytes,
+    /// An arbitrary Rust type, only accessible to filters that know its type.
+    Any,
+}
+
+/// A type that can be stored as a [`Value`] in [`DynamicMetadata`].
+pub trait MetadataType: Sized + 'static {
+    const KIND: ValueKind;
+
+    fn into_value(self) -> Value;
+    fn from_value(value: &Value) -> Option<&Self>;
+}
+
+macro_rules! metadata_type {
+    ($($typ:ty => $kind:ident),+ $(,)?) => {
+        $(
+            impl MetadataType for $typ {
+                const KIND: ValueKind = ValueKind::$kind;
+
+                #[inline]
+                fn into_value(self) -> Value {
+                    Value::$kind(self)
+                }
+
+                #[inline]
+                fn from_value(value: &Value) -> Option<&Self> {
+                    match value {
+                        Value::$kind(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+        )+
+    }
+}
+
+metadata_type! {
+    bool => Bool,
+    u64 => Number,
+    Vec<Value> => List,
+    String => String,
+    bytes::Bytes => Bytes,
+}
+
+/// A metadata [`Key`] associated with the type of the value stored under it.
+pub struct TypedKey<T> {
+    key: Key,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedKey<T> {
+    pub fn new(key: impl Into<Key>) -> Self {
+        Self {
+            key: key.into(),
+            _type: PhantomData,
+        }
+    }
+
+    /// Creates a key of the form `<namespace>/<name>`, filters should use their
+    /// [`StaticFilter::NAME`](crate::filters::StaticFilter::NAME) or a domain
+    /// they own as the namespace to avoid collisions with other filters.
+    pub fn namespaced(namespace: &str, name: &str) -> Self {
+        Self::new(format!("{namespace}/{name}"))
+    }
+
+    #[inline]
+    pub fn key(&self) -> Key {
+        self.key
+    }
+}
+
+impl<T: MetadataType> TypedKey<T> {
+    /// Registers this key in the metadata schema, so its type and purpose can
+    /// be discovered by users and tooling.
+    pub fn register(self, description: &'static str) -> Self {
+        register(self.key, T::KIND, description);
+        self
+    }
+}
+
+impl<T: Any + Send + Sync> TypedKey<T> {
+    /// Registers this key in the metadata schema as holding an arbitrary value.
+    pub fn register_any(self, description: &'static str) -> Self {
+        register(self.key, ValueKind::Any, description);
+        self
+    }
+}
+
+impl<T> Clone for TypedKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TypedKey<T> {}
+
+impl<T> std::fmt::Debug for TypedKey<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.key.fmt(f)
+    }
+}
+
+impl<T> std::fmt::Display for TypedKey<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.key.fmt(f)
+    }
+}
+
+impl<T> From<Key> for TypedKey<T> {
+    fn from(key: Key) -> Self {
+        Self::new(key)
+    }
+}
+
+/// The registered description of a metadata key.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct KeySchema {
+    pub key: Key,
+    pub kind: ValueKind,
+    pub description: &'static str,
+}
+
+fn register(key: Key, kind: ValueKind, description: &'static str) {
+    let mut schema = SCHEMA.write();
+    if let Some(existing) = schema.get(&key) {
+        if existing.kind != kind {
+            tracing::warn!(
+                %key,
+                existing = ?existing.kind,
+                new = ?kind,
+                "metadata key registered with conflicting types"
+            );
+        }
+    }
+
+    schema.insert(
+        key,
+        KeySchema {
+            key,
+            kind,
+            description,
+        },
+    );
+}
+
+/// Returns every registered metadata key, sorted by key.
+pub fn registered_keys() -> Vec<KeySchema> {
+    SCHEMA.read().values().cloned().collect()
+}
+
+/// Shared state between [`Filter`][crate::filters::Filter]s during processing for a single packet.
+///
+/// Dereferences to the map of [`Value`]s, which is also available through the
+/// typed accessors. Values of arbitrary types that can't be represented as a
+/// [`Value`] are stored separately, see [`Self::insert_any`].
+#[derive(Clone, Default)]
+pub struct DynamicMetadata {
+    values: gxhash::HashMap<Key, Value>,
+    any: gxhash::HashMap<Key, Arc<dyn Any + Send + Sync>>,
+}
+
+impl DynamicMetadata {
+    /// Returns the value for `key`, if present and of the right type.
+    #[inline]
+    pub fn get_typed<T: MetadataType>(&self, key: &TypedKey<T>) -> Option<&T> {
+        self.values.get(&key.key).and_then(T::from_value)
+    }
+
+    /// Inserts `value` for `key`, returning any previous value.
+    #[inline]
+    pub fn insert_typed<T: MetadataType>(&mut self, key: &TypedKey<T>, value: T) -> Option<Value> {
+        self.values.insert(key.key, value.into_value())
+    }
+
+    /// Returns the arbitrary value for `key`, if present and of the right type.
+    #[inline]
+    pub fn get_any<T: Any + Send + Sync>(&self, key: &TypedKey<T>) -> Option<&T> {
+        self.any
+            .get(&key.key)
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Inserts an arbitrary `value` for `key`, replacing any previous value.
+    #[inline]
+    pub fn insert_any<T: Any + Send + Sync>(&mut self, key: &TypedKey<T>, value: T) {
+        self.any.insert(key.key, Arc::new(value));
+    }
+
+    /// Removes the arbitrary value for `key`, returning whether it was present.
+    #[inline]
+    pub fn remove_any<T: Any + Send + Sync>(&mut self, key: &TypedKey<T>) -> bool {
+        self.any.remove(&key.key).is_some()
+    }
+}
+
+impl std::ops::Deref for DynamicMetadata {
+    type Target = gxhash::HashMap<Key, Value>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl std::ops::DerefMut for DynamicMetadata {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.values
+    }
+}
+
+impl std::fmt::Debug for DynamicMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.values.iter())
+            .entries(self.any.keys().map(|key| (key, "<any>")))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_values() {
+        let key = TypedKey::<bytes::Bytes>::namespaced("test.dev", "token");
+        let mut metadata = DynamicMetadata::default();
+
+        assert!(metadata.get_typed(&key).is_none());
+        metadata.insert_typed(&key, bytes::Bytes::from_static(b"abc"));
+        assert_eq!(&metadata.get_typed(&key).unwrap()[..], b"abc");
+        assert_eq!(
+            metadata.get(&Key::new("test.dev/token")),
+            Some(&Value::Bytes(bytes::Bytes::from_static(b"abc")))
+        );
+
+        // The wrong type is treated as absent.
+        let as_number = TypedKey::<u64>::new(key.key());
+        assert!(metadata.get_typed(&as_number).is_none());
+    }
+
+    #[test]
+    fn any_values() {
+        #[derive(Debug, PartialEq)]
+        struct Session(u32);
+
+        let key = TypedKey::<Session>::namespaced("test.dev", "session");
+        let mut metadata = DynamicMetadata::default();
+        metadata.insert_any(&key, Session(5));
+
+        assert_eq!(metadata.get_any(&key), Some(&Session(5)));
+        assert!(metadata.get(&key.key()).is_none());
+        assert!(metadata.get_any(&TypedKey::<u32>::new(key.key())).is_none());
+        assert!(metadata.remove_any(&key));
+    }
+
+    #[test]
+    fn registration() {
+        let key = TypedKey::<bool>::namespaced("test.dev", "registered")
+            .register("whether the test registered");
+
+        let schema = registered_keys()
+            .into_iter()
+            .find(|schema| schema.key == key.key())
+            .unwrap();
+        assert_eq!(schema.kind, ValueKind::Bool);
+    }
+}
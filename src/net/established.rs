@@ -0,0 +1,63 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Whether a client has completed its handshake, set by authentication and
+//! routing filters.
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key filters can set to mark the packet's client as
+/// having completed its handshake, or as no longer having done so. When the
+/// proxy requires a handshake, packets from a client are only forwarded once
+/// a filter has set this to `true`.
+pub const METADATA_KEY: &str = "quilkin.dev/established";
+
+static KEY: Lazy<TypedKey<bool>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY).register("whether the packet's client has completed its handshake")
+});
+
+/// Returns whether a filter marked the packet's client as established in
+/// `metadata`, if it did either way.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> Option<bool> {
+    metadata.get_typed(&KEY).copied()
+}
+
+/// Marks the packet's client as having completed its handshake or not in
+/// `metadata`.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata, established: bool) {
+    metadata.insert_typed(&KEY, established);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert_eq!(get(&metadata), None);
+
+        set(&mut metadata, true);
+        assert_eq!(get(&metadata), Some(true));
+
+        set(&mut metadata, false);
+        assert_eq!(get(&metadata), Some(false));
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+//! Hot restart support, allowing a new proxy process to take over the bound
+//! sockets and active sessions of a running one without dropping traffic.
+//!
+//! The running proxy listens on a Unix domain socket. A new process connects
+//! to it and sends a handoff request, the running proxy replies with its bound
+//! UDP, QCMP and phoenix sockets over `SCM_RIGHTS`, followed by its active
+//! sessions and the state of its filters, and then the upstream sockets of
+//! those sessions, so game servers keep seeing the same source port. Once the
+//! new process acknowledges the handoff, the previous process stops reading
+//! from the sockets, leaving all traffic to the new process.
+
+use std::{io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{components::proxy::SessionKey, filters::FilterState, net::upstream::UpstreamBinding};
+
+/// The hot restart configuration for a proxy.
+pub struct HotRestart {
+    /// The path of the Unix domain socket used to coordinate handoffs.
+    pub path: PathBuf,
+    /// The state received from the previous process, which is restored on
+    /// startup.
+    pub state: State,
+    /// The sockets of each of the `state`'s upstreams, in the same order.
+    pub upstreams: Vec<socket2::Socket>,
+}
+
+/// The state received from the previous process during a handoff.
+pub struct Handoff {
+    pub socket: socket2::Socket,
+    pub qcmp: socket2::Socket,
+    pub phoenix: super::TcpListener,
+    pub state: State,
+    /// The sockets of each of the `state`'s upstreams, in the same order.
+    pub upstreams: Vec<socket2::Socket>,
+}
+
+/// The state handed off to the next process along with the sockets.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct State {
+    /// The active sessions without an upstream socket that's handed off,
+    /// which are recreated by the next process.
+    pub sessions: Vec<SessionKey>,
+    /// The upstream sockets handed off, with the sessions using them.
+    pub upstreams: Vec<Upstream>,
+    /// The state of the filters in the filter chain, see
+    /// [`FilterChain::export_state`](crate::filters::FilterChain::export_state).
+    pub filters: Vec<FilterState>,
+}
+
+/// An upstream socket handed off to the next process, whose descriptor is
+/// sent after the state.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Upstream {
+    /// The port the socket is bound to.
+    pub port: u16,
+    /// How the socket is bound, sessions only share sockets bound the same
+    /// way.
+    #[serde(default)]
+    pub binding: Option<UpstreamBinding>,
+    /// The sessions sending packets through the socket.
+    pub sessions: Vec<SessionKey>,
+}
+
+/// The sockets that will be handed off to the next process.
+pub struct Sockets {
+    pub socket: socket2::Socket,
+    pub qcmp: socket2::Socket,
+    pub phoenix: std::net::TcpListener,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        mod linux;
+        pub use linux::{request, Server};
+    } else {
+        /// Hot restarts are only supported on Linux.
+        pub fn request(_path: &std::path::Path) -> io::Result<Option<Handoff>> {
+            Err(unsupported())
+        }
+
+        /// Hot restarts are only supported on Linux.
+        pub struct Server;
+
+        impl Server {
+            pub fn bind(_path: &std::path::Path) -> io::Result<Self> {
+                Err(unsupported())
+            }
+
+            pub fn serve(
+                self,
+                _sockets: Sockets,
+                _state: impl Fn() -> (State, Vec<socket2::Socket>),
+            ) -> io::Result<()> {
+                Err(unsupported())
+            }
+        }
+
+        fn unsupported() -> io::Error {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "hot restarts are only supported on linux",
+            )
+        }
+    }
+}
+
+/// Spawns a thread that waits for the next process to request a handoff,
+/// returning a receiver that resolves once the handoff has completed.
+pub fn spawn(
+    path: PathBuf,
+    sockets: Sockets,
+    sessions: std::sync::Arc<crate::components::proxy::SessionPool>,
+    config: std::sync::Arc<crate::Config>,
+) -> io::Result<tokio::sync::oneshot::Receiver<()>> {
+    let server = Server::bind(&path)?;
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    std::thread::Builder::new()
+        .name("hot-restart".into())
+        .spawn(move || {
+            tracing::info!(path = %path.display(), "waiting for hot restart requests");
+            let state = || {
+                let (keys, upstreams) = sessions.handoff();
+                let (upstreams, sockets) = upstreams.into_iter().unzip();
+                let state = State {
+                    sessions: keys,
+                    upstreams,
+                    filters: config.filters.load().export_state(),
+                };
+                (state, sockets)
+            };
+            match server.serve(sockets, state) {
+                Ok(()) => {
+                    tracing::info!("handed off sockets to new process");
+                    let _ = tx.send(());
+                }
+                Err(error) => {
+                    tracing::error!(%error, "hot restart handoff failed");
+                }
+            }
+        })?;
+
+    Ok(rx)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hot-restart.sock");
+
+        let socket = crate::net::raw_socket_with_reuse(0).unwrap();
+        let qcmp = crate::net::raw_socket_with_reuse(0).unwrap();
+        let phoenix = std::net::TcpListener::bind((std::net::Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let port = crate::net::socket_port(&socket);
+        let phoenix_port = phoenix.local_addr().unwrap().port();
+
+        let key = SessionKey {
+            source: (std::net::Ipv4Addr::LOCALHOST, 8080).into(),
+            dest: (std::net::Ipv4Addr::LOCALHOST, 8081).into(),
+        };
+        let upstream = crate::net::raw_socket_with_reuse(0).unwrap();
+        let upstream_port = crate::net::socket_port(&upstream);
+        let filters = vec![FilterState {
+            index: 0,
+            name: "quilkin.filters.local_rate_limit.v1alpha1.LocalRateLimit".into(),
+            state: serde_json::json!([]),
+        }];
+        let sent = filters.clone();
+
+        let server = Server::bind(&path).unwrap();
+        let thread = std::thread::spawn(move || {
+            server.serve(
+                Sockets {
+                    socket,
+                    qcmp,
+                    phoenix,
+                },
+                move || {
+                    let state = State {
+                        sessions: vec![key],
+                        upstreams: vec![Upstream {
+                            port: upstream_port,
+                            binding: None,
+                            sessions: vec![key],
+                        }],
+                        filters: sent.clone(),
+                    };
+                    (state, vec![upstream.try_clone().unwrap()])
+                },
+            )
+        });
+
+        let handoff = request(&path).unwrap().unwrap();
+        thread.join().unwrap().unwrap();
+
+        assert_eq!(crate::net::socket_port(&handoff.socket), port);
+        assert_eq!(handoff.phoenix.port(), phoenix_port);
+        assert_eq!(handoff.state.sessions, vec![key]);
+        assert_eq!(handoff.state.filters, filters);
+        assert_eq!(handoff.state.upstreams[0].sessions, vec![key]);
+        assert_eq!(handoff.upstreams.len(), 1);
+        assert_eq!(
+            crate::net::socket_port(&handoff.upstreams[0]),
+            upstream_port
+        );
+    }
+
+    #[test]
+    fn no_previous_process() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(request(&dir.path().join("missing.sock")).unwrap().is_none());
+    }
+}
@@ -0,0 +1,317 @@
+/*
+ * Copyright 2024 Google LLC All Rights Reserved.
+ *
+ *  Licensed under the Apache License, Version 2.0 (the "License");
+ *  you may not use this file except in compliance with the License.
+ *  You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+use std::{
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    time::Duration,
+};
+
+use super::{Handoff, Sockets, State};
+
+/// Sent by the new process to request a handoff.
+const REQUEST: u8 = b'H';
+/// Sent by the new process once it has received the handoff.
+const ACK: u8 = b'A';
+/// The socket, qcmp, and phoenix descriptors.
+const FD_COUNT: usize = 3;
+/// The most upstream socket descriptors sent in a single message, below the
+/// kernel's limit of 253.
+const MAX_UPSTREAM_FDS: usize = 250;
+/// How long either process waits on the other while handing off, so neither
+/// hangs if the other is wedged.
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the server waits before accepting again after failing to.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Requests a handoff from a proxy listening at `path`, returning `None` if
+/// there is no proxy to take over from.
+pub fn request(path: &Path) -> io::Result<Option<Handoff>> {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(error)
+            if matches!(
+                error.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            tracing::debug!(path = %path.display(), "no previous process to take over from");
+            return Ok(None);
+        }
+        Err(error) => return Err(error),
+    };
+    stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDOFF_TIMEOUT))?;
+
+    stream.write_all(&[REQUEST])?;
+
+    let mut header = [0u8; 4];
+    let [socket, qcmp, phoenix]: [OwnedFd; FD_COUNT] = recv_fds(&stream, &mut header, FD_COUNT)?
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("recv_fds checks the number of descriptors"));
+    let mut payload = vec![0; u32::from_le_bytes(header) as usize];
+    stream.read_exact(&mut payload)?;
+    let state: State = serde_json::from_slice(&payload)?;
+
+    // The upstream sockets follow in batches, each headed by its length.
+    let mut upstreams = Vec::with_capacity(state.upstreams.len());
+    while upstreams.len() < state.upstreams.len() {
+        let count = (state.upstreams.len() - upstreams.len()).min(MAX_UPSTREAM_FDS);
+        upstreams.extend(
+            recv_fds(&stream, &mut header, count)?
+                .into_iter()
+                .map(socket2::Socket::from),
+        );
+    }
+
+    stream.write_all(&[ACK])?;
+
+    tracing::info!(
+        path = %path.display(),
+        sessions = state.sessions.len(),
+        upstreams = upstreams.len(),
+        filters = state.filters.len(),
+        "received hot restart handoff"
+    );
+
+    Ok(Some(Handoff {
+        socket: socket.into(),
+        qcmp: qcmp.into(),
+        phoenix: std::net::TcpListener::from(phoenix).into(),
+        state,
+        upstreams,
+    }))
+}
+
+/// Listens for a handoff request from the next process.
+pub struct Server {
+    listener: UnixListener,
+}
+
+impl Server {
+    /// Binds to `path`, replacing any socket left behind by a previous process.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+
+    /// Blocks until a process requests a handoff and acknowledges receiving
+    /// the `sockets`, along with the `state` at the time of the request and
+    /// the sockets of its upstreams. Handoffs that fail are logged, and the
+    /// next request is waited for.
+    pub fn serve(
+        self,
+        sockets: Sockets,
+        state: impl Fn() -> (State, Vec<socket2::Socket>),
+    ) -> io::Result<()> {
+        let fds = [
+            sockets.socket.as_raw_fd(),
+            sockets.qcmp.as_raw_fd(),
+            sockets.phoenix.as_raw_fd(),
+        ];
+
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    tracing::warn!(%error, "failed to accept hot restart request");
+                    std::thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            if let Err(error) = stream
+                .set_read_timeout(Some(HANDOFF_TIMEOUT))
+                .and_then(|()| stream.set_write_timeout(Some(HANDOFF_TIMEOUT)))
+            {
+                tracing::warn!(%error, "failed to set hot restart timeouts");
+                continue;
+            }
+
+            let mut request = [0u8];
+            if let Err(error) = stream.read_exact(&mut request) {
+                tracing::warn!(%error, "failed to read hot restart request");
+                continue;
+            }
+
+            if request[0] != REQUEST {
+                tracing::warn!(request = request[0], "unknown hot restart request");
+                continue;
+            }
+
+            let (state, upstreams) = state();
+            let payload = match serde_json::to_vec(&state) {
+                Ok(payload) => payload,
+                Err(error) => {
+                    tracing::warn!(%error, "failed to serialize hot restart state");
+                    continue;
+                }
+            };
+            let Ok(length) = u32::try_from(payload.len()) else {
+                tracing::warn!(bytes = payload.len(), "hot restart state too large");
+                continue;
+            };
+            let header = length.to_le_bytes();
+            let upstreams: Vec<RawFd> = upstreams.iter().map(AsRawFd::as_raw_fd).collect();
+
+            let result = send_fds(&stream, &header, &fds)
+                .and_then(|()| stream.write_all(&payload))
+                .and_then(|()| {
+                    upstreams.chunks(MAX_UPSTREAM_FDS).try_for_each(|fds| {
+                        send_fds(&stream, &(fds.len() as u32).to_le_bytes(), fds)
+                    })
+                })
+                .and_then(|()| {
+                    let mut ack = [0u8];
+                    stream.read_exact(&mut ack)?;
+                    if ack[0] == ACK {
+                        Ok(())
+                    } else {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "invalid hot restart acknowledgement",
+                        ))
+                    }
+                });
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(error) => {
+                    tracing::warn!(%error, "hot restart handoff was not completed");
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn cmsg_space(count: usize) -> usize {
+    // SAFETY: CMSG_SPACE is a pure size calculation
+    unsafe { libc::CMSG_SPACE((count * std::mem::size_of::<RawFd>()) as u32) as usize }
+}
+
+fn send_fds(stream: &UnixStream, header: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let mut control = vec![0u8; cmsg_space(fds.len())];
+    let mut iov = libc::iovec {
+        iov_base: header.as_ptr() as *mut _,
+        iov_len: header.len(),
+    };
+
+    // SAFETY: msghdr is POD
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+
+    // SAFETY: the control buffer is large enough to hold a single header with
+    // every descriptor, so the first header is non-null and its data is
+    // within the buffer
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN((fds.len() * std::mem::size_of::<RawFd>()) as u32) as _;
+        let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+        for (i, fd) in fds.iter().enumerate() {
+            data.add(i).write_unaligned(*fd);
+        }
+    }
+
+    // SAFETY: msg only references buffers that outlive the call
+    let sent = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if sent as usize != header.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "failed to send hot restart header",
+        ));
+    }
+
+    Ok(())
+}
+
+fn recv_fds(stream: &UnixStream, header: &mut [u8; 4], count: usize) -> io::Result<Vec<OwnedFd>> {
+    let mut control = vec![0u8; cmsg_space(count)];
+    let mut iov = libc::iovec {
+        iov_base: header.as_mut_ptr().cast(),
+        iov_len: header.len(),
+    };
+
+    // SAFETY: msghdr is POD
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+
+    // SAFETY: msg only references buffers that outlive the call
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fds = Vec::with_capacity(count);
+
+    // SAFETY: msg has been filled in by recvmsg, and the kernel guarantees
+    // the header and its data are within the control buffer. Every descriptor
+    // received is taken ownership of exactly once.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                for i in 0..len / std::mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if received as usize != header.len() || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated hot restart handoff",
+        ));
+    }
+
+    if fds.len() != count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expected {count} descriptors in hot restart handoff, received {}",
+                fds.len()
+            ),
+        ));
+    }
+
+    Ok(fds)
+}
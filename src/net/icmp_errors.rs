@@ -0,0 +1,173 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Reading ICMP errors, such as port unreachable, for packets sent from a
+//! socket through its [error queue](https://man7.org/linux/man-pages/man7/ip.7.html).
+
+use std::{
+    io,
+    net::SocketAddr,
+    os::fd::{AsRawFd, RawFd},
+};
+
+/// The most errors read from a socket at once.
+const MAX_ERRORS: usize = 64;
+
+/// Why a destination was reported as unreachable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unreachable {
+    /// Nothing is listening on the destination's port.
+    Port,
+    /// The destination's host or network can't be reached.
+    Host,
+}
+
+impl Unreachable {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Port => "port unreachable",
+            Self::Host => "host unreachable",
+        }
+    }
+
+    fn from_errno(errno: u32) -> Option<Self> {
+        match errno as libc::c_int {
+            libc::ECONNREFUSED => Some(Self::Port),
+            libc::EHOSTUNREACH | libc::ENETUNREACH => Some(Self::Host),
+            _ => None,
+        }
+    }
+}
+
+/// Queues ICMP errors for packets sent from `socket`, for both IPv4 and IPv6
+/// destinations, to be read with [`drain`].
+pub fn enable(socket: &socket2::Socket) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    for (level, name) in [
+        (libc::IPPROTO_IP, libc::IP_RECVERR),
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVERR),
+    ] {
+        // SAFETY: the socket is valid, and the option value is a c_int.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                (&enable as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the errors queued on `fd` without blocking, calling `report` with
+/// the destination of each packet reported as unreachable.
+pub fn drain(fd: RawFd, mut report: impl FnMut(SocketAddr, Unreachable)) {
+    for _ in 0..MAX_ERRORS {
+        // SAFETY: sockaddr_storage is POD
+        let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        // SAFETY: msghdr is POD
+        let mut msghdr: libc::msghdr = unsafe { std::mem::zeroed() };
+        // Large enough for a `sock_extended_err` followed by the offender's
+        // address.
+        let mut control = [0u64; 64];
+
+        msghdr.msg_name = std::ptr::addr_of_mut!(addr).cast();
+        msghdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as _;
+        msghdr.msg_control = control.as_mut_ptr().cast();
+        msghdr.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        // SAFETY: the message header points at buffers that outlive the call.
+        let result =
+            unsafe { libc::recvmsg(fd, &mut msghdr, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+        if result < 0 {
+            // The queue is empty, or the socket has been closed.
+            return;
+        }
+
+        // SAFETY: the kernel wrote the destination's address to `addr`.
+        let destination = unsafe { socket2::SockAddr::new(addr, msghdr.msg_namelen) }.as_socket();
+        let Some(mut destination) = destination else {
+            continue;
+        };
+        destination.set_ip(destination.ip().to_canonical());
+
+        // SAFETY: the control buffer was filled in by the kernel, and is
+        // walked with the CMSG macros so every header is within it.
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msghdr) };
+        while !cmsg.is_null() {
+            // SAFETY: `cmsg` is non-null and within the control buffer.
+            let (level, kind) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+            let is_error = (level == libc::IPPROTO_IP && kind == libc::IP_RECVERR)
+                || (level == libc::IPPROTO_IPV6 && kind == libc::IPV6_RECVERR);
+
+            if is_error {
+                // SAFETY: errors are reported as a `sock_extended_err`.
+                let error = unsafe {
+                    libc::CMSG_DATA(cmsg)
+                        .cast::<libc::sock_extended_err>()
+                        .read_unaligned()
+                };
+                let from_icmp = error.ee_origin == libc::SO_EE_ORIGIN_ICMP
+                    || error.ee_origin == libc::SO_EE_ORIGIN_ICMP6;
+                if let Some(reason) = from_icmp
+                    .then(|| Unreachable::from_errno(error.ee_errno))
+                    .flatten()
+                {
+                    report(destination, reason);
+                }
+            }
+
+            // SAFETY: as above.
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msghdr, cmsg) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_closed_ports() {
+        // Find a port nothing is listening on.
+        let closed = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let destination = closed.local_addr().unwrap();
+        drop(closed);
+
+        let socket = crate::net::raw_socket_with_reuse(0).unwrap();
+        enable(&socket).unwrap();
+        socket.send_to(b"hello", &destination.into()).unwrap();
+
+        let mut reported = Vec::new();
+        for _ in 0..100 {
+            drain(socket.as_raw_fd(), |destination, reason| {
+                reported.push((destination, reason))
+            });
+            if !reported.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(reported, [(destination, Unreachable::Port)]);
+    }
+}
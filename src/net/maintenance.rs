@@ -0,0 +1,372 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Scheduled maintenance windows, during which endpoints are drained and
+//! packets can be sent to fallback endpoints instead.
+
+use std::{collections::HashSet, fmt, net::SocketAddr, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// A recurring window of time during which endpoints are drained, such as
+/// every night between 02:00 and 03:00 UTC.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindow {
+    /// The name of the window, shown in logs and the admin API.
+    pub name: String,
+    /// The time of day the window starts, such as `02:00`.
+    #[schemars(with = "String")]
+    pub start: TimeOfDay,
+    /// The time of day the window ends. Windows ending before they start
+    /// span midnight.
+    #[schemars(with = "String")]
+    pub end: TimeOfDay,
+    /// The timezone `start` and `end` are in, as an offset from UTC such as
+    /// `+09:00`. UTC when unset.
+    #[serde(default)]
+    #[schemars(with = "String")]
+    pub timezone: Timezone,
+    /// The days the window starts on, every day when empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// The endpoints that stop being sent packets during the window.
+    #[serde(default)]
+    pub drain: Vec<SocketAddr>,
+    /// The endpoints packets are sent to during the window when every
+    /// endpoint they were for has been drained.
+    #[serde(default)]
+    pub fallback: Vec<SocketAddr>,
+}
+
+impl MaintenanceWindow {
+    /// Returns whether the window is in effect at `now`.
+    pub fn is_active(&self, now: OffsetDateTime) -> bool {
+        let now = now.to_offset(self.timezone.offset());
+        let minute = TimeOfDay::from_time(now.time());
+        let today = Weekday::from(now.weekday());
+
+        if self.start <= self.end {
+            self.starts_on(today) && self.start <= minute && minute < self.end
+        } else {
+            (self.starts_on(today) && self.start <= minute)
+                || (self.starts_on(today.previous()) && minute < self.end)
+        }
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+}
+
+/// The endpoints drained by the maintenance windows in effect.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ActiveMaintenance {
+    /// The names of the windows in effect.
+    pub windows: Vec<String>,
+    drained: HashSet<SocketAddr>,
+    fallback: Vec<SocketAddr>,
+}
+
+impl ActiveMaintenance {
+    /// Returns the drained endpoints of the `windows` in effect at `now`.
+    pub fn new(windows: &[MaintenanceWindow], now: OffsetDateTime) -> Self {
+        let mut active = Self::default();
+        for window in windows.iter().filter(|window| window.is_active(now)) {
+            active.windows.push(window.name.clone());
+            active.drained.extend(&window.drain);
+            active.fallback.extend(
+                window
+                    .fallback
+                    .iter()
+                    .filter(|address| !window.drain.contains(address)),
+            );
+        }
+
+        active
+            .fallback
+            .retain(|address| !active.drained.contains(address));
+        active.fallback.sort();
+        active.fallback.dedup();
+        active
+    }
+
+    /// Returns whether `endpoint` has been drained.
+    #[inline]
+    pub fn is_drained(&self, endpoint: SocketAddr) -> bool {
+        !self.drained.is_empty() && self.drained.contains(&endpoint)
+    }
+
+    /// The endpoints packets are sent to when every endpoint they were for
+    /// has been drained.
+    #[inline]
+    pub fn fallback(&self) -> &[SocketAddr] {
+        &self.fallback
+    }
+}
+
+/// A time of day, in minutes since midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    const MINUTES_PER_DAY: u16 = 24 * 60;
+
+    pub fn new(hour: u8, minute: u8) -> Option<Self> {
+        (hour < 24 && minute < 60).then(|| Self(u16::from(hour) * 60 + u16::from(minute)))
+    }
+
+    fn from_time(time: time::Time) -> Self {
+        Self(u16::from(time.hour()) * 60 + u16::from(time.minute()))
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || format!("`{value}` is not a time of day, such as `02:30`");
+        let (hour, minute) = value.split_once(':').ok_or_else(error)?;
+        let hour: u8 = hour.parse().map_err(|_| error())?;
+        let minute: u8 = minute.parse().map_err(|_| error())?;
+
+        // `24:00` is allowed as the end of the day.
+        if hour == 24 && minute == 0 {
+            return Ok(Self(Self::MINUTES_PER_DAY));
+        }
+
+        Self::new(hour, minute).ok_or_else(error)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// A timezone, as a fixed offset from UTC in minutes. Daylight saving time
+/// isn't applied, so windows in such timezones have to be updated when the
+/// clocks change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timezone(i16);
+
+impl Timezone {
+    fn offset(self) -> time::UtcOffset {
+        time::UtcOffset::from_whole_seconds(i32::from(self.0) * 60).unwrap_or(time::UtcOffset::UTC)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("utc") || value.eq_ignore_ascii_case("z") {
+            return Ok(Self(0));
+        }
+
+        let error = || format!("`{value}` is not a UTC offset, such as `+09:00` or `UTC`");
+        let (sign, offset) = if let Some(offset) = value.strip_prefix('+') {
+            (1, offset)
+        } else if let Some(offset) = value.strip_prefix('-') {
+            (-1, offset)
+        } else {
+            return Err(error());
+        };
+        let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+        let hours: u8 = hours.parse().map_err(|_| error())?;
+        let minutes: u8 = minutes.parse().map_err(|_| error())?;
+        if hours > 14 || minutes >= 60 {
+            return Err(error());
+        }
+
+        Ok(Self(sign * (i16::from(hours) * 60 + i16::from(minutes))))
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("UTC");
+        }
+
+        let sign = if self.0 < 0 { '-' } else { '+' };
+        let minutes = self.0.unsigned_abs();
+        write!(f, "{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+}
+
+macro_rules! string_serde {
+    ($($ty:ty),+) => {
+        $(
+            impl Serialize for $ty {
+                fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+                    ser.collect_str(self)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+                    String::deserialize(de)?
+                        .parse()
+                        .map_err(serde::de::Error::custom)
+                }
+            }
+        )+
+    };
+}
+
+string_serde!(TimeOfDay, Timezone);
+
+/// A day of the week.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    #[serde(alias = "monday")]
+    Mon,
+    #[serde(alias = "tuesday")]
+    Tue,
+    #[serde(alias = "wednesday")]
+    Wed,
+    #[serde(alias = "thursday")]
+    Thu,
+    #[serde(alias = "friday")]
+    Fri,
+    #[serde(alias = "saturday")]
+    Sat,
+    #[serde(alias = "sunday")]
+    Sun,
+}
+
+impl Weekday {
+    fn previous(self) -> Self {
+        time::Weekday::from(self).previous().into()
+    }
+}
+
+impl From<time::Weekday> for Weekday {
+    fn from(day: time::Weekday) -> Self {
+        match day {
+            time::Weekday::Monday => Self::Mon,
+            time::Weekday::Tuesday => Self::Tue,
+            time::Weekday::Wednesday => Self::Wed,
+            time::Weekday::Thursday => Self::Thu,
+            time::Weekday::Friday => Self::Fri,
+            time::Weekday::Saturday => Self::Sat,
+            time::Weekday::Sunday => Self::Sun,
+        }
+    }
+}
+
+impl From<Weekday> for time::Weekday {
+    fn from(day: Weekday) -> Self {
+        match day {
+            Weekday::Mon => Self::Monday,
+            Weekday::Tue => Self::Tuesday,
+            Weekday::Wed => Self::Wednesday,
+            Weekday::Thu => Self::Thursday,
+            Weekday::Fri => Self::Friday,
+            Weekday::Sat => Self::Saturday,
+            Weekday::Sun => Self::Sunday,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(yaml: &str) -> MaintenanceWindow {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// Monday 2024-01-01 at `hour:minute` UTC.
+    fn monday(hour: u8, minute: u8) -> OffsetDateTime {
+        time::Date::from_calendar_date(2024, time::Month::January, 1)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn parses() {
+        assert_eq!("02:30".parse(), Ok(TimeOfDay(150)));
+        assert_eq!("24:00".parse(), Ok(TimeOfDay(24 * 60)));
+        assert!("25:00".parse::<TimeOfDay>().is_err());
+        assert!("2".parse::<TimeOfDay>().is_err());
+
+        assert_eq!("UTC".parse(), Ok(Timezone(0)));
+        assert_eq!("+09:00".parse(), Ok(Timezone(9 * 60)));
+        assert_eq!("-05:30".parse(), Ok(Timezone(-330)));
+        assert_eq!("-05".parse(), Ok(Timezone(-300)));
+        assert!("09:00".parse::<Timezone>().is_err());
+        assert_eq!(Timezone(-330).to_string(), "-05:30");
+    }
+
+    #[test]
+    fn active_windows() {
+        let nightly = window(
+            "
+name: nightly
+start: '02:00'
+end: '03:00'
+drain: [127.0.0.1:7001]
+fallback: [127.0.0.1:7002]
+",
+        );
+
+        assert!(!nightly.is_active(monday(1, 59)));
+        assert!(nightly.is_active(monday(2, 0)));
+        assert!(nightly.is_active(monday(2, 59)));
+        assert!(!nightly.is_active(monday(3, 0)));
+
+        let active = ActiveMaintenance::new(&[nightly], monday(2, 30));
+        assert_eq!(active.windows, ["nightly"]);
+        assert!(active.is_drained("127.0.0.1:7001".parse().unwrap()));
+        assert!(!active.is_drained("127.0.0.1:7002".parse().unwrap()));
+        assert_eq!(active.fallback(), ["127.0.0.1:7002".parse().unwrap()]);
+    }
+
+    #[test]
+    fn timezones_and_days() {
+        // 02:00 to 03:00 in UTC+9 is 17:00 to 18:00 UTC the day before.
+        let tokyo = window(
+            "
+name: tokyo
+start: '02:00'
+end: '03:00'
+timezone: '+09:00'
+days: [tue]
+",
+        );
+        assert!(tokyo.is_active(monday(17, 30)));
+        assert!(!tokyo.is_active(monday(2, 30)));
+
+        // Windows spanning midnight are for the day they start on.
+        let sunday_night = window(
+            "
+name: sunday night
+start: '23:00'
+end: '01:00'
+days: [sunday]
+",
+        );
+        assert!(sunday_night.is_active(monday(0, 30)));
+        assert!(!sunday_night.is_active(monday(23, 30)));
+    }
+}
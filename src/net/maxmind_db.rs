@@ -21,8 +21,27 @@ use hyper_util::client::legacy;
 use maxminddb::Reader;
 use once_cell::sync::Lazy;
 
+use super::circuit_breaker::{self, CallError, CircuitBreaker};
+use crate::collections::decision_cache::DecisionCache;
+
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Stops downloading the database for a while after repeated failures,
+/// rather than retrying against a server that's down.
+static BREAKER: Lazy<CircuitBreaker> = Lazy::new(|| {
+    CircuitBreaker::new(
+        "maxmind",
+        circuit_breaker::Config {
+            max_concurrent: 1,
+            min_calls: 3,
+            window: std::time::Duration::from_secs(300),
+            open_for: std::time::Duration::from_secs(60),
+            timeout: Some(std::time::Duration::from_secs(120)),
+            ..<_>::default()
+        },
+    )
+});
+
 static HTTP: Lazy<
     legacy::Client<
         hyper_rustls::HttpsConnector<legacy::connect::HttpConnector>,
@@ -40,6 +59,11 @@ static HTTP: Lazy<
 });
 pub static CLIENT: Lazy<arc_swap::ArcSwapOption<MaxmindDb>> = Lazy::new(<_>::default);
 
+/// The entries of recently seen IPs, as they're looked up for the metrics of
+/// every packet. Cleared whenever the database is updated.
+static LOOKUPS: Lazy<DecisionCache<std::net::IpAddr, Option<IpNetEntry>>> =
+    Lazy::new(|| DecisionCache::new("maxmind", 16384, std::time::Duration::from_secs(600)));
+
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 #[serde(tag = "kind")]
 pub enum Source {
@@ -85,19 +109,24 @@ impl MaxmindDb {
             }
         };
 
-        match mmdb.lookup::<IpNetEntry>(ip) {
+        LOOKUPS.get_or_insert_with(ip, || match mmdb.lookup::<IpNetEntry>(ip) {
             Ok(asn) => Some(asn),
             Err(error) => {
-                tracing::warn!(%ip, %error, "ip not found in maxmind database");
+                crate::log_throttle::throttled!(warn!(
+                    %ip,
+                    %error,
+                    "ip not found in maxmind database"
+                ));
                 None
             }
-        }
+        })
     }
 
     #[tracing::instrument(skip_all)]
     pub async fn update(source: Source) -> Result<()> {
         let db = Self::from_source(source).await?;
         CLIENT.store(Some(Arc::new(db)));
+        LOOKUPS.clear();
         tracing::info!("maxmind database updated");
         Ok(())
     }
@@ -124,6 +153,17 @@ impl MaxmindDb {
     /// the cached result, retreiving a fresh copy otherwise.
     #[tracing::instrument(skip_all, fields(url = %url))]
     pub async fn open_url(url: &url::Url) -> Result<Self> {
+        BREAKER
+            .call(|| Self::download(url))
+            .await
+            .map_err(|error| match error {
+                CallError::Rejected(rejected) => rejected.into(),
+                CallError::Failed(error) => error,
+                CallError::TimedOut(timeout) => Error::TimedOut(timeout),
+            })
+    }
+
+    async fn download(url: &url::Url) -> Result<Self> {
         tracing::info!("requesting maxmind database from network");
 
         use http_body_util::BodyExt;
@@ -156,7 +196,7 @@ impl std::ops::DerefMut for MaxmindDb {
     }
 }
 
-#[derive(Clone, serde::Deserialize)]
+#[derive(Clone, Default, serde::Deserialize)]
 pub struct IpNetEntry {
     #[serde(default, rename = "as")]
     pub id: u64,
@@ -198,4 +238,8 @@ pub enum Error {
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Rejected(#[from] circuit_breaker::Rejected),
+    #[error("download timed out after {0:?}")]
+    TimedOut(std::time::Duration),
 }
@@ -0,0 +1,64 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Requests from clients to be routed to a different endpoint, set by filters.
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key filters can set to the number of times the
+/// packet's client asked to be rerouted, so that filters choosing endpoints
+/// by client choose a different one each time it changes.
+pub const METADATA_KEY: &str = "quilkin.dev/reroute";
+
+static KEY: Lazy<TypedKey<u64>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY)
+        .register("the number of times the packet's client asked to be routed to another endpoint")
+});
+
+/// Returns the number of reroutes set in `metadata` by a filter, if any.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> Option<u64> {
+    metadata
+        .get_typed(&KEY)
+        .copied()
+        .filter(|reroutes| *reroutes > 0)
+}
+
+/// Sets the number of times the packet's client asked to be rerouted in
+/// `metadata`.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata, reroutes: u64) {
+    metadata.insert_typed(&KEY, reroutes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert_eq!(get(&metadata), None);
+
+        set(&mut metadata, 2);
+        assert_eq!(get(&metadata), Some(2));
+
+        set(&mut metadata, 0);
+        assert_eq!(get(&metadata), None);
+    }
+}
@@ -0,0 +1,150 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Selection of the endpoints a packet is sent to by filters, overriding the
+//! destinations set by the filter chain.
+
+use once_cell::sync::Lazy;
+
+use crate::net::{
+    cluster::ClusterMap,
+    endpoint::{
+        metadata::{DynamicMetadata, Key, TypedKey, Value},
+        EndpointAddress,
+    },
+};
+
+/// The dynamic metadata key filters can set to select the endpoints the
+/// packet is sent to, either the address of an endpoint as a string, or a
+/// token as bytes selecting every endpoint with that token.
+pub const METADATA_KEY: &str = "quilkin.dev/selected_endpoint";
+
+static KEY: Lazy<Key> = Lazy::new(|| {
+    TypedKey::<String>::new(METADATA_KEY).register(
+        "the address of the endpoint the packet is sent to, or a token as bytes selecting every \
+         endpoint with that token, overriding the destinations set by filters",
+    );
+    Key::from_static(METADATA_KEY)
+});
+
+/// The endpoints selected for a packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// The endpoint with this address.
+    Address(EndpointAddress),
+    /// Every endpoint with this token.
+    Token(bytes::Bytes),
+}
+
+/// Returns the endpoints selected in `metadata` by a filter, if any.
+pub fn get(metadata: &DynamicMetadata) -> Option<Selection> {
+    match metadata.get(&*KEY)? {
+        Value::String(address) => address.parse().ok().map(Selection::Address),
+        Value::Bytes(token) => Some(Selection::Token(token.clone())),
+        _ => None,
+    }
+}
+
+/// Selects the endpoints the packet is sent to in `metadata`, replacing any
+/// previous selection.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata, selection: Selection) {
+    let value = match selection {
+        Selection::Address(address) => Value::String(address.to_string()),
+        Selection::Token(token) => Value::Bytes(token),
+    };
+    metadata.insert(*KEY, value);
+}
+
+/// Replaces `destinations` with the endpoints selected in `metadata`, if
+/// there is a selection. Returns `false` when the selection doesn't match any
+/// endpoint in `endpoints`, in which case the packet should be dropped.
+pub(crate) fn apply(
+    metadata: &DynamicMetadata,
+    endpoints: &ClusterMap,
+    destinations: &mut Vec<EndpointAddress>,
+) -> bool {
+    if !metadata.contains_key(&*KEY) {
+        return true;
+    }
+
+    destinations.clear();
+    match get(metadata) {
+        Some(Selection::Address(address)) => {
+            if !endpoints.contains_address(&address) {
+                return false;
+            }
+            destinations.push(address);
+        }
+        Some(Selection::Token(token)) => {
+            endpoints.addresses_for_token_or_wildcard(&token, destinations);
+        }
+        None => {
+            tracing::trace!("ignoring invalid endpoint selection in metadata");
+            return false;
+        }
+    }
+
+    !destinations.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::endpoint::{Endpoint, Metadata};
+
+    #[test]
+    fn selects_endpoints() {
+        let first: EndpointAddress = "127.0.0.1:80".parse().unwrap();
+        let second: EndpointAddress = "127.0.0.1:90".parse().unwrap();
+        let endpoints = ClusterMap::new_default(
+            [
+                Endpoint::new(first.clone()),
+                Endpoint::with_metadata(
+                    second.clone(),
+                    Metadata {
+                        tokens: vec!["abc".into()].into_iter().collect(),
+                        ..<_>::default()
+                    },
+                ),
+            ]
+            .into(),
+        );
+
+        let mut metadata = DynamicMetadata::default();
+        let mut destinations = vec![first.clone(), second.clone()];
+        assert!(apply(&metadata, &endpoints, &mut destinations));
+        assert_eq!(destinations.len(), 2, "unchanged without a selection");
+
+        set(&mut metadata, Selection::Address(first.clone()));
+        assert_eq!(get(&metadata), Some(Selection::Address(first.clone())));
+        assert!(apply(&metadata, &endpoints, &mut destinations));
+        assert_eq!(destinations, [first]);
+
+        set(&mut metadata, Selection::Token("abc".into()));
+        assert!(apply(&metadata, &endpoints, &mut destinations));
+        assert_eq!(destinations, [second]);
+
+        set(
+            &mut metadata,
+            Selection::Address("127.0.0.1:100".parse().unwrap()),
+        );
+        assert!(!apply(&metadata, &endpoints, &mut destinations));
+
+        metadata.insert(*KEY, Value::String("not an address".into()));
+        assert!(!apply(&metadata, &endpoints, &mut destinations));
+    }
+}
@@ -0,0 +1,184 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per session lifetime quotas, set per listener or by filters.
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key filters can set to limit the packet's session,
+/// holding a [`SessionQuota`].
+pub const METADATA_KEY: &str = "quilkin.dev/session_quota";
+
+/// The packets per second allowed through a throttled session by default.
+pub const DEFAULT_THROTTLE_PACKETS_PER_SEC: u32 = 10;
+
+static KEY: Lazy<TypedKey<SessionQuota>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY).register_any("the lifetime quota of the packet's session")
+});
+
+/// The limits on how much a single session can be used over its lifetime,
+/// such as for trial access to a game, and what happens once one is
+/// exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionQuota {
+    /// The bytes sent in both directions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// The packets sent in both directions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_packets: Option<u64>,
+    /// How long in seconds since the session was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_duration_secs: Option<u64>,
+    /// What happens to the session once a limit is exceeded.
+    #[serde(default)]
+    pub action: QuotaAction,
+    /// The packets per second forwarded from the client once the session is
+    /// throttled.
+    #[serde(default = "default_throttle_packets_per_sec")]
+    pub throttle_packets_per_sec: u32,
+}
+
+fn default_throttle_packets_per_sec() -> u32 {
+    DEFAULT_THROTTLE_PACKETS_PER_SEC
+}
+
+impl Default for SessionQuota {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            max_packets: None,
+            max_duration_secs: None,
+            action: QuotaAction::default(),
+            throttle_packets_per_sec: DEFAULT_THROTTLE_PACKETS_PER_SEC,
+        }
+    }
+}
+
+/// What happens to a session that has exceeded its [`SessionQuota`].
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Packets are dropped in both directions, until the client has stopped
+    /// sending for long enough that the session expires.
+    #[default]
+    Terminate,
+    /// Packets from the client are forwarded at up to
+    /// [`SessionQuota::throttle_packets_per_sec`].
+    Throttle,
+    /// Packets are still forwarded, the session is only logged, counted and
+    /// reported to subscribers of the admin server's `/events`.
+    Event,
+}
+
+impl QuotaAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Terminate => "terminate",
+            Self::Throttle => "throttle",
+            Self::Event => "event",
+        }
+    }
+}
+
+impl SessionQuota {
+    /// Returns the limit exceeded after sending `bytes` and `packets` over
+    /// `elapsed`, if any.
+    #[inline]
+    pub fn exceeded(&self, bytes: u64, packets: u64, elapsed: Duration) -> Option<&'static str> {
+        if self.max_bytes.is_some_and(|max| bytes > max) {
+            Some("bytes")
+        } else if self.max_packets.is_some_and(|max| packets > max) {
+            Some("packets")
+        } else if self
+            .max_duration_secs
+            .is_some_and(|max| elapsed > Duration::from_secs(max))
+        {
+            Some("duration")
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the session quota set in `metadata` by a filter, if any.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> Option<&SessionQuota> {
+    metadata.get_any(&KEY)
+}
+
+/// Sets the quota of the packet's session in `metadata`.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata, quota: SessionQuota) {
+    metadata.insert_any(&KEY, quota);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert_eq!(get(&metadata), None);
+
+        let quota = SessionQuota {
+            max_bytes: Some(100),
+            ..<_>::default()
+        };
+        set(&mut metadata, quota);
+        assert_eq!(get(&metadata), Some(&quota));
+    }
+
+    #[test]
+    fn exceeded() {
+        let quota: SessionQuota = serde_yaml::from_str(
+            "
+max_bytes: 100
+max_packets: 10
+max_duration_secs: 60
+action: throttle
+",
+        )
+        .unwrap();
+        assert_eq!(quota.action, QuotaAction::Throttle);
+        assert_eq!(
+            quota.throttle_packets_per_sec,
+            DEFAULT_THROTTLE_PACKETS_PER_SEC
+        );
+
+        let minute = Duration::from_secs(60);
+        assert_eq!(quota.exceeded(100, 10, minute), None);
+        assert_eq!(quota.exceeded(101, 10, minute), Some("bytes"));
+        assert_eq!(quota.exceeded(100, 11, minute), Some("packets"));
+        assert_eq!(
+            quota.exceeded(100, 10, minute + Duration::from_secs(1)),
+            Some("duration")
+        );
+        assert_eq!(
+            SessionQuota::default().exceeded(u64::MAX, u64::MAX, minute),
+            None
+        );
+    }
+}
@@ -0,0 +1,69 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per session idle timeouts, set by filters.
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key filters can set to override how long the
+/// packet's session is kept after its last packet, in seconds.
+pub const METADATA_KEY: &str = "quilkin.dev/session_timeout";
+
+static KEY: Lazy<TypedKey<u64>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY)
+        .register("the idle timeout of the packet's session in seconds, overriding the default")
+});
+
+/// Returns the session timeout set in `metadata` by a filter, if any.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> Option<Duration> {
+    match metadata.get_typed(&KEY) {
+        Some(0) => {
+            tracing::trace!("ignoring zero session timeout in metadata");
+            None
+        }
+        Some(secs) => Some(Duration::from_secs(*secs)),
+        None => None,
+    }
+}
+
+/// Sets the session timeout in `metadata`, overriding the default for the
+/// packet's session.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata, timeout: Duration) {
+    metadata.insert_typed(&KEY, timeout.as_secs());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert_eq!(get(&metadata), None);
+
+        set(&mut metadata, Duration::from_secs(10));
+        assert_eq!(get(&metadata), Some(Duration::from_secs(10)));
+
+        set(&mut metadata, Duration::ZERO);
+        assert_eq!(get(&metadata), None);
+    }
+}
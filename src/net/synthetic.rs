@@ -0,0 +1,58 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Whether a packet is a synthetic probe run through the filters by the proxy
+//! itself, rather than one sent by a client.
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key set to `true` on the packets of the proxy's
+/// synthetic probes, so filters can tell them apart from clients' packets,
+/// e.g. to leave them out of rate limits.
+pub const METADATA_KEY: &str = "quilkin.dev/synthetic";
+
+static KEY: Lazy<TypedKey<bool>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY)
+        .register("whether the packet is one of the proxy's synthetic probes")
+});
+
+/// Returns whether the packet is one of the proxy's synthetic probes.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> bool {
+    metadata.get_typed(&KEY).copied().unwrap_or_default()
+}
+
+/// Marks the packet as one of the proxy's synthetic probes in `metadata`.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata) {
+    metadata.insert_typed(&KEY, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert!(!get(&metadata));
+
+        set(&mut metadata);
+        assert!(get(&metadata));
+    }
+}
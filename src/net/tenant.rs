@@ -0,0 +1,56 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The tenant a packet belongs to, when several are served by one proxy.
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key holding the name of the tenant the packet
+/// belongs to. Set by filters before the [`Tenants`](crate::filters::Tenants)
+/// filter to assign packets to a tenant, and by the filter itself otherwise.
+pub const METADATA_KEY: &str = "quilkin.dev/tenant";
+
+static KEY: Lazy<TypedKey<String>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY).register("the name of the tenant the packet belongs to")
+});
+
+/// Returns the name of the tenant the packet belongs to, if any.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> Option<&str> {
+    metadata.get_typed(&KEY).map(String::as_str)
+}
+
+/// Sets the name of the tenant the packet belongs to in `metadata`.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata, tenant: &str) {
+    metadata.insert_typed(&KEY, tenant.to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert_eq!(get(&metadata), None);
+
+        set(&mut metadata, "title-a");
+        assert_eq!(get(&metadata), Some("title-a"));
+    }
+}
@@ -0,0 +1,60 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Whether one of a client's sessions has stopped getting responses from its
+//! upstream, set by the proxy.
+
+use once_cell::sync::Lazy;
+
+use crate::net::endpoint::metadata::{DynamicMetadata, TypedKey};
+
+/// The dynamic metadata key the proxy sets to `true` on packets from clients
+/// with a session whose upstream hasn't responded within the response
+/// timeout, so filters can route them elsewhere.
+pub const METADATA_KEY: &str = "quilkin.dev/unresponsive";
+
+static KEY: Lazy<TypedKey<bool>> = Lazy::new(|| {
+    TypedKey::new(METADATA_KEY)
+        .register("whether one of the client's sessions has had no responses from its upstream")
+});
+
+/// Returns whether the packet's client was marked as having an unresponsive
+/// session in `metadata`.
+#[inline]
+pub fn get(metadata: &DynamicMetadata) -> bool {
+    metadata.get_typed(&KEY).copied().unwrap_or_default()
+}
+
+/// Marks the packet's client as having an unresponsive session in
+/// `metadata`.
+#[inline]
+pub fn set(metadata: &mut DynamicMetadata) {
+    metadata.insert_typed(&KEY, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let mut metadata = DynamicMetadata::default();
+        assert!(!get(&metadata));
+
+        set(&mut metadata);
+        assert!(get(&metadata));
+    }
+}
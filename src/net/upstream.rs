@@ -0,0 +1,283 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Binding of the sockets used to send packets to upstream endpoints.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Protocol, Socket, Type};
+
+use crate::net::endpoint::Locality;
+
+/// How the sockets used to send packets to the endpoints of a cluster are
+/// bound, for hosts with several interfaces or addresses, or upstreams whose
+/// firewalls only accept traffic from a known range of ports.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamBinding {
+    /// The locality of the cluster this binding applies to, the cluster
+    /// without a locality when unset.
+    #[serde(default)]
+    pub locality: Option<Locality>,
+    /// The network interface to send packets from. Only supported on Linux.
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// The local address to send packets from.
+    #[serde(default)]
+    pub address: Option<IpAddr>,
+    /// A pool of local addresses to send packets from in place of `address`,
+    /// one of which is assigned to each session, so the proxy isn't limited
+    /// to the ports of a single address.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub address_pool: Vec<IpAddr>,
+    /// How an address from `address_pool` is assigned to each session.
+    #[serde(default)]
+    pub pool_selection: PoolSelection,
+    /// The range of local ports to send packets from, when unset the system
+    /// assigns a port.
+    #[serde(default)]
+    pub ports: Option<PortRange>,
+}
+
+/// How an address from an [`UpstreamBinding::address_pool`] is assigned to
+/// each session.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolSelection {
+    /// By a hash of the client's address, so each client is always sent from
+    /// the same address.
+    #[default]
+    Hash,
+    /// Each new session is assigned the next address in turn.
+    RoundRobin,
+}
+
+impl UpstreamBinding {
+    /// Returns the binding of a session from `source`, with an address from
+    /// the pool assigned to it, if there is one. `next` is the index of the
+    /// next address to assign in turn.
+    pub(crate) fn for_session(&self, source: SocketAddr, next: &AtomicUsize) -> Self {
+        if self.address_pool.is_empty() {
+            return self.clone();
+        }
+
+        let index = match self.pool_selection {
+            PoolSelection::Hash => {
+                use std::hash::{Hash, Hasher};
+
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                source.hash(&mut hasher);
+                hasher.finish() as usize
+            }
+            PoolSelection::RoundRobin => next.fetch_add(1, Ordering::Relaxed),
+        };
+
+        Self {
+            address: Some(self.address_pool[index % self.address_pool.len()]),
+            address_pool: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Creates a new dual stack socket bound as configured, trying each port
+    /// in the range until one is available.
+    pub fn socket(&self) -> io::Result<Socket> {
+        let sock = Socket::new(socket2::Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        sock.set_nonblocking(true)?;
+        sock.set_only_v6(false)?;
+
+        if let Some(interface) = &self.interface {
+            cfg_if::cfg_if! {
+                if #[cfg(target_os = "linux")] {
+                    sock.bind_device(Some(interface.as_bytes()))?;
+                } else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("binding to interface `{interface}` is only supported on Linux"),
+                    ));
+                }
+            }
+        }
+
+        let ip = match self.address {
+            Some(IpAddr::V4(ip)) => ip.to_ipv6_mapped(),
+            Some(IpAddr::V6(ip)) => ip,
+            None => Ipv6Addr::UNSPECIFIED,
+        };
+
+        let Some(ports) = self.ports else {
+            sock.bind(&SocketAddr::from((ip, 0)).into())?;
+            return Ok(sock);
+        };
+
+        if ports.start > ports.end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("port range {ports} is empty"),
+            ));
+        }
+
+        // Start from a random port so sockets are spread across the range
+        // rather than contending for the first free one.
+        let len = u32::from(ports.end - ports.start) + 1;
+        let offset = rand::random::<u32>() % len;
+        for i in 0..len {
+            let port = ports.start + ((offset + i) % len) as u16;
+            match sock.bind(&SocketAddr::from((ip, port)).into()) {
+                Ok(()) => return Ok(sock),
+                Err(error) if error.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("no ports are available in {ports}"),
+        ))
+    }
+}
+
+/// An inclusive range of ports.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl std::fmt::Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Returns the binding for the cluster containing `dest` in `clusters`, if
+/// there is one in `bindings`.
+pub(crate) fn find_binding<'b>(
+    bindings: &'b [UpstreamBinding],
+    clusters: &crate::net::ClusterMap,
+    dest: SocketAddr,
+) -> Option<&'b UpstreamBinding> {
+    if bindings.is_empty() {
+        return None;
+    }
+
+    let endpoint = crate::net::Endpoint::new(dest.into());
+    let locality = clusters
+        .iter()
+        .find(|cluster| cluster.value().contains(&endpoint))
+        .map(|cluster| cluster.key().clone())?;
+
+    bindings.iter().find(|binding| binding.locality == locality)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn binds_within_port_range() {
+        let port = crate::net::socket_port(&crate::net::raw_socket_with_reuse(0).unwrap());
+        let binding: UpstreamBinding = serde_yaml::from_str(&format!(
+            "
+address: 127.0.0.1
+ports:
+  start: {port}
+  end: {port}
+"
+        ))
+        .unwrap();
+
+        let socket = binding.socket().unwrap();
+        assert_eq!(
+            socket.local_addr().unwrap().as_socket().unwrap(),
+            SocketAddr::from((Ipv4Addr::LOCALHOST.to_ipv6_mapped(), port))
+        );
+        assert_eq!(
+            binding.socket().unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+    }
+
+    #[test]
+    fn finds_cluster_binding() {
+        let locality: Locality = "us:east1:a".parse().unwrap();
+        let dest: SocketAddr = "127.0.0.1:7777".parse().unwrap();
+        let clusters = crate::net::ClusterMap::default();
+        clusters.insert(
+            Some(locality.clone()),
+            [crate::net::Endpoint::new(dest.into())].into(),
+        );
+
+        let bindings = [
+            UpstreamBinding {
+                address: Some(IpAddr::from([127, 0, 0, 2])),
+                ..<_>::default()
+            },
+            UpstreamBinding {
+                locality: Some(locality),
+                address: Some(IpAddr::from([127, 0, 0, 3])),
+                ..<_>::default()
+            },
+        ];
+
+        assert_eq!(
+            find_binding(&bindings, &clusters, dest).unwrap().address,
+            Some(IpAddr::from([127, 0, 0, 3]))
+        );
+        assert!(find_binding(&bindings, &clusters, "127.0.0.1:1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn assigns_pool_addresses() {
+        let mut binding: UpstreamBinding = serde_yaml::from_str(
+            "
+address_pool: [127.0.0.2, 127.0.0.3]
+pool_selection: round_robin
+",
+        )
+        .unwrap();
+        let next = AtomicUsize::new(0);
+        let client: SocketAddr = "10.0.0.1:7000".parse().unwrap();
+
+        let addresses: Vec<_> = (0..4)
+            .map(|_| binding.for_session(client, &next).address.unwrap())
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                [127, 0, 0, 2],
+                [127, 0, 0, 3],
+                [127, 0, 0, 2],
+                [127, 0, 0, 3]
+            ]
+            .map(IpAddr::from)
+        );
+
+        binding.pool_selection = PoolSelection::Hash;
+        let session = binding.for_session(client, &next);
+        assert!(session.address_pool.is_empty());
+        assert_eq!(session, binding.for_session(client, &next));
+    }
+}
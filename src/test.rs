@@ -31,6 +31,8 @@ use crate::{
     ShutdownKind, ShutdownRx, ShutdownTx,
 };
 
+pub mod conformance;
+
 static LOG_ONCE: Once = Once::new();
 
 /// Call to safely enable logging calls with a given tracing env filter, e.g. "quilkin=debug"
@@ -303,13 +305,38 @@ impl TestHelper {
             crate::components::proxy::Proxy {
                 num_workers: std::num::NonZeroUsize::new(1).unwrap(),
                 mmdb: None,
+                mmdb_refresh: None,
                 management_servers: Vec::new(),
                 to: Vec::new(),
                 to_tokens: None,
                 socket: crate::net::raw_socket_with_reuse(0).unwrap(),
+                listener_ports: Vec::new(),
                 qcmp,
                 phoenix,
                 notifier: None,
+                drain_timeout: std::time::Duration::ZERO,
+                drain_notification: None,
+                hot_restart: None,
+                dscp: Default::default(),
+                overload: Default::default(),
+                transparent: false,
+                write_errors: Default::default(),
+                coalesce: Default::default(),
+                max_sessions: None,
+                history: Default::default(),
+                heatmap: Default::default(),
+                handshake: Default::default(),
+                duplicate: Default::default(),
+                fairness: Default::default(),
+                ejection: Default::default(),
+                response_timeout: Default::default(),
+                admission: Default::default(),
+                drop_log_sample: 0,
+                shared_sessions: Default::default(),
+                tombstones: Default::default(),
+                warm: Default::default(),
+                affinity: Default::default(),
+                address_discovery: None,
             }
         });
 
@@ -397,6 +424,53 @@ where
     assert_eq!(contents, &*context.contents);
 }
 
+/// Asserts that the YAML configuration of `F` survives being converted to
+/// Protobuf, as when distributed over xDS, and back, and that a filter can be
+/// created from the Protobuf configuration.
+pub fn assert_config_round_trip<F>(yaml: &str)
+where
+    F: StaticFilter + 'static,
+    F::Configuration: PartialEq + std::fmt::Debug,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    let config: F::Configuration = serde_yaml::from_str(yaml).unwrap();
+    let factory = F::factory();
+
+    let proto = factory
+        .encode_config_to_protobuf(serde_json::to_value(&config).unwrap())
+        .unwrap();
+    assert_eq!(proto.type_url, F::NAME);
+
+    let json = factory.encode_config_to_json(proto.clone()).unwrap();
+    assert_eq!(
+        serde_json::from_value::<F::Configuration>(json).unwrap(),
+        config
+    );
+
+    factory
+        .create_filter(crate::filters::CreateFilterArgs::dynamic(Some(proto)))
+        .unwrap();
+}
+
+/// Generates a test asserting that a filter's configuration survives being
+/// converted to Protobuf and back, see [`assert_config_round_trip`].
+///
+/// ```
+/// use quilkin::filters::HealthProbe;
+///
+/// quilkin::config_round_trip_test!(round_trip: HealthProbe => "payloads: [aGVhbHRo]");
+/// ```
+#[macro_export]
+macro_rules! config_round_trip_test {
+    ($name:ident: $filter:ty => $yaml:expr) => {
+        #[test]
+        fn $name() {
+            $crate::test::assert_config_round_trip::<$filter>($yaml);
+        }
+    };
+}
+
 pub fn map_to_localhost(address: &mut EndpointAddress) {
     let mut socket_addr = address.to_socket_addr().unwrap();
     map_addr_to_localhost(&mut socket_addr);
@@ -0,0 +1,296 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Table driven conformance tests for filters, read from YAML fixtures.
+//!
+//! A fixture is a filter chain, the endpoints it routes to, and a list of
+//! cases, each a packet run through the chain and what's expected of it.
+//!
+//! ```yaml
+//! filters:
+//!   - name: quilkin.filters.capture.v1alpha1.Capture
+//!     config:
+//!       prefix:
+//!         size: 3
+//!         remove: true
+//! clusters:
+//!   - endpoints:
+//!       - address: 127.0.0.1:7001
+//! cases:
+//!   - name: removes the prefix
+//!     contents: abcdef
+//!     expect:
+//!       contents: def
+//!       metadata:
+//!         quilkin.dev/capture: abc
+//! ```
+
+use std::{collections::BTreeMap, net::SocketAddr, path::Path, sync::Arc};
+
+use crate::{
+    filters::{Filter as _, FilterChain, ReadContext, WriteContext},
+    net::{
+        endpoint::{
+            metadata::{DynamicMetadata, Key, Value},
+            EndpointAddress,
+        },
+        ClusterMap,
+    },
+};
+
+/// A filter chain, and the cases run through it.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    pub filters: FilterChain,
+    #[serde(default)]
+    pub clusters: ClusterMap,
+    pub cases: Vec<Case>,
+}
+
+/// Which way a packet is sent through the filter chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// From a client to the endpoints.
+    #[default]
+    Read,
+    /// From an endpoint back to a client.
+    Write,
+}
+
+/// A packet run through the filter chain, and what's expected of it.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    #[serde(default)]
+    pub direction: Direction,
+    /// The client's address.
+    #[serde(default = "default_client")]
+    pub client: SocketAddr,
+    /// The address of the endpoint sending the packet, when writing.
+    #[serde(default = "default_endpoint")]
+    pub endpoint: EndpointAddress,
+    pub contents: Contents,
+    /// Metadata set before the packet is run through the chain, strings are
+    /// set as bytes, as they would be by the Capture filter.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, Value>,
+    pub expect: Expect,
+}
+
+/// What's expected of a packet after it has been through the filter chain.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    /// The discriminant of the error the packet is dropped with, if any.
+    pub error: Option<String>,
+    /// The packet's contents, if checked.
+    pub contents: Option<Contents>,
+    /// The endpoints the packet is sent to in any order, if checked.
+    pub destinations: Option<Vec<EndpointAddress>>,
+    /// The reply sent back to the client instead of forwarding the packet,
+    /// if checked.
+    pub reply: Option<Contents>,
+    /// Metadata the packet has to have, strings also match bytes.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, Value>,
+}
+
+/// The contents of a packet, either as text or base64 for binary data.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Contents {
+    Base64 { base64: String },
+    Text(String),
+}
+
+impl Contents {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Base64 { base64 } => crate::codec::base64::decode(base64)
+                .unwrap_or_else(|error| panic!("`{base64}` is not valid base64: {error}")),
+            Self::Text(text) => text.as_bytes().to_vec(),
+        }
+    }
+}
+
+fn default_client() -> SocketAddr {
+    (std::net::Ipv4Addr::LOCALHOST, 9000).into()
+}
+
+fn default_endpoint() -> EndpointAddress {
+    (std::net::Ipv4Addr::LOCALHOST, 7001).into()
+}
+
+impl Fixture {
+    /// Reads the fixture at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    /// Runs every case, returning a description of each failed expectation.
+    pub fn run(self) -> Vec<String> {
+        let endpoints = Arc::new(self.clusters);
+        let mut failures = Vec::new();
+
+        for case in &self.cases {
+            let result = match case.direction {
+                Direction::Read => run_read(&self.filters, &endpoints, case),
+                Direction::Write => run_write(&self.filters, case),
+            };
+
+            failures.extend(
+                result
+                    .into_iter()
+                    .map(|failure| format!("{}: {failure}", case.name)),
+            );
+        }
+
+        failures
+    }
+}
+
+/// Runs the fixture at `path`, panicking with every failed expectation.
+#[track_caller]
+pub fn run_file(path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let fixture = Fixture::from_file(path)
+        .unwrap_or_else(|error| panic!("failed to read {}: {error}", path.display()));
+
+    let failures = fixture.run();
+    assert!(
+        failures.is_empty(),
+        "{}:\n{}",
+        path.display(),
+        failures.join("\n")
+    );
+}
+
+fn run_read(filters: &FilterChain, endpoints: &Arc<ClusterMap>, case: &Case) -> Vec<String> {
+    let mut destinations = Vec::new();
+    let mut ctx = ReadContext::new(
+        endpoints.clone(),
+        case.client.into(),
+        super::alloc_buffer(case.contents.to_bytes()),
+        &mut destinations,
+    );
+    set_metadata(&mut ctx.metadata, &case.metadata);
+
+    let result = filters.read(&mut ctx);
+    let mut failures = check_error(result, &case.expect);
+    check_contents(&mut failures, &ctx.contents, &case.expect);
+    check_metadata(&mut failures, &ctx.metadata, &case.expect);
+
+    if let Some(expected) = &case.expect.reply {
+        let expected = expected.to_bytes();
+        if ctx.reply.as_deref() != Some(&*expected) {
+            failures.push(format!(
+                "expected reply {:?}, got {:?}",
+                String::from_utf8_lossy(&expected),
+                ctx.reply.as_deref().map(String::from_utf8_lossy)
+            ));
+        }
+    }
+
+    if let Some(expected) = &case.expect.destinations {
+        let mut expected = expected.clone();
+        expected.sort();
+        ctx.destinations.sort();
+        if *ctx.destinations != expected {
+            failures.push(format!(
+                "expected destinations {expected:?}, got {:?}",
+                ctx.destinations
+            ));
+        }
+    }
+
+    failures
+}
+
+fn run_write(filters: &FilterChain, case: &Case) -> Vec<String> {
+    let mut ctx = WriteContext::new(
+        case.endpoint.clone(),
+        case.client.into(),
+        super::alloc_buffer(case.contents.to_bytes()),
+    );
+    set_metadata(&mut ctx.metadata, &case.metadata);
+
+    let result = filters.write(&mut ctx);
+    let mut failures = check_error(result, &case.expect);
+    check_contents(&mut failures, &ctx.contents, &case.expect);
+    check_metadata(&mut failures, &ctx.metadata, &case.expect);
+
+    if case.expect.destinations.is_some() || case.expect.reply.is_some() {
+        failures.push("destinations and replies are only checked when reading".into());
+    }
+
+    failures
+}
+
+fn set_metadata(metadata: &mut DynamicMetadata, values: &BTreeMap<String, Value>) {
+    for (key, value) in values {
+        let value = match value {
+            Value::String(string) => Value::Bytes(string.clone().into()),
+            value => value.clone(),
+        };
+        metadata.insert(Key::from(key.as_str()), value);
+    }
+}
+
+fn check_error(result: Result<(), crate::filters::FilterError>, expect: &Expect) -> Vec<String> {
+    let actual = result.err().map(|error| error.discriminant());
+    if actual == expect.error.as_deref() {
+        return Vec::new();
+    }
+
+    vec![format!("expected error {:?}, got {actual:?}", expect.error)]
+}
+
+fn check_contents(failures: &mut Vec<String>, contents: &[u8], expect: &Expect) {
+    let Some(expected) = &expect.contents else {
+        return;
+    };
+
+    let expected = expected.to_bytes();
+    if contents != &*expected {
+        failures.push(format!(
+            "expected contents {:?}, got {:?}",
+            String::from_utf8_lossy(&expected),
+            String::from_utf8_lossy(contents)
+        ));
+    }
+}
+
+fn check_metadata(failures: &mut Vec<String>, metadata: &DynamicMetadata, expect: &Expect) {
+    for (key, expected) in &expect.metadata {
+        let actual = metadata.get(&Key::from(key.as_str()));
+        let matches = match (expected, actual) {
+            (Value::String(expected), Some(Value::Bytes(actual))) => {
+                expected.as_bytes() == &**actual
+            }
+            (expected, actual) => Some(expected) == actual,
+        };
+
+        if !matches {
+            failures.push(format!(
+                "expected metadata `{key}` to be {expected:?}, got {actual:?}"
+            ));
+        }
+    }
+}
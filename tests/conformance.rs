@@ -0,0 +1,52 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *       http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *  Unless required by applicable law or agreed to in writing, software
+ *  distributed under the License is distributed on an "AS IS" BASIS,
+ *  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *  See the License for the specific language governing permissions and
+ *  limitations under the License.
+ */
+
+use std::path::Path;
+
+use quilkin::test::conformance::Fixture;
+
+/// Runs every fixture in `tests/fixtures/filters`, see
+/// [`quilkin::test::conformance`] for their format.
+#[test]
+fn filters() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/filters");
+    let mut paths: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "yaml")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures in {}", directory.display());
+
+    let mut failures = Vec::new();
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        match Fixture::from_file(&path) {
+            Ok(fixture) => failures.extend(
+                fixture
+                    .run()
+                    .into_iter()
+                    .map(|failure| format!("{name}: {failure}")),
+            ),
+            Err(error) => failures.push(format!("{name}: failed to read fixture: {error}")),
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
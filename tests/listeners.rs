@@ -0,0 +1,85 @@
+/*
+ * Copyright 2024 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::net::Ipv4Addr;
+
+use tokio::time::{timeout, Duration};
+
+use quilkin::{
+    config::Filter,
+    filters::{Listeners, StaticFilter},
+    net::endpoint::Endpoint,
+    test::{available_addr, AddressType, TestHelper},
+};
+
+#[tokio::test]
+#[cfg_attr(target_os = "macos", ignore)]
+async fn listeners() {
+    let mut t = TestHelper::default();
+    let echo = t.run_echo_server(AddressType::Ipv4).await;
+    let listener_port = available_addr(AddressType::Ipv4).await.port();
+    let yaml = format!(
+        "
+listeners:
+  - ports: [{listener_port}]
+    filters:
+      - name: quilkin.filters.concatenate.v1alpha1.Concatenate
+        config:
+          on_read: APPEND
+          bytes: YWJj #abc
+"
+    );
+
+    let server_config = std::sync::Arc::new(quilkin::Config::default_non_agent());
+    server_config
+        .clusters
+        .modify(|clusters| clusters.insert_default([Endpoint::new(echo.clone())].into()));
+    server_config.filters.store(
+        quilkin::filters::FilterChain::try_create([Filter {
+            name: Listeners::factory().name().into(),
+            label: None,
+            config: serde_yaml::from_str(&yaml).unwrap(),
+        }])
+        .map(std::sync::Arc::new)
+        .unwrap(),
+    );
+    let server_port = t.run_server(server_config, None, None).await;
+
+    let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let mut buf = [0; 64];
+
+    // Each port runs its own filter chain, and packets are sent back to the
+    // client from the port it sent its packets to.
+    for (port, expected) in [
+        (server_port, &b"hello"[..]),
+        (listener_port, &b"helloabc"[..]),
+        (server_port, &b"hello"[..]),
+    ] {
+        socket
+            .send_to(b"hello", (Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let (size, source) = timeout(Duration::from_millis(500), socket.recv_from(&mut buf))
+            .await
+            .expect("should have received a packet")
+            .unwrap();
+
+        assert_eq!(&buf[..size], expected);
+        assert_eq!(source.port(), port);
+    }
+}